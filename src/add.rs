@@ -0,0 +1,297 @@
+//! Adding existing dotfiles to a manifest
+//!
+//! ```
+//! add_file(Path::new("manifest.yml"), "~/.config/kitty/kitty.conf",
+//!          &[String::from("gui")])?;
+//! ```
+
+use anyhow::{Context, Result, bail};
+use shellexpand::tilde;
+use std::fs;
+use std::path::{Component, Path, PathBuf, absolute};
+use super::local::link_file;
+use super::manifest::parse_manifest_str;
+
+/// A file that was added to a manifest
+#[derive(Debug, PartialEq)]
+pub struct AddedFile {
+    /// The new location of the file, relative to the manifest
+    pub src: String,
+
+    /// The original location of the file, as it appears in the manifest
+    pub dst: String,
+}
+
+/// Moves a file into the directory containing a manifest, appends a step that
+/// links it back to its original location, and installs the link
+///
+/// The file is placed at the same path relative to the manifest as it was
+/// relative to the home directory, without any leading dots (e.g.
+/// `~/.config/kitty/kitty.conf` becomes `config/kitty/kitty.conf`). Files
+/// outside of the home directory are placed next to the manifest. Returns an
+/// Err if the file can't be added, in which case nothing is modified unless the
+/// final link fails.
+///
+/// ```
+/// add_file(Path::new("manifest.yml"), "~/.bashrc", &[String::from("linux")])?;
+/// ```
+pub fn add_file(manifest_path: &Path, path: &str, tags: &[String]) ->
+    Result<AddedFile> {
+
+    let file = absolute(tilde(path).as_ref()).with_context(|| {
+        format!("Failed to make {} absolute", path)
+    })?;
+    match fs::symlink_metadata(&file) {
+        Err(_) => bail!("{} doesn't exist", path),
+        Ok(metadata) if metadata.is_symlink() => {
+            bail!("{} is already a link", path);
+        },
+        Ok(metadata) if !metadata.is_file() => {
+            bail!("{} isn't a regular file", path);
+        },
+        Ok(_) => {},
+    }
+
+    let home = absolute(tilde("~").as_ref())
+        .context("Failed to locate home directory")?;
+    let added = manifest_paths(&file, &home);
+
+    let base_dir = match manifest_path.parent() {
+        Some(p) if p != Path::new("") => p.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let repo_file = base_dir.join(&added.src);
+    if fs::symlink_metadata(&repo_file).is_ok() {
+        bail!("{} already exists", repo_file.display());
+    }
+
+    let contents = fs::read_to_string(manifest_path).with_context(|| {
+        format!("Failed to read {}", manifest_path.display())
+    })?;
+    let new_contents = append_link_step(&contents, &added, tags)?;
+
+    if let Some(parent) = repo_file.parent() {
+        fs::create_dir_all(parent).with_context(|| {
+            format!("Failed to create {}", parent.display())
+        })?;
+    }
+    move_file(&file, &repo_file)?;
+    fs::write(manifest_path, new_contents).with_context(|| {
+        format!("Failed to write {}", manifest_path.display())
+    })?;
+
+    let repo_abs = absolute(&repo_file).with_context(|| {
+        format!("Failed to make {} absolute", repo_file.display())
+    })?;
+    link_file(&repo_abs.to_string_lossy(), &file.to_string_lossy())
+        .with_context(|| format!("Failed to link {} to {}", added.src, path))?;
+
+    Ok(added)
+}
+
+/// Determines where a file should be placed relative to the manifest and how
+/// its destination should appear in the manifest
+fn manifest_paths(file: &Path, home: &Path) -> AddedFile {
+    match file.strip_prefix(home) {
+        Ok(relative) => {
+            let components: Vec<String> = relative.components().map(|x| {
+                let name = x.as_os_str().to_string_lossy();
+                name.trim_start_matches('.').to_string()
+            }).collect();
+            AddedFile {
+                src: components.join("/"),
+                dst: format!("~/{}", to_slashes(relative)),
+            }
+        },
+        Err(_) => {
+            let name = file.file_name().unwrap_or_default().to_string_lossy();
+            AddedFile {
+                src: name.trim_start_matches('.').to_string(),
+                dst: file.to_string_lossy().to_string(),
+            }
+        },
+    }
+}
+
+/// Joins the normal components of a path with `/`
+fn to_slashes(path: &Path) -> String {
+    path.components().filter_map(|x| match x {
+        Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+        _ => None,
+    }).collect::<Vec<String>>().join("/")
+}
+
+/// Appends a step that links a file to the text of a manifest, returning an
+/// Err if the result isn't a valid manifest
+fn append_link_step(contents: &str, added: &AddedFile, tags: &[String]) ->
+    Result<String> {
+
+    let mut new_contents = contents.trim_end().to_string();
+
+    // An empty list of steps can't be extended by appending text
+    if new_contents.ends_with("steps: []") {
+        new_contents.truncate(new_contents.len() - 3);
+    }
+
+    new_contents.push_str(&format!("\n\n  - link:\n    - src: {}\n", added.src));
+    new_contents.push_str(&format!("      dst: {}\n", added.dst));
+    if !tags.is_empty() {
+        new_contents.push_str(&format!("    tags: [ {} ]\n",
+                                       tags.join(", ")));
+    }
+
+    parse_manifest_str(&new_contents, Path::new("."))
+        .context("Failed to append a step to the manifest")?;
+    Ok(new_contents)
+}
+
+/// Moves a file, copying it if it can't be renamed (e.g. across file systems)
+fn move_file(src: &Path, dst: &Path) -> Result<()> {
+    if fs::rename(src, dst).is_ok() { return Ok(()); }
+
+    fs::copy(src, dst).with_context(|| {
+        format!("Failed to move {} to {}", src.display(), dst.display())
+    })?;
+    fs::remove_file(src).with_context(|| {
+        format!("Failed to remove {}", src.display())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{read_file, setup_integration, write_file};
+
+    #[test]
+    fn test_manifest_paths_home() {
+        let result = manifest_paths(Path::new("/home/u/.config/kitty/a.conf"),
+                                    Path::new("/home/u"));
+
+        assert_eq!(result, AddedFile {
+            src: String::from("config/kitty/a.conf"),
+            dst: String::from("~/.config/kitty/a.conf"),
+        });
+    }
+
+    #[test]
+    fn test_manifest_paths_outside_home() {
+        let result = manifest_paths(Path::new("/etc/.hosts"),
+                                    Path::new("/home/user"));
+
+        assert_eq!(result, AddedFile {
+            src: String::from("hosts"),
+            dst: String::from("/etc/.hosts"),
+        });
+    }
+
+    #[test]
+    fn test_append_link_step_basic() {
+        let added = AddedFile {
+            src: String::from("bashrc"),
+            dst: String::from("~/.bashrc"),
+        };
+        let contents = "steps:\n  - copy:\n    - src: foo\n      dst: ~/foo\n";
+
+        let result = append_link_step(contents, &added,
+                                      &[String::from("linux")]);
+
+        assert_eq!(result.unwrap(), "\
+steps:
+  - copy:
+    - src: foo
+      dst: ~/foo
+
+  - link:
+    - src: bashrc
+      dst: ~/.bashrc
+    tags: [ linux ]
+");
+    }
+
+    #[test]
+    fn test_append_link_step_empty() {
+        let added = AddedFile {
+            src: String::from("bashrc"),
+            dst: String::from("~/.bashrc"),
+        };
+
+        let result = append_link_step("steps: []\n", &added, &[]);
+
+        assert_eq!(result.unwrap(), "\
+steps:
+
+  - link:
+    - src: bashrc
+      dst: ~/.bashrc
+");
+    }
+
+    #[test]
+    fn test_append_link_step_invalid() {
+        let added = AddedFile {
+            src: String::from("bashrc"),
+            dst: String::from("~/.bashrc"),
+        };
+
+        let result = append_link_step("steps: {}\n", &added, &[]);
+
+        assert_eq!(result.is_ok(), false);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_add_file_basic() {
+        let tmp = setup_integration("test_add_file_basic");
+
+        let manifest_path = tmp.local.join("manifest.yml");
+        let file = tmp.local.join(".foo");
+        write_file(&manifest_path, "steps: []\n");
+        write_file(&file, "contents of foo");
+
+        let result = add_file(&manifest_path, &file.to_string_lossy(), &[]);
+
+        let src = String::from("foo");
+        assert_eq!(result.unwrap(), AddedFile {
+            src: src.clone(),
+            dst: file.to_string_lossy().to_string(),
+        });
+        assert_eq!(read_file(&tmp.local.join("foo")), "contents of foo");
+        assert_eq!(fs::read_link(&file).unwrap(), tmp.local.join("foo"));
+        let manifest = crate::manifest::parse_manifest_file(&manifest_path);
+        assert_eq!(manifest.unwrap().steps[0].link[0].src, src);
+    }
+
+    #[test]
+    fn test_add_file_existing() {
+        let tmp = setup_integration("test_add_file_existing");
+
+        let manifest_path = tmp.local.join("manifest.yml");
+        let file = tmp.local.join(".foo");
+        write_file(&manifest_path, "steps: []\n");
+        write_file(&file, "contents of .foo");
+        write_file(&tmp.local.join("foo"), "contents of foo");
+
+        let result = add_file(&manifest_path, &file.to_string_lossy(), &[]);
+
+        assert_eq!(result.is_ok(), false);
+        assert_eq!(result.unwrap_err().to_string(), format!(
+            "{} already exists", tmp.local.join("foo").display()));
+        assert_eq!(read_file(&file), "contents of .foo");
+        assert_eq!(read_file(&manifest_path), "steps: []\n");
+    }
+
+    #[test]
+    fn test_add_file_missing() {
+        let tmp = setup_integration("test_add_file_missing");
+
+        let manifest_path = tmp.local.join("manifest.yml");
+        let file = tmp.local.join(".foo");
+        write_file(&manifest_path, "steps: []\n");
+
+        let result = add_file(&manifest_path, &file.to_string_lossy(), &[]);
+
+        assert_eq!(result.is_ok(), false);
+        assert_eq!(result.unwrap_err().to_string(),
+                   format!("{} doesn't exist", file.display()));
+    }
+}
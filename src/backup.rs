@@ -0,0 +1,762 @@
+//! Content-addressed storage for backups of overwritten dotfiles
+//!
+//! Before coliru overwrites a file on the local machine, the file's contents
+//! are saved in a backup store. Contents are stored once per unique hash under
+//! `objects/`, and the versions of each destination are recorded in an index
+//! file under `index/`, so repeatedly backing up identical files doesn't use
+//! any extra space.
+//!
+//! ```
+//! let store = default_store()?;
+//! backup_file(&store, Path::new("foo"), Path::new("~/foo"))?;
+//! restore_file(&store, Path::new("~/foo"), None)?;
+//! ```
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use shellexpand::tilde;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf, absolute};
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::hash::{HashAlgorithm, hash_file, hash_reader};
+use super::manifest::xdg_dir;
+use tempfile::NamedTempFile;
+
+/// A single backed up version of a file
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Backup {
+    /// The SHA-256 hash of the file's contents
+    pub hash: String,
+
+    /// The time the backup was made, in seconds since the Unix epoch
+    pub time: u64,
+}
+
+/// The backed up versions of a file, as stored in an index file
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Index {
+    /// The absolute path of the file
+    path: PathBuf,
+
+    /// The backed up versions of the file, from oldest to newest
+    versions: Vec<Backup>,
+}
+
+/// Returns the location of the default backup store
+///
+/// The store is located at `$XDG_STATE_HOME/coliru/backups`, which defaults to
+/// `~/.local/state/coliru/backups` on Unix and `%LOCALAPPDATA%\coliru\backups`
+/// on Windows.
+///
+/// ```
+/// let store = default_store()?;
+/// ```
+pub fn default_store() -> Result<PathBuf> {
+    Ok(state_dir()?.join("coliru").join("backups"))
+}
+
+/// Returns the location of the directory that replaced files are moved into
+/// when they're quarantined
+///
+/// The directory is located at `$XDG_STATE_HOME/coliru/quarantine`, next to the
+/// default backup store.
+///
+/// ```
+/// let quarantine = default_quarantine()?;
+/// ```
+pub fn default_quarantine() -> Result<PathBuf> {
+    Ok(state_dir()?.join("coliru").join("quarantine"))
+}
+
+/// Returns the location of the file that records which destinations coliru has
+/// installed on the local machine
+///
+/// The file is located at `$XDG_STATE_HOME/coliru/installed.json`, next to the
+/// default backup store.
+///
+/// ```
+/// let state = load_state(&default_state_file()?)?;
+/// ```
+pub fn default_state_file() -> Result<PathBuf> {
+    Ok(state_dir()?.join("coliru").join("installed.json"))
+}
+
+/// Returns the location of the user's state directory
+fn state_dir() -> Result<PathBuf> {
+    let dir = xdg_dir("XDG_STATE_HOME", "~/.local/state", "LOCALAPPDATA")?;
+    Ok(PathBuf::from(tilde(&dir).to_string()))
+}
+
+/// Backs up a file that is about to be overwritten by another file
+///
+/// Nothing is backed up if `dst` isn't a regular file (e.g. it doesn't exist or
+/// it's a symbolic link), has the same contents as `src`, or has the same
+/// contents as its most recent backup. Tildes are expanded if present. Returns
+/// a bool indicating whether a new version was backed up.
+///
+/// ```
+/// backup_file(&store, Path::new("foo"), Path::new("~/foo"))?;
+/// ```
+pub fn backup_file(store: &Path, src: &Path, dst: &Path) -> Result<bool> {
+    let _dst = expand_path(dst)?;
+    match fs::symlink_metadata(&_dst) {
+        Ok(metadata) if metadata.is_file() => {},
+        _ => return Ok(false),
+    };
+
+    let hash = hash_file(&_dst, HashAlgorithm::Sha256)?;
+    if src.exists() && hash_file(src, HashAlgorithm::Sha256)? == hash {
+        return Ok(false);
+    }
+
+    let mut index = read_index(store, &_dst)?;
+    if index.versions.last().is_some_and(|x| x.hash == hash) {
+        return Ok(false);
+    }
+
+    write_object(store, &_dst, &hash)?;
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    index.versions.push(Backup { hash, time });
+    write_index(store, &index)?;
+
+    Ok(true)
+}
+
+/// Returns the backed up versions of a file, from oldest to newest
+///
+/// Tildes are expanded if present.
+///
+/// ```
+/// let versions = list_backups(&store, Path::new("~/foo"))?;
+/// ```
+pub fn list_backups(store: &Path, dst: &Path) -> Result<Vec<Backup>> {
+    Ok(read_index(store, &expand_path(dst)?)?.versions)
+}
+
+/// Restores a backed up version of a file and returns the restored version
+///
+/// Versions are numbered from 1 (the oldest backup) and the most recent backup
+/// is restored if `version` is None. The current contents of the file are
+/// backed up before they are replaced. Tildes are expanded if present.
+///
+/// ```
+/// restore_file(&store, Path::new("~/foo"), Some(2))?;
+/// ```
+pub fn restore_file(store: &Path, dst: &Path, version: Option<usize>) ->
+    Result<usize> {
+
+    let _dst = expand_path(dst)?;
+    let versions = read_index(store, &_dst)?.versions;
+    if versions.is_empty() {
+        bail!("No backups of {} exist", _dst.display());
+    }
+
+    let number = version.unwrap_or(versions.len());
+    if number == 0 || number > versions.len() {
+        bail!("Version {} of {} doesn't exist (latest is version {})", number,
+              _dst.display(), versions.len());
+    }
+    let object = store.join("objects").join(&versions[number-1].hash);
+
+    backup_file(store, &object, &_dst)?;
+
+    if let Some(parent) = _dst.parent() {
+        fs::create_dir_all(parent).with_context(|| {
+            format!("Failed to create parent directories of {}",
+                    _dst.display())
+        })?;
+    }
+    if fs::symlink_metadata(&_dst).is_ok() {
+        fs::remove_file(&_dst).with_context(|| {
+            format!("Failed to remove existing file at {}", _dst.display())
+        })?;
+    }
+    fs::copy(&object, &_dst).with_context(|| {
+        format!("Failed to copy {} to {}", object.display(), _dst.display())
+    })?;
+
+    Ok(number)
+}
+
+/// Expands tildes in a path and makes it absolute
+///
+/// ```
+/// let path = expand_path(Path::new("~/.bashrc"))?;
+/// ```
+pub fn expand_path(path: &Path) -> Result<PathBuf> {
+    let expanded = tilde(&path.to_string_lossy()).to_string();
+    absolute(&expanded).with_context(|| {
+        format!("Failed to make {} absolute", path.display())
+    })
+}
+
+/// Returns the location of the index file of an absolute path
+fn index_path(store: &Path, path: &Path) -> Result<PathBuf> {
+    let path_str = path.to_string_lossy();
+    let name = hash_reader(&mut path_str.as_bytes(), HashAlgorithm::Sha256)?;
+    Ok(store.join("index").join(format!("{}.json", name)))
+}
+
+/// Reads the index file of an absolute path, which is empty if it doesn't exist
+fn read_index(store: &Path, path: &Path) -> Result<Index> {
+    let index_file = index_path(store, path)?;
+    if !index_file.exists() {
+        return Ok(Index { path: path.to_path_buf(), versions: vec![] });
+    }
+
+    let contents = fs::read_to_string(&index_file).with_context(|| {
+        format!("Failed to read {}", index_file.display())
+    })?;
+    serde_json::from_str(&contents).with_context(|| {
+        format!("Failed to parse {}", index_file.display())
+    })
+}
+
+/// Overwrites the index file of an absolute path
+fn write_index(store: &Path, index: &Index) -> Result<()> {
+    let index_file = index_path(store, &index.path)?;
+    let contents = serde_json::to_string_pretty(index)?;
+    write_atomic(&index_file, |file| Ok(file.write_all(contents.as_bytes())?))
+}
+
+/// Adds the contents of a file to the store unless they're already present
+fn write_object(store: &Path, path: &Path, hash: &str) -> Result<()> {
+    let object = store.join("objects").join(hash);
+    if object.exists() { return Ok(()); }
+
+    write_atomic(&object, |file| {
+        let mut src = fs::File::open(path)?;
+        std::io::copy(&mut src, file)?;
+        Ok(())
+    }).with_context(|| format!("Failed to back up {}", path.display()))
+}
+
+/// Writes a file by writing to a temporary file and then renaming it, so that
+/// concurrent readers never see partially written files
+///
+/// ```
+/// write_atomic(Path::new("foo"), |file| Ok(file.write_all(b"foo")?))?;
+/// ```
+pub fn write_atomic<F>(path: &Path, write: F) -> Result<()>
+    where F: FnOnce(&mut fs::File) -> Result<()> {
+
+    let parent = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(parent).with_context(|| {
+        format!("Failed to create {}", parent.display())
+    })?;
+    let mut temp_file = NamedTempFile::new_in(parent).with_context(|| {
+        format!("Failed to create temporary file in {}", parent.display())
+    })?;
+    write(temp_file.as_file_mut())?;
+    temp_file.persist(path).with_context(|| {
+        format!("Failed to write {}", path.display())
+    })?;
+    Ok(())
+}
+
+/// Retention policies for garbage collecting a backup store
+#[derive(Debug, Default)]
+pub struct GcOptions {
+    /// The maximum number of versions to keep for each file
+    pub keep: Option<usize>,
+
+    /// The maximum total size of the stored contents, in bytes
+    pub max_size: Option<u64>,
+
+    /// The directories whose files' backups are kept, or empty to keep the
+    /// backups of all files
+    pub roots: Vec<PathBuf>,
+}
+
+/// The results of garbage collecting a backup store
+#[derive(Debug, Default, PartialEq)]
+pub struct GcStats {
+    /// The number of versions that were removed
+    pub versions: usize,
+
+    /// The number of stored contents that were removed
+    pub objects: usize,
+
+    /// The total size of the removed contents, in bytes
+    pub bytes: u64,
+}
+
+/// Removes old backups from a store according to a set of retention policies
+///
+/// Backups of files outside of `roots` are removed first, then all but the
+/// newest `keep` versions of each file, and finally the oldest remaining
+/// versions until the store is no larger than `max_size`. Contents that are no
+/// longer used by any version are then deleted. If `dry_run` is true, the
+/// results are computed without modifying the store.
+///
+/// ```
+/// let options = GcOptions { keep: Some(5), ..Default::default() };
+/// let stats = gc_store(&store, &options, false)?;
+/// ```
+pub fn gc_store(store: &Path, options: &GcOptions, dry_run: bool) ->
+    Result<GcStats> {
+
+    let mut stats = GcStats::default();
+    let mut indexes = read_all_indexes(store)?;
+    let original_paths: Vec<PathBuf> = indexes.iter().map(|x| {
+        x.path.clone()
+    }).collect();
+
+    if !options.roots.is_empty() {
+        let roots = options.roots.iter().map(|x| expand_path(x))
+            .collect::<Result<Vec<_>>>()?;
+        indexes.retain(|index| {
+            let keep = roots.iter().any(|x| index.path.starts_with(x));
+            if !keep { stats.versions += index.versions.len(); }
+            keep
+        });
+    }
+
+    if let Some(keep) = options.keep {
+        for index in &mut indexes {
+            let count = index.versions.len().saturating_sub(keep);
+            index.versions.drain(..count);
+            stats.versions += count;
+        }
+    }
+
+    let objects = read_objects(store)?;
+    if let Some(max_size) = options.max_size {
+        let mut size = referenced_size(&indexes, &objects);
+        while size > max_size {
+            // Remove the oldest remaining version
+            let Some(oldest) = (0..indexes.len()).filter(|&i| {
+                !indexes[i].versions.is_empty()
+            }).min_by_key(|&i| indexes[i].versions[0].time) else { break };
+            indexes[oldest].versions.remove(0);
+            stats.versions += 1;
+            size = referenced_size(&indexes, &objects);
+        }
+    }
+
+    for (hash, object_size) in &objects {
+        let is_used = indexes.iter().any(|index| {
+            index.versions.iter().any(|x| x.hash == *hash)
+        });
+        if is_used { continue; }
+
+        stats.objects += 1;
+        stats.bytes += object_size;
+        if !dry_run {
+            let object = store.join("objects").join(hash);
+            fs::remove_file(&object).with_context(|| {
+                format!("Failed to remove {}", object.display())
+            })?;
+        }
+    }
+
+    if !dry_run {
+        for path in original_paths {
+            match indexes.iter().find(|x| x.path == path) {
+                Some(index) if !index.versions.is_empty() => {
+                    write_index(store, index)?;
+                },
+                _ => {
+                    let index_file = index_path(store, &path)?;
+                    fs::remove_file(&index_file).with_context(|| {
+                        format!("Failed to remove {}", index_file.display())
+                    })?;
+                },
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+/// Reads every index file in a store
+fn read_all_indexes(store: &Path) -> Result<Vec<Index>> {
+    let index_dir = store.join("index");
+    if !index_dir.exists() { return Ok(vec![]); }
+
+    let mut indexes = vec![];
+    for entry in fs::read_dir(&index_dir).with_context(|| {
+        format!("Failed to read {}", index_dir.display())
+    })? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|x| x == "json") {
+            let contents = fs::read_to_string(&path).with_context(|| {
+                format!("Failed to read {}", path.display())
+            })?;
+            indexes.push(serde_json::from_str(&contents).with_context(|| {
+                format!("Failed to parse {}", path.display())
+            })?);
+        }
+    }
+    Ok(indexes)
+}
+
+/// Returns the hashes and sizes of the stored contents in a store
+///
+/// Temporary files that are still being written are ignored.
+fn read_objects(store: &Path) -> Result<Vec<(String, u64)>> {
+    let object_dir = store.join("objects");
+    if !object_dir.exists() { return Ok(vec![]); }
+
+    let mut objects = vec![];
+    for entry in fs::read_dir(&object_dir).with_context(|| {
+        format!("Failed to read {}", object_dir.display())
+    })? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.len() == 64 && name.chars().all(|x| x.is_ascii_hexdigit()) {
+            objects.push((name, entry.metadata()?.len()));
+        }
+    }
+    objects.sort();
+    Ok(objects)
+}
+
+/// Returns the total size of the stored contents used by a set of indexes
+fn referenced_size(indexes: &[Index], objects: &[(String, u64)]) -> u64 {
+    objects.iter().filter(|(hash, _)| {
+        indexes.iter().any(|index| {
+            index.versions.iter().any(|x| x.hash == *hash)
+        })
+    }).map(|(_, size)| size).sum()
+}
+
+/// Parses a size in bytes with an optional `K`, `M`, or `G` suffix
+///
+/// ```
+/// assert_eq!(parse_size("10M"), Ok(10 * 1024 * 1024));
+/// ```
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let (number, multiplier) = match size.chars().last() {
+        Some('K') | Some('k') => (&size[..size.len()-1], 1024),
+        Some('M') | Some('m') => (&size[..size.len()-1], 1024 * 1024),
+        Some('G') | Some('g') => (&size[..size.len()-1], 1024 * 1024 * 1024),
+        _ => (size, 1),
+    };
+    number.parse::<u64>().ok().and_then(|x| x.checked_mul(multiplier))
+        .ok_or(format!("invalid size '{}'", size))
+}
+
+/// Formats a size in bytes using the largest suitable unit
+///
+/// ```
+/// assert_eq!(format_size(1536), "1.5 KiB");
+/// ```
+pub fn format_size(bytes: u64) -> String {
+    let units = ["KiB", "MiB", "GiB"];
+    if bytes < 1024 { return format!("{} B", bytes); }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+/// Formats a Unix timestamp as a UTC date and time
+///
+/// ```
+/// assert_eq!(format_time(0), "1970-01-01 00:00:00 UTC");
+/// ```
+pub fn format_time(time: u64) -> String {
+    let [year, month, day, hour, minute, second] = utc_fields(time);
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, hour,
+            minute, second)
+}
+
+/// Splits a Unix timestamp into a UTC year, month, day, hour, minute, and
+/// second
+///
+/// ```
+/// assert_eq!(utc_fields(0), [1970, 1, 1, 0, 0, 0]);
+/// ```
+pub fn utc_fields(time: u64) -> [u64; 6] {
+    let (days, seconds) = (time / 86400, time % 86400);
+
+    // Convert days since the epoch to a civil date (proleptic Gregorian)
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524
+                       - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4
+                                    - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    [year as u64, month as u64, day as u64, seconds / 3600, seconds % 3600 / 60,
+     seconds % 60]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{read_file, setup_integration, write_file};
+
+    #[test]
+    fn test_backup_file_basic() {
+        let tmp = setup_integration("test_backup_file_basic");
+
+        let store = tmp.local.join("store");
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+        write_file(&dst, "contents of bar");
+
+        let result = backup_file(&store, &src, &dst);
+
+        assert_eq!(result.unwrap(), true);
+        let versions = list_backups(&store, &dst).unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(read_file(&store.join("objects").join(&versions[0].hash)),
+                   "contents of bar");
+    }
+
+    #[test]
+    fn test_backup_file_missing() {
+        let tmp = setup_integration("test_backup_file_missing");
+
+        let store = tmp.local.join("store");
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+
+        let result = backup_file(&store, &src, &dst);
+
+        assert_eq!(result.unwrap(), false);
+        assert_eq!(store.exists(), false);
+    }
+
+    #[test]
+    fn test_backup_file_same_as_src() {
+        let tmp = setup_integration("test_backup_file_same_as_src");
+
+        let store = tmp.local.join("store");
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+        write_file(&dst, "contents of foo");
+
+        let result = backup_file(&store, &src, &dst);
+
+        assert_eq!(result.unwrap(), false);
+        assert_eq!(list_backups(&store, &dst).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_backup_file_deduplication() {
+        let tmp = setup_integration("test_backup_file_deduplication");
+
+        let store = tmp.local.join("store");
+        let src = tmp.local.join("foo");
+        let dst_1 = tmp.local.join("bar");
+        let dst_2 = tmp.local.join("baz");
+        write_file(&src, "contents of foo");
+        write_file(&dst_1, "contents of bar");
+        write_file(&dst_2, "contents of bar");
+
+        let result_1 = backup_file(&store, &src, &dst_1);
+        let result_2 = backup_file(&store, &src, &dst_1);
+        let result_3 = backup_file(&store, &src, &dst_2);
+
+        assert_eq!(result_1.unwrap(), true);
+        assert_eq!(result_2.unwrap(), false);
+        assert_eq!(result_3.unwrap(), true);
+        assert_eq!(list_backups(&store, &dst_1).unwrap().len(), 1);
+        assert_eq!(list_backups(&store, &dst_2).unwrap().len(), 1);
+        assert_eq!(fs::read_dir(store.join("objects")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_restore_file_latest() {
+        let tmp = setup_integration("test_restore_file_latest");
+
+        let store = tmp.local.join("store");
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+        write_file(&dst, "bar #1");
+        backup_file(&store, &src, &dst).unwrap();
+        write_file(&dst, "bar #2");
+        backup_file(&store, &src, &dst).unwrap();
+        write_file(&dst, "contents of foo");
+
+        let result = restore_file(&store, &dst, None);
+
+        assert_eq!(result.unwrap(), 2);
+        assert_eq!(read_file(&dst), "bar #2");
+    }
+
+    #[test]
+    fn test_restore_file_version() {
+        let tmp = setup_integration("test_restore_file_version");
+
+        let store = tmp.local.join("store");
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+        write_file(&dst, "bar #1");
+        backup_file(&store, &src, &dst).unwrap();
+        write_file(&dst, "bar #2");
+
+        let result = restore_file(&store, &dst, Some(1));
+
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(read_file(&dst), "bar #1");
+        assert_eq!(list_backups(&store, &dst).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_restore_file_bad_version() {
+        let tmp = setup_integration("test_restore_file_bad_version");
+
+        let store = tmp.local.join("store");
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+        write_file(&dst, "bar #1");
+        backup_file(&store, &src, &dst).unwrap();
+
+        let result = restore_file(&store, &dst, Some(2));
+
+        assert_eq!(result.is_ok(), false);
+        assert_eq!(result.unwrap_err().to_string(), format!(
+            "Version 2 of {} doesn't exist (latest is version 1)",
+            dst.display()));
+        assert_eq!(read_file(&dst), "bar #1");
+    }
+
+    #[test]
+    fn test_restore_file_no_backups() {
+        let tmp = setup_integration("test_restore_file_no_backups");
+
+        let store = tmp.local.join("store");
+        let dst = tmp.local.join("bar");
+
+        let result = restore_file(&store, &dst, None);
+
+        assert_eq!(result.is_ok(), false);
+        assert_eq!(result.unwrap_err().to_string(),
+                   format!("No backups of {} exist", dst.display()));
+    }
+
+    fn backup_versions(store: &Path, dir: &Path, name: &str, count: usize) {
+        let src = dir.join("src");
+        let dst = dir.join(name);
+        write_file(&src, "contents of src");
+        for i in 0..count {
+            write_file(&dst, &format!("{} #{}", name, i));
+            backup_file(store, &src, &dst).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_gc_store_keep() {
+        let tmp = setup_integration("test_gc_store_keep");
+
+        let store = tmp.local.join("store");
+        backup_versions(&store, &tmp.local, "foo", 3);
+        backup_versions(&store, &tmp.local, "bar", 1);
+        let options = GcOptions { keep: Some(1), ..Default::default() };
+
+        let result = gc_store(&store, &options, false);
+
+        assert_eq!(result.unwrap(), GcStats {
+            versions: 2, objects: 2, bytes: 12
+        });
+        let versions = list_backups(&store, &tmp.local.join("foo")).unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(read_file(&store.join("objects").join(&versions[0].hash)),
+                   "foo #2");
+        assert_eq!(list_backups(&store, &tmp.local.join("bar")).unwrap().len(),
+                   1);
+    }
+
+    #[test]
+    fn test_gc_store_max_size() {
+        let tmp = setup_integration("test_gc_store_max_size");
+
+        let store = tmp.local.join("store");
+        backup_versions(&store, &tmp.local, "foo", 3);
+        let options = GcOptions { max_size: Some(12), ..Default::default() };
+
+        let result = gc_store(&store, &options, false);
+
+        assert_eq!(result.unwrap(), GcStats {
+            versions: 1, objects: 1, bytes: 6
+        });
+        assert_eq!(list_backups(&store, &tmp.local.join("foo")).unwrap().len(),
+                   2);
+    }
+
+    #[test]
+    fn test_gc_store_roots() {
+        let tmp = setup_integration("test_gc_store_roots");
+
+        let store = tmp.local.join("store");
+        fs::create_dir_all(tmp.local.join("a")).unwrap();
+        fs::create_dir_all(tmp.local.join("b")).unwrap();
+        backup_versions(&store, &tmp.local.join("a"), "foo", 2);
+        backup_versions(&store, &tmp.local.join("b"), "foo", 1);
+        let options = GcOptions {
+            roots: vec![tmp.local.join("b")], ..Default::default()
+        };
+
+        let result = gc_store(&store, &options, false);
+
+        assert_eq!(result.unwrap(), GcStats {
+            versions: 2, objects: 1, bytes: 6
+        });
+        assert_eq!(list_backups(&store, &tmp.local.join("a/foo")).unwrap(),
+                   vec![]);
+        assert_eq!(fs::read_dir(store.join("index")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_gc_store_dry_run() {
+        let tmp = setup_integration("test_gc_store_dry_run");
+
+        let store = tmp.local.join("store");
+        backup_versions(&store, &tmp.local, "foo", 3);
+        let options = GcOptions { keep: Some(0), ..Default::default() };
+
+        let result = gc_store(&store, &options, true);
+
+        assert_eq!(result.unwrap(), GcStats {
+            versions: 3, objects: 3, bytes: 18
+        });
+        assert_eq!(list_backups(&store, &tmp.local.join("foo")).unwrap().len(),
+                   3);
+        assert_eq!(fs::read_dir(store.join("objects")).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("100"), Ok(100));
+        assert_eq!(parse_size("2K"), Ok(2048));
+        assert_eq!(parse_size("3m"), Ok(3 * 1024 * 1024));
+        assert_eq!(parse_size("1G"), Ok(1024 * 1024 * 1024));
+        assert_eq!(parse_size("1.5G"),
+                   Err(String::from("invalid size '1.5G'")));
+        assert_eq!(parse_size(""), Err(String::from("invalid size ''")));
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(100), "100 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_time(951782400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_time(1728561845), "2024-10-10 12:04:05 UTC");
+    }
+}
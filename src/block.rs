@@ -0,0 +1,169 @@
+//! Managed blocks inside files that coliru doesn't fully own
+//!
+//! A block command keeps a marked block containing the contents of a source
+//! file up to date inside a destination file, leaving the rest of the
+//! destination alone:
+//!
+//! ```text
+//! # BEGIN coliru bashrc_extra
+//! alias ll='ls -l'
+//! # END coliru bashrc_extra
+//! ```
+//!
+//! ```
+//! let rendered = render_block("bashrc_extra", "~/.bashrc", Path::new("."),
+//!                             "#")?;
+//! copy_file(rendered.path().to_str().unwrap(), "~/.bashrc")?;
+//! ```
+
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use tempfile::NamedTempFile;
+use super::local::resolve_local_path;
+
+/// Inserts or replaces a marked block inside the contents of a file
+///
+/// The block is delimited by `{comment} BEGIN coliru {name}` and
+/// `{comment} END coliru {name}` lines. An existing block with the same name is
+/// replaced in place, and otherwise the block is appended. Returns an Err if
+/// the contents contain a begin marker without a matching end marker.
+///
+/// ```
+/// let merged = merge_block("export A=1\n", "alias ll='ls -l'\n", "extra",
+///                          "#")?;
+/// assert_eq!(merged, "export A=1\n# BEGIN coliru extra\nalias ll='ls -l'\n\
+///                     # END coliru extra\n");
+/// ```
+pub fn merge_block(contents: &str, block: &str, name: &str, comment: &str) ->
+    Result<String> {
+
+    let begin = format!("{} BEGIN coliru {}", comment, name);
+    let end = format!("{} END coliru {}", comment, name);
+
+    let mut managed = format!("{}\n{}", begin, block);
+    if !block.is_empty() && !block.ends_with('\n') { managed.push('\n'); }
+    managed.push_str(&end);
+    managed.push('\n');
+
+    // Find the byte ranges of the marker lines, including their line breaks
+    let mut begin_start = None;
+    let mut offset = 0;
+    for line in contents.split_inclusive('\n') {
+        let line_end = offset + line.len();
+        match begin_start {
+            None if line.trim_end() == begin => begin_start = Some(offset),
+            Some(start) if line.trim_end() == end => {
+                return Ok(format!("{}{}{}", &contents[..start], managed,
+                                  &contents[line_end..]));
+            },
+            _ => {},
+        }
+        offset = line_end;
+    }
+    if begin_start.is_some() {
+        bail!("Found \"{}\" without a matching \"{}\"", begin, end);
+    }
+
+    let mut merged = contents.to_owned();
+    if !merged.is_empty() && !merged.ends_with('\n') { merged.push('\n'); }
+    merged.push_str(&managed);
+    Ok(merged)
+}
+
+/// Writes a copy of a destination file with a source file merged into it as a
+/// marked block to a temporary file
+///
+/// Tildes in the destination are expanded if present, relative paths are
+/// relative to `base_dir`, and a missing destination is treated as empty. The
+/// temporary file has the same permissions as the destination (or the source
+/// if the destination doesn't exist) and is deleted when the returned value is
+/// dropped.
+///
+/// ```
+/// let rendered = render_block("bashrc_extra", "~/.bashrc",
+///                             Path::new("dotfiles"), "#")?;
+/// ```
+pub fn render_block(src: &str, dst: &str, base_dir: &Path, comment: &str) ->
+    Result<NamedTempFile> {
+
+    let _src = resolve_local_path(src, base_dir);
+    let block = fs::read_to_string(&_src).with_context(|| {
+        format!("Failed to read {}", src)
+    })?;
+    let _dst = resolve_local_path(dst, base_dir);
+    let (contents, metadata) = match fs::metadata(&_dst) {
+        Ok(metadata) => {
+            let contents = fs::read_to_string(&_dst).with_context(|| {
+                format!("Failed to read {}", dst)
+            })?;
+            (contents, metadata)
+        },
+        Err(_) => (String::new(), fs::metadata(&_src).with_context(|| {
+            format!("Failed to read {}", src)
+        })?),
+    };
+    let merged = merge_block(&contents, &block, src, comment).with_context(|| {
+        format!("Failed to merge {} into {}", src, dst)
+    })?;
+
+    let mut rendered = NamedTempFile::new()
+        .context("Failed to create temporary file")?;
+    rendered.write_all(merged.as_bytes())
+        .and_then(|_| {
+            fs::set_permissions(rendered.path(), metadata.permissions())
+        })
+        .with_context(|| format!("Failed to merge {} into {}", src, dst))?;
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{setup_integration, write_file};
+
+    #[test]
+    fn test_merge_block_basic() {
+        let block = "alias ll='ls -l'";
+
+        let appended = merge_block("export A=1", block, "extra", "#").unwrap();
+        let replaced = merge_block(&format!("{}export B=2\n", appended),
+                                   "alias la='ls -a'\n", "extra", "#")
+            .unwrap();
+        let unterminated = merge_block("# BEGIN coliru extra\n", block,
+                                       "extra", "#");
+
+        assert_eq!(appended, "export A=1\n# BEGIN coliru extra\n\
+                              alias ll='ls -l'\n# END coliru extra\n");
+        assert_eq!(replaced, "export A=1\n# BEGIN coliru extra\n\
+                              alias la='ls -a'\n# END coliru extra\n\
+                              export B=2\n");
+        assert_eq!(unterminated.unwrap_err().to_string(),
+                   "Found \"# BEGIN coliru extra\" without a matching \
+                    \"# END coliru extra\"");
+    }
+
+    #[test]
+    fn test_render_block_basic() {
+        let tmp = setup_integration("test_render_block_basic");
+
+        write_file(&tmp.local.join("foo"), "contents of foo\n");
+        write_file(&tmp.local.join("bar"), "contents of bar\n");
+
+        let rendered = render_block("foo", "bar", &tmp.local, "\"").unwrap();
+        let missing = render_block("foo", "missing", &tmp.local, "#").unwrap();
+
+        assert_eq!(fs::read_to_string(rendered.path()).unwrap(), "\
+contents of bar
+\" BEGIN coliru foo
+contents of foo
+\" END coliru foo
+");
+        assert_eq!(fs::read_to_string(missing.path()).unwrap(), "\
+# BEGIN coliru foo
+contents of foo
+# END coliru foo
+");
+    }
+}
@@ -0,0 +1,123 @@
+//! Homebrew commands for installing formulae, casks, and Brewfiles
+//!
+//! Each brew command is checked before it's installed, so that packages that
+//! are already installed are reported as unchanged instead of being reinstalled
+//! or upgraded.
+//!
+//! ```
+//! let package = BrewOptions::Formula(String::from("ripgrep"));
+//! println!("{}", describe(&package));
+//! ```
+
+use super::local::quote;
+use super::manifest::BrewOptions;
+
+/// Returns a description of a brew command for output
+///
+/// ```
+/// assert_eq!(describe(&BrewOptions::Cask(String::from("firefox"))),
+///            "Install cask firefox with Homebrew");
+/// ```
+pub fn describe(brew: &BrewOptions) -> String {
+    match brew {
+        BrewOptions::Formula(name) => {
+            format!("Install formula {} with Homebrew", name)
+        },
+        BrewOptions::Cask(name) => {
+            format!("Install cask {} with Homebrew", name)
+        },
+        BrewOptions::Brewfile(path) => {
+            format!("Install bundle {} with Homebrew", path)
+        },
+    }
+}
+
+/// Returns a shell command that succeeds if a brew command's packages are
+/// already installed, without printing anything
+///
+/// Brewfiles are checked without upgrades, so outdated packages count as
+/// installed.
+///
+/// ```
+/// assert_eq!(check_command(&BrewOptions::Formula(String::from("git"))),
+///            "brew list --formula 'git' >/dev/null 2>&1");
+/// ```
+pub fn check_command(brew: &BrewOptions) -> String {
+    let command = match brew {
+        BrewOptions::Formula(name) => {
+            format!("brew list --formula {}", quote(name))
+        },
+        BrewOptions::Cask(name) => {
+            format!("brew list --cask {}", quote(name))
+        },
+        BrewOptions::Brewfile(path) => {
+            format!("brew bundle check --no-upgrade --file={}", quote(path))
+        },
+    };
+    format!("{} >/dev/null 2>&1", command)
+}
+
+/// Returns a shell command that installs a brew command's packages
+///
+/// ```
+/// assert_eq!(install_command(&BrewOptions::Cask(String::from("firefox"))),
+///            "brew install --cask 'firefox'");
+/// ```
+pub fn install_command(brew: &BrewOptions) -> String {
+    match brew {
+        BrewOptions::Formula(name) => {
+            format!("brew install --formula {}", quote(name))
+        },
+        BrewOptions::Cask(name) => {
+            format!("brew install --cask {}", quote(name))
+        },
+        BrewOptions::Brewfile(path) => {
+            format!("brew bundle install --no-upgrade --file={}", quote(path))
+        },
+    }
+}
+
+/// Returns the Brewfiles of a set of brew commands, which are sent to another
+/// machine along with scripts
+///
+/// ```
+/// let srcs = brewfiles(&step.brew);
+/// ```
+pub fn brewfiles(brews: &[BrewOptions]) -> Vec<&str> {
+    brews.iter().filter_map(|x| match x {
+        BrewOptions::Brewfile(path) => Some(path.as_str()),
+        _ => None,
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_brew_commands() {
+        let formula = BrewOptions::Formula(String::from("git"));
+        let cask = BrewOptions::Cask(String::from("firefox"));
+        let brewfile = BrewOptions::Brewfile(String::from("mac/Brewfile"));
+
+        assert_eq!(describe(&formula), "Install formula git with Homebrew");
+        assert_eq!(describe(&brewfile),
+                   "Install bundle mac/Brewfile with Homebrew");
+        assert_eq!(check_command(&cask),
+                   "brew list --cask 'firefox' >/dev/null 2>&1");
+        assert_eq!(check_command(&brewfile),
+                   "brew bundle check --no-upgrade --file='mac/Brewfile' \
+                    >/dev/null 2>&1");
+        assert_eq!(install_command(&formula), "brew install --formula 'git'");
+        assert_eq!(install_command(&brewfile),
+                   "brew bundle install --no-upgrade --file='mac/Brewfile'");
+    }
+
+    #[test]
+    fn test_brewfiles() {
+        let brews = [BrewOptions::Formula(String::from("git")),
+                     BrewOptions::Brewfile(String::from("Brewfile"))];
+
+        assert_eq!(brewfiles(&brews), vec!["Brewfile"]);
+    }
+}
@@ -0,0 +1,172 @@
+//! Removal of files that coliru leaves behind while installing dotfiles
+//!
+//! Cleaning the local machine removes staging directories left behind by
+//! installs that were interrupted, along with every backup if requested.
+//! Cleaning another machine removes the scripts and temporary directories that
+//! were sent to it over SSH, but keeps any files that were installed there.
+//!
+//! ```
+//! let failure = clean(&Host::default(), false, false)?;
+//! let failure = clean(&host, false, false)?;
+//! ```
+
+use anyhow::{Context, Result, bail};
+use colored::Colorize;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use super::backup::{GcOptions, default_store, format_size, gc_store};
+use super::core::{SSH_INSTALL_DIR, STAGING_PREFIX};
+use super::exit::{Failure, classify};
+use super::local::CapturedOutput;
+use super::remote::{BINARY_DIR, REPO_DIR};
+use super::ssh::{Host, RemoteOs, quote_path, send_command};
+use super::state::{REMOTE_STATE_FILE, load_remote_state};
+
+/// How long a staging directory must go unmodified before it's considered to be
+/// left behind, so that the staging directories of running installs are kept
+const STAGING_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Removes the files that coliru left behind on the local machine or on
+/// another machine via SSH
+///
+/// If `host` is the local machine, staging directories left behind on it are
+/// removed, along with every backup if `backups` is true. Otherwise, files in
+/// `~/.coliru` on `host` that aren't recorded in its install state (e.g.
+/// scripts) are removed, along with temporary directories left behind by
+/// `--sudo`. If `dry_run` is true, the files are listed without removing them.
+/// Returns an Err if the files can't be listed and returns the class of failure
+/// with the greatest precedence among any other errors otherwise.
+///
+/// ```
+/// let failure = clean(&host, false, true)?;
+/// ```
+pub fn clean(host: &Host, backups: bool, dry_run: bool) ->
+    Result<Option<Failure>> {
+
+    if host.is_local() {
+        clean_local(backups, dry_run)
+    } else if backups {
+        bail!("Backups are only stored on the local machine");
+    } else {
+        clean_remote(host, dry_run)
+    }
+}
+
+/// Removes staging directories left behind on the local machine, and every
+/// backup if `backups` is true
+fn clean_local(backups: bool, dry_run: bool) -> Result<Option<Failure>> {
+    let dirs = leftover_staging_dirs()?;
+    if dirs.is_empty() && !backups {
+        println!("Nothing to clean");
+        return Ok(None);
+    }
+
+    let mut failure = None;
+    for dir in dirs {
+        print!("Remove {}", dir.display());
+        if dry_run {
+            println!(" (DRY RUN)");
+            continue;
+        }
+
+        match fs::remove_dir_all(&dir) {
+            Ok(()) => println!(" ({})", "removed".green()),
+            Err(why) => {
+                println!(" ({})", "FAILED".bold().red());
+                let why = anyhow::Error::new(why).context(format!(
+                    "Failed to remove {}", dir.display()));
+                eprintln!("  {} {:#}", "Error:".bold().red(), why);
+                failure = failure.max(Some(classify(&why, Failure::LocalFile)));
+            },
+        }
+    }
+
+    if backups {
+        let options = GcOptions { keep: Some(0), ..GcOptions::default() };
+        let stats = gc_store(&default_store()?, &options, dry_run)?;
+        println!("{} {} backups and {} files ({}){}",
+                 if dry_run { "Would remove" } else { "Removed" },
+                 stats.versions, stats.objects, format_size(stats.bytes),
+                 if dry_run { " (DRY RUN)" } else { "" });
+    }
+    Ok(failure)
+}
+
+/// Returns the staging directories in the temporary directory that haven't
+/// been modified recently
+fn leftover_staging_dirs() -> Result<Vec<PathBuf>> {
+    let temp_dir = env::temp_dir();
+    let items = fs::read_dir(&temp_dir)
+        .and_then(|x| x.collect::<Result<Vec<_>, _>>())
+        .with_context(|| {
+            format!("Failed to list contents of {}", temp_dir.display())
+        })?;
+
+    let now = SystemTime::now();
+    let mut dirs: Vec<PathBuf> = items.iter().filter(|item| {
+        let is_staging = item.file_name().to_string_lossy()
+            .starts_with(STAGING_PREFIX);
+        let is_old = item.metadata().and_then(|x| x.modified()).map(|x| {
+            now.duration_since(x).unwrap_or_default() >= STAGING_MAX_AGE
+        });
+        is_staging && item.path().is_dir() && is_old.unwrap_or(false)
+    }).map(|x| x.path()).collect();
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Removes the files in `~/.coliru` on another machine that aren't recorded in
+/// its install state, along with temporary directories left behind by `--sudo`
+///
+/// The directories used by [`remote_exec`](super::remote::remote_exec) are
+/// kept, since dotfiles installed by it may link to files in them.
+fn clean_remote(host: &Host, dry_run: bool) -> Result<Option<Failure>> {
+    if host.options.remote_os == RemoteOs::Windows {
+        bail!("Cleaning Windows hosts isn't supported");
+    }
+
+    let installed = load_remote_state(host)?;
+    let mut captured = CapturedOutput::default();
+    let command = format!("find ~/{dir} \\( -type f -o -type l \\) ! -path \
+                           ~/{dir}/{REMOTE_STATE_FILE} ! -path \
+                           '*/{dir}/{REPO_DIR}/*' ! -path \
+                           '*/{dir}/{BINARY_DIR}/*' 2>/dev/null; \
+                           find ~ -maxdepth 1 -name '{dir}-root-*' \
+                           2>/dev/null; true", dir=SSH_INSTALL_DIR);
+    send_command(&command, host, Some(&mut captured)).with_context(|| {
+        format!("Failed to list files in {}:~/{}", host, SSH_INSTALL_DIR)
+    })?;
+
+    let mut files: Vec<&str> = captured.stdout.lines()
+        .map(|x| x.trim_end_matches('\r'))
+        .filter(|x| !x.is_empty() && !installed.iter().any(|y| y == x))
+        .collect();
+    files.sort();
+    if files.is_empty() {
+        println!("Nothing to clean");
+        return Ok(None);
+    }
+
+    for file in &files {
+        print!("Remove {}:{}", host, file);
+        println!("{}", if dry_run { " (DRY RUN)" } else { "" });
+    }
+    if dry_run { return Ok(None); }
+
+    let mut command = String::from("rm -rf");
+    for file in &files {
+        command.push(' ');
+        command.push_str(&quote_path(file));
+    }
+    command.push_str(&format!(" && find ~/{} -mindepth 1 -type d -empty \
+                               -delete", SSH_INSTALL_DIR));
+    let result = send_command(&command, host, None)
+        .context("Failed to remove files");
+    if let Err(why) = result {
+        eprintln!("  {} {:#}", "Error:".bold().red(), why);
+        return Ok(Some(classify(&why, Failure::Transport)));
+    }
+    Ok(None)
+}
@@ -1,11 +1,56 @@
 //! The coliru command line interface
 
-use anyhow::{Context, Result};
-use colored::{Colorize, control::set_override};
-use clap::{Parser, ColorChoice};
-use std::path::Path;
-use super::core::{install_manifest, list_tags};
-use super::manifest::parse_manifest_file;
+use anyhow::{Context, Result, anyhow, bail};
+use colored::{Colorize, control::{SHOULD_COLORIZE, set_override}};
+use clap::{Args as ClapArgs, Parser, Subcommand, ColorChoice, ValueEnum};
+use gethostname::gethostname;
+use glob::Pattern;
+use shellexpand::tilde;
+use std::env;
+use std::io::{IsTerminal, stderr, stdin, stdout};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use super::add::add_file;
+use super::backup::{GcOptions, default_store, format_size, format_time,
+    gc_store, list_backups, parse_size, restore_file};
+use super::clean::clean;
+use super::config::{Config, default_config_path, load_config};
+use super::container::{Container, Engine, check_container};
+use super::core::{InstallOptions, install_manifest, list_tags};
+use super::doctor::run_doctor;
+use super::exclude::parse_exclude;
+use super::exit::{Error, Failure, classify};
+use super::export::{ansible_playbook, shell_script};
+use super::facts::{container_facts, filter_platform_steps, local_facts,
+    remote_facts};
+use super::fix::fix_links;
+use super::hash::HashAlgorithm;
+use super::import::import_chezmoi;
+use super::init::init_manifest;
+use super::lint::run_lint;
+use super::local::{set_escalation, set_replaced_files};
+use super::manifest::{LinkMode, Manifest, Shell, apply_host_overrides,
+    cleanup_scripts, get_manifest_tags, get_tag_usage, parse_env_var,
+    parse_manifest_file, parse_manifest_reader,
+    preserve_mtimes, read_tag_rules_file, relative_links, remote_links,
+    resolve_known_folders, resolve_xdg_dirs, script_env, script_shells,
+    script_timeouts, validate_exclusive_tags, validate_tag_rules};
+use super::plan::{build_plan, print_plan};
+use super::remote::remote_exec;
+use super::reporter::{ConsoleReporter, JsonReporter, NullReporter,
+    QuietReporter, Reporter};
+use super::ssh::{Host, HostKeyPolicy, RemoteOs, SshOptions, SudoPassword,
+                 Transport, check_ssh_options, check_sudo_password,
+                 preflight_connection, read_ssh_password, split_port,
+                 with_port};
+use super::tag_expr::{join_tag_rules, unknown_tags};
+use super::tui::{select_steps, select_tags};
+use super::uninstall::uninstall;
+use super::verify::verify_manifest;
+use super::watch::watch_manifest;
+
+/// The manifest path that reads the manifest from stdin
+const STDIN_MANIFEST: &str = "-";
 
 /// CLI about description
 const HELP_ABOUT: &str = "A minimal, flexible, dotfile installer";
@@ -13,85 +58,1501 @@ const HELP_ABOUT: &str = "A minimal, flexible, dotfile installer";
 /// CLI examples to be appended to help output
 const HELP_EXAMPLES: &str = "\
 Examples:
+  # Generate a starting manifest for the dotfiles in the current directory
+  coliru init
+
   # List tags in manifest
   coliru manifest.yml --list-tags
 
   # Preview installation steps with tags matching A && (B || C) && !D
   coliru manifest.yml --tag-rules A B,C ^D --dry-run
 
+  # Preview installation steps with tags matching (A || B) && !C
+  coliru manifest.yml --tag-rules '(A || B) && !C' --dry-run
+
+  # Show the resolved installation steps, including skipped steps
+  coliru plan manifest.yml --tag-rules A B,C ^D
+
   # Install dotfiles on local machine
   coliru manifest.yml --tag-rules A B,C ^D
 
+  # Re-create links that broke after moving the dotfile repository
+  coliru manifest.yml --tag-rules A B,C ^D --fix-links
+
+  # Install dotfiles and re-install them whenever they are edited
+  coliru manifest.yml --tag-rules A B,C ^D --watch
+
   # Install dotfiles to user@hostname over SSH
-  coliru manifest.yml --tag-rules A B,C ^D --host user@hostname";
+  coliru manifest.yml --tag-rules A B,C ^D --host user@hostname
+
+  # Restore the most recent backup of a file that was overwritten
+  coliru restore ~/.bashrc
+
+  # Move a file into the dotfile repository and link it back
+  coliru add ~/.config/kitty/kitty.conf --tags linux macos
+
+  # Check for problems that would prevent installing dotfiles over SSH
+  coliru doctor manifest.yml --host user@hostname
+
+  # Keep only the 5 most recent backups of each file
+  coliru gc --keep 5
+
+  # Remove the dotfiles that were installed on user@hostname
+  coliru uninstall --host user@hostname
+
+  # Remove scripts that were copied to user@hostname
+  coliru clean --host user@hostname
+
+  # Check dotfiles on user@hostname without making any changes
+  coliru verify manifest.yml --tag-rules A B,C ^D --host user@hostname";
 
 /// Arguments to the coliru CLI
 #[derive(Parser, Debug)]
 #[command(version, color=ColorChoice::Never, arg_required_else_help=true,
-          about=HELP_ABOUT, after_help=HELP_EXAMPLES)]
+          args_conflicts_with_subcommands=true, subcommand_negates_reqs=true,
+          disable_help_subcommand=true, about=HELP_ABOUT,
+          after_help=HELP_EXAMPLES)]
 struct Args {
-    /// The path to the coliru manifest file
-    pub manifest: String,
+    /// The subcommand to run instead of installing dotfiles
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// The path to the coliru manifest file, or - to read it from stdin
+    pub manifest: Option<String>,
 
     /// The set of tag rules to enforce
     #[arg(short, long, value_name="RULE", num_args=0..)]
-    pub tag_rules: Vec<String>,
+    pub tag_rules: Option<Vec<String>>,
+
+    /// Read additional tag rules from a file
+    #[arg(long, value_name="PATH")]
+    pub tag_rules_file: Option<String>,
+
+    /// Skip steps tagged for another OS or CPU architecture (the default with
+    /// --host)
+    #[arg(long)]
+    pub auto_tags: bool,
+
+    /// Don't skip steps tagged for another OS or CPU architecture
+    #[arg(long, conflicts_with="auto_tags")]
+    pub no_auto_tags: bool,
+
+    /// Fail if a tag rule uses a tag that isn't in the manifest
+    #[arg(long)]
+    pub strict_tags: bool,
 
     /// List available tags and quit without installing
     #[arg(short, long)]
     pub list_tags: bool,
 
+    /// Show which steps use each tag and match the rules with --list-tags
+    #[arg(long, requires="list_tags")]
+    pub tag_usage: bool,
+
     /// Do a trial run without any permanent changes
     #[arg(short = 'n', long)]
     pub dry_run: bool,
 
+    /// Show changes to file contents when installing or during a dry run
+    #[arg(long, visible_alias="show-diff")]
+    pub diff: bool,
+
     /// Install dotfiles on another machine over SSH
-    #[arg(long, default_value="", hide_default_value=true)]
-    pub host: String,
+    #[arg(long)]
+    pub host: Option<String>,
+
+    #[command(flatten)]
+    pub container: ContainerArgs,
+
+    #[command(flatten)]
+    pub ssh: SshArgs,
+
+    /// Install untyped links as a symlink, hardlink, or copy
+    #[arg(long, value_name="MODE", hide_possible_values=true)]
+    pub link_mode: Option<LinkMode>,
+
+    /// Same as --link-mode copy
+    #[arg(long, conflicts_with="link_mode")]
+    pub copy: bool,
+
+    /// Keep the modification times of copied files
+    #[arg(long)]
+    pub preserve_mtime: bool,
+
+    /// Create symlinks with relative targets
+    #[arg(long)]
+    pub relative_links: bool,
+
+    /// Create symlinks instead of copies over SSH
+    #[arg(long)]
+    pub remote_links: bool,
+
+    /// Install dotfiles by running coliru on the host
+    #[arg(long, conflicts_with_all=["watch", "fix_links", "list_tags",
+                                    "report", "remote_links",
+                                    "cleanup_scripts", "docker", "podman"])]
+    pub remote_exec: bool,
+
+    /// Send this coliru binary to the host with --remote-exec
+    #[arg(long, requires="remote_exec")]
+    pub push_binary: bool,
+
+    /// Execute up to N parallel steps concurrently
+    #[arg(short, long, value_name="N", default_value_t=1,
+          value_parser=clap::value_parser!(u16).range(1..))]
+    pub jobs: u16,
+
+    /// Kill scripts that run for longer than SECS seconds
+    #[arg(long, value_name="SECS",
+          value_parser=clap::value_parser!(u64).range(1..))]
+    pub script_timeout: Option<u64>,
+
+    /// Execute scripts with a different SHELL (e.g. bash or pwsh)
+    #[arg(long, value_name="SHELL", hide_possible_values=true)]
+    pub shell: Option<Shell>,
+
+    /// Delete scripts from the host after they succeed
+    #[arg(long)]
+    pub cleanup_scripts: bool,
+
+    /// Execute scripts with the environment variable NAME=VALUE
+    #[arg(long, value_name="NAME=VALUE", value_parser=parse_env_var)]
+    pub env: Vec<(String, String)>,
+
+    /// Re-install dotfiles when they change
+    #[arg(short, long, conflicts_with_all=["dry_run", "list_tags"])]
+    pub watch: bool,
+
+    /// Use sudo to install files in locations that aren't writable
+    #[arg(long)]
+    pub sudo: bool,
+
+    /// Give sudo a password over SSH with MODE (none, tty, or env)
+    #[arg(long, value_name="MODE", requires="sudo",
+          hide_possible_values=true)]
+    pub sudo_password: Option<SudoPassword>,
+
+    /// Replace existing files that coliru didn't install
+    #[arg(short, long)]
+    pub force: bool,
 
-    /// Interpret link commands as copy commands
+    /// Only re-create broken or misdirected symlinks
+    #[arg(long, conflicts_with_all=["watch", "list_tags", "host", "docker",
+                                    "podman"])]
+    pub fix_links: bool,
+
+    /// Skip commands whose src or dst matches a glob PATTERN
+    #[arg(long, value_name="PATTERN", value_parser=parse_exclude)]
+    pub exclude: Vec<Pattern>,
+
+    /// Write a JSON or JUnit (.xml) report of each step
+    #[arg(long, value_name="PATH",
+          conflicts_with_all=["watch", "list_tags", "fix_links"])]
+    pub report: Option<PathBuf>,
+
+    /// Print each action, outcome, and error as a line of JSON
+    #[arg(long, conflicts_with_all=["watch", "list_tags", "fix_links",
+                                    "remote_exec", "quiet"])]
+    pub json: bool,
+
+    /// Only print errors, retries, and a summary of any errors
+    #[arg(short, long, conflicts_with_all=["list_tags", "fix_links"])]
+    pub quiet: bool,
+
+    /// Disable color output
     #[arg(long)]
+    pub no_color: bool,
+}
+
+/// Coliru subcommands
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check installed dotfiles without making any changes
+    Verify(VerifyArgs),
+
+    /// Restore a backup of a file that was overwritten by coliru
+    Restore(RestoreArgs),
+
+    /// Generate a starting manifest for a directory of dotfiles
+    Init(InitArgs),
+
+    /// Remove old backups according to retention policies
+    Gc(GcArgs),
+
+    /// Move a file into the dotfile repository and link it back
+    Add(AddArgs),
+
+    /// Check the environment for problems that would prevent installation
+    Doctor(DoctorArgs),
+
+    /// Show the resolved installation steps without executing them
+    Plan(PlanArgs),
+
+    /// Remove the dotfiles that coliru installed
+    Uninstall(UninstallArgs),
+
+    /// Remove scripts and staging files that coliru left behind
+    Clean(CleanArgs),
+
+    /// Convert dotfiles managed by another tool into a coliru manifest
+    Import(ImportArgs),
+
+    /// Convert a manifest into the format of another provisioning tool
+    Export(ExportArgs),
+
+    /// Choose the steps to install from an interactive list
+    Tui(TuiArgs),
+
+    /// Check a manifest for common mistakes without installing anything
+    Lint(LintArgs),
+}
+
+/// Arguments to the verify subcommand
+#[derive(ClapArgs, Debug)]
+struct VerifyArgs {
+    /// The path to the coliru manifest file
+    pub manifest: Option<String>,
+
+    /// The set of tag rules to enforce
+    #[arg(short, long, value_name="RULE", num_args=0..)]
+    pub tag_rules: Option<Vec<String>>,
+
+    /// Read additional tag rules from a file
+    #[arg(long, value_name="PATH")]
+    pub tag_rules_file: Option<String>,
+
+    /// Check dotfiles on another machine over SSH
+    #[arg(long)]
+    pub host: Option<String>,
+
+    #[command(flatten)]
+    pub ssh: SshArgs,
+
+    /// Install untyped links as a symlink, hardlink, or copy
+    #[arg(long, value_name="MODE", hide_possible_values=true)]
+    pub link_mode: Option<LinkMode>,
+
+    /// Same as --link-mode copy
+    #[arg(long, conflicts_with="link_mode")]
     pub copy: bool,
 
+    /// The algorithm used to compare file contents
+    #[arg(long, value_name="ALGORITHM", default_value_t=HashAlgorithm::Sha256)]
+    pub hash: HashAlgorithm,
+
+    /// Print a JSON report containing file hashes
+    #[arg(long)]
+    pub json: bool,
+
+    /// Disable color output
+    #[arg(long)]
+    pub no_color: bool,
+}
+
+/// Arguments to the restore subcommand
+#[derive(ClapArgs, Debug)]
+struct RestoreArgs {
+    /// The path of the overwritten file
+    pub dst: String,
+
+    /// The version to restore, where 1 is the oldest [default: latest]
+    #[arg(long, value_name="N", conflicts_with="list",
+          value_parser=clap::value_parser!(u32).range(1..))]
+    pub version: Option<u32>,
+
+    /// List the available versions and quit without restoring
+    #[arg(short, long)]
+    pub list: bool,
+}
+
+/// Arguments to the init subcommand
+#[derive(ClapArgs, Debug)]
+struct InitArgs {
+    /// The directory containing the dotfiles
+    #[arg(default_value=".")]
+    pub dir: String,
+}
+
+/// Arguments to the import subcommand
+#[derive(ClapArgs, Debug)]
+struct ImportArgs {
+    /// The tool that manages the dotfiles
+    #[command(subcommand)]
+    pub source: ImportSource,
+}
+
+/// The tools that dotfiles can be imported from
+#[derive(Subcommand, Debug)]
+enum ImportSource {
+    /// Import a chezmoi source directory
+    Chezmoi(ChezmoiArgs),
+}
+
+/// Arguments to the import chezmoi subcommand
+#[derive(ClapArgs, Debug)]
+struct ChezmoiArgs {
+    /// The chezmoi source directory (e.g. ~/.local/share/chezmoi)
+    pub source_dir: String,
+
+    /// The directory to write the manifest and dotfiles to
+    #[arg(short, long, value_name="DIR", default_value=".")]
+    pub output: String,
+}
+
+/// Arguments to the export subcommand
+#[derive(ClapArgs, Debug)]
+struct ExportArgs {
+    /// The format to convert the manifest into
+    #[command(subcommand)]
+    pub format: ExportFormat,
+}
+
+/// The formats that manifests can be exported to
+#[derive(Subcommand, Debug)]
+enum ExportFormat {
+    /// Print an Ansible playbook that installs the dotfiles
+    Ansible(ExportManifestArgs),
+
+    /// Print a POSIX shell script that installs the dotfiles
+    Shell(ExportManifestArgs),
+}
+
+/// Arguments to the export subcommands
+#[derive(ClapArgs, Debug)]
+struct ExportManifestArgs {
+    /// The path to the coliru manifest file
+    pub manifest: Option<String>,
+
+    /// The set of tag rules to enforce
+    #[arg(short, long, value_name="RULE", num_args=0..)]
+    pub tag_rules: Option<Vec<String>>,
+
+    /// Read additional tag rules from a file
+    #[arg(long, value_name="PATH")]
+    pub tag_rules_file: Option<String>,
+
+    /// Apply the host overrides of a machine
+    #[arg(long, value_name="NAME")]
+    pub hostname: Option<String>,
+
+    /// Install untyped links as a symlink, hardlink, or copy
+    #[arg(long, value_name="MODE", hide_possible_values=true)]
+    pub link_mode: Option<LinkMode>,
+
+    /// Same as --link-mode copy
+    #[arg(long, conflicts_with="link_mode")]
+    pub copy: bool,
+}
+
+/// Arguments to the gc subcommand
+#[derive(ClapArgs, Debug)]
+struct GcArgs {
+    /// Keep at most N backups of each file
+    #[arg(long, value_name="N")]
+    pub keep: Option<usize>,
+
+    /// Remove the oldest backups until they use at most SIZE (e.g. 500M)
+    #[arg(long, value_name="SIZE", value_parser=parse_size)]
+    pub max_size: Option<u64>,
+
+    /// Remove backups of files outside of the given directories
+    #[arg(long, value_name="DIR", num_args=1..)]
+    pub root: Vec<String>,
+
+    /// Show what would be removed without removing anything
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+}
+
+/// Arguments to the add subcommand
+#[derive(ClapArgs, Debug)]
+struct AddArgs {
+    /// The path of the file to add
+    pub path: String,
+
+    /// The path to the coliru manifest file [default: manifest.yml]
+    #[arg(short, long)]
+    pub manifest: Option<String>,
+
+    /// The tags of the new manifest step
+    #[arg(long, value_name="TAG", num_args=1..)]
+    pub tags: Vec<String>,
+}
+
+/// Arguments to the doctor subcommand
+#[derive(ClapArgs, Debug)]
+struct DoctorArgs {
+    /// The path to the coliru manifest file to check
+    pub manifest: Option<String>,
+
+    /// The set of tag rules to enforce
+    #[arg(short, long, value_name="RULE", num_args=0..)]
+    pub tag_rules: Option<Vec<String>>,
+
+    /// Read additional tag rules from a file
+    #[arg(long, value_name="PATH")]
+    pub tag_rules_file: Option<String>,
+
+    /// Check the connection to another machine over SSH
+    #[arg(long)]
+    pub host: Option<String>,
+
+    #[command(flatten)]
+    pub ssh: SshArgs,
+
+    /// Disable color output
+    #[arg(long)]
+    pub no_color: bool,
+}
+
+/// Arguments to the uninstall subcommand
+#[derive(ClapArgs, Debug)]
+struct UninstallArgs {
+    /// Remove dotfiles from another machine over SSH
+    #[arg(long)]
+    pub host: Option<String>,
+
+    #[command(flatten)]
+    pub ssh: SshArgs,
+
+    /// Show what would be removed without removing anything
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Disable color output
+    #[arg(long)]
+    pub no_color: bool,
+}
+
+/// Arguments to the clean subcommand
+#[derive(ClapArgs, Debug)]
+struct CleanArgs {
+    /// Remove scripts from another machine over SSH
+    #[arg(long)]
+    pub host: Option<String>,
+
+    #[command(flatten)]
+    pub ssh: SshArgs,
+
+    /// Also remove every backup of overwritten files
+    #[arg(long, conflicts_with="host")]
+    pub backups: bool,
+
+    /// Show what would be removed without removing anything
+    #[arg(short = 'n', long)]
+    pub dry_run: bool,
+
+    /// Disable color output
+    #[arg(long)]
+    pub no_color: bool,
+}
+
+/// Arguments to the plan subcommand
+#[derive(ClapArgs, Debug)]
+struct PlanArgs {
+    /// The path to the coliru manifest file
+    pub manifest: Option<String>,
+
+    /// The set of tag rules to enforce
+    #[arg(short, long, value_name="RULE", num_args=0..)]
+    pub tag_rules: Option<Vec<String>>,
+
+    /// Read additional tag rules from a file
+    #[arg(long, value_name="PATH")]
+    pub tag_rules_file: Option<String>,
+
+    /// Plan an installation on another machine over SSH
+    #[arg(long)]
+    pub host: Option<String>,
+
+    #[command(flatten)]
+    pub ssh: SshArgs,
+
+    /// Install untyped links as a symlink, hardlink, or copy
+    #[arg(long, value_name="MODE", hide_possible_values=true)]
+    pub link_mode: Option<LinkMode>,
+
+    /// Same as --link-mode copy
+    #[arg(long, conflicts_with="link_mode")]
+    pub copy: bool,
+
+    /// Print the plan as JSON
+    #[arg(long)]
+    pub json: bool,
+
+    /// Disable color output
+    #[arg(long)]
+    pub no_color: bool,
+}
+
+/// Arguments to the tui subcommand
+#[derive(ClapArgs, Debug)]
+struct TuiArgs {
+    /// The path to the coliru manifest file
+    pub manifest: Option<String>,
+
+    /// The set of tag rules that select the initial steps
+    #[arg(short, long, value_name="RULE", num_args=0..)]
+    pub tag_rules: Option<Vec<String>>,
+
+    /// Read additional tag rules from a file
+    #[arg(long, value_name="PATH")]
+    pub tag_rules_file: Option<String>,
+
+    /// Install dotfiles on another machine over SSH
+    #[arg(long)]
+    pub host: Option<String>,
+
+    #[command(flatten)]
+    pub container: ContainerArgs,
+
+    #[command(flatten)]
+    pub ssh: SshArgs,
+
+    /// Install untyped links as a symlink, hardlink, or copy
+    #[arg(long, value_name="MODE", hide_possible_values=true)]
+    pub link_mode: Option<LinkMode>,
+
+    /// Same as --link-mode copy
+    #[arg(long, conflicts_with="link_mode")]
+    pub copy: bool,
+
+    /// Print a diff of each file that's updated
+    #[arg(long)]
+    pub diff: bool,
+
+    /// Replace existing files that coliru didn't install
+    #[arg(long)]
+    pub force: bool,
+
     /// Disable color output
     #[arg(long)]
     pub no_color: bool,
 }
 
+/// Arguments to the lint subcommand
+#[derive(ClapArgs, Debug)]
+struct LintArgs {
+    /// The path to the coliru manifest file, or - to read it from stdin
+    pub manifest: Option<String>,
+
+    /// Disable color output
+    #[arg(long)]
+    pub no_color: bool,
+}
+
+/// Arguments that select a container to install dotfiles in instead of a host
+#[derive(ClapArgs, Clone, Debug, Default)]
+struct ContainerArgs {
+    /// Install dotfiles in the Docker CONTAINER
+    #[arg(long, value_name="CONTAINER",
+          conflicts_with_all=["host", "port", "transport", "podman"])]
+    pub docker: Option<String>,
+
+    /// Install dotfiles in the Podman CONTAINER
+    #[arg(long, value_name="CONTAINER",
+          conflicts_with_all=["host", "port", "transport"])]
+    pub podman: Option<String>,
+}
+impl ContainerArgs {
+    /// Returns the selected container, if any
+    fn container(&self) -> Option<Container> {
+        match (&self.docker, &self.podman) {
+            (Some(name), _) => Some(Container::new(Engine::Docker, name)),
+            (_, Some(name)) => Some(Container::new(Engine::Podman, name)),
+            _ => None,
+        }
+    }
+}
+
+/// Arguments that control how to connect to the host over SSH
+#[derive(ClapArgs, Clone, Debug, Default)]
+struct SshArgs {
+    /// Connect to the host over SSH on a different PORT
+    #[arg(short, long, value_name="PORT")]
+    pub port: Option<u16>,
+
+    /// Authenticate with the host using the private key at PATH
+    #[arg(long, value_name="PATH")]
+    pub ssh_key: Option<String>,
+
+    /// Read SSH options from the configuration file at PATH
+    #[arg(long, value_name="PATH")]
+    pub ssh_config: Option<String>,
+
+    /// Connect to the host through the bastion HOST
+    #[arg(long, value_name="HOST")]
+    pub jump: Option<String>,
+
+    /// Check host keys with POLICY (strict, accept-new, or off)
+    #[arg(long, value_name="POLICY", hide_possible_values=true)]
+    pub host_key_policy: Option<HostKeyPolicy>,
+
+    /// Send files to the host with TRANSPORT (e.g. scp or rsync)
+    #[arg(long, value_name="TRANSPORT", hide_possible_values=true)]
+    pub transport: Option<Transport>,
+
+    /// Compress data sent to the host over SSH
+    #[arg(long)]
+    pub compress: bool,
+
+    /// Give up connecting to the host after SECS seconds
+    #[arg(long, value_name="SECS")]
+    pub connect_timeout: Option<u64>,
+
+    /// Forward the local SSH agent to the host
+    #[arg(long)]
+    pub forward_agent: bool,
+
+    /// Fail instead of prompting for SSH passwords
+    #[arg(long)]
+    pub batch_mode: bool,
+
+    /// Prompt once for the SSH password and reuse it
+    #[arg(long)]
+    pub ssh_password: bool,
+
+    /// Retry SSH transfers up to N times after network failures
+    #[arg(long, value_name="N")]
+    pub ssh_retries: Option<u32>,
+
+    /// Treat the host as running OS (unix or windows)
+    #[arg(long, value_name="OS", hide_possible_values=true)]
+    pub remote_os: Option<RemoteOs>,
+}
+
+/// The options shared by commands that install or inspect dotfiles, after
+/// falling back to environment variables and then the user configuration for
+/// any options that weren't provided on the command line
+struct Options {
+    /// The path to the coliru manifest file, if one was provided
+    pub manifest: Option<String>,
+
+    /// The set of tag rules to enforce
+    pub tag_rules: Vec<String>,
+
+    /// The machine to install dotfiles on, or an empty string for the local
+    /// machine
+    pub host: String,
+
+    /// The options used to connect to `host`
+    pub ssh: SshOptions,
+
+    /// How to install link commands without a type
+    pub link_mode: LinkMode,
+
+    /// The command used to install files that the current user can't write to
+    pub sudo_command: String,
+
+    /// How the sudo command gets a password on the host
+    pub sudo_password: SudoPassword,
+
+    /// Whether to skip steps tagged for another platform than the target's,
+    /// or None to only skip them when installing on another machine
+    pub auto_tags: Option<bool>,
+}
+impl Options {
+    /// Merges command line options with environment variables and the user
+    /// configuration, validating the tag rules and SSH options, disabling color
+    /// output if requested, and setting what happens to replaced files
+    ///
+    /// Tag rules read from `tag_rules_file` are combined with any tag rules
+    /// provided on the command line. The SSH password is read once here if one
+    /// was requested.
+    ///
+    /// ```
+    /// let ssh = SshArgs { port: None, ssh_key: None, ssh_config: None,
+    ///                    jump: None, host_key_policy: None,
+    ///                    transport: None, compress: false,
+    ///                    connect_timeout: None, forward_agent: false,
+    ///                    batch_mode: false, ssh_password: false,
+    ///                    ssh_retries: None, remote_os: None };
+    /// let options = Options::resolve(None, None, None, None, ssh, None,
+    ///                                false)?;
+    /// ```
+    fn resolve(manifest: Option<String>, tag_rules: Option<Vec<String>>,
+               tag_rules_file: Option<String>, host: Option<String>,
+               ssh: SshArgs, link_mode: Option<LinkMode>, no_color: bool) ->
+        Result<Options> {
+
+        let config = load_user_config()?;
+
+        let env_no_color = env_flag("COLIRU_NO_COLOR")?;
+        if no_color || env_no_color.or(config.no_color).unwrap_or(false) {
+            set_override(false);
+        }
+        set_replaced_files(config.replaced_files.unwrap_or_default());
+        let batch_mode = ssh.batch_mode || config.batch_mode.unwrap_or(false);
+        let ssh_password = ssh.ssh_password ||
+            config.ssh_password.unwrap_or(false);
+        if batch_mode && ssh_password {
+            bail!("Batch mode disables SSH password prompts");
+        }
+        let ssh_options = SshOptions {
+            identity_file: ssh.ssh_key.or_else(|| config.ssh_key.clone()),
+            config_file: ssh.ssh_config.or_else(|| config.ssh_config.clone()),
+            jump_host: ssh.jump.or_else(|| config.jump.clone()),
+            compress: ssh.compress || config.compress.unwrap_or(false),
+            connect_timeout: ssh.connect_timeout.or(config.connect_timeout),
+            forward_agent: ssh.forward_agent ||
+                config.forward_agent.unwrap_or(false),
+            batch_mode,
+            retries: ssh.ssh_retries.or(config.ssh_retries).unwrap_or_default(),
+            host_key_policy: ssh.host_key_policy.or(env_host_key_policy()?)
+                .or(config.host_key_policy),
+            transport: ssh.transport.or(config.transport).unwrap_or_default(),
+            remote_os: ssh.remote_os.or(config.remote_os).unwrap_or_default(),
+            ..SshOptions::default()
+        };
+        check_ssh_options(&ssh_options)?;
+
+        let env_tag_rules = env_var("COLIRU_TAG_RULES").map(|x| {
+            x.split_whitespace().map(String::from).collect()
+        });
+        let env_link_mode = env_link_mode()?;
+        let config_link_mode = config.link_mode.or_else(|| {
+            config.copy.map(copy_link_mode)
+        });
+
+        let tag_rules = match tag_rules_file {
+            Some(path) => {
+                let mut rules = read_tag_rules(&path)?;
+                rules.extend(tag_rules.unwrap_or_default());
+                Some(rules)
+            },
+            None => tag_rules,
+        };
+
+        let mut options = Options {
+            manifest: manifest.or_else(|| default_manifest(&config)),
+            tag_rules: tag_rules.or(env_tag_rules).or(config.tag_rules)
+                .unwrap_or_default(),
+            host: host.or_else(|| env_var("COLIRU_HOST")).or(config.host)
+                .unwrap_or_default(),
+            ssh: ssh_options,
+            link_mode: link_mode.or(env_link_mode).or(config_link_mode)
+                .unwrap_or_default(),
+            sudo_command: config.sudo_command
+                .unwrap_or_else(|| String::from("sudo")),
+            sudo_password: config.sudo_password.unwrap_or_default(),
+            auto_tags: config.auto_tags,
+        };
+        check_tag_rules(&options.tag_rules)?;
+        if let Some(port) = ssh.port {
+            if options.host.is_empty() {
+                bail!("A port was provided without a host");
+            }
+            options.host = with_port(&options.host, port);
+        }
+        if ssh_password && !options.host.is_empty() {
+            options.ssh.password = Some(read_ssh_password(&options.host)?);
+        }
+        Ok(options)
+    }
+
+    /// Returns the machine to install dotfiles on along with the options used
+    /// to connect to it
+    fn host(&self) -> Host {
+        Host::new(&self.host, self.ssh.clone())
+    }
+
+    /// Returns the path to the manifest file or an Err if one wasn't provided
+    fn require_manifest(&self) -> Result<String> {
+        match &self.manifest {
+            Some(manifest) => Ok(manifest.clone()),
+            None => bail!("No manifest was provided on the command line, in \
+                           COLIRU_MANIFEST, or in the config file"),
+        }
+    }
+}
+
 /// Runs the coliru CLI
 pub fn run() {
+    set_override(env_color());
     let args = Args::parse();
 
     match run_args(args) {
         Err(why) => {
             eprintln!("{} {:#}", "Error:".bold().red(), why);
-            std::process::exit(2);
+            std::process::exit(classify(&why, Failure::Critical).code());
         },
-        Ok(minor_errors) => {
-            std::process::exit(if minor_errors { 1 } else { 0 });
+        Ok(failure) => {
+            std::process::exit(failure.map_or(0, |x| x.code()));
         },
     }
 }
 
 /// Runs the coliru CLI according to a set of arguments
 ///
-/// Returns an Err if a critical occurs, Ok(true) if minor errors occurred, and
-/// Ok(false) if no errors occurred.
-fn run_args(args: Args) -> Result<bool> {
-    if args.no_color {
-        set_override(false);
+/// Returns an Err if a critical error occurs, the class of failure with the
+/// greatest precedence if minor errors occurred, and Ok(None) otherwise.
+fn run_args(args: Args) -> Result<Option<Failure>> {
+    match args.command {
+        Some(Command::Verify(sub_args)) => return run_verify(sub_args),
+        Some(Command::Restore(sub_args)) => return run_restore(sub_args),
+        Some(Command::Init(sub_args)) => return run_init(sub_args),
+        Some(Command::Gc(sub_args)) => return run_gc(sub_args),
+        Some(Command::Add(sub_args)) => return run_add(sub_args),
+        Some(Command::Doctor(sub_args)) => return run_doctor_args(sub_args),
+        Some(Command::Plan(sub_args)) => return run_plan(sub_args),
+        Some(Command::Uninstall(sub_args)) => return run_uninstall(sub_args),
+        Some(Command::Clean(sub_args)) => return run_clean(sub_args),
+        Some(Command::Import(sub_args)) => return run_import(sub_args),
+        Some(Command::Export(sub_args)) => return run_export(sub_args),
+        Some(Command::Tui(sub_args)) => return run_tui(sub_args),
+        Some(Command::Lint(sub_args)) => return run_lint_args(sub_args),
+        None => {},
     }
 
-    let manifest = parse_manifest_file(Path::new(&args.manifest))
-        .with_context(|| {
-            format!("Failed to parse {}", args.manifest)
-        })?;
+    let mut options = Options::resolve(args.manifest.clone(),
+                                       args.tag_rules.clone(),
+                                       args.tag_rules_file.clone(),
+                                       args.host.clone(), args.ssh.clone(),
+                                       cli_link_mode(args.link_mode, args.copy),
+                                       args.no_color)?;
+    if args.remote_exec {
+        if options.host.is_empty() {
+            bail!("--remote-exec requires a host");
+        }
+        let manifest = options.require_manifest()?;
+        if manifest == STDIN_MANIFEST {
+            bail!("--remote-exec requires a manifest file");
+        }
+        return remote_exec(Path::new(&manifest), &options.host(),
+                           &remote_exec_args(&args, &options),
+                           args.push_binary);
+    }
+
+    let container = args.container.container();
+    let manifest_path = options.require_manifest()?;
+    let mut manifest = parse_manifest(&manifest_path,
+                                      target_name(&options, &container))?;
+    if prompt_for_tags(&args, &options, &manifest_path) {
+        let tags = get_manifest_tags(manifest.clone());
+        if !tags.is_empty() {
+            options.tag_rules = select_tags(&tags, stdin().lock())?;
+            print_selected_rules(&options.tag_rules);
+        }
+    }
+    check_rule_tags(&options.tag_rules, &manifest, args.strict_tags)?;
+    if args.preserve_mtime {
+        manifest = preserve_mtimes(manifest);
+    }
+    if args.relative_links {
+        manifest = relative_links(manifest);
+    }
+    if args.remote_links {
+        manifest = remote_links(manifest);
+    }
+    let mut detected_tags = vec![];
+    if auto_tags(&args, &options) {
+        let facts = if let Some(container) = &container {
+            check_container(container)?;
+            container_facts(container)?
+        } else if options.host.is_empty() {
+            local_facts()
+        } else {
+            // Unreachable hosts are reported the same way as when installing
+            let host = options.host();
+            preflight_connection(&host)?;
+            remote_facts(&host)?
+        };
+        manifest = filter_platform_steps(manifest, &facts);
+        detected_tags = vec![facts.os, facts.arch];
+    }
+    if !args.list_tags {
+        validate_exclusive_tags(&manifest, &options.tag_rules, &detected_tags)
+            .context(Error::TagRules(String::from("Invalid tag rules")))?;
+    }
+    if let Some(timeout) = args.script_timeout {
+        manifest = script_timeouts(manifest, timeout);
+    }
+    if let Some(shell) = args.shell {
+        manifest = script_shells(manifest, shell);
+    }
+    if args.cleanup_scripts {
+        manifest = cleanup_scripts(manifest);
+    }
+    if !args.env.is_empty() {
+        manifest = script_env(manifest, &args.env);
+    }
+    if args.sudo {
+        set_escalation(&options.sudo_command);
+        let strategy = args.sudo_password.unwrap_or(options.sudo_password);
+        check_sudo_password(strategy)?;
+        options.ssh.sudo_password = strategy;
+    }
+
+    let install_options = InstallOptions {
+        tag_rules: options.tag_rules,
+        host: options.host,
+        ssh: options.ssh,
+        container,
+        dry_run: args.dry_run,
+        diff: args.diff,
+        link_mode: options.link_mode,
+        force: args.force,
+        jobs: args.jobs.into(),
+        exclude: args.exclude,
+        report: args.report,
+        reporter: reporter(args.json, args.quiet),
+        ..InstallOptions::default()
+    };
 
     if args.list_tags {
-        list_tags(manifest);
-        Ok(false)
+        list_tags(manifest, &install_options.tag_rules, args.tag_usage);
+        Ok(None)
+    } else if args.watch {
+        watch_manifest(manifest, &install_options)
+    } else if args.fix_links {
+        fix_links(manifest, &install_options.tag_rules,
+                  install_options.link_mode, args.dry_run,
+                  &install_options.exclude)
+    } else {
+        install_manifest(manifest, &install_options)
+    }
+}
+
+/// Checks whether to prompt for tags because no tag rules were given at all
+/// (an empty `--tag-rules` disables the prompt) and stdin is a terminal
+fn prompt_for_tags(args: &Args, options: &Options, manifest_path: &str) ->
+    bool {
+
+    options.tag_rules.is_empty() && args.tag_rules.is_none() &&
+        !args.list_tags && !args.json && manifest_path != STDIN_MANIFEST &&
+        stdin().is_terminal()
+}
+
+/// Prints the tag rules that were chosen interactively, so that they can be
+/// passed to `--tag-rules` next time
+fn print_selected_rules(tag_rules: &[String]) {
+    if tag_rules.is_empty() {
+        println!("No tags selected, installing every step");
     } else {
-        install_manifest(manifest, args.tag_rules, &args.host, args.dry_run,
-                         args.copy)
+        println!("Installing with --tag-rules {}",
+                 join_tag_rules(tag_rules, Shell::Sh));
+    }
+}
+
+/// Returns the destination of an install's output, which prints JSON if `json`
+/// is true and only prints errors if `quiet` is true
+fn reporter(json: bool, quiet: bool) -> Arc<dyn Reporter> {
+    if json {
+        Arc::new(JsonReporter)
+    } else if quiet {
+        Arc::new(QuietReporter::default())
+    } else {
+        Arc::new(ConsoleReporter)
+    }
+}
+
+/// Checks whether to skip steps tagged for another platform than the machine
+/// that dotfiles are installed on, which is the default when installing on
+/// another machine
+fn auto_tags(args: &Args, options: &Options) -> bool {
+    if args.no_auto_tags {
+        return false;
+    }
+    let remote = !options.host.is_empty() ||
+        args.container.container().is_some();
+    args.auto_tags || options.auto_tags.unwrap_or(remote)
+}
+
+/// Returns the name of the machine that dotfiles will be installed on, which
+/// is `container` if one was selected and the host otherwise
+fn target_name<'a>(options: &'a Options, container: &'a Option<Container>) ->
+    &'a str {
+
+    container.as_ref().map_or(&options.host, |x| &x.name)
+}
+
+/// Returns the arguments that make coliru on another machine install a manifest
+/// the same way that `args` would with `--remote-exec`
+///
+/// Options that were resolved from the environment or the user configuration
+/// (e.g. tag rules) are included, since coliru reads those on the other machine
+/// from its own environment and configuration.
+fn remote_exec_args(args: &Args, options: &Options) -> Vec<String> {
+    let mut remote_args = vec![];
+    if !options.tag_rules.is_empty() {
+        remote_args.push(String::from("--tag-rules"));
+        remote_args.extend(options.tag_rules.iter().cloned());
+    }
+    remote_args.push(String::from("--link-mode"));
+    remote_args.push(value_name(options.link_mode));
+
+    let flags = [
+        (auto_tags(args, options), "--auto-tags"),
+        (args.strict_tags, "--strict-tags"),
+        (args.dry_run, "--dry-run"),
+        (args.diff, "--diff"),
+        (args.preserve_mtime, "--preserve-mtime"),
+        (args.relative_links, "--relative-links"),
+        (args.sudo, "--sudo"),
+        (args.force, "--force"),
+        (args.quiet, "--quiet"),
+        (!SHOULD_COLORIZE.should_colorize(), "--no-color"),
+    ];
+    for (_, flag) in flags.iter().filter(|x| x.0) {
+        remote_args.push(flag.to_string());
+    }
+
+    if args.jobs != 1 {
+        remote_args.push(format!("--jobs={}", args.jobs));
+    }
+    if let Some(timeout) = args.script_timeout {
+        remote_args.push(format!("--script-timeout={}", timeout));
+    }
+    if let Some(shell) = args.shell {
+        remote_args.push(format!("--shell={}", value_name(shell)));
+    }
+    for (name, value) in &args.env {
+        remote_args.push(format!("--env={}={}", name, value));
+    }
+    // Exclude patterns were expanded with the local home directory
+    let home = tilde("~").to_string();
+    for pattern in &args.exclude {
+        let pattern = match pattern.as_str().strip_prefix(&home) {
+            Some(rest) => format!("~{}", rest),
+            None => pattern.as_str().to_owned(),
+        };
+        remote_args.push(format!("--exclude={}", pattern));
+    }
+    remote_args
+}
+
+/// Returns the name of a command line value (e.g. `hardlink`)
+fn value_name<T: ValueEnum>(value: T) -> String {
+    value.to_possible_value().map_or_else(String::new, |x| {
+        x.get_name().to_owned()
+    })
+}
+
+/// Runs the verify subcommand according to a set of arguments
+///
+/// Returns an Err if a critical error occurs, Ok(Some(Failure::Minor)) if any
+/// destinations aren't compliant, and Ok(None) otherwise.
+fn run_verify(args: VerifyArgs) -> Result<Option<Failure>> {
+    let options = Options::resolve(args.manifest, args.tag_rules,
+                                   args.tag_rules_file, args.host, args.ssh,
+                                   cli_link_mode(args.link_mode, args.copy),
+                                   args.no_color)?;
+    let manifest = parse_manifest(&options.require_manifest()?,
+                                  &options.host)?;
+    let compliant = verify_manifest(manifest, &options.tag_rules,
+                                    &options.host(), options.link_mode,
+                                    args.hash, args.json)?;
+    Ok(if compliant { None } else { Some(Failure::Minor) })
+}
+
+/// Runs the restore subcommand according to a set of arguments
+///
+/// Returns an Err if a critical error occurs and Ok(None) otherwise.
+fn run_restore(args: RestoreArgs) -> Result<Option<Failure>> {
+    let store = default_store()?;
+    let dst = Path::new(&args.dst);
+
+    if args.list {
+        for (i, backup) in list_backups(&store, dst)?.iter().enumerate() {
+            println!("{}  {}  {}", i+1, format_time(backup.time),
+                     &backup.hash[..12]);
+        }
+    } else {
+        let version = restore_file(&store, dst,
+                                   args.version.map(|x| x as usize))?;
+        println!("Restored version {} of {}", version, args.dst);
+    }
+    Ok(None)
+}
+
+/// Runs the init subcommand according to a set of arguments
+///
+/// Returns an Err if a critical error occurs and Ok(None) otherwise.
+fn run_init(args: InitArgs) -> Result<Option<Failure>> {
+    let (manifest_path, skipped) = init_manifest(Path::new(&args.dir))?;
+    println!("Created {}", manifest_path.display());
+    for name in skipped {
+        println!("Skipped {} (unrecognized)", name);
+    }
+    Ok(None)
+}
+
+/// Runs the import subcommand according to a set of arguments
+///
+/// Returns an Err if a critical error occurs and Ok(None) otherwise.
+fn run_import(args: ImportArgs) -> Result<Option<Failure>> {
+    match args.source {
+        ImportSource::Chezmoi(args) => {
+            let import = import_chezmoi(Path::new(&args.source_dir),
+                                        Path::new(&args.output))?;
+            println!("Created {}", import.manifest.display());
+            for name in import.templates {
+                println!("Review {} (template)", name);
+            }
+            for (name, reason) in import.skipped {
+                println!("Skipped {} ({})", name, reason);
+            }
+        },
+    }
+    Ok(None)
+}
+
+/// Runs the export subcommand according to a set of arguments
+///
+/// Returns an Err if a critical error occurs and Ok(None) otherwise.
+fn run_export(args: ExportArgs) -> Result<Option<Failure>> {
+    let (args, export): (_, fn(&Manifest, &[String], LinkMode) -> _) =
+        match args.format {
+            ExportFormat::Ansible(args) => (args, ansible_playbook),
+            ExportFormat::Shell(args) => (args, shell_script),
+        };
+    let options = Options::resolve(args.manifest, args.tag_rules,
+                                   args.tag_rules_file, None,
+                                   SshArgs::default(),
+                                   cli_link_mode(args.link_mode, args.copy),
+                                   false)?;
+    let path = options.require_manifest()?;
+    let manifest = parse_export_manifest(&path, args.hostname.as_deref())?;
+    print!("{}", export(&manifest, &options.tag_rules, options.link_mode)?);
+    Ok(None)
+}
+
+/// Runs the gc subcommand according to a set of arguments
+///
+/// Returns an Err if a critical error occurs and Ok(None) otherwise.
+fn run_gc(args: GcArgs) -> Result<Option<Failure>> {
+    let options = GcOptions {
+        keep: args.keep,
+        max_size: args.max_size,
+        roots: args.root.iter().map(PathBuf::from).collect(),
+    };
+    let stats = gc_store(&default_store()?, &options, args.dry_run)?;
+    println!("{} {} backups and {} files ({}){}",
+             if args.dry_run { "Would remove" } else { "Removed" },
+             stats.versions, stats.objects, format_size(stats.bytes),
+             if args.dry_run { " (DRY RUN)" } else { "" });
+    Ok(None)
+}
+
+/// Runs the add subcommand according to a set of arguments
+///
+/// Returns an Err if a critical error occurs and Ok(None) otherwise.
+fn run_add(args: AddArgs) -> Result<Option<Failure>> {
+    let config = load_user_config()?;
+    let manifest = args.manifest.or_else(|| default_manifest(&config))
+        .unwrap_or_else(|| String::from("manifest.yml"));
+    let added = add_file(Path::new(&manifest), &args.path, &args.tags)?;
+    println!("Moved {} to {}", args.path, added.src);
+    println!("Linked {} to {}", added.src, added.dst);
+    Ok(None)
+}
+
+/// Runs the doctor subcommand according to a set of arguments
+///
+/// Returns an Err if a critical error occurs, Ok(Some(Failure::Minor)) if any
+/// checks found errors, and Ok(None) otherwise.
+fn run_doctor_args(args: DoctorArgs) -> Result<Option<Failure>> {
+    let options = Options::resolve(args.manifest, args.tag_rules,
+                                   args.tag_rules_file, args.host, args.ssh,
+                                   None, args.no_color)?;
+    let manifest = options.manifest.as_ref().map(Path::new);
+    let healthy = run_doctor(manifest, &options.tag_rules, &options.host())?;
+    Ok(if healthy { None } else { Some(Failure::Minor) })
+}
+
+/// Runs the lint subcommand according to a set of arguments
+///
+/// Returns an Err if a critical error occurs, Ok(Some(Failure::Minor)) if the
+/// manifest has errors, and Ok(None) otherwise.
+fn run_lint_args(args: LintArgs) -> Result<Option<Failure>> {
+    let options = Options::resolve(args.manifest, None, None, None,
+                                   SshArgs::default(), None, args.no_color)?;
+    let manifest = read_manifest(&options.require_manifest()?)?;
+    let clean = run_lint(&manifest)?;
+    Ok(if clean { None } else { Some(Failure::Minor) })
+}
+
+/// Runs the uninstall subcommand according to a set of arguments
+///
+/// Returns an Err if a critical error occurs and returns the class of failure
+/// with the greatest precedence among any other errors otherwise.
+fn run_uninstall(args: UninstallArgs) -> Result<Option<Failure>> {
+    let options = Options::resolve(None, None, None, args.host, args.ssh,
+                                   None, args.no_color)?;
+    uninstall(&options.host(), args.dry_run)
+}
+
+/// Runs the clean subcommand according to a set of arguments
+///
+/// Returns an Err if a critical error occurs and otherwise returns the class
+/// of failure with the greatest precedence among any errors that occurred.
+fn run_clean(args: CleanArgs) -> Result<Option<Failure>> {
+    let options = Options::resolve(None, None, None, args.host, args.ssh,
+                                   None, args.no_color)?;
+    clean(&options.host(), args.backups, args.dry_run)
+}
+
+/// Runs the plan subcommand according to a set of arguments
+///
+/// Returns an Err if a critical error occurs and Ok(None) otherwise.
+fn run_plan(args: PlanArgs) -> Result<Option<Failure>> {
+    let options = Options::resolve(args.manifest, args.tag_rules,
+                                   args.tag_rules_file, args.host, args.ssh,
+                                   cli_link_mode(args.link_mode, args.copy),
+                                   args.no_color)?;
+    let manifest = parse_manifest(&options.require_manifest()?,
+                                  &options.host)?;
+    let plan = build_plan(&manifest, &options.tag_rules, &options.host(),
+                          options.link_mode)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&plan)
+            .context("Failed to serialize plan")?);
+    } else {
+        print_plan(&plan);
+    }
+    Ok(None)
+}
+
+/// Runs the tui subcommand according to a set of arguments
+///
+/// Returns an Err if a critical error occurs, the class of failure with the
+/// greatest precedence if minor errors occurred while installing the selected
+/// steps, and Ok(None) otherwise.
+fn run_tui(args: TuiArgs) -> Result<Option<Failure>> {
+    let options = Options::resolve(args.manifest, args.tag_rules,
+                                   args.tag_rules_file, args.host, args.ssh,
+                                   cli_link_mode(args.link_mode, args.copy),
+                                   args.no_color)?;
+    let manifest_path = options.require_manifest()?;
+    if manifest_path == STDIN_MANIFEST {
+        bail!("The tui subcommand requires a manifest file");
+    }
+    if !stdin().is_terminal() || !stdout().is_terminal() {
+        bail!("The tui subcommand requires an interactive terminal");
+    }
+    let container = args.container.container();
+    let manifest = parse_manifest(&manifest_path,
+                                  target_name(&options, &container))?;
+    check_rule_tags(&options.tag_rules, &manifest, false)?;
+
+    // The selected steps are installed regardless of the tag rules, which are
+    // still passed to their scripts
+    let install_options = InstallOptions {
+        tag_rules: options.tag_rules.clone(),
+        filter_steps: false,
+        host: options.host,
+        ssh: options.ssh,
+        container,
+        diff: args.diff,
+        link_mode: options.link_mode,
+        force: args.force,
+        ..InstallOptions::default()
+    };
+    let preview_options = InstallOptions {
+        dry_run: true,
+        ..install_options.clone()
+    };
+    let selected = select_steps(&manifest, &options.tag_rules, |x, observer| {
+        let options = InstallOptions {
+            reporter: Arc::new(NullReporter),
+            observer: Some(observer),
+            ..preview_options.clone()
+        };
+        install_manifest(x.clone(), &options).map(|_| ())
+    })?;
+    match selected {
+        Some(selected) => install_manifest(selected, &install_options),
+        None => Ok(None),
+    }
+}
+
+/// Reads the user configuration file, which is located at `$COLIRU_CONFIG` if
+/// set and the default location otherwise
+fn load_user_config() -> Result<Config> {
+    match env_var("COLIRU_CONFIG") {
+        Some(path) => load_config(Path::new(tilde(&path).as_ref())),
+        None => load_config(&default_config_path()?),
+    }
+}
+
+/// Returns the manifest path from `$COLIRU_MANIFEST` or the user configuration,
+/// expanding any tildes
+fn default_manifest(config: &Config) -> Option<String> {
+    env_var("COLIRU_MANIFEST").or_else(|| config.manifest.clone()).map(|x| {
+        tilde(&x).to_string()
+    })
+}
+
+/// Checks whether output should be colored according to the environment, before
+/// any `--no-color` flag or configuration is applied
+///
+/// Color is disabled by `NO_COLOR` or `TERM=dumb`, forced by `CLICOLOR_FORCE`,
+/// and otherwise only enabled if both stdout and stderr are terminals (e.g. not
+/// piped into a file or a CI log).
+fn env_color() -> bool {
+    let term = env_var("TERM");
+    if env_var("NO_COLOR").is_some() || term.as_deref() == Some("dumb") {
+        return false;
+    }
+    if env_var("CLICOLOR_FORCE").is_some_and(|x| x != "0") {
+        return true;
+    }
+    stdout().is_terminal() && stderr().is_terminal()
+}
+
+/// Reads an environment variable, treating empty values as unset
+fn env_var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|x| !x.is_empty())
+}
+
+/// Reads a boolean environment variable, returning an Err if its value isn't
+/// recognized
+///
+/// ```
+/// env::set_var("COLIRU_COPY", "yes");
+/// assert_eq!(env_flag("COLIRU_COPY")?, Some(true));
+/// ```
+fn env_flag(name: &str) -> Result<Option<bool>> {
+    match env_var(name).map(|x| x.to_lowercase()).as_deref() {
+        None => Ok(None),
+        Some("1" | "true" | "yes" | "on") => Ok(Some(true)),
+        Some("0" | "false" | "no" | "off") => Ok(Some(false)),
+        Some(value) => bail!("Invalid value for {}: {} (expected true or \
+                              false)", name, value),
+    }
+}
+
+/// Returns the link mode selected on the command line, if any
+fn cli_link_mode(link_mode: Option<LinkMode>, copy: bool) -> Option<LinkMode> {
+    link_mode.or(copy.then_some(LinkMode::Copy))
+}
+
+/// Returns the link mode that a `copy` flag from the environment or the user
+/// configuration stands for
+fn copy_link_mode(copy: bool) -> LinkMode {
+    if copy { LinkMode::Copy } else { LinkMode::default() }
+}
+
+/// Reads the link mode from `$COLIRU_LINK_MODE`, falling back to
+/// `$COLIRU_COPY`, and returns an Err if either value isn't recognized
+fn env_link_mode() -> Result<Option<LinkMode>> {
+    if let Some(value) = env_var("COLIRU_LINK_MODE") {
+        return match LinkMode::from_str(&value, true) {
+            Ok(mode) => Ok(Some(mode)),
+            Err(_) => bail!("Invalid value for COLIRU_LINK_MODE: {} (expected \
+                             symlink, hardlink, or copy)", value),
+        };
+    }
+    Ok(env_flag("COLIRU_COPY")?.map(copy_link_mode))
+}
+
+/// Returns the host key policy set in the environment, if any
+fn env_host_key_policy() -> Result<Option<HostKeyPolicy>> {
+    match env_var("COLIRU_HOST_KEY_POLICY") {
+        Some(value) => match HostKeyPolicy::from_str(&value, true) {
+            Ok(policy) => Ok(Some(policy)),
+            Err(_) => bail!("Invalid value for COLIRU_HOST_KEY_POLICY: {} \
+                             (expected strict, accept-new, or off)", value),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Parses a manifest file, or stdin if `path` is [`STDIN_MANIFEST`], adding
+/// context to any errors
+///
+/// Relative paths in a manifest read from stdin are resolved against the
+/// working directory.
+fn read_manifest(path: &str) -> Result<Manifest> {
+    let manifest = if path == STDIN_MANIFEST {
+        parse_manifest_reader(stdin().lock(), Path::new("."))
+    } else {
+        parse_manifest_file(Path::new(path))
+    };
+    manifest.with_context(|| {
+        Error::Manifest(format!("Failed to parse {}", path))
+    })
+}
+
+/// Parses a manifest (see [`read_manifest`]), applies the overrides for the
+/// machine that dotfiles will be installed on, and resolves XDG and Windows
+/// folder placeholders, adding context to any errors
+fn parse_manifest(path: &str, host: &str) -> Result<Manifest> {
+    let manifest = read_manifest(path)?;
+    let manifest = apply_host_overrides(manifest, &target_hostname(host));
+    let remote = !host.is_empty();
+    resolve_known_folders(manifest, remote)
+        .and_then(|x| resolve_xdg_dirs(x, remote))
+        .with_context(|| {
+            Error::Manifest(format!("Failed to parse {}", path))
+        })
+}
+
+/// Parses a manifest that's exported to another tool, applying the overrides
+/// for `hostname` if it's set and resolving placeholders to their defaults on
+/// other machines
+fn parse_export_manifest(path: &str, hostname: Option<&str>) ->
+    Result<Manifest> {
+
+    let manifest = read_manifest(path)?;
+    let manifest = match hostname {
+        Some(hostname) => apply_host_overrides(manifest, hostname),
+        None => manifest,
+    };
+    resolve_known_folders(manifest, true)
+        .and_then(|x| resolve_xdg_dirs(x, true))
+        .with_context(|| {
+            Error::Manifest(format!("Failed to parse {}", path))
+        })
+}
+
+/// Returns the hostname of the machine that dotfiles will be installed on,
+/// which is the local machine's hostname if `host` is empty and `host` without
+/// any username or port otherwise
+fn target_hostname(host: &str) -> String {
+    if host.is_empty() {
+        gethostname().to_string_lossy().to_string()
+    } else {
+        let destination = split_port(host).0;
+        destination.rsplit('@').next().unwrap_or(destination).to_owned()
+    }
+}
+
+/// Reads a tag rules file, classifying any errors
+fn read_tag_rules(path: &str) -> Result<Vec<String>> {
+    read_tag_rules_file(Path::new(tilde(path).as_ref())).with_context(|| {
+        Error::TagRules(format!("Failed to read {}", path))
+    })
+}
+
+/// Checks that a list of tag rules is well-formed, classifying any errors
+fn check_tag_rules(tag_rules: &[String]) -> Result<()> {
+    validate_tag_rules(tag_rules)
+        .context(Error::TagRules(String::from("Invalid tag rules")))
+}
+
+/// Prints a warning for each tag in a list of tag rules that isn't in a
+/// manifest's steps or exclusive tag groups, suggesting the most similar tag in
+/// the manifest if any
+///
+/// Returns an Err for the first such tag instead if `strict` is true.
+fn check_rule_tags(tag_rules: &[String], manifest: &Manifest, strict: bool) ->
+    Result<()> {
+
+    let mut tags: Vec<String> = get_tag_usage(manifest).into_keys().collect();
+    tags.extend(manifest.exclusive_tags.iter().flatten().cloned());
+    for rule in tag_rules {
+        for (tag, suggestion) in unknown_tags(rule, &tags) {
+            let mut message = format!("Tag rule \"{}\" refers to unknown tag \
+                                       {}", rule, tag);
+            if let Some(suggestion) = suggestion {
+                message.push_str(&format!(" (did you mean {}?)", suggestion));
+            }
+            if strict {
+                return Err(anyhow!(Error::TagRules(message)));
+            }
+            eprintln!("{} {}", "Warning:".bold().yellow(), message);
+        }
     }
+    Ok(())
 }
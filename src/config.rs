@@ -0,0 +1,216 @@
+//! User configuration of default command line options
+//!
+//! ```
+//! let config = load_config(&default_config_path()?)?;
+//! let host = config.host.unwrap_or_default();
+//! ```
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use shellexpand::tilde;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use super::local::ReplacedFiles;
+use super::manifest::{LinkMode, xdg_dir};
+use super::ssh::{HostKeyPolicy, RemoteOs, SudoPassword, Transport};
+
+/// Default values for command line options, which are overridden by any options
+/// that are provided on the command line
+#[derive(Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// The path to the coliru manifest file
+    pub manifest: Option<String>,
+
+    /// The set of tag rules to enforce
+    pub tag_rules: Option<Vec<String>>,
+
+    /// Whether to skip steps tagged for another platform than the target's,
+    /// which is only done for other machines by default
+    pub auto_tags: Option<bool>,
+
+    /// The machine to install dotfiles on over SSH
+    pub host: Option<String>,
+
+    /// The private key used to authenticate with the host over SSH
+    pub ssh_key: Option<String>,
+
+    /// The SSH configuration file used to connect to the host
+    pub ssh_config: Option<String>,
+
+    /// The bastion host used to reach the host over SSH
+    pub jump: Option<String>,
+
+    /// How the host's key is checked
+    pub host_key_policy: Option<HostKeyPolicy>,
+
+    /// How files and commands are sent to the host
+    pub transport: Option<Transport>,
+
+    /// Whether to compress data sent to the host over SSH
+    pub compress: Option<bool>,
+
+    /// How many seconds to wait for a connection to the host
+    pub connect_timeout: Option<u64>,
+
+    /// Whether to forward the local SSH agent to the host
+    pub forward_agent: Option<bool>,
+
+    /// Whether to fail instead of prompting for SSH passwords
+    pub batch_mode: Option<bool>,
+
+    /// Whether to prompt for the SSH password once and reuse it
+    pub ssh_password: Option<bool>,
+
+    /// How many times to retry transfers that fail because of network problems
+    pub ssh_retries: Option<u32>,
+
+    /// The operating system of the host
+    pub remote_os: Option<RemoteOs>,
+
+    /// Whether to interpret link commands as copy commands
+    pub copy: Option<bool>,
+
+    /// How to install link commands without a type, which takes precedence
+    /// over `copy`
+    pub link_mode: Option<LinkMode>,
+
+    /// Whether to disable color output
+    pub no_color: Option<bool>,
+
+    /// What happens to existing files that are replaced by copies and links
+    pub replaced_files: Option<ReplacedFiles>,
+
+    /// The command used to install files that the current user can't write to
+    /// when `--sudo` is provided (e.g. `doas`)
+    pub sudo_command: Option<String>,
+
+    /// How the sudo command gets a password on the host
+    pub sudo_password: Option<SudoPassword>,
+}
+
+/// Returns the location of the default configuration file
+///
+/// The file is located at `$XDG_CONFIG_HOME/coliru/config.toml`, which defaults
+/// to `~/.config/coliru/config.toml` on Unix and `%APPDATA%\coliru\config.toml`
+/// on Windows.
+///
+/// ```
+/// let path = default_config_path()?;
+/// ```
+pub fn default_config_path() -> Result<PathBuf> {
+    let dir = xdg_dir("XDG_CONFIG_HOME", "~/.config", "APPDATA")?;
+    Ok(PathBuf::from(tilde(&dir).to_string()).join("coliru")
+        .join("config.toml"))
+}
+
+/// Reads a configuration file, returning an empty configuration if the file
+/// doesn't exist
+///
+/// ```
+/// let config = load_config(Path::new("~/.config/coliru/config.toml"))?;
+/// ```
+pub fn load_config(path: &Path) -> Result<Config> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(why) if why.kind() == ErrorKind::NotFound => {
+            return Ok(Config::default());
+        },
+        Err(why) => {
+            return Err(why).with_context(|| {
+                format!("Failed to read {}", path.display())
+            });
+        },
+    };
+
+    toml::from_str(&contents).with_context(|| {
+        format!("Failed to parse {}", path.display())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{setup_integration, write_file};
+
+    #[test]
+    fn test_load_config_basic() {
+        let tmp = setup_integration("test_load_config_basic");
+
+        let path = tmp.local.join("config.toml");
+        write_file(&path, "\
+manifest = \"~/dotfiles/manifest.yml\"
+tag_rules = [\"linux\", \"^work\"]
+auto_tags = true
+host = \"laptop\"
+ssh_key = \"~/.ssh/deploy_key\"
+ssh_config = \"~/.ssh/coliru_config\"
+jump = \"bastion\"
+host_key_policy = \"accept-new\"
+transport = \"native\"
+compress = true
+connect_timeout = 10
+forward_agent = true
+batch_mode = true
+ssh_password = true
+ssh_retries = 3
+remote_os = \"windows\"
+copy = true
+link_mode = \"hardlink\"
+replaced_files = \"trash\"
+sudo_command = \"doas\"
+sudo_password = \"tty\"
+");
+
+        let result = load_config(&path);
+
+        assert_eq!(result.unwrap(), Config {
+            manifest: Some(String::from("~/dotfiles/manifest.yml")),
+            tag_rules: Some(vec![String::from("linux"), String::from("^work")]),
+            auto_tags: Some(true),
+            host: Some(String::from("laptop")),
+            ssh_key: Some(String::from("~/.ssh/deploy_key")),
+            ssh_config: Some(String::from("~/.ssh/coliru_config")),
+            jump: Some(String::from("bastion")),
+            host_key_policy: Some(HostKeyPolicy::AcceptNew),
+            transport: Some(Transport::Native),
+            compress: Some(true),
+            connect_timeout: Some(10),
+            forward_agent: Some(true),
+            batch_mode: Some(true),
+            ssh_password: Some(true),
+            ssh_retries: Some(3),
+            remote_os: Some(RemoteOs::Windows),
+            copy: Some(true),
+            link_mode: Some(LinkMode::Hardlink),
+            no_color: None,
+            replaced_files: Some(ReplacedFiles::Trash),
+            sudo_command: Some(String::from("doas")),
+            sudo_password: Some(SudoPassword::Tty),
+        });
+    }
+
+    #[test]
+    fn test_load_config_missing() {
+        let tmp = setup_integration("test_load_config_missing");
+
+        let result = load_config(&tmp.local.join("config.toml"));
+
+        assert_eq!(result.unwrap(), Config::default());
+    }
+
+    #[test]
+    fn test_load_config_unknown_field() {
+        let tmp = setup_integration("test_load_config_unknown_field");
+
+        let path = tmp.local.join("config.toml");
+        write_file(&path, "hots = \"laptop\"\n");
+
+        let result = load_config(&path);
+
+        assert_eq!(result.is_ok(), false);
+        assert_eq!(result.unwrap_err().to_string(),
+                   format!("Failed to parse {}", path.display()));
+    }
+}
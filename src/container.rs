@@ -0,0 +1,352 @@
+//! Container dotfile installation utilities
+//!
+//! Dotfiles are installed in running Docker and Podman containers without SSH.
+//! Files are staged like files for other machines (see
+//! [`stage_file`](super::ssh::stage_file)), copied into the container with
+//! `docker cp`, and then moved into place by the container's user so that they
+//! belong to that user instead of root. Commands are executed by `sh` with
+//! `docker exec`. Podman containers use the equivalent `podman` commands.
+//!
+//! ```
+//! let container = Container::new(Engine::Docker, "user@dev");
+//! stage_file("foo.sh", "~/foo.sh", Path::new("/tmp/staging"), false);
+//! send_staged_files(Path::new("/tmp/staging"), &container, None);
+//! send_command("sh ~/foo.sh", &container, None);
+//! ```
+
+use anyhow::{Context, Result, anyhow, bail};
+use std::fmt;
+use std::fs::remove_dir_all;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use super::exit::Error;
+use super::local::{CapturedOutput, escalation, execute_command,
+    execute_command_indented, quote};
+use super::ssh::{escalated_install_script, staged_attributes_command,
+    with_remote_timeout};
+
+/// The exit status used by `podman exec` (and by `docker exec` for some
+/// errors) to indicate that the engine failed rather than the command
+const ENGINE_ERROR_STATUS: i32 = 125;
+
+/// The number of staging directories copied into containers so far, which
+/// gives each copy its own temporary directory in the container
+static COPIES: AtomicUsize = AtomicUsize::new(0);
+
+/// A program that runs containers
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Engine {
+    /// Docker, which is controlled with the `docker` program
+    Docker,
+
+    /// Podman, which is controlled with the `podman` program
+    Podman,
+}
+impl Engine {
+    /// Returns the program that controls the engine's containers
+    ///
+    /// ```
+    /// assert_eq!(Engine::Podman.program(), "podman");
+    /// ```
+    pub fn program(self) -> &'static str {
+        match self {
+            Engine::Docker => "docker",
+            Engine::Podman => "podman",
+        }
+    }
+}
+impl fmt::Display for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Engine::Docker => write!(f, "Docker"),
+            Engine::Podman => write!(f, "Podman"),
+        }
+    }
+}
+
+/// A running container that dotfiles are installed in
+#[derive(Clone, Debug, PartialEq)]
+pub struct Container {
+    /// The engine that runs the container
+    pub engine: Engine,
+
+    /// The container in the form `[user@]name`, where `user` is the user that
+    /// commands are executed as instead of the container's default user
+    pub name: String,
+}
+impl Container {
+    /// Creates a new Container
+    ///
+    /// ```
+    /// let container = Container::new(Engine::Docker, "user@dev");
+    /// ```
+    pub fn new(engine: Engine, name: &str) -> Container {
+        Container { engine, name: name.to_owned() }
+    }
+
+    /// Returns the user that commands are executed as, or None for the
+    /// container's default user
+    fn user(&self) -> Option<&str> {
+        self.name.split_once('@').map(|x| x.0)
+    }
+
+    /// Returns the name of the container without its user
+    fn id(&self) -> &str {
+        self.name.split_once('@').map_or(&self.name, |x| x.1)
+    }
+}
+impl fmt::Display for Container {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Transfers the files in a staging directory to a container
+///
+/// Use [`stage_file`](super::ssh::stage_file) to produce a staging directory.
+/// The files are copied to a temporary directory with `docker cp` and given to
+/// the container's user, who then copies them to their destinations, so that
+/// they don't belong to root. If an escalation command has been set with
+/// `--sudo`, files outside of the home directory are placed by root instead,
+/// without the escalation command. The permissions and modification time of
+/// each staged file are applied to the transferred file. The contents of the
+/// staging directory are deleted after they are successfully transferred. The
+/// output of each command is captured instead of printed if `capture` is
+/// provided.
+///
+/// ```
+/// send_staged_files(Path::new("/tmp/staging"), &container, None)?;
+/// ```
+pub fn send_staged_files(staging_dir: &Path, container: &Container,
+                         mut capture: Option<&mut CapturedOutput>) ->
+    Result<()> {
+
+    let home_dir = staging_dir.join("home");
+    let root_dir = staging_dir.join("root");
+    if !home_dir.exists() && !root_dir.exists() { return Ok(()); }
+
+    // Files placed by root get their attributes from the install script
+    let escalate = escalation().is_some() && root_dir.exists();
+    let attributes_cmd = staged_attributes_command(staging_dir, !escalate)?;
+    let install_script = if escalate {
+        Some(escalated_install_script(&root_dir)?)
+    } else {
+        None
+    };
+
+    let mut owner = CapturedOutput::default();
+    send_command("echo \"$(id -u):$(id -g)\"", container, Some(&mut owner))
+        .context("Failed to get the container's user")?;
+    let temp_dir = format!("/tmp/coliru-{}-{}", std::process::id(),
+                           COPIES.fetch_add(1, Ordering::SeqCst));
+    copy_dir(staging_dir, &temp_dir, container, capture.as_deref_mut())?;
+
+    let mut root_cmd = format!("chown -R {} {temp_dir}", owner.stdout.trim());
+    if let Some(script) = install_script {
+        root_cmd = format!("{root_cmd} && sh -c {} sh {temp_dir}/root",
+                           quote(&script));
+    }
+    let mut user_cmds = vec![];
+    if home_dir.exists() {
+        user_cmds.push(format!("cp -R {temp_dir}/home/. ~"));
+    }
+    if root_dir.exists() && !escalate {
+        user_cmds.push(format!("cp -R {temp_dir}/root/. /"));
+    }
+    user_cmds.extend(attributes_cmd);
+
+    // The temporary directory is removed even if installation fails
+    let mut result = exec(&root_cmd, container, true, capture.as_deref_mut());
+    if result.is_ok() && !user_cmds.is_empty() {
+        result = send_command(&user_cmds.join(" && "), container,
+                              capture.as_deref_mut());
+    }
+    let cleanup = exec(&format!("rm -rf {temp_dir}"), container, true,
+                       capture);
+    result.context("Failed to place transferred files")?;
+    cleanup.context("Failed to remove transferred files")?;
+
+    for dir in [home_dir, root_dir] {
+        if !dir.exists() { continue; }
+        remove_dir_all(&dir).with_context(|| {
+            format!("Failed to remove staging dir {} after use", dir.display())
+        })?;
+    }
+    Ok(())
+}
+
+/// Copies the contents of a directory to a new directory in a container with
+/// `docker cp`, which makes every file belong to root
+fn copy_dir(src: &Path, dst: &str, container: &Container,
+            capture: Option<&mut CapturedOutput>) -> Result<()> {
+    let mut cmd = Command::new(container.engine.program());
+    cmd.arg("cp").arg(src.join(".")).arg(format!("{}:{}", container.id(),
+                                                 dst));
+    check_status(execute_command(&mut cmd, capture), container)
+}
+
+/// Executes a command in a container as the container's user
+///
+/// The command's stdout and stderr are captured instead of printed if `capture`
+/// is provided. Errors caused by the container engine rather than the command
+/// are classified as transport failures.
+///
+/// ```
+/// send_command("echo 'Hello World'", &container, None);
+/// ```
+pub fn send_command(command: &str, container: &Container,
+                    capture: Option<&mut CapturedOutput>) -> Result<()> {
+    exec(command, container, false, capture)
+}
+
+/// Executes a run command in a container, indenting each line of its output
+/// like [`run_command`](super::local::run_command)
+///
+/// The command is stopped with
+/// [`with_remote_timeout`](super::ssh::with_remote_timeout) if it runs for
+/// longer than `timeout`.
+///
+/// ```
+/// run_container_command("sh ~/foo.sh", &container, None, None);
+/// ```
+pub fn run_container_command(command: &str, container: &Container,
+                             timeout: Option<Duration>,
+                             capture: Option<&mut CapturedOutput>) ->
+    Result<()> {
+
+    let command = match timeout {
+        Some(timeout) => with_remote_timeout(command, timeout),
+        None => command.to_owned(),
+    };
+    let mut cmd = exec_command(&command, container, false);
+    check_status(execute_command_indented(&mut cmd, timeout, capture),
+                 container)
+}
+
+/// Checks whether commands can be executed in a container before anything is
+/// installed in it
+///
+/// Returns an Err classified as a transport failure if they can't, which
+/// describes whether the container doesn't exist or isn't running when that
+/// can be determined from the engine's error message.
+///
+/// ```
+/// check_container(&container)?;
+/// ```
+pub fn check_container(container: &Container) -> Result<()> {
+    let mut cmd = exec_command("exit 0", container, false);
+    let output = cmd.output().with_context(|| {
+        Error::Transport(format!("Failed to run {}", container.engine))
+    })?;
+    if output.status.success() { return Ok(()); }
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let message = stderr.to_lowercase();
+    let reason = if message.contains("no such container") {
+        " (the container doesn't exist)"
+    } else if ["is not running", "container state improper"].iter()
+        .any(|x| message.contains(x)) {
+        " (the container isn't running)"
+    } else {
+        ""
+    };
+    Err(anyhow!("{}", stderr.trim()).context(Error::Transport(format!(
+        "Failed to connect to {}{}", container, reason))))
+}
+
+/// Executes a command in a container as root if `root` is true and as the
+/// container's user otherwise
+fn exec(command: &str, container: &Container, root: bool,
+        capture: Option<&mut CapturedOutput>) -> Result<()> {
+    let mut cmd = exec_command(command, container, root);
+    check_status(execute_command(&mut cmd, capture), container)
+}
+
+/// Creates a Command that executes a command with `sh` in a container, as root
+/// if `root` is true and as the container's user otherwise
+///
+/// ```
+/// let mut cmd = exec_command("echo 'Hello World'", &container, false);
+/// ```
+fn exec_command(command: &str, container: &Container, root: bool) -> Command {
+    let mut cmd = Command::new(container.engine.program());
+    cmd.args(["exec", "-i"]);
+    if root {
+        cmd.args(["-u", "root"]);
+    } else if let Some(user) = container.user() {
+        cmd.args(["-u", user]);
+    }
+    cmd.args([container.id(), "sh", "-c", command]);
+    cmd
+}
+
+/// Checks the exit status of a container engine command, classifying errors
+/// caused by the engine rather than the command as transport failures
+fn check_status(status: Result<ExitStatus>, container: &Container) ->
+    Result<()> {
+
+    // Errors that were already classified (e.g. timeouts) aren't caused by the
+    // engine
+    let status = status.map_err(|why| {
+        if why.is::<Error>() { return why; }
+        why.context(Error::Transport(format!("Failed to run {}",
+                                             container.engine)))
+    })?;
+    if status.code() == Some(ENGINE_ERROR_STATUS) {
+        return Err(anyhow!(Error::Transport(format!(
+            "{} terminated unsuccessfully: {}", container.engine, status))));
+    }
+    if !status.success() {
+        bail!("{} terminated unsuccessfully: {}", container.engine, status);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_container_user() {
+        let container = Container::new(Engine::Docker, "user@dev");
+        assert_eq!(container.user(), Some("user"));
+        assert_eq!(container.id(), "dev");
+        assert_eq!(container.to_string(), "user@dev");
+    }
+
+    #[test]
+    fn test_container_no_user() {
+        let container = Container::new(Engine::Podman, "dev");
+        assert_eq!(container.user(), None);
+        assert_eq!(container.id(), "dev");
+        assert_eq!(container.to_string(), "dev");
+    }
+
+    #[test]
+    fn test_exec_command() {
+        let container = Container::new(Engine::Podman, "user@dev");
+        let cmd = exec_command("true", &container, false);
+        assert_eq!(cmd.get_program(), "podman");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(),
+                   ["exec", "-i", "-u", "user", "dev", "sh", "-c", "true"]);
+    }
+
+    #[test]
+    fn test_exec_command_root() {
+        let container = Container::new(Engine::Docker, "user@dev");
+        let cmd = exec_command("true", &container, true);
+        assert_eq!(cmd.get_program(), "docker");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(),
+                   ["exec", "-i", "-u", "root", "dev", "sh", "-c", "true"]);
+    }
+
+    #[test]
+    fn test_exec_command_default_user() {
+        let container = Container::new(Engine::Docker, "dev");
+        let cmd = exec_command("true", &container, false);
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(),
+                   ["exec", "-i", "dev", "sh", "-c", "true"]);
+    }
+}
@@ -1,181 +1,1831 @@
 //! Core manifest operation functions
 
-use anyhow::{Context, Result};
-use colored::{Colorize, ColoredString};
-use std::env::set_current_dir;
-use std::path::Path;
-use super::manifest::{Manifest, CopyLinkOptions, RunOptions, get_manifest_tags,
-    filter_manifest_steps};
-use super::local::{copy_file, link_file, run_command};
-use super::ssh::{resolve_path, send_command, send_staged_files, stage_file};
-use tempfile::tempdir;
+use anyhow::{Context, Result, anyhow};
+use glob::Pattern;
+use std::io::{Write, stderr, stdout};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use super::backup::default_state_file;
+use super::block::render_block;
+use super::cron;
+use super::defaults;
+use super::brew::{brewfiles, check_command, describe, install_command};
+use super::container::Container;
+use super::repo::{clone_command, current_command, exists_command,
+    fetch_command, update_command};
+use super::exclude::excluded_by;
+use super::exit::{Error, Failure, classify};
+use super::extension;
+use super::facts::apply_target_facts;
+use super::fetch;
+use super::preview::{Change, diff_files, preview_copy};
+use super::report::{Report, StepReport, write_report};
+use super::reporter::{ConsoleReporter, Event, Outcome, Reporter};
+use super::manifest::{BlockOptions, BrewOptions, Manifest, CopyLinkOptions,
+    CronOptions, DefaultsOptions, ExtensionOptions, FetchOptions, LinkMode,
+    PackageOptions, PatchOptions, RepoOptions, RunOptions, Step, SystemdOptions,
+    get_manifest_tags, get_tag_usage, filter_manifest_steps,
+    unmatched_tag_rule};
+use super::packages::{self, PackageManager, detect_command};
+use super::patch::render_patch;
+use super::render::render_copy;
+use super::local::{CapturedOutput, as_user, copy_file, resolve_local_path,
+    with_env};
+use super::ssh::{Host, SshOptions};
+use super::state::{InstallState, load_state, save_state};
+use super::systemd;
+use super::tag_expr::join_tag_rules;
+use super::target::{Target, backup_dst, new_target};
+use tempfile::{Builder, NamedTempFile};
 
 /// The base directory for SSH installs, relative to the home directory
-const SSH_INSTALL_DIR: &str = ".coliru";
+pub const SSH_INSTALL_DIR: &str = ".coliru";
+
+/// The prefix of the temporary directories that files are staged in
+pub const STAGING_PREFIX: &str = "coliru-staging-";
+
+/// Options that control how [`install_manifest`] installs a manifest
+///
+/// Options should be set by name, with the rest taken from
+/// [`InstallOptions::default`], so that adding an option doesn't change every
+/// caller.
+///
+/// ```
+/// let options = InstallOptions {
+///     tag_rules: vec![String::from("linux")],
+///     dry_run: true,
+///     ..InstallOptions::default()
+/// };
+/// ```
+#[derive(Clone)]
+pub struct InstallOptions {
+    /// The set of tag rules that steps must match to be installed, which are
+    /// also passed to scripts
+    pub tag_rules: Vec<String>,
+
+    /// Whether steps that don't match `tag_rules` are skipped, which is
+    /// disabled when the steps were already chosen another way (e.g.
+    /// interactively)
+    pub filter_steps: bool,
+
+    /// The machine to install dotfiles on, or an empty string for the local
+    /// machine
+    pub host: String,
+
+    /// The options used to connect to `host` and install files on it
+    pub ssh: SshOptions,
+
+    /// The container to install dotfiles in instead of `host`, or None to
+    /// install them on `host`
+    pub container: Option<Container>,
+
+    /// The directory that relative sources (and relative destinations on the
+    /// local machine) are relative to, or None for the manifest's
+    /// [`base_dir`](Manifest::base_dir)
+    pub base_dir: Option<PathBuf>,
+
+    /// Whether to print the commands without installing anything
+    pub dry_run: bool,
+
+    /// Whether to print a diff of each local file that's updated, or that
+    /// would be updated during a dry run
+    pub diff: bool,
+
+    /// How link commands without a type are installed
+    pub link_mode: LinkMode,
+
+    /// Whether to replace existing files on the local machine that coliru
+    /// didn't install
+    pub force: bool,
+
+    /// The maximum number of steps to execute concurrently
+    pub jobs: usize,
+
+    /// The patterns of sources and destinations whose commands are skipped
+    pub exclude: Vec<Pattern>,
+
+    /// The file that a report of the outcome of each step is written to
+    pub report: Option<PathBuf>,
+
+    /// The destination of the install's output
+    pub reporter: Arc<dyn Reporter>,
+
+    /// The observer that is notified of the progress of the install
+    pub observer: Option<Arc<dyn InstallObserver>>,
+}
+impl Default for InstallOptions {
+    fn default() -> Self {
+        InstallOptions {
+            tag_rules: vec![],
+            filter_steps: true,
+            host: String::new(),
+            ssh: SshOptions::default(),
+            container: None,
+            base_dir: None,
+            dry_run: false,
+            diff: false,
+            link_mode: LinkMode::default(),
+            force: false,
+            jobs: 1,
+            exclude: vec![],
+            report: None,
+            reporter: Arc::new(ConsoleReporter),
+            observer: None,
+        }
+    }
+}
+
+/// Receives the progress of an install as it happens, so that a frontend can
+/// render it without parsing the printed output
+///
+/// Every method does nothing by default. Steps are numbered from 1, and hooks
+/// are reported with a step of `None`. Methods may be called from several
+/// threads at once when steps are executed concurrently.
+///
+/// ```
+/// struct Progress;
+/// impl InstallObserver for Progress {
+///     fn on_step_start(&self, step: usize, step_count: usize) {
+///         println!("{}/{}", step, step_count);
+///     }
+/// }
+/// let options = InstallOptions { observer: Some(Arc::new(Progress)),
+///                                ..InstallOptions::default() };
+/// ```
+pub trait InstallObserver: Send + Sync {
+    /// Called before a step is executed
+    fn on_step_start(&self, _step: usize, _step_count: usize) {}
+
+    /// Called once a command has been executed (or skipped), with a
+    /// description of the command (e.g. `Copy foo to ~/foo`) and its outcome
+    /// (e.g. `created`, `skipped`, or `failed`)
+    fn on_action(&self, _step: Option<usize>, _description: &str,
+                 _outcome: &str) {}
+
+    /// Called when a command fails, after its action has been reported
+    fn on_error(&self, _step: Option<usize>, _message: &str) {}
+
+    /// Called once every step and hook has been executed without a critical
+    /// error, with the class of failure with the greatest precedence among any
+    /// errors that occurred
+    fn on_complete(&self, _failure: Option<Failure>) {}
+}
 
 /// Performs a dry-run check inside of a loop
 ///
-/// Will print `(DRY RUN)` and then continue to next loop iteration if `dry_run`
+/// Will report a dry run and then continue to next loop iteration if `dry_run`
 /// evaluates to `true`.
 macro_rules! check_dry_run {
-    ($dry_run:expr) => {
+    ($dry_run:expr, $output:expr) => {
         if $dry_run {
-            println!(" (DRY RUN)");
+            $output.finish_action(Outcome::DryRun, None);
+            continue;
+        }
+        $output.finish_action(Outcome::Pending, None);
+    }
+}
+
+/// Skips excluded commands inside of a loop
+///
+/// Will report `skipped` along with the matching pattern and then continue to
+/// next loop iteration if any of the paths match an exclude pattern.
+macro_rules! check_excluded {
+    ($exclude:expr, $paths:expr, $output:expr) => {
+        if let Some(pattern) = excluded_by($exclude, $paths) {
+            $output.finish_action(Outcome::Skipped, Some(pattern.to_string()));
             continue;
         }
-        println!("");
     }
 }
 
-/// Handles minor errors that occur during command execution and returns a bool
-/// indicating whether an error occurred
-fn handle_error(result: Result<()>) -> bool {
+/// Held while buffered events are reported, so that the events of concurrent
+/// steps aren't interleaved
+static REPORT_LOCK: Mutex<()> = Mutex::new(());
+
+/// The output of a step, which is either reported immediately or buffered
+/// until the step is complete so that the output of concurrent steps isn't
+/// interleaved
+struct StepOutput {
+    /// Whether events are buffered instead of reported immediately
+    buffered: bool,
+
+    /// The buffered events, in the order reported
+    events: Vec<Event>,
+
+    /// The error messages of any commands that failed
+    errors: Vec<String>,
+
+    /// The step that the output belongs to, or None for hooks
+    step: Option<usize>,
+
+    /// The destination of the step's events
+    reporter: Arc<dyn Reporter>,
+
+    /// The observer that is notified of each action and error
+    observer: Option<Arc<dyn InstallObserver>>,
+
+    /// The description of the action that is being executed, if any
+    action: Option<String>,
+}
+impl StepOutput {
+    /// Creates a new StepOutput
+    ///
+    /// ```
+    /// let output = StepOutput::new(true, Some(1), Arc::new(ConsoleReporter),
+    ///                              None);
+    /// ```
+    fn new(buffered: bool, step: Option<usize>, reporter: Arc<dyn Reporter>,
+           observer: Option<Arc<dyn InstallObserver>>) -> StepOutput {
+        StepOutput { buffered, events: vec![], errors: vec![], step, reporter,
+                     observer, action: None }
+    }
+
+    /// Reports an event, or buffers it if output is buffered
+    fn report(&mut self, event: Event) {
+        if self.buffered {
+            self.events.push(event);
+        } else {
+            self.reporter.report(&event);
+        }
+    }
+
+    /// Reports the start of an action with the label of its step or hook
+    /// (e.g. `1/3`) and a description of the action
+    fn start_action(&mut self, label: &str, description: &str) {
+        self.report(Event::Action { step: self.step, label: label.to_owned(),
+                                    description: description.to_owned() });
+        self.action = Some(description.to_owned());
+    }
+
+    /// Reports the outcome of the current action, and also reports it to the
+    /// observer unless the outcome is pending
+    fn finish_action(&mut self, outcome: Outcome, detail: Option<String>) {
+        let observed = match (outcome, &detail) {
+            (Outcome::DryRun, Some(detail)) => {
+                format!("{}: {}", outcome, detail)
+            },
+            _ => outcome.to_string(),
+        };
+        self.report(Event::Outcome { step: self.step, outcome, detail });
+        if outcome != Outcome::Pending {
+            self.end_action(&observed);
+        }
+    }
+
+    /// Reports the outcome of the current action to the observer
+    fn end_action(&mut self, outcome: &str) {
+        if let (Some(action), Some(observer)) = (self.action.take(),
+                                                 &self.observer) {
+            observer.on_action(self.step, &action, outcome);
+        }
+    }
+
+    /// Reports the captured output of a command
+    fn report_captured(&mut self, captured: CapturedOutput) {
+        for (text, stderr) in [(captured.stdout, false),
+                               (captured.stderr, true)] {
+            if !text.is_empty() {
+                self.report(Event::Output { step: self.step, text, stderr });
+            }
+        }
+    }
+
+    /// Runs a command, capturing its output unless it can be written directly
+    /// to the terminal
+    ///
+    /// ```
+    /// output.run(|capture| {
+    ///     run_command("echo 'Hello world'", Path::new("."), None, None,
+    ///                 capture)
+    /// });
+    /// ```
+    fn run<F>(&mut self, command: F) -> Result<()>
+        where F: FnOnce(Option<&mut CapturedOutput>) -> Result<()> {
+
+        if !self.buffered && self.reporter.streams_output() {
+            return command(None);
+        }
+        let mut captured = CapturedOutput::default();
+        let result = command(Some(&mut captured));
+        self.report_captured(captured);
+        result
+    }
+
+    /// Reports any buffered events at once
+    fn flush(self) {
+        let _lock = REPORT_LOCK.lock().unwrap_or_else(|x| x.into_inner());
+        for event in &self.events {
+            self.reporter.report(event);
+        }
+        // Errors writing to stdout/stderr can't be reported anywhere else
+        let _ = stdout().flush();
+        let _ = stderr().flush();
+    }
+}
+
+/// Handles minor errors that occur during command execution and returns the
+/// class of failure that occurred, if any
+///
+/// Errors that weren't classified when they were created are assumed to belong
+/// to the `default` class of failure.
+fn handle_error(result: Result<()>, default: Failure,
+                output: &mut StepOutput) -> Option<Failure> {
     if let Err(why) = result {
-        eprintln!("  {} {:#}", "Error:".bold().red(), why);
-        return true;
+        output.report(Event::Error { step: output.step,
+                                     message: format!("{:#}", why) });
+        output.errors.push(format!("{:#}", why));
+        if let Some(observer) = &output.observer {
+            observer.on_error(output.step, &format!("{:#}", why));
+        }
+        return Some(classify(&why, default));
     }
-    false
+    None
 }
 
 /// Prints the available tags in a manifest
-pub fn list_tags(manifest: Manifest) {
-    for tag in get_manifest_tags(manifest) {
-        println!("{}", tag);
+///
+/// If `usage` is true, the steps that use each tag and the steps that match
+/// `tag_rules` are printed as well.
+///
+/// ```
+/// list_tags(manifest, &[String::from("linux")], true);
+/// ```
+pub fn list_tags(manifest: Manifest, tag_rules: &[String], usage: bool) {
+    if !usage {
+        for tag in get_manifest_tags(manifest) {
+            println!("{}", tag);
+        }
+        return;
     }
+
+    let tag_usage = get_tag_usage(&manifest);
+    let width = tag_usage.keys().map(|x| x.chars().count()).max()
+        .unwrap_or_default();
+    for (tag, steps) in &tag_usage {
+        let noun = if steps.len() == 1 { "step" } else { "steps" };
+        println!("{:width$}  {} {} ({})", tag, steps.len(), noun,
+                 join_numbers(steps));
+    }
+
+    let matching: Vec<_> = manifest.steps.iter().enumerate().filter(|(_, x)| {
+        unmatched_tag_rule(tag_rules, &x.tags).is_none()
+    }).map(|(i, _)| i + 1).collect();
+    if !tag_usage.is_empty() { println!(); }
+    println!("Steps matching tag rules: {}", if matching.is_empty() {
+        String::from("none")
+    } else {
+        join_numbers(&matching)
+    });
 }
 
-/// Executes the steps in a coliru manifest according to a set of tag rules
+/// Joins a list of step numbers with commas for output
+fn join_numbers(numbers: &[usize]) -> String {
+    numbers.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(", ")
+}
+
+/// Splits a list of steps into batches that are executed one after another
+///
+/// Consecutive parallel steps form a single batch, and every other step is a
+/// batch of its own, so that it only starts once the steps before it finish.
 ///
-/// Returns an Err if a critical error occurs and returns a bool indicating
-/// whether any minor errors occurred otherwise
-pub fn install_manifest(manifest: Manifest, tag_rules: Vec<String>, host: &str,
-                        dry_run: bool, copy: bool) -> Result<bool> {
+/// ```
+/// // Steps 2 and 3 are parallel
+/// assert_eq!(step_batches(&steps), vec![0..1, 1..3, 3..4]);
+/// ```
+fn step_batches(steps: &[Step]) -> Vec<Range<usize>> {
+    let mut batches: Vec<Range<usize>> = vec![];
+    for (i, step) in steps.iter().enumerate() {
+        match batches.last_mut() {
+            Some(batch) if step.parallel && steps[i-1].parallel => {
+                batch.end = i+1;
+            },
+            _ => batches.push(i..i+1),
+        }
+    }
+    batches
+}
 
-    let filtered_manifest = filter_manifest_steps(manifest, &tag_rules);
+/// Executes the steps in a coliru manifest that match a set of tag rules
+///
+/// The manifest's pre-install hooks are executed before the first step and its
+/// post-install hooks are executed after the last step. Up to `jobs`
+/// consecutive parallel steps are executed concurrently, in which case the
+/// output of each step is printed once the step is complete. During a dry run,
+/// the change that each local copy and link command would make is printed. If
+/// `diff` is true, a diff of each local file that's updated (or would be during
+/// a dry run) is printed as well. Link commands without a type are installed
+/// according to `link_mode`, and all link commands are installed as copies over
+/// SSH. Existing files on the local machine that coliru didn't install aren't
+/// replaced unless `force` is true. Commands whose source or destination
+/// matches an `exclude` pattern are skipped. If `report` is provided, a report
+/// of the outcome of each step is written to it once all steps are complete. If
+/// `observer` is provided, it's notified of each step, command, and error.
+/// Returns an Err if a critical error occurs (including a failed hook or an
+/// unreachable host) and otherwise returns the class of failure with the
+/// greatest precedence among any minor errors that occurred.
+///
+/// ```
+/// let options = InstallOptions { host: String::from("user@hostname"),
+///                                ..InstallOptions::default() };
+/// let failure = install_manifest(manifest, &options)?;
+/// ```
+pub fn install_manifest(manifest: Manifest, options: &InstallOptions) ->
+    Result<Option<Failure>> {
 
-    let temp_dir = tempdir().context("Failed to create temporary directory")?;
-    set_current_dir(filtered_manifest.base_dir)
-        .context("Failed to set working directory")?;
+    let InstallOptions { ref tag_rules, filter_steps, ref host, ref ssh,
+                         ref container, ref base_dir, dry_run, diff, link_mode,
+                         force, jobs, ref exclude, ref report, ref reporter,
+                         ref observer } = *options;
+    let jobs = jobs.max(1);
+    let start_time = Instant::now();
+    let host = Host::new(host, ssh.clone());
+    let container = container.as_ref();
+    let base_dir = base_dir.clone().unwrap_or_else(|| {
+        manifest.base_dir.clone()
+    });
 
-    let mut errors = false;
+    let temp_dir = Builder::new().prefix(STAGING_PREFIX).tempdir()
+        .context("Failed to create temporary directory")?;
 
-    for (i, step) in filtered_manifest.steps.iter().enumerate() {
-        let step_str = format!("[{}/{}]", i+1,
-            filtered_manifest.steps.len()).bold();
+    // Only destinations on the local machine are recorded
+    let state_path = if host.is_local() && container.is_none() {
+        Some(default_state_file()?)
+    } else {
+        None
+    };
+    let state = Mutex::new(match &state_path {
+        Some(path) => load_state(path)?,
+        None => InstallState::default(),
+    });
 
-        errors |= execute_copies(&step.copy, host, temp_dir.path(), dry_run,
-                                 &step_str);
+    // Hooks don't install any files, so they don't need a staging directory
+    let hook_target = new_target(&host, container, &state, force, &base_dir,
+                                 temp_dir.path());
 
-        if !copy && host == "" {
-            errors |= execute_links(&step.link, dry_run, &step_str);
-        } else {
-            errors |= execute_copies(&step.link, host, temp_dir.path(), dry_run,
-                           &step_str);
+    // Unreachable hosts would otherwise fail every step with the same error
+    if !dry_run {
+        hook_target.connect()?;
+    }
+
+    let manifest = if filter_steps {
+        filter_manifest_steps(manifest, tag_rules)
+    } else {
+        manifest
+    };
+    let filtered_manifest = apply_target_facts(manifest,
+                                               hook_target.as_ref())?;
+
+    execute_hooks(&filtered_manifest.pre_install, "pre_install",
+                  hook_target.as_ref(), dry_run, reporter, observer)?;
+
+    let steps = &filtered_manifest.steps;
+    let failure = Mutex::new(None);
+    let step_reports = Mutex::new(vec![]);
+
+    for batch in step_batches(steps) {
+        let concurrent = jobs > 1 && batch.len() > 1;
+        let next_step = AtomicUsize::new(batch.start);
+
+        // Each worker repeatedly claims the next unexecuted step in the batch
+        // until none remain
+        let worker = || {
+            loop {
+                let i = next_step.fetch_add(1, Ordering::SeqCst);
+                if i >= batch.end { break; }
+
+                if let Some(observer) = observer {
+                    observer.on_step_start(i+1, steps.len());
+                }
+                let mut output = StepOutput::new(concurrent, Some(i+1),
+                                                 reporter.clone(),
+                                                 observer.clone());
+                // Each step gets its own staging directory so that concurrent
+                // steps don't transfer each other's files
+                let staging_dir = temp_dir.path().join(i.to_string());
+                let mut target = new_target(&host, container, &state, force,
+                                            &base_dir, &staging_dir);
+                let step_start_time = Instant::now();
+                let step_failure = execute_step(&steps[i], i, steps.len(),
+                                                tag_rules, target.as_mut(),
+                                                dry_run, diff, link_mode,
+                                                exclude, &mut output);
+                step_reports.lock().unwrap().push(StepReport {
+                    step: i+1,
+                    tags: steps[i].tags.clone(),
+                    failure: step_failure,
+                    errors: output.errors.clone(),
+                    duration: step_start_time.elapsed().as_secs_f64(),
+                });
+                output.flush();
+                let mut _failure = failure.lock().unwrap();
+                *_failure = (*_failure).max(step_failure);
+            }
+        };
+
+        thread::scope(|scope| {
+            for _ in 1..jobs.min(batch.len()) {
+                scope.spawn(worker);
+            }
+            worker();
+        });
+    }
+
+    if let (Some(path), false) = (state_path, dry_run) {
+        save_state(&path, &state.lock().unwrap())?;
+    }
+
+    let hook_result = execute_hooks(&filtered_manifest.post_install,
+                                    "post_install", hook_target.as_ref(),
+                                    dry_run, reporter, observer);
+
+    if let Some(path) = report {
+        let mut _report = Report::new(tag_rules, hook_target.host());
+        _report.steps = step_reports.into_inner().unwrap();
+        _report.steps.sort_by_key(|x| x.step);
+        _report.duration = start_time.elapsed().as_secs_f64();
+        write_report(path, &_report)?;
+    }
+
+    reporter.finish();
+    hook_result?;
+    let failure = failure.into_inner().unwrap();
+    if let Some(observer) = observer {
+        observer.on_complete(failure);
+    }
+    Ok(failure)
+}
+
+/// Executes a manifest's pre-install or post-install hooks
+///
+/// Hooks are executed in the directory containing the manifest, or in the home
+/// directory when installing over SSH. Returns an Err if any hook fails, in
+/// which case the remaining hooks aren't executed.
+fn execute_hooks(hooks: &[String], name: &str, target: &dyn Target,
+                 dry_run: bool, reporter: &Arc<dyn Reporter>,
+                 observer: &Option<Arc<dyn InstallObserver>>) -> Result<()> {
+
+    let mut output = StepOutput::new(false, None, reporter.clone(),
+                                     observer.clone());
+
+    for cmd in hooks {
+        output.start_action(name, &format!("Run {}{}", cmd, on_host(target)));
+
+        check_dry_run!(dry_run, output);
+
+        let result = output.run(|capture| target.run_hook(cmd, capture))
+            .with_context(|| {
+                Error::Critical(format!("Failed to run {} hook", name))
+            });
+        output.end_action(if result.is_ok() { "succeeded" } else { "failed" });
+        if let (Err(why), Some(observer)) = (&result, observer) {
+            observer.on_error(None, &format!("{:#}", why));
         }
+        result?;
+    }
+
+    Ok(())
+}
+
+/// Executes a single manifest step on a target and returns the class of failure
+/// with the greatest precedence among any errors that occurred
+#[allow(clippy::too_many_arguments)]
+fn execute_step(step: &Step, index: usize, step_count: usize,
+                tag_rules: &[String], target: &mut dyn Target, dry_run: bool,
+                diff: bool, link_mode: LinkMode, exclude: &[Pattern],
+                output: &mut StepOutput) ->
+    Option<Failure> {
+
+    let step_str = format!("{}/{}", index+1, step_count);
 
-        errors |= execute_runs(&step.run, &tag_rules, host, temp_dir.path(),
-                               dry_run, &step_str);
+    let mut failure = execute_copies(&step.copy, target, dry_run, diff, true,
+                                     exclude, &step_str, output);
+    failure = failure.max(execute_links(&step.link, target, link_mode,
+                                        dry_run, diff, exclude, &step_str,
+                                        output));
+    failure = failure.max(execute_blocks(&step.block, target.host(),
+                                         target.base_dir(), dry_run, diff,
+                                         exclude, &step_str, output));
+    failure = failure.max(execute_patches(&step.patch, target.host(),
+                                          target.base_dir(), dry_run, diff,
+                                          exclude, &step_str, output));
+
+    // Scripts aren't recorded in the remote state file so that `clean` can
+    // remove them
+    let srcs: Vec<&str> = brewfiles(&step.brew).into_iter()
+        .chain(step.systemd.iter().filter_map(|x| x.src.as_deref()))
+        .chain(step.run.iter().map(|x| x.src.as_str())).collect();
+    let script_copies = target.script_copies(&srcs);
+    failure = failure.max(execute_copies(&script_copies, target, dry_run,
+                                         false, false, exclude, &step_str,
+                                         output));
+    if !dry_run {
+        let result = output.run(|capture| target.finish(capture));
+        failure = failure.max(handle_error(result, Failure::Transport,
+                                           output));
     }
 
-    Ok(errors)
+    failure = failure.max(execute_packages(&step.packages, target, dry_run,
+                                           exclude, &step_str, output));
+    failure = failure.max(execute_brews(&step.brew, target, dry_run, exclude,
+                                        &step_str, output));
+    failure = failure.max(execute_repos(&step.repo, target, dry_run, exclude,
+                                        &step_str, output));
+    failure = failure.max(execute_fetches(&step.fetch, target, dry_run,
+                                          exclude, &step_str, output));
+    failure = failure.max(execute_systemds(&step.systemd, target, dry_run,
+                                           exclude, &step_str, output));
+    failure = failure.max(execute_defaults(&step.defaults, target, dry_run,
+                                           exclude, &step_str, output));
+    failure = failure.max(execute_extensions(&step.extensions, target, dry_run,
+                                             exclude, &step_str, output));
+    failure = failure.max(execute_crons(&step.cron, target, dry_run, exclude,
+                                        &step_str, output));
+    failure.max(execute_runs(&step.run, tag_rules, target, dry_run, exclude,
+                             &step_str, output))
 }
 
-/// Executes a set of copy commands and returns a bool indicating whether any
-/// error occurred
-fn execute_copies(copies: &[CopyLinkOptions], host: &str, staging_dir: &Path,
-                  dry_run: bool, step_str: &ColoredString) -> bool {
+/// Executes a set of copy commands on a target and returns the class of
+/// failure with the greatest precedence among any errors that occurred
+///
+/// Destinations are recorded in the install state if `record` is true.
+#[allow(clippy::too_many_arguments)]
+fn execute_copies(copies: &[CopyLinkOptions], target: &mut dyn Target,
+                  dry_run: bool, diff: bool, record: bool, exclude: &[Pattern],
+                  step_str: &str, output: &mut StepOutput) ->
+    Option<Failure> {
 
-    let mut errors = false;
+    let mut failure = None;
 
     for copy in copies {
-        // Resolve relative dst paths if installing over SSH
-        let _dst = if host != "" {
-            resolve_path(&copy.dst, &format!("~/{}", SSH_INSTALL_DIR))
+        let _dst = target.resolve_dst(&copy.dst);
+        output.start_action(step_str, &format!("Copy {} to {}", copy.src,
+                                               target.display_path(&_dst)));
+
+        let mut paths = copy.sources();
+        paths.push(&copy.dst);
+        check_excluded!(exclude, &paths, output);
+
+        // Concatenated files and files with converted line endings are
+        // installed from a temporary copy, which must outlive the installation
+        let rendered = match render_copy(copy, target.base_dir(),
+                                         !target.host().is_empty()) {
+            Ok(rendered) => rendered,
+            Err(why) => {
+                failure = failure.max(report_outcome(Err(why), output));
+                continue;
+            },
+        };
+        let src = rendered.as_ref().map_or(copy.src.clone(), |x| {
+            x.path().to_string_lossy().to_string()
+        });
+
+        failure = failure.max(install(target, &src, &_dst, LinkMode::Copy,
+                                      copy, record, dry_run, diff, output));
+    }
+
+    failure
+}
+
+/// Executes a set of link commands on a target and returns the class of
+/// failure with the greatest precedence among any errors that occurred
+///
+/// Each link is installed according to the target's
+/// [`link_mode`](Target::link_mode), which falls back to `link_mode` for links
+/// without a type.
+#[allow(clippy::too_many_arguments)]
+fn execute_links(links: &[CopyLinkOptions], target: &mut dyn Target,
+                 link_mode: LinkMode, dry_run: bool, diff: bool,
+                 exclude: &[Pattern], step_str: &str,
+                 output: &mut StepOutput) ->
+    Option<Failure> {
+
+    let mut failure = None;
+
+    for link in links {
+        let mode = target.link_mode(link, link_mode);
+        let verb = match mode {
+            LinkMode::Symlink => "Link",
+            LinkMode::Hardlink => "Hard link",
+            LinkMode::Copy => "Copy",
+        };
+        let _dst = target.resolve_dst(&link.dst);
+        output.start_action(step_str, &format!("{} {} to {}", verb, link.src,
+                                               target.display_path(&_dst)));
+
+        check_excluded!(exclude, &[&link.src, &link.dst], output);
+
+        failure = failure.max(install(target, &link.src, &_dst, mode, link,
+                                      true, dry_run, diff, output));
+    }
+
+    failure
+}
+
+/// Installs the file of a copy or link command on a target, or previews the
+/// change it would make during a dry run, printing the outcome and returning
+/// the class of failure if the installation failed
+///
+/// The outcome of a file that the target installs later (e.g. by transferring
+/// it over SSH) isn't known yet, so it's only labeled if it failed. If `diff`
+/// is true, the changes to a file's contents are printed after its outcome
+/// when the target can preview them.
+#[allow(clippy::too_many_arguments)]
+fn install(target: &mut dyn Target, src: &str, dst: &str, mode: LinkMode,
+           entry: &CopyLinkOptions, record: bool, dry_run: bool, diff: bool,
+           output: &mut StepOutput) -> Option<Failure> {
+
+    if dry_run {
+        return match target.preview(src, dst, mode, entry) {
+            Some(change) => {
+                report_preview(change, src, dst, target.base_dir(), diff,
+                               output)
+            },
+            None => {
+                output.finish_action(Outcome::DryRun, None);
+                None
+            },
+        };
+    }
+
+    // The diff must be computed before the destination is replaced
+    let diff_text = if diff {
+        target.preview(src, dst, mode, entry).and_then(|x| x.ok())
+            .map(|change| change_diff(&change, src, dst, target.base_dir()))
+    } else {
+        None
+    };
+    let failure = match target.install(src, dst, mode, entry, record) {
+        Ok(Some(change)) => report_outcome(Ok(change), output),
+        Ok(None) => {
+            output.finish_action(Outcome::Pending, None);
+            output.end_action("staged");
+            None
+        },
+        Err(why) => return report_outcome(Err(why), output),
+    };
+    failure.max(report_diff(diff_text, output))
+}
+
+/// Executes a set of block commands on the local machine and returns the class
+/// of failure with the greatest precedence among any errors that occurred
+///
+/// Block commands edit files that coliru doesn't own, so they aren't subject to
+/// conflict checks and aren't recorded in the install state. They aren't
+/// supported over SSH.
+#[allow(clippy::too_many_arguments)]
+fn execute_blocks(blocks: &[BlockOptions], host: &str, base_dir: &Path,
+                  dry_run: bool, diff: bool, exclude: &[Pattern],
+                  step_str: &str, output: &mut StepOutput) ->
+    Option<Failure> {
+
+    let mut failure = None;
+
+    for block in blocks {
+        let host_str = if host.is_empty() {
+            String::new()
         } else {
-            copy.dst.clone()
+            format!("{}:", host)
         };
+        output.start_action(step_str, &format!("Merge {} into {}{}", block.src,
+                                               host_str, block.dst));
+
+        check_excluded!(exclude, &[&block.src, &block.dst], output);
 
-        print!("{} Copy {} to ", step_str, copy.src);
-        if host != "" {
-            print!("{}:", host);
+        if !host.is_empty() {
+            let why = anyhow!("Block commands aren't supported over SSH");
+            failure = failure.max(report_outcome(Err(why), output));
+            continue;
         }
-        print!("{}", _dst);
 
-        check_dry_run!(dry_run);
+        let comment = block.comment.as_deref().unwrap_or("#");
+        let rendered = render_block(&block.src, &block.dst, base_dir, comment);
+        failure = failure.max(install_rendered(rendered, &block.dst, base_dir,
+                                               dry_run, diff, output));
+    }
 
-        if host == "" {
-            errors |= handle_error(copy_file(&copy.src, &_dst));
+    failure
+}
+
+/// Executes a set of patch commands on the local machine and returns the class
+/// of failure with the greatest precedence among any errors that occurred
+///
+/// Like block commands, patch commands edit files that coliru doesn't own and
+/// aren't supported over SSH. A dry run reports whether each patch would apply
+/// cleanly.
+#[allow(clippy::too_many_arguments)]
+fn execute_patches(patches: &[PatchOptions], host: &str, base_dir: &Path,
+                   dry_run: bool, diff: bool, exclude: &[Pattern],
+                   step_str: &str, output: &mut StepOutput) ->
+    Option<Failure> {
+
+    let mut failure = None;
+
+    for patch in patches {
+        let host_str = if host.is_empty() {
+            String::new()
         } else {
-            errors |= handle_error(stage_file(&copy.src, &_dst, staging_dir)
-               .with_context(|| {
-                   format!("Failed to copy {} to staging directory", &copy.src)
-               }));
+            format!("{}:", host)
+        };
+        output.start_action(step_str, &format!("Patch {}{} with {}", host_str,
+                                               patch.dst, patch.src));
+
+        check_excluded!(exclude, &[&patch.src, &patch.dst], output);
+
+        if !host.is_empty() {
+            let why = anyhow!("Patch commands aren't supported over SSH");
+            failure = failure.max(report_outcome(Err(why), output));
+            continue;
         }
+
+        let rendered = render_patch(&patch.src, &patch.dst, base_dir);
+        failure = failure.max(install_rendered(rendered, &patch.dst, base_dir,
+                                               dry_run, diff, output));
     }
 
-    if !dry_run {
-        errors |= handle_error(send_staged_files(staging_dir, host)
-            .context("Failed to transfer staged files"));
+    failure
+}
+
+/// Backs up a destination on the local machine and replaces it with a file that
+/// was rendered from it (e.g. with a block merged into it), printing the change
+/// that was made and returning the class of failure if the installation failed
+///
+/// The destination isn't subject to conflict checks and isn't recorded in the
+/// install state, since coliru doesn't own it. A relative destination is
+/// relative to `base_dir`.
+fn install_rendered(rendered: Result<NamedTempFile>, dst: &str,
+                    base_dir: &Path, dry_run: bool, diff: bool,
+                    output: &mut StepOutput) -> Option<Failure> {
+    // The rendered file must outlive the installation
+    let rendered = match rendered {
+        Ok(rendered) => rendered,
+        Err(why) => return report_outcome(Err(why), output),
+    };
+    let src = rendered.path().to_string_lossy().to_string();
+    let _dst = resolve_local_path(dst, base_dir);
+
+    let change = preview_copy(&src, &_dst);
+    if dry_run {
+        return report_preview(change, &src, dst, base_dir, diff, output);
     }
+    let change = change.unwrap_or(Change::Update);
+    let diff_text = diff.then(|| change_diff(&change, &src, dst, base_dir));
+    let result = if change == Change::Unchanged {
+        Ok(change)
+    } else {
+        backup_dst(&src, &_dst)
+            .and_then(|_| copy_file(&src, &_dst))
+            .map(|_| change)
+    };
+    let installed = result.is_ok();
+    let failure = report_outcome(result, output);
+    if !installed { return failure; }
+    failure.max(report_diff(diff_text, output))
+}
+
+/// The change that a checked command would make, as determined by its checks
+struct Plan<S> {
+    /// The change that a dry run reports (e.g. `install` or `unchanged`)
+    change: String,
 
-    errors
+    /// The information needed to make the change, or None if nothing needs to
+    /// change
+    pending: Option<S>,
+}
+impl<S> Plan<S> {
+    /// Creates a new Plan
+    ///
+    /// ```
+    /// let plan = Plan::new("install", Some(()));
+    /// ```
+    fn new(change: &str, pending: Option<S>) -> Plan<S> {
+        Plan { change: change.to_owned(), pending }
+    }
 }
 
-/// Executes a set of link commands and returns a bool indicating whether any
-/// error occurred
-fn execute_links(links: &[CopyLinkOptions], dry_run: bool,
-                 step_str: &ColoredString) -> bool {
+/// Executes a set of commands that check the target before changing it and
+/// returns the class of failure with the greatest precedence among any errors
+/// that occurred
+///
+/// `describe` returns the description of each item and the paths that are
+/// matched against `exclude`, and every item fails with `windows_error` if the
+/// target runs Windows. `check` determines the change that an item would make,
+/// usually with [`Target::check`]. Unless it's a dry run or nothing would
+/// change, `apply` then makes the change using the function it's passed to run
+/// commands, whose output is reported after the outcome that `apply` returns.
+///
+/// ```
+/// execute_checked(&brews, target, dry_run, exclude, step_str, output,
+///                 "Homebrew isn't supported on Windows",
+///                 |x| (describe(x), vec![]),
+///                 |x| Ok(Plan::new("install", Some(()))),
+///                 |x, _, run| run(&install_command(x))
+///                     .map(|_| Outcome::Created));
+/// ```
+#[allow(clippy::too_many_arguments)]
+fn execute_checked<T, S, D, C, A>(items: &[T], target: &dyn Target,
+                                  dry_run: bool, exclude: &[Pattern],
+                                  step_str: &str, output: &mut StepOutput,
+                                  windows_error: &str, describe: D, check: C,
+                                  apply: A) -> Option<Failure>
+    where D: Fn(&T) -> (String, Vec<&str>),
+          C: Fn(&T) -> Result<Plan<S>>,
+          A: Fn(&T, S, &mut dyn FnMut(&str) -> Result<()>) ->
+              Result<Outcome> {
 
-    let mut errors = false;
+    let mut failure = None;
 
-    for link in links {
-        print!("{} Link {} to {}", step_str, link.src, link.dst);
+    for item in items {
+        let (description, paths) = describe(item);
+        output.start_action(step_str, &description);
+
+        check_excluded!(exclude, &paths, output);
+
+        let plan = if target.is_windows() {
+            Err(anyhow!(windows_error.to_owned()))
+        } else {
+            check(item)
+        };
+        let plan = match plan {
+            Ok(plan) => plan,
+            Err(why) => {
+                output.finish_action(Outcome::Failed, None);
+                failure = failure.max(handle_error(Err(why), Failure::Script,
+                                                   output));
+                continue;
+            },
+        };
+        if dry_run {
+            output.finish_action(Outcome::DryRun, Some(plan.change));
+            continue;
+        }
+        let Some(pending) = plan.pending else {
+            output.finish_action(Outcome::Unchanged, None);
+            continue;
+        };
+
+        let mut captured = CapturedOutput::default();
+        let result = apply(item, pending, &mut |command| {
+            target.run(command, None, None, Some(&mut captured))
+        });
+        output.finish_action(*result.as_ref().unwrap_or(&Outcome::Failed),
+                             None);
+        output.report_captured(captured);
+        failure = failure.max(handle_error(result.map(|_| ()), Failure::Script,
+                                           output));
+    }
+
+    failure
+}
 
-        check_dry_run!(dry_run);
+/// Executes a set of packages commands on a target and returns the class of
+/// failure with the greatest precedence among any errors that occurred
+///
+/// The package manager is detected once for the whole set. Packages that are
+/// already installed are reported as unchanged without running the package
+/// manager, and a dry run also checks each package, so it reports which ones
+/// would be installed. Packages whose name matches an `exclude` pattern are
+/// skipped.
+fn execute_packages(packages: &[PackageOptions], target: &dyn Target,
+                    dry_run: bool, exclude: &[Pattern], step_str: &str,
+                    output: &mut StepOutput) -> Option<Failure> {
 
-        errors |= handle_error(link_file(&link.src, &link.dst));
+    if packages.is_empty() {
+        return None;
     }
 
-    errors
+    let manager = if target.is_windows() {
+        None
+    } else {
+        PackageManager::ALL.into_iter().find(|x| {
+            target.check(&detect_command(*x))
+        })
+    };
+    let name = |package: &PackageOptions| match manager {
+        Some(manager) => manager.package_name(package).to_owned(),
+        None => package.name.clone(),
+    };
+
+    execute_checked(packages, target, dry_run, exclude, step_str, output,
+                    "System packages aren't supported on Windows", |x| {
+        (format!("{}{}", packages::describe(&name(x), manager),
+                 on_host(target)), vec![x.name.as_str()])
+    }, |x| {
+        let manager = manager.ok_or_else(|| {
+            anyhow!("No supported package manager (apt, dnf, pacman, or \
+                     zypper) was found")
+        })?;
+        Ok(if target.check(&packages::check_command(manager, &name(x))) {
+            Plan::new("unchanged", None)
+        } else {
+            Plan::new("install", Some(manager))
+        })
+    }, |x, manager, run| {
+        run(&packages::install_command(manager, &name(x)))
+            .map(|_| Outcome::Created)
+    })
 }
 
-/// Executes a set of run commands and returns a bool indicating whether any
-/// error occurred
-fn execute_runs(runs: &[RunOptions], tag_rules: &[String], host: &str,
-                staging_dir: &Path, dry_run: bool, step_str: &ColoredString) ->
-bool {
+/// Executes a set of brew commands on a target and returns the class of failure
+/// with the greatest precedence among any errors that occurred
+///
+/// Packages that are already installed are reported as unchanged without
+/// running `brew install`. A dry run also checks each package, so it reports
+/// which ones would be installed. Like run commands, brew commands need the
+/// target to be [finished](Target::finish) so that Brewfiles have been sent to
+/// it.
+fn execute_brews(brews: &[BrewOptions], target: &dyn Target, dry_run: bool,
+                 exclude: &[Pattern], step_str: &str,
+                 output: &mut StepOutput) -> Option<Failure> {
 
-    let mut errors = false;
+    execute_checked(brews, target, dry_run, exclude, step_str, output,
+                    "Homebrew isn't supported on Windows", |x| {
+        (format!("{}{}", describe(x), on_host(target)),
+         brewfiles(std::slice::from_ref(x)))
+    }, |x| {
+        Ok(if target.check(&check_command(x)) {
+            Plan::new("unchanged", None)
+        } else {
+            Plan::new("install", Some(()))
+        })
+    }, |x, _, run| run(&install_command(x)).map(|_| Outcome::Created))
+}
 
-    if host != "" {
-        // Copy scripts to remote machine
-        let run_copies: Vec<CopyLinkOptions> = runs.iter().map(|x| {
-            CopyLinkOptions { src: x.src.clone(), dst: x.src.clone() }
-        }).collect();
+/// Executes a set of repo commands on a target and returns the class of failure
+/// with the greatest precedence among any errors that occurred
+///
+/// Missing repositories are cloned, and existing ones are fetched and then
+/// updated unless they've already checked out the right commit. A dry run
+/// doesn't fetch anything, so it reports whether each repository would be
+/// cloned or updated. The output of Git is reported after the outcome of each
+/// command.
+fn execute_repos(repos: &[RepoOptions], target: &dyn Target, dry_run: bool,
+                 exclude: &[Pattern], step_str: &str,
+                 output: &mut StepOutput) -> Option<Failure> {
 
-        errors |= execute_copies(&run_copies, host, staging_dir, dry_run,
-                                 step_str);
-    }
+    execute_checked(repos, target, dry_run, exclude, step_str, output,
+                    "Repo commands aren't supported on Windows", |x| {
+        let dst = target.display_path(&target.resolve_dst(&x.dst));
+        (format!("Clone {} to {}", x.url, dst), vec![x.dst.as_str()])
+    }, |x| {
+        // Existing repositories can't be checked without fetching them
+        Ok(if target.check(&exists_command(x)) {
+            Plan::new("update", Some(true))
+        } else {
+            Plan::new("clone", Some(false))
+        })
+    }, |x, exists, run| {
+        if !exists {
+            return run(&clone_command(x)).map(|_| Outcome::Created);
+        }
+        run(&fetch_command(x))?;
+        if target.check(&current_command(x)) {
+            return Ok(Outcome::Unchanged);
+        }
+        run(&update_command(x)).map(|_| Outcome::Updated)
+    })
+}
+
+/// Executes a set of fetch commands on a target and returns the class of
+/// failure with the greatest precedence among any errors that occurred
+///
+/// Destinations that already have the expected hash are left alone. Other
+/// files are downloaded next to their destination and only replace it once
+/// their hash has been verified. A dry run only hashes the existing
+/// destinations, so it reports whether each file would be created or updated
+/// without downloading it.
+fn execute_fetches(fetches: &[FetchOptions], target: &dyn Target,
+                   dry_run: bool, exclude: &[Pattern], step_str: &str,
+                   output: &mut StepOutput) -> Option<Failure> {
+
+    execute_checked(fetches, target, dry_run, exclude, step_str, output,
+                    "Fetch commands aren't supported on Windows", |x| {
+        let dst = target.display_path(&target.resolve_dst(&x.dst));
+        (format!("Download {} to {}", x.url, dst), vec![x.dst.as_str()])
+    }, |x| {
+        let change = if target.check(&fetch::current_command(x)) {
+            Change::Unchanged
+        } else if target.check(&fetch::exists_command(x)) {
+            Change::Update
+        } else {
+            Change::Create
+        };
+        let pending = (change != Change::Unchanged).then_some(change);
+        Ok(Plan::new(&change.to_string(), pending))
+    }, |x, change, run| {
+        run(&fetch::download_command(x)).and_then(|_| {
+            if !target.check(&fetch::verify_command(x)) {
+                // The download is already being reported as failed
+                let _ = run(&fetch::discard_command(x));
+                return Err(anyhow!("Checksum doesn't match {}", x.sha256));
+            }
+            run(&fetch::install_command(x))
+        }).with_context(|| format!("Failed to download {}", x.url))?;
+        Ok(if change == Change::Create {
+            Outcome::Created
+        } else {
+            Outcome::Updated
+        })
+    })
+}
+
+/// The changes that a systemd command would make to its unit
+struct SystemdChanges {
+    /// Whether the unit file would be installed
+    install: bool,
+
+    /// Whether the unit file already exists
+    exists: bool,
+
+    /// Whether the unit would be enabled
+    enable: bool,
+
+    /// Whether the unit is already running
+    active: bool,
+
+    /// Whether the unit would be started or restarted
+    start: bool,
+}
+
+/// Executes a set of systemd commands on a target and returns the class of
+/// failure with the greatest precedence among any errors that occurred
+///
+/// Unit files are only installed if their contents differ, and units are only
+/// enabled and started if they aren't already. A unit that's already running
+/// is restarted if its unit file changed. A dry run reports the changes that
+/// would be made, or `unchanged` if there aren't any.
+fn execute_systemds(units: &[SystemdOptions], target: &dyn Target,
+                    dry_run: bool, exclude: &[Pattern], step_str: &str,
+                    output: &mut StepOutput) -> Option<Failure> {
+
+    execute_checked(units, target, dry_run, exclude, step_str, output,
+                    "Systemd commands aren't supported on Windows", |x| {
+        (format!("{}{}", systemd::describe(x), on_host(target)),
+         x.src.iter().map(|x| x.as_str()).collect())
+    }, |x| {
+        let install = x.src.is_some() &&
+            !target.check(&systemd::installed_command(x));
+        let exists = !install || target.check(&systemd::exists_command(x));
+        let enable = x.enable && !target.check(&systemd::enabled_command(x));
+        let active = target.check(&systemd::active_command(x));
+        let start = x.start && (!active || install);
+
+        let changes = [(install, "install"), (enable, "enable"),
+                       (start && !active, "start"),
+                       (start && active, "restart")];
+        let changes: Vec<&str> = changes.iter().filter(|x| x.0).map(|x| x.1)
+            .collect();
+        Ok(if changes.is_empty() {
+            Plan::new("unchanged", None)
+        } else {
+            Plan::new(&changes.join(", "), Some(SystemdChanges {
+                install, exists, enable, active, start,
+            }))
+        })
+    }, |x, changes, run| {
+        let mut run_if = |command: &str, needed: bool| {
+            if needed { run(command) } else { Ok(()) }
+        };
+        run_if(&systemd::install_command(x), changes.install)
+            .and_then(|_| run_if(&systemd::enable_command(x), changes.enable))
+            .and_then(|_| {
+                run_if(&systemd::start_command(x, changes.active),
+                       changes.start)
+            })
+            .with_context(|| format!("Failed to set up {} unit {}", x.scope,
+                                     x.unit))?;
+        Ok(if changes.exists { Outcome::Updated } else { Outcome::Created })
+    })
+}
+
+/// Executes a set of defaults commands on a target and returns the class of
+/// failure with the greatest precedence among any errors that occurred
+///
+/// Each key is read before it's written, so keys that already have their value
+/// are left alone. A dry run reports the current and desired value of each key
+/// that would be changed.
+fn execute_defaults(entries: &[DefaultsOptions], target: &dyn Target,
+                    dry_run: bool, exclude: &[Pattern], step_str: &str,
+                    output: &mut StepOutput) -> Option<Failure> {
+
+    execute_checked(entries, target, dry_run, exclude, step_str, output,
+                    "Defaults commands aren't supported on Windows", |x| {
+        (format!("{}{}", defaults::describe(x), on_host(target)),
+         vec![x.domain.as_str()])
+    }, |x| {
+        // Keys that aren't set can't be read, so their current value is None
+        let mut read = CapturedOutput::default();
+        let current = target.run(&defaults::read_command(x), None, None,
+                                 Some(&mut read)).ok()
+            .map(|_| read.stdout.trim().to_owned());
+        let expected = defaults::expected_value(&x.value);
+        Ok(if current.as_ref() == Some(&expected) {
+            Plan::new("unchanged", None)
+        } else {
+            Plan::new(&format!("{} -> {}",
+                               current.as_deref().unwrap_or("unset"),
+                               expected), Some(current.is_some()))
+        })
+    }, |x, exists, run| {
+        run(&defaults::write_command(x)).with_context(|| {
+            format!("Failed to write {} {}", x.domain, x.key)
+        })?;
+        Ok(if exists { Outcome::Updated } else { Outcome::Created })
+    })
+}
+
+/// Executes a set of extension commands on a target and returns the class of
+/// failure with the greatest precedence among any errors that occurred
+///
+/// VS Code extensions that are already installed are left alone. JetBrains
+/// plugins can't be checked, so they're always passed to the IDE, which is
+/// reported as an update, and a dry run reports that they'd be installed if
+/// they're missing. Extensions whose ID matches an `exclude` pattern are
+/// skipped.
+fn execute_extensions(extensions: &[ExtensionOptions], target: &dyn Target,
+                      dry_run: bool, exclude: &[Pattern], step_str: &str,
+                      output: &mut StepOutput) -> Option<Failure> {
+
+    execute_checked(extensions, target, dry_run, exclude, step_str, output,
+                    "Extension commands aren't supported on Windows", |x| {
+        (format!("{}{}", extension::describe(x), on_host(target)),
+         vec![x.id()])
+    }, |x| {
+        Ok(match extension::check_command(x).map(|x| target.check(&x)) {
+            Some(true) => Plan::new("unchanged", None),
+            Some(false) => Plan::new("install", Some(Outcome::Created)),
+            None => Plan::new("install if missing", Some(Outcome::Updated)),
+        })
+    }, |x, outcome, run| {
+        run(&extension::install_command(x)).map(|_| outcome)
+    })
+}
+
+/// Executes a set of cron commands on a target and returns the class of
+/// failure with the greatest precedence among any errors that occurred
+///
+/// Jobs that are already in the crontab beneath their marker are left alone,
+/// and jobs whose line changed are replaced. A dry run reports whether each
+/// job would be added, updated, or removed. Jobs whose name matches an
+/// `exclude` pattern are skipped.
+fn execute_crons(jobs: &[CronOptions], target: &dyn Target, dry_run: bool,
+                 exclude: &[Pattern], step_str: &str,
+                 output: &mut StepOutput) -> Option<Failure> {
+
+    execute_checked(jobs, target, dry_run, exclude, step_str, output,
+                    "Cron commands aren't supported on Windows", |x| {
+        (format!("{}{}", cron::describe(x), on_host(target)),
+         vec![x.name.as_str()])
+    }, |x| {
+        let exists = target.check(&cron::exists_command(x));
+        Ok(if x.remove {
+            let pending = (cron::remove_command(x), Outcome::Updated);
+            Plan::new(if exists { "remove" } else { "unchanged" },
+                      exists.then_some(pending))
+        } else if !exists {
+            Plan::new("add", Some((cron::install_command(x),
+                                   Outcome::Created)))
+        } else if !target.check(&cron::current_command(x)) {
+            Plan::new("update", Some((cron::install_command(x),
+                                      Outcome::Updated)))
+        } else {
+            Plan::new("unchanged", None)
+        })
+    }, |x, (command, outcome), run| {
+        run(&command).with_context(|| {
+            format!("Failed to update the crontab for {}", x.name)
+        })?;
+        Ok(outcome)
+    })
+}
+
+/// Executes a set of run commands on a target and returns the class of failure
+/// with the greatest precedence among any errors that occurred
+///
+/// The target must already have been [finished](Target::finish), so that any
+/// scripts it sends to another machine have been transferred.
+fn execute_runs(runs: &[RunOptions], tag_rules: &[String], target: &dyn Target,
+                dry_run: bool, exclude: &[Pattern], step_str: &str,
+                output: &mut StepOutput) ->
+    Option<Failure> {
+
+    let mut failure = None;
 
     for run in runs {
+        // Rules are quoted for the shell that executes the command, which also
+        // sets its environment variables
+        let env_shell = run.shell.unwrap_or(target.default_shell());
         let postfix = run.postfix.replace("$COLIRU_RULES",
-                                          &tag_rules.join(" "));
+                                          &join_tag_rules(tag_rules,
+                                                          env_shell));
         let cmd = format!("{} {} {}", run.prefix, run.src, postfix);
 
-        print!("{} Run {}", step_str, cmd);
-        if host != "" {
-            print!(" on {}", host);
+        let user_str = match &run.user {
+            Some(user) => format!(" as {}", user),
+            None => String::new(),
+        };
+        output.start_action(step_str, &format!("Run {}{}{}", cmd, user_str,
+                                               on_host(target)));
+
+        check_excluded!(exclude, &[&run.src], output);
+        check_dry_run!(dry_run, output);
+
+        if run.user.is_some() && target.is_windows() {
+            let result = Err(anyhow!("Running scripts as another user isn't \
+                                      supported on Windows"));
+            output.end_action("failed");
+            failure = failure.max(handle_error(result, Failure::Script,
+                                               output));
+            continue;
         }
+        // Variables are set by the command line itself so that they also
+        // reach scripts run as another user, whose environment sudo resets
+        let cmd = match with_env(&cmd, &run.env, env_shell) {
+            Ok(cmd) => cmd,
+            Err(why) => {
+                output.end_action("failed");
+                failure = failure.max(handle_error(Err(why), Failure::Script,
+                                                   output));
+                continue;
+            },
+        };
+        // Scripts run as another user are wrapped in a command line that
+        // selects the shell itself
+        let (cmd, shell) = match &run.user {
+            Some(user) => (as_user(&cmd, user, run.shell), None),
+            None => (cmd, run.shell),
+        };
 
-        check_dry_run!(dry_run);
+        let timeout = run.timeout.map(Duration::from_secs);
+        let result = run_with_retries(run, output, |output| {
+            output.run(|capture| target.run(&cmd, shell, timeout, capture))
+        });
+        let succeeded = result.is_ok();
+        output.end_action(if succeeded { "succeeded" } else { "failed" });
+        failure = failure.max(handle_error(result, Failure::Script, output));
 
-        if host == "" {
-            errors |= handle_error(run_command(&cmd));
-        } else {
-            let ssh_cmd = format!("cd {} && {}", SSH_INSTALL_DIR, &cmd);
-            errors |= handle_error(send_command(&ssh_cmd, host));
+        if succeeded && run.cleanup {
+            let result = output.run(|capture| {
+                target.remove_script(&run.src, capture)
+            });
+            failure = failure.max(handle_error(result, Failure::Transport,
+                                               output));
         }
     }
 
-    errors
+    failure
+}
+
+/// Returns the text that follows a run command in the output to show the host
+/// that it's executed on, which is empty for the local machine
+fn on_host(target: &dyn Target) -> String {
+    match target.host() {
+        "" => String::new(),
+        host => format!(" on {}", host),
+    }
+}
+
+/// Executes a run command until it succeeds or has been re-run as many times as
+/// it allows, waiting between attempts and printing the error of each attempt
+/// that's retried
+fn run_with_retries<F>(run: &RunOptions, output: &mut StepOutput,
+                       mut attempt: F) -> Result<()>
+    where F: FnMut(&mut StepOutput) -> Result<()> {
+
+    for retry in 1..=run.retries {
+        let Err(why) = attempt(output) else { return Ok(()) };
+        output.report(Event::Retry { step: output.step, retry,
+                                     retries: run.retries,
+                                     message: format!("{:#}", why) });
+        thread::sleep(Duration::from_secs(run.retry_delay));
+    }
+    attempt(output)
+}
+
+/// Reports the outcome of a copy or link command on the local machine and
+/// returns the class of failure if the command failed
+fn report_outcome(result: Result<Change>, output: &mut StepOutput) ->
+    Option<Failure> {
+
+    let outcome = match result {
+        Ok(Change::Create) => Outcome::Created,
+        Ok(Change::Unchanged) => Outcome::Unchanged,
+        Ok(_) => Outcome::Updated,
+        Err(_) => Outcome::Failed,
+    };
+    output.finish_action(outcome, None);
+    handle_error(result.map(|_| ()), Failure::LocalFile, output)
+}
+
+/// Reports the change that a copy or link command would make during a dry run,
+/// followed by a diff of the destination's contents if requested, and returns
+/// the class of failure if the destination couldn't be inspected
+fn report_preview(change: Result<Change>, src: &str, dst: &str,
+                  base_dir: &Path, diff: bool, output: &mut StepOutput) ->
+    Option<Failure> {
+
+    let change = match change {
+        Ok(change) => change,
+        Err(why) => {
+            output.finish_action(Outcome::DryRun, None);
+            return handle_error(Err(why), Failure::LocalFile, output);
+        },
+    };
+    output.finish_action(Outcome::DryRun, Some(change.to_string()));
+
+    report_diff(diff.then(|| change_diff(&change, src, dst, base_dir)), output)
+}
+
+/// Returns a diff of the changes that a copy or link command would make to the
+/// contents of its destination on the local machine, or None if it wouldn't
+/// update them
+fn change_diff(change: &Change, src: &str, dst: &str, base_dir: &Path) ->
+    Result<Option<String>> {
+
+    if !matches!(change, Change::Update | Change::ReplaceFile) {
+        return Ok(None);
+    }
+    Ok(Some(diff_files(src, dst, base_dir)?.unwrap_or_else(|| {
+        String::from("Binary files differ\n")
+    })))
+}
+
+/// Prints a diff from [`change_diff`], if any, and returns the class of failure
+/// if it couldn't be computed
+fn report_diff(diff_text: Option<Result<Option<String>>>,
+               output: &mut StepOutput) -> Option<Failure> {
+    match diff_text {
+        Some(Ok(Some(text))) => {
+            output.report(Event::Diff { step: output.step, text });
+            None
+        },
+        Some(Err(why)) => handle_error(Err(why), Failure::LocalFile, output),
+        Some(Ok(None)) | None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exclude::parse_exclude;
+    use crate::facts::{Facts, local_facts};
+    use crate::local::CapturedOutput;
+    use crate::manifest::{Shell, parse_manifest_str};
+    use crate::reporter::CaptureReporter;
+    use std::path::Path;
+
+    /// A target that records the operations performed on it instead of
+    /// installing anything
+    #[derive(Default)]
+    struct MockTarget {
+        log: Vec<String>,
+
+        /// The commands passed to `run`, in order
+        commands: Mutex<Vec<String>>,
+
+        /// The packages that brew checks report as installed
+        installed: Vec<String>,
+    }
+    impl Target for MockTarget {
+        fn host(&self) -> &str { "mock" }
+
+        fn is_windows(&self) -> bool { false }
+
+        fn base_dir(&self) -> &Path { Path::new(".") }
+
+        fn connect(&self) -> Result<()> { Ok(()) }
+
+        fn facts(&self) -> Result<Facts> { Ok(local_facts()) }
+
+        fn resolve_dst(&self, dst: &str) -> String {
+            dst.replace("~/", "/home/mock/")
+        }
+
+        fn link_mode(&self, link: &CopyLinkOptions, default: LinkMode) ->
+            LinkMode {
+
+            link.link_mode(default)
+        }
+
+        fn preview(&self, _src: &str, _dst: &str, _mode: LinkMode,
+                   _entry: &CopyLinkOptions) -> Option<Result<Change>> {
+            Some(Ok(Change::Create))
+        }
+
+        fn install(&mut self, src: &str, dst: &str, mode: LinkMode,
+                   _entry: &CopyLinkOptions, record: bool) ->
+            Result<Option<Change>> {
+
+            self.log.push(format!("install {} {} {:?} {}", src, dst, mode,
+                                  record));
+            Ok(Some(Change::Create))
+        }
+
+        fn script_copies(&self, srcs: &[&str]) -> Vec<CopyLinkOptions> {
+            srcs.iter().map(|x| CopyLinkOptions::script(x, x)).collect()
+        }
+
+        fn finish(&mut self, _capture: Option<&mut CapturedOutput>) ->
+            Result<()> {
+
+            self.log.push(String::from("finish"));
+            Ok(())
+        }
+
+        fn run(&self, command: &str, _shell: Option<Shell>,
+               _timeout: Option<Duration>,
+               _capture: Option<&mut CapturedOutput>) -> Result<()> {
+            self.commands.lock().unwrap().push(command.to_owned());
+            Ok(())
+        }
+
+        fn check(&self, command: &str) -> bool {
+            self.commands.lock().unwrap().push(command.to_owned());
+            self.installed.iter().any(|x| {
+                command.contains(&format!("'{}'", x))
+            })
+        }
+
+        fn run_hook(&self, _command: &str,
+                    _capture: Option<&mut CapturedOutput>) -> Result<()> {
+            Ok(())
+        }
+
+        fn remove_script(&self, _src: &str,
+                         _capture: Option<&mut CapturedOutput>) ->
+            Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Returns the first step of a manifest
+    fn step(manifest: &str) -> Step {
+        parse_manifest_str(manifest, Path::new(".")).unwrap().steps.remove(0)
+    }
+
+    #[test]
+    fn test_step_batches() {
+        let manifest = parse_manifest_str("steps:
+  - tags: [ a ]
+  - { tags: [ b ], parallel: true }
+  - { tags: [ c ], parallel: true }
+  - tags: [ d ]
+  - { tags: [ e ], parallel: true }", Path::new(".")).unwrap();
+
+        let result = step_batches(&manifest.steps);
+
+        assert_eq!(result, vec![0..1, 1..3, 3..4, 4..5]);
+    }
+
+    #[test]
+    fn test_execute_step() {
+        let step = step("steps:
+  - copy: [ { src: gitconfig, dst: ~/.gitconfig } ]
+    link: [ { src: bashrc, dst: ~/.bashrc } ]
+    run: [ { src: script.sh, prefix: sh, postfix: arg } ]");
+        let mut target = MockTarget::default();
+        let reporter = Arc::new(CaptureReporter::default());
+        let mut output = StepOutput::new(true, Some(1), reporter.clone(),
+                                         None);
+
+        let failure = execute_step(&step, 0, 1, &[], &mut target, false,
+                                   false, LinkMode::Symlink, &[],
+                                   &mut output);
+        output.flush();
+
+        assert_eq!(failure, None);
+        assert_eq!(target.log, [
+            "install gitconfig /home/mock/.gitconfig Copy true",
+            "install bashrc /home/mock/.bashrc Symlink true",
+            "install script.sh script.sh Copy false",
+            "finish",
+        ]);
+        let events = reporter.events.lock().unwrap();
+        let lines: Vec<String> = events.iter().map(|x| match x {
+            Event::Action { label, description, .. } => {
+                format!("[{}] {}", label, description)
+            },
+            Event::Outcome { outcome, .. } => format!("({})", outcome),
+            _ => format!("{:?}", x),
+        }).collect();
+        assert_eq!(lines, [
+            "[1/1] Copy gitconfig to mock:/home/mock/.gitconfig", "(created)",
+            "[1/1] Link bashrc to mock:/home/mock/.bashrc", "(created)",
+            "[1/1] Copy script.sh to mock:script.sh", "(created)",
+            "[1/1] Run sh script.sh arg on mock", "(pending)",
+        ]);
+    }
+
+    #[test]
+    fn test_execute_step_dry_run() {
+        let step = step("steps:
+  - copy: [ { src: gitconfig, dst: ~/.gitconfig } ]
+    run: [ { src: script.sh } ]");
+        let mut target = MockTarget::default();
+        let reporter = Arc::new(CaptureReporter::default());
+        let mut output = StepOutput::new(true, Some(1), reporter, None);
+
+        let failure = execute_step(&step, 0, 1, &[], &mut target, true, false,
+                                   LinkMode::Symlink, &[], &mut output);
+
+        assert_eq!(failure, None);
+        assert_eq!(target.log, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_execute_step_packages() {
+        let step = step("steps:
+  - packages: [ git, { name: fd, dnf: fd-find } ]");
+        let mut target = MockTarget {
+            installed: vec![String::from("dnf"), String::from("git")],
+            ..MockTarget::default()
+        };
+        let reporter = Arc::new(CaptureReporter::default());
+        let mut output = StepOutput::new(true, Some(1), reporter.clone(),
+                                         None);
+
+        let failure = execute_step(&step, 0, 1, &[], &mut target, false,
+                                   false, LinkMode::Symlink, &[],
+                                   &mut output);
+        output.flush();
+
+        assert_eq!(failure, None);
+        assert_eq!(*target.commands.lock().unwrap(), [
+            "command -v 'apt-get' >/dev/null 2>&1",
+            "command -v 'dnf' >/dev/null 2>&1",
+            "rpm -q 'git' >/dev/null 2>&1",
+            "rpm -q 'fd-find' >/dev/null 2>&1",
+            "if [ \"$(id -u)\" -eq 0 ]; then dnf install -y 'fd-find'; \
+             else sudo dnf install -y 'fd-find'; fi",
+        ]);
+        let events = reporter.events.lock().unwrap();
+        let descriptions: Vec<&str> = events.iter().filter_map(|x| match x {
+            Event::Action { description, .. } => Some(description.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(descriptions, ["Install package git with dnf on mock",
+                                  "Install package fd-find with dnf on mock"]);
+    }
+
+    #[test]
+    fn test_execute_step_packages_no_manager() {
+        let step = step("steps:
+  - packages: [ git ]");
+        let mut target = MockTarget::default();
+        let reporter = Arc::new(CaptureReporter::default());
+        let mut output = StepOutput::new(true, Some(1), reporter.clone(),
+                                         None);
+
+        let failure = execute_step(&step, 0, 1, &[], &mut target, true, false,
+                                   LinkMode::Symlink, &[], &mut output);
+        output.flush();
+
+        assert_eq!(failure, Some(Failure::Script));
+        assert_eq!(target.commands.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_execute_step_brew() {
+        let step = step("steps:
+  - brew: [ git, { cask: firefox }, { brewfile: Brewfile } ]");
+        let mut target = MockTarget {
+            installed: vec![String::from("git")],
+            ..MockTarget::default()
+        };
+        let reporter = Arc::new(CaptureReporter::default());
+        let mut output = StepOutput::new(true, Some(1), reporter.clone(),
+                                         None);
+
+        let failure = execute_step(&step, 0, 1, &[], &mut target, false,
+                                   false, LinkMode::Symlink, &[],
+                                   &mut output);
+        output.flush();
+
+        assert_eq!(failure, None);
+        assert_eq!(target.log, [
+            "install Brewfile Brewfile Copy false",
+            "finish",
+        ]);
+        assert_eq!(*target.commands.lock().unwrap(), [
+            "brew list --formula 'git' >/dev/null 2>&1",
+            "brew list --cask 'firefox' >/dev/null 2>&1",
+            "brew install --cask 'firefox'",
+            "brew bundle check --no-upgrade --file='Brewfile' >/dev/null 2>&1",
+            "brew bundle install --no-upgrade --file='Brewfile'",
+        ]);
+        let events = reporter.events.lock().unwrap();
+        let outcomes: Vec<Outcome> = events.iter().filter_map(|x| match x {
+            Event::Outcome { outcome, .. } => Some(*outcome),
+            _ => None,
+        }).collect();
+        assert_eq!(outcomes, [Outcome::Created, Outcome::Unchanged,
+                              Outcome::Created, Outcome::Created]);
+    }
+
+    #[test]
+    fn test_execute_step_brew_dry_run() {
+        let step = step("steps:
+  - brew: [ git, ripgrep ]");
+        let mut target = MockTarget {
+            installed: vec![String::from("git")],
+            ..MockTarget::default()
+        };
+        let reporter = Arc::new(CaptureReporter::default());
+        let mut output = StepOutput::new(true, Some(1), reporter.clone(),
+                                         None);
+
+        let failure = execute_step(&step, 0, 1, &[], &mut target, true, false,
+                                   LinkMode::Symlink, &[], &mut output);
+        output.flush();
+
+        assert_eq!(failure, None);
+        assert_eq!(target.commands.lock().unwrap().len(), 2);
+        let events = reporter.events.lock().unwrap();
+        let details: Vec<Option<String>> = events.iter().filter_map(|x| {
+            match x {
+                Event::Outcome { detail, .. } => Some(detail.clone()),
+                _ => None,
+            }
+        }).collect();
+        assert_eq!(details, [Some(String::from("unchanged")),
+                             Some(String::from("install"))]);
+    }
+
+    #[test]
+    fn test_execute_step_exclude_checked() {
+        let step = step("steps:
+  - packages: [ git ]
+    extensions: [ ms-python.python, vscodevim.vim ]
+    cron: [ { name: backup, schedule: '@daily', command: backup.sh } ]");
+        let mut target = MockTarget::default();
+        let reporter = Arc::new(CaptureReporter::default());
+        let mut output = StepOutput::new(true, Some(1), reporter.clone(),
+                                         None);
+        let exclude = ["git", "vscodevim.*", "backup"].map(|x| {
+            parse_exclude(x).unwrap()
+        });
+
+        let failure = execute_step(&step, 0, 1, &[], &mut target, true, false,
+                                   LinkMode::Symlink, &exclude, &mut output);
+        output.flush();
+
+        assert_eq!(failure, None);
+        let events = reporter.events.lock().unwrap();
+        let outcomes: Vec<Outcome> = events.iter().filter_map(|x| match x {
+            Event::Outcome { outcome, .. } => Some(*outcome),
+            _ => None,
+        }).collect();
+        assert_eq!(outcomes, [Outcome::Skipped, Outcome::DryRun,
+                              Outcome::Skipped, Outcome::Skipped]);
+    }
+
+    /// An observer that records each notification it receives
+    #[derive(Default)]
+    struct MockObserver {
+        log: Mutex<Vec<String>>,
+    }
+    impl InstallObserver for MockObserver {
+        fn on_action(&self, step: Option<usize>, description: &str,
+                     outcome: &str) {
+            self.log.lock().unwrap().push(format!("{:?} {} ({})", step,
+                                                  description, outcome));
+        }
+
+        fn on_error(&self, step: Option<usize>, message: &str) {
+            self.log.lock().unwrap().push(format!("{:?} error: {}", step,
+                                                  message));
+        }
+    }
+
+    #[test]
+    fn test_execute_step_observer() {
+        let step = step("steps:
+  - copy: [ { src: gitconfig, dst: ~/.gitconfig } ]
+    link: [ { src: bashrc, dst: ~/.bashrc } ]
+    block: [ { src: block, dst: ~/.profile } ]
+    run: [ { src: script.sh, prefix: sh, postfix: arg } ]");
+        let mut target = MockTarget::default();
+        let observer = Arc::new(MockObserver::default());
+        let reporter = Arc::new(CaptureReporter::default());
+        let mut output = StepOutput::new(true, Some(1), reporter,
+                                         Some(observer.clone()));
+        let exclude = [Pattern::new("bashrc").unwrap()];
+
+        let failure = execute_step(&step, 0, 1, &[], &mut target, false,
+                                   false, LinkMode::Symlink, &exclude,
+                                   &mut output);
+
+        assert_eq!(failure, Some(Failure::LocalFile));
+        assert_eq!(*observer.log.lock().unwrap(), [
+            "Some(1) Copy gitconfig to mock:/home/mock/.gitconfig (created)",
+            "Some(1) Link bashrc to mock:/home/mock/.bashrc (skipped)",
+            "Some(1) Merge block into mock:~/.profile (failed)",
+            "Some(1) error: Block commands aren't supported over SSH",
+            "Some(1) Copy script.sh to mock:script.sh (created)",
+            "Some(1) Run sh script.sh arg on mock (succeeded)",
+        ]);
+    }
 }
@@ -0,0 +1,131 @@
+//! Shell commands for managing crontab entries
+//!
+//! Each cron job is written to the user's crontab beneath a marker comment
+//! (`# coliru: <name>`), so that it can be found and updated or removed by
+//! later installs without touching any other lines. Each function returns a
+//! POSIX shell command, so that crontabs can be managed the same way on the
+//! local machine and over SSH.
+//!
+//! ```
+//! let install = install_command(&job);
+//! ```
+
+use super::local::quote;
+use super::manifest::CronOptions;
+
+/// Returns a description of a cron command for output
+///
+/// ```
+/// assert_eq!(describe(&job), "Add cron job backup");
+/// ```
+pub fn describe(job: &CronOptions) -> String {
+    let verb = if job.remove { "Remove" } else { "Add" };
+    format!("{} cron job {}", verb, job.name)
+}
+
+/// Returns the comment that marks a cron job in the crontab
+fn marker(job: &CronOptions) -> String {
+    format!("# coliru: {}", job.name)
+}
+
+/// Returns the crontab line of a cron job that isn't being removed
+///
+/// ```
+/// assert_eq!(line(&job), "0 3 * * * ~/bin/backup.sh");
+/// ```
+pub fn line(job: &CronOptions) -> String {
+    format!("{} {}", job.schedule, job.command)
+}
+
+/// Returns a shell command that prints the crontab without a cron job
+fn without_job(job: &CronOptions) -> String {
+    format!("crontab -l 2>/dev/null | COLIRU_MARKER={} awk '$0 == \
+             ENVIRON[\"COLIRU_MARKER\"] {{ s = 1; next }} s {{ s = 0; next }} \
+             {{ print }}'", quote(&marker(job)))
+}
+
+/// Returns a shell command that succeeds if the crontab contains a cron job's
+/// marker
+///
+/// ```
+/// assert_eq!(exists_command(&job),
+///            "crontab -l 2>/dev/null | grep -qxF '# coliru: backup'");
+/// ```
+pub fn exists_command(job: &CronOptions) -> String {
+    format!("crontab -l 2>/dev/null | grep -qxF {}", quote(&marker(job)))
+}
+
+/// Returns a shell command that succeeds if the crontab already contains a
+/// cron job's line beneath its marker
+///
+/// ```
+/// let current = current_command(&job);
+/// ```
+pub fn current_command(job: &CronOptions) -> String {
+    format!("crontab -l 2>/dev/null | COLIRU_MARKER={} COLIRU_LINE={} awk 'p \
+             && $0 == ENVIRON[\"COLIRU_LINE\"] {{ f = 1 }} {{ p = ($0 == \
+             ENVIRON[\"COLIRU_MARKER\"]) }} END {{ exit !f }}'",
+            quote(&marker(job)), quote(&line(job)))
+}
+
+/// Returns a shell command that adds a cron job to the crontab, replacing any
+/// existing line beneath its marker
+///
+/// ```
+/// let install = install_command(&job);
+/// ```
+pub fn install_command(job: &CronOptions) -> String {
+    format!("{{ {}; printf '%s\\n' {} {}; }} | crontab -", without_job(job),
+            quote(&marker(job)), quote(&line(job)))
+}
+
+/// Returns a shell command that removes a cron job and its marker from the
+/// crontab
+///
+/// ```
+/// let remove = remove_command(&job);
+/// ```
+pub fn remove_command(job: &CronOptions) -> String {
+    format!("{} | crontab -", without_job(job))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(remove: bool) -> CronOptions {
+        CronOptions {
+            name: String::from("backup"),
+            schedule: String::from("0 3 * * *"),
+            command: String::from("~/bin/backup.sh"),
+            remove,
+        }
+    }
+
+    #[test]
+    fn test_cron_describe() {
+        assert_eq!(describe(&job(false)), "Add cron job backup");
+        assert_eq!(describe(&job(true)), "Remove cron job backup");
+        assert_eq!(line(&job(false)), "0 3 * * * ~/bin/backup.sh");
+    }
+
+    #[test]
+    fn test_cron_commands() {
+        assert_eq!(exists_command(&job(false)),
+                   "crontab -l 2>/dev/null | grep -qxF '# coliru: backup'");
+        assert_eq!(current_command(&job(false)),
+                   "crontab -l 2>/dev/null | COLIRU_MARKER='# coliru: backup' \
+                    COLIRU_LINE='0 3 * * * ~/bin/backup.sh' awk 'p && $0 == \
+                    ENVIRON[\"COLIRU_LINE\"] { f = 1 } { p = ($0 == \
+                    ENVIRON[\"COLIRU_MARKER\"]) } END { exit !f }'");
+        assert_eq!(install_command(&job(false)),
+                   "{ crontab -l 2>/dev/null | COLIRU_MARKER='# coliru: \
+                    backup' awk '$0 == ENVIRON[\"COLIRU_MARKER\"] { s = 1; \
+                    next } s { s = 0; next } { print }'; printf '%s\\n' '# \
+                    coliru: backup' '0 3 * * * ~/bin/backup.sh'; } | crontab -");
+        assert_eq!(remove_command(&job(true)),
+                   "crontab -l 2>/dev/null | COLIRU_MARKER='# coliru: backup' \
+                    awk '$0 == ENVIRON[\"COLIRU_MARKER\"] { s = 1; next } s { \
+                    s = 0; next } { print }' | crontab -");
+    }
+}
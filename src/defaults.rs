@@ -0,0 +1,115 @@
+//! Shell commands for reading and writing macOS user defaults
+//!
+//! Each defaults command is compared with the value that `defaults read`
+//! prints before it's written, so that settings that are already correct are
+//! reported as unchanged. Each function returns a POSIX shell command, so that
+//! defaults can be written the same way on the local machine and over SSH.
+//!
+//! ```
+//! let write = write_command(&entry);
+//! ```
+
+use super::local::quote;
+use super::manifest::{DefaultsOptions, DefaultsValue};
+
+/// Returns a description of a defaults command for output
+///
+/// ```
+/// assert_eq!(describe(&entry), "Set com.apple.dock autohide to true");
+/// ```
+pub fn describe(entry: &DefaultsOptions) -> String {
+    format!("Set {} {} to {}", entry.domain, entry.key, entry.value)
+}
+
+/// Returns the text that `defaults read` prints for a defaults command's value
+///
+/// Booleans are printed as `1` or `0`.
+///
+/// ```
+/// assert_eq!(expected_value(&DefaultsValue::Bool(true)), "1");
+/// ```
+pub fn expected_value(value: &DefaultsValue) -> String {
+    match value {
+        DefaultsValue::Bool(true) => String::from("1"),
+        DefaultsValue::Bool(false) => String::from("0"),
+        _ => value.to_string(),
+    }
+}
+
+/// Returns a shell command that prints the current value of a defaults
+/// command's key, or fails if it isn't set
+///
+/// ```
+/// assert_eq!(read_command(&entry),
+///            "defaults read 'com.apple.dock' 'autohide'");
+/// ```
+pub fn read_command(entry: &DefaultsOptions) -> String {
+    format!("defaults read {} {}", quote(&entry.domain), quote(&entry.key))
+}
+
+/// Returns a shell command that succeeds if a defaults command's key already
+/// has its value
+///
+/// ```
+/// let current = current_command(&entry);
+/// ```
+pub fn current_command(entry: &DefaultsOptions) -> String {
+    format!("test \"$({} 2>/dev/null)\" = {}", read_command(entry),
+            quote(&expected_value(&entry.value)))
+}
+
+/// Returns a shell command that writes a defaults command's value
+///
+/// ```
+/// assert_eq!(write_command(&entry),
+///            "defaults write 'com.apple.dock' 'autohide' -bool true");
+/// ```
+pub fn write_command(entry: &DefaultsOptions) -> String {
+    let value = match &entry.value {
+        DefaultsValue::Bool(value) => format!("-bool {}", value),
+        DefaultsValue::Int(value) => format!("-int {}", value),
+        DefaultsValue::Float(value) => format!("-float {}", value),
+        DefaultsValue::String(value) => format!("-string {}", quote(value)),
+    };
+    format!("defaults write {} {} {}", quote(&entry.domain), quote(&entry.key),
+            value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(value: DefaultsValue) -> DefaultsOptions {
+        DefaultsOptions {
+            domain: String::from("com.apple.dock"),
+            key: String::from("autohide"),
+            value,
+        }
+    }
+
+    #[test]
+    fn test_defaults_describe() {
+        assert_eq!(describe(&entry(DefaultsValue::Bool(true))),
+                   "Set com.apple.dock autohide to true");
+        assert_eq!(expected_value(&DefaultsValue::Bool(false)), "0");
+        assert_eq!(expected_value(&DefaultsValue::Float(0.5)), "0.5");
+        assert_eq!(expected_value(&DefaultsValue::Float(2.0)), "2");
+    }
+
+    #[test]
+    fn test_defaults_commands() {
+        assert_eq!(read_command(&entry(DefaultsValue::Int(2))),
+                   "defaults read 'com.apple.dock' 'autohide'");
+        assert_eq!(current_command(&entry(DefaultsValue::Bool(true))),
+                   "test \"$(defaults read 'com.apple.dock' 'autohide' \
+                    2>/dev/null)\" = '1'");
+        assert_eq!(write_command(&entry(DefaultsValue::Int(2))),
+                   "defaults write 'com.apple.dock' 'autohide' -int 2");
+        assert_eq!(write_command(&entry(DefaultsValue::Float(0.5))),
+                   "defaults write 'com.apple.dock' 'autohide' -float 0.5");
+        assert_eq!(write_command(&entry(DefaultsValue::String(
+                       String::from("it's")))),
+                   "defaults write 'com.apple.dock' 'autohide' -string \
+                    'it'\\''s'");
+    }
+}
@@ -0,0 +1,491 @@
+//! Diagnostics for the environment that coliru runs in
+//!
+//! ```
+//! let healthy = run_doctor(Some(Path::new("manifest.yml")), &[],
+//!                          &Host::default())?;
+//! ```
+
+use anyhow::Result;
+use colored::Colorize;
+use shellexpand::tilde;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf, absolute};
+use std::process::Command;
+use super::brew::brewfiles;
+use super::manifest::{ExtensionOptions, Manifest, filter_manifest_steps,
+    parse_manifest_file};
+use super::packages::PackageManager;
+use super::ssh::{Host, check_connection, transfer_program};
+use tempfile::{tempdir, tempfile_in};
+
+/// The outcome of a diagnostic check
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheckStatus {
+    /// The check passed
+    Ok,
+
+    /// The check found a problem that only affects some features
+    Warning,
+
+    /// The check found a problem that will cause installation to fail
+    Error,
+}
+impl fmt::Display for CheckStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CheckStatus::Ok => write!(f, "{}", "ok".green()),
+            CheckStatus::Warning => write!(f, "{}", "warning".yellow()),
+            CheckStatus::Error => write!(f, "{}", "error".red()),
+        }
+    }
+}
+
+/// The result of a diagnostic check
+#[derive(Debug, PartialEq)]
+pub struct Check {
+    /// The name of the check
+    pub name: String,
+
+    /// The outcome of the check
+    pub status: CheckStatus,
+
+    /// A description of what was found
+    pub detail: String,
+
+    /// How to fix the problem, if the check didn't pass
+    pub fix: Option<String>,
+}
+impl Check {
+    /// Creates a passing Check
+    fn ok(name: &str, detail: &str) -> Check {
+        Check {
+            name: name.to_owned(),
+            status: CheckStatus::Ok,
+            detail: detail.to_owned(),
+            fix: None,
+        }
+    }
+
+    /// Creates a Check that found a problem
+    fn problem(name: &str, status: CheckStatus, detail: &str, fix: &str) ->
+        Check {
+
+        Check {
+            name: name.to_owned(),
+            status,
+            detail: detail.to_owned(),
+            fix: Some(fix.to_owned()),
+        }
+    }
+}
+
+/// Checks the prerequisites for installing dotfiles and prints the results
+///
+/// If `manifest_path` is provided, the manifest's validity and the steps
+/// matching `tag_rules` are checked as well. If `host` isn't the local machine,
+/// a connection to it is attempted. Returns a bool indicating
+/// whether every check passed without errors.
+///
+/// ```
+/// let healthy = run_doctor(Some(Path::new("manifest.yml")), &[],
+///                          &Host::default())?;
+/// ```
+pub fn run_doctor(manifest_path: Option<&Path>, tag_rules: &[String],
+                  host: &Host) -> Result<bool> {
+
+    // The built-in SSH client doesn't need any external programs
+    let program = transfer_program(&host.options);
+    let required = !host.is_local() && program.is_some();
+    let mut checks = vec![check_program("ssh", &["-V"], required)];
+    if let Some(program) = program {
+        checks.push(check_program(program, &[], required));
+    }
+    checks.push(check_links());
+
+    if !host.is_local() {
+        checks.push(check_host(host));
+    }
+
+    if let Some(path) = manifest_path {
+        match parse_manifest_file(path) {
+            Ok(manifest) => {
+                let filtered = filter_manifest_steps(manifest, tag_rules);
+                checks.push(Check::ok("manifest", &format!(
+                    "{} is valid ({} matching steps)", path.display(),
+                    filtered.steps.len())));
+                checks.push(check_sources(&filtered));
+                if host.is_local() {
+                    checks.push(check_destinations(&filtered));
+                }
+                if host.is_local() &&
+                    filtered.steps.iter().any(|x| !x.packages.is_empty()) {
+                    checks.push(check_package_manager());
+                }
+                if host.is_local() &&
+                    filtered.steps.iter().any(|x| !x.brew.is_empty()) {
+                    checks.push(check_brew());
+                }
+                if host.is_local() &&
+                    filtered.steps.iter().any(|x| !x.repo.is_empty()) {
+                    checks.push(check_git());
+                }
+                if host.is_local() &&
+                    filtered.steps.iter().any(|x| !x.systemd.is_empty()) {
+                    checks.push(check_systemctl());
+                }
+                if host.is_local() &&
+                    filtered.steps.iter().any(|x| !x.defaults.is_empty()) {
+                    checks.push(check_defaults());
+                }
+                if host.is_local() {
+                    checks.extend(check_editors(&filtered));
+                }
+            },
+            Err(why) => checks.push(Check::problem("manifest",
+                CheckStatus::Error,
+                &format!("{} is invalid: {:#}", path.display(), why),
+                "Fix the manifest so that it matches the format described in \
+                 the README")),
+        }
+    }
+
+    for check in &checks {
+        println!("{} {}: {}", format!("[{}]", check.status).bold(), check.name,
+                 check.detail);
+        if let Some(fix) = &check.fix {
+            println!("  {} {}", "Fix:".bold(), fix);
+        }
+    }
+
+    Ok(checks.iter().all(|x| x.status != CheckStatus::Error))
+}
+
+/// Checks whether a program is installed, reporting its version if `args`
+/// cause it to print one
+///
+/// Missing programs are errors if they're `required` and warnings otherwise.
+fn check_program(program: &str, args: &[&str], required: bool) -> Check {
+    match Command::new(program).args(args).output() {
+        Ok(output) => {
+            // ssh -V prints its version to stderr
+            let text = if output.stdout.is_empty() {
+                String::from_utf8_lossy(&output.stderr).to_string()
+            } else {
+                String::from_utf8_lossy(&output.stdout).to_string()
+            };
+            let version = text.lines().next().unwrap_or_default().trim();
+            if args.is_empty() || version.is_empty() {
+                Check::ok(program, "installed")
+            } else {
+                Check::ok(program, version)
+            }
+        },
+        Err(why) => Check::problem(program,
+            if required { CheckStatus::Error } else { CheckStatus::Warning },
+            &format!("not found ({})", why),
+            &format!("Install `{}` (e.g. from an OpenSSH client) and add it \
+                      to your PATH (only needed for --host)", program)),
+    }
+}
+
+/// Checks whether a supported package manager is installed, which packages
+/// commands require
+fn check_package_manager() -> Check {
+    let manager = PackageManager::ALL.into_iter().find(|x| {
+        Command::new(x.program()).arg("--version").output().is_ok()
+    });
+    match manager {
+        Some(manager) => Check::ok("package manager", &manager.to_string()),
+        None => Check::problem("package manager", CheckStatus::Error,
+            "not found",
+            "Install packages with apt, dnf, pacman, or zypper, or remove the \
+             packages commands from the manifest"),
+    }
+}
+
+/// Checks whether Homebrew is installed, which brew commands require
+fn check_brew() -> Check {
+    let mut check = check_program("brew", &["--version"], true);
+    if check.fix.is_some() {
+        check.fix = Some(String::from("Install Homebrew (see https://brew.sh) \
+                                       and add `brew` to your PATH"));
+    }
+    check
+}
+
+/// Checks whether Git is installed, which repo commands require
+fn check_git() -> Check {
+    let mut check = check_program("git", &["--version"], true);
+    if check.fix.is_some() {
+        check.fix = Some(String::from("Install Git (see https://git-scm.com) \
+                                       and add `git` to your PATH"));
+    }
+    check
+}
+
+/// Checks whether systemd's `systemctl` is installed, which systemd commands
+/// require
+fn check_systemctl() -> Check {
+    let mut check = check_program("systemctl", &["--version"], true);
+    if check.fix.is_some() {
+        check.fix = Some(String::from("Systemd commands are only supported on \
+                                       Linux machines that use systemd"));
+    }
+    check
+}
+
+/// Checks whether macOS's `defaults` is installed, which defaults commands
+/// require
+fn check_defaults() -> Check {
+    let mut check = check_program("defaults", &[], true);
+    if check.fix.is_some() {
+        check.fix = Some(String::from("Defaults commands are only supported on \
+                                       macOS"));
+    }
+    check
+}
+
+/// Checks whether the VS Code compatible editors that a manifest's extension
+/// commands use are installed
+///
+/// JetBrains IDEs aren't checked, since running their launchers opens them.
+fn check_editors(manifest: &Manifest) -> Vec<Check> {
+    let mut programs: Vec<&str> = vec![];
+    for entry in manifest.steps.iter().flat_map(|x| x.extensions.iter()) {
+        if let ExtensionOptions::VsCode { program, .. } = entry {
+            if !programs.contains(&program.as_str()) {
+                programs.push(program);
+            }
+        }
+    }
+
+    programs.into_iter().map(|program| {
+        let mut check = check_program(program, &["--version"], true);
+        if check.fix.is_some() {
+            check.fix = Some(format!("Install the editor's command line \
+                                      program and add `{}` to your PATH",
+                                     program));
+        }
+        check
+    }).collect()
+}
+
+/// Checks whether links can be created in a temporary directory
+///
+/// Symbolic links are used on Unix and hard links are used on Windows.
+fn check_links() -> Check {
+    let result = tempdir().map_err(anyhow::Error::from).and_then(|dir| {
+        let src = dir.path().join("src");
+        let dst = dir.path().join("dst");
+        fs::write(&src, "")?;
+        #[cfg(target_family = "unix")]
+        std::os::unix::fs::symlink(&src, &dst)?;
+        #[cfg(not(target_family = "unix"))]
+        fs::hard_link(&src, &dst)?;
+        Ok(())
+    });
+
+    let kind = if cfg!(target_family = "unix") { "symbolic" } else { "hard" };
+    match result {
+        Ok(()) => Check::ok("links", &format!("{} links are supported", kind)),
+        Err(why) => Check::problem("links", CheckStatus::Error,
+            &format!("failed to create {} link: {}", kind, why),
+            "Use --copy to install link commands as copies, or use a file \
+             system that supports links"),
+    }
+}
+
+/// Checks whether a remote machine can be reached over SSH
+fn check_host(host: &Host) -> Check {
+    match check_connection(host) {
+        Ok(()) => Check::ok("host", &format!("connected to {}", host)),
+        Err(why) => Check::problem("host", CheckStatus::Error,
+            &format!("failed to connect to {}: {:#}", host, why),
+            &format!("Check that `ssh {}` works without a password prompt \
+                      (e.g. by adding your key with ssh-copy-id)", host)),
+    }
+}
+
+/// Checks whether the source files of a manifest exist
+fn check_sources(manifest: &Manifest) -> Check {
+    let mut missing = vec![];
+    for step in &manifest.steps {
+        let srcs = step.copy.iter().chain(step.link.iter())
+            .flat_map(|x| x.sources())
+            .chain(brewfiles(&step.brew))
+            .chain(step.systemd.iter().filter_map(|x| x.src.as_deref()))
+            .chain(step.run.iter().map(|x| x.src.as_str()));
+        for src in srcs {
+            if !manifest.base_dir.join(src).exists() &&
+                !missing.iter().any(|x| x == src) {
+                missing.push(src.to_owned());
+            }
+        }
+    }
+
+    if missing.is_empty() {
+        Check::ok("sources", "all source files exist")
+    } else {
+        Check::problem("sources", CheckStatus::Error,
+            &format!("missing {}", missing.join(", ")),
+            "Create the missing files or fix their paths, which are relative \
+             to the manifest")
+    }
+}
+
+/// Checks whether the destinations of a manifest's copy and link commands can
+/// be written to on the local machine
+fn check_destinations(manifest: &Manifest) -> Check {
+    let mut unwritable = vec![];
+    for step in &manifest.steps {
+        for entry in step.copy.iter().chain(step.link.iter()) {
+            let dst = PathBuf::from(tilde(&entry.dst).to_string());
+            let dst = if dst.is_relative() {
+                manifest.base_dir.join(dst)
+            } else {
+                dst
+            };
+            if !is_writable(&dst) && !unwritable.contains(&entry.dst) {
+                unwritable.push(entry.dst.clone());
+            }
+        }
+    }
+
+    if unwritable.is_empty() {
+        Check::ok("destinations", "all destinations are writable")
+    } else {
+        Check::problem("destinations", CheckStatus::Error,
+            &format!("cannot write to {}", unwritable.join(", ")),
+            "Fix the permissions of these files or their parent directories")
+    }
+}
+
+/// Checks whether a file can be created or replaced by creating a temporary
+/// file in its nearest existing ancestor directory
+fn is_writable(path: &Path) -> bool {
+    let Ok(path) = absolute(path) else { return false };
+    let mut dir = path.parent();
+    while let Some(_dir) = dir {
+        if _dir.is_dir() {
+            return tempfile_in(_dir).is_ok();
+        }
+        dir = _dir.parent();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::{CopyLinkOptions, Step};
+    use crate::test_utils::{setup_integration, write_file};
+    use std::collections::BTreeMap;
+
+    fn manifest(dir: &Path, src: &str, dst: &str) -> Manifest {
+        Manifest {
+            steps: vec![Step {
+                copy: vec![CopyLinkOptions {
+                    src: src.to_owned(),
+                    dst: dst.to_owned(),
+                    link_type: None,
+                    preserve_mtime: false,
+                    relative: false,
+                    remote: false,
+                    eol: None,
+                    concat: vec![],
+                    template: None,
+                }],
+                link: vec![],
+                block: vec![],
+                patch: vec![],
+                packages: vec![],
+                brew: vec![],
+                repo: vec![],
+                fetch: vec![],
+                systemd: vec![],
+                defaults: vec![],
+                extensions: vec![],
+                cron: vec![],
+                run: vec![],
+                stow: vec![],
+                parallel: false,
+                tags: vec![],
+            }],
+            pre_install: vec![],
+            post_install: vec![],
+            hosts: BTreeMap::new(),
+            vars: BTreeMap::new(),
+            exclusive_tags: vec![],
+            base_dir: dir.to_path_buf(),
+        }
+    }
+
+    #[test]
+    fn test_check_program_missing() {
+        let result = check_program("coliru-missing-program", &[], false);
+
+        assert_eq!(result.status, CheckStatus::Warning);
+        assert_eq!(result.fix.is_some(), true);
+    }
+
+    #[test]
+    fn test_check_links() {
+        let result = check_links();
+
+        assert_eq!(result.status, CheckStatus::Ok);
+    }
+
+    #[test]
+    fn test_check_sources_ok() {
+        let tmp = setup_integration("test_check_sources_ok");
+
+        write_file(&tmp.local.join("foo"), "contents of foo");
+
+        let result = check_sources(&manifest(&tmp.local, "foo", "bar"));
+
+        assert_eq!(result, Check::ok("sources", "all source files exist"));
+    }
+
+    #[test]
+    fn test_check_sources_missing() {
+        let tmp = setup_integration("test_check_sources_missing");
+
+        let result = check_sources(&manifest(&tmp.local, "foo", "bar"));
+
+        assert_eq!(result.status, CheckStatus::Error);
+        assert_eq!(result.detail, "missing foo");
+    }
+
+    #[test]
+    fn test_check_destinations_ok() {
+        let tmp = setup_integration("test_check_destinations_ok");
+
+        let result = check_destinations(&manifest(&tmp.local, "foo",
+                                                  "dir/bar"));
+
+        assert_eq!(result.status, CheckStatus::Ok);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_check_destinations_read_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = setup_integration("test_check_destinations_read_only");
+
+        let dir = tmp.local.join("dir");
+        fs::create_dir_all(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+        let is_root = tempfile_in(&dir).is_ok(); // root ignores permissions
+
+        let result = check_destinations(&manifest(&tmp.local, "foo",
+                                                  "dir/bar"));
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        if !is_root {
+            assert_eq!(result.status, CheckStatus::Error);
+            assert_eq!(result.detail, "cannot write to dir/bar");
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//! Line ending conversion for copied dotfiles
+//!
+//! Copy commands with an `eol` option install a version of their source file
+//! with converted line endings, which is rendered by the `render` module.
+//!
+//! ```
+//! let converted = convert_line_endings(b"foo\r\n", false);
+//! ```
+
+/// Converts the line endings of a file's contents
+///
+/// Existing `\r\n` and `\n` line endings are normalized to `\n` and then
+/// replaced with `\r\n` if `crlf` is true. Contents that contain a null byte
+/// are assumed to be binary and are returned unchanged.
+///
+/// ```
+/// assert_eq!(convert_line_endings(b"a\r\nb\n", true), b"a\r\nb\r\n");
+/// ```
+pub fn convert_line_endings(contents: &[u8], crlf: bool) -> Vec<u8> {
+    if contents.contains(&0) { return contents.to_vec(); }
+
+    let mut converted = Vec::with_capacity(contents.len());
+    for (i, byte) in contents.iter().enumerate() {
+        if *byte == b'\r' && contents.get(i+1) == Some(&b'\n') { continue; }
+        if *byte == b'\n' && crlf { converted.push(b'\r'); }
+        converted.push(*byte);
+    }
+    converted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_line_endings_basic() {
+        let contents = b"foo\r\nbar\nbaz\rqux";
+
+        assert_eq!(convert_line_endings(contents, false),
+                   b"foo\nbar\nbaz\rqux");
+        assert_eq!(convert_line_endings(contents, true),
+                   b"foo\r\nbar\r\nbaz\rqux");
+        assert_eq!(convert_line_endings(b"a\nb\0", true), b"a\nb\0");
+    }
+}
@@ -0,0 +1,67 @@
+//! Patterns for excluding commands from an installation
+//!
+//! ```
+//! let patterns = [parse_exclude("~/.ssh/*")?];
+//! assert_eq!(excluded_by(&patterns, &["ssh_config", "~/.ssh/config"]),
+//!            Some(&patterns[0]));
+//! ```
+
+use glob::Pattern;
+use shellexpand::tilde;
+
+/// Parses an exclude pattern, expanding a leading tilde
+///
+/// ```
+/// let pattern = parse_exclude("~/.ssh/*")?;
+/// ```
+pub fn parse_exclude(pattern: &str) -> Result<Pattern, String> {
+    Pattern::new(&tilde(pattern)).map_err(|why| {
+        format!("invalid pattern '{}': {}", pattern, why)
+    })
+}
+
+/// Returns the first pattern that matches any of a command's paths, if any
+///
+/// Tildes in the paths are expanded before they're matched, and `*` matches
+/// any sequence of characters including path separators.
+///
+/// ```
+/// let patterns = [parse_exclude("*.conf")?];
+/// assert_eq!(excluded_by(&patterns, &["vimrc", "~/.vimrc"]), None);
+/// ```
+pub fn excluded_by<'a>(patterns: &'a [Pattern], paths: &[&str]) ->
+    Option<&'a Pattern> {
+
+    patterns.iter().find(|pattern| {
+        paths.iter().any(|path| pattern.matches(&tilde(path)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_excluded_by_basic() {
+        let patterns = [
+            parse_exclude("~/.ssh/*").unwrap(),
+            parse_exclude("scripts/*.sh").unwrap(),
+        ];
+
+        assert_eq!(excluded_by(&patterns, &["ssh", "~/.ssh/config"]),
+                   Some(&patterns[0]));
+        assert_eq!(excluded_by(&patterns, &["ssh", "~/.ssh/keys/id"]),
+                   Some(&patterns[0]));
+        assert_eq!(excluded_by(&patterns, &["scripts/setup.sh"]),
+                   Some(&patterns[1]));
+        assert_eq!(excluded_by(&patterns, &["bashrc", "~/.bashrc"]), None);
+        assert_eq!(excluded_by(&[], &["bashrc", "~/.bashrc"]), None);
+    }
+
+    #[test]
+    fn test_parse_exclude_invalid() {
+        let result = parse_exclude("[");
+
+        assert_eq!(result.is_ok(), false);
+    }
+}
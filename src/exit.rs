@@ -0,0 +1,181 @@
+//! Exit codes for each class of failure
+//!
+//! ```
+//! let error = anyhow!(Error::Script(String::from("Script failed")));
+//! std::process::exit(classify(&error, Failure::Critical).code());
+//! ```
+
+use serde::Serialize;
+use thiserror::Error;
+
+/// A class of failure, each of which is reported with a distinct exit code
+///
+/// Failures are ordered by precedence, so if several classes of failure occur
+/// the greatest one determines the exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    /// Minor errors that don't belong to another class, such as non-compliant
+    /// destinations during verification
+    Minor = 1,
+
+    /// A critical error that doesn't belong to another class
+    Critical = 2,
+
+    /// The manifest couldn't be read or parsed
+    Manifest = 3,
+
+    /// A tag rule is invalid
+    TagRules = 4,
+
+    /// A file on the local machine couldn't be copied, linked, or staged
+    LocalFile = 5,
+
+    /// A script or an external command (e.g. `brew` or `git`) exited
+    /// unsuccessfully
+    Script = 6,
+
+    /// SSH or SCP couldn't communicate with another machine
+    Transport = 7,
+}
+impl Failure {
+    /// Returns the exit code of the failure
+    ///
+    /// ```
+    /// assert_eq!(Failure::Transport.code(), 7);
+    /// ```
+    pub fn code(self) -> i32 {
+        self as i32
+    }
+
+    /// Returns the failure with an exit code, or None if no failure has it
+    ///
+    /// ```
+    /// assert_eq!(Failure::from_code(7), Some(Failure::Transport));
+    /// ```
+    pub fn from_code(code: i32) -> Option<Failure> {
+        [Failure::Minor, Failure::Critical, Failure::Manifest,
+         Failure::TagRules, Failure::LocalFile, Failure::Script,
+         Failure::Transport].into_iter().find(|x| x.code() == code)
+    }
+}
+
+/// An error that belongs to a known class of failure, so that callers can
+/// match on the kind of error instead of its message
+///
+/// It may be used as the error itself or as context added to another error.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// A critical error that doesn't belong to another class
+    #[error("{0}")]
+    Critical(String),
+
+    /// The manifest couldn't be read or parsed
+    #[error("{0}")]
+    Manifest(String),
+
+    /// A tag rule is invalid
+    #[error("{0}")]
+    TagRules(String),
+
+    /// A file on the local machine couldn't be copied, linked, or staged
+    #[error("{0}")]
+    LocalFile(String),
+
+    /// A script exited unsuccessfully or timed out
+    #[error("{0}")]
+    Script(String),
+
+    /// SSH or SCP couldn't communicate with another machine
+    #[error("{0}")]
+    Transport(String),
+}
+impl Error {
+    /// Returns the class of failure of the error
+    ///
+    /// ```
+    /// let error = Error::Script(String::from("Script failed"));
+    /// assert_eq!(error.failure(), Failure::Script);
+    /// ```
+    pub fn failure(&self) -> Failure {
+        match self {
+            Error::Critical(_) => Failure::Critical,
+            Error::Manifest(_) => Failure::Manifest,
+            Error::TagRules(_) => Failure::TagRules,
+            Error::LocalFile(_) => Failure::LocalFile,
+            Error::Script(_) => Failure::Script,
+            Error::Transport(_) => Failure::Transport,
+        }
+    }
+}
+
+/// Determines the class of failure of an error, falling back to `default` if
+/// the error wasn't classified
+///
+/// ```
+/// let error = anyhow::anyhow!("Something went wrong");
+/// assert_eq!(classify(&error, Failure::Critical), Failure::Critical);
+/// ```
+pub fn classify(error: &anyhow::Error, default: Failure) -> Failure {
+    match error.downcast_ref::<Error>() {
+        Some(classified) => classified.failure(),
+        None => default,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::{Context, Result, anyhow};
+
+    #[test]
+    fn test_classify_unclassified() {
+        let error = anyhow!("Something went wrong");
+
+        assert_eq!(classify(&error, Failure::Script), Failure::Script);
+    }
+
+    #[test]
+    fn test_classify_error() {
+        let error = anyhow!(Error::Transport(String::from("Failed")));
+
+        assert_eq!(classify(&error, Failure::Script), Failure::Transport);
+        assert_eq!(error.to_string(), "Failed");
+    }
+
+    #[test]
+    fn test_classify_context() {
+        let result: Result<()> = Err(anyhow!("Inner error"))
+            .context(Error::Manifest(String::from("Middle error")))
+            .context("Outer error");
+        let error = result.unwrap_err();
+
+        assert_eq!(classify(&error, Failure::Critical), Failure::Manifest);
+        assert_eq!(format!("{:#}", error),
+                   "Outer error: Middle error: Inner error");
+    }
+
+    #[test]
+    fn test_error_failure() {
+        let error = Error::LocalFile(String::from("Failed to copy foo"));
+
+        assert_eq!(error.failure(), Failure::LocalFile);
+        assert_eq!(error.to_string(), "Failed to copy foo");
+    }
+
+    #[test]
+    fn test_failure_precedence() {
+        assert_eq!(Some(Failure::Transport).max(Some(Failure::Script)),
+                   Some(Failure::Transport));
+        assert_eq!(None.max(Some(Failure::LocalFile)),
+                   Some(Failure::LocalFile));
+    }
+
+    #[test]
+    fn test_failure_from_code() {
+        assert_eq!(Failure::from_code(1), Some(Failure::Minor));
+        assert_eq!(Failure::from_code(5), Some(Failure::LocalFile));
+        assert_eq!(Failure::from_code(0), None);
+        assert_eq!(Failure::from_code(127), None);
+    }
+}
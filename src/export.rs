@@ -0,0 +1,1278 @@
+//! Conversion of manifests into the formats of other provisioning tools
+//!
+//! ```
+//! let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+//! let playbook = ansible_playbook(&manifest, &[String::from("linux")],
+//!                                 LinkMode::Symlink)?;
+//! let script = shell_script(&manifest, &[String::from("linux")],
+//!                           LinkMode::Symlink)?;
+//! ```
+
+use anyhow::{Context, Result};
+use serde_yaml::{Mapping, Value};
+use std::path::{Path, absolute};
+use super::brew::{check_command, describe, install_command};
+use super::core::SSH_INSTALL_DIR;
+use super::cron;
+use super::defaults;
+use super::extension;
+use super::fetch;
+use super::repo::{clone_command, current_command, exists_command,
+    fetch_command, update_command};
+use super::facts::{Facts, expand_facts, uses_facts};
+use super::local::{as_user, quote, shell_command_line, with_env};
+use super::manifest::{BlockOptions, BrewOptions, CopyLinkOptions, CronOptions,
+    DefaultsOptions, DefaultsValue, ExtensionOptions, FetchOptions, LineEnding,
+    LinkMode, Manifest, PackageOptions, PatchOptions, RepoOptions, RunOptions,
+    Shell, SystemdOptions, SystemdScope, filter_manifest_steps};
+use super::packages::{self, PackageManager, shell_function};
+use super::ssh::quote_path;
+use super::systemd;
+use super::tag_expr::join_tag_rules;
+
+/// The start of every exported shell script, which stops at the first error and
+/// runs from the directory that contains the script
+const SCRIPT_HEADER: &str = "\
+#!/bin/sh
+# Generated by coliru export shell. Place this script in the directory that
+# contains the manifest, then run it to install the dotfiles.
+set -e
+cd \"$(dirname \"$0\")\"
+";
+
+/// The shell commands that set the variables of facts about the machine, which
+/// are included in exported scripts whose run postfixes use facts
+const SCRIPT_FACTS: &str = "\
+COLIRU_OS=$(uname -s | tr '[:upper:]' '[:lower:]')
+if [ \"$COLIRU_OS\" = darwin ]; then COLIRU_OS=macos; fi
+COLIRU_ARCH=$(uname -m)
+case \"$COLIRU_ARCH\" in
+    arm64) COLIRU_ARCH=aarch64 ;;
+    amd64) COLIRU_ARCH=x86_64 ;;
+esac
+COLIRU_HOSTNAME=$(hostname)
+COLIRU_USER=$(whoami)
+COLIRU_HOME=$HOME
+COLIRU_REMOTE=false
+export COLIRU_OS COLIRU_ARCH COLIRU_HOSTNAME COLIRU_USER COLIRU_HOME \\
+    COLIRU_REMOTE
+";
+
+/// The shell function that re-runs a failed script, which is included in
+/// exported scripts with run commands that have retries
+const SCRIPT_RETRY: &str = "\
+coliru_retry() {
+    retries=$1 delay=$2 attempt=0
+    shift 2
+    until \"$@\"; do
+        attempt=$((attempt + 1))
+        if [ \"$attempt\" -gt \"$retries\" ]; then return 1; fi
+        echo \"  Retrying ($attempt/$retries)\" >&2
+        sleep \"$delay\"
+    done
+}
+";
+
+/// The facts substituted into run postfixes in a playbook, as Jinja expressions
+/// that Ansible evaluates and quotes on each managed node
+fn ansible_facts() -> Facts {
+    Facts {
+        os: String::from("{{ 'macos' if ansible_system == 'Darwin' else \
+                          ansible_system | lower }}"),
+        arch: String::from("{{ ansible_architecture | quote }}"),
+        hostname: String::from("{{ ansible_hostname | quote }}"),
+        username: String::from("{{ ansible_user_id | quote }}"),
+        home: String::from("{{ ansible_env.HOME | quote }}"),
+        remote: true,
+    }
+}
+
+/// Converts the steps of a manifest that match a set of tag rules into an
+/// Ansible playbook
+///
+/// The playbook has a single play for all hosts. Copy commands become `copy`
+/// tasks, link commands become `file` tasks with `state: link` (or
+/// `state: hard`) that point to a copy of their source file in `~/.coliru`,
+/// and run commands become `command` tasks that execute a copy of their script
+/// in `~/.coliru`, like an installation over SSH. Block and patch commands
+/// become `blockinfile` and `ansible.posix.patch` tasks, packages commands
+/// become `package` tasks that run as root, brew commands become `homebrew`
+/// and `homebrew_cask` tasks (or `brew bundle` for Brewfiles), repo commands
+/// become `git` tasks, fetch commands become `get_url` tasks, systemd commands
+/// become `copy` and `systemd_service` tasks, defaults commands become
+/// `osx_defaults` tasks, extension commands become `command` tasks, cron
+/// commands become `cron` tasks (which mark jobs with Ansible's own comments),
+/// hooks run on every play, and each task is tagged with its step's tags. Line
+/// endings aren't converted, and units aren't restarted when their unit file
+/// changes. Returns an Err if the manifest's directory can't be resolved.
+///
+/// ```
+/// let playbook = ansible_playbook(&manifest, &[], LinkMode::Symlink)?;
+/// ```
+pub fn ansible_playbook(manifest: &Manifest, tag_rules: &[String],
+                        link_mode: LinkMode) -> Result<String> {
+
+    let base_dir = absolute(&manifest.base_dir).with_context(|| {
+        format!("Failed to make {} absolute", manifest.base_dir.display())
+    })?;
+    let manifest = filter_manifest_steps(manifest.clone(), tag_rules);
+    let facts = ansible_facts();
+
+    let mut tasks = vec![];
+    let hook_tags = [String::from("always")];
+    for cmd in &manifest.pre_install {
+        tasks.push(hook_task(cmd, &hook_tags));
+    }
+    for step in &manifest.steps {
+        let mut step_tasks = StepTasks::new(&base_dir, &step.tags);
+        for copy in &step.copy {
+            step_tasks.copy(copy);
+        }
+        for link in &step.link {
+            match link.link_mode(link_mode) {
+                LinkMode::Copy => step_tasks.copy(link),
+                LinkMode::Symlink => step_tasks.link(link, "link"),
+                LinkMode::Hardlink => step_tasks.link(link, "hard"),
+            }
+        }
+        for block in &step.block {
+            step_tasks.block(block);
+        }
+        for patch in &step.patch {
+            step_tasks.patch(patch);
+        }
+        for package in &step.packages {
+            step_tasks.package(package);
+        }
+        for brew in &step.brew {
+            step_tasks.brew(brew);
+        }
+        for repo in &step.repo {
+            step_tasks.repo(repo);
+        }
+        for fetch in &step.fetch {
+            step_tasks.fetch(fetch);
+        }
+        for unit in &step.systemd {
+            step_tasks.systemd(unit);
+        }
+        for entry in &step.defaults {
+            step_tasks.defaults(entry);
+        }
+        for entry in &step.extensions {
+            step_tasks.extension(entry);
+        }
+        for job in &step.cron {
+            step_tasks.cron(job);
+        }
+        for run in &step.run {
+            step_tasks.run(run, tag_rules, &facts);
+        }
+        tasks.extend(step_tasks.tasks);
+    }
+    for cmd in &manifest.post_install {
+        tasks.push(hook_task(cmd, &hook_tags));
+    }
+
+    let play = mapping(vec![
+        ("name", Value::from("Install dotfiles")),
+        ("hosts", Value::from("all")),
+        ("tasks", Value::Sequence(tasks)),
+    ]);
+    let yaml = serde_yaml::to_string(&Value::Sequence(vec![play]))
+        .context("Failed to serialize playbook")?;
+    Ok(format!("# Generated by coliru export ansible\n{}", yaml))
+}
+
+/// Converts the steps of a manifest that match a set of tag rules into a POSIX
+/// shell script
+///
+/// The script performs the same copies, links, packages, brews, clones,
+/// downloads, systemd units, defaults, extensions, cron jobs, and runs as an
+/// installation on the machine that it's run on, printing the same description
+/// of each command, and stops at the first error. Packages and VS Code
+/// extensions that are already installed aren't reinstalled, system packages
+/// are installed with whichever supported package manager the machine has,
+/// cloned repositories are updated, downloads are verified, and units,
+/// defaults, and cron jobs are only changed if they aren't already set. Run
+/// postfixes read facts about the machine from variables that the script sets.
+/// Symbolic links always have absolute targets, block and patch commands are
+/// skipped with a warning, and scripts don't time out.
+///
+/// ```
+/// let script = shell_script(&manifest, &[], LinkMode::Symlink)?;
+/// ```
+pub fn shell_script(manifest: &Manifest, tag_rules: &[String],
+                    link_mode: LinkMode) -> Result<String> {
+
+    let manifest = filter_manifest_steps(manifest.clone(), tag_rules);
+    let runs = || manifest.steps.iter().flat_map(|x| x.run.iter());
+
+    let mut script = String::from(SCRIPT_HEADER);
+    if uses_facts(&manifest) {
+        script.push_str(&format!("\n{}", SCRIPT_FACTS));
+    }
+    if runs().any(|x| x.retries > 0) {
+        script.push_str(&format!("\n{}", SCRIPT_RETRY));
+    }
+    if manifest.steps.iter().any(|x| !x.packages.is_empty()) {
+        script.push_str(&format!("\n{}", shell_function()));
+    }
+
+    let mut lines = vec![];
+    for cmd in &manifest.pre_install {
+        lines.push(echo(&format!("[pre_install] Run {}", cmd)));
+        lines.push(cmd.clone());
+    }
+    let step_count = manifest.steps.len();
+    for (i, step) in manifest.steps.iter().enumerate() {
+        let label = format!("[{}/{}]", i + 1, step_count);
+        for copy in &step.copy {
+            lines.push(echo(&format!("{} Copy {} to {}", label, copy.src,
+                                     copy.dst)));
+            lines.push(copy_line(copy));
+        }
+        for link in &step.link {
+            let mode = link.link_mode(link_mode);
+            let verb = if mode == LinkMode::Copy { "Copy" } else { "Link" };
+            lines.push(echo(&format!("{} {} {} to {}", label, verb, link.src,
+                                     link.dst)));
+            lines.push(match mode {
+                LinkMode::Copy => copy_line(link),
+                LinkMode::Symlink | LinkMode::Hardlink => {
+                    link_line(link, mode)
+                },
+            });
+        }
+        for block in &step.block {
+            lines.push(format!("{} >&2", echo(&format!(
+                "{} Skipping merge of {} into {} (block commands aren't \
+                 exported)", label, block.src, block.dst))));
+        }
+        for patch in &step.patch {
+            lines.push(format!("{} >&2", echo(&format!(
+                "{} Skipping patch of {} with {} (patch commands aren't \
+                 exported)", label, patch.dst, patch.src))));
+        }
+        for package in &step.packages {
+            lines.push(echo(&format!("{} {}", label,
+                                     packages::describe(&package.name, None))));
+            let names: Vec<String> = PackageManager::ALL.iter()
+                .map(|x| quote(x.package_name(package))).collect();
+            lines.push(format!("coliru_package {}", names.join(" ")));
+        }
+        for brew in &step.brew {
+            lines.push(echo(&format!("{} {}", label, describe(brew))));
+            lines.push(format!("{} || {}", check_command(brew),
+                               install_command(brew)));
+        }
+        for repo in &step.repo {
+            lines.push(echo(&format!("{} Clone {} to {}", label, repo.url,
+                                     repo.dst)));
+            lines.push(format!("if {}; then {} && {{ {} || {}; }}; else {}; fi",
+                               exists_command(repo), fetch_command(repo),
+                               current_command(repo), update_command(repo),
+                               clone_command(repo)));
+        }
+        for entry in &step.fetch {
+            lines.push(echo(&format!("{} Download {} to {}", label, entry.url,
+                                     entry.dst)));
+            lines.push(format!("{} || {{ {} && {{ {} || {{ {}; echo {} >&2; \
+                                exit 1; }}; }} && {}; }}",
+                               fetch::current_command(entry),
+                               fetch::download_command(entry),
+                               fetch::verify_command(entry),
+                               fetch::discard_command(entry),
+                               quote(&format!("  Error: Checksum doesn't \
+                                               match {}", entry.sha256)),
+                               fetch::install_command(entry)));
+        }
+        for unit in &step.systemd {
+            lines.push(echo(&format!("{} {}", label, systemd::describe(unit))));
+            if unit.src.is_some() {
+                let install = systemd::install_command(unit);
+                let install = if unit.start {
+                    format!("{{ {} && {{ ! {} || {}; }}; }}", install,
+                            systemd::active_command(unit),
+                            systemd::start_command(unit, true))
+                } else {
+                    install
+                };
+                lines.push(format!("{} || {}",
+                                   systemd::installed_command(unit), install));
+            }
+            if unit.enable {
+                lines.push(format!("{} || {}", systemd::enabled_command(unit),
+                                   systemd::enable_command(unit)));
+            }
+            if unit.start {
+                lines.push(format!("{} || {}", systemd::active_command(unit),
+                                   systemd::start_command(unit, false)));
+            }
+        }
+        for entry in &step.defaults {
+            lines.push(echo(&format!("{} {}", label,
+                                     defaults::describe(entry))));
+            lines.push(format!("{} || {}", defaults::current_command(entry),
+                               defaults::write_command(entry)));
+        }
+        for entry in &step.extensions {
+            lines.push(echo(&format!("{} {}", label,
+                                     extension::describe(entry))));
+            let install = extension::install_command(entry);
+            lines.push(match extension::check_command(entry) {
+                Some(check) => format!("{} || {}", check, install),
+                None => install,
+            });
+        }
+        for job in &step.cron {
+            lines.push(echo(&format!("{} {}", label, cron::describe(job))));
+            lines.push(if job.remove {
+                format!("! {} || {}", cron::exists_command(job),
+                        cron::remove_command(job))
+            } else {
+                format!("{} || {}", cron::current_command(job),
+                        cron::install_command(job))
+            });
+        }
+        for run in &step.run {
+            let shell = run.shell.unwrap_or(Shell::Sh);
+            let postfix = run.postfix.replace("$COLIRU_RULES",
+                                              &join_tag_rules(tag_rules,
+                                                              shell));
+            let cmd = format!("{} {} {}", run.prefix, run.src, postfix);
+            lines.push(echo(&format!("{} Run {}", label, cmd.trim())));
+            lines.push(run_line(&cmd, run)?);
+        }
+    }
+    for cmd in &manifest.post_install {
+        lines.push(echo(&format!("[post_install] Run {}", cmd)));
+        lines.push(cmd.clone());
+    }
+
+    if !lines.is_empty() {
+        script.push_str(&format!("\n{}\n", lines.join("\n")));
+    }
+    Ok(script)
+}
+
+/// Returns a shell command that prints a line of text
+fn echo(text: &str) -> String {
+    format!("echo {}", quote(text))
+}
+
+/// Returns a shell command that creates the parent directory of a destination
+fn mkdir_line(dst: &str) -> String {
+    format!("mkdir -p \"$(dirname {})\"", quote_path(dst))
+}
+
+/// Returns a shell command that installs a copy command's file, replacing the
+/// destination instead of writing through it
+///
+/// The file is written next to the destination and then renamed, which also
+/// works if the source and destination are the same file.
+fn copy_line(copy: &CopyLinkOptions) -> String {
+    let dst = quote_path(&copy.dst);
+    let temp = format!("{}.coliru-tmp", dst);
+    let sources: Vec<String> = copy.sources().into_iter().map(quote_path)
+        .collect();
+    let filter = match copy.eol {
+        Some(LineEnding::Crlf) => {
+            Some("awk '{ sub(/\\r$/, \"\"); printf \"%s\\r\\n\", $0 }'")
+        },
+        Some(LineEnding::Lf | LineEnding::Native) => {
+            Some("awk '{ sub(/\\r$/, \"\"); print }'")
+        },
+        None => None,
+    };
+    let write = match filter {
+        Some(filter) => {
+            format!("cat {} | {} > {}", sources.join(" "), filter, temp)
+        },
+        None if sources.len() > 1 => {
+            format!("cat {} > {}", sources.join(" "), temp)
+        },
+        None => {
+            let flags = if copy.preserve_mtime { "-p " } else { "" };
+            format!("cp {}{} {}", flags, sources[0], temp)
+        },
+    };
+    format!("{} && {} && mv -f {} {}", mkdir_line(&copy.dst), write, temp,
+            dst)
+}
+
+/// Returns a shell command that installs a link command's file as a symbolic
+/// or hard link
+fn link_line(link: &CopyLinkOptions, mode: LinkMode) -> String {
+    let src = if link.src.starts_with(['~', '/']) {
+        quote_path(&link.src)
+    } else {
+        format!("\"$PWD\"/{}", quote(link.src.trim_start_matches("./")))
+    };
+    let flags = if mode == LinkMode::Hardlink { "-f" } else { "-sfn" };
+    format!("{} && ln {} {} {}", mkdir_line(&link.dst), flags, src,
+            quote_path(&link.dst))
+}
+
+/// Returns the shell command that executes a run command's command line
+fn run_line(cmd: &str, run: &RunOptions) -> Result<String> {
+    let line = with_env(cmd, &run.env, run.shell.unwrap_or(Shell::Sh))?;
+    let line = match &run.user {
+        Some(user) => as_user(&line, user, run.shell),
+        // Variables are exported in a subshell so they only reach the script
+        None if run.shell.is_none() && !run.env.is_empty() => {
+            format!("({})", line)
+        },
+        None => shell_command_line(&line, run.shell),
+    };
+    if run.retries > 0 {
+        return Ok(format!("coliru_retry {} {} sh -c {}", run.retries,
+                          run.retry_delay, quote(&line)));
+    }
+    Ok(line)
+}
+
+/// The tasks that install a single step of a manifest
+struct StepTasks<'a> {
+    /// The absolute path of the directory containing the manifest
+    base_dir: &'a Path,
+
+    /// The step's tags
+    tags: &'a [String],
+
+    /// The directories that tasks in the step have already created
+    dirs: Vec<String>,
+
+    /// The tasks, in order
+    tasks: Vec<Value>,
+}
+impl<'a> StepTasks<'a> {
+    /// Creates an empty set of tasks for a step
+    fn new(base_dir: &'a Path, tags: &'a [String]) -> StepTasks<'a> {
+        StepTasks { base_dir, tags, dirs: vec![], tasks: vec![] }
+    }
+
+    /// Adds a task that executes an Ansible module, with the step's tags
+    fn push(&mut self, name: String, module: &str,
+            args: Vec<(&str, Value)>) {
+        self.tasks.push(task(name, module, args, self.tags, vec![]));
+    }
+
+    /// Adds a task that creates the parent directory of a destination, unless
+    /// an earlier task in the step already created it or one of its
+    /// subdirectories
+    fn create_parent(&mut self, dst: &str) {
+        let Some((parent, _)) = dst.rsplit_once('/') else { return };
+        if parent.is_empty() || parent == "~" ||
+            self.dirs.iter().any(|x| x == parent) {
+            return;
+        }
+        let mut dir = parent;
+        while let Some((ancestor, _)) = dir.rsplit_once('/') {
+            self.dirs.push(dir.to_owned());
+            dir = ancestor;
+        }
+        self.push(format!("Create {}", parent), "ansible.builtin.file", vec![
+            ("path", Value::from(parent)),
+            ("state", Value::from("directory")),
+        ]);
+    }
+
+    /// Returns the absolute path of a source file on the control node
+    fn local_path(&self, src: &str) -> String {
+        self.base_dir.join(src.trim_start_matches("./")).to_string_lossy()
+            .to_string()
+    }
+
+    /// Adds a task that copies a file into `~/.coliru` on the managed node,
+    /// returning the path of the copy
+    fn stage(&mut self, src: &str) -> String {
+        let staged = format!("~/{}/{}", SSH_INSTALL_DIR,
+                             src.trim_start_matches("./"));
+        self.create_parent(&staged);
+        let local_path = self.local_path(src);
+        self.push(format!("Copy {} to {}", src, staged), "ansible.builtin.copy",
+                  vec![
+                      ("src", Value::from(local_path)),
+                      ("dest", Value::from(staged.as_str())),
+                      ("mode", Value::from("preserve")),
+                  ]);
+        staged
+    }
+
+    /// Adds the tasks of a copy command
+    fn copy(&mut self, copy: &CopyLinkOptions) {
+        self.create_parent(&copy.dst);
+        // Concatenated files are rendered on the control node, so there's no
+        // single source file to preserve the permissions of
+        let args = if copy.concat.is_empty() {
+            vec![
+                ("src", Value::from(self.local_path(&copy.src))),
+                ("dest", Value::from(copy.dst.as_str())),
+                ("mode", Value::from("preserve")),
+            ]
+        } else {
+            let content: String = copy.concat.iter().map(|x| {
+                format!("{{{{ lookup('file', '{}', rstrip=False) }}}}",
+                        self.local_path(x))
+            }).collect();
+            vec![
+                ("content", Value::from(content)),
+                ("dest", Value::from(copy.dst.as_str())),
+            ]
+        };
+        self.push(format!("Copy {} to {}", copy.src, copy.dst),
+                  "ansible.builtin.copy", args);
+    }
+
+    /// Adds the tasks of a link command that creates a link with a `file`
+    /// state of `state`
+    fn link(&mut self, link: &CopyLinkOptions, state: &str) {
+        let staged = self.stage(&link.src);
+        self.create_parent(&link.dst);
+        self.push(format!("Link {} to {}", link.src, link.dst),
+                  "ansible.builtin.file", vec![
+                      ("src", Value::from(staged)),
+                      ("dest", Value::from(link.dst.as_str())),
+                      ("state", Value::from(state)),
+                      ("force", Value::from(true)),
+                  ]);
+    }
+
+    /// Adds the tasks of a block command
+    fn block(&mut self, block: &BlockOptions) {
+        let comment = block.comment.as_deref().unwrap_or("#");
+        let local_path = self.local_path(&block.src);
+        self.push(format!("Merge {} into {}", block.src, block.dst),
+                  "ansible.builtin.blockinfile", vec![
+                      ("path", Value::from(block.dst.as_str())),
+                      ("block", Value::from(format!(
+                          "{{{{ lookup('file', '{}') }}}}", local_path))),
+                      ("marker", Value::from(format!(
+                          "{} {{mark}} coliru {}", comment, block.src))),
+                      ("create", Value::from(true)),
+                  ]);
+    }
+
+    /// Adds the tasks of a patch command
+    fn patch(&mut self, patch: &PatchOptions) {
+        let local_path = self.local_path(&patch.src);
+        self.push(format!("Patch {} with {}", patch.dst, patch.src),
+                  "ansible.posix.patch", vec![
+                      ("src", Value::from(local_path)),
+                      ("dest", Value::from(patch.dst.as_str())),
+                  ]);
+    }
+
+    /// Adds the task of a packages command, which picks the package's name for
+    /// the managed node's package manager when it has overrides
+    fn package(&mut self, package: &PackageOptions) {
+        let overrides: Vec<String> = PackageManager::ALL.iter()
+            .filter(|x| x.package_name(package) != package.name)
+            .map(|x| format!("'{}': '{}'", x, x.package_name(package)))
+            .collect();
+        let name = if overrides.is_empty() {
+            package.name.clone()
+        } else {
+            format!("{{{{ {{{}}}.get(ansible_pkg_mgr, '{}') }}}}",
+                    overrides.join(", "), package.name)
+        };
+        self.tasks.push(task(packages::describe(&package.name, None),
+                             "ansible.builtin.package", vec![
+                                 ("name", Value::from(name)),
+                                 ("state", Value::from("present")),
+                             ], self.tags, vec![
+                                 ("become", Value::from(true)),
+                             ]));
+    }
+
+    /// Adds the tasks of a brew command
+    fn brew(&mut self, brew: &BrewOptions) {
+        let (module, name) = match brew {
+            BrewOptions::Formula(name) => ("community.general.homebrew", name),
+            BrewOptions::Cask(name) => {
+                ("community.general.homebrew_cask", name)
+            },
+            BrewOptions::Brewfile(path) => {
+                self.stage(path);
+                let file = format!("--file={}", path.trim_start_matches("./"));
+                self.push(describe(brew), "ansible.builtin.command", vec![
+                    ("argv", Value::from(vec!["brew", "bundle", "install",
+                                              "--no-upgrade", &file])),
+                    ("chdir", Value::from(format!("~/{}", SSH_INSTALL_DIR))),
+                ]);
+                return;
+            },
+        };
+        self.push(describe(brew), module, vec![
+            ("name", Value::from(name.as_str())),
+            ("state", Value::from("present")),
+        ]);
+    }
+
+    /// Adds the tasks of a repo command
+    fn repo(&mut self, repo: &RepoOptions) {
+        self.create_parent(repo.dst.trim_end_matches('/'));
+        let mut args = vec![
+            ("repo", Value::from(repo.url.as_str())),
+            ("dest", Value::from(repo.dst.as_str())),
+        ];
+        if let Some(rev) = &repo.rev {
+            args.push(("version", Value::from(rev.as_str())));
+        }
+        self.push(format!("Clone {} to {}", repo.url, repo.dst),
+                  "ansible.builtin.git", args);
+    }
+
+    /// Adds the tasks of a fetch command
+    fn fetch(&mut self, fetch: &FetchOptions) {
+        self.create_parent(&fetch.dst);
+        let mut args = vec![
+            ("url", Value::from(fetch.url.as_str())),
+            ("dest", Value::from(fetch.dst.as_str())),
+            ("checksum", Value::from(format!("sha256:{}", fetch.sha256))),
+        ];
+        // Ansible reads modes without a leading zero as decimal
+        if let Some(mode) = &fetch.mode {
+            args.push(("mode", Value::from(format!("{:0>4}", mode))));
+        }
+        self.push(format!("Download {} to {}", fetch.url, fetch.dst),
+                  "ansible.builtin.get_url", args);
+    }
+
+    /// Adds the tasks of a systemd command
+    fn systemd(&mut self, unit: &SystemdOptions) {
+        let system = unit.scope == SystemdScope::System;
+        let keywords = || if system {
+            vec![("become", Value::from(true))]
+        } else {
+            vec![]
+        };
+        if let Some(src) = &unit.src {
+            let dst = systemd::unit_path(unit);
+            if !system {
+                self.create_parent(&dst);
+            }
+            self.tasks.push(task(format!("Copy {} to {}", src, dst),
+                                 "ansible.builtin.copy", vec![
+                                     ("src", Value::from(self.local_path(src))),
+                                     ("dest", Value::from(dst.as_str())),
+                                     ("mode", Value::from("0644")),
+                                 ], self.tags, keywords()));
+        }
+
+        let mut args = vec![
+            ("name", Value::from(unit.unit.as_str())),
+            ("scope", Value::from(unit.scope.to_string())),
+        ];
+        if unit.src.is_some() {
+            args.push(("daemon_reload", Value::from(true)));
+        }
+        if unit.enable {
+            args.push(("enabled", Value::from(true)));
+        }
+        if unit.start {
+            args.push(("state", Value::from("started")));
+        }
+        self.tasks.push(task(systemd::describe(unit),
+                             "ansible.builtin.systemd_service", args,
+                             self.tags, keywords()));
+    }
+
+    /// Adds the tasks of a defaults command
+    fn defaults(&mut self, entry: &DefaultsOptions) {
+        let value_type = match entry.value {
+            DefaultsValue::Bool(_) => "bool",
+            DefaultsValue::Int(_) => "int",
+            DefaultsValue::Float(_) => "float",
+            DefaultsValue::String(_) => "string",
+        };
+        self.push(defaults::describe(entry), "community.general.osx_defaults",
+                  vec![
+                      ("domain", Value::from(entry.domain.as_str())),
+                      ("key", Value::from(entry.key.as_str())),
+                      ("type", Value::from(value_type)),
+                      ("value", Value::from(entry.value.to_string())),
+                      ("state", Value::from("present")),
+                  ]);
+    }
+
+    /// Adds the tasks of an extension command
+    fn extension(&mut self, entry: &ExtensionOptions) {
+        let argv = match entry {
+            ExtensionOptions::VsCode { id, program } => {
+                vec![program.as_str(), "--install-extension", id]
+            },
+            ExtensionOptions::JetBrains { id, program } => {
+                vec![program.as_str(), "installPlugins", id]
+            },
+        };
+        self.push(extension::describe(entry), "ansible.builtin.command", vec![
+            ("argv", Value::from(argv)),
+        ]);
+    }
+
+    /// Adds the tasks of a cron command
+    fn cron(&mut self, job: &CronOptions) {
+        let mut args = vec![("name", Value::from(job.name.as_str()))];
+        if job.remove {
+            args.push(("state", Value::from("absent")));
+        } else if let Some(special) = job.schedule.strip_prefix('@') {
+            args.push(("special_time", Value::from(special)));
+            args.push(("job", Value::from(job.command.as_str())));
+        } else {
+            let fields = ["minute", "hour", "day", "month", "weekday"];
+            args.extend(fields.into_iter().zip(job.schedule.split_whitespace())
+                .map(|(key, value)| (key, Value::from(value))));
+            args.push(("job", Value::from(job.command.as_str())));
+        }
+        self.push(cron::describe(job), "ansible.builtin.cron", args);
+    }
+
+    /// Adds the tasks of a run command
+    fn run(&mut self, run: &RunOptions, tag_rules: &[String], facts: &Facts) {
+        self.stage(&run.src);
+
+        let shell = run.shell.unwrap_or(Shell::Sh);
+        let postfix = run.postfix.replace("$COLIRU_RULES",
+                                          &join_tag_rules(tag_rules, shell));
+        let postfix = expand_facts(&postfix, facts, None);
+        let cmd = format!("{} {} {}", run.prefix, run.src, postfix);
+        let (program, args) = shell.invocation();
+        let mut argv: Vec<Value> = vec![Value::from(program)];
+        argv.extend(args.iter().map(|x| Value::from(*x)));
+        argv.push(Value::from(cmd.trim()));
+
+        let mut keywords = vec![];
+        if !run.env.is_empty() {
+            keywords.push(("environment", mapping(run.env.iter().map(|x| {
+                (x.0.as_str(), Value::from(x.1.as_str()))
+            }).collect())));
+        }
+        if let Some(user) = &run.user {
+            keywords.push(("become", Value::from(true)));
+            keywords.push(("become_user", Value::from(user.as_str())));
+        }
+        if let Some(timeout) = run.timeout {
+            keywords.push(("timeout", Value::from(timeout)));
+        }
+        if run.retries > 0 {
+            keywords.push(("register", Value::from("coliru_run")));
+            keywords.push(("retries", Value::from(run.retries)));
+            keywords.push(("delay", Value::from(run.retry_delay)));
+            keywords.push(("until", Value::from("coliru_run is succeeded")));
+        }
+
+        self.tasks.push(task(format!("Run {}", cmd.trim()),
+                             "ansible.builtin.command", vec![
+                                 ("argv", Value::Sequence(argv)),
+                                 ("chdir", Value::from(format!(
+                                     "~/{}", SSH_INSTALL_DIR))),
+                             ], self.tags, keywords));
+    }
+}
+
+/// Returns a task that runs a pre-install or post-install hook from the home
+/// directory
+fn hook_task(cmd: &str, tags: &[String]) -> Value {
+    task(format!("Run {}", cmd), "ansible.builtin.command", vec![
+        ("argv", Value::from(vec!["sh", "-c", cmd])),
+        ("chdir", Value::from("~")),
+    ], tags, vec![])
+}
+
+/// Returns a task that executes an Ansible module with a set of arguments,
+/// followed by any tags and other task keywords
+fn task(name: String, module: &str, args: Vec<(&str, Value)>, tags: &[String],
+        keywords: Vec<(&str, Value)>) -> Value {
+
+    let mut pairs = vec![
+        ("name", Value::from(name)),
+        (module, mapping(args)),
+    ];
+    pairs.extend(keywords);
+    if !tags.is_empty() {
+        pairs.push(("tags", Value::from(tags)));
+    }
+    mapping(pairs)
+}
+
+/// Returns a YAML mapping with a set of keys and values, in order
+fn mapping(pairs: Vec<(&str, Value)>) -> Value {
+    let mut mapping = Mapping::new();
+    for (key, value) in pairs {
+        mapping.insert(Value::from(key), value);
+    }
+    Value::Mapping(mapping)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::parse_manifest_str;
+
+    #[test]
+    fn test_ansible_playbook_basic() {
+        let contents = "\
+pre_install: [ git pull ]
+steps:
+  - copy: [ { src: gitconfig, dst: ~/.gitconfig } ]
+    link: [ { src: nvim/init.lua, dst: ~/.config/nvim/init.lua } ]
+    run: [ { src: ./setup.sh, postfix: $COLIRU_RULES $COLIRU_USER } ]
+    tags: [ linux ]
+  - copy: [ { src: vimrc, dst: ~/_vimrc } ]
+    tags: [ windows ]
+";
+        let manifest = parse_manifest_str(contents, Path::new("/dotfiles"))
+            .unwrap();
+        let rules = [String::from("linux")];
+
+        let actual = ansible_playbook(&manifest, &rules, LinkMode::Symlink)
+            .unwrap();
+
+        let expected = "\
+# Generated by coliru export ansible
+- name: Install dotfiles
+  hosts: all
+  tasks:
+  - name: Run git pull
+    ansible.builtin.command:
+      argv:
+      - sh
+      - -c
+      - git pull
+      chdir: '~'
+    tags:
+    - always
+  - name: Copy gitconfig to ~/.gitconfig
+    ansible.builtin.copy:
+      src: /dotfiles/gitconfig
+      dest: ~/.gitconfig
+      mode: preserve
+    tags:
+    - linux
+  - name: Create ~/.coliru/nvim
+    ansible.builtin.file:
+      path: ~/.coliru/nvim
+      state: directory
+    tags:
+    - linux
+  - name: Copy nvim/init.lua to ~/.coliru/nvim/init.lua
+    ansible.builtin.copy:
+      src: /dotfiles/nvim/init.lua
+      dest: ~/.coliru/nvim/init.lua
+      mode: preserve
+    tags:
+    - linux
+  - name: Create ~/.config/nvim
+    ansible.builtin.file:
+      path: ~/.config/nvim
+      state: directory
+    tags:
+    - linux
+  - name: Link nvim/init.lua to ~/.config/nvim/init.lua
+    ansible.builtin.file:
+      src: ~/.coliru/nvim/init.lua
+      dest: ~/.config/nvim/init.lua
+      state: link
+      force: true
+    tags:
+    - linux
+  - name: Copy ./setup.sh to ~/.coliru/setup.sh
+    ansible.builtin.copy:
+      src: /dotfiles/setup.sh
+      dest: ~/.coliru/setup.sh
+      mode: preserve
+    tags:
+    - linux
+  - name: Run ./setup.sh linux {{ ansible_user_id | quote }}
+    ansible.builtin.command:
+      argv:
+      - sh
+      - -c
+      - ./setup.sh linux {{ ansible_user_id | quote }}
+      chdir: ~/.coliru
+    tags:
+    - linux
+";
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_ansible_playbook_commands() {
+        let contents = "\
+steps:
+  - copy: [ { src: [a, b], dst: ~/ab } ]
+    link: [ { src: c, dst: ~/c, type: hardlink } ]
+    block: [ { src: d, dst: ~/.vimrc, comment: '\"' } ]
+    run:
+      - src: e.sh
+        user: root
+        retries: 2
+        retry_delay: 5
+        env: { TOKEN: abc }
+";
+        let manifest = parse_manifest_str(contents, Path::new("/dotfiles"))
+            .unwrap();
+
+        let actual = ansible_playbook(&manifest, &[], LinkMode::Copy).unwrap();
+
+        assert_eq!(actual.contains("\
+      content: '{{ lookup(''file'', ''/dotfiles/a'', rstrip=False) }}{{ lookup(''file'', ''/dotfiles/b'', rstrip=False) }}'
+"), true);
+        assert_eq!(actual.contains("      state: hard\n"), true);
+        assert_eq!(actual.contains("      marker: '\" {mark} coliru d'\n"),
+                   true);
+        assert_eq!(actual.contains("\
+    environment:
+      TOKEN: abc
+    become: true
+    become_user: root
+    register: coliru_run
+    retries: 2
+    delay: 5
+    until: coliru_run is succeeded
+"), true);
+    }
+
+    #[test]
+    fn test_shell_script_basic() {
+        let contents = "\
+steps:
+  - copy: [ { src: gitconfig, dst: ~/.gitconfig } ]
+    link: [ { src: vimrc, dst: ~/.vim/vimrc } ]
+    run: [ { src: ./setup.sh, postfix: $COLIRU_RULES } ]
+    tags: [ linux ]
+  - copy: [ { src: vimrc, dst: ~/_vimrc } ]
+    tags: [ windows ]
+";
+        let manifest = parse_manifest_str(contents, Path::new(".")).unwrap();
+        let rules = [String::from("linux")];
+
+        let actual = shell_script(&manifest, &rules, LinkMode::Symlink)
+            .unwrap();
+
+        let expected = format!("{}
+echo '[1/1] Copy gitconfig to ~/.gitconfig'
+mkdir -p \"$(dirname ~/'.gitconfig')\" && cp 'gitconfig' ~/'.gitconfig'.coliru-tmp && mv -f ~/'.gitconfig'.coliru-tmp ~/'.gitconfig'
+echo '[1/1] Link vimrc to ~/.vim/vimrc'
+mkdir -p \"$(dirname ~/'.vim/vimrc')\" && ln -sfn \"$PWD\"/'vimrc' ~/'.vim/vimrc'
+echo '[1/1] Run ./setup.sh linux'
+ ./setup.sh linux
+", SCRIPT_HEADER);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_shell_script_runs() {
+        let contents = "\
+pre_install: [ git pull ]
+steps:
+  - run:
+    - { src: a.sh, env: { A: b }, postfix: $COLIRU_OS }
+    - { src: b.sh, retries: 2, retry_delay: 5 }
+    - { src: c.sh, user: root }
+";
+        let manifest = parse_manifest_str(contents, Path::new(".")).unwrap();
+
+        let actual = shell_script(&manifest, &[], LinkMode::Symlink).unwrap();
+
+        assert_eq!(actual.contains(SCRIPT_FACTS), true);
+        assert_eq!(actual.contains(SCRIPT_RETRY), true);
+        assert_eq!(actual.contains("\
+echo '[pre_install] Run git pull'
+git pull
+"), true);
+        assert_eq!(actual.contains("\n(export A='b' &&  a.sh $COLIRU_OS)\n"),
+                   true);
+        assert_eq!(actual.contains("\ncoliru_retry 2 5 sh -c ' b.sh '\n"),
+                   true);
+        assert_eq!(actual.contains("\nsudo -u 'root' sh -c ' c.sh '\n"),
+                   true);
+    }
+
+    #[test]
+    fn test_export_packages() {
+        let contents = "\
+steps:
+  - packages: [ git, { name: fd, apt: fd-find } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new("/dotfiles"))
+            .unwrap();
+
+        let actual = ansible_playbook(&manifest, &[], LinkMode::Symlink)
+            .unwrap();
+
+        assert_eq!(actual.contains("\
+  - name: Install package git
+    ansible.builtin.package:
+      name: git
+      state: present
+    become: true
+"), true);
+        assert_eq!(actual.contains("\
+      name: '{{ {''apt'': ''fd-find''}.get(ansible_pkg_mgr, ''fd'') }}'
+"), true);
+
+        let actual = shell_script(&manifest, &[], LinkMode::Symlink).unwrap();
+
+        assert_eq!(actual.contains("\ncoliru_package() {\n"), true);
+        assert_eq!(actual.contains("\
+echo '[1/1] Install package fd'
+coliru_package 'fd-find' 'fd' 'fd' 'fd'
+"), true);
+    }
+
+    #[test]
+    fn test_export_repo() {
+        let contents = "\
+steps:
+  - repo: [ { url: 'https://example.com/a.git', dst: ~/a, rev: v1 } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new("/dotfiles"))
+            .unwrap();
+
+        let actual = ansible_playbook(&manifest, &[], LinkMode::Symlink)
+            .unwrap();
+
+        assert_eq!(actual.contains("\
+  - name: Clone https://example.com/a.git to ~/a
+    ansible.builtin.git:
+      repo: https://example.com/a.git
+      dest: ~/a
+      version: v1
+"), true);
+
+        let actual = shell_script(&manifest, &[], LinkMode::Symlink).unwrap();
+
+        assert_eq!(actual.contains("\nif test -d ~/'a'/.git; then git -C ~/'a' \
+                                    fetch --quiet origin && { test "), true);
+    }
+
+    #[test]
+    fn test_export_fetch() {
+        let contents = "\
+steps:
+  - fetch:
+    - url: https://example.com/tool
+      dst: ~/bin/tool
+      sha256: 8419ff13897cbe37259aafcdf99caa54532b826445935be0fcb71456525ebc26
+      mode: 755
+";
+        let manifest = parse_manifest_str(contents, Path::new("/dotfiles"))
+            .unwrap();
+
+        let actual = ansible_playbook(&manifest, &[], LinkMode::Symlink)
+            .unwrap();
+
+        assert_eq!(actual.contains("\
+  - name: Download https://example.com/tool to ~/bin/tool
+    ansible.builtin.get_url:
+      url: https://example.com/tool
+      dest: ~/bin/tool
+      checksum: sha256:8419ff13897cbe37259aafcdf99caa54532b826445935be0fcb71456525ebc26
+      mode: '0755'
+"), true);
+
+        let actual = shell_script(&manifest, &[], LinkMode::Symlink).unwrap();
+
+        assert_eq!(actual.contains("|| { mkdir -p \"$(dirname ~/'bin/tool')\" \
+                                    && { curl"), true);
+        assert_eq!(actual.contains("|| { rm -f ~/'bin/tool'.coliru-tmp; echo \
+                                    '  Error: Checksum doesn'\\''t match \
+                                    8419ff13897cbe37259aafcdf99caa54532b82644\
+                                    5935be0fcb71456525ebc26' >&2; exit 1; }; \
+                                    } && chmod 755"), true);
+    }
+
+    #[test]
+    fn test_export_systemd() {
+        let contents = "\
+steps:
+  - systemd:
+    - src: units/syncthing.service
+    - { unit: sshd.service, scope: system, start: false }
+";
+        let manifest = parse_manifest_str(contents, Path::new("/dotfiles"))
+            .unwrap();
+
+        let actual = ansible_playbook(&manifest, &[], LinkMode::Symlink)
+            .unwrap();
+
+        assert_eq!(actual.contains("\
+  - name: Copy units/syncthing.service to ~/.config/systemd/user/syncthing.service
+    ansible.builtin.copy:
+      src: /dotfiles/units/syncthing.service
+      dest: ~/.config/systemd/user/syncthing.service
+      mode: '0644'
+  - name: Install user unit syncthing.service
+    ansible.builtin.systemd_service:
+      name: syncthing.service
+      scope: user
+      daemon_reload: true
+      enabled: true
+      state: started
+  - name: Enable system unit sshd.service
+    ansible.builtin.systemd_service:
+      name: sshd.service
+      scope: system
+      enabled: true
+    become: true
+"), true);
+
+        let actual = shell_script(&manifest, &[], LinkMode::Symlink).unwrap();
+
+        assert_eq!(actual.contains("\
+echo '[1/1] Install user unit syncthing.service'
+cmp -s 'units/syncthing.service' ~/'.config/systemd/user/syncthing.service' || { mkdir -p ~/'.config/systemd/user' && cp 'units/syncthing.service' ~/'.config/systemd/user/syncthing.service' && systemctl --user daemon-reload && { ! systemctl --user is-active --quiet 'syncthing.service' || systemctl --user restart 'syncthing.service'; }; }
+systemctl --user is-enabled --quiet 'syncthing.service' || systemctl --user enable 'syncthing.service'
+systemctl --user is-active --quiet 'syncthing.service' || systemctl --user start 'syncthing.service'
+echo '[1/1] Enable system unit sshd.service'
+sudo systemctl is-enabled --quiet 'sshd.service' || sudo systemctl enable 'sshd.service'
+"), true);
+    }
+
+    #[test]
+    fn test_export_defaults() {
+        let contents = "\
+steps:
+  - defaults: [ { domain: com.apple.dock, key: autohide, value: true } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new("/dotfiles"))
+            .unwrap();
+
+        let actual = ansible_playbook(&manifest, &[], LinkMode::Symlink)
+            .unwrap();
+
+        assert_eq!(actual.contains("\
+  - name: Set com.apple.dock autohide to true
+    community.general.osx_defaults:
+      domain: com.apple.dock
+      key: autohide
+      type: bool
+      value: 'true'
+      state: present
+"), true);
+
+        let actual = shell_script(&manifest, &[], LinkMode::Symlink).unwrap();
+
+        assert_eq!(actual.contains("\
+echo '[1/1] Set com.apple.dock autohide to true'
+test \"$(defaults read 'com.apple.dock' 'autohide' 2>/dev/null)\" = '1' || defaults write 'com.apple.dock' 'autohide' -bool true
+"), true);
+    }
+
+    #[test]
+    fn test_export_extensions() {
+        let contents = "\
+steps:
+  - extensions: [ ms-python.python, { jetbrains: IdeaVIM } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new("/dotfiles"))
+            .unwrap();
+
+        let actual = ansible_playbook(&manifest, &[], LinkMode::Symlink)
+            .unwrap();
+
+        assert_eq!(actual.contains("\
+  - name: Install extension ms-python.python with code
+    ansible.builtin.command:
+      argv:
+      - code
+      - --install-extension
+      - ms-python.python
+"), true);
+
+        let actual = shell_script(&manifest, &[], LinkMode::Symlink).unwrap();
+
+        assert_eq!(actual.contains("\
+echo '[1/1] Install extension ms-python.python with code'
+code --list-extensions 2>/dev/null | grep -qixF 'ms-python.python' || code --install-extension 'ms-python.python'
+echo '[1/1] Install plugin IdeaVIM with idea'
+idea installPlugins 'IdeaVIM'
+"), true);
+    }
+
+    #[test]
+    fn test_export_cron() {
+        let contents = "\
+steps:
+  - cron:
+    - { name: backup, schedule: 0 3 * * 1, command: ~/bin/backup.sh }
+    - { name: sync, schedule: '@hourly', command: ~/bin/sync.sh }
+    - { name: old, remove: true }
+";
+        let manifest = parse_manifest_str(contents, Path::new("/dotfiles"))
+            .unwrap();
+
+        let actual = ansible_playbook(&manifest, &[], LinkMode::Symlink)
+            .unwrap();
+
+        assert_eq!(actual.contains("\
+  - name: Add cron job backup
+    ansible.builtin.cron:
+      name: backup
+      minute: '0'
+      hour: '3'
+      day: '*'
+      month: '*'
+      weekday: '1'
+      job: ~/bin/backup.sh
+  - name: Add cron job sync
+    ansible.builtin.cron:
+      name: sync
+      special_time: hourly
+      job: ~/bin/sync.sh
+  - name: Remove cron job old
+    ansible.builtin.cron:
+      name: old
+      state: absent
+"), true);
+
+        let actual = shell_script(&manifest, &[], LinkMode::Symlink).unwrap();
+
+        assert_eq!(actual.contains("\
+echo '[1/1] Remove cron job old'
+! crontab -l 2>/dev/null | grep -qxF '# coliru: old' || crontab -l"), true);
+    }
+
+    #[test]
+    fn test_export_brew() {
+        let contents = "\
+steps:
+  - brew: [ git, { cask: firefox }, { brewfile: mac/Brewfile } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new("/dotfiles"))
+            .unwrap();
+
+        let actual = ansible_playbook(&manifest, &[], LinkMode::Symlink)
+            .unwrap();
+
+        assert_eq!(actual.contains("\
+  - name: Install formula git with Homebrew
+    community.general.homebrew:
+      name: git
+      state: present
+"), true);
+        assert_eq!(actual.contains("    community.general.homebrew_cask:\n"),
+                   true);
+        assert_eq!(actual.contains("\
+  - name: Install bundle mac/Brewfile with Homebrew
+    ansible.builtin.command:
+      argv:
+      - brew
+      - bundle
+      - install
+      - --no-upgrade
+      - --file=mac/Brewfile
+      chdir: ~/.coliru
+"), true);
+
+        let actual = shell_script(&manifest, &[], LinkMode::Symlink).unwrap();
+
+        assert_eq!(actual.contains("\
+echo '[1/1] Install cask firefox with Homebrew'
+brew list --cask 'firefox' >/dev/null 2>&1 || brew install --cask 'firefox'
+"), true);
+    }
+}
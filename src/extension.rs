@@ -0,0 +1,102 @@
+//! Commands for installing editor extensions
+//!
+//! VS Code extensions are checked with `--list-extensions` before they're
+//! installed, so that extensions that are already installed are reported as
+//! unchanged. JetBrains IDEs can't list their plugins from the command line,
+//! so plugins are always passed to `installPlugins`, which skips plugins that
+//! are already installed.
+//!
+//! ```
+//! let extension = ExtensionOptions::VsCode {
+//!     id: String::from("ms-python.python"),
+//!     program: String::from("code"),
+//! };
+//! println!("{}", install_command(&extension));
+//! ```
+
+use super::local::quote;
+use super::manifest::ExtensionOptions;
+
+/// Returns a description of an extension command for output
+///
+/// ```
+/// assert_eq!(describe(&extension),
+///            "Install extension ms-python.python with code");
+/// ```
+pub fn describe(extension: &ExtensionOptions) -> String {
+    match extension {
+        ExtensionOptions::VsCode { id, program } => {
+            format!("Install extension {} with {}", id, program)
+        },
+        ExtensionOptions::JetBrains { id, program } => {
+            format!("Install plugin {} with {}", id, program)
+        },
+    }
+}
+
+/// Returns a shell command that succeeds if an extension command's extension
+/// is already installed, without printing anything, or None if it can't be
+/// checked
+///
+/// Extension IDs are compared case-insensitively, like VS Code does.
+///
+/// ```
+/// assert_eq!(check_command(&extension).unwrap(),
+///            "code --list-extensions 2>/dev/null | grep -qixF \
+///             'ms-python.python'");
+/// ```
+pub fn check_command(extension: &ExtensionOptions) -> Option<String> {
+    match extension {
+        ExtensionOptions::VsCode { id, program } => {
+            Some(format!("{} --list-extensions 2>/dev/null | grep -qixF {}",
+                         program, quote(id)))
+        },
+        ExtensionOptions::JetBrains { .. } => None,
+    }
+}
+
+/// Returns a shell command that installs an extension command's extension
+///
+/// ```
+/// assert_eq!(install_command(&extension),
+///            "code --install-extension 'ms-python.python'");
+/// ```
+pub fn install_command(extension: &ExtensionOptions) -> String {
+    match extension {
+        ExtensionOptions::VsCode { id, program } => {
+            format!("{} --install-extension {}", program, quote(id))
+        },
+        ExtensionOptions::JetBrains { id, program } => {
+            format!("{} installPlugins {}", program, quote(id))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extension_commands() {
+        let vscode = ExtensionOptions::VsCode {
+            id: String::from("ms-python.python"),
+            program: String::from("codium"),
+        };
+        let jetbrains = ExtensionOptions::JetBrains {
+            id: String::from("IdeaVIM"),
+            program: String::from("idea"),
+        };
+
+        assert_eq!(describe(&vscode),
+                   "Install extension ms-python.python with codium");
+        assert_eq!(describe(&jetbrains), "Install plugin IdeaVIM with idea");
+        assert_eq!(check_command(&vscode),
+                   Some(String::from("codium --list-extensions 2>/dev/null | \
+                                      grep -qixF 'ms-python.python'")));
+        assert_eq!(check_command(&jetbrains), None);
+        assert_eq!(install_command(&vscode),
+                   "codium --install-extension 'ms-python.python'");
+        assert_eq!(install_command(&jetbrains),
+                   "idea installPlugins 'IdeaVIM'");
+    }
+}
@@ -0,0 +1,447 @@
+//! Facts about the machine that dotfiles are installed on
+//!
+//! ```
+//! let facts = local_facts();
+//! let postfix = expand_facts("--os $COLIRU_OS", &facts, Some(Shell::Sh));
+//! ```
+
+use anyhow::{Context, Result, bail};
+use gethostname::gethostname;
+use regex::{Captures, Regex};
+use shellexpand::tilde;
+use std::env;
+use super::container::{self, Container};
+use super::local::{CapturedOutput, quote_if_needed};
+use super::manifest::{Manifest, Shell};
+use super::ssh::{Host, RemoteOs, send_command};
+use super::target::{Target, host_shell};
+
+/// The tags that refer to an operating system, which are matched against the
+/// `os` fact by [`filter_platform_steps`]
+const OS_TAGS: [&str; 8] = ["android", "freebsd", "ios", "linux", "macos",
+                            "netbsd", "openbsd", "windows"];
+
+/// The tags that refer to a CPU architecture, which are matched against the
+/// `arch` fact by [`filter_platform_steps`]
+const ARCH_TAGS: [&str; 5] = ["aarch64", "arm", "riscv64", "x86", "x86_64"];
+
+/// The shell command used to collect facts on another machine, which prints one
+/// fact per line in the order they're parsed by `parse_facts`
+const REMOTE_FACTS_COMMAND: &str = "uname -s; uname -m; hostname; whoami; \
+                                    echo \"$HOME\"";
+
+/// The command used to collect facts on another machine running Windows, which
+/// prints the same facts as [`REMOTE_FACTS_COMMAND`] with Windows PowerShell
+const WINDOWS_FACTS_COMMAND: &str = "powershell.exe -NoProfile -Command \
+    \"'windows'; $env:PROCESSOR_ARCHITECTURE; hostname; $env:USERNAME; \
+    $env:USERPROFILE\"";
+
+/// Facts about the machine that dotfiles are installed on
+#[derive(Clone, Debug, PartialEq)]
+pub struct Facts {
+    /// The operating system (e.g. `linux`, `macos`, or `windows`)
+    pub os: String,
+
+    /// The CPU architecture (e.g. `x86_64` or `aarch64`)
+    pub arch: String,
+
+    /// The machine's hostname
+    pub hostname: String,
+
+    /// The name of the user that dotfiles are installed for
+    pub username: String,
+
+    /// The user's home directory
+    pub home: String,
+
+    /// Whether dotfiles are installed on another machine or in a container
+    pub remote: bool,
+}
+impl Facts {
+    /// Returns the value of each fact along with the variable that it's
+    /// substituted for in run postfixes and its placeholder in templated files
+    fn variables(&self) -> [(&'static str, &'static str, String); 6] {
+        [
+            ("$COLIRU_OS", "facts.os", self.os.clone()),
+            ("$COLIRU_ARCH", "facts.arch", self.arch.clone()),
+            ("$COLIRU_HOSTNAME", "facts.hostname", self.hostname.clone()),
+            ("$COLIRU_USER", "facts.username", self.username.clone()),
+            ("$COLIRU_HOME", "facts.home", self.home.clone()),
+            ("$COLIRU_REMOTE", "facts.remote", self.remote.to_string()),
+        ]
+    }
+}
+
+/// Collects facts about the local machine
+///
+/// ```
+/// let facts = local_facts();
+/// ```
+pub fn local_facts() -> Facts {
+    Facts {
+        os: env::consts::OS.to_owned(),
+        arch: env::consts::ARCH.to_owned(),
+        hostname: gethostname().to_string_lossy().to_string(),
+        username: env::var("USER").or_else(|_| env::var("USERNAME"))
+            .unwrap_or_default(),
+        home: tilde("~").to_string(),
+        remote: false,
+    }
+}
+
+/// Converts an architecture reported by `uname -m` or Windows into the name
+/// that Rust uses for it, which is the value of the local `arch` fact
+///
+/// ```
+/// assert_eq!(normalize_arch("armv7l"), "arm");
+/// ```
+fn normalize_arch(arch: &str) -> String {
+    let arch = arch.to_lowercase();
+    match arch.as_str() {
+        "amd64" | "x64" => String::from("x86_64"),
+        "arm64" => String::from("aarch64"),
+        "i386" | "i486" | "i586" | "i686" | "i86pc" | "x86" =>
+            String::from("x86"),
+        _ if arch.starts_with("armv") || arch == "armhf" =>
+            String::from("arm"),
+        _ => arch,
+    }
+}
+
+/// Collects facts about another machine via SSH
+///
+/// The machine must provide a POSIX shell along with the `uname`, `hostname`,
+/// and `whoami` commands, or Windows PowerShell if it runs Windows. Operating
+/// system and architecture names are normalized to match the values reported
+/// for the local machine.
+///
+/// ```
+/// let facts = remote_facts(&host)?;
+/// ```
+pub fn remote_facts(host: &Host) -> Result<Facts> {
+    let mut output = CapturedOutput::default();
+    let command = match host.options.remote_os {
+        RemoteOs::Unix => REMOTE_FACTS_COMMAND,
+        RemoteOs::Windows => WINDOWS_FACTS_COMMAND,
+    };
+    send_command(command, host, Some(&mut output))
+        .with_context(|| format!("Failed to collect facts from {}", host))?;
+    parse_facts(&output.stdout, &host.name)
+}
+
+/// Collects facts about a container like [`remote_facts`]
+///
+/// The container must provide a POSIX shell along with the `uname`,
+/// `hostname`, and `whoami` commands.
+///
+/// ```
+/// let facts = container_facts(&container)?;
+/// ```
+pub fn container_facts(container: &Container) -> Result<Facts> {
+    let mut output = CapturedOutput::default();
+    container::send_command(REMOTE_FACTS_COMMAND, container, Some(&mut output))
+        .with_context(|| {
+            format!("Failed to collect facts from {}", container)
+        })?;
+    parse_facts(&output.stdout, &container.name)
+}
+
+/// Parses the output of [`REMOTE_FACTS_COMMAND`] or [`WINDOWS_FACTS_COMMAND`]
+/// on `machine`, normalizing operating system and architecture names
+fn parse_facts(output: &str, machine: &str) -> Result<Facts> {
+    let lines: Vec<&str> = output.lines().map(|x| x.trim()).collect();
+    let [os, arch, hostname, username, home] = lines[..] else {
+        bail!("Failed to collect facts from {}: unexpected output", machine);
+    };
+
+    Ok(Facts {
+        os: match os {
+            "Darwin" => String::from("macos"),
+            _ => os.to_lowercase(),
+        },
+        arch: normalize_arch(arch),
+        hostname: hostname.to_owned(),
+        username: username.to_owned(),
+        home: home.to_owned(),
+        remote: true,
+    })
+}
+
+/// Removes the steps of a manifest that are tagged for another operating system
+/// or CPU architecture
+///
+/// A step that has any operating system tags (e.g. `linux` or `macos`) is only
+/// kept if one of them matches the machine's operating system, and likewise
+/// for CPU architecture tags (e.g. `x86_64` or `aarch64`). Steps without either
+/// kind of tag are always kept.
+///
+/// ```
+/// let manifest = filter_platform_steps(manifest, &remote_facts(host)?);
+/// ```
+pub fn filter_platform_steps(mut manifest: Manifest, facts: &Facts) ->
+    Manifest {
+
+    let matches = |tags: &[String], known: &[&str], value: &str| {
+        let mut platform_tags = tags.iter().filter(|x| {
+            known.contains(&x.as_str())
+        }).peekable();
+        platform_tags.peek().is_none() || platform_tags.any(|x| x == value)
+    };
+    manifest.steps.retain(|step| {
+        matches(&step.tags, &OS_TAGS, &facts.os) &&
+            matches(&step.tags, &ARCH_TAGS, &facts.arch)
+    });
+    manifest
+}
+
+/// Returns a pattern that matches the variables that may refer to facts (e.g.
+/// `$COLIRU_OS`), which only matches whole names so that `$COLIRU_USER` isn't
+/// found in `$COLIRU_USERNAME`
+fn variable_pattern() -> Regex {
+    Regex::new(r"\$COLIRU_[A-Z_]+\b").unwrap()
+}
+
+/// Checks whether any run postfix in a manifest refers to a fact or any copy
+/// command is templated
+///
+/// ```
+/// if uses_facts(&manifest) {
+///     let facts = remote_facts("user@hostname")?;
+/// }
+/// ```
+pub fn uses_facts(manifest: &Manifest) -> bool {
+    let variables = local_facts().variables().map(|x| x.0);
+    manifest.steps.iter().any(|step| {
+        step.copy.iter().any(|x| x.template.is_some()) ||
+            step.run.iter().any(|run| {
+                variable_pattern().find_iter(&run.postfix)
+                    .any(|x| variables.contains(&x.as_str()))
+            })
+    })
+}
+
+/// Substitutes the value of each fact for its variable in a string, quoting
+/// the values for `shell` if it's provided
+///
+/// ```
+/// let facts = local_facts();
+/// assert_eq!(expand_facts("$COLIRU_REMOTE", &facts, None), "false");
+/// ```
+pub fn expand_facts(text: &str, facts: &Facts, shell: Option<Shell>) ->
+    String {
+
+    let variables = facts.variables();
+    variable_pattern().replace_all(text, |x: &Captures| {
+        let value = variables.iter().find(|y| y.0 == &x[0]).map(|y| &y.2);
+        match (value, shell) {
+            (Some(value), Some(shell)) => quote_if_needed(value, shell),
+            (Some(value), None) => value.clone(),
+            (None, _) => x[0].to_owned(),
+        }
+    }).into_owned()
+}
+
+/// Substitutes facts into the run postfixes of a manifest, quoted for the
+/// shells that execute them (or for `default_shell` if they don't have one),
+/// and passes facts and the manifest's variables to its templated copy
+/// commands
+///
+/// ```
+/// let manifest = expand_manifest_facts(manifest, &local_facts(), Shell::Sh);
+/// ```
+pub fn expand_manifest_facts(mut manifest: Manifest, facts: &Facts,
+                             default_shell: Shell) -> Manifest {
+
+    for step in manifest.steps.iter_mut() {
+        for run in step.run.iter_mut() {
+            let shell = run.shell.unwrap_or(default_shell);
+            run.postfix = expand_facts(&run.postfix, facts, Some(shell));
+        }
+        for values in step.copy.iter_mut().filter_map(|x| x.template.as_mut()) {
+            values.extend(manifest.vars.clone());
+            values.extend(facts.variables().map(|x| (x.1.to_owned(), x.2)));
+        }
+    }
+    manifest
+}
+
+/// Substitutes facts about the machine that dotfiles will be installed on into
+/// the run postfixes and templated copy commands of a manifest
+///
+/// Facts are only collected from another machine if `host` isn't the local
+/// machine and the manifest uses facts, so that SSH isn't used unnecessarily.
+///
+/// ```
+/// let manifest = apply_facts(manifest, &host)?;
+/// ```
+pub fn apply_facts(manifest: Manifest, host: &Host) -> Result<Manifest> {
+    let shell = host_shell(host);
+    if host.is_local() {
+        Ok(expand_manifest_facts(manifest, &local_facts(), shell))
+    } else if uses_facts(&manifest) {
+        Ok(expand_manifest_facts(manifest, &remote_facts(host)?, shell))
+    } else {
+        Ok(manifest)
+    }
+}
+
+/// Substitutes facts about the machine that a target installs on into a
+/// manifest like [`apply_facts`]
+///
+/// ```
+/// let manifest = apply_target_facts(manifest, target.as_ref())?;
+/// ```
+pub fn apply_target_facts(manifest: Manifest, target: &dyn Target) ->
+    Result<Manifest> {
+
+    if target.host().is_empty() || uses_facts(&manifest) {
+        Ok(expand_manifest_facts(manifest, &target.facts()?,
+                                 target.default_shell()))
+    } else {
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::parse_manifest_str;
+    use std::path::Path;
+
+    fn facts() -> Facts {
+        Facts {
+            os: String::from("linux"),
+            arch: String::from("x86_64"),
+            hostname: String::from("laptop"),
+            username: String::from("user"),
+            home: String::from("/home/user"),
+            remote: true,
+        }
+    }
+
+    #[test]
+    fn test_expand_facts_basic() {
+        let text = "$COLIRU_OS $COLIRU_ARCH $COLIRU_HOSTNAME $COLIRU_USER \
+                    $COLIRU_HOME $COLIRU_REMOTE $COLIRU_RULES";
+
+        let result = expand_facts(text, &facts(), None);
+
+        assert_eq!(result, "linux x86_64 laptop user /home/user true \
+                            $COLIRU_RULES");
+    }
+
+    #[test]
+    fn test_expand_facts_quoted() {
+        let mut facts = facts();
+        facts.home = String::from("/home/my user");
+
+        let sh = expand_facts("$COLIRU_HOME $COLIRU_OS", &facts,
+                              Some(Shell::Sh));
+        let cmd = expand_facts("$COLIRU_HOME", &facts, Some(Shell::Cmd));
+
+        assert_eq!(sh, "'/home/my user' linux");
+        assert_eq!(cmd, "\"/home/my user\"");
+    }
+
+    #[test]
+    fn test_expand_facts_longer_name() {
+        let text = "$COLIRU_USERNAME $COLIRU_USER";
+
+        let result = expand_facts(text, &facts(), None);
+
+        assert_eq!(result, "$COLIRU_USERNAME user");
+    }
+
+    #[test]
+    fn test_expand_manifest_facts_basic() {
+        let manifest = parse_manifest_str("\
+steps:
+  - run:
+    - src: script.sh
+      postfix: $COLIRU_OS $COLIRU_RULES
+", Path::new(".")).unwrap();
+
+        assert_eq!(uses_facts(&manifest), true);
+        let result = expand_manifest_facts(manifest, &facts(), Shell::Sh);
+
+        assert_eq!(result.steps[0].run[0].postfix, "linux $COLIRU_RULES");
+        assert_eq!(uses_facts(&result), false);
+    }
+
+    #[test]
+    fn test_expand_manifest_facts_template() {
+        let manifest = parse_manifest_str("\
+steps:
+  - copy:
+    - src: foo
+      dst: ~/foo
+      template: true
+vars:
+  email: me@example.com
+", Path::new(".")).unwrap();
+
+        assert_eq!(uses_facts(&manifest), true);
+        let result = expand_manifest_facts(manifest, &facts(), Shell::Sh);
+
+        let values = result.steps[0].copy[0].template.as_ref().unwrap();
+        assert_eq!(values["facts.os"], "linux");
+        assert_eq!(values["facts.home"], "/home/user");
+        assert_eq!(values["facts.remote"], "true");
+        assert_eq!(values["email"], "me@example.com");
+    }
+
+    #[test]
+    fn test_filter_platform_steps_basic() {
+        let manifest = parse_manifest_str("\
+steps:
+  - copy:
+    - src: common
+      dst: ~/common
+    tags: [user]
+  - copy:
+    - src: linux
+      dst: ~/linux
+    tags: [linux, user]
+  - copy:
+    - src: unix
+      dst: ~/unix
+    tags: [linux, macos]
+  - copy:
+    - src: windows
+      dst: ~/windows
+    tags: [windows]
+  - copy:
+    - src: linux-arm
+      dst: ~/linux-arm
+    tags: [linux, aarch64]
+", Path::new(".")).unwrap();
+
+        let result = filter_platform_steps(manifest, &facts());
+
+        let srcs: Vec<&str> = result.steps.iter().map(|x| {
+            x.copy[0].src.as_str()
+        }).collect();
+        assert_eq!(srcs, ["common", "linux", "unix"]);
+    }
+
+    #[test]
+    fn test_normalize_arch() {
+        assert_eq!(normalize_arch("x86_64"), "x86_64");
+        assert_eq!(normalize_arch("AMD64"), "x86_64");
+        assert_eq!(normalize_arch("arm64"), "aarch64");
+        assert_eq!(normalize_arch("i686"), "x86");
+        assert_eq!(normalize_arch("x86"), "x86");
+        assert_eq!(normalize_arch("armv7l"), "arm");
+        assert_eq!(normalize_arch("armv6l"), "arm");
+        assert_eq!(normalize_arch("riscv64"), "riscv64");
+    }
+
+    #[test]
+    fn test_local_facts_basic() {
+        let facts = local_facts();
+
+        assert_eq!(facts.os, env::consts::OS);
+        assert_eq!(facts.remote, false);
+        assert_eq!(facts.hostname.is_empty(), false);
+    }
+}
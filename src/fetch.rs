@@ -0,0 +1,142 @@
+//! Shell commands for downloading files and verifying their checksums
+//!
+//! A file is downloaded next to its destination, verified, and only then moved
+//! into place, so a failed or tampered download never replaces a working file.
+//! Each function returns a POSIX shell command, so that files can be
+//! downloaded the same way on the local machine and over SSH.
+//!
+//! ```
+//! let download = download_command(&fetch);
+//! let verify = verify_command(&fetch);
+//! ```
+
+use super::local::quote;
+use super::manifest::FetchOptions;
+use super::ssh::quote_path;
+
+/// Returns a shell command that prints the SHA-256 hash of a file, or nothing
+/// if it doesn't exist
+///
+/// `sha256sum` is used where it's available and `shasum` is used otherwise
+/// (e.g. on macOS).
+fn hash_command(path: &str) -> String {
+    format!("{{ sha256sum {path} 2>/dev/null || shasum -a 256 {path} \
+             2>/dev/null; }} | cut -d ' ' -f 1")
+}
+
+/// Returns the quoted path of the file that a fetch command downloads to
+/// before it's verified
+fn temp_path(fetch: &FetchOptions) -> String {
+    format!("{}.coliru-tmp", quote_path(&fetch.dst))
+}
+
+/// Returns a shell command that succeeds if a fetch command's destination
+/// exists
+///
+/// ```
+/// assert_eq!(exists_command(&fetch), "test -e ~/'.local/bin/tool'");
+/// ```
+pub fn exists_command(fetch: &FetchOptions) -> String {
+    format!("test -e {}", quote_path(&fetch.dst))
+}
+
+/// Returns a shell command that succeeds if a fetch command's destination
+/// already has the expected hash
+///
+/// ```
+/// let current = current_command(&fetch);
+/// ```
+pub fn current_command(fetch: &FetchOptions) -> String {
+    format!("test \"$({})\" = {}", hash_command(&quote_path(&fetch.dst)),
+            quote(&fetch.sha256))
+}
+
+/// Returns a shell command that downloads a fetch command's file next to its
+/// destination, using `curl` or `wget`
+///
+/// ```
+/// let download = download_command(&fetch);
+/// ```
+pub fn download_command(fetch: &FetchOptions) -> String {
+    let temp = temp_path(fetch);
+    let url = quote(&fetch.url);
+    format!("mkdir -p \"$(dirname {})\" && {{ curl -fsSL -o {temp} {url} || \
+             wget -q -O {temp} {url}; }}", quote_path(&fetch.dst))
+}
+
+/// Returns a shell command that succeeds if a downloaded file has the expected
+/// hash
+///
+/// ```
+/// let verify = verify_command(&fetch);
+/// ```
+pub fn verify_command(fetch: &FetchOptions) -> String {
+    format!("test \"$({})\" = {}", hash_command(&temp_path(fetch)),
+            quote(&fetch.sha256))
+}
+
+/// Returns a shell command that removes a downloaded file that failed
+/// verification
+///
+/// ```
+/// let discard = discard_command(&fetch);
+/// ```
+pub fn discard_command(fetch: &FetchOptions) -> String {
+    format!("rm -f {}", temp_path(fetch))
+}
+
+/// Returns a shell command that sets the permissions of a verified file and
+/// moves it to its destination
+///
+/// ```
+/// let install = install_command(&fetch);
+/// ```
+pub fn install_command(fetch: &FetchOptions) -> String {
+    let temp = temp_path(fetch);
+    let mv = format!("mv -f {} {}", temp, quote_path(&fetch.dst));
+    match &fetch.mode {
+        Some(mode) => format!("chmod {} {} && {}", mode, temp, mv),
+        None => mv,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fetch(mode: Option<&str>) -> FetchOptions {
+        FetchOptions {
+            url: String::from("https://example.com/tool"),
+            dst: String::from("~/bin/tool"),
+            sha256: String::from("abc123"),
+            mode: mode.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_fetch_check_commands() {
+        assert_eq!(exists_command(&fetch(None)), "test -e ~/'bin/tool'");
+        assert_eq!(current_command(&fetch(None)),
+                   "test \"$({ sha256sum ~/'bin/tool' 2>/dev/null || shasum \
+                    -a 256 ~/'bin/tool' 2>/dev/null; } | cut -d ' ' -f 1)\" = \
+                    'abc123'");
+        assert_eq!(verify_command(&fetch(None)).contains(
+            "sha256sum ~/'bin/tool'.coliru-tmp"), true);
+    }
+
+    #[test]
+    fn test_fetch_install_commands() {
+        assert_eq!(download_command(&fetch(None)),
+                   "mkdir -p \"$(dirname ~/'bin/tool')\" && { curl -fsSL -o \
+                    ~/'bin/tool'.coliru-tmp 'https://example.com/tool' || \
+                    wget -q -O ~/'bin/tool'.coliru-tmp \
+                    'https://example.com/tool'; }");
+        assert_eq!(install_command(&fetch(None)),
+                   "mv -f ~/'bin/tool'.coliru-tmp ~/'bin/tool'");
+        assert_eq!(install_command(&fetch(Some("755"))),
+                   "chmod 755 ~/'bin/tool'.coliru-tmp && mv -f \
+                    ~/'bin/tool'.coliru-tmp ~/'bin/tool'");
+        assert_eq!(discard_command(&fetch(None)),
+                   "rm -f ~/'bin/tool'.coliru-tmp");
+    }
+}
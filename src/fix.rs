@@ -0,0 +1,185 @@
+//! Repair of broken or misdirected symbolic links
+//!
+//! Repairing only re-creates symbolic links that already exist at manifest
+//! destinations, so it's safe to run after moving the dotfile repository.
+//!
+//! ```
+//! let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+//! let failure = fix_links(manifest, &["linux"], LinkMode::Symlink, false,
+//!                         &[])?;
+//! ```
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use glob::Pattern;
+use shellexpand::tilde;
+use std::env::set_current_dir;
+use std::fs;
+use std::path::PathBuf;
+use super::exclude::excluded_by;
+use super::exit::{Failure, classify};
+use super::local::{link_file, link_file_relative};
+use super::manifest::{LinkMode, Manifest, filter_manifest_steps};
+use super::preview::{Change, preview_link, preview_relative_link};
+
+/// The outcome of repairing a single link command
+#[derive(Debug, PartialEq)]
+pub enum Repair {
+    /// The link already points to its source file
+    Ok,
+
+    /// The link was broken or pointed to another file, and was re-created
+    Fixed {
+        /// The link's previous target
+        old_target: PathBuf,
+    },
+
+    /// The destination doesn't exist, so it was left alone
+    Missing,
+
+    /// The destination isn't a symbolic link, so it was left alone
+    NotLink,
+}
+
+/// Re-creates the broken or misdirected symbolic links in a manifest according
+/// to a set of tag rules
+///
+/// Only link commands that are installed as symbolic links are checked, and
+/// destinations that are missing or aren't symbolic links are left alone. If
+/// `dry_run` is true, the links that would be fixed are reported without
+/// changing them. Returns an Err if a critical error occurs and returns the
+/// class of failure with the greatest precedence among any other errors
+/// otherwise.
+///
+/// ```
+/// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// let failure = fix_links(manifest, &["linux"], LinkMode::Symlink, true,
+///                         &[])?;
+/// ```
+pub fn fix_links(manifest: Manifest, tag_rules: &[String],
+                 link_mode: LinkMode, dry_run: bool, exclude: &[Pattern]) ->
+    Result<Option<Failure>> {
+
+    let filtered_manifest = filter_manifest_steps(manifest, tag_rules);
+    set_current_dir(filtered_manifest.base_dir)
+        .context("Failed to set working directory")?;
+
+    let mut failure = None;
+
+    for (i, step) in filtered_manifest.steps.iter().enumerate() {
+        let step_str = format!("[{}/{}]", i+1,
+            filtered_manifest.steps.len()).bold();
+
+        for link in &step.link {
+            if link.link_mode(link_mode) != LinkMode::Symlink { continue; }
+            print!("{} Link {} to {}", step_str, link.src, link.dst);
+
+            if let Some(pattern) = excluded_by(exclude, &[&link.src,
+                                                          &link.dst]) {
+                println!(" ({}: excluded by {})", "skipped".cyan(), pattern);
+                continue;
+            }
+
+            match fix_link(&link.src, &link.dst, link.relative, dry_run) {
+                Ok(Repair::Ok) => println!(" ({})", "ok".dimmed()),
+                Ok(Repair::Fixed { old_target }) => {
+                    let label = if dry_run { "DRY RUN: fix" } else { "fixed" };
+                    println!(" ({}, was {})", label.yellow(),
+                             old_target.display());
+                },
+                Ok(Repair::Missing) => {
+                    println!(" ({}: missing)", "skipped".cyan());
+                },
+                Ok(Repair::NotLink) => {
+                    println!(" ({}: not a link)", "skipped".cyan());
+                },
+                Err(why) => {
+                    println!(" ({})", "FAILED".bold().red());
+                    eprintln!("  {} {:#}", "Error:".bold().red(), why);
+                    failure = failure.max(Some(classify(&why,
+                                                        Failure::LocalFile)));
+                },
+            }
+        }
+    }
+
+    Ok(failure)
+}
+
+/// Re-creates a symbolic link on the local machine if it's broken or points to
+/// the wrong file
+///
+/// The link is given a target relative to its directory if `relative` is true.
+/// Nothing is changed if `dry_run` is true.
+///
+/// ```
+/// match fix_link("bashrc", "~/.bashrc", false, false)? {
+///     Repair::Fixed { old_target } => println!("{}", old_target.display()),
+///     _ => {},
+/// }
+/// ```
+pub fn fix_link(src: &str, dst: &str, relative: bool, dry_run: bool) ->
+    Result<Repair> {
+
+    let _dst = tilde(dst).to_string();
+    match fs::symlink_metadata(&_dst) {
+        Err(_) => return Ok(Repair::Missing),
+        Ok(metadata) if !metadata.is_symlink() => return Ok(Repair::NotLink),
+        Ok(_) => {},
+    }
+    let old_target = fs::read_link(&_dst).with_context(|| {
+        format!("Failed to read link {}", dst)
+    })?;
+
+    let (change, install): (_, fn(&str, &str) -> Result<()>) = if relative {
+        (preview_relative_link(src, dst)?, link_file_relative)
+    } else {
+        (preview_link(src, dst)?, link_file)
+    };
+    if change != Change::ReplaceSymlink { return Ok(Repair::Ok); }
+
+    if !dry_run { install(src, dst)?; }
+    Ok(Repair::Fixed { old_target })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{setup_integration, write_file};
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_fix_link_basic() {
+        use std::os::unix::fs::symlink;
+
+        let tmp = setup_integration("test_fix_link_basic");
+
+        let src = tmp.local.join("foo");
+        let linked = tmp.local.join("linked");
+        let broken = tmp.local.join("broken");
+        let file = tmp.local.join("file");
+        let missing = tmp.local.join("missing");
+        write_file(&src, "contents of foo");
+        write_file(&file, "contents of file");
+        symlink(&src, &linked).unwrap();
+        symlink("missing", &broken).unwrap();
+
+        let src = src.to_str().unwrap();
+        assert_eq!(fix_link(src, linked.to_str().unwrap(), false, false)
+                   .unwrap(), Repair::Ok);
+        assert_eq!(fix_link(src, file.to_str().unwrap(), false, false)
+                   .unwrap(), Repair::NotLink);
+        assert_eq!(fix_link(src, missing.to_str().unwrap(), false, false)
+                   .unwrap(), Repair::Missing);
+        assert_eq!(fix_link(src, broken.to_str().unwrap(), false, true)
+                   .unwrap(), Repair::Fixed {
+            old_target: PathBuf::from("missing"),
+        });
+        assert_eq!(fs::read_link(&broken).unwrap(), PathBuf::from("missing"));
+        assert_eq!(fix_link(src, broken.to_str().unwrap(), false, false)
+                   .unwrap(), Repair::Fixed {
+            old_target: PathBuf::from("missing"),
+        });
+        assert_eq!(fs::read_link(&broken).unwrap(), PathBuf::from(src));
+    }
+}
@@ -0,0 +1,187 @@
+//! File hashing utilities
+//!
+//! Files are hashed in fixed-size chunks so that large files are never read
+//! into memory all at once.
+//!
+//! ```
+//! let hash = hash_file(Path::new("foo"), HashAlgorithm::Blake3)?;
+//! ```
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use xxhash_rust::xxh3::Xxh3;
+
+/// The number of bytes read from a file at a time while hashing
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A supported hash algorithm
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    /// SHA-256, a cryptographic hash
+    #[default]
+    Sha256,
+
+    /// BLAKE3, a fast cryptographic hash
+    Blake3,
+
+    /// XXH3 (128-bit), a fast non-cryptographic hash
+    Xxh3,
+}
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HashAlgorithm::Sha256 => write!(f, "sha256"),
+            HashAlgorithm::Blake3 => write!(f, "blake3"),
+            HashAlgorithm::Xxh3 => write!(f, "xxh3"),
+        }
+    }
+}
+
+/// The incremental state of a hash algorithm
+enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+    Xxh3(Box<Xxh3>),
+}
+impl Hasher {
+    /// Creates a new Hasher for an algorithm
+    fn new(algorithm: HashAlgorithm) -> Hasher {
+        match algorithm {
+            HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            HashAlgorithm::Blake3 => {
+                Hasher::Blake3(Box::new(blake3::Hasher::new()))
+            },
+            HashAlgorithm::Xxh3 => Hasher::Xxh3(Box::new(Xxh3::new())),
+        }
+    }
+
+    /// Adds a chunk of data to the hash
+    fn update(&mut self, chunk: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(chunk),
+            Hasher::Blake3(hasher) => { hasher.update(chunk); },
+            Hasher::Xxh3(hasher) => hasher.update(chunk),
+        }
+    }
+
+    /// Returns the final hash as a hex string
+    fn finalize(self) -> String {
+        match self {
+            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+            Hasher::Xxh3(hasher) => format!("{:032x}", hasher.digest128()),
+        }
+    }
+}
+
+/// Computes the hash of the contents of a reader as a hex string
+///
+/// ```
+/// let hash = hash_reader(&mut "abc".as_bytes(), HashAlgorithm::Sha256)?;
+/// ```
+pub fn hash_reader<R: Read>(reader: &mut R, algorithm: HashAlgorithm) ->
+    Result<String> {
+
+    let mut hasher = Hasher::new(algorithm);
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let count = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(count) => count,
+            Err(why) if why.kind() == ErrorKind::Interrupted => continue,
+            Err(why) => return Err(why.into()),
+        };
+        hasher.update(&buffer[..count]);
+    }
+    Ok(hasher.finalize())
+}
+
+/// Computes the hash of a file as a hex string
+///
+/// ```
+/// let hash = hash_file(Path::new("foo"), HashAlgorithm::Sha256)?;
+/// ```
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> Result<String> {
+    let mut file = File::open(path).with_context(|| {
+        format!("Failed to open {}", path.display())
+    })?;
+    hash_reader(&mut file, algorithm).with_context(|| {
+        format!("Failed to read {}", path.display())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{setup_integration, write_file};
+
+    const ABC_SHA256: &str = "\
+ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+    const ABC_BLAKE3: &str = "\
+6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85";
+    const ABC_XXH3: &str = "06b05ab6733a618578af5f94892f3950";
+
+    #[test]
+    fn test_hash_reader_sha256() {
+        let result = hash_reader(&mut "abc".as_bytes(), HashAlgorithm::Sha256);
+
+        assert_eq!(result.unwrap(), ABC_SHA256);
+    }
+
+    #[test]
+    fn test_hash_reader_blake3() {
+        let result = hash_reader(&mut "abc".as_bytes(), HashAlgorithm::Blake3);
+
+        assert_eq!(result.unwrap(), ABC_BLAKE3);
+    }
+
+    #[test]
+    fn test_hash_reader_xxh3() {
+        let result = hash_reader(&mut "abc".as_bytes(), HashAlgorithm::Xxh3);
+
+        assert_eq!(result.unwrap(), ABC_XXH3);
+    }
+
+    #[test]
+    fn test_hash_reader_multiple_chunks() {
+        let data = "abc".repeat(CHUNK_SIZE);
+
+        let result = hash_reader(&mut data.as_bytes(), HashAlgorithm::Sha256);
+
+        let mut expected = Sha256::new();
+        expected.update(data.as_bytes());
+        assert_eq!(result.unwrap(), format!("{:x}", expected.finalize()));
+    }
+
+    #[test]
+    fn test_hash_file_basic() {
+        let tmp = setup_integration("test_hash_file_basic");
+
+        let src = tmp.local.join("foo");
+        write_file(&src, "abc");
+
+        let result = hash_file(&src, HashAlgorithm::Sha256);
+
+        assert_eq!(result.unwrap(), ABC_SHA256);
+    }
+
+    #[test]
+    fn test_hash_file_missing() {
+        let tmp = setup_integration("test_hash_file_missing");
+
+        let src = tmp.local.join("foo");
+
+        let result = hash_file(&src, HashAlgorithm::Sha256);
+
+        assert_eq!(result.is_ok(), false);
+        assert_eq!(result.unwrap_err().to_string(),
+                   format!("Failed to open {}", src.display()));
+    }
+}
@@ -0,0 +1,586 @@
+//! Conversion of dotfiles managed by other tools into coliru manifests
+//!
+//! ```
+//! let import = import_chezmoi(Path::new("~/.local/share/chezmoi"),
+//!                             Path::new("."))?;
+//! ```
+
+use anyhow::{Context, Result, bail};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The file in a chezmoi source directory that names the subdirectory
+/// containing the source state
+const CHEZMOI_ROOT: &str = ".chezmoiroot";
+
+/// The directory in a chezmoi source directory that contains scripts that
+/// aren't associated with a target directory
+const CHEZMOI_SCRIPTS: &str = ".chezmoiscripts";
+
+/// The directory that imported scripts are placed in, relative to the output
+/// directory
+const SCRIPTS_DIR: &str = "scripts";
+
+/// The attribute prefixes of chezmoi source files, in the order they appear
+const FILE_PREFIXES: &[&str] = &[
+    "encrypted_", "private_", "readonly_", "empty_", "executable_", "dot_",
+];
+
+/// The attribute prefixes of chezmoi source directories, in the order they
+/// appear
+const DIR_PREFIXES: &[&str] = &["exact_", "private_", "readonly_", "dot_"];
+
+/// The attribute prefixes of chezmoi scripts, in the order they appear
+const SCRIPT_PREFIXES: &[&str] = &[
+    "run_", "once_", "onchange_", "before_", "after_",
+];
+
+/// The prefixes of chezmoi source files and directories that can't be
+/// imported, along with the reasons why
+const UNSUPPORTED_PREFIXES: &[(&str, &str)] = &[
+    ("create_", "create-only files aren't supported"),
+    ("external_", "externals aren't supported"),
+    ("modify_", "modify scripts aren't supported"),
+    ("remove_", "removals aren't supported"),
+    ("symlink_", "symlinks aren't supported"),
+];
+
+/// The chezmoi configuration files that can't be imported, along with the
+/// reasons why
+const UNSUPPORTED_FILES: &[(&str, &str)] = &[
+    (".chezmoiexternal", "externals aren't supported"),
+    (".chezmoiignore", "ignore files aren't supported"),
+    (".chezmoiremove", "removals aren't supported"),
+];
+
+/// The permissions that a chezmoi source name gives its file
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Attributes {
+    /// Whether the file is executable
+    executable: bool,
+
+    /// Whether the file is only readable by its owner
+    private: bool,
+
+    /// Whether the file is read-only
+    readonly: bool,
+}
+
+/// A chezmoi source file that's imported
+#[derive(Debug, PartialEq)]
+struct Entry {
+    /// The path of the file, relative to the chezmoi source directory
+    source: String,
+
+    /// The path of the imported file, relative to the output directory
+    src: String,
+
+    /// The destination of the file, or None if the file is a script
+    dst: Option<String>,
+
+    /// Whether a script is run before the dotfiles are installed
+    before: bool,
+
+    /// Whether the file is a template
+    template: bool,
+
+    /// The permissions of the file
+    attributes: Attributes,
+}
+
+/// The result of importing a chezmoi source directory
+#[derive(Debug, PartialEq)]
+pub struct ChezmoiImport {
+    /// The path of the generated manifest
+    pub manifest: PathBuf,
+
+    /// The imported templates, which were copied without being rendered and
+    /// should be reviewed by hand
+    pub templates: Vec<String>,
+
+    /// The source files that weren't imported, along with the reasons why
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Converts a chezmoi source directory into a manifest and plain dotfiles
+///
+/// The name attributes of chezmoi source files are translated into copy
+/// commands (e.g. `dot_bashrc` becomes a copy of `bashrc` to `~/.bashrc`) and
+/// the permissions of the copied files, and scripts become run commands.
+/// Templates are copied without being rendered. The files are written to
+/// `output` at their destinations relative to the home directory without
+/// leading dots. Returns an Err if the manifest or any of the files already
+/// exist.
+///
+/// ```
+/// let import = import_chezmoi(Path::new("~/.local/share/chezmoi"),
+///                             Path::new("."))?;
+/// ```
+pub fn import_chezmoi(source: &Path, output: &Path) -> Result<ChezmoiImport> {
+    let manifest_path = output.join("manifest.yml");
+    if manifest_path.exists() {
+        bail!("{} already exists", manifest_path.display());
+    }
+
+    let root_file = source.join(CHEZMOI_ROOT);
+    let root = if root_file.is_file() {
+        let contents = fs::read_to_string(&root_file).with_context(|| {
+            format!("Failed to read {}", root_file.display())
+        })?;
+        source.join(contents.trim())
+    } else {
+        source.to_path_buf()
+    };
+    if !root.is_dir() {
+        bail!("{} is not a directory", root.display());
+    }
+
+    let mut entries = vec![];
+    let mut skipped = vec![];
+    scan_dir(&root, "", "", &mut entries, &mut skipped)?;
+
+    let mut srcs = HashSet::new();
+    for entry in &entries {
+        if !srcs.insert(&entry.src) || output.join(&entry.src).exists() {
+            bail!("{} already exists", output.join(&entry.src).display());
+        }
+    }
+    for entry in &entries {
+        write_file(&root.join(&entry.source), &output.join(&entry.src),
+                   entry.attributes)?;
+    }
+    fs::write(&manifest_path, generate_manifest(&entries, &skipped))
+        .with_context(|| {
+            format!("Failed to write {}", manifest_path.display())
+        })?;
+
+    Ok(ChezmoiImport {
+        manifest: manifest_path,
+        templates: entries.iter().filter(|x| x.template)
+            .map(|x| x.src.clone()).collect(),
+        skipped,
+    })
+}
+
+/// Finds the files that can be imported in a directory of a chezmoi source
+/// state, given the directory's path relative to the source directory and its
+/// destination relative to the home directory
+fn scan_dir(dir: &Path, source: &str, target: &str, entries: &mut Vec<Entry>,
+            skipped: &mut Vec<(String, String)>) -> Result<()> {
+
+    for name in sorted_names(dir)? {
+        let path = dir.join(&name);
+        let source_name = format!("{}{}", source, name);
+
+        if name.starts_with('.') {
+            if name == CHEZMOI_SCRIPTS && source.is_empty() && path.is_dir() {
+                scan_scripts(&path, &format!("{}/", name), entries,
+                             skipped)?;
+            } else if let Some((_, reason)) = UNSUPPORTED_FILES.iter()
+                .find(|x| name.starts_with(x.0)) {
+                skipped.push((source_name, reason.to_string()));
+            }
+            // chezmoi ignores all other files starting with a dot
+            continue;
+        }
+        if let Some((_, reason)) = UNSUPPORTED_PREFIXES.iter().find(|x| {
+            name.starts_with(x.0)
+        }) {
+            skipped.push((source_name, reason.to_string()));
+            continue;
+        }
+
+        if path.is_dir() {
+            let (_, target_name) = parse_name(&name, DIR_PREFIXES);
+            scan_dir(&path, &format!("{}/", source_name),
+                     &format!("{}{}/", target, target_name), entries,
+                     skipped)?;
+        } else if name.starts_with("run_") {
+            entries.push(script_entry(&source_name, &name));
+        } else {
+            let (name, template) = strip_template(&name);
+            let (attributes, target_name) = parse_name(name, FILE_PREFIXES);
+            if attributes.contains(&"encrypted_") {
+                skipped.push((source_name,
+                              String::from("encrypted files aren't supported")));
+                continue;
+            }
+            let dst = format!("{}{}", target, target_name);
+            entries.push(Entry {
+                src: dst.split('/').map(|x| x.trim_start_matches('.'))
+                    .collect::<Vec<_>>().join("/"),
+                dst: Some(format!("~/{}", dst)),
+                source: source_name,
+                before: false,
+                template,
+                attributes: Attributes {
+                    executable: attributes.contains(&"executable_"),
+                    private: attributes.contains(&"private_"),
+                    readonly: attributes.contains(&"readonly_"),
+                },
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Finds the scripts in the `.chezmoiscripts` directory of a chezmoi source
+/// state, or one of its subdirectories
+fn scan_scripts(dir: &Path, source: &str, entries: &mut Vec<Entry>,
+                skipped: &mut Vec<(String, String)>) -> Result<()> {
+
+    for name in sorted_names(dir)? {
+        let path = dir.join(&name);
+        let source_name = format!("{}{}", source, name);
+        if path.is_dir() {
+            scan_scripts(&path, &format!("{}/", source_name), entries,
+                         skipped)?;
+        } else if name.starts_with("run_") {
+            entries.push(script_entry(&source_name, &name));
+        } else if !name.starts_with('.') {
+            skipped.push((source_name, String::from("not a script")));
+        }
+    }
+    Ok(())
+}
+
+/// Returns the entry that imports a chezmoi script
+fn script_entry(source: &str, name: &str) -> Entry {
+    let (name, template) = strip_template(name);
+    let (attributes, script_name) = parse_name(name, SCRIPT_PREFIXES);
+    Entry {
+        source: source.to_owned(),
+        src: format!("{}/{}", SCRIPTS_DIR, script_name),
+        dst: None,
+        before: attributes.contains(&"before_"),
+        template,
+        attributes: Attributes { executable: true, ..Attributes::default() },
+    }
+}
+
+/// Strips the attribute prefixes from a chezmoi source name, returning the
+/// attributes that were found and the name of the destination
+///
+/// Prefixes must appear in the same order as `prefixes`, and a `literal_`
+/// prefix stops any further prefixes from being parsed. A `dot_` prefix is
+/// replaced with a `.`.
+///
+/// ```
+/// let (attributes, name) = parse_name("executable_dot_foo", FILE_PREFIXES);
+/// assert_eq!(attributes, vec!["executable_", "dot_"]);
+/// assert_eq!(name, ".foo");
+/// ```
+fn parse_name(name: &str, prefixes: &[&'static str]) ->
+    (Vec<&'static str>, String) {
+
+    let mut attributes = vec![];
+    let mut rest = name;
+    for prefix in prefixes {
+        if rest.starts_with("literal_") { break; }
+        if let Some(stripped) = rest.strip_prefix(prefix) {
+            attributes.push(*prefix);
+            rest = stripped;
+        }
+    }
+    let rest = rest.strip_prefix("literal_").unwrap_or(rest);
+
+    if attributes.contains(&"dot_") {
+        (attributes, format!(".{}", rest))
+    } else {
+        (attributes, rest.to_owned())
+    }
+}
+
+/// Strips the suffixes from a chezmoi source file name, returning the rest of
+/// the name and whether the file is a template
+fn strip_template(name: &str) -> (&str, bool) {
+    if let Some(literal) = name.strip_suffix(".literal") {
+        (literal, false)
+    } else if let Some(template) = name.strip_suffix(".tmpl") {
+        (template.strip_suffix(".literal").unwrap_or(template), true)
+    } else {
+        (name, false)
+    }
+}
+
+/// Returns the sorted names of the entries in a directory
+fn sorted_names(dir: &Path) -> Result<Vec<String>> {
+    let mut names = vec![];
+    for entry in fs::read_dir(dir).with_context(|| {
+        format!("Failed to read {}", dir.display())
+    })? {
+        let entry = entry.with_context(|| {
+            format!("Failed to read {}", dir.display())
+        })?;
+        names.push(entry.file_name().to_string_lossy().to_string());
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Copies an imported file to the output directory and sets its permissions
+fn write_file(src: &Path, dst: &Path, attributes: Attributes) -> Result<()> {
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent).with_context(|| {
+            format!("Failed to create {}", parent.display())
+        })?;
+    }
+    fs::copy(src, dst).with_context(|| {
+        format!("Failed to copy {} to {}", src.display(), dst.display())
+    })?;
+
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut mode = 0o644;
+        if attributes.executable { mode |= 0o111; }
+        if attributes.private { mode &= 0o700; }
+        if attributes.readonly { mode &= !0o222; }
+        fs::set_permissions(dst, fs::Permissions::from_mode(mode))
+            .with_context(|| {
+                format!("Failed to set the permissions of {}", dst.display())
+            })?;
+    }
+    #[cfg(not(target_family = "unix"))]
+    let _ = attributes;
+
+    Ok(())
+}
+
+/// Generates the contents of a manifest file that installs a set of imported
+/// files, listing templates and skipped files in comments
+fn generate_manifest(entries: &[Entry], skipped: &[(String, String)]) ->
+    String {
+
+    let mut manifest = String::from("\
+# Generated by coliru import chezmoi. Review the destinations below, then
+# install the dotfiles with `coliru manifest.yml`.
+");
+
+    if entries.iter().any(|x| x.template) {
+        manifest.push_str("#\n# Templates that were copied without being \
+                           rendered:\n");
+        for entry in entries.iter().filter(|x| x.template) {
+            manifest.push_str(&format!("# - {}\n", entry.src));
+        }
+    }
+    if !skipped.is_empty() {
+        manifest.push_str("#\n# Files that were not imported:\n");
+        for (name, reason) in skipped {
+            manifest.push_str(&format!("# - {} ({})\n", name, reason));
+        }
+    }
+
+    manifest.push_str("\nsteps:");
+    let scripts = |before: bool| -> Vec<&Entry> {
+        entries.iter().filter(|x| x.dst.is_none() && x.before == before)
+            .collect()
+    };
+    let copies: Vec<&Entry> = entries.iter().filter(|x| x.dst.is_some())
+        .collect();
+    let mut is_empty = true;
+    for (command, step) in [("run", scripts(true)), ("copy", copies),
+                            ("run", scripts(false))] {
+        if step.is_empty() { continue; }
+
+        manifest.push_str(&format!("\n  - {}:\n", command));
+        for entry in step {
+            manifest.push_str(&format!("    - src: {}\n", entry.src));
+            if let Some(dst) = &entry.dst {
+                manifest.push_str(&format!("      dst: {}\n", dst));
+            }
+        }
+        is_empty = false;
+    }
+    if is_empty {
+        manifest.push_str(" []\n");
+    }
+
+    manifest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::parse_manifest_file;
+    use crate::test_utils::{read_file, setup_integration, write_file};
+
+    #[test]
+    fn test_parse_name() {
+        let (attributes, name) = parse_name("executable_dot_foo",
+                                            FILE_PREFIXES);
+        assert_eq!(attributes, vec!["executable_", "dot_"]);
+        assert_eq!(name, ".foo");
+
+        let (attributes, name) = parse_name("dot_literal_dot_foo",
+                                            FILE_PREFIXES);
+        assert_eq!(attributes, vec!["dot_"]);
+        assert_eq!(name, ".dot_foo");
+
+        let (attributes, name) = parse_name("run_once_before_install.sh",
+                                            SCRIPT_PREFIXES);
+        assert_eq!(attributes, vec!["run_", "once_", "before_"]);
+        assert_eq!(name, "install.sh");
+    }
+
+    #[test]
+    fn test_strip_template() {
+        assert_eq!(strip_template("dot_gitconfig.tmpl"),
+                   ("dot_gitconfig", true));
+        assert_eq!(strip_template("foo.tmpl.literal"), ("foo.tmpl", false));
+        assert_eq!(strip_template("dot_bashrc"), ("dot_bashrc", false));
+    }
+
+    #[test]
+    fn test_scan_dir_basic() {
+        let tmp = setup_integration("test_import_scan_dir_basic");
+        fs::create_dir_all(tmp.local.join("private_dot_config/nvim")).unwrap();
+        fs::create_dir_all(tmp.local.join(".git")).unwrap();
+        write_file(&tmp.local.join("dot_bashrc"), "");
+        write_file(&tmp.local.join("executable_dot_local_bin"), "");
+        write_file(&tmp.local.join("private_dot_config/nvim/init.lua.tmpl"),
+                   "");
+        write_file(&tmp.local.join("run_after_setup.sh"), "");
+        write_file(&tmp.local.join("symlink_dot_vim"), "");
+        write_file(&tmp.local.join(".chezmoiignore"), "");
+        write_file(&tmp.local.join(".git/HEAD"), "");
+
+        let mut entries = vec![];
+        let mut skipped = vec![];
+        scan_dir(&tmp.local, "", "", &mut entries, &mut skipped).unwrap();
+
+        assert_eq!(entries, vec![
+            Entry {
+                source: String::from("dot_bashrc"),
+                src: String::from("bashrc"),
+                dst: Some(String::from("~/.bashrc")),
+                before: false,
+                template: false,
+                attributes: Attributes::default(),
+            },
+            Entry {
+                source: String::from("executable_dot_local_bin"),
+                src: String::from("local_bin"),
+                dst: Some(String::from("~/.local_bin")),
+                before: false,
+                template: false,
+                attributes: Attributes { executable: true,
+                                         ..Attributes::default() },
+            },
+            Entry {
+                source: String::from("private_dot_config/nvim/init.lua.tmpl"),
+                src: String::from("config/nvim/init.lua"),
+                dst: Some(String::from("~/.config/nvim/init.lua")),
+                before: false,
+                template: true,
+                attributes: Attributes::default(),
+            },
+            Entry {
+                source: String::from("run_after_setup.sh"),
+                src: String::from("scripts/setup.sh"),
+                dst: None,
+                before: false,
+                template: false,
+                attributes: Attributes { executable: true,
+                                         ..Attributes::default() },
+            },
+        ]);
+        assert_eq!(skipped, vec![
+            (String::from(".chezmoiignore"),
+             String::from("ignore files aren't supported")),
+            (String::from("symlink_dot_vim"),
+             String::from("symlinks aren't supported")),
+        ]);
+    }
+
+    #[test]
+    fn test_import_chezmoi_basic() {
+        let tmp = setup_integration("test_import_chezmoi_basic");
+        let source = tmp.local.join("chezmoi");
+        fs::create_dir_all(source.join(".chezmoiscripts")).unwrap();
+        write_file(&source.join("dot_bashrc"), "bash\n");
+        write_file(&source.join("dot_gitconfig.tmpl"), "{{ .email }}\n");
+        write_file(&source.join(".chezmoiscripts/run_once_before_a.sh"), "");
+
+        let import = import_chezmoi(&source, &tmp.local).unwrap();
+
+        let manifest_path = tmp.local.join("manifest.yml");
+        assert_eq!(import, ChezmoiImport {
+            manifest: manifest_path.clone(),
+            templates: vec![String::from("gitconfig")],
+            skipped: vec![],
+        });
+        assert_eq!(read_file(&tmp.local.join("bashrc")), "bash\n");
+        assert_eq!(read_file(&tmp.local.join("gitconfig")), "{{ .email }}\n");
+        assert_eq!(tmp.local.join("scripts/a.sh").exists(), true);
+        assert_eq!(read_file(&manifest_path), "\
+# Generated by coliru import chezmoi. Review the destinations below, then
+# install the dotfiles with `coliru manifest.yml`.
+#
+# Templates that were copied without being rendered:
+# - gitconfig
+
+steps:
+  - run:
+    - src: scripts/a.sh
+
+  - copy:
+    - src: bashrc
+      dst: ~/.bashrc
+    - src: gitconfig
+      dst: ~/.gitconfig
+");
+        let manifest = parse_manifest_file(&manifest_path).unwrap();
+        assert_eq!(manifest.steps.len(), 2);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_import_chezmoi_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = setup_integration("test_import_chezmoi_permissions");
+        write_file(&tmp.local.join("private_dot_netrc"), "");
+        write_file(&tmp.local.join("executable_dot_hook"), "");
+        let output = tmp.local.join("out");
+
+        import_chezmoi(&tmp.local, &output).unwrap();
+
+        let mode = |name: &str| {
+            fs::metadata(output.join(name)).unwrap().permissions().mode()
+        };
+        assert_eq!(mode("netrc") & 0o777, 0o600);
+        assert_eq!(mode("hook") & 0o777, 0o755);
+    }
+
+    #[test]
+    fn test_import_chezmoi_root() {
+        let tmp = setup_integration("test_import_chezmoi_root");
+        fs::create_dir_all(tmp.local.join("home")).unwrap();
+        write_file(&tmp.local.join(".chezmoiroot"), "home\n");
+        write_file(&tmp.local.join("home/dot_zshrc"), "");
+        let output = tmp.local.join("out");
+
+        import_chezmoi(&tmp.local, &output).unwrap();
+
+        assert_eq!(output.join("zshrc").exists(), true);
+    }
+
+    #[test]
+    fn test_import_chezmoi_existing() {
+        let tmp = setup_integration("test_import_chezmoi_existing");
+        let source = tmp.local.join("chezmoi");
+        fs::create_dir_all(&source).unwrap();
+        write_file(&source.join("dot_bashrc"), "");
+        write_file(&tmp.local.join("bashrc"), "existing\n");
+
+        let why = import_chezmoi(&source, &tmp.local).unwrap_err();
+
+        assert_eq!(why.to_string(), format!("{} already exists",
+                   tmp.local.join("bashrc").display()));
+        assert_eq!(read_file(&tmp.local.join("bashrc")), "existing\n");
+        assert_eq!(tmp.local.join("manifest.yml").exists(), false);
+    }
+}
@@ -0,0 +1,348 @@
+//! Generation of starting manifests from existing dotfile directories
+//!
+//! ```
+//! let (manifest_path, skipped) = init_manifest(Path::new("~/dotfiles"))?;
+//! ```
+
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The platforms that a recognized dotfile is installed on
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Platform {
+    /// Linux, MacOS, and Windows
+    All,
+
+    /// Linux and MacOS
+    Unix,
+
+    /// Windows
+    Windows,
+}
+
+/// Recognized dotfiles and their destinations, by file name without any leading
+/// dot
+const KNOWN_FILES: &[(&str, &str, Platform)] = &[
+    ("bash_aliases", "~/.bash_aliases", Platform::Unix),
+    ("bash_logout", "~/.bash_logout", Platform::Unix),
+    ("bash_profile", "~/.bash_profile", Platform::Unix),
+    ("bashrc", "~/.bashrc", Platform::Unix),
+    ("curlrc", "~/.curlrc", Platform::Unix),
+    ("editorconfig", "~/.editorconfig", Platform::All),
+    ("gitconfig", "~/.gitconfig", Platform::All),
+    ("gitignore_global", "~/.gitignore_global", Platform::All),
+    ("inputrc", "~/.inputrc", Platform::Unix),
+    ("profile", "~/.profile", Platform::Unix),
+    ("tmux.conf", "~/.tmux.conf", Platform::Unix),
+    ("vimrc", "~/.vimrc", Platform::Unix),
+    ("vimrc", "~/_vimrc", Platform::Windows),
+    ("wgetrc", "~/.wgetrc", Platform::Unix),
+    ("zprofile", "~/.zprofile", Platform::Unix),
+    ("zshenv", "~/.zshenv", Platform::Unix),
+    ("zshrc", "~/.zshrc", Platform::Unix),
+];
+
+/// Recognized directories whose files are installed under `~/.config/`
+const KNOWN_CONFIG_DIRS: &[&str] = &[
+    "alacritty", "fish", "helix", "hypr", "i3", "kitty", "nvim", "sway",
+    "wezterm", "zellij",
+];
+
+/// Files and directories that are never included in a generated manifest
+const IGNORED_NAMES: &[&str] = &[
+    ".git", ".gitignore", "LICENSE", "README.md", "manifest.yml",
+];
+
+/// A link command in a generated manifest
+#[derive(Debug, PartialEq)]
+struct Entry {
+    /// The path of the dotfile, relative to the manifest
+    src: String,
+
+    /// The destination of the dotfile
+    dst: String,
+
+    /// The platforms the dotfile is installed on
+    platform: Platform,
+}
+
+/// Generates a starting `manifest.yml` file for the dotfiles in a directory
+///
+/// Recognized dotfiles are linked to their usual locations under `~/` in steps
+/// tagged by platform. Returns an Err if the manifest already exists and
+/// otherwise returns the path of the new manifest and the names of the files
+/// and directories that weren't recognized.
+///
+/// ```
+/// let (manifest_path, skipped) = init_manifest(Path::new("~/dotfiles"))?;
+/// ```
+pub fn init_manifest(dir: &Path) -> Result<(PathBuf, Vec<String>)> {
+    let manifest_path = dir.join("manifest.yml");
+    if manifest_path.exists() {
+        bail!("{} already exists", manifest_path.display());
+    }
+
+    let (entries, skipped) = scan_dir(dir)?;
+    fs::write(&manifest_path, generate_manifest(&entries, &skipped))
+        .with_context(|| {
+            format!("Failed to write {}", manifest_path.display())
+        })?;
+
+    Ok((manifest_path, skipped))
+}
+
+/// Finds the recognized dotfiles in a directory, returning link commands for
+/// them and the names of the unrecognized files and directories
+fn scan_dir(dir: &Path) -> Result<(Vec<Entry>, Vec<String>)> {
+    let mut entries = vec![];
+    let mut skipped = vec![];
+
+    for name in sorted_names(dir)? {
+        if IGNORED_NAMES.contains(&name.as_str()) { continue; }
+
+        let path = dir.join(&name);
+        let bare_name = name.strip_prefix('.').unwrap_or(&name);
+
+        if path.is_dir() {
+            if !KNOWN_CONFIG_DIRS.contains(&bare_name) {
+                skipped.push(format!("{}/", name));
+                continue;
+            }
+            for file in dir_files(&path, &name)? {
+                let relative = &file[name.len()..];
+                entries.push(Entry {
+                    dst: format!("~/.config/{}{}", bare_name, relative),
+                    src: file,
+                    platform: Platform::Unix,
+                });
+            }
+        } else {
+            let mut found = false;
+            for (known, dst, platform) in KNOWN_FILES {
+                if *known == bare_name {
+                    entries.push(Entry {
+                        src: name.clone(),
+                        dst: dst.to_string(),
+                        platform: *platform,
+                    });
+                    found = true;
+                }
+            }
+            if !found { skipped.push(name); }
+        }
+    }
+
+    Ok((entries, skipped))
+}
+
+/// Returns the sorted names of the entries in a directory
+fn sorted_names(dir: &Path) -> Result<Vec<String>> {
+    let mut names = vec![];
+    for entry in fs::read_dir(dir).with_context(|| {
+        format!("Failed to read {}", dir.display())
+    })? {
+        let entry = entry.with_context(|| {
+            format!("Failed to read {}", dir.display())
+        })?;
+        names.push(entry.file_name().to_string_lossy().to_string());
+    }
+    names.sort();
+    Ok(names)
+}
+
+/// Recursively lists the files in a directory as sorted `/` separated paths
+/// starting with `prefix`
+fn dir_files(dir: &Path, prefix: &str) -> Result<Vec<String>> {
+    let mut files = vec![];
+    for name in sorted_names(dir)? {
+        let path = dir.join(&name);
+        let relative = format!("{}/{}", prefix, name);
+        if path.is_dir() {
+            files.extend(dir_files(&path, &relative)?);
+        } else {
+            files.push(relative);
+        }
+    }
+    Ok(files)
+}
+
+/// Generates the contents of a manifest file from a set of link commands,
+/// listing any unrecognized files in a comment
+fn generate_manifest(entries: &[Entry], skipped: &[String]) -> String {
+    let mut manifest = String::from("\
+# Generated by coliru init. Review the destinations and tags below, then install
+# the dotfiles with `coliru manifest.yml --tag-rules <OS>`.
+");
+
+    if !skipped.is_empty() {
+        manifest.push_str("#\n# Unrecognized files that were not included:\n");
+        for name in skipped {
+            manifest.push_str(&format!("# - {}\n", name));
+        }
+    }
+
+    manifest.push_str("\nsteps:");
+    let steps = [
+        (Platform::All, "windows, linux, macos"),
+        (Platform::Unix, "linux, macos"),
+        (Platform::Windows, "windows"),
+    ];
+    let mut is_empty = true;
+    for (platform, tags) in steps {
+        let links: Vec<&Entry> = entries.iter().filter(|x| {
+            x.platform == platform
+        }).collect();
+        if links.is_empty() { continue; }
+
+        manifest.push_str("\n  - link:\n");
+        for link in links {
+            manifest.push_str(&format!("    - src: {}\n      dst: {}\n",
+                                       link.src, link.dst));
+        }
+        manifest.push_str(&format!("    tags: [ {} ]\n", tags));
+        is_empty = false;
+    }
+    if is_empty {
+        manifest.push_str(" []\n");
+    }
+
+    manifest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::parse_manifest_file;
+    use crate::test_utils::{read_file, setup_integration, write_file};
+
+    #[test]
+    fn test_scan_dir_basic() {
+        let tmp = setup_integration("test_scan_dir_basic");
+
+        write_file(&tmp.local.join(".bashrc"), "");
+        write_file(&tmp.local.join("gitconfig"), "");
+        write_file(&tmp.local.join("notes.txt"), "");
+        write_file(&tmp.local.join("README.md"), "");
+        fs::create_dir_all(tmp.local.join("nvim/lua")).unwrap();
+        write_file(&tmp.local.join("nvim/init.lua"), "");
+        write_file(&tmp.local.join("nvim/lua/plugins.lua"), "");
+        fs::create_dir_all(tmp.local.join("misc")).unwrap();
+
+        let (entries, skipped) = scan_dir(&tmp.local).unwrap();
+
+        assert_eq!(entries, vec![
+            Entry {
+                src: String::from(".bashrc"),
+                dst: String::from("~/.bashrc"),
+                platform: Platform::Unix,
+            },
+            Entry {
+                src: String::from("gitconfig"),
+                dst: String::from("~/.gitconfig"),
+                platform: Platform::All,
+            },
+            Entry {
+                src: String::from("nvim/init.lua"),
+                dst: String::from("~/.config/nvim/init.lua"),
+                platform: Platform::Unix,
+            },
+            Entry {
+                src: String::from("nvim/lua/plugins.lua"),
+                dst: String::from("~/.config/nvim/lua/plugins.lua"),
+                platform: Platform::Unix,
+            },
+        ]);
+        assert_eq!(skipped, vec!["misc/", "notes.txt"]);
+    }
+
+    #[test]
+    fn test_scan_dir_multiple_platforms() {
+        let tmp = setup_integration("test_scan_dir_multiple_platforms");
+
+        write_file(&tmp.local.join("vimrc"), "");
+
+        let (entries, skipped) = scan_dir(&tmp.local).unwrap();
+
+        assert_eq!(entries, vec![
+            Entry {
+                src: String::from("vimrc"),
+                dst: String::from("~/.vimrc"),
+                platform: Platform::Unix,
+            },
+            Entry {
+                src: String::from("vimrc"),
+                dst: String::from("~/_vimrc"),
+                platform: Platform::Windows,
+            },
+        ]);
+        assert_eq!(skipped, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_init_manifest_basic() {
+        let tmp = setup_integration("test_init_manifest_basic");
+
+        write_file(&tmp.local.join("gitconfig"), "");
+        write_file(&tmp.local.join("vimrc"), "");
+        write_file(&tmp.local.join("notes.txt"), "");
+
+        let result = init_manifest(&tmp.local);
+
+        let manifest_path = tmp.local.join("manifest.yml");
+        assert_eq!(result.unwrap(),
+                   (manifest_path.clone(), vec![String::from("notes.txt")]));
+        assert_eq!(read_file(&manifest_path), "\
+# Generated by coliru init. Review the destinations and tags below, then install
+# the dotfiles with `coliru manifest.yml --tag-rules <OS>`.
+#
+# Unrecognized files that were not included:
+# - notes.txt
+
+steps:
+  - link:
+    - src: gitconfig
+      dst: ~/.gitconfig
+    tags: [ windows, linux, macos ]
+
+  - link:
+    - src: vimrc
+      dst: ~/.vimrc
+    tags: [ linux, macos ]
+
+  - link:
+    - src: vimrc
+      dst: ~/_vimrc
+    tags: [ windows ]
+");
+        assert_eq!(parse_manifest_file(&manifest_path).unwrap().steps.len(),
+                   3);
+    }
+
+    #[test]
+    fn test_init_manifest_empty() {
+        let tmp = setup_integration("test_init_manifest_empty");
+
+        let result = init_manifest(&tmp.local);
+
+        let manifest_path = tmp.local.join("manifest.yml");
+        assert_eq!(result.unwrap(), (manifest_path.clone(), vec![]));
+        assert_eq!(parse_manifest_file(&manifest_path).unwrap().steps,
+                   vec![]);
+    }
+
+    #[test]
+    fn test_init_manifest_existing() {
+        let tmp = setup_integration("test_init_manifest_existing");
+
+        let manifest_path = tmp.local.join("manifest.yml");
+        write_file(&manifest_path, "steps: []");
+
+        let result = init_manifest(&tmp.local);
+
+        assert_eq!(result.is_ok(), false);
+        assert_eq!(result.unwrap_err().to_string(),
+                   format!("{} already exists", manifest_path.display()));
+        assert_eq!(read_file(&manifest_path), "steps: []");
+    }
+}
@@ -0,0 +1,78 @@
+//! A minimal, flexible, dotfile installer
+//!
+//! The `coliru` binary is a thin wrapper around this library, which can also
+//! install manifests on its own and report their progress to a frontend.
+//!
+//! ```
+//! let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+//! let options = InstallOptions { tag_rules: vec![String::from("linux")],
+//!                                ..InstallOptions::default() };
+//! let failure = install_manifest(manifest, &options)?;
+//! ```
+
+mod add;
+mod backup;
+mod block;
+mod brew;
+mod clean;
+mod cli;
+mod config;
+mod container;
+mod core;
+mod cron;
+mod defaults;
+mod doctor;
+mod eol;
+mod exclude;
+mod exit;
+mod export;
+mod extension;
+mod facts;
+mod fetch;
+mod fix;
+mod hash;
+mod import;
+mod init;
+mod lint;
+mod local;
+mod manifest;
+#[cfg(feature = "native-ssh")]
+mod native;
+#[cfg(feature = "async")]
+mod nonblocking;
+mod packages;
+mod patch;
+mod plan;
+mod preview;
+mod remote;
+mod render;
+mod repo;
+mod report;
+mod reporter;
+mod ssh;
+mod state;
+mod stow;
+mod systemd;
+mod tag_expr;
+mod target;
+mod tui;
+mod uninstall;
+mod verify;
+mod watch;
+
+pub use cli::run;
+pub use container::{Container, Engine};
+pub use self::core::{InstallObserver, InstallOptions, install_manifest};
+pub use exit::{Error, Failure};
+pub use manifest::{LinkMode, Manifest, Step, parse_manifest_file,
+    parse_manifest_str};
+#[cfg(feature = "async")]
+pub use nonblocking::install_manifest_async;
+pub use reporter::{ConsoleReporter, Event, JsonReporter, NullReporter,
+    Outcome, QuietReporter, Reporter};
+pub use ssh::{HostKeyPolicy, RemoteOs, SshOptions, SshPassword, SudoPassword,
+    Transport, askpass_password, read_ssh_password};
+
+#[cfg(test)]
+#[path = "../tests/test_utils/mod.rs"]
+mod test_utils; // Re-use E2E test utils for integration tests
@@ -0,0 +1,414 @@
+//! Static checks for common mistakes in manifests
+//!
+//! Unlike the doctor subcommand, linting only reads the manifest and its
+//! source files, and checks every step regardless of tag rules.
+//!
+//! ```
+//! let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+//! let clean = run_lint(&manifest)?;
+//! ```
+
+use anyhow::Result;
+use colored::Colorize;
+use shellexpand::tilde;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use super::brew::brewfiles;
+use super::manifest::{Manifest, Step, get_tag_usage};
+use super::tag_expr::closest_tag;
+
+/// How serious a problem found in a manifest is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    /// The problem is suspicious but may be intentional
+    Warning,
+
+    /// The problem will cause installation to fail or behave unexpectedly
+    Error,
+}
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "{}", "warning".yellow()),
+            Severity::Error => write!(f, "{}", "error".red()),
+        }
+    }
+}
+
+/// A problem found in a manifest
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    /// The 1-based index of the step with the problem, or None if the problem
+    /// isn't in a single step
+    pub step: Option<usize>,
+
+    /// How serious the problem is
+    pub severity: Severity,
+
+    /// A description of the problem
+    pub message: String,
+}
+impl Problem {
+    /// Creates a Problem with a severity of [`Severity::Warning`]
+    fn warning(step: Option<usize>, message: String) -> Problem {
+        Problem { step, severity: Severity::Warning, message }
+    }
+
+    /// Creates a Problem with a severity of [`Severity::Error`]
+    fn error(step: Option<usize>, message: String) -> Problem {
+        Problem { step, severity: Severity::Error, message }
+    }
+}
+
+/// Lints a manifest and prints the problems that are found
+///
+/// Returns a bool indicating whether the manifest is free of errors (warnings
+/// are allowed).
+///
+/// ```
+/// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// let clean = run_lint(&manifest)?;
+/// ```
+pub fn run_lint(manifest: &Manifest) -> Result<bool> {
+    let problems = lint_manifest(manifest);
+
+    for problem in &problems {
+        let location = match problem.step {
+            Some(step) => format!("step {}", step),
+            None => String::from("manifest"),
+        };
+        println!("{} {}: {}", format!("[{}]", problem.severity).bold(),
+                 location, problem.message);
+    }
+
+    let count = |severity| {
+        problems.iter().filter(|x| x.severity == severity).count()
+    };
+    let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+    if problems.is_empty() {
+        println!("{}", "No problems found".bold());
+    } else {
+        println!("{}", format!("Found {} error{} and {} warning{}", errors,
+                               if errors == 1 { "" } else { "s" }, warnings,
+                               if warnings == 1 { "" } else { "s" }).bold());
+    }
+
+    Ok(errors == 0)
+}
+
+/// Returns the problems in a manifest, ordered by step, followed by the
+/// problems that aren't in a single step
+///
+/// Every step is checked for missing or absolute source files, scripts that
+/// can't be executed without a prefix, a lack of commands, destinations that
+/// another step can install at the same time, and tags that are probably
+/// typos. Tags in `exclusive_tags` that no step uses are also reported.
+///
+/// ```
+/// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// for problem in lint_manifest(&manifest) {
+///     println!("{}", problem.message);
+/// }
+/// ```
+pub fn lint_manifest(manifest: &Manifest) -> Vec<Problem> {
+    let mut problems = vec![];
+
+    for (i, step) in manifest.steps.iter().enumerate() {
+        if is_empty_step(step) {
+            problems.push(Problem::warning(Some(i+1),
+                String::from("step doesn't have any commands")));
+        }
+        problems.extend(check_sources(step, i+1, &manifest.base_dir));
+        problems.extend(check_scripts(step, i+1, &manifest.base_dir));
+    }
+    problems.extend(check_destinations(&manifest.steps));
+    problems.extend(check_tags(manifest));
+
+    problems.sort_by_key(|x| x.step.unwrap_or(usize::MAX));
+    problems
+}
+
+/// Checks whether a step doesn't have any commands
+fn is_empty_step(step: &Step) -> bool {
+    step.copy.is_empty() && step.link.is_empty() && step.block.is_empty() &&
+        step.patch.is_empty() && step.packages.is_empty() &&
+        step.brew.is_empty() && step.repo.is_empty() && step.fetch.is_empty() &&
+        step.systemd.is_empty() && step.defaults.is_empty() &&
+        step.extensions.is_empty() && step.cron.is_empty() &&
+        step.run.is_empty() && step.stow.is_empty()
+}
+
+/// Checks that the source files of a step exist and are relative to the
+/// manifest
+fn check_sources(step: &Step, step_num: usize, base_dir: &Path) ->
+    Vec<Problem> {
+
+    let srcs = step.copy.iter().chain(step.link.iter())
+        .flat_map(|x| x.sources())
+        .chain(step.block.iter().map(|x| x.src.as_str()))
+        .chain(step.patch.iter().map(|x| x.src.as_str()))
+        .chain(brewfiles(&step.brew))
+        .chain(step.systemd.iter().filter_map(|x| x.src.as_deref()))
+        .chain(step.run.iter().map(|x| x.src.as_str()));
+
+    let mut problems = vec![];
+    let mut checked = HashSet::new();
+    for src in srcs.filter(|x| checked.insert(*x)) {
+        if is_absolute_src(src) {
+            problems.push(Problem::warning(Some(step_num), format!(
+                "source {} is an absolute path, so the manifest won't work on \
+                 other machines", src)));
+        }
+        if !base_dir.join(tilde(src).as_ref()).exists() {
+            problems.push(Problem::error(Some(step_num), format!(
+                "source {} doesn't exist", src)));
+        }
+    }
+    problems
+}
+
+/// Checks whether a source path is absolute on any platform, instead of
+/// relative to the manifest
+///
+/// ```
+/// assert_eq!(is_absolute_src("~/.bashrc"), true);
+/// assert_eq!(is_absolute_src("C:\\bashrc"), true);
+/// assert_eq!(is_absolute_src("bashrc"), false);
+/// ```
+fn is_absolute_src(src: &str) -> bool {
+    let drive = src.chars().next().is_some_and(|x| x.is_ascii_alphabetic()) &&
+        src[1..].starts_with(':');
+    src.starts_with(['/', '\\', '~']) || src.starts_with("%USERPROFILE%") ||
+        drive
+}
+
+/// Checks that the scripts of a step without a prefix can be executed
+///
+/// Windows scripts (`.bat`, `.cmd`, and `.ps1` files) are run by `cmd`, so
+/// they don't need to be executable.
+fn check_scripts(step: &Step, step_num: usize, base_dir: &Path) ->
+    Vec<Problem> {
+
+    let windows_exts = ["bat", "cmd", "ps1"];
+    step.run.iter().filter(|x| x.prefix.trim().is_empty()).filter(|x| {
+        let ext = Path::new(&x.src).extension()
+            .map(|x| x.to_string_lossy().to_lowercase());
+        !ext.is_some_and(|x| windows_exts.contains(&x.as_str()))
+    }).filter_map(|x| {
+        let path = base_dir.join(&x.src);
+        (path.exists() && !is_executable(&path)).then(|| {
+            Problem::warning(Some(step_num), format!(
+                "script {} isn't executable, so it needs a prefix (e.g. sh) or \
+                 to be made executable with chmod +x", x.src))
+        })
+    }).collect()
+}
+
+/// Checks whether a file can be executed on the local machine
+#[cfg(target_family = "unix")]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata().is_ok_and(|x| x.permissions().mode() & 0o111 != 0)
+}
+#[cfg(not(target_family = "unix"))]
+fn is_executable(_path: &Path) -> bool {
+    true
+}
+
+/// Checks that no two copy, link, repo, or fetch commands have the same
+/// destination, unless they're in steps that are never installed together
+fn check_destinations(steps: &[Step]) -> Vec<Problem> {
+    let dsts: Vec<Vec<&str>> = steps.iter().map(|step| {
+        step.copy.iter().chain(step.link.iter()).map(|x| x.dst.as_str())
+            .chain(step.repo.iter().map(|x| x.dst.as_str()))
+            .chain(step.fetch.iter().map(|x| x.dst.as_str()))
+            .collect()
+    }).collect();
+
+    // Tags that every step has (e.g. default tags) don't separate any steps
+    let common: Vec<&String> = steps.first().map_or(vec![], |first| {
+        first.tags.iter().filter(|tag| {
+            steps.iter().all(|x| x.tags.contains(tag))
+        }).collect()
+    });
+    let overlap = |a: &Step, b: &Step| {
+        let a: Vec<_> = a.tags.iter().filter(|x| !common.contains(x))
+            .collect();
+        let b: Vec<_> = b.tags.iter().filter(|x| !common.contains(x))
+            .collect();
+        a.is_empty() || b.is_empty() || a.iter().any(|x| b.contains(x))
+    };
+
+    let mut problems = vec![];
+    for (i, step_dsts) in dsts.iter().enumerate() {
+        for (j, dst) in step_dsts.iter().enumerate() {
+            if step_dsts[..j].contains(dst) {
+                problems.push(Problem::error(Some(i+1), format!(
+                    "destination {} is installed more than once", dst)));
+                continue;
+            }
+            let other = (0..i).find(|k| {
+                dsts[*k].contains(dst) && overlap(&steps[i], &steps[*k])
+            });
+            if let Some(k) = other {
+                problems.push(Problem::error(Some(i+1), format!(
+                    "destination {} is also installed by step {}", dst, k+1)));
+            }
+        }
+    }
+    problems
+}
+
+/// Checks for tags that are only used by one step and look like a typo of a
+/// tag that's used by several steps, and tags in `exclusive_tags` that aren't
+/// used by any step
+fn check_tags(manifest: &Manifest) -> Vec<Problem> {
+    let usage = get_tag_usage(manifest);
+    let common: Vec<String> = usage.iter().filter(|x| x.1.len() > 1)
+        .map(|x| x.0.clone()).collect();
+
+    let mut problems = vec![];
+    for (tag, steps) in &usage {
+        if steps.len() != 1 { continue; }
+        if let Some(closest) = closest_tag(tag, &common) {
+            problems.push(Problem::warning(Some(steps[0]), format!(
+                "tag {} isn't used by any other step (did you mean {}?)",
+                tag, closest)));
+        }
+    }
+    for tag in manifest.exclusive_tags.iter().flatten() {
+        if !usage.contains_key(tag) {
+            problems.push(Problem::warning(None, format!(
+                "tag {} in exclusive_tags isn't used by any step", tag)));
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::parse_manifest_str;
+    use crate::test_utils::{setup_integration, write_file};
+
+    #[test]
+    fn test_lint_manifest_clean() {
+        let tmp = setup_integration("test_lint_manifest_clean");
+        write_file(&tmp.local.join("bashrc"), "");
+        write_file(&tmp.local.join("vimrc"), "");
+        let contents = "\
+steps:
+  - copy: [ { src: bashrc, dst: ~/.bashrc } ]
+    tags: [ linux ]
+  - copy: [ { src: vimrc, dst: ~/.vimrc } ]
+    tags: [ linux ]
+";
+        let manifest = parse_manifest_str(contents, &tmp.local).unwrap();
+
+        assert_eq!(lint_manifest(&manifest), vec![]);
+    }
+
+    #[test]
+    fn test_lint_manifest_sources() {
+        let tmp = setup_integration("test_lint_manifest_sources");
+        write_file(&tmp.local.join("bashrc"), "");
+        let contents = "\
+steps:
+  - copy:
+    - { src: bashrc, dst: ~/.bashrc }
+    - { src: vimrc, dst: ~/.vimrc }
+    - { src: /etc/hosts, dst: ~/hosts }
+";
+        let manifest = parse_manifest_str(contents, &tmp.local).unwrap();
+
+        assert_eq!(lint_manifest(&manifest), vec![
+            Problem::error(Some(1), String::from("source vimrc doesn't exist")),
+            Problem::warning(Some(1), String::from("source /etc/hosts is an \
+                absolute path, so the manifest won't work on other machines")),
+        ]);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_lint_manifest_scripts() {
+        let tmp = setup_integration("test_lint_manifest_scripts");
+        write_file(&tmp.local.join("script.sh"), "");
+        write_file(&tmp.local.join("script.bat"), "");
+        let contents = "\
+steps:
+  - run:
+    - { src: script.sh }
+    - { src: script.sh, prefix: sh }
+    - { src: script.bat }
+";
+        let manifest = parse_manifest_str(contents, &tmp.local).unwrap();
+
+        assert_eq!(lint_manifest(&manifest), vec![
+            Problem::warning(Some(1), String::from("script script.sh isn't \
+                executable, so it needs a prefix (e.g. sh) or to be made \
+                executable with chmod +x")),
+        ]);
+    }
+
+    #[test]
+    fn test_lint_manifest_destinations() {
+        let contents = "\
+tags: [ dotfiles ]
+steps:
+  - copy: [ { src: ., dst: ~/.bashrc }, { src: ., dst: ~/.bashrc } ]
+    tags: [ linux ]
+  - copy: [ { src: ., dst: ~/.bashrc } ]
+    tags: [ windows ]
+  - copy: [ { src: ., dst: ~/.bashrc } ]
+    tags: [ linux, macos ]
+  - copy: [ { src: ., dst: ~/.bashrc } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new(".")).unwrap();
+
+        assert_eq!(lint_manifest(&manifest), vec![
+            Problem::error(Some(1), String::from("destination ~/.bashrc is \
+                installed more than once")),
+            Problem::error(Some(3), String::from("destination ~/.bashrc is \
+                also installed by step 1")),
+            Problem::error(Some(4), String::from("destination ~/.bashrc is \
+                also installed by step 1")),
+        ]);
+    }
+
+    #[test]
+    fn test_lint_manifest_tags() {
+        let contents = "\
+exclusive_tags: [ [ linux, windows, bsd ] ]
+steps:
+  - tags: [ linux ]
+  - tags: [ windows, linux ]
+  - tags: [ lnux ]
+";
+        let manifest = parse_manifest_str(contents, Path::new(".")).unwrap();
+
+        assert_eq!(lint_manifest(&manifest), vec![
+            Problem::warning(Some(1), String::from("step doesn't have any \
+                commands")),
+            Problem::warning(Some(2), String::from("step doesn't have any \
+                commands")),
+            Problem::warning(Some(3), String::from("step doesn't have any \
+                commands")),
+            Problem::warning(Some(3), String::from("tag lnux isn't used by any \
+                other step (did you mean linux?)")),
+            Problem::warning(None, String::from("tag bsd in exclusive_tags \
+                isn't used by any step")),
+        ]);
+    }
+
+    #[test]
+    fn test_lint_is_absolute_src() {
+        assert_eq!(is_absolute_src("~/.bashrc"), true);
+        assert_eq!(is_absolute_src("/etc/hosts"), true);
+        assert_eq!(is_absolute_src("C:\\bashrc"), true);
+        assert_eq!(is_absolute_src("%USERPROFILE%\\bashrc"), true);
+        assert_eq!(is_absolute_src("dir/bashrc"), false);
+        assert_eq!(is_absolute_src("c"), false);
+    }
+}
@@ -2,19 +2,100 @@
 //!
 //! ```
 //! copy_file("foo", "~/foo");
+//! copy_file_preserving_mtime("foo", "~/foo");
 //! link_file("bar", "~/bar");
-//! run_command("echo 'Hello world'");
+//! link_file_relative("bar", "~/bar");
+//! hard_link_file("baz", "~/baz");
+//! run_command("echo 'Hello world'", Path::new("."), None, None, None);
 //! ```
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use serde::Deserialize;
 use shellexpand::tilde;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
 use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
 #[cfg(target_family = "unix")]
-use std::os::unix::fs::symlink;
-use std::path::{PathBuf, absolute};
-use std::process::Command;
+use std::os::unix::fs::{PermissionsExt, symlink};
+#[cfg(target_family = "unix")]
+use std::os::unix::process::CommandExt;
+#[cfg(target_family = "windows")]
+use std::os::windows::io::AsRawHandle;
+use std::path::{Component, Path, PathBuf, absolute};
+use std::process::{Child, Command, ExitStatus, Stdio};
+#[cfg(target_family = "windows")]
+use std::ptr;
+use std::sync::OnceLock;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use super::backup::default_quarantine;
+use super::exit::Error;
+use super::manifest::Shell;
+use tempfile::Builder;
+#[cfg(target_family = "windows")]
+use windows_sys::Win32::Foundation::{CloseHandle, HANDLE};
+#[cfg(target_family = "windows")]
+use windows_sys::Win32::System::JobObjects::{AssignProcessToJobObject,
+    CreateJobObjectW, TerminateJobObject};
+
+/// The indentation of each line of output from a command run with
+/// [`run_command`], which places it beneath the command's step
+const OUTPUT_INDENT: &str = "  ";
+
+/// How often a command run with [`run_command`] is checked for having exited
+/// or timed out while its output is read
+pub const OUTPUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// What happens to existing files that are replaced, set once by
+/// [`set_replaced_files`]
+static REPLACED_FILES: OnceLock<ReplacedFiles> = OnceLock::new();
+
+/// What happens to an existing file on the local machine when it's replaced by
+/// a copy or link
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplacedFiles {
+    /// The file is deleted
+    #[default]
+    Delete,
+
+    /// The file is moved to the system trash
+    Trash,
+
+    /// The file is moved into the quarantine directory
+    Quarantine,
+}
+
+/// The command used to install files in locations that the current user can't
+/// write to, set once by [`set_escalation`]
+static ESCALATION: OnceLock<String> = OnceLock::new();
+
+/// A file system entry that's installed at a destination
+enum Entry<'a> {
+    /// A copy of a file, optionally with a modification time
+    Copy { src: &'a str, mtime: Option<SystemTime> },
+
+    /// A symbolic link with a target
+    #[cfg(target_family = "unix")]
+    Symlink(&'a Path),
 
-/// Copies the contents of a file to another file
+    /// A hard link to a file
+    HardLink(&'a str),
+}
+
+/// The output of a command that was captured instead of being printed
+#[derive(Debug, Default, PartialEq)]
+pub struct CapturedOutput {
+    /// The contents of the command's stdout
+    pub stdout: String,
+
+    /// The contents of the command's stderr
+    pub stderr: String,
+}
+
+/// Copies the contents and permissions of a file to another file
 ///
 /// Tildes are expanded if present and the destination file is overwritten if
 /// necessary.
@@ -23,6 +104,24 @@ use std::process::Command;
 /// copy_file("foo", "~/foo");
 /// ```
 pub fn copy_file(src: &str, dst: &str) -> Result<()> {
+    copy_file_with(src, dst, false)
+}
+
+/// Copies the contents, permissions, and modification time of a file to another
+/// file
+///
+/// Tildes are expanded if present and the destination file is overwritten if
+/// necessary.
+///
+/// ```
+/// copy_file_preserving_mtime("foo", "~/foo");
+/// ```
+pub fn copy_file_preserving_mtime(src: &str, dst: &str) -> Result<()> {
+    copy_file_with(src, dst, true)
+}
+
+/// Copies a file to another file, optionally preserving its modification time
+fn copy_file_with(src: &str, dst: &str, preserve_mtime: bool) -> Result<()> {
     let src_abs = absolute(src).with_context(|| {
         format!("Failed to make {} absolute", src)
     })?;
@@ -31,9 +130,16 @@ pub fn copy_file(src: &str, dst: &str) -> Result<()> {
     })?;
     if src_abs == dst_abs { return Ok(()); }
 
+    let mtime = if preserve_mtime {
+        Some(fs::metadata(src).and_then(|x| x.modified()).with_context(|| {
+            format!("Failed to read modification time of {}", src)
+        })?)
+    } else {
+        None
+    };
+
     let _dst = prepare_path(dst)?;
-    fs::copy(src, _dst)?;
-    Ok(())
+    install_atomically(&_dst, Entry::Copy { src, mtime })
 }
 
 /// Creates a symbolic link to a file
@@ -55,8 +161,7 @@ pub fn link_file(src: &str, dst: &str) -> Result<()> {
     if src_abs == dst_abs { return Ok(()); }
 
     let _dst = prepare_path(dst)?;
-    symlink(src_abs, _dst)?;
-    Ok(())
+    install_atomically(&_dst, Entry::Symlink(&src_abs))
 }
 #[cfg(not(target_family = "unix"))]
 pub fn link_file(src: &str, dst: &str) -> Result<()> {
@@ -69,12 +174,163 @@ pub fn link_file(src: &str, dst: &str) -> Result<()> {
     if src_abs == dst_abs { return Ok(()); }
 
     let _dst = prepare_path(dst)?;
-    fs::hard_link(src, _dst)?;
-    Ok(())
+    install_atomically(&_dst, Entry::HardLink(src))
 }
 
-/// Creates the parent directories of a path, deletes the file if it exists, and
-/// returns the path with tildes expanded
+/// Creates a symbolic link to a file with a target that's relative to the
+/// link's directory
+///
+/// Tildes are expanded if present and the destination file is overwritten if
+/// necessary. On non-Unix platforms, a hard link will be created instead.
+///
+/// ```
+/// link_file_relative("bar", "~/bar");
+/// ```
+#[cfg(target_family = "unix")]
+pub fn link_file_relative(src: &str, dst: &str) -> Result<()> {
+    let src_abs = absolute(src).with_context(|| {
+        format!("Failed to make {} absolute", src)
+    })?;
+    let dst_abs = absolute(dst).with_context(|| {
+        format!("Failed to make {} absolute", dst)
+    })?;
+    if src_abs == dst_abs { return Ok(()); }
+
+    let target = relative_target(src, dst)?;
+    let _dst = prepare_path(dst)?;
+    install_atomically(&_dst, Entry::Symlink(&target))
+}
+#[cfg(not(target_family = "unix"))]
+pub fn link_file_relative(src: &str, dst: &str) -> Result<()> {
+    link_file(src, dst)
+}
+
+/// Creates a hard link to a file
+///
+/// Tildes are expanded if present and the destination file is overwritten if
+/// necessary.
+///
+/// ```
+/// hard_link_file("baz", "~/baz");
+/// ```
+pub fn hard_link_file(src: &str, dst: &str) -> Result<()> {
+    let src_abs = absolute(src).with_context(|| {
+        format!("Failed to make {} absolute", src)
+    })?;
+    let dst_abs = absolute(dst).with_context(|| {
+        format!("Failed to make {} absolute", dst)
+    })?;
+    if src_abs == dst_abs { return Ok(()); }
+
+    let _dst = prepare_path(dst)?;
+    install_atomically(&_dst, Entry::HardLink(src))
+}
+
+/// Returns the path of a file relative to the directory of a link to it
+///
+/// Tildes are expanded if present. The paths are compared without resolving
+/// symbolic links, and an absolute path is returned if they share no common
+/// prefix (e.g. if they're on different drives).
+///
+/// ```
+/// // With a current directory of /home/user/dotfiles
+/// assert_eq!(relative_target("bashrc", "~/.bashrc")?,
+///            PathBuf::from("dotfiles/bashrc"));
+/// ```
+pub fn relative_target(src: &str, dst: &str) -> Result<PathBuf> {
+    let src_abs = absolute(src).with_context(|| {
+        format!("Failed to make {} absolute", src)
+    })?;
+    let dst_abs = absolute(tilde(dst).as_ref()).with_context(|| {
+        format!("Failed to make {} absolute", dst)
+    })?;
+    let src_abs = normalize_path(&src_abs);
+    let dst_abs = normalize_path(&dst_abs);
+    let dir = dst_abs.parent().unwrap_or(&dst_abs);
+
+    let common = src_abs.components().zip(dir.components())
+        .take_while(|(a, b)| a == b).count();
+    if common == 0 { return Ok(src_abs); }
+
+    let mut target = PathBuf::new();
+    for _ in dir.components().skip(common) {
+        target.push("..");
+    }
+    target.extend(src_abs.components().skip(common));
+    Ok(target)
+}
+
+/// Returns the path on the local machine that a path in a manifest refers to,
+/// expanding tildes and resolving relative paths relative to `base_dir`
+///
+/// ```
+/// assert_eq!(resolve_local_path("bashrc", Path::new("/home/user/dotfiles")),
+///            "/home/user/dotfiles/bashrc");
+/// ```
+pub fn resolve_local_path(path: &str, base_dir: &Path) -> String {
+    base_dir.join(tilde(path).as_ref()).to_string_lossy().to_string()
+}
+
+/// Removes the `.` and `..` components of a path without accessing the file
+/// system
+///
+/// ```
+/// assert_eq!(normalize_path(Path::new("/a/./b/../c")), PathBuf::from("/a/c"));
+/// ```
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {},
+            Component::ParentDir => { result.pop(); },
+            _ => result.push(component),
+        }
+    }
+    result
+}
+
+/// Sets what happens to existing files on the local machine that are replaced
+/// by copies and links for the rest of the program
+///
+/// Existing files are deleted if this is never called, and only the first call
+/// has any effect.
+///
+/// ```
+/// set_replaced_files(ReplacedFiles::Trash);
+/// ```
+pub fn set_replaced_files(mode: ReplacedFiles) {
+    let _ = REPLACED_FILES.set(mode);
+}
+
+/// Sets the command (e.g. `sudo`) used to install files in locations that the
+/// current user can't write to for the rest of the program
+///
+/// Such files fail to install if this is never called, and only the first call
+/// has any effect. Escalation is only supported on Unix.
+///
+/// ```
+/// set_escalation("sudo");
+/// ```
+pub fn set_escalation(command: &str) {
+    let _ = ESCALATION.set(command.to_owned());
+}
+
+/// Returns the command set by [`set_escalation`], if any
+///
+/// ```
+/// if let Some(command) = escalation() {
+///     println!("Escalating with {}", command);
+/// }
+/// ```
+pub fn escalation() -> Option<&'static str> {
+    ESCALATION.get().map(|x| x.as_str())
+}
+
+/// Creates the parent directories of a path and returns the path with tildes
+/// expanded
+///
+/// Existing files are left in place to be atomically replaced, unless
+/// [`set_replaced_files`] says to move them to the trash or quarantine.
 ///
 /// ```
 /// prepare_path("~/foo");
@@ -82,44 +338,596 @@ pub fn link_file(src: &str, dst: &str) -> Result<()> {
 fn prepare_path(path: &str) -> Result<PathBuf> {
     let _dst: PathBuf = (&tilde(path).to_mut()).into();
     if let Some(_path) = _dst.parent() {
-        fs::create_dir_all(_path).with_context(|| {
-            format!("Failed to create parent directories of {}", path)
+        let result = match (fs::create_dir_all(_path), escalation()) {
+            (Err(why), Some(command)) if is_permission_denied(&why) => {
+                run_escalated(command, "mkdir -p \"$1\"", &[_path.as_os_str()])
+            },
+            (result, _) => result.map_err(|x| x.into()),
+        };
+        result.with_context(|| {
+            Error::LocalFile(format!("Failed to create parent directories of \
+                                      {}", path))
         })?;
     }
-    if fs::symlink_metadata(&_dst).is_ok() {
+    let mode = REPLACED_FILES.get().copied().unwrap_or_default();
+    if mode != ReplacedFiles::Delete && fs::symlink_metadata(&_dst).is_ok() {
         // Check for existing files, including broken symlinks
-        fs::remove_file(&_dst).with_context(|| {
+        remove_existing(&_dst, mode).with_context(|| {
             format!("Failed to remove existing file at {}", path)
         })?;
     }
     Ok(_dst)
 }
 
-/// Executes a command using `sh` on Unix and `cmd` on Windows
+/// Creates an entry at a temporary path in a destination's directory and then
+/// renames it over the destination
+///
+/// The destination is never left missing or partially written, even if the
+/// program is interrupted. The temporary file is removed if anything fails. If
+/// the current user isn't allowed to write to the destination's directory, the
+/// entry is installed with the command set by [`set_escalation`] instead.
+fn install_atomically(dst: &Path, entry: Entry) -> Result<()> {
+    let dir = parent_dir(dst);
+    let result = Builder::new().prefix(".coliru-")
+        .make_in(dir, |x| create_entry(&entry, x));
+    let temp = match (result, escalation()) {
+        (Ok(temp), _) => temp,
+        (Err(why), Some(command)) if is_permission_denied(&why) => {
+            return install_escalated(dst, &entry, command);
+        },
+        (Err(why), _) => return Err(why.into()),
+    };
+
+    match (temp.persist(dst), escalation()) {
+        (Ok(_), _) => Ok(()),
+        (Err(why), Some(command)) if is_permission_denied(&why.error) => {
+            install_escalated(dst, &entry, command)
+        },
+        (Err(why), _) => Err(why.error).with_context(|| {
+            Error::LocalFile(format!("Failed to replace {}", dst.display()))
+        }),
+    }
+}
+
+/// Creates an entry at a path
+fn create_entry(entry: &Entry, path: &Path) -> io::Result<()> {
+    match entry {
+        Entry::Copy { src, mtime } => {
+            fs::copy(src, path)?;
+            if let Some(mtime) = mtime {
+                fs::File::options().write(true).open(path)?
+                    .set_modified(*mtime)?;
+            }
+            Ok(())
+        },
+        #[cfg(target_family = "unix")]
+        Entry::Symlink(target) => symlink(target, path),
+        Entry::HardLink(src) => fs::hard_link(src, path),
+    }
+}
+
+/// Installs an entry at a destination by running a shell script with an
+/// escalation command (e.g. `sudo`)
+///
+/// The entry is created at a temporary path in the destination's directory
+/// and then renamed over the destination, as in [`install_atomically`].
+#[cfg(target_family = "unix")]
+fn install_escalated(dst: &Path, entry: &Entry, command: &str) -> Result<()> {
+    let name = dst.file_name().unwrap_or_default().to_string_lossy();
+    let temp_name = format!(".coliru-{}-{}", std::process::id(), name);
+    let temp = parent_dir(dst).join(temp_name);
+
+    let (script, source): (String, &OsStr) = match entry {
+        Entry::Copy { src, mtime } => {
+            let mode = fs::metadata(src).with_context(|| {
+                format!("Failed to read {}", src)
+            })?.permissions().mode() & 0o7777;
+            let touch = if mtime.is_some() {
+                " && touch -r \"$1\" \"$2\""
+            } else {
+                ""
+            };
+            (format!("cp \"$1\" \"$2\" && chmod {:o} \"$2\"{}", mode,
+                     touch), src.as_ref())
+        },
+        Entry::Symlink(target) => {
+            (String::from("ln -s \"$1\" \"$2\""), target.as_os_str())
+        },
+        Entry::HardLink(src) => {
+            (String::from("ln \"$1\" \"$2\""), src.as_ref())
+        },
+    };
+    let script = format!("{{ {} && mv -f \"$2\" \"$3\"; }} || \
+                          {{ rm -f \"$2\"; exit 1; }}", script);
+    run_escalated(command, &script, &[source, temp.as_os_str(),
+                                      dst.as_os_str()])
+        .with_context(|| format!("Failed to replace {}", dst.display()))
+}
+#[cfg(not(target_family = "unix"))]
+fn install_escalated(_dst: &Path, _entry: &Entry, command: &str) -> Result<()> {
+    bail!("Installing files with {} is only supported on Unix", command);
+}
+
+/// Runs a shell script with an escalation command (e.g. `sudo`), passing it a
+/// list of positional arguments
 ///
 /// ```
-/// run_command("echo 'Hello world'");
+/// run_escalated("sudo", "mkdir -p \"$1\"", &[OsStr::new("/etc/foo")])?;
 /// ```
-pub fn run_command(command: &str) -> Result<()>
-{
-    let mut cmd;
-    if cfg!(target_family = "unix") {
-        cmd = Command::new("sh");
-        cmd.args(["-c", command]);
-    } else {
-        cmd = Command::new("cmd.exe");
-        cmd.args(["/C", command]);
+fn run_escalated(command: &str, script: &str, args: &[&OsStr]) -> Result<()> {
+    let mut parts = command.split_whitespace();
+    let Some(program) = parts.next() else {
+        bail!("The escalation command is empty");
+    };
+
+    let mut cmd = Command::new(program);
+    cmd.args(parts).args(["sh", "-c", script, "sh"]).args(args);
+    let status = execute_command(&mut cmd, None)?;
+    if !status.success() {
+        bail!("{} terminated unsuccessfully: {}", command, status);
     }
+    Ok(())
+}
 
-    let status = cmd.status().with_context(|| {
-        format!("Failed to execute {:?}", cmd)
-    })?;
+/// Checks whether an I/O error occurred because the current user isn't allowed
+/// to access a file
+fn is_permission_denied(why: &io::Error) -> bool {
+    why.kind() == io::ErrorKind::PermissionDenied
+}
+
+/// Returns the directory containing a path, which is `.` for bare file names
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Removes an existing file by deleting it, moving it to the system trash, or
+/// moving it into the quarantine directory
+///
+/// Symbolic links are always deleted, since they don't contain any data.
+fn remove_existing(path: &Path, mode: ReplacedFiles) -> Result<()> {
+    let is_file = fs::symlink_metadata(path)?.is_file();
+    match mode {
+        ReplacedFiles::Trash if is_file => trash::delete(path)?,
+        ReplacedFiles::Quarantine if is_file => {
+            quarantine_file(path, &default_quarantine()?)?;
+        },
+        _ => fs::remove_file(path)?,
+    }
+    Ok(())
+}
+
+/// Moves a file into a quarantine directory and returns its new location
+///
+/// The file is moved to a subdirectory named after the current Unix time,
+/// under its absolute path without the root (e.g. `/home/user/.bashrc` is moved
+/// to `quarantine/1728561845/home/user/.bashrc`).
+///
+/// ```
+/// let moved = quarantine_file(Path::new("/home/user/.bashrc"),
+///                             &default_quarantine()?)?;
+/// ```
+pub fn quarantine_file(path: &Path, quarantine: &Path) -> Result<PathBuf> {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    let relative: PathBuf = absolute(path)?.components().filter(|x| {
+        matches!(x, Component::Normal(_))
+    }).collect();
+    let target = quarantine.join(time.to_string()).join(relative);
+
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent).with_context(|| {
+            format!("Failed to create {}", parent.display())
+        })?;
+    }
+    // Files can't be renamed across file systems, so copy them instead
+    if fs::rename(path, &target).is_err() {
+        fs::copy(path, &target).with_context(|| {
+            format!("Failed to copy {} to {}", path.display(), target.display())
+        })?;
+        fs::remove_file(path)?;
+    }
+    Ok(target)
+}
+
+/// Executes a command in a working directory using `sh` on Unix and `cmd` on
+/// Windows, or using `shell` if it's provided
+///
+/// Each line of the command's stdout and stderr is indented and printed as soon
+/// as it's written, or captured instead of printed if `capture` is provided.
+/// The command is killed along with the processes that it started if it runs
+/// for longer than `timeout`, so it doesn't receive signals from the terminal
+/// (e.g. Ctrl+C) and can't read from it if a timeout is provided.
+///
+/// ```
+/// run_command("echo 'Hello world'", Path::new("."), None, None, None);
+/// ```
+pub fn run_command(command: &str, dir: &Path, shell: Option<Shell>,
+                   timeout: Option<Duration>,
+                   capture: Option<&mut CapturedOutput>) -> Result<()> {
+
+    let (program, args) = match shell {
+        Some(shell) => shell.invocation(),
+        None if cfg!(target_family = "unix") => Shell::Sh.invocation(),
+        None => Shell::Cmd.invocation(),
+    };
+    let mut cmd = Command::new(program);
+    cmd.args(args).arg(command).current_dir(dir);
+    if timeout.is_some() { ProcessTree::prepare(&mut cmd); }
+
+    let status = execute_command_indented(&mut cmd, timeout, capture)?;
     if !status.success() {
-        bail!("Process terminated unsuccessfully: {}", status);
+        return Err(anyhow!(Error::Script(format!(
+            "Process terminated unsuccessfully: {}", status))));
     }
     Ok(())
 }
 
+/// Quotes a string so that a POSIX shell treats it as a single word
+///
+/// ```
+/// assert_eq!(quote("it's"), "'it'\\''s'");
+/// ```
+pub fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "'\\''"))
+}
+
+/// Quotes a string so that `shell` treats it as a single word
+///
+/// ```
+/// assert_eq!(quote_for("it's", Shell::Powershell), "'it''s'");
+/// ```
+pub fn quote_for(text: &str, shell: Shell) -> String {
+    match shell {
+        Shell::Sh | Shell::Bash | Shell::Zsh | Shell::Fish => quote(text),
+        Shell::Cmd => format!("\"{}\"", text.replace('"', "\"\"")),
+        Shell::Powershell | Shell::Pwsh => {
+            format!("'{}'", text.replace('\'', "''"))
+        },
+    }
+}
+
+/// Quotes a string for `shell` like [`quote_for`] if it contains any characters
+/// that the shell would interpret (e.g. spaces or `&&`)
+///
+/// ```
+/// assert_eq!(quote_if_needed("linux,macos", Shell::Sh), "linux,macos");
+/// assert_eq!(quote_if_needed("a b", Shell::Cmd), "\"a b\"");
+/// ```
+pub fn quote_if_needed(text: &str, shell: Shell) -> String {
+    let plain_chars = match shell {
+        Shell::Sh | Shell::Bash | Shell::Zsh => "^,.-_+:@/=",
+        // Fish redirects stderr with a leading caret
+        Shell::Fish => ",.-_+:@/=",
+        // The command prompt escapes characters with carets and splits
+        // arguments at commas, while PowerShell treats commas as arrays
+        Shell::Cmd | Shell::Powershell | Shell::Pwsh => ".-_",
+    };
+    let is_plain = !text.is_empty() && text.chars().all(|c| {
+        c.is_alphanumeric() || plain_chars.contains(c)
+    });
+    if is_plain { text.to_owned() } else { quote_for(text, shell) }
+}
+
+/// Returns a command line that executes a command with a shell when it's run
+/// by a POSIX shell (e.g. over SSH), or the command itself if `shell` is None
+///
+/// ```
+/// assert_eq!(shell_command_line("ls", Some(Shell::Cmd)), "cmd.exe /C 'ls'");
+/// ```
+pub fn shell_command_line(command: &str, shell: Option<Shell>) -> String {
+    let Some(shell) = shell else { return command.to_owned() };
+    let (program, args) = shell.invocation();
+    format!("{} {} {}", program, args.join(" "), quote(command))
+}
+
+/// Returns a command line that sets environment variables before executing a
+/// command, using the syntax of the shell that executes it
+///
+/// Each value is quoted for that shell. Values used with the Windows command
+/// prompt can't contain double quotes, which it has no way to escape.
+///
+/// ```
+/// let env = BTreeMap::from([(String::from("A"), String::from("it's"))]);
+/// assert_eq!(with_env("ls", &env, Shell::Sh)?,
+///            "export A='it'\\''s' && ls");
+/// ```
+pub fn with_env(command: &str, env: &BTreeMap<String, String>, shell: Shell) ->
+    Result<String> {
+
+    let mut line = String::new();
+    for (name, value) in env {
+        match shell {
+            Shell::Sh | Shell::Bash | Shell::Zsh | Shell::Fish => {
+                line.push_str(&format!("export {}={} && ", name, quote(value)));
+            },
+            Shell::Cmd => {
+                if value.contains('"') {
+                    bail!("The value of {} contains a double quote, which the \
+                           command prompt can't escape", name);
+                }
+                line.push_str(&format!("set \"{}={}\" && ", name, value));
+            },
+            Shell::Powershell | Shell::Pwsh => {
+                line.push_str(&format!("$env:{}={}; ", name,
+                                       quote_for(value, shell)));
+            },
+        }
+    }
+    line.push_str(command);
+    Ok(line)
+}
+
+/// Wraps a shell command so that it's executed as another user with `sudo`,
+/// using `sh` unless `shell` is provided
+///
+/// ```
+/// assert_eq!(as_user("echo hi", "svc", None),
+///            "sudo -u 'svc' sh -c 'echo hi'");
+/// ```
+pub fn as_user(command: &str, user: &str, shell: Option<Shell>) -> String {
+    let command = shell_command_line(command, shell.or(Some(Shell::Sh)));
+    format!("sudo -u {} {}", quote(user), command)
+}
+
+/// Executes a Command and returns its exit status, capturing its stdout and
+/// stderr if `capture` is provided
+///
+/// ```
+/// let mut cmd = Command::new("ls");
+/// let mut output = CapturedOutput::default();
+/// let status = execute_command(&mut cmd, Some(&mut output))?;
+/// ```
+pub fn execute_command(cmd: &mut Command, capture: Option<&mut CapturedOutput>)
+    -> Result<ExitStatus> {
+
+    match capture {
+        None => cmd.status().with_context(|| {
+            format!("Failed to execute {:?}", cmd)
+        }),
+        Some(captured) => {
+            let output = cmd.output().with_context(|| {
+                format!("Failed to execute {:?}", cmd)
+            })?;
+            captured.stdout.push_str(&String::from_utf8_lossy(&output.stdout));
+            captured.stderr.push_str(&String::from_utf8_lossy(&output.stderr));
+            Ok(output.status)
+        },
+    }
+}
+
+/// Executes a Command with some input written to its stdin and returns its exit
+/// status, capturing its stdout and stderr if `capture` is provided
+///
+/// ```
+/// let mut cmd = Command::new("cat");
+/// let status = execute_command_with_input(&mut cmd, b"foo", None)?;
+/// ```
+pub fn execute_command_with_input(cmd: &mut Command, input: &[u8],
+                                  capture: Option<&mut CapturedOutput>) ->
+    Result<ExitStatus> {
+
+    cmd.stdin(Stdio::piped());
+    if capture.is_some() {
+        cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    let mut child = cmd.spawn().with_context(|| {
+        format!("Failed to execute {:?}", cmd)
+    })?;
+    // Dropping stdin closes it, so that the command doesn't wait for more input
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input).with_context(|| {
+            format!("Failed to write to stdin of {:?}", cmd)
+        })?;
+    }
+
+    match capture {
+        None => child.wait().with_context(|| {
+            format!("Failed to execute {:?}", cmd)
+        }),
+        Some(captured) => {
+            let output = child.wait_with_output().with_context(|| {
+                format!("Failed to execute {:?}", cmd)
+            })?;
+            captured.stdout.push_str(&String::from_utf8_lossy(&output.stdout));
+            captured.stderr.push_str(&String::from_utf8_lossy(&output.stderr));
+            Ok(output.status)
+        },
+    }
+}
+
+/// Executes a Command and returns its exit status, indenting each line of its
+/// stdout and stderr and capturing them if `capture` is provided
+///
+/// Output that isn't captured is printed line by line while the command runs.
+/// Output is no longer read once the command exits, even if processes that it
+/// started in the background keep its stdout or stderr open. If the command
+/// runs for longer than `timeout`, it's killed along with the processes that it
+/// started and a script failure is returned instead of its exit status. On
+/// Unix, those processes are only killed if the command leads its own process
+/// group, like the commands executed by [`run_command`] with a timeout.
+///
+/// ```
+/// let mut cmd = Command::new("ls");
+/// let status = execute_command_indented(&mut cmd, None, None)?;
+/// ```
+pub fn execute_command_indented(cmd: &mut Command, timeout: Option<Duration>,
+                                capture: Option<&mut CapturedOutput>) ->
+    Result<ExitStatus> {
+
+    let mut child = cmd.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn()
+        .with_context(|| format!("Failed to execute {:?}", cmd))?;
+    let tree = timeout.map(|_| ProcessTree::new(&child));
+
+    // Each stream is read on its own thread, which exits once the stream is
+    // closed or nothing is receiving its lines anymore
+    let (sender, receiver) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        let sender = sender.clone();
+        thread::spawn(move || send_lines(stdout, false, sender));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        let sender = sender.clone();
+        thread::spawn(move || send_lines(stderr, true, sender));
+    }
+    drop(sender);
+
+    let deadline = timeout.map(|x| Instant::now() + x);
+    let mut capture = capture;
+    let mut status = None;
+    loop {
+        match receiver.recv_timeout(OUTPUT_POLL_INTERVAL) {
+            Ok((is_stderr, line)) => {
+                write_line(&line, is_stderr, capture.as_deref_mut());
+            },
+            Err(RecvTimeoutError::Disconnected) => break,
+            // The command exited before the previous interval and any output
+            // that it wrote has been read
+            Err(RecvTimeoutError::Timeout) if status.is_some() => break,
+            Err(RecvTimeoutError::Timeout) => {},
+        }
+
+        if status.is_none() {
+            status = child.try_wait().with_context(|| {
+                format!("Failed to execute {:?}", cmd)
+            })?;
+        }
+        if status.is_none() && deadline.is_some_and(|x| Instant::now() >= x) {
+            if let Some(tree) = &tree { tree.kill(); }
+            // Errors killing a command that just exited can be ignored
+            let _ = child.kill();
+            let _ = child.wait();
+            let message = format!("Timed out after {:?}",
+                                  timeout.unwrap_or_default());
+            return Err(anyhow!(Error::Script(message)));
+        }
+    }
+
+    match status {
+        Some(status) => Ok(status),
+        None => child.wait().with_context(|| {
+            format!("Failed to execute {:?}", cmd)
+        }),
+    }
+}
+
+/// Writes an indented line of a command's output to stdout or stderr, or to
+/// `capture` if it's provided
+pub fn write_line(line: &[u8], is_stderr: bool,
+                  capture: Option<&mut CapturedOutput>) {
+    if let Some(captured) = capture {
+        let text = if is_stderr {
+            &mut captured.stderr
+        } else {
+            &mut captured.stdout
+        };
+        text.push_str(OUTPUT_INDENT);
+        text.push_str(&String::from_utf8_lossy(line));
+        return;
+    }
+
+    let mut sink: Box<dyn Write> = if is_stderr {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    };
+    // Errors writing to stdout/stderr can't be reported anywhere else
+    let _ = sink.write_all(OUTPUT_INDENT.as_bytes())
+        .and_then(|_| sink.write_all(line))
+        .and_then(|_| sink.flush());
+}
+
+/// Reads the lines of a stream as they're written and sends them along with
+/// whether the stream is stderr, until the stream is closed or the receiver is
+/// dropped
+///
+/// A line break is added to the last line if it doesn't already end with one.
+fn send_lines<R: Read>(stream: R, is_stderr: bool,
+                       sender: Sender<(bool, Vec<u8>)>) {
+    let mut reader = BufReader::new(stream);
+    let mut line = vec![];
+    while matches!(reader.read_until(b'\n', &mut line), Ok(n) if n > 0) {
+        if !line.ends_with(b"\n") { line.push(b'\n'); }
+        if sender.send((is_stderr, line.clone())).is_err() { return; }
+        line.clear();
+    }
+}
+
+/// A command's process along with every process that it starts, so that
+/// background processes can be killed with a command that timed out
+///
+/// On Unix, the command is the leader of its own process group. On Windows, it
+/// is assigned to a job object, which the processes it starts also belong to.
+struct ProcessTree {
+    #[cfg(target_family = "unix")]
+    pid: u32,
+
+    #[cfg(target_family = "windows")]
+    job: HANDLE,
+}
+
+impl ProcessTree {
+    /// Prepares a Command so that the processes it starts are tracked by
+    /// [`ProcessTree::new`] once it's spawned
+    fn prepare(cmd: &mut Command) {
+        #[cfg(target_family = "unix")]
+        cmd.process_group(0);
+        #[cfg(target_family = "windows")]
+        let _ = cmd;
+    }
+
+    /// Tracks the processes started by a Command, which are only found if it
+    /// was prepared with [`ProcessTree::prepare`]
+    #[cfg(target_family = "unix")]
+    fn new(child: &Child) -> Self {
+        ProcessTree { pid: child.id() }
+    }
+
+    /// Tracks the processes started by a Command
+    ///
+    /// Processes started before the command is assigned to the job object
+    /// aren't tracked.
+    #[cfg(target_family = "windows")]
+    fn new(child: &Child) -> Self {
+        // The job can't be used if it fails to be created or assigned, in which
+        // case only the command itself is killed
+        unsafe {
+            let job = CreateJobObjectW(ptr::null(), ptr::null());
+            if !job.is_null() &&
+                AssignProcessToJobObject(job, child.as_raw_handle()) == 0 {
+
+                CloseHandle(job);
+                return ProcessTree { job: ptr::null_mut() };
+            }
+            ProcessTree { job }
+        }
+    }
+
+    /// Kills every process in the tree
+    fn kill(&self) {
+        // Errors killing processes that already exited can be ignored
+        #[cfg(target_family = "unix")]
+        unsafe {
+            libc::kill(-(self.pid as libc::pid_t), libc::SIGKILL);
+        }
+        #[cfg(target_family = "windows")]
+        if !self.job.is_null() {
+            unsafe { TerminateJobObject(self.job, 1); }
+        }
+    }
+}
+
+#[cfg(target_family = "windows")]
+impl Drop for ProcessTree {
+    fn drop(&mut self) {
+        if !self.job.is_null() {
+            unsafe { CloseHandle(self.job); }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -173,6 +981,98 @@ mod tests {
         assert_eq!(contents, "old contents of foo");
     }
 
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_copy_file_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = setup_integration("test_copy_file_permissions");
+
+        let src = &tmp.local.join("foo");
+        let dst = &tmp.local.join("bar");
+        write_file(src, "contents of foo");
+        write_file(dst, "contents of bar");
+        fs::set_permissions(src, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let result = copy_file(src.to_str().unwrap(), dst.to_str().unwrap());
+
+        let mode = fs::metadata(dst).unwrap().permissions().mode();
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(mode & 0o777, 0o755);
+    }
+
+    #[test]
+    fn test_copy_file_no_temporary_files() {
+        let tmp = setup_integration("test_copy_file_no_temporary_files");
+
+        let src = &tmp.local.join("foo");
+        let dst = &tmp.local.join("dir").join("bar");
+        write_file(src, "contents of foo");
+        fs::create_dir_all(tmp.local.join("dir")).unwrap();
+        write_file(dst, "contents of bar");
+
+        let result = copy_file(src.to_str().unwrap(), dst.to_str().unwrap());
+
+        let contents = fs::read_to_string(dst).unwrap();
+        let files: Vec<_> = fs::read_dir(tmp.local.join("dir")).unwrap()
+            .map(|x| x.unwrap().file_name()).collect();
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(contents, "contents of foo");
+        assert_eq!(files, ["bar"]);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_install_escalated_basic() {
+        let tmp = setup_integration("test_install_escalated_basic");
+
+        let src = tmp.local.join("foo");
+        let copied = tmp.local.join("dir").join("copied");
+        let linked = tmp.local.join("dir").join("linked");
+        write_file(&src, "contents of foo");
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o751)).unwrap();
+        fs::create_dir_all(tmp.local.join("dir")).unwrap();
+        write_file(&copied, "contents of copied");
+        let entry = Entry::Copy { src: src.to_str().unwrap(), mtime: None };
+
+        let result1 = install_escalated(&copied, &entry, "env");
+        let result2 = install_escalated(&linked, &Entry::Symlink(&src), "env");
+
+        let mode = fs::metadata(&copied).unwrap().permissions().mode();
+        let files: Vec<_> = fs::read_dir(tmp.local.join("dir")).unwrap()
+            .map(|x| x.unwrap().file_name()).collect();
+        assert_eq!(result1.is_ok(), true);
+        assert_eq!(result2.is_ok(), true);
+        assert_eq!(fs::read_to_string(&copied).unwrap(), "contents of foo");
+        assert_eq!(mode & 0o777, 0o751);
+        assert_eq!(fs::read_link(&linked).unwrap(), src);
+        assert_eq!(files.len(), 2);
+        let result = install_escalated(&copied, &entry, "false");
+        assert_eq!(result.is_err(), true);
+    }
+
+    #[test]
+    fn test_copy_file_preserving_mtime_basic() {
+        let tmp = setup_integration("test_copy_file_preserving_mtime_basic");
+
+        let src = &tmp.local.join("foo");
+        let dst = &tmp.local.join("bar");
+        let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(
+            1728561845);
+        write_file(src, "contents of foo");
+        fs::File::options().write(true).open(src).unwrap().set_modified(mtime)
+            .unwrap();
+
+        let result = copy_file_preserving_mtime(src.to_str().unwrap(),
+                                                dst.to_str().unwrap());
+
+        let contents = fs::read_to_string(dst).unwrap();
+        let dst_mtime = fs::metadata(dst).unwrap().modified().unwrap();
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(contents, "contents of foo");
+        assert_eq!(dst_mtime, mtime);
+    }
+
     #[test]
     #[cfg(target_family = "unix")]
     fn test_copy_file_existing_broken_symlink() {
@@ -257,6 +1157,61 @@ mod tests {
         assert_eq!(contents, "new contents of foo");
     }
 
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_link_file_relative_basic() {
+        let tmp = setup_integration("test_link_file_relative_basic");
+
+        let src = &tmp.local.join("dotfiles").join("foo");
+        let dst = &tmp.local.join("dir").join("bar");
+        fs::create_dir_all(tmp.local.join("dotfiles")).unwrap();
+        write_file(src, "old contents of foo");
+
+        let result = link_file_relative(src.to_str().unwrap(),
+                                        dst.to_str().unwrap());
+
+        write_file(src, "new contents of foo");
+        let contents = fs::read_to_string(dst).unwrap();
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(fs::read_link(dst).unwrap(),
+                   PathBuf::from("../dotfiles/foo"));
+        assert_eq!(contents, "new contents of foo");
+    }
+
+    #[test]
+    fn test_relative_target_basic() {
+        let result = relative_target("/a/b/c/foo", "/a/d/bar").unwrap();
+        assert_eq!(result, PathBuf::from("../b/c/foo"));
+
+        let result = relative_target("/a/b/foo", "/a/b/bar").unwrap();
+        assert_eq!(result, PathBuf::from("foo"));
+
+        let result = relative_target("/a/foo", "/a/b/../c/./bar").unwrap();
+        assert_eq!(result, PathBuf::from("../foo"));
+    }
+
+    #[test]
+    fn test_normalize_path_basic() {
+        let result = normalize_path(Path::new("/a/./b/../c"));
+
+        assert_eq!(result, PathBuf::from("/a/c"));
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_resolve_local_path_basic() {
+        let base_dir = Path::new("/home/user/dotfiles");
+        let home = tilde("~").to_string();
+
+        let relative = resolve_local_path("bashrc", base_dir);
+        let absolute = resolve_local_path("/etc/hosts", base_dir);
+        let tilde = resolve_local_path("~/.bashrc", base_dir);
+
+        assert_eq!(relative, "/home/user/dotfiles/bashrc");
+        assert_eq!(absolute, "/etc/hosts");
+        assert_eq!(tilde, format!("{}/.bashrc", home));
+    }
+
     #[test]
     #[cfg(target_family = "unix")]
     fn test_link_file_existing_broken_symlink() {
@@ -299,7 +1254,7 @@ mod tests {
         let dir = PathBuf::from("tests/.temp/ssh/test_link_file_relative_source");
         fs::create_dir_all(&dir).unwrap();
 
-        let src = absolute(&dir.join("foo")).unwrap();
+        let src = absolute(dir.join("foo")).unwrap();
         let src_rel = "tests/.temp/ssh/test_link_file_relative_source/foo";
         let dst = &dir.join("dir1").join("dir2").join("bar");
         write_file(&src, "old contents of foo");
@@ -316,6 +1271,42 @@ mod tests {
         fs::remove_dir_all(&dir).unwrap();
     }
 
+    #[test]
+    fn test_hard_link_file_existing_file() {
+        let tmp = setup_integration("test_hard_link_file_existing_file");
+
+        let src = &tmp.local.join("foo");
+        let dst = &tmp.local.join("bar");
+        write_file(src, "old contents of foo");
+        write_file(dst, "old contents of bar");
+
+        let result = hard_link_file(src.to_str().unwrap(),
+                                    dst.to_str().unwrap());
+
+        write_file(src, "new contents of foo");
+        let contents = fs::read_to_string(dst).unwrap();
+        let is_symlink = fs::symlink_metadata(dst).unwrap().is_symlink();
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(contents, "new contents of foo");
+        assert_eq!(is_symlink, false);
+    }
+
+    #[test]
+    fn test_quarantine_file_basic() {
+        let tmp = setup_integration("test_quarantine_file_basic");
+
+        let path = tmp.local.join("foo");
+        let quarantine = tmp.local.join("quarantine");
+        write_file(&path, "contents of foo");
+
+        let result = quarantine_file(&path, &quarantine).unwrap();
+
+        assert_eq!(path.exists(), false);
+        assert_eq!(result.starts_with(&quarantine), true);
+        assert_eq!(result.ends_with("test_quarantine_file_basic/foo"), true);
+        assert_eq!(fs::read_to_string(result).unwrap(), "contents of foo");
+    }
+
     #[test]
     #[cfg(target_family = "unix")]
     fn test_run_command_successful() {
@@ -324,7 +1315,8 @@ mod tests {
         let src = &tmp.local.join("foo");
         write_file(src, "exit 0");
 
-        let result = run_command(&format!("sh {}", src.to_str().unwrap()));
+        let cmd = format!("sh {}", src.to_str().unwrap());
+        let result = run_command(&cmd, &tmp.local, None, None, None);
 
         assert_eq!(result.is_ok(), true);
     }
@@ -337,7 +1329,8 @@ mod tests {
         let src = &tmp.local.join("foo.bat");
         write_file(src, "exit 0");
 
-        let result = run_command(src.to_str().unwrap());
+        let result = run_command(src.to_str().unwrap(), &tmp.local, None, None,
+                                 None);
 
         assert_eq!(result.is_ok(), true);
     }
@@ -350,11 +1343,13 @@ mod tests {
         let src = &tmp.local.join("foo");
         write_file(src, "exit 2");
 
-        let result = run_command(&format!("sh {}", src.to_str().unwrap()));
+        let cmd = format!("sh {}", src.to_str().unwrap());
+        let result = run_command(&cmd, &tmp.local, None, None, None);
 
-        assert_eq!(result.is_ok(), false);
-        assert_eq!(result.unwrap_err().to_string(),
+        let why = result.unwrap_err();
+        assert_eq!(why.to_string(),
             "Process terminated unsuccessfully: exit status: 2");
+        assert_eq!(matches!(why.downcast_ref(), Some(Error::Script(_))), true);
     }
 
     #[test]
@@ -365,13 +1360,117 @@ mod tests {
         let src = &tmp.local.join("foo.bat");
         write_file(src, "exit 1");
 
-        let result = run_command(src.to_str().unwrap());
+        let result = run_command(src.to_str().unwrap(), &tmp.local, None, None,
+                                 None);
 
         assert_eq!(result.is_ok(), false);
         assert_eq!(result.unwrap_err().to_string(),
             "Process terminated unsuccessfully: exit code: 1");
     }
 
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_run_command_dir() {
+        let tmp = setup_integration("test_run_command_dir");
+
+        write_file(&tmp.local.join("foo"), "echo foo > bar");
+
+        let result = run_command("sh foo", &tmp.local, None, None, None);
+
+        let contents = fs::read_to_string(tmp.local.join("bar")).unwrap();
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(contents, "foo\n");
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_run_command_capture() {
+        let tmp = setup_integration("test_run_command_capture");
+
+        let src = &tmp.local.join("foo");
+        write_file(src, "echo stdout; echo stderr >&2; exit 2");
+
+        let mut captured = CapturedOutput::default();
+        let cmd = format!("sh {}", src.to_str().unwrap());
+        let result = run_command(&cmd, &tmp.local, None, None,
+                                 Some(&mut captured));
+
+        assert_eq!(result.is_ok(), false);
+        assert_eq!(captured.stdout, "  stdout\n");
+        assert_eq!(captured.stderr, "  stderr\n");
+    }
+
+    #[test]
+    fn test_as_user() {
+        let result = as_user("echo 'it works'", "svc", None);
+        let pwsh = as_user("echo hi", "svc", Some(Shell::Pwsh));
+
+        assert_eq!(result, "sudo -u 'svc' sh -c 'echo '\\''it works'\\'''");
+        assert_eq!(pwsh, "sudo -u 'svc' pwsh -NoProfile -Command 'echo hi'");
+    }
+
+    #[test]
+    fn test_with_env() {
+        let env = BTreeMap::from([(String::from("A"), String::from("it's")),
+                                  (String::from("B"), String::from("2"))]);
+
+        let sh = with_env("ls", &env, Shell::Sh).unwrap();
+        let pwsh = with_env("ls", &env, Shell::Pwsh).unwrap();
+        let cmd = with_env("dir", &env, Shell::Cmd).unwrap();
+        let none = with_env("ls", &BTreeMap::new(), Shell::Sh).unwrap();
+
+        assert_eq!(sh, "export A='it'\\''s' && export B='2' && ls");
+        assert_eq!(pwsh, "$env:A='it''s'; $env:B='2'; ls");
+        assert_eq!(cmd, "set \"A=it's\" && set \"B=2\" && dir");
+        assert_eq!(none, "ls");
+    }
+
+    #[test]
+    fn test_with_env_cmd_quote() {
+        let env = BTreeMap::from([(String::from("A"), String::from("\""))]);
+
+        let result = with_env("dir", &env, Shell::Cmd);
+
+        assert_eq!(result.unwrap_err().to_string(),
+                   "The value of A contains a double quote, which the command \
+                    prompt can't escape");
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_run_command_timeout() {
+        let mut captured = CapturedOutput::default();
+        let timeout = Some(Duration::from_millis(200));
+        let dir = Path::new(".");
+        let result = run_command("echo started; sleep 10", dir, None, timeout,
+                                 Some(&mut captured));
+        let background = run_command("sleep 10 & echo done", dir, None,
+                                     timeout, None);
+
+        let why = result.unwrap_err();
+        assert_eq!(why.to_string(), "Timed out after 200ms");
+        assert_eq!(matches!(why.downcast_ref(), Some(Error::Script(_))), true);
+        assert_eq!(captured.stdout, "  started\n");
+        assert_eq!(background.is_ok(), true);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_run_command_timeout_kills_background() {
+        let tmp = setup_integration(
+            "test_run_command_timeout_kills_background");
+
+        let late = tmp.local.join("late");
+        let command = format!("(sleep 1; echo late > {}) & sleep 10",
+                              quote(&late.to_string_lossy()));
+        let result = run_command(&command, &tmp.local, None,
+                                 Some(Duration::from_millis(200)), None);
+        thread::sleep(Duration::from_millis(1500));
+
+        assert_eq!(result.is_err(), true);
+        assert_eq!(late.exists(), false);
+    }
+
     #[test]
     #[cfg(target_family = "unix")]
     fn test_run_command_arguments() {
@@ -382,7 +1481,8 @@ mod tests {
         write_file(src, &format!("echo $@ > {}", dst.to_str().unwrap()));
 
         let result = run_command(&format!("sh {} arg1 arg2",
-                                          src.to_str().unwrap()));
+                                          src.to_str().unwrap()),
+                                 &tmp.local, None, None, None);
 
         let contents = fs::read_to_string(dst).unwrap();
         assert_eq!(result.is_ok(), true);
@@ -399,7 +1499,8 @@ mod tests {
         write_file(src, &format!("echo %* > {}", dst.to_str().unwrap()));
 
         let result = run_command(&format!("{} arg1 arg2",
-                                          src.to_str().unwrap()));
+                                          src.to_str().unwrap()),
+                                 &tmp.local, None, None, None);
 
         let contents = fs::read_to_string(dst).unwrap();
         assert_eq!(result.is_ok(), true);
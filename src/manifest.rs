@@ -1,20 +1,247 @@
 //! Coliru manifest parsing and tag matching
 
-use anyhow::Result;
-use serde::Deserialize;
-use serde_yaml;
-use std::collections::HashSet;
+use super::stow::stow_links;
+use super::tag_expr::{TagExpr, selected_group_tags};
+use anyhow::{Context, Result, bail};
+use clap::ValueEnum;
+use serde::{Deserialize, Deserializer};
+use std::collections::{BTreeMap, HashSet};
+use std::env;
+use std::fmt;
 use std::fs::read_to_string;
+use std::io::Read;
+use std::mem::take;
 use std::path::{Path, PathBuf};
 
+/// The XDG base directory placeholders that may appear in destinations, along
+/// with their default values on Unix and the variables they fall back to on
+/// Windows
+const XDG_DIRS: [(&str, &str, &str); 3] = [
+    ("XDG_CONFIG_HOME", "~/.config", "APPDATA"),
+    ("XDG_DATA_HOME", "~/.local/share", "APPDATA"),
+    ("XDG_STATE_HOME", "~/.local/state", "LOCALAPPDATA"),
+];
+
+/// The Windows known folders that may appear as placeholders in destinations,
+/// which are located in the user's profile directory
+const KNOWN_FOLDERS: [&str; 6] = ["Desktop", "Documents", "Downloads", "Music",
+                                  "Pictures", "Videos"];
+
+/// The Windows environment variables that are expanded in destinations
+const WINDOWS_VARS: [&str; 3] = ["APPDATA", "LOCALAPPDATA", "USERPROFILE"];
+
+/// How a link command installs its file on the local machine
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMode {
+    /// A symbolic link, or a hard link on non-Unix platforms
+    #[default]
+    Symlink,
+
+    /// A hard link
+    Hardlink,
+
+    /// A copy of the file
+    Copy,
+}
+
+/// The line endings that a copy command converts its file to
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    /// Unix line endings (`\n`)
+    Lf,
+
+    /// Windows line endings (`\r\n`)
+    Crlf,
+
+    /// The line endings of the machine that the file is installed on
+    Native,
+}
+
+/// The shell that a run command's script is executed with
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    /// The POSIX shell
+    Sh,
+
+    /// Bash
+    Bash,
+
+    /// Zsh
+    Zsh,
+
+    /// Fish
+    Fish,
+
+    /// The Windows command prompt
+    Cmd,
+
+    /// Windows PowerShell
+    Powershell,
+
+    /// PowerShell 7 or later
+    Pwsh,
+}
+impl Shell {
+    /// Returns the program and arguments that execute a command with the shell
+    ///
+    /// ```
+    /// let (program, args) = Shell::Pwsh.invocation();
+    /// ```
+    pub fn invocation(self) -> (&'static str, &'static [&'static str]) {
+        match self {
+            Shell::Sh => ("sh", &["-c"]),
+            Shell::Bash => ("bash", &["-c"]),
+            Shell::Zsh => ("zsh", &["-c"]),
+            Shell::Fish => ("fish", &["-c"]),
+            Shell::Cmd => ("cmd.exe", &["/C"]),
+            Shell::Powershell => {
+                ("powershell.exe", &["-NoProfile", "-Command"])
+            },
+            Shell::Pwsh => ("pwsh", &["-NoProfile", "-Command"]),
+        }
+    }
+}
+
 /// The options for a copy or link command
 #[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RawCopyLinkOptions")]
 pub struct CopyLinkOptions {
-    /// The source file (relative to the parent manifest file)
+    /// The source file (relative to the parent manifest file), or a label
+    /// listing the concatenated source files
     pub src: String,
 
     /// The destination path (relative to the parent manifest file)
     pub dst: String,
+
+    /// How a link command installs its file, which overrides the default mode
+    pub link_type: Option<LinkMode>,
+
+    /// Whether copies of the file keep the source file's modification time
+    pub preserve_mtime: bool,
+
+    /// Whether a link command's symbolic link has a target relative to the
+    /// destination's directory instead of an absolute target
+    pub relative: bool,
+
+    /// Whether a link command creates a symbolic link on the remote machine,
+    /// pointing to a copy of its source file in `~/.coliru`, instead of a copy
+    /// when installing over SSH
+    pub remote: bool,
+
+    /// The line endings that a copy command's file is converted to, if any
+    pub eol: Option<LineEnding>,
+
+    /// The source files that a copy command concatenates in order, which is
+    /// empty unless `src` is a list
+    pub concat: Vec<String>,
+
+    /// The values of the placeholders (e.g. `{{ facts.os }}`) in a templated
+    /// copy command's file, keyed by name, or None if it isn't templated
+    pub template: Option<BTreeMap<String, String>>,
+}
+impl CopyLinkOptions {
+    /// Creates a copy command without any options, such as the one that sends
+    /// a script to another machine before it's run
+    ///
+    /// ```
+    /// let copy = CopyLinkOptions::script("script.sh", "script.sh");
+    /// ```
+    pub fn script(src: &str, dst: &str) -> CopyLinkOptions {
+        CopyLinkOptions {
+            src: src.to_owned(),
+            dst: dst.to_owned(),
+            link_type: None,
+            preserve_mtime: false,
+            relative: false,
+            remote: false,
+            eol: None,
+            concat: vec![],
+            template: None,
+        }
+    }
+
+    /// Returns how a link command installs its file, given the mode used for
+    /// link commands without a type
+    ///
+    /// ```
+    /// let mode = link.link_mode(LinkMode::Symlink);
+    /// ```
+    pub fn link_mode(&self, default: LinkMode) -> LinkMode {
+        self.link_type.unwrap_or(default)
+    }
+
+    /// Returns the paths of the command's source files
+    ///
+    /// ```
+    /// for src in copy.sources() {
+    ///     println!("{}", src);
+    /// }
+    /// ```
+    pub fn sources(&self) -> Vec<&str> {
+        if self.concat.is_empty() {
+            vec![&self.src]
+        } else {
+            self.concat.iter().map(|x| x.as_str()).collect()
+        }
+    }
+}
+
+/// The source of a copy or link command as written in a manifest
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawSource {
+    /// A single source file
+    File(String),
+
+    /// Source files that are concatenated in order
+    Files(Vec<String>),
+}
+
+/// The options for a copy or link command as written in a manifest
+#[derive(Deserialize)]
+struct RawCopyLinkOptions {
+    src: RawSource,
+    dst: String,
+    #[serde(default, rename = "type")]
+    link_type: Option<LinkMode>,
+    #[serde(default)]
+    preserve_mtime: bool,
+    #[serde(default)]
+    relative: bool,
+    #[serde(default)]
+    remote: bool,
+    #[serde(default)]
+    eol: Option<LineEnding>,
+    #[serde(default)]
+    template: bool,
+}
+impl TryFrom<RawCopyLinkOptions> for CopyLinkOptions {
+    type Error = String;
+
+    fn try_from(raw: RawCopyLinkOptions) -> Result<Self, Self::Error> {
+        let (src, concat) = match raw.src {
+            RawSource::File(src) => (src, vec![]),
+            RawSource::Files(files) if files.is_empty() => {
+                return Err(format!("Copy command for {} has an empty list of \
+                                    sources", raw.dst));
+            },
+            RawSource::Files(files) => (files.join(" + "), files),
+        };
+        Ok(CopyLinkOptions {
+            src,
+            dst: raw.dst,
+            link_type: raw.link_type,
+            preserve_mtime: raw.preserve_mtime,
+            relative: raw.relative,
+            remote: raw.remote,
+            eol: raw.eol,
+            concat,
+            template: raw.template.then(BTreeMap::new),
+        })
+    }
 }
 
 /// The options for a run command
@@ -30,6 +257,572 @@ pub struct RunOptions {
     /// The optional shell command postfix
     #[serde(default)]
     pub postfix: String,
+
+    /// The number of seconds after which the script is killed
+    #[serde(default)]
+    pub timeout: Option<u64>,
+
+    /// The number of times the script is re-run if it fails
+    #[serde(default)]
+    pub retries: u32,
+
+    /// The number of seconds to wait before re-running the script
+    #[serde(default)]
+    pub retry_delay: u64,
+
+    /// The user that the script is executed as, if not the current user
+    #[serde(default)]
+    pub user: Option<String>,
+
+    /// The shell that the script is executed with, if not the default shell
+    #[serde(default)]
+    pub shell: Option<Shell>,
+
+    /// Whether the script is deleted from the remote machine after it succeeds
+    #[serde(default)]
+    pub cleanup: bool,
+
+    /// The environment variables that the script is executed with
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+
+    /// The files or directories that the script creates, which are checked by
+    /// the verify subcommand
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub creates: Vec<String>,
+}
+
+/// A list of strings as written in a manifest, which may be a single string
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawList {
+    /// A single string
+    One(String),
+
+    /// A list of strings
+    Many(Vec<String>),
+}
+
+/// Deserializes a string or a list of strings into a list of strings
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) ->
+    Result<Vec<String>, D::Error> {
+
+    Ok(match RawList::deserialize(deserializer)? {
+        RawList::One(x) => vec![x],
+        RawList::Many(x) => x,
+    })
+}
+
+/// The options for a block command
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct BlockOptions {
+    /// The file containing the block's contents (relative to the parent
+    /// manifest file)
+    pub src: String,
+
+    /// The file that the block is merged into
+    pub dst: String,
+
+    /// The comment prefix of the destination's marker lines (`#` by default)
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+/// The options for a patch command
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct PatchOptions {
+    /// The unified diff to apply (relative to the parent manifest file)
+    pub src: String,
+
+    /// The existing file that the diff is applied to
+    pub dst: String,
+}
+
+/// The options for a packages command
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(from = "RawPackageOptions")]
+pub struct PackageOptions {
+    /// The name of the package
+    pub name: String,
+
+    /// The name of the package for apt, if it's different
+    pub apt: Option<String>,
+
+    /// The name of the package for dnf, if it's different
+    pub dnf: Option<String>,
+
+    /// The name of the package for pacman, if it's different
+    pub pacman: Option<String>,
+
+    /// The name of the package for zypper, if it's different
+    pub zypper: Option<String>,
+}
+
+/// The options for a packages command as written in a manifest
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum RawPackageOptions {
+    /// The name of a package that's the same for every package manager
+    Name(String),
+
+    /// A package name with overrides for specific package managers
+    Table {
+        name: String,
+        #[serde(default)]
+        apt: Option<String>,
+        #[serde(default)]
+        dnf: Option<String>,
+        #[serde(default)]
+        pacman: Option<String>,
+        #[serde(default)]
+        zypper: Option<String>,
+    },
+}
+impl From<RawPackageOptions> for PackageOptions {
+    fn from(raw: RawPackageOptions) -> Self {
+        match raw {
+            RawPackageOptions::Name(name) => PackageOptions {
+                name,
+                apt: None,
+                dnf: None,
+                pacman: None,
+                zypper: None,
+            },
+            RawPackageOptions::Table { name, apt, dnf, pacman, zypper } => {
+                PackageOptions { name, apt, dnf, pacman, zypper }
+            },
+        }
+    }
+}
+
+/// The options for a stow command
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct StowOptions {
+    /// The stow package directory (relative to the parent manifest file)
+    pub src: String,
+
+    /// The directory that the package's files are linked into (`~` by default)
+    #[serde(default = "default_stow_target")]
+    pub dst: String,
+
+    /// Whether a `dot-` prefix in the package is replaced with a `.`, like GNU
+    /// Stow's `--dotfiles` option
+    #[serde(default)]
+    pub dotfiles: bool,
+}
+
+/// Returns the default target directory of a stow command
+fn default_stow_target() -> String {
+    String::from("~")
+}
+
+/// The options for a repo command
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct RepoOptions {
+    /// The URL of the Git repository
+    pub url: String,
+
+    /// The directory that the repository is cloned into
+    pub dst: String,
+
+    /// The branch, tag, or commit that's checked out, if not the repository's
+    /// default branch
+    #[serde(default)]
+    pub rev: Option<String>,
+}
+
+/// The options for a fetch command
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RawFetchOptions")]
+pub struct FetchOptions {
+    /// The URL that the file is downloaded from
+    pub url: String,
+
+    /// The path that the file is downloaded to
+    pub dst: String,
+
+    /// The expected SHA-256 hash of the file, in lowercase hexadecimal
+    pub sha256: String,
+
+    /// The octal permissions of the file (e.g. `755`), if not the default
+    pub mode: Option<String>,
+}
+
+/// The permissions of a fetch command as written in a manifest, which may be
+/// a string or a number
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawMode {
+    /// Permissions written as a string (e.g. `'0755'`)
+    Text(String),
+
+    /// Permissions written as a number (e.g. `755`)
+    Number(u64),
+}
+
+/// The options for a fetch command as written in a manifest
+#[derive(Deserialize)]
+struct RawFetchOptions {
+    url: String,
+    dst: String,
+    sha256: String,
+    #[serde(default)]
+    mode: Option<RawMode>,
+}
+impl TryFrom<RawFetchOptions> for FetchOptions {
+    type Error = String;
+
+    fn try_from(raw: RawFetchOptions) -> Result<Self, Self::Error> {
+        let sha256 = raw.sha256.to_lowercase();
+        if sha256.len() != 64 ||
+            !sha256.chars().all(|x| x.is_ascii_hexdigit()) {
+            return Err(format!("Fetch command for {} has an invalid sha256, \
+                                which must be 64 hexadecimal digits", raw.url));
+        }
+        let mode = raw.mode.map(|x| match x {
+            RawMode::Text(mode) => mode,
+            RawMode::Number(mode) => mode.to_string(),
+        });
+        if let Some(mode) = &mode {
+            if !(3..=4).contains(&mode.len()) ||
+                !mode.chars().all(|x| ('0'..='7').contains(&x)) {
+                return Err(format!("Fetch command for {} has an invalid mode \
+                                    {}, which must be 3 or 4 octal digits",
+                                   raw.url, mode));
+            }
+        }
+        Ok(FetchOptions { url: raw.url, dst: raw.dst, sha256, mode })
+    }
+}
+
+/// The options for a brew command
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RawBrewOptions")]
+pub enum BrewOptions {
+    /// A Homebrew formula (e.g. `ripgrep`)
+    Formula(String),
+
+    /// A Homebrew cask (e.g. `firefox`)
+    Cask(String),
+
+    /// A Brewfile (relative to the parent manifest file) that's installed with
+    /// `brew bundle`
+    Brewfile(String),
+}
+
+/// The options for a brew command as written in a manifest
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum RawBrewOptions {
+    /// The name of a formula
+    Name(String),
+
+    /// A single `formula`, `cask`, or `brewfile` field
+    Table {
+        #[serde(default)]
+        formula: Option<String>,
+        #[serde(default)]
+        cask: Option<String>,
+        #[serde(default)]
+        brewfile: Option<String>,
+    },
+}
+impl TryFrom<RawBrewOptions> for BrewOptions {
+    type Error = String;
+
+    fn try_from(raw: RawBrewOptions) -> Result<Self, Self::Error> {
+        match raw {
+            RawBrewOptions::Name(name) => Ok(BrewOptions::Formula(name)),
+            RawBrewOptions::Table { formula: Some(name), cask: None,
+                                    brewfile: None } => {
+                Ok(BrewOptions::Formula(name))
+            },
+            RawBrewOptions::Table { formula: None, cask: Some(name),
+                                    brewfile: None } => {
+                Ok(BrewOptions::Cask(name))
+            },
+            RawBrewOptions::Table { formula: None, cask: None,
+                                    brewfile: Some(path) } => {
+                Ok(BrewOptions::Brewfile(path))
+            },
+            RawBrewOptions::Table { .. } => {
+                Err(String::from("Brew command must have exactly one of \
+                                  formula, cask, or brewfile"))
+            },
+        }
+    }
+}
+
+/// The scope of a systemd command's unit
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SystemdScope {
+    /// A unit of the user's service manager, managed with `systemctl --user`
+    #[default]
+    User,
+
+    /// A unit of the system's service manager, managed with `sudo systemctl`
+    System,
+}
+impl fmt::Display for SystemdScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SystemdScope::User => write!(f, "user"),
+            SystemdScope::System => write!(f, "system"),
+        }
+    }
+}
+
+/// The options for a systemd command
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RawSystemdOptions")]
+pub struct SystemdOptions {
+    /// The unit file (relative to the parent manifest file) that's installed,
+    /// if the unit isn't already provided by a package
+    pub src: Option<String>,
+
+    /// The name of the unit (e.g. `syncthing.service`)
+    pub unit: String,
+
+    /// The scope of the unit
+    pub scope: SystemdScope,
+
+    /// Whether the unit is enabled
+    pub enable: bool,
+
+    /// Whether the unit is started
+    pub start: bool,
+}
+
+/// Returns true, the default of a systemd command's `enable` and `start` fields
+fn default_true() -> bool {
+    true
+}
+
+/// The options for a systemd command as written in a manifest
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSystemdOptions {
+    #[serde(default)]
+    src: Option<String>,
+    #[serde(default)]
+    unit: Option<String>,
+    #[serde(default)]
+    scope: SystemdScope,
+    #[serde(default = "default_true")]
+    enable: bool,
+    #[serde(default = "default_true")]
+    start: bool,
+}
+impl TryFrom<RawSystemdOptions> for SystemdOptions {
+    type Error = String;
+
+    fn try_from(raw: RawSystemdOptions) -> Result<Self, Self::Error> {
+        let file_name = raw.src.as_deref()
+            .and_then(|x| Path::new(x).file_name())
+            .map(|x| x.to_string_lossy().to_string());
+        let Some(unit) = raw.unit.or(file_name) else {
+            return Err(String::from("Systemd command must have a src or \
+                                     unit"));
+        };
+        if raw.src.is_none() && !raw.enable && !raw.start {
+            return Err(format!("Systemd command for {} must install, enable, \
+                                or start its unit", unit));
+        }
+        Ok(SystemdOptions {
+            src: raw.src,
+            unit,
+            scope: raw.scope,
+            enable: raw.enable,
+            start: raw.start,
+        })
+    }
+}
+
+/// The value of a defaults command, whose type is written to the defaults
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum DefaultsValue {
+    /// A boolean, written with `-bool`
+    Bool(bool),
+
+    /// An integer, written with `-int`
+    Int(i64),
+
+    /// A floating point number, written with `-float`
+    Float(f64),
+
+    /// A string, written with `-string`
+    String(String),
+}
+impl fmt::Display for DefaultsValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DefaultsValue::Bool(value) => write!(f, "{}", value),
+            DefaultsValue::Int(value) => write!(f, "{}", value),
+            DefaultsValue::Float(value) => write!(f, "{}", value),
+            DefaultsValue::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// The options for a defaults command
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DefaultsOptions {
+    /// The domain that the value is written to (e.g. `com.apple.dock` or
+    /// `NSGlobalDomain`)
+    pub domain: String,
+
+    /// The key that the value is written to
+    pub key: String,
+
+    /// The value
+    pub value: DefaultsValue,
+}
+
+/// The options for an extension command
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RawExtensionOptions")]
+pub enum ExtensionOptions {
+    /// A VS Code extension, installed with a VS Code compatible `program`
+    /// (e.g. `code` or `codium`)
+    VsCode {
+        /// The extension's ID (e.g. `ms-python.python`)
+        id: String,
+
+        /// The editor's command line program
+        program: String,
+    },
+
+    /// A JetBrains plugin, installed with an IDE's launcher `program` (e.g.
+    /// `idea` or `pycharm`)
+    JetBrains {
+        /// The plugin's ID (e.g. `IdeaVIM`)
+        id: String,
+
+        /// The IDE's launcher
+        program: String,
+    },
+}
+impl ExtensionOptions {
+    /// Returns the ID of the command's extension or plugin
+    ///
+    /// ```
+    /// assert_eq!(extension.id(), "ms-python.python");
+    /// ```
+    pub fn id(&self) -> &str {
+        match self {
+            ExtensionOptions::VsCode { id, .. } => id,
+            ExtensionOptions::JetBrains { id, .. } => id,
+        }
+    }
+}
+
+/// The options for an extension command as written in a manifest
+#[derive(Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+enum RawExtensionOptions {
+    /// The ID of a VS Code extension
+    Id(String),
+
+    /// A single `vscode` or `jetbrains` field and an optional program
+    Table {
+        #[serde(default)]
+        vscode: Option<String>,
+        #[serde(default)]
+        jetbrains: Option<String>,
+        #[serde(default)]
+        program: Option<String>,
+    },
+}
+impl TryFrom<RawExtensionOptions> for ExtensionOptions {
+    type Error = String;
+
+    fn try_from(raw: RawExtensionOptions) -> Result<Self, Self::Error> {
+        match raw {
+            RawExtensionOptions::Id(id) => {
+                let program = String::from("code");
+                Ok(ExtensionOptions::VsCode { id, program })
+            },
+            RawExtensionOptions::Table { vscode: Some(id), jetbrains: None,
+                                         program } => {
+                let program = program.unwrap_or_else(|| String::from("code"));
+                Ok(ExtensionOptions::VsCode { id, program })
+            },
+            RawExtensionOptions::Table { vscode: None, jetbrains: Some(id),
+                                         program } => {
+                let program = program.unwrap_or_else(|| String::from("idea"));
+                Ok(ExtensionOptions::JetBrains { id, program })
+            },
+            RawExtensionOptions::Table { .. } => {
+                Err(String::from("Extension command must have exactly one of \
+                                  vscode or jetbrains"))
+            },
+        }
+    }
+}
+
+/// The options for a cron command
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "RawCronOptions")]
+pub struct CronOptions {
+    /// The name that identifies the job's line in the crontab
+    pub name: String,
+
+    /// The job's schedule (e.g. `0 3 * * *` or `@daily`), which is empty if
+    /// the job is being removed
+    pub schedule: String,
+
+    /// The job's shell command, which is empty if the job is being removed
+    pub command: String,
+
+    /// Whether the job is removed from the crontab instead of added
+    pub remove: bool,
+}
+
+/// The options for a cron command as written in a manifest
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawCronOptions {
+    name: String,
+    #[serde(default)]
+    schedule: Option<String>,
+    #[serde(default)]
+    command: Option<String>,
+    #[serde(default)]
+    remove: bool,
+}
+impl TryFrom<RawCronOptions> for CronOptions {
+    type Error = String;
+
+    fn try_from(raw: RawCronOptions) -> Result<Self, Self::Error> {
+        let (schedule, command) = match (raw.schedule, raw.command) {
+            (None, None) if raw.remove => (String::new(), String::new()),
+            (Some(schedule), Some(command)) if !raw.remove => {
+                (schedule, command)
+            },
+            _ => return Err(format!("Cron command for {} must have a schedule \
+                                     and command, or remove: true", raw.name)),
+        };
+        let fields = schedule.split_whitespace().count();
+        if !raw.remove && fields != 5 && !schedule.starts_with('@') {
+            return Err(format!("Cron command for {} has an invalid schedule \
+                                {}, which must have 5 fields or start with @",
+                               raw.name, schedule));
+        }
+        let text = [&raw.name, &schedule, &command];
+        if text.iter().any(|x| x.contains(['\n', '\r'])) {
+            return Err(format!("Cron command for {} contains a line break",
+                               raw.name));
+        }
+        Ok(CronOptions {
+            name: raw.name,
+            schedule,
+            command,
+            remove: raw.remove,
+        })
+    }
 }
 
 /// A manifest step
@@ -43,21 +836,114 @@ pub struct Step {
     #[serde(default)]
     pub link: Vec<CopyLinkOptions>,
 
+    /// The step's block commands
+    #[serde(default)]
+    pub block: Vec<BlockOptions>,
+
+    /// The step's patch commands
+    #[serde(default)]
+    pub patch: Vec<PatchOptions>,
+
+    /// The step's packages commands
+    #[serde(default)]
+    pub packages: Vec<PackageOptions>,
+
+    /// The step's brew commands
+    #[serde(default)]
+    pub brew: Vec<BrewOptions>,
+
+    /// The step's repo commands
+    #[serde(default)]
+    pub repo: Vec<RepoOptions>,
+
+    /// The step's fetch commands
+    #[serde(default)]
+    pub fetch: Vec<FetchOptions>,
+
+    /// The step's systemd commands
+    #[serde(default)]
+    pub systemd: Vec<SystemdOptions>,
+
+    /// The step's defaults commands
+    #[serde(default)]
+    pub defaults: Vec<DefaultsOptions>,
+
+    /// The step's extension commands
+    #[serde(default)]
+    pub extensions: Vec<ExtensionOptions>,
+
+    /// The step's cron commands
+    #[serde(default)]
+    pub cron: Vec<CronOptions>,
+
     /// The step's run commands
     #[serde(default)]
     pub run: Vec<RunOptions>,
 
+    /// The step's stow packages, which are replaced by link commands when the
+    /// manifest is parsed
+    #[serde(default)]
+    pub stow: Vec<StowOptions>,
+
+    /// Whether the step may be executed concurrently with the steps next to it
+    /// that are also parallel when `--jobs` is greater than one
+    #[serde(default)]
+    pub parallel: bool,
+
     /// The step's tags
     #[serde(default)]
     pub tags: Vec<String>,
 }
 
+/// The overrides for a specific machine
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HostOverrides {
+    /// Additional steps to execute after the manifest's steps
+    #[serde(default)]
+    pub steps: Vec<Step>,
+
+    /// Replacement destinations for copy, link, block, patch, repo, and fetch
+    /// commands, keyed by their original destinations
+    #[serde(default)]
+    pub dst: BTreeMap<String, String>,
+
+    /// Values that replace the manifest's template variables with the same
+    /// names
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+}
+
 /// A coliru manifest as it appears in a file, without the base_dir property
 #[derive(Debug, PartialEq, Deserialize)]
 struct RawManifest {
 
     /// The manifest steps
     steps: Vec<Step>,
+
+    /// The shell commands to execute before the first step
+    #[serde(default)]
+    pre_install: Vec<String>,
+
+    /// The shell commands to execute after the last step
+    #[serde(default)]
+    post_install: Vec<String>,
+
+    /// The overrides for specific machines, keyed by hostname
+    #[serde(default)]
+    hosts: BTreeMap<String, HostOverrides>,
+
+    /// The values of the placeholders in templated copy commands, keyed by name
+    #[serde(default)]
+    vars: BTreeMap<String, String>,
+
+    /// The tags that are added to every step
+    #[serde(default)]
+    tags: Vec<String>,
+
+    /// The groups of tags that tag rules must select exactly one tag from
+    #[serde(default)]
+    exclusive_tags: Vec<Vec<String>>,
 }
 
 /// A parsed coliru manifest
@@ -66,103 +952,631 @@ pub struct Manifest {
     /// The manifest steps
     pub steps: Vec<Step>,
 
+    /// The shell commands to execute before the first step
+    pub pre_install: Vec<String>,
+
+    /// The shell commands to execute after the last step
+    pub post_install: Vec<String>,
+
+    /// The overrides for specific machines, keyed by hostname
+    pub hosts: BTreeMap<String, HostOverrides>,
+
+    /// The values of the placeholders in templated copy commands, keyed by name
+    pub vars: BTreeMap<String, String>,
+
+    /// The groups of tags that tag rules must select exactly one tag from
+    pub exclusive_tags: Vec<Vec<String>>,
+
     /// The parent directory of the manifest file
     pub base_dir: PathBuf,
 }
 
-/// Checks if a list of tags matches a list of tag rules
+/// Checks if a list of tags matches a list of tag rules
+///
+/// ```
+/// let rules = ["linux,macos", "system", "!work && !server"];
+/// let tags_1 = ["macos", "system", "user"];
+/// let tags_2 = ["linux", "system", "work"];
+/// assert_eq!(tags_match(&rules, &tags_1), true);
+/// assert_eq!(tags_match(&rules, &tags_2), false);
+/// ```
+fn tags_match<S: AsRef<str>>(rules: &[S], tags: &[S]) -> bool {
+    unmatched_tag_rule(rules, tags).is_none()
+}
+
+/// Returns the first tag rule that a list of tags doesn't satisfy, if any
+///
+/// Rules that can't be parsed are never satisfied.
+///
+/// ```
+/// let rules = ["linux,macos", "system", "^work"];
+/// let tags = ["linux", "system", "work"];
+/// assert_eq!(unmatched_tag_rule(&rules, &tags), Some(&"^work"));
+/// ```
+pub fn unmatched_tag_rule<'a, S: AsRef<str>>(rules: &'a [S], tags: &[S]) ->
+    Option<&'a S> {
+
+    rules.iter().find(|rule| {
+        !TagExpr::parse(rule.as_ref()).is_ok_and(|x| x.eval(tags))
+    })
+}
+
+/// Checks that a list of tag rules is well-formed
+///
+/// Each rule must be a valid expression (see [`TagExpr::parse`]).
+///
+/// ```
+/// validate_tag_rules(&["linux,macos", "^work", "(gui || ssh) && !server"])?;
+/// assert_eq!(validate_tag_rules(&["linux,"]).is_ok(), false);
+/// ```
+pub fn validate_tag_rules<S: AsRef<str>>(rules: &[S]) -> Result<()> {
+    for rule in rules.iter() {
+        TagExpr::parse(rule.as_ref())?;
+    }
+    Ok(())
+}
+
+/// Checks that a list of tag rules selects exactly one tag from each of a
+/// manifest's exclusive tag groups
+///
+/// `detected` tags (e.g. the operating system with `--auto-tags`) count as
+/// selected. See [`selected_group_tags`] for which tags a rule selects.
+///
+/// ```
+/// validate_exclusive_tags(&manifest, &["linux", "^work"], &[])?;
+/// ```
+pub fn validate_exclusive_tags<S: AsRef<str>>(manifest: &Manifest,
+                                              rules: &[S], detected: &[S]) ->
+    Result<()> {
+
+    for group in &manifest.exclusive_tags {
+        let mut selected = selected_group_tags(rules, group);
+        for tag in detected.iter().map(|x| x.as_ref()) {
+            if group.iter().any(|x| x == tag) && !selected.contains(&tag) {
+                selected.push(tag);
+            }
+        }
+        if selected.len() != 1 {
+            let selected_str = if selected.is_empty() {
+                String::from("none")
+            } else {
+                selected.join(", ")
+            };
+            bail!("Exactly one of {} must be selected, but {} are",
+                  group.join(", "), selected_str);
+        }
+    }
+    Ok(())
+}
+
+/// Reads a list of tag rules from a file
+///
+/// Rules are separated by whitespace, which usually means one rule per line.
+/// A line that contains an operator other than `,` or `^` (e.g. `&&` or `(`) is
+/// read as a single rule, so that expressions may contain spaces. Blank lines
+/// are ignored, as is any text following a `#`.
+///
+/// ```
+/// let rules = read_tag_rules_file(Path::new("rules.txt"))?;
+/// ```
+pub fn read_tag_rules_file(path: &Path) -> Result<Vec<String>> {
+    let contents = read_to_string(path)?;
+    Ok(contents.lines().flat_map(|line| {
+        let line = line.split('#').next().unwrap_or_default().trim();
+        if line.contains(['(', ')', '!', '&', '|']) {
+            vec![String::from(line)]
+        } else {
+            line.split_whitespace().map(String::from).collect::<Vec<_>>()
+        }
+    }).collect())
+}
+
+/// Parse a coliru YAML manifest file
+///
+/// ```
+/// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// ```
+pub fn parse_manifest_file(path: &Path) -> Result<Manifest> {
+    let raw_str = read_to_string(path)?;
+    let base_dir = match path.parent() {
+        None => Path::new("."),
+        Some(p) => if p == Path::new("") { Path::new(".") } else { p },
+    };
+
+    parse_manifest_str(&raw_str, base_dir)
+}
+
+/// Parse a coliru YAML manifest from a reader (e.g. stdin), resolving relative
+/// paths against `base_dir`
+///
+/// ```
+/// let manifest = parse_manifest_reader(std::io::stdin(), Path::new("."))?;
+/// ```
+pub fn parse_manifest_reader<R: Read>(mut reader: R, base_dir: &Path) ->
+    Result<Manifest> {
+
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+    parse_manifest_str(&contents, base_dir)
+}
+
+/// Parse a coliru YAML manifest from a string
+///
+/// ```
+/// let manifest = parse_manifest_str("steps: []", Path::new("."))?;
+/// ```
+pub fn parse_manifest_str(contents: &str, base_dir: &Path) -> Result<Manifest> {
+    let raw_manifest = serde_yaml::from_str::<RawManifest>(contents)?;
+
+    let host_steps = raw_manifest.hosts.values().flat_map(|x| x.steps.iter());
+    for step in raw_manifest.steps.iter().chain(host_steps) {
+        if let Some(entry) = step.copy.iter().find(|x| x.link_type.is_some()) {
+            bail!("Copy command for {} has a type, which is only allowed on \
+                   link commands", entry.src);
+        }
+        if let Some(entry) = step.copy.iter().find(|x| x.relative) {
+            bail!("Copy command for {} is relative, which is only allowed on \
+                   link commands", entry.src);
+        }
+        if let Some(entry) = step.copy.iter().find(|x| x.remote) {
+            bail!("Copy command for {} is remote, which is only allowed on \
+                   link commands", entry.src);
+        }
+        if let Some(entry) = step.link.iter().find(|x| x.eol.is_some()) {
+            bail!("Link command for {} has an eol, which is only allowed on \
+                   copy commands", entry.src);
+        }
+        if let Some(entry) = step.link.iter().find(|x| x.template.is_some()) {
+            bail!("Link command for {} is templated, which is only allowed on \
+                   copy commands", entry.src);
+        }
+        if let Some(entry) = step.link.iter().find(|x| !x.concat.is_empty()) {
+            bail!("Link command for {} has multiple sources, which is only \
+                   allowed on copy commands", entry.src);
+        }
+        for entry in &step.run {
+            if let Some(name) = entry.env.keys().find(|x| !is_env_name(x)) {
+                bail!("Run command for {} has an invalid environment variable \
+                       name: {}", entry.src, name);
+            }
+        }
+    }
+
+    if raw_manifest.exclusive_tags.iter().any(|x| x.len() < 2) {
+        bail!("Exclusive tag groups must contain at least two tags");
+    }
+
+    let host_vars = raw_manifest.hosts.values().flat_map(|x| x.vars.keys());
+    if let Some(name) = raw_manifest.vars.keys().chain(host_vars).find(|x| {
+        !is_env_name(x)
+    }) {
+        bail!("Invalid variable name: {}", name);
+    }
+
+    let mut steps = raw_manifest.steps;
+    let mut hosts = raw_manifest.hosts;
+    let host_steps = hosts.values_mut().flat_map(|x| x.steps.iter_mut());
+    for step in steps.iter_mut().chain(host_steps) {
+        for package in take(&mut step.stow) {
+            step.link.extend(stow_links(&package, base_dir)?);
+        }
+        step.tags = with_default_tags(take(&mut step.tags),
+                                      &raw_manifest.tags)?;
+    }
+
+    Ok(Manifest {
+        steps,
+        pre_install: raw_manifest.pre_install,
+        post_install: raw_manifest.post_install,
+        hosts,
+        vars: raw_manifest.vars,
+        exclusive_tags: raw_manifest.exclusive_tags,
+        base_dir: base_dir.to_path_buf(),
+    })
+}
+
+/// Adds a manifest's default tags to a step's tags
+///
+/// A step opts out of a default tag by listing it with a leading caret (`^`),
+/// which is removed along with the default tag.
+///
+/// ```
+/// let tags = vec![String::from("^work"), String::from("linux")];
+/// let defaults = [String::from("work"), String::from("dotfiles")];
+/// assert_eq!(with_default_tags(tags, &defaults)?, ["linux", "dotfiles"]);
+/// ```
+fn with_default_tags(tags: Vec<String>, defaults: &[String]) ->
+    Result<Vec<String>> {
+
+    let (removed, mut tags): (Vec<_>, Vec<_>) = tags.into_iter()
+        .partition(|x| x.starts_with('^'));
+    let removed: Vec<_> = removed.iter().map(|x| &x[1..]).collect();
+    let unknown = removed.iter().find(|x| !defaults.iter().any(|y| y == *x));
+    if let Some(tag) = unknown {
+        bail!("Step opts out of tag {}, which isn't a manifest tag", tag);
+    }
+    for tag in defaults {
+        if !removed.contains(&tag.as_str()) && !tags.contains(tag) {
+            tags.push(tag.clone());
+        }
+    }
+    Ok(tags)
+}
+
+/// Returns a sorted, de-duplicated vector of all tags in a manifest
+///
+/// ```
+/// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// let tags = get_manifest_tags(manifest);
+/// ```
+pub fn get_manifest_tags(manifest: Manifest) -> Vec<String> {
+    let mut tag_set: HashSet<String> = HashSet::new();
+
+    for step in manifest.steps {
+        for tag in step.tags {
+            tag_set.insert(tag);
+        }
+    }
+
+    let mut tags: Vec<String> = tag_set.iter().map(|s| s.to_owned()).collect();
+    tags.sort();
+    tags
+}
+
+/// Returns the 1-based numbers of the steps that use each tag in a manifest
+///
+/// ```
+/// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// let usage = get_tag_usage(&manifest);
+/// assert_eq!(usage["linux"], [1, 2]);
+/// ```
+pub fn get_tag_usage(manifest: &Manifest) -> BTreeMap<String, Vec<usize>> {
+    let mut usage: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (i, step) in manifest.steps.iter().enumerate() {
+        for tag in &step.tags {
+            usage.entry(tag.clone()).or_default().push(i + 1);
+        }
+    }
+    usage
+}
+
+/// Filter a manifest to only include steps that satisfy a set of tag rules
+///
+/// ```
+/// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// let tag_rules = [String::from("linux"), String::from("^windows")];
+/// let filtered_manifest = filter_manifest_steps(manifest, &tag_rules);
+/// let filtered_tags = get_manifest_tags(filtered_manifest);
+/// assert_eq!(filtered_tags.contains(String::from("windows")), false);
+/// ```
+pub fn filter_manifest_steps(manifest: Manifest, tag_rules: &[String]) ->
+    Manifest {
+
+    Manifest {
+        steps: manifest.steps.iter().filter(|x|
+            tags_match(tag_rules, &x.tags)
+        ).cloned().collect(),
+        pre_install: manifest.pre_install,
+        post_install: manifest.post_install,
+        hosts: manifest.hosts,
+        vars: manifest.vars,
+        exclusive_tags: manifest.exclusive_tags,
+        base_dir: manifest.base_dir,
+    }
+}
+
+/// Applies the overrides for a machine to a manifest
+///
+/// An entry in the `hosts` section applies if its name matches `hostname` or
+/// the first component of `hostname` (e.g. `laptop` matches
+/// `laptop.example.com`), ignoring case. Destination overrides are applied to
+/// every step, including the additional steps of all matching entries, and
+/// variable overrides replace the manifest's variables.
+///
+/// ```
+/// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// let manifest = apply_host_overrides(manifest, "laptop");
+/// ```
+pub fn apply_host_overrides(manifest: Manifest, hostname: &str) -> Manifest {
+    let short_name = hostname.split('.').next().unwrap_or(hostname);
+    let overrides: Vec<&HostOverrides> = manifest.hosts.iter().filter(|x| {
+        x.0.eq_ignore_ascii_case(hostname) ||
+            x.0.eq_ignore_ascii_case(short_name)
+    }).map(|x| x.1).collect();
+
+    let mut steps = manifest.steps.clone();
+    let mut vars = manifest.vars.clone();
+    for _overrides in overrides.iter() {
+        steps.extend(_overrides.steps.iter().cloned());
+        vars.extend(_overrides.vars.clone());
+    }
+    for _overrides in overrides.iter() {
+        for dst in steps.iter_mut().flat_map(destinations_mut) {
+            if let Some(new_dst) = _overrides.dst.get(dst) {
+                *dst = new_dst.clone();
+            }
+        }
+    }
+
+    Manifest { steps, vars, ..manifest }
+}
+
+/// Returns the destinations of a step's copy, link, block, patch, repo, and
+/// fetch commands
+///
+/// ```
+/// for dst in destinations_mut(&mut step) {
+///     *dst = dst.replace("~/.config", "~/config");
+/// }
+/// ```
+fn destinations_mut(step: &mut Step) -> Vec<&mut String> {
+    let mut dsts: Vec<&mut String> = vec![];
+    dsts.extend(step.copy.iter_mut().map(|x| &mut x.dst));
+    dsts.extend(step.link.iter_mut().map(|x| &mut x.dst));
+    dsts.extend(step.block.iter_mut().map(|x| &mut x.dst));
+    dsts.extend(step.patch.iter_mut().map(|x| &mut x.dst));
+    dsts.extend(step.repo.iter_mut().map(|x| &mut x.dst));
+    dsts.extend(step.fetch.iter_mut().map(|x| &mut x.dst));
+    dsts
+}
+
+/// Replaces the XDG base directory placeholders (e.g. `{XDG_CONFIG_HOME}`) in
+/// the destinations of a manifest's copy and link commands
+///
+/// Placeholders resolve to the corresponding environment variable if it's set
+/// to an absolute path, and to the default from the XDG specification (e.g.
+/// `~/.config`) otherwise. On Windows, the defaults are `%APPDATA%` for the
+/// config and data directories and `%LOCALAPPDATA%` for the state directory. If
+/// `remote` is true, the local environment is ignored and the Unix defaults are
+/// always used.
+///
+/// ```
+/// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// let manifest = resolve_xdg_dirs(manifest, false)?;
+/// ```
+pub fn resolve_xdg_dirs(mut manifest: Manifest, remote: bool) ->
+    Result<Manifest> {
+
+    for entry in manifest.steps.iter_mut().flat_map(|x| {
+        x.copy.iter_mut().chain(x.link.iter_mut())
+    }) {
+        for (var, default, windows_var) in XDG_DIRS {
+            let placeholder = format!("{{{}}}", var);
+            if !entry.dst.contains(&placeholder) { continue; }
+
+            let dir = if remote {
+                default.to_owned()
+            } else {
+                xdg_dir(var, default, windows_var)?
+            };
+            entry.dst = entry.dst.replace(&placeholder, &dir);
+        }
+    }
+    Ok(manifest)
+}
+
+/// Returns the location of an XDG base directory on the local machine
+///
+/// The directory is the value of `var` if it's set to an absolute path, since
+/// the XDG specification says relative paths are invalid and should be ignored.
+/// Otherwise, it's `default` on Unix and the value of `windows_var` on Windows.
+/// Tildes in `default` aren't expanded.
+///
+/// ```
+/// let dir = xdg_dir("XDG_STATE_HOME", "~/.local/state", "LOCALAPPDATA")?;
+/// ```
+pub fn xdg_dir(var: &str, default: &str, windows_var: &str) -> Result<String> {
+    match env::var(var) {
+        Ok(dir) if Path::new(&dir).is_absolute() => Ok(dir),
+        _ if cfg!(target_family = "unix") => Ok(default.to_owned()),
+        _ => match env::var(windows_var) {
+            Ok(dir) => Ok(dir),
+            Err(_) => bail!("Failed to locate {}", var),
+        },
+    }
+}
+
+/// Expands the Windows folders in the destinations of a manifest's copy and
+/// link commands
+///
+/// On Windows, `%APPDATA%`, `%LOCALAPPDATA%`, and `%USERPROFILE%` are replaced
+/// with the values of those variables, and known folder placeholders (e.g.
+/// `{Documents}`) are replaced with the matching folder in `%USERPROFILE%`. On
+/// other platforms, or if `remote` is true, known folders resolve to the
+/// matching folder in `~` and variables are left alone.
+///
+/// ```
+/// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// let manifest = resolve_known_folders(manifest, false)?;
+/// ```
+pub fn resolve_known_folders(mut manifest: Manifest, remote: bool) ->
+    Result<Manifest> {
+
+    let windows = cfg!(target_family = "windows") && !remote;
+    for entry in manifest.steps.iter_mut().flat_map(|x| {
+        x.copy.iter_mut().chain(x.link.iter_mut())
+    }) {
+        if windows {
+            for var in WINDOWS_VARS {
+                let placeholder = format!("%{}%", var);
+                if !entry.dst.contains(&placeholder) { continue; }
+
+                let value = env::var(var).with_context(|| {
+                    format!("Failed to expand {} in {}", placeholder, entry.dst)
+                })?;
+                entry.dst = entry.dst.replace(&placeholder, &value);
+            }
+        }
+        for folder in KNOWN_FOLDERS {
+            let placeholder = format!("{{{}}}", folder);
+            if !entry.dst.contains(&placeholder) { continue; }
+
+            let dir = if windows {
+                let profile = env::var("USERPROFILE").with_context(|| {
+                    format!("Failed to expand {} in {}", placeholder, entry.dst)
+                })?;
+                Path::new(&profile).join(folder).to_string_lossy().to_string()
+            } else {
+                format!("~/{}", folder)
+            };
+            entry.dst = entry.dst.replace(&placeholder, &dir);
+        }
+    }
+    Ok(manifest)
+}
+
+/// Makes every copy and link command in a manifest preserve modification times
+/// when its file is copied
 ///
 /// ```
-/// let rules = ["linux,macos", "system", "^work"];
-/// let tags_1 = ["macos", "system", "user"];
-/// let tags_2 = ["linux", "system", "work"];
-/// assert_eq!(tags_match(&rules, &tags_1), true);
-/// assert_eq!(tags_match(&rules, &tags_2), false);
+/// let manifest = preserve_mtimes(parse_manifest_file(Path::new("a.yml"))?);
 /// ```
-fn tags_match<S: AsRef<str>>(rules: &[S], tags: &[S]) -> bool {
-    for rule in rules.iter() {
-        let mut _rule = rule.as_ref();
-        let is_negated = _rule.chars().nth(0) == Some('^');
-        if is_negated {
-            _rule = &_rule[1..]; // Strip leading '^'
-        }
+pub fn preserve_mtimes(mut manifest: Manifest) -> Manifest {
+    for entry in manifest.steps.iter_mut().flat_map(|x| {
+        x.copy.iter_mut().chain(x.link.iter_mut())
+    }) {
+        entry.preserve_mtime = true;
+    }
+    manifest
+}
 
-        let tag_found = _rule.split(",").any(|subrule| {
-            tags.iter().any(|tag| {
-                tag.as_ref() == subrule
-            })
-        });
+/// Makes every link command in a manifest create symbolic links with relative
+/// targets
+///
+/// ```
+/// let manifest = relative_links(parse_manifest_file(Path::new("a.yml"))?);
+/// ```
+pub fn relative_links(mut manifest: Manifest) -> Manifest {
+    for entry in manifest.steps.iter_mut().flat_map(|x| x.link.iter_mut()) {
+        entry.relative = true;
+    }
+    manifest
+}
 
-        if tag_found == is_negated {
-            return false
+/// Makes every link command in a manifest create symbolic links on the remote
+/// machine when installing over SSH
+///
+/// ```
+/// let manifest = remote_links(parse_manifest_file(Path::new("a.yml"))?);
+/// ```
+pub fn remote_links(mut manifest: Manifest) -> Manifest {
+    let host_steps = manifest.hosts.values_mut().flat_map(|x| {
+        x.steps.iter_mut()
+    });
+    for step in manifest.steps.iter_mut().chain(host_steps) {
+        for entry in step.link.iter_mut() {
+            entry.remote = true;
         }
     }
-
-    true
+    manifest
 }
 
-/// Parse a coliru YAML manifest file
+/// Sets a timeout on every run command in a manifest that doesn't already have
+/// one
 ///
 /// ```
 /// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// let manifest = script_timeouts(manifest, 60);
 /// ```
-pub fn parse_manifest_file(path: &Path) -> Result<Manifest> {
-    let raw_str = read_to_string(path)?;
-    let raw_manifest = serde_yaml::from_str::<RawManifest>(&raw_str)?;
-    let base_dir = match path.parent() {
-        None => &Path::new("."),
-        Some(p) => if p == Path::new("") { &Path::new(".") } else { p },
-    };
+pub fn script_timeouts(mut manifest: Manifest, timeout: u64) -> Manifest {
+    let host_steps = manifest.hosts.values_mut().flat_map(|x| {
+        x.steps.iter_mut()
+    });
+    for step in manifest.steps.iter_mut().chain(host_steps) {
+        for entry in step.run.iter_mut() {
+            entry.timeout.get_or_insert(timeout);
+        }
+    }
+    manifest
+}
 
-    Ok(Manifest {
-        steps: raw_manifest.steps,
-        base_dir: base_dir.to_path_buf(),
-    })
+/// Makes every run command in a manifest delete its script from the remote
+/// machine after it succeeds
+///
+/// ```
+/// let manifest = cleanup_scripts(parse_manifest_file(Path::new("a.yml"))?);
+/// ```
+pub fn cleanup_scripts(mut manifest: Manifest) -> Manifest {
+    let host_steps = manifest.hosts.values_mut().flat_map(|x| {
+        x.steps.iter_mut()
+    });
+    for step in manifest.steps.iter_mut().chain(host_steps) {
+        for entry in step.run.iter_mut() {
+            entry.cleanup = true;
+        }
+    }
+    manifest
 }
 
-/// Returns a sorted, de-duplicated vector of all tags in a manifest
+/// Sets the shell of every run command in a manifest that doesn't already
+/// have one
 ///
 /// ```
 /// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
-/// let tags = get_manifest_tags(manifest);
+/// let manifest = script_shells(manifest, Shell::Pwsh);
 /// ```
-pub fn get_manifest_tags(manifest: Manifest) -> Vec<String> {
-    let mut tag_set: HashSet<String> = HashSet::new();
-
-    for step in manifest.steps {
-        for tag in step.tags {
-            tag_set.insert(tag);
+pub fn script_shells(mut manifest: Manifest, shell: Shell) -> Manifest {
+    let host_steps = manifest.hosts.values_mut().flat_map(|x| {
+        x.steps.iter_mut()
+    });
+    for step in manifest.steps.iter_mut().chain(host_steps) {
+        for entry in step.run.iter_mut() {
+            entry.shell.get_or_insert(shell);
         }
     }
-
-    let mut tags: Vec<String> = tag_set.iter().map(|s| s.to_owned()).collect();
-    tags.sort();
-    tags
+    manifest
 }
 
-/// Filter a manifest to only include steps that satisfy a set of tag rules
+/// Sets environment variables on every run command in a manifest, replacing
+/// any variables with the same names
 ///
 /// ```
 /// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
-/// let tag_rules = [String::from("linux"), String::from("^windows")];
-/// let filtered_manifest = filter_manifest_steps(manifest, &tag_rules);
-/// let filtered_tags = get_manifest_tags(filtered_manifest);
-/// assert_eq!(filtered_tags.contains(String::from("windows")), false);
+/// let manifest = script_env(manifest, &[parse_env_var("TOKEN=abc")?]);
 /// ```
-pub fn filter_manifest_steps(manifest: Manifest, tag_rules: &[String]) ->
+pub fn script_env(mut manifest: Manifest, vars: &[(String, String)]) ->
     Manifest {
 
-    Manifest {
-        steps: manifest.steps.iter().filter(|x|
-            tags_match(tag_rules, &x.tags)
-        ).map(|x| x.clone()).collect(),
-        base_dir: manifest.base_dir,
+    let host_steps = manifest.hosts.values_mut().flat_map(|x| {
+        x.steps.iter_mut()
+    });
+    for step in manifest.steps.iter_mut().chain(host_steps) {
+        for entry in step.run.iter_mut() {
+            entry.env.extend(vars.iter().cloned());
+        }
+    }
+    manifest
+}
+
+/// Parses an environment variable in the form `NAME=VALUE`
+///
+/// ```
+/// assert_eq!(parse_env_var("TOKEN=a=b")?,
+///            (String::from("TOKEN"), String::from("a=b")));
+/// ```
+pub fn parse_env_var(var: &str) -> Result<(String, String), String> {
+    let Some((name, value)) = var.split_once('=') else {
+        return Err(format!("expected NAME=VALUE, found '{}'", var));
+    };
+    if !is_env_name(name) {
+        return Err(format!("invalid environment variable name '{}'", name));
     }
+    Ok((name.to_owned(), value.to_owned()))
+}
+
+/// Returns whether a string is a valid environment variable name, which
+/// consists of letters, digits, and underscores and doesn't start with a digit
+fn is_env_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|x| x.is_ascii_alphabetic() || x == '_') &&
+        chars.all(|x| x.is_ascii_alphanumeric() || x == '_')
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_utils::{setup_integration, write_file};
+    use std::fs::create_dir_all;
 
     #[test]
     fn test_manifest_tags_match_empty_parameters() {
@@ -228,6 +1642,93 @@ mod tests {
         assert_eq!(tags_match(&rules_2.clone(), &tags_4.clone()), false);
     }
 
+    #[test]
+    fn test_manifest_unmatched_tag_rule() {
+        let rules = ["linux,macos", "^work"];
+        let tags_1 = ["macos", "user"];
+        let tags_2 = ["windows", "work"];
+        let tags_3 = ["linux", "work"];
+
+        assert_eq!(unmatched_tag_rule(&rules, &tags_1), None);
+        assert_eq!(unmatched_tag_rule(&rules, &tags_2), Some(&"linux,macos"));
+        assert_eq!(unmatched_tag_rule(&rules, &tags_3), Some(&"^work"));
+    }
+
+    #[test]
+    fn test_manifest_validate_tag_rules() {
+        assert_eq!(validate_tag_rules(&["linux,macos", "^work"]).is_ok(), true);
+        assert_eq!(validate_tag_rules::<&str>(&[]).is_ok(), true);
+        assert_eq!(validate_tag_rules(&[""]).is_ok(), false);
+        assert_eq!(validate_tag_rules(&["^"]).is_ok(), false);
+        assert_eq!(validate_tag_rules(&["linux,,macos"]).is_ok(), false);
+        assert_eq!(validate_tag_rules(&["linux,^work"]).is_ok(), false);
+        assert_eq!(validate_tag_rules(&["linux,"]).unwrap_err().to_string(),
+                   "\"linux,\" contains an empty tag");
+        assert_eq!(validate_tag_rules(&["(linux || macos) && !work"]).is_ok(),
+                   true);
+        assert_eq!(validate_tag_rules(&["(linux || macos"]).is_ok(), false);
+    }
+
+    #[test]
+    fn test_manifest_tags_match_expression() {
+        let rules = ["(linux || macos) && !work", "gui,ssh"];
+        let tags_1 = ["linux", "gui"];
+        let tags_2 = ["macos", "work", "ssh"];
+        let tags_3 = ["windows", "gui"];
+
+        assert_eq!(tags_match(&rules, &tags_1), true);
+        assert_eq!(tags_match(&rules, &tags_2), false);
+        assert_eq!(tags_match(&rules, &tags_3), false);
+        assert_eq!(unmatched_tag_rule(&rules, &tags_2),
+                   Some(&"(linux || macos) && !work"));
+    }
+
+    #[test]
+    fn test_manifest_tags_match_wildcard() {
+        let rules = ["ubuntu-*,macos"];
+        let tags_1 = ["ubuntu-22.04"];
+        let tags_2 = ["ubuntu-24.04", "gui"];
+        let tags_3 = ["debian-12"];
+
+        assert_eq!(tags_match(&rules, &tags_1), true);
+        assert_eq!(tags_match(&rules, &tags_2), true);
+        assert_eq!(tags_match(&rules, &tags_3), false);
+    }
+
+    #[test]
+    fn test_manifest_validate_exclusive_tags() {
+        let contents = "\
+exclusive_tags: [ [ linux, macos, windows ] ]
+steps: []
+";
+        let manifest = parse_manifest_str(contents, Path::new(".")).unwrap();
+        let error = |rules: &[&str], detected: &[&str]| {
+            validate_exclusive_tags(&manifest, rules, detected).unwrap_err()
+                .to_string()
+        };
+
+        assert_eq!(validate_exclusive_tags(&manifest, &["linux", "^work"],
+                                           &[]).is_ok(), true);
+        assert_eq!(validate_exclusive_tags(&manifest, &["^work"],
+                                           &["linux"]).is_ok(), true);
+        assert_eq!(validate_exclusive_tags(&manifest, &["linux"],
+                                           &["linux"]).is_ok(), true);
+        assert_eq!(error(&["^windows"], &[]),
+                   "Exactly one of linux, macos, windows must be selected, \
+                    but none are");
+        assert_eq!(error(&["linux,macos"], &[]),
+                   "Exactly one of linux, macos, windows must be selected, \
+                    but linux, macos are");
+        assert_eq!(error(&["macos"], &["linux"]),
+                   "Exactly one of linux, macos, windows must be selected, \
+                    but macos, linux are");
+
+        let contents = "exclusive_tags: [ [ linux ] ]\nsteps: []\n";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string(),
+                   "Exclusive tag groups must contain at least two tags");
+    }
+
     #[test]
     fn test_manifest_tags_match_union() {
         let rules = ["linux,macos"];
@@ -324,6 +1825,25 @@ mod tests {
         assert_eq!(actual.unwrap_err().to_string(), exp);
     }
 
+    #[test]
+    fn test_manifest_parse_manifest_reader() {
+        let contents = "steps:\n  - copy: [ { src: foo, dst: ~/foo } ]\n";
+        let base_dir = Path::new("examples/test");
+
+        let actual = parse_manifest_reader(contents.as_bytes(), base_dir);
+
+        assert_eq!(actual.unwrap(), parse_manifest_str(contents, base_dir)
+                   .unwrap());
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_reader_invalid() {
+        let actual = parse_manifest_reader(&[0xff, 0xfe][..], Path::new("."));
+
+        assert_eq!(actual.unwrap_err().to_string(),
+                   "stream did not contain valid UTF-8");
+    }
+
     #[test]
     fn test_manifest_parse_manifest_file_valid() {
         let manifest_path = Path::new("examples/test/manifest.yml");
@@ -334,10 +1854,29 @@ mod tests {
                         CopyLinkOptions {
                             src: String::from("gitconfig"),
                             dst: String::from("~/.gitconfig"),
+                            link_type: None,
+                            preserve_mtime: false,
+                            relative: false,
+                            remote: false,
+                            eol: None,
+                            concat: vec![],
+                            template: None,
                         },
                     ],
                     link: vec![],
+                    block: vec![],
+                    patch: vec![],
+                    packages: vec![],
+                    brew: vec![],
+                    repo: vec![],
+                    fetch: vec![],
+                    systemd: vec![],
+                    defaults: vec![],
+                    extensions: vec![],
+                    cron: vec![],
                     run: vec![],
+                    stow: vec![],
+                    parallel: false,
                     tags: vec![
                         String::from("windows"),
                         String::from("linux"),
@@ -349,25 +1888,66 @@ mod tests {
                         CopyLinkOptions {
                             src: String::from("scripts/foo"),
                             dst: String::from("scripts/foo"),
+                            link_type: None,
+                            preserve_mtime: false,
+                            relative: false,
+                            remote: false,
+                            eol: None,
+                            concat: vec![],
+                            template: None,
                         },
                     ],
                     link: vec![
                         CopyLinkOptions {
                             src: String::from("bashrc"),
                             dst: String::from("~/.bashrc"),
+                            link_type: None,
+                            preserve_mtime: false,
+                            relative: false,
+                            remote: false,
+                            eol: None,
+                            concat: vec![],
+                            template: None,
                         },
                         CopyLinkOptions {
                             src: String::from("vimrc"),
                             dst: String::from("~/.vimrc"),
+                            link_type: None,
+                            preserve_mtime: false,
+                            relative: false,
+                            remote: false,
+                            eol: None,
+                            concat: vec![],
+                            template: None,
                         },
                     ],
+                    block: vec![],
+                    patch: vec![],
+                    packages: vec![],
+                    brew: vec![],
+                    repo: vec![],
+                    fetch: vec![],
+                    systemd: vec![],
+                    defaults: vec![],
+                    extensions: vec![],
+                    cron: vec![],
                     run: vec![
                         RunOptions {
                             src: String::from("scripts/script.sh"),
                             prefix: String::from("sh"),
                             postfix: String::from("arg1 $COLIRU_RULES"),
+                            timeout: None,
+                            retries: 0,
+                            retry_delay: 0,
+                            user: None,
+                            shell: None,
+                            cleanup: false,
+                            env: BTreeMap::new(),
+                            creates: vec![],
                         },
                     ],
+                    stow: vec![],
+                    parallel: false,
                     tags: vec![String::from("linux"), String::from("macos")],
                 },
                 Step {
@@ -375,24 +1955,63 @@ mod tests {
                         CopyLinkOptions {
                             src: String::from("scripts/foo"),
                             dst: String::from("scripts/foo"),
+                            link_type: None,
+                            preserve_mtime: false,
+                            relative: false,
+                            remote: false,
+                            eol: None,
+                            concat: vec![],
+                            template: None,
                         },
                     ],
                     link: vec![
                         CopyLinkOptions {
                             src: String::from("vimrc"),
                             dst: String::from("~/_vimrc"),
+                            link_type: None,
+                            preserve_mtime: false,
+                            relative: false,
+                            remote: false,
+                            eol: None,
+                            concat: vec![],
+                            template: None,
                         },
                     ],
+                    block: vec![],
+                    patch: vec![],
+                    packages: vec![],
+                    brew: vec![],
+                    repo: vec![],
+                    fetch: vec![],
+                    systemd: vec![],
+                    defaults: vec![],
+                    extensions: vec![],
+                    cron: vec![],
                     run: vec![
                         RunOptions {
                             src: String::from("scripts/script.bat"),
                             prefix: String::from(""),
                             postfix: String::from("arg1 $COLIRU_RULES"),
+                            timeout: None,
+                            retries: 0,
+                            retry_delay: 0,
+                            user: None,
+                            shell: None,
+                            cleanup: false,
+                            env: BTreeMap::new(),
+                            creates: vec![],
                         },
                     ],
+                    stow: vec![],
+                    parallel: false,
                     tags: vec![String::from("windows")],
                 },
             ],
+            pre_install: vec![],
+            post_install: vec![],
+            hosts: BTreeMap::new(),
+            vars: BTreeMap::new(),
+            exclusive_tags: vec![],
             base_dir: PathBuf::from("examples/test"),
         };
         let actual = parse_manifest_file(manifest_path);
@@ -400,6 +2019,729 @@ mod tests {
         assert_eq!(actual.unwrap(), expected);
     }
 
+    #[test]
+    fn test_manifest_parse_manifest_str_hooks() {
+        let contents = "\
+pre_install: [ git pull ]
+post_install: [ tmux source ~/.tmux.conf, echo done ]
+steps: []
+";
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+        assert_eq!(actual.pre_install, vec![String::from("git pull")]);
+        assert_eq!(actual.post_install, vec![
+            String::from("tmux source ~/.tmux.conf"),
+            String::from("echo done"),
+        ]);
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_default_tags() {
+        let contents = "\
+tags: [ work, dotfiles ]
+steps:
+  - tags: [ linux ]
+  - tags: [ ^work, work-laptop, dotfiles ]
+hosts:
+  laptop:
+    steps:
+      - tags: []
+";
+
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+
+        assert_eq!(actual.steps[0].tags, ["linux", "work", "dotfiles"]);
+        assert_eq!(actual.steps[1].tags, ["work-laptop", "dotfiles"]);
+        assert_eq!(actual.hosts["laptop"].steps[0].tags, ["work", "dotfiles"]);
+
+        let contents = "tags: [ work ]\nsteps:\n  - tags: [ ^linux ]\n";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string(),
+                   "Step opts out of tag linux, which isn't a manifest tag");
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_link_type() {
+        let contents = "\
+steps:
+  - link:
+    - { src: bashrc, dst: ~/.bashrc }
+    - { src: flatpak.conf, dst: ~/.var/flatpak.conf, type: copy }
+";
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+        let links = &actual.steps[0].link;
+        assert_eq!(links[0].link_mode(LinkMode::Hardlink), LinkMode::Hardlink);
+        assert_eq!(links[1].link_mode(LinkMode::Hardlink), LinkMode::Copy);
+
+        let contents = "\
+steps:
+  - copy: [ { src: bashrc, dst: ~/.bashrc, type: hardlink } ]
+";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string(), "Copy command for bashrc \
+                   has a type, which is only allowed on link commands");
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_eol() {
+        let contents = "\
+steps:
+  - copy:
+    - { src: bashrc, dst: ~/.bashrc, eol: lf }
+    - { src: profile.ps1, dst: profile.ps1, eol: native }
+";
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+        let copies = &actual.steps[0].copy;
+        assert_eq!(copies[0].eol, Some(LineEnding::Lf));
+        assert_eq!(copies[1].eol, Some(LineEnding::Native));
+
+        let contents = "\
+steps:
+  - link: [ { src: bashrc, dst: ~/.bashrc, eol: crlf } ]
+";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string(), "Link command for bashrc \
+                   has an eol, which is only allowed on copy commands");
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_creates() {
+        let contents = "\
+steps:
+  - run:
+    - { src: install.sh, creates: ~/.local/bin/tool }
+    - { src: plugins.sh, creates: [ ~/.vim/autoload, ~/.vim/plugged ] }
+    - { src: setup.sh }
+";
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+        let runs = &actual.steps[0].run;
+        assert_eq!(runs[0].creates, vec!["~/.local/bin/tool"]);
+        assert_eq!(runs[1].creates, vec!["~/.vim/autoload", "~/.vim/plugged"]);
+        assert_eq!(runs[2].creates, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_concat() {
+        let contents = "\
+steps:
+  - copy:
+    - { src: [base, work], dst: ~/.gitconfig }
+    - { src: bashrc, dst: ~/.bashrc }
+";
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+        let copies = &actual.steps[0].copy;
+        assert_eq!(copies[0].src, "base + work");
+        assert_eq!(copies[0].concat, vec!["base", "work"]);
+        assert_eq!(copies[0].sources(), vec!["base", "work"]);
+        assert_eq!(copies[1].concat, Vec::<String>::new());
+        assert_eq!(copies[1].sources(), vec!["bashrc"]);
+
+        let contents = "\
+steps:
+  - link: [ { src: [base, work], dst: ~/.gitconfig } ]
+";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string(), "Link command for base + \
+                   work has multiple sources, which is only allowed on copy \
+                   commands");
+
+        let contents = "\
+steps:
+  - copy: [ { src: [], dst: ~/.gitconfig } ]
+";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.is_err(), true);
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_template() {
+        let contents = "\
+steps:
+  - copy:
+    - { src: foo, dst: ~/foo, template: true }
+    - { src: bar, dst: ~/bar }
+";
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+        let copies = &actual.steps[0].copy;
+        assert_eq!(copies[0].template, Some(BTreeMap::new()));
+        assert_eq!(copies[1].template, None);
+
+        let contents = "\
+steps:
+  - link: [ { src: foo, dst: ~/foo, template: true } ]
+";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string(), "Link command for foo is \
+                   templated, which is only allowed on copy commands");
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_stow() {
+        let tmp = setup_integration("test_manifest_parse_manifest_str_stow");
+        create_dir_all(tmp.local.join("vim")).unwrap();
+        create_dir_all(tmp.local.join("zsh")).unwrap();
+        write_file(&tmp.local.join("vim/dot-vimrc"), "");
+        write_file(&tmp.local.join("zsh/.zshrc"), "");
+        let contents = "\
+steps:
+  - link: [ { src: bashrc, dst: ~/.bashrc } ]
+    stow: [ { src: vim, dotfiles: true } ]
+hosts:
+  laptop:
+    steps:
+      - stow: [ { src: zsh, dst: ~/zsh } ]
+";
+
+        let actual = parse_manifest_str(contents, &tmp.local).unwrap();
+
+        let links = &actual.steps[0].link;
+        assert_eq!(links.len(), 2);
+        assert_eq!((links[1].src.as_str(), links[1].dst.as_str()),
+                   ("vim/dot-vimrc", "~/.vimrc"));
+        assert_eq!(actual.steps[0].stow, vec![]);
+        let links = &actual.hosts["laptop"].steps[0].link;
+        assert_eq!((links[0].src.as_str(), links[0].dst.as_str()),
+                   ("zsh/.zshrc", "~/zsh/.zshrc"));
+
+        let contents = "steps:\n  - stow: [ { src: missing } ]\n";
+        let actual = parse_manifest_str(contents, &tmp.local);
+        assert_eq!(actual.unwrap_err().to_string(),
+                   "Stow package missing is not a directory");
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_packages() {
+        let contents = "\
+steps:
+  - packages:
+    - git
+    - name: fd
+      apt: fd-find
+      zypper: fd
+";
+
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+
+        assert_eq!(actual.steps[0].packages, vec![
+            PackageOptions {
+                name: String::from("git"),
+                apt: None,
+                dnf: None,
+                pacman: None,
+                zypper: None,
+            },
+            PackageOptions {
+                name: String::from("fd"),
+                apt: Some(String::from("fd-find")),
+                dnf: None,
+                pacman: None,
+                zypper: Some(String::from("fd")),
+            },
+        ]);
+
+        let contents = "steps:\n  - packages: [ { name: fd, brew: fd } ]\n";
+        assert_eq!(parse_manifest_str(contents, Path::new(".")).is_err(),
+                   true);
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_brew() {
+        let contents = "\
+steps:
+  - brew:
+    - git
+    - formula: ripgrep
+    - cask: firefox
+    - brewfile: Brewfile
+";
+
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+
+        assert_eq!(actual.steps[0].brew, vec![
+            BrewOptions::Formula(String::from("git")),
+            BrewOptions::Formula(String::from("ripgrep")),
+            BrewOptions::Cask(String::from("firefox")),
+            BrewOptions::Brewfile(String::from("Brewfile")),
+        ]);
+
+        let contents = "steps:\n  - brew: [ { cask: a, formula: b } ]\n";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string().starts_with(
+            "steps[0].brew: Brew command must have exactly one of formula, \
+             cask, or brewfile"), true);
+
+        let contents = "steps:\n  - brew: [ { cask: a, version: 2 } ]\n";
+        assert_eq!(parse_manifest_str(contents, Path::new(".")).is_err(),
+                   true);
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_fetch() {
+        let hash = "8419FF13897cbe37259aafcdf99caa54\
+                    532b826445935be0fcb71456525ebc26";
+        let contents = format!("\
+steps:
+  - fetch:
+    - {{ url: a, dst: ~/a, sha256: {hash}, mode: 755 }}
+    - {{ url: b, dst: ~/b, sha256: {hash}, mode: '0640' }}
+    - {{ url: c, dst: ~/c, sha256: {hash} }}
+");
+
+        let actual = parse_manifest_str(&contents, Path::new(".")).unwrap();
+
+        let fetches = &actual.steps[0].fetch;
+        assert_eq!(fetches[0].sha256, hash.to_lowercase());
+        assert_eq!(fetches[0].mode, Some(String::from("755")));
+        assert_eq!(fetches[1].mode, Some(String::from("0640")));
+        assert_eq!(fetches[2].mode, None);
+
+        let contents = "steps:\n  - fetch: [ { url: a, dst: b, sha256: 1 } ]\n";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string().starts_with(
+            "steps[0].fetch: Fetch command for a has an invalid sha256"),
+            true);
+
+        let contents = format!("steps:\n  - fetch: [ {{ url: a, dst: b, \
+                                sha256: {hash}, mode: 789 }} ]\n");
+        let actual = parse_manifest_str(&contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string().starts_with(
+            "steps[0].fetch: Fetch command for a has an invalid mode 789"),
+            true);
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_systemd() {
+        let contents = "\
+steps:
+  - systemd:
+    - src: units/syncthing.service
+    - { unit: sshd.service, scope: system, start: false }
+";
+
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+
+        assert_eq!(actual.steps[0].systemd, vec![
+            SystemdOptions {
+                src: Some(String::from("units/syncthing.service")),
+                unit: String::from("syncthing.service"),
+                scope: SystemdScope::User,
+                enable: true,
+                start: true,
+            },
+            SystemdOptions {
+                src: None,
+                unit: String::from("sshd.service"),
+                scope: SystemdScope::System,
+                enable: true,
+                start: false,
+            },
+        ]);
+
+        let contents = "steps:\n  - systemd: [ { scope: user } ]\n";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string().starts_with(
+            "steps[0].systemd: Systemd command must have a src or unit"),
+            true);
+
+        let contents = "steps:\n  - systemd: [ { unit: a, enable: false, \
+                        start: false } ]\n";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string().starts_with(
+            "steps[0].systemd: Systemd command for a must install, enable, or \
+             start its unit"), true);
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_defaults() {
+        let contents = "\
+steps:
+  - defaults:
+    - { domain: com.apple.dock, key: autohide, value: true }
+    - { domain: NSGlobalDomain, key: KeyRepeat, value: 2 }
+    - { domain: com.apple.dock, key: tilesize, value: 36.5 }
+    - { domain: com.apple.finder, key: FXPreferredViewStyle, value: Nlsv }
+";
+
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+
+        let values: Vec<&DefaultsValue> = actual.steps[0].defaults.iter()
+            .map(|x| &x.value).collect();
+        assert_eq!(values, vec![
+            &DefaultsValue::Bool(true),
+            &DefaultsValue::Int(2),
+            &DefaultsValue::Float(36.5),
+            &DefaultsValue::String(String::from("Nlsv")),
+        ]);
+
+        let contents = "steps:\n  - defaults: [ { domain: a, value: 1 } ]\n";
+        assert_eq!(parse_manifest_str(contents, Path::new(".")).is_err(),
+                   true);
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_extensions() {
+        let contents = "\
+steps:
+  - extensions:
+    - ms-python.python
+    - { vscode: vscodevim.vim, program: codium }
+    - { jetbrains: IdeaVIM }
+";
+
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+
+        assert_eq!(actual.steps[0].extensions, vec![
+            ExtensionOptions::VsCode {
+                id: String::from("ms-python.python"),
+                program: String::from("code"),
+            },
+            ExtensionOptions::VsCode {
+                id: String::from("vscodevim.vim"),
+                program: String::from("codium"),
+            },
+            ExtensionOptions::JetBrains {
+                id: String::from("IdeaVIM"),
+                program: String::from("idea"),
+            },
+        ]);
+
+        let contents = "steps:\n  - extensions: [ { vscode: a, jetbrains: b } \
+                        ]\n";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string().starts_with(
+            "steps[0].extensions: Extension command must have exactly one of \
+             vscode or jetbrains"), true);
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_cron() {
+        let contents = "\
+steps:
+  - cron:
+    - { name: backup, schedule: 0 3 * * *, command: ~/bin/backup.sh }
+    - { name: sync, schedule: '@hourly', command: ~/bin/sync.sh }
+    - { name: old, remove: true }
+";
+
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+
+        let jobs = &actual.steps[0].cron;
+        assert_eq!(jobs[0].schedule, "0 3 * * *");
+        assert_eq!(jobs[1].schedule, "@hourly");
+        assert_eq!(jobs[2], CronOptions {
+            name: String::from("old"),
+            schedule: String::new(),
+            command: String::new(),
+            remove: true,
+        });
+
+        let contents = "steps:\n  - cron: [ { name: a, command: b } ]\n";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string().starts_with(
+            "steps[0].cron: Cron command for a must have a schedule and \
+             command, or remove: true"), true);
+
+        let contents = "steps:\n  - cron: [ { name: a, schedule: 0 3 * *, \
+                        command: b } ]\n";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string().starts_with(
+            "steps[0].cron: Cron command for a has an invalid schedule 0 3 * \
+             *"), true);
+    }
+
+    #[test]
+    fn test_manifest_preserve_mtimes() {
+        let contents = "\
+steps:
+  - copy: [ { src: gitconfig, dst: ~/.gitconfig } ]
+    link: [ { src: bashrc, dst: ~/.bashrc, preserve_mtime: false } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new(".")).unwrap();
+        assert_eq!(manifest.steps[0].copy[0].preserve_mtime, false);
+
+        let actual = preserve_mtimes(manifest);
+        assert_eq!(actual.steps[0].copy[0].preserve_mtime, true);
+        assert_eq!(actual.steps[0].link[0].preserve_mtime, true);
+    }
+
+    #[test]
+    fn test_manifest_relative_links() {
+        let contents = "\
+steps:
+  - copy: [ { src: gitconfig, dst: ~/.gitconfig } ]
+    link: [ { src: bashrc, dst: ~/.bashrc } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new(".")).unwrap();
+        assert_eq!(manifest.steps[0].link[0].relative, false);
+
+        let actual = relative_links(manifest);
+        assert_eq!(actual.steps[0].copy[0].relative, false);
+        assert_eq!(actual.steps[0].link[0].relative, true);
+
+        let contents = "\
+steps:
+  - copy: [ { src: bashrc, dst: ~/.bashrc, relative: true } ]
+";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string(), "Copy command for bashrc \
+                   is relative, which is only allowed on link commands");
+    }
+
+    #[test]
+    fn test_manifest_remote_links() {
+        let contents = "\
+steps:
+  - copy: [ { src: gitconfig, dst: ~/.gitconfig } ]
+    link: [ { src: bashrc, dst: ~/.bashrc } ]
+hosts:
+  laptop:
+    steps:
+      - link: [ { src: vimrc, dst: ~/.vimrc } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new(".")).unwrap();
+        assert_eq!(manifest.steps[0].link[0].remote, false);
+
+        let actual = remote_links(manifest);
+        assert_eq!(actual.steps[0].copy[0].remote, false);
+        assert_eq!(actual.steps[0].link[0].remote, true);
+        assert_eq!(actual.hosts["laptop"].steps[0].link[0].remote, true);
+
+        let contents = "\
+steps:
+  - copy: [ { src: bashrc, dst: ~/.bashrc, remote: true } ]
+";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string(), "Copy command for bashrc \
+                   is remote, which is only allowed on link commands");
+    }
+
+    #[test]
+    fn test_manifest_script_timeouts() {
+        let contents = "\
+steps:
+  - run: [ { src: a.sh }, { src: b.sh, timeout: 5 } ]
+hosts:
+  laptop:
+    steps: [ { run: [ { src: c.sh } ] } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new(".")).unwrap();
+        assert_eq!(manifest.steps[0].run[0].timeout, None);
+
+        let actual = script_timeouts(manifest, 60);
+        assert_eq!(actual.steps[0].run[0].timeout, Some(60));
+        assert_eq!(actual.steps[0].run[1].timeout, Some(5));
+        assert_eq!(actual.hosts["laptop"].steps[0].run[0].timeout, Some(60));
+    }
+
+    #[test]
+    fn test_manifest_cleanup_scripts() {
+        let contents = "\
+steps:
+  - run: [ { src: a.sh }, { src: b.sh, cleanup: true } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new(".")).unwrap();
+        assert!(!manifest.steps[0].run[0].cleanup);
+        assert!(manifest.steps[0].run[1].cleanup);
+
+        let actual = cleanup_scripts(manifest);
+        assert!(actual.steps[0].run[0].cleanup);
+        assert!(actual.steps[0].run[1].cleanup);
+    }
+
+    #[test]
+    fn test_manifest_script_env() {
+        let contents = "\
+steps:
+  - run: [ { src: a.sh }, { src: b.sh, env: { A: '1', B: '2' } } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new(".")).unwrap();
+        assert_eq!(manifest.steps[0].run[0].env, BTreeMap::new());
+
+        let actual = script_env(manifest, &[parse_env_var("B=3").unwrap()]);
+        assert_eq!(actual.steps[0].run[0].env,
+                   BTreeMap::from([(String::from("B"), String::from("3"))]));
+        assert_eq!(actual.steps[0].run[1].env,
+                   BTreeMap::from([(String::from("A"), String::from("1")),
+                                   (String::from("B"), String::from("3"))]));
+
+        let contents = "\
+steps:
+  - run: [ { src: a.sh, env: { A-B: '1' } } ]
+";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string(), "Run command for a.sh has \
+                   an invalid environment variable name: A-B");
+    }
+
+    #[test]
+    fn test_manifest_parse_env_var() {
+        assert_eq!(parse_env_var("TOKEN=a=b"),
+                   Ok((String::from("TOKEN"), String::from("a=b"))));
+        assert_eq!(parse_env_var("_A1="),
+                   Ok((String::from("_A1"), String::new())));
+        assert_eq!(parse_env_var("TOKEN"),
+                   Err(String::from("expected NAME=VALUE, found 'TOKEN'")));
+        assert_eq!(parse_env_var("1A=b"),
+                   Err(String::from("invalid environment variable name '1A'")));
+    }
+
+    #[test]
+    fn test_manifest_script_shells() {
+        let contents = "\
+steps:
+  - run: [ { src: a.ps1 }, { src: b.bat, shell: cmd } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new(".")).unwrap();
+        assert_eq!(manifest.steps[0].run[0].shell, None);
+        assert_eq!(manifest.steps[0].run[1].shell, Some(Shell::Cmd));
+
+        let actual = script_shells(manifest, Shell::Pwsh);
+        assert_eq!(actual.steps[0].run[0].shell, Some(Shell::Pwsh));
+        assert_eq!(actual.steps[0].run[1].shell, Some(Shell::Cmd));
+    }
+
+    #[test]
+    fn test_manifest_apply_host_overrides() {
+        let contents = "\
+steps:
+  - copy:
+    - src: gitconfig
+      dst: ~/.gitconfig
+  - block: [ { src: aliases, dst: ~/.bashrc } ]
+    repo: [ { url: 'https://example.com/vim.git', dst: ~/.vim } ]
+vars:
+  email: me@example.com
+  editor: vim
+hosts:
+  work:
+    dst:
+      ~/.gitconfig: ~/.gitconfig-personal
+      ~/.bashrc: ~/.bashrc-work
+      ~/.vim: ~/vim
+    steps:
+      - copy:
+        - src: gitconfig-work
+          dst: ~/.gitconfig
+        tags: [ work ]
+    vars:
+      email: me@work.example.com
+  home:
+    steps:
+      - link: [ { src: bashrc, dst: ~/.bashrc } ]
+";
+        let manifest = parse_manifest_str(contents, Path::new(".")).unwrap();
+
+        let work = apply_host_overrides(manifest.clone(), "WORK.example.com");
+        let other = apply_host_overrides(manifest.clone(), "laptop");
+
+        assert_eq!(work.steps.len(), 3);
+        assert_eq!(work.steps[0].copy[0].dst, "~/.gitconfig-personal");
+        assert_eq!(work.steps[1].block[0].dst, "~/.bashrc-work");
+        assert_eq!(work.steps[1].repo[0].dst, "~/vim");
+        assert_eq!(work.steps[2].copy[0].src, "gitconfig-work");
+        assert_eq!(work.steps[2].copy[0].dst, "~/.gitconfig-personal");
+        assert_eq!(work.steps[2].tags, vec![String::from("work")]);
+        assert_eq!(work.vars["email"], "me@work.example.com");
+        assert_eq!(work.vars["editor"], "vim");
+        assert_eq!(other, manifest);
+    }
+
+    #[test]
+    fn test_manifest_parse_manifest_str_vars() {
+        let contents = "\
+steps: []
+vars: { email: me@example.com }
+hosts: { work: { vars: { email: me@work.example.com } } }
+";
+        let actual = parse_manifest_str(contents, Path::new(".")).unwrap();
+        assert_eq!(actual.vars["email"], "me@example.com");
+        assert_eq!(actual.hosts["work"].vars["email"], "me@work.example.com");
+
+        let contents = "\
+steps: []
+hosts: { work: { vars: { facts.os: linux } } }
+";
+        let actual = parse_manifest_str(contents, Path::new("."));
+        assert_eq!(actual.unwrap_err().to_string(),
+                   "Invalid variable name: facts.os");
+    }
+
+    #[test]
+    fn test_manifest_resolve_xdg_dirs() {
+        let manifest = parse_manifest_str("
+steps:
+  - copy: [ { src: gitconfig, dst: '{XDG_CONFIG_HOME}/git/config' } ]
+    link:
+      - { src: bashrc, dst: ~/.bashrc }
+      - { src: history, dst: '{XDG_STATE_HOME}/{XDG_DATA_HOME}' }
+", Path::new(".")).unwrap();
+
+        let result = resolve_xdg_dirs(manifest, true).unwrap();
+
+        assert_eq!(result.steps[0].copy[0].dst, "~/.config/git/config");
+        assert_eq!(result.steps[0].link[0].dst, "~/.bashrc");
+        assert_eq!(result.steps[0].link[1].dst,
+                   "~/.local/state/~/.local/share");
+    }
+
+    #[test]
+    fn test_manifest_resolve_known_folders_remote() {
+        let manifest = parse_manifest_str("
+steps:
+  - copy: [ { src: profile.ps1, dst: '{Documents}/PowerShell/profile.ps1' } ]
+    link: [ { src: settings.json, dst: '%APPDATA%/Code/settings.json' } ]
+", Path::new(".")).unwrap();
+
+        let result = resolve_known_folders(manifest, true).unwrap();
+
+        assert_eq!(result.steps[0].copy[0].dst,
+                   "~/Documents/PowerShell/profile.ps1");
+        assert_eq!(result.steps[0].link[0].dst, "%APPDATA%/Code/settings.json");
+    }
+
+    #[test]
+    #[cfg(target_family = "windows")]
+    fn test_manifest_resolve_known_folders_windows() {
+        let manifest = parse_manifest_str("
+steps:
+  - copy: [ { src: profile.ps1, dst: '{Documents}/profile.ps1' } ]
+    link: [ { src: settings.json, dst: '%APPDATA%/Code/settings.json' } ]
+", Path::new(".")).unwrap();
+
+        let result = resolve_known_folders(manifest, false).unwrap();
+
+        let documents = Path::new(&env::var("USERPROFILE").unwrap())
+            .join("Documents");
+        assert_eq!(result.steps[0].copy[0].dst,
+                   format!("{}/profile.ps1", documents.display()));
+        assert_eq!(result.steps[0].link[0].dst,
+                   format!("{}/Code/settings.json",
+                           env::var("APPDATA").unwrap()));
+    }
+
+    #[test]
+    fn test_manifest_read_tag_rules_file() {
+        let tmp = setup_integration("test_manifest_read_tag_rules_file");
+        let path = tmp.local.join("rules");
+        write_file(&path, "\
+# Rules for my laptop
+linux,macos
+  ^work # Not a work machine
+
+gui ssh
+(gui || ssh) && !server # Expressions may contain spaces
+");
+
+        let result = read_tag_rules_file(&path);
+
+        assert_eq!(result.unwrap(), vec![
+            String::from("linux,macos"),
+            String::from("^work"),
+            String::from("gui"),
+            String::from("ssh"),
+            String::from("(gui || ssh) && !server"),
+        ]);
+    }
+
     #[test]
     fn test_manifest_get_manifest_tags_basic() {
         let manifest_path = Path::new("examples/test/manifest.yml");
@@ -417,6 +2759,11 @@ mod tests {
     fn test_manifest_get_manifest_tags_empty_manifest() {
         let manifest = Manifest {
             steps: vec![],
+            pre_install: vec![],
+            post_install: vec![],
+            hosts: BTreeMap::new(),
+            vars: BTreeMap::new(),
+            exclusive_tags: vec![],
             base_dir: PathBuf::from("examples/test/empty.yml"),
         };
         let expected: Vec<String> = vec![];
@@ -436,6 +2783,20 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_manifest_get_tag_usage() {
+        let manifest_path = Path::new("examples/test/manifest.yml");
+        let manifest = parse_manifest_file(manifest_path).unwrap();
+
+        let actual = get_tag_usage(&manifest);
+
+        assert_eq!(actual, BTreeMap::from([
+            (String::from("linux"), vec![1, 2]),
+            (String::from("macos"), vec![1, 2]),
+            (String::from("windows"), vec![1, 3]),
+        ]));
+    }
+
     #[test]
     fn test_manifest_filter_manifest_steps_basic() {
         let manifest_path = Path::new("examples/test/manifest.yml");
@@ -463,6 +2824,11 @@ mod tests {
     fn test_manifest_filter_manifest_steps_empty_manifest() {
         let manifest = Manifest {
             steps: vec![],
+            pre_install: vec![],
+            post_install: vec![],
+            hosts: BTreeMap::new(),
+            vars: BTreeMap::new(),
+            exclusive_tags: vec![],
             base_dir: PathBuf::from("examples/test/empty.yml"),
         };
         let tags = [String::from("linux")];
@@ -0,0 +1,432 @@
+//! Remote dotfile installation without the `ssh` and `scp` programs
+//!
+//! This module is only built with the `native-ssh` feature. It implements the
+//! transfers and commands in [`ssh`](super::ssh) with libssh2, using SFTP to
+//! transfer files. SSH configuration files and aliases aren't supported, so
+//! hosts must be in the form `user@hostname[:port]`.
+//!
+//! ```
+//! let options = SshOptions::default();
+//! send_dir(Path::new("/tmp/staging/home"), "~", "user@hostname", &options)?;
+//! send_command("echo 'Hello World'", "user@hostname", &options, false, None,
+//!              None, None)?;
+//! ```
+
+use anyhow::{Context, Result, anyhow, bail};
+use shellexpand::tilde;
+use ssh2::{Channel, CheckResult, KnownHostFileKind, KnownHostKeyFormat,
+           OpenFlags, OpenType, Session, Sftp};
+use std::env;
+use std::fs::{File, OpenOptions, read_dir};
+#[cfg(target_family = "unix")]
+use std::fs::metadata;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+use super::exit::Error;
+use super::local::{CapturedOutput, OUTPUT_POLL_INTERVAL, write_line};
+use super::ssh::{HostKeyPolicy, SshOptions, home_relative_path, split_port};
+
+/// The port used when a host doesn't include one
+const DEFAULT_PORT: u16 = 22;
+
+/// Opens an authenticated SSH session with a host
+///
+/// The host key is checked against `~/.ssh/known_hosts` according to the host
+/// key policy in `options`, and must already be present if no policy was
+/// provided. If the server requires
+/// authentication, the private key in `options` is used if one was provided and
+/// the SSH agent is used otherwise, falling back to the password in `options`
+/// if there is one. Errors are classified as transport failures.
+///
+/// ```
+/// let session = connect("user@hostname:2222", &SshOptions::default())?;
+/// ```
+pub fn connect(host: &str, options: &SshOptions) -> Result<Session> {
+    open_session(host, options).with_context(|| {
+        Error::Transport(format!("Failed to connect to {}", host))
+    })
+}
+
+/// Opens a TCP connection to the first address of a host that accepts one
+/// within `timeout`
+fn connect_timeout<A: ToSocketAddrs>(address: A, timeout: Duration) ->
+    io::Result<TcpStream> {
+
+    let mut error = io::Error::new(ErrorKind::NotFound,
+                                   "failed to lookup address information");
+    for address in address.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(why) => error = why,
+        }
+    }
+    Err(error)
+}
+
+/// Opens an authenticated SSH session with a host without classifying errors
+fn open_session(host: &str, options: &SshOptions) -> Result<Session> {
+    let (destination, port) = split_port(host);
+    let port = match port {
+        Some(port) => port.parse().with_context(|| {
+            format!("Invalid port {}", port)
+        })?,
+        None => DEFAULT_PORT,
+    };
+    let (user, hostname) = match destination.split_once('@') {
+        Some((user, hostname)) => (user.to_owned(), hostname),
+        None => (local_user()?, destination),
+    };
+    let hostname = hostname.trim_start_matches('[').trim_end_matches(']');
+
+    let stream = match options.connect_timeout {
+        Some(secs) => {
+            connect_timeout((hostname, port), Duration::from_secs(secs))?
+        },
+        None => TcpStream::connect((hostname, port))?,
+    };
+    let mut session = Session::new()?;
+    session.set_compress(options.compress);
+    session.set_tcp_stream(stream);
+    session.handshake()?;
+    match options.host_key_policy {
+        Some(HostKeyPolicy::Off) => {},
+        policy => {
+            let accept_new = policy == Some(HostKeyPolicy::AcceptNew);
+            check_host_key(&session, hostname, port, accept_new)?;
+        },
+    }
+
+    // Listing the authentication methods succeeds immediately if the server
+    // doesn't require any authentication
+    let _ = session.auth_methods(&user);
+    if !session.authenticated() {
+        let result = match &options.identity_file {
+            Some(path) => {
+                session.userauth_pubkey_file(&user, None,
+                                             Path::new(tilde(path).as_ref()),
+                                             None)
+            },
+            None => session.userauth_agent(&user),
+        };
+        match &options.password {
+            // Keys are optional when a password was provided
+            Some(password) if !session.authenticated() => {
+                session.userauth_password(&user, password.as_str())?;
+            },
+            _ => result?,
+        }
+    }
+    if !session.authenticated() {
+        bail!("Failed to authenticate as {}", user);
+    }
+    Ok(session)
+}
+
+/// Returns the name of the current user on the local machine
+fn local_user() -> Result<String> {
+    env::var("USER").or_else(|_| env::var("USERNAME")).map_err(|_| {
+        anyhow!("Failed to determine the username to connect with")
+    })
+}
+
+/// Returns an Err if a session's host key isn't in `~/.ssh/known_hosts`, or
+/// adds it to that file if `accept_new` is true and the host isn't there
+fn check_host_key(session: &Session, hostname: &str, port: u16,
+                  accept_new: bool) -> Result<()> {
+
+    let (key, key_type) = session.host_key().ok_or_else(|| {
+        anyhow!("Failed to read the host key of {}", hostname)
+    })?;
+    let mut known_hosts = session.known_hosts()?;
+    let path = tilde("~/.ssh/known_hosts");
+    // A missing known_hosts file is the same as one without any hosts
+    let _ = known_hosts.read_file(Path::new(path.as_ref()),
+                                  KnownHostFileKind::OpenSSH);
+    match known_hosts.check_port(hostname, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::NotFound if accept_new => {
+            add_host_key(session, hostname, port, key, key_type.into(),
+                         Path::new(path.as_ref()))
+        },
+        CheckResult::NotFound => {
+            bail!("Host key of {} isn't in {}", hostname, path)
+        },
+        CheckResult::Mismatch => {
+            bail!("Host key of {} doesn't match {}", hostname, path)
+        },
+        CheckResult::Failure => bail!("Failed to check host key of {}",
+                                      hostname),
+    }
+}
+
+/// Appends a host key to a known hosts file
+///
+/// The file is appended to instead of rewritten, so that entries libssh2
+/// doesn't understand are kept.
+fn add_host_key(session: &Session, hostname: &str, port: u16, key: &[u8],
+                format: KnownHostKeyFormat, path: &Path) -> Result<()> {
+    let name = if port == DEFAULT_PORT {
+        hostname.to_owned()
+    } else {
+        format!("[{}]:{}", hostname, port)
+    };
+    let mut known_hosts = session.known_hosts()?;
+    known_hosts.add(&name, key, "", format)?;
+    let line = match known_hosts.hosts()?.first() {
+        Some(host) => {
+            known_hosts.write_string(host, KnownHostFileKind::OpenSSH)?
+        },
+        None => bail!("Failed to read the host key of {}", hostname),
+    };
+
+    OpenOptions::new().create(true).append(true).open(path)
+        .and_then(|mut x| x.write_all(line.as_bytes()))
+        .with_context(|| {
+            format!("Failed to add the host key of {} to {}", hostname,
+                    path.display())
+        })
+}
+
+/// Copies the contents of a directory to another machine via SFTP and merges
+/// them with a destination directory
+///
+/// The destination may start with `~` to refer to the remote user's home
+/// directory.
+///
+/// ```
+/// send_dir(Path::new("/tmp/staging/home"), "~", "user@hostname",
+///          &SshOptions::default())?;
+/// ```
+pub fn send_dir(src: &Path, dst: &str, host: &str, options: &SshOptions) ->
+    Result<()> {
+
+    let session = connect(host, options)?;
+    let sftp = session.sftp().with_context(|| {
+        Error::Transport(String::from("Failed to start SFTP"))
+    })?;
+
+    send_dir_contents(&sftp, src, Path::new(&home_relative_path(dst)))
+        .context("Failed to transfer files over SFTP")
+}
+
+/// Recursively uploads the contents of a local directory to a remote directory
+fn send_dir_contents(sftp: &Sftp, src: &Path, dst: &Path) -> Result<()> {
+    let items = read_dir(src).with_context(|| {
+        format!("Failed to list contents of {}", src.display())
+    })?;
+    for item in items {
+        let _src = item.with_context(|| {
+            format!("Failed to list contents of {}", src.display())
+        })?.path();
+        let _dst = dst.join(_src.file_name().unwrap_or_default());
+
+        if _src.is_dir() {
+            if sftp.stat(&_dst).is_err() {
+                sftp.mkdir(&_dst, 0o755).with_context(|| {
+                    format!("Failed to create {}", _dst.display())
+                })?;
+            }
+            send_dir_contents(sftp, &_src, &_dst)?;
+            continue;
+        }
+
+        let mut local = File::open(&_src).with_context(|| {
+            format!("Failed to read {}", _src.display())
+        })?;
+        let mut remote = sftp.open_mode(&_dst, OpenFlags::WRITE |
+                                        OpenFlags::CREATE | OpenFlags::TRUNCATE,
+                                        file_mode(&_src), OpenType::File)
+            .with_context(|| format!("Failed to create {}", _dst.display()))?;
+        io::copy(&mut local, &mut remote).with_context(|| {
+            format!("Failed to write {}", _dst.display())
+        })?;
+    }
+    Ok(())
+}
+
+/// Returns the permission bits used to create the remote copy of a file
+#[cfg(target_family = "unix")]
+fn file_mode(path: &Path) -> i32 {
+    metadata(path).map_or(0o644, |x| (x.permissions().mode() & 0o7777) as i32)
+}
+#[cfg(not(target_family = "unix"))]
+fn file_mode(_path: &Path) -> i32 {
+    0o644
+}
+
+/// Executes a command on another machine, returning an Err if it exits
+/// unsuccessfully
+///
+/// The command's stdout and stderr are captured instead of printed if
+/// `capture` is provided, and each line is indented like
+/// [`run_command`](super::local::run_command) if `indented` is true. The
+/// connection is closed and a script failure is returned if the command runs
+/// for longer than `timeout`. If `input` is provided, it's written to the
+/// command's stdin.
+///
+/// ```
+/// send_command("echo 'Hello World'", "user@hostname", &SshOptions::default(),
+///              false, None, None, None)?;
+/// ```
+#[allow(clippy::too_many_arguments)]
+pub fn send_command(command: &str, host: &str, options: &SshOptions,
+                    indented: bool, timeout: Option<Duration>,
+                    input: Option<&[u8]>,
+                    capture: Option<&mut CapturedOutput>) -> Result<()> {
+
+    let status = send_command_status(command, host, options, indented, timeout,
+                                     input, capture)?;
+    if status != 0 {
+        bail!("SSH terminated unsuccessfully: exit status: {}", status);
+    }
+    Ok(())
+}
+
+/// Executes a command on another machine like [`send_command`] and returns its
+/// exit status instead of an Err if it exits unsuccessfully
+///
+/// ```
+/// let status = send_command_status("exit 3", "user@hostname",
+///                                  &SshOptions::default(), false, None, None,
+///                                  None)?;
+/// ```
+#[allow(clippy::too_many_arguments)]
+pub fn send_command_status(command: &str, host: &str, options: &SshOptions,
+                           indented: bool, timeout: Option<Duration>,
+                           input: Option<&[u8]>,
+                           mut capture: Option<&mut CapturedOutput>) ->
+    Result<i32> {
+
+    let session = connect(host, options)?;
+    let mut channel = session.channel_session()
+        .and_then(|mut x| {
+            if options.forward_agent {
+                x.request_auth_agent_forwarding()?;
+            }
+            x.exec(command).map(|_| x)
+        })
+        .with_context(|| {
+            Error::Transport(String::from("Failed to start remote command"))
+        })?;
+    if let Some(input) = input {
+        channel.write_all(input)
+            .and_then(|_| channel.send_eof().map_err(io::Error::from))
+            .context("Failed to write input")?;
+    }
+
+    // Read stdout and stderr without blocking so that neither one fills up
+    // while waiting for the other and so that the timeout can be enforced
+    session.set_blocking(false);
+    let start = Instant::now();
+    let mut lines = [vec![], vec![]];
+    let mut buffer = [0; 4096];
+    while !channel.eof() {
+        let mut idle = true;
+        for is_stderr in [false, true] {
+            let result = if is_stderr {
+                channel.stderr().read(&mut buffer)
+            } else {
+                channel.read(&mut buffer)
+            };
+            let n = match result {
+                Ok(n) => n,
+                Err(why) if why.kind() == ErrorKind::WouldBlock => 0,
+                Err(why) => return Err(why).context("Failed to read output"),
+            };
+            if n == 0 { continue; }
+            idle = false;
+
+            let pending = &mut lines[is_stderr as usize];
+            pending.extend_from_slice(&buffer[..n]);
+            write_output(pending, is_stderr, indented, false,
+                         capture.as_deref_mut());
+        }
+
+        if let Some(timeout) = timeout.filter(|x| start.elapsed() >= *x) {
+            return Err(anyhow!(Error::Script(format!("Timed out after {:?}",
+                                                     timeout))));
+        }
+        if idle { thread::sleep(OUTPUT_POLL_INTERVAL); }
+    }
+    for (is_stderr, pending) in [false, true].into_iter().zip(&mut lines) {
+        write_output(pending, is_stderr, indented, true,
+                     capture.as_deref_mut());
+    }
+
+    session.set_blocking(true);
+    channel.wait_close()
+        .and_then(|_| channel.exit_status())
+        .with_context(|| {
+            Error::Transport(String::from("Failed to read exit status"))
+        })
+}
+
+/// Writes the complete lines of a command's pending output, or all of it if
+/// `finished` is true, removing them from `pending`
+fn write_output(pending: &mut Vec<u8>, is_stderr: bool, indented: bool,
+                finished: bool, mut capture: Option<&mut CapturedOutput>) {
+    let end = if finished {
+        pending.len()
+    } else {
+        match pending.iter().rposition(|x| *x == b'\n') {
+            Some(i) => i + 1,
+            None => return,
+        }
+    };
+    if end == 0 { return; }
+    let output: Vec<u8> = pending.drain(..end).collect();
+
+    if !indented {
+        match capture {
+            Some(captured) if is_stderr => {
+                captured.stderr.push_str(&String::from_utf8_lossy(&output));
+            },
+            Some(captured) => {
+                captured.stdout.push_str(&String::from_utf8_lossy(&output));
+            },
+            // Errors writing to stdout/stderr can't be reported anywhere else
+            None if is_stderr => { let _ = io::stderr().write_all(&output); },
+            None => { let _ = io::stdout().write_all(&output); },
+        }
+        return;
+    }
+
+    for line in output.split_inclusive(|x| *x == b'\n') {
+        let mut line = line.to_vec();
+        if !line.ends_with(b"\n") { line.push(b'\n'); }
+        write_line(&line, is_stderr, capture.as_deref_mut());
+    }
+}
+
+/// Executes a command on another machine and streams its stdout to a function,
+/// returning the command's exit status
+///
+/// ```
+/// let (status, hash) = read_command("cat ~/.bashrc", "user@hostname",
+///                                    &SshOptions::default(), |x| {
+///     hash_reader(x, HashAlgorithm::Sha256)
+/// })?;
+/// ```
+pub fn read_command<F, T>(command: &str, host: &str, options: &SshOptions,
+                          read: F) -> Result<(i32, Result<T>)>
+    where F: FnOnce(&mut Channel) -> Result<T> {
+
+    let session = connect(host, options)?;
+    let mut channel = session.channel_session()
+        .and_then(|mut x| x.exec(command).map(|_| x))
+        .with_context(|| {
+            Error::Transport(String::from("Failed to start remote command"))
+        })?;
+    let result = read(&mut channel);
+    let status = channel.wait_close()
+        .and_then(|_| channel.exit_status())
+        .with_context(|| {
+            Error::Transport(String::from("Failed to read exit status"))
+        })?;
+    Ok((status, result))
+}
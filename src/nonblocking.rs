@@ -0,0 +1,83 @@
+//! An async interface to the install pipeline for callers that run on tokio
+//!
+//! The pipeline itself stays synchronous, since SSH, SCP, and scripts are run
+//! as child processes and concurrent steps already have threads of their own.
+//! Installs are instead executed on tokio's blocking thread pool, so that
+//! awaiting one doesn't stall the runtime's other tasks.
+//!
+//! ```
+//! let options = InstallOptions { host: String::from("user@hostname"),
+//!                                ..InstallOptions::default() };
+//! let failure = install_manifest_async(manifest, options).await?;
+//! ```
+
+use anyhow::{Context, Result};
+use super::core::{InstallOptions, install_manifest};
+use super::exit::Failure;
+use super::manifest::Manifest;
+
+/// Executes the steps in a coliru manifest on tokio's blocking thread pool
+///
+/// Behaves like [`install_manifest`]. Installs started concurrently (e.g. to
+/// several hosts) are executed at the same time, since each install only uses
+/// its own options and doesn't change the working directory. Returns an Err if
+/// a critical error occurs or the install panics, and returns the class of
+/// failure with the greatest precedence among any minor errors otherwise.
+///
+/// ```
+/// let laptop = tokio::spawn(install_manifest_async(manifest.clone(),
+///                                                  laptop_options));
+/// let server = install_manifest_async(manifest, server_options).await?;
+/// let laptop = laptop.await??;
+/// ```
+pub async fn install_manifest_async(manifest: Manifest,
+                                    options: InstallOptions) ->
+    Result<Option<Failure>> {
+
+    tokio::task::spawn_blocking(move || install_manifest(manifest, &options))
+        .await.context("Failed to complete install")?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::parse_manifest_str;
+    use crate::reporter::{CaptureReporter, Event};
+    use std::path::Path;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_install_manifest_async_concurrent() {
+        let manifest = parse_manifest_str("\
+steps:
+  - run:
+    - src: script.sh
+", Path::new(".")).unwrap();
+        let reporters = [Arc::new(CaptureReporter::default()),
+                         Arc::new(CaptureReporter::default())];
+        let installs = reporters.clone().map(|reporter| {
+            install_manifest_async(manifest.clone(), InstallOptions {
+                dry_run: true,
+                reporter,
+                ..InstallOptions::default()
+            })
+        });
+
+        // Each install runs on its own blocking thread even though the runtime
+        // only has one worker thread
+        let runtime = tokio::runtime::Builder::new_current_thread().build()
+            .unwrap();
+        let results = runtime.block_on(async {
+            let [first, second] = installs.map(tokio::spawn);
+            (first.await.unwrap(), second.await.unwrap())
+        });
+
+        assert_eq!(results.0.unwrap(), None);
+        assert_eq!(results.1.unwrap(), None);
+        for reporter in reporters {
+            let events = reporter.events.lock().unwrap();
+            assert!(events.iter().any(|x| matches!(x, Event::Action {
+                description, .. } if description.contains("script.sh"))));
+        }
+    }
+}
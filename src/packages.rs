@@ -0,0 +1,240 @@
+//! System package commands for installing packages with apt, dnf, pacman, or
+//! zypper
+//!
+//! The package manager is detected on the machine that packages are installed
+//! on, and each package is checked before it's installed, so that packages
+//! that are already installed are reported as unchanged. Packages are installed
+//! as root, using `sudo` unless the command is already running as root.
+//!
+//! ```
+//! let manager = PackageManager::Apt;
+//! println!("{}", install_command(manager, "build-essential"));
+//! ```
+
+use std::fmt;
+use super::local::quote;
+use super::manifest::PackageOptions;
+
+/// A system package manager
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PackageManager {
+    /// The Debian and Ubuntu package manager
+    Apt,
+
+    /// The Fedora and RHEL package manager
+    Dnf,
+
+    /// The Arch Linux package manager
+    Pacman,
+
+    /// The openSUSE package manager
+    Zypper,
+}
+
+impl PackageManager {
+    /// Every supported package manager, in the order they're detected
+    pub const ALL: [PackageManager; 4] = [PackageManager::Apt,
+                                          PackageManager::Dnf,
+                                          PackageManager::Pacman,
+                                          PackageManager::Zypper];
+
+    /// Returns the program that a package manager installs packages with
+    pub fn program(&self) -> &'static str {
+        match self {
+            PackageManager::Apt => "apt-get",
+            PackageManager::Dnf => "dnf",
+            PackageManager::Pacman => "pacman",
+            PackageManager::Zypper => "zypper",
+        }
+    }
+
+    /// Returns the name of a package for a package manager, which is the
+    /// package's override for the manager if it has one
+    ///
+    /// ```
+    /// assert_eq!(PackageManager::Dnf.package_name(&package), "fd-find");
+    /// ```
+    pub fn package_name<'a>(&self, package: &'a PackageOptions) -> &'a str {
+        let name = match self {
+            PackageManager::Apt => &package.apt,
+            PackageManager::Dnf => &package.dnf,
+            PackageManager::Pacman => &package.pacman,
+            PackageManager::Zypper => &package.zypper,
+        };
+        name.as_deref().unwrap_or(&package.name)
+    }
+}
+
+impl fmt::Display for PackageManager {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PackageManager::Apt => write!(f, "apt"),
+            PackageManager::Dnf => write!(f, "dnf"),
+            PackageManager::Pacman => write!(f, "pacman"),
+            PackageManager::Zypper => write!(f, "zypper"),
+        }
+    }
+}
+
+/// Returns a description of a packages command for output, which includes the
+/// package manager if one was detected
+///
+/// ```
+/// assert_eq!(describe("git", Some(PackageManager::Apt)),
+///            "Install package git with apt");
+/// ```
+pub fn describe(name: &str, manager: Option<PackageManager>) -> String {
+    match manager {
+        Some(manager) => format!("Install package {} with {}", name, manager),
+        None => format!("Install package {}", name),
+    }
+}
+
+/// Returns a shell command that succeeds if a package manager is installed,
+/// without printing anything
+///
+/// ```
+/// assert_eq!(detect_command(PackageManager::Dnf),
+///            "command -v 'dnf' >/dev/null 2>&1");
+/// ```
+pub fn detect_command(manager: PackageManager) -> String {
+    format!("command -v {} >/dev/null 2>&1", quote(manager.program()))
+}
+
+/// Returns a shell command that succeeds if a package is already installed,
+/// without printing anything
+///
+/// ```
+/// assert_eq!(check_command(PackageManager::Pacman, "git"),
+///            "pacman -Q 'git' >/dev/null 2>&1");
+/// ```
+pub fn check_command(manager: PackageManager, name: &str) -> String {
+    check_word(manager, &quote(name))
+}
+
+/// Returns a shell command that installs a package as root
+///
+/// ```
+/// assert_eq!(install_command(PackageManager::Dnf, "git"),
+///            "if [ \"$(id -u)\" -eq 0 ]; then dnf install -y 'git'; \
+///             else sudo dnf install -y 'git'; fi");
+/// ```
+pub fn install_command(manager: PackageManager, name: &str) -> String {
+    install_word(manager, &quote(name))
+}
+
+/// Returns a POSIX shell function named `coliru_package` that detects the
+/// package manager and installs a package unless it's already installed
+///
+/// The function takes the package's name for each manager in
+/// [`PackageManager::ALL`] as separate arguments.
+///
+/// ```
+/// script.push_str(&shell_function());
+/// ```
+pub fn shell_function() -> String {
+    let mut function = String::from("coliru_package() {\n");
+    for (i, manager) in PackageManager::ALL.iter().enumerate() {
+        let word = format!("\"${}\"", i + 1);
+        function.push_str(&format!(
+            "    {} {}; then\n        {} || {}\n",
+            if i == 0 { "if" } else { "elif" }, detect_command(*manager),
+            check_word(*manager, &word), install_word(*manager, &word)));
+    }
+    function.push_str("    else
+        echo 'No supported package manager found' >&2
+        return 1
+    fi
+}
+");
+    function
+}
+
+/// Returns a shell command that checks whether a package is installed, given a
+/// shell word that expands to its name
+fn check_word(manager: PackageManager, word: &str) -> String {
+    let command = match manager {
+        PackageManager::Apt => format!("dpkg -s {}", word),
+        PackageManager::Dnf | PackageManager::Zypper => {
+            format!("rpm -q {}", word)
+        },
+        PackageManager::Pacman => format!("pacman -Q {}", word),
+    };
+    format!("{} >/dev/null 2>&1", command)
+}
+
+/// Returns a shell command that installs a package as root, given a shell word
+/// that expands to its name
+fn install_word(manager: PackageManager, word: &str) -> String {
+    let command = match manager {
+        PackageManager::Apt => {
+            format!("env DEBIAN_FRONTEND=noninteractive apt-get install -y {}",
+                    word)
+        },
+        PackageManager::Dnf => format!("dnf install -y {}", word),
+        PackageManager::Pacman => {
+            format!("pacman -S --noconfirm --needed {}", word)
+        },
+        PackageManager::Zypper => {
+            format!("zypper --non-interactive install {}", word)
+        },
+    };
+    format!("if [ \"$(id -u)\" -eq 0 ]; then {0}; else sudo {0}; fi", command)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packages_commands() {
+        assert_eq!(describe("git", Some(PackageManager::Zypper)),
+                   "Install package git with zypper");
+        assert_eq!(describe("git", None), "Install package git");
+        assert_eq!(detect_command(PackageManager::Apt),
+                   "command -v 'apt-get' >/dev/null 2>&1");
+        assert_eq!(check_command(PackageManager::Apt, "git"),
+                   "dpkg -s 'git' >/dev/null 2>&1");
+        assert_eq!(check_command(PackageManager::Zypper, "git"),
+                   "rpm -q 'git' >/dev/null 2>&1");
+        assert_eq!(install_command(PackageManager::Apt, "git"),
+                   "if [ \"$(id -u)\" -eq 0 ]; then env \
+                    DEBIAN_FRONTEND=noninteractive apt-get install -y 'git'; \
+                    else sudo env DEBIAN_FRONTEND=noninteractive apt-get \
+                    install -y 'git'; fi");
+        assert_eq!(install_command(PackageManager::Pacman, "git"),
+                   "if [ \"$(id -u)\" -eq 0 ]; then pacman -S --noconfirm \
+                    --needed 'git'; else sudo pacman -S --noconfirm --needed \
+                    'git'; fi");
+    }
+
+    #[test]
+    fn test_packages_package_name() {
+        let package = PackageOptions {
+            name: String::from("fd"),
+            apt: Some(String::from("fd-find")),
+            dnf: None,
+            pacman: None,
+            zypper: None,
+        };
+
+        assert_eq!(PackageManager::Apt.package_name(&package), "fd-find");
+        assert_eq!(PackageManager::Dnf.package_name(&package), "fd");
+    }
+
+    #[test]
+    fn test_packages_shell_function() {
+        let function = shell_function();
+
+        assert_eq!(function.starts_with("\
+coliru_package() {
+    if command -v 'apt-get' >/dev/null 2>&1; then
+        dpkg -s \"$1\" >/dev/null 2>&1 || if"), true);
+        assert_eq!(function.contains("
+    elif command -v 'zypper' >/dev/null 2>&1; then
+        rpm -q \"$4\" >/dev/null 2>&1 || if [ \"$(id -u)\" -eq 0 ]; then \
+zypper --non-interactive install \"$4\"; else sudo zypper --non-interactive \
+install \"$4\"; fi
+"), true);
+    }
+}
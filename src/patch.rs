@@ -0,0 +1,268 @@
+//! Application of unified diffs to files that coliru doesn't fully own
+//!
+//! A patch command applies a unified diff from the dotfile repository to an
+//! existing destination file. Patches that have already been applied are
+//! detected, so installing the same patch again leaves the file unchanged.
+//!
+//! ```
+//! let rendered = render_patch("sshd_config.patch", "/etc/ssh/sshd_config",
+//!                             Path::new("."))?;
+//! copy_file(rendered.path().to_str().unwrap(), "/etc/ssh/sshd_config")?;
+//! ```
+
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use tempfile::NamedTempFile;
+use super::local::resolve_local_path;
+
+/// A single hunk of a unified diff
+#[derive(Debug, PartialEq)]
+struct Hunk {
+    /// The line number of the hunk in the original file
+    old_start: usize,
+
+    /// The line number of the hunk in the patched file
+    new_start: usize,
+
+    /// The lines of the hunk in the original file
+    old: Vec<String>,
+
+    /// The lines of the hunk in the patched file
+    new: Vec<String>,
+}
+
+/// Applies a unified diff to the contents of a file
+///
+/// Each hunk is applied at the nearest position where its context matches,
+/// so patches still apply if lines were added or removed elsewhere in the
+/// file. The contents are returned unchanged if the patch has already been
+/// applied. Returns an Err if the patch is malformed or doesn't apply cleanly.
+///
+/// ```
+/// let patch = "@@ -1,2 +1,2 @@\n a\n-b\n+c\n";
+/// assert_eq!(apply_patch("a\nb\n", patch)?, "a\nc\n");
+/// assert_eq!(apply_patch("a\nc\n", patch)?, "a\nc\n");
+/// ```
+pub fn apply_patch(contents: &str, patch: &str) -> Result<String> {
+    let hunks = parse_patch(patch)?;
+    let lines: Vec<String> = contents.lines().map(String::from).collect();
+
+    if apply_hunks(&lines, &hunks, true).is_some() {
+        return Ok(contents.to_owned());
+    }
+    let Some(patched) = apply_hunks(&lines, &hunks, false) else {
+        bail!("Patch doesn't apply cleanly");
+    };
+
+    let mut result = patched.join("\n");
+    if contents.ends_with('\n') || contents.is_empty() { result.push('\n'); }
+    Ok(result)
+}
+
+/// Parses the hunks of a unified diff, ignoring any headers
+fn parse_patch(patch: &str) -> Result<Vec<Hunk>> {
+    let mut hunks: Vec<Hunk> = vec![];
+    let (mut old_left, mut new_left) = (0, 0);
+
+    for line in patch.lines() {
+        if old_left == 0 && new_left == 0 {
+            let Some(header) = line.strip_prefix("@@ ") else { continue };
+            let (old_start, old_len, new_start, new_len) = parse_header(header)
+                .with_context(|| format!("Invalid hunk header: {}", line))?;
+            hunks.push(Hunk { old_start, new_start, old: vec![],
+                              new: vec![] });
+            (old_left, new_left) = (old_len, new_len);
+            continue;
+        }
+
+        let Some(hunk) = hunks.last_mut() else { continue };
+        let kind = line.chars().next();
+        let text = &line[kind.map_or(0, char::len_utf8)..];
+        match kind {
+            Some(' ') | None if old_left > 0 && new_left > 0 => {
+                hunk.old.push(text.to_owned());
+                hunk.new.push(text.to_owned());
+                old_left -= 1;
+                new_left -= 1;
+            },
+            Some('-') if old_left > 0 => {
+                hunk.old.push(text.to_owned());
+                old_left -= 1;
+            },
+            Some('+') if new_left > 0 => {
+                hunk.new.push(text.to_owned());
+                new_left -= 1;
+            },
+            Some('\\') => {}, // "\ No newline at end of file"
+            _ => bail!("Invalid line in hunk: {}", line),
+        }
+    }
+
+    if old_left > 0 || new_left > 0 {
+        bail!("Patch ends in the middle of a hunk");
+    }
+    if hunks.is_empty() {
+        bail!("Patch doesn't contain any hunks");
+    }
+    Ok(hunks)
+}
+
+/// Parses the ranges in a hunk header (e.g. `-12,5 +12,6 @@`) into the start
+/// and length of the hunk in the original and patched files
+fn parse_header(header: &str) -> Option<(usize, usize, usize, usize)> {
+    let mut ranges = header.split_whitespace();
+    let old = ranges.next()?.strip_prefix('-')?;
+    let new = ranges.next()?.strip_prefix('+')?;
+
+    let parse_range = |range: &str| -> Option<(usize, usize)> {
+        match range.split_once(',') {
+            Some((start, len)) => {
+                Some((start.parse().ok()?, len.parse().ok()?))
+            },
+            None => Some((range.parse().ok()?, 1)),
+        }
+    };
+    let (old_start, old_len) = parse_range(old)?;
+    let (new_start, new_len) = parse_range(new)?;
+    Some((old_start, old_len, new_start, new_len))
+}
+
+/// Applies a set of hunks to the lines of a file, or reverts them if `reverse`
+/// is true, returning None if any hunk doesn't match
+fn apply_hunks(lines: &[String], hunks: &[Hunk], reverse: bool) ->
+    Option<Vec<String>> {
+
+    let mut result = vec![];
+    let mut pos = 0;
+    let mut offset = 0;
+
+    for hunk in hunks {
+        let (start, old, new) = if reverse {
+            (hunk.new_start, &hunk.new, &hunk.old)
+        } else {
+            (hunk.old_start, &hunk.old, &hunk.new)
+        };
+        // Empty ranges start after the given line instead of at it, and each
+        // hunk is expected to be shifted as far as the previous hunk was
+        let start = if old.is_empty() { start } else { start.max(1) - 1 };
+        let expected = start.saturating_add_signed(offset);
+
+        // Find the nearest match that doesn't overlap the previous hunk
+        let matches = |i: usize| {
+            i >= pos && lines.get(i..i+old.len()) == Some(old.as_slice())
+        };
+        let found = (0..=lines.len().max(expected)).flat_map(|d| {
+            [expected.checked_add(d), expected.checked_sub(d)]
+        }).flatten().find(|i| matches(*i))?;
+
+        offset = found as isize - start as isize;
+        result.extend_from_slice(&lines[pos..found]);
+        result.extend_from_slice(new);
+        pos = found + old.len();
+    }
+
+    result.extend_from_slice(&lines[pos..]);
+    Some(result)
+}
+
+/// Writes a copy of a destination file with a patch applied to it to a
+/// temporary file
+///
+/// Tildes in the destination are expanded if present and relative paths are
+/// relative to `base_dir`. The temporary file has the same permissions as the
+/// destination and is deleted when the returned value is dropped.
+///
+/// ```
+/// let rendered = render_patch("sshd_config.patch", "/etc/ssh/sshd_config",
+///                             Path::new("dotfiles"))?;
+/// ```
+pub fn render_patch(src: &str, dst: &str, base_dir: &Path) ->
+    Result<NamedTempFile> {
+
+    let patch = fs::read_to_string(resolve_local_path(src, base_dir))
+        .with_context(|| format!("Failed to read {}", src))?;
+    let _dst = resolve_local_path(dst, base_dir);
+    let contents = fs::read_to_string(&_dst).with_context(|| {
+        format!("Failed to read {}", dst)
+    })?;
+    let metadata = fs::metadata(&_dst).with_context(|| {
+        format!("Failed to read {}", dst)
+    })?;
+    let patched = apply_patch(&contents, &patch).with_context(|| {
+        format!("Failed to apply {} to {}", src, dst)
+    })?;
+
+    let mut rendered = NamedTempFile::new()
+        .context("Failed to create temporary file")?;
+    rendered.write_all(patched.as_bytes())
+        .and_then(|_| {
+            fs::set_permissions(rendered.path(), metadata.permissions())
+        })
+        .with_context(|| format!("Failed to apply {} to {}", src, dst))?;
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{setup_integration, write_file};
+
+    const PATCH: &str = "\
+--- a/config
++++ b/config
+@@ -2,3 +2,3 @@
+ b
+-c
++C
+ d
+@@ -6,0 +7,1 @@
++g
+";
+
+    #[test]
+    fn test_apply_patch_basic() {
+        let contents = "a\nb\nc\nd\ne\nf\n";
+
+        let patched = apply_patch(contents, PATCH).unwrap();
+        let reapplied = apply_patch(&patched, PATCH).unwrap();
+        let offset = apply_patch(&format!("z\n{}", contents), PATCH).unwrap();
+        let conflict = apply_patch("a\nb\nx\nd\n", PATCH);
+
+        assert_eq!(patched, "a\nb\nC\nd\ne\nf\ng\n");
+        assert_eq!(reapplied, patched);
+        assert_eq!(offset, "z\na\nb\nC\nd\ne\nf\ng\n");
+        assert_eq!(conflict.unwrap_err().to_string(),
+                   "Patch doesn't apply cleanly");
+    }
+
+    #[test]
+    fn test_apply_patch_invalid() {
+        let truncated = apply_patch("a\n", "@@ -1,2 +1,2 @@\n a\n");
+        let empty = apply_patch("a\n", "--- a/config\n+++ b/config\n");
+
+        assert_eq!(truncated.unwrap_err().to_string(),
+                   "Patch ends in the middle of a hunk");
+        assert_eq!(empty.unwrap_err().to_string(),
+                   "Patch doesn't contain any hunks");
+    }
+
+    #[test]
+    fn test_render_patch_basic() {
+        let tmp = setup_integration("test_render_patch_basic");
+
+        let src = tmp.local.join("config.patch");
+        let dst = tmp.local.join("config");
+        write_file(&src, PATCH);
+        write_file(&dst, "a\nb\nc\nd\ne\nf\n");
+
+        let rendered = render_patch("config.patch", "config", &tmp.local)
+            .unwrap();
+        let missing = render_patch("config.patch", "missing", &tmp.local);
+
+        assert_eq!(fs::read_to_string(rendered.path()).unwrap(),
+                   "a\nb\nC\nd\ne\nf\ng\n");
+        assert_eq!(missing.is_err(), true);
+    }
+}
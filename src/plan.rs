@@ -0,0 +1,854 @@
+//! Resolved execution plans for manifests
+//!
+//! A plan lists every step in a manifest along with the exact actions that
+//! would be executed, without executing any of them.
+//!
+//! ```
+//! let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+//! let plan = build_plan(&manifest, &[String::from("linux")],
+//!                       &Host::default(), LinkMode::Symlink)?;
+//! print_plan(&plan);
+//! ```
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use serde::Serialize;
+use shellexpand::tilde;
+use std::fmt;
+use std::path::{Path, absolute};
+use super::brew::{brewfiles, install_command};
+use super::core::SSH_INSTALL_DIR;
+use super::cron;
+use super::defaults::write_command;
+use super::extension;
+use super::facts::{expand_facts, local_facts};
+use super::manifest::{BrewOptions, CopyLinkOptions, ExtensionOptions, LinkMode,
+    Manifest, unmatched_tag_rule};
+use super::ssh::{Host, resolve_path};
+use super::systemd::{enable_command, start_command, unit_path};
+use super::tag_expr::join_tag_rules;
+use super::target::host_shell;
+
+/// The type of a planned action
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    /// Copy a file to its destination
+    Copy,
+
+    /// Link a file to its destination
+    Link,
+
+    /// Hard link a file to its destination
+    HardLink,
+
+    /// Merge a file into its destination as a marked block
+    Block,
+
+    /// Apply a unified diff to its destination
+    Patch,
+
+    /// Install a system package
+    Package,
+
+    /// Install Homebrew packages
+    Brew,
+
+    /// Clone or update a Git repository
+    Repo,
+
+    /// Download a file and verify its checksum
+    Fetch,
+
+    /// Install a systemd unit and enable or start it
+    Systemd,
+
+    /// Write a macOS user default
+    Defaults,
+
+    /// Install an editor extension
+    Extension,
+
+    /// Add or remove a crontab entry
+    Cron,
+
+    /// Run a script
+    Run,
+}
+impl fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ActionKind::Copy => write!(f, "Copy"),
+            ActionKind::Link => write!(f, "Link"),
+            ActionKind::HardLink => write!(f, "Hard link"),
+            ActionKind::Block => write!(f, "Merge"),
+            ActionKind::Patch => write!(f, "Patch"),
+            ActionKind::Package => write!(f, "Package"),
+            ActionKind::Brew => write!(f, "Brew"),
+            ActionKind::Repo => write!(f, "Clone"),
+            ActionKind::Fetch => write!(f, "Download"),
+            ActionKind::Systemd => write!(f, "Install"),
+            ActionKind::Defaults => write!(f, "Write"),
+            ActionKind::Extension => write!(f, "Extension"),
+            ActionKind::Cron => write!(f, "Cron"),
+            ActionKind::Run => write!(f, "Run"),
+        }
+    }
+}
+
+/// A single action that would be executed during installation
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Action {
+    /// The type of action
+    pub kind: ActionKind,
+
+    /// The absolute path of the source file or script, the name of a system or
+    /// Homebrew package, systemd unit, or cron job, the domain of a default,
+    /// the ID of an extension, or the URL of a Git repository or downloaded
+    /// file
+    pub src: String,
+
+    /// The resolved destination of a copy, link, repo, fetch, or systemd
+    /// action
+    pub dst: Option<String>,
+
+    /// The shell command of a run, brew, systemd, defaults, or extension
+    /// action, or the crontab line of a cron action
+    pub command: Option<String>,
+
+    /// The directory that a run, brew, systemd, or extension action's command
+    /// is executed in
+    pub cwd: Option<String>,
+}
+
+/// A manifest step in an execution plan
+#[derive(Debug, PartialEq, Serialize)]
+pub struct PlannedStep {
+    /// The 1-based index of the step in the manifest
+    pub step: usize,
+
+    /// The step's tags
+    pub tags: Vec<String>,
+
+    /// Why the step would be skipped, if it doesn't match the tag rules
+    pub skip_reason: Option<String>,
+
+    /// The actions that would be executed, in order
+    pub actions: Vec<Action>,
+}
+
+/// The fully resolved execution plan of a manifest
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Plan {
+    /// The machine that dotfiles would be installed on, if not the local
+    /// machine
+    pub host: Option<String>,
+
+    /// The tag rules used to filter steps
+    pub tag_rules: Vec<String>,
+
+    /// The shell commands that would be executed before the first step
+    pub pre_install: Vec<String>,
+
+    /// Every step in the manifest, including skipped steps
+    pub steps: Vec<PlannedStep>,
+
+    /// The shell commands that would be executed after the last step
+    pub post_install: Vec<String>,
+
+    /// The directory that hooks would be executed in
+    pub hook_cwd: String,
+}
+
+/// Resolves the actions that installing a manifest would execute, without
+/// executing them
+///
+/// Unlike a dry run, steps that don't match `tag_rules` are included along with
+/// the reason they would be skipped, and source and destination paths are
+/// resolved. Local destinations are made absolute and remote destinations are
+/// resolved relative to the remote install directory. Facts about the local
+/// machine are substituted into run postfixes, but facts about other machines
+/// aren't collected. Returns an Err if a path can't be resolved.
+///
+/// ```
+/// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// let plan = build_plan(&manifest, &[String::from("linux")],
+///                       &Host::default(), LinkMode::Symlink)?;
+/// ```
+pub fn build_plan(manifest: &Manifest, tag_rules: &[String], host: &Host,
+                  link_mode: LinkMode) -> Result<Plan> {
+
+    let base_dir = absolute(&manifest.base_dir).with_context(|| {
+        format!("Failed to make {} absolute", manifest.base_dir.display())
+    })?;
+
+    let facts = local_facts();
+    let mut steps = vec![];
+    for (i, step) in manifest.steps.iter().enumerate() {
+        let mut planned = PlannedStep {
+            step: i+1,
+            tags: step.tags.clone(),
+            skip_reason: unmatched_tag_rule(tag_rules, &step.tags).map(|x| {
+                format!("doesn't match tag rule {}", x)
+            }),
+            actions: vec![],
+        };
+        if planned.skip_reason.is_some() {
+            steps.push(planned);
+            continue;
+        }
+
+        for _copy in &step.copy {
+            planned.actions.push(plan_copy_link(ActionKind::Copy, _copy,
+                                                &base_dir, host)?);
+        }
+
+        for link in &step.link {
+            // Links are installed as copies over SSH unless they're remote
+            let link_kind = match link.link_mode(link_mode) {
+                LinkMode::Symlink if link.remote => ActionKind::Link,
+                _ if !host.is_local() => ActionKind::Copy,
+                LinkMode::Symlink => ActionKind::Link,
+                LinkMode::Hardlink => ActionKind::HardLink,
+                LinkMode::Copy => ActionKind::Copy,
+            };
+            planned.actions.push(plan_copy_link(link_kind, link, &base_dir,
+                                                host)?);
+        }
+
+        for block in &step.block {
+            planned.actions.push(plan_file(ActionKind::Block, &block.src,
+                                           &block.dst, &base_dir, host)?);
+        }
+
+        for patch in &step.patch {
+            planned.actions.push(plan_file(ActionKind::Patch, &patch.src,
+                                           &patch.dst, &base_dir, host)?);
+        }
+
+        let cwd = if host.is_local() {
+            base_dir.to_string_lossy().to_string()
+        } else {
+            format!("~/{}", SSH_INSTALL_DIR)
+        };
+
+        if !host.is_local() {
+            // Scripts, Brewfiles, and unit files are copied to the remote
+            // machine before they're used
+            let units = step.systemd.iter().filter_map(|x| x.src.as_deref());
+            let runs = step.run.iter().map(|x| x.src.as_str());
+            for src in brewfiles(&step.brew).into_iter().chain(units)
+                .chain(runs) {
+                let run_copy = CopyLinkOptions::script(src, src);
+                planned.actions.push(plan_copy_link(ActionKind::Copy,
+                    &run_copy, &base_dir, host)?);
+            }
+        }
+
+        for package in &step.packages {
+            // The package manager isn't detected while planning
+            planned.actions.push(Action {
+                kind: ActionKind::Package,
+                src: package.name.clone(),
+                dst: None,
+                command: None,
+                cwd: None,
+            });
+        }
+
+        for brew in &step.brew {
+            let src = match brew {
+                BrewOptions::Formula(name) | BrewOptions::Cask(name) => {
+                    name.clone()
+                },
+                BrewOptions::Brewfile(path) => resolve_src(path, &base_dir),
+            };
+            planned.actions.push(Action {
+                kind: ActionKind::Brew,
+                src,
+                dst: None,
+                command: Some(install_command(brew)),
+                cwd: Some(cwd.clone()),
+            });
+        }
+
+        for repo in &step.repo {
+            let mut action = plan_file(ActionKind::Repo, "", &repo.dst,
+                                       &base_dir, host)?;
+            action.src = repo.url.clone();
+            planned.actions.push(action);
+        }
+
+        for fetch in &step.fetch {
+            let mut action = plan_file(ActionKind::Fetch, "", &fetch.dst,
+                                       &base_dir, host)?;
+            action.src = fetch.url.clone();
+            planned.actions.push(action);
+        }
+
+        for unit in &step.systemd {
+            let mut action = match &unit.src {
+                Some(src) => plan_file(ActionKind::Systemd, src,
+                                       &unit_path(unit), &base_dir, host)?,
+                None => Action {
+                    kind: ActionKind::Systemd,
+                    src: unit.unit.clone(),
+                    dst: None,
+                    command: None,
+                    cwd: None,
+                },
+            };
+            let mut commands = vec![];
+            if unit.enable {
+                commands.push(enable_command(unit));
+            }
+            if unit.start {
+                commands.push(start_command(unit, false));
+            }
+            if !commands.is_empty() {
+                action.command = Some(commands.join(" && "));
+                action.cwd = Some(cwd.clone());
+            }
+            planned.actions.push(action);
+        }
+
+        for entry in &step.defaults {
+            planned.actions.push(Action {
+                kind: ActionKind::Defaults,
+                src: entry.domain.clone(),
+                dst: None,
+                command: Some(write_command(entry)),
+                cwd: None,
+            });
+        }
+
+        for entry in &step.extensions {
+            let (ExtensionOptions::VsCode { id, .. } |
+                 ExtensionOptions::JetBrains { id, .. }) = entry;
+            planned.actions.push(Action {
+                kind: ActionKind::Extension,
+                src: id.clone(),
+                dst: None,
+                command: Some(extension::install_command(entry)),
+                cwd: Some(cwd.clone()),
+            });
+        }
+
+        for job in &step.cron {
+            planned.actions.push(Action {
+                kind: ActionKind::Cron,
+                src: job.name.clone(),
+                dst: None,
+                command: (!job.remove).then(|| cron::line(job)),
+                cwd: None,
+            });
+        }
+
+        for run in &step.run {
+            // Facts about other machines aren't collected while planning
+            let shell = run.shell.unwrap_or(host_shell(host));
+            let postfix = if host.is_local() {
+                expand_facts(&run.postfix, &facts, Some(shell))
+            } else {
+                run.postfix.clone()
+            };
+            let postfix = postfix.replace("$COLIRU_RULES",
+                                          &join_tag_rules(tag_rules, shell));
+            let cmd = format!("{} {} {}", run.prefix, run.src, postfix);
+            planned.actions.push(Action {
+                kind: ActionKind::Run,
+                src: resolve_src(&run.src, &base_dir),
+                dst: None,
+                command: Some(cmd),
+                cwd: Some(cwd.clone()),
+            });
+        }
+
+        steps.push(planned);
+    }
+
+    Ok(Plan {
+        host: if host.is_local() { None } else { Some(host.name.clone()) },
+        tag_rules: tag_rules.to_vec(),
+        pre_install: manifest.pre_install.clone(),
+        steps,
+        post_install: manifest.post_install.clone(),
+        hook_cwd: if host.is_local() {
+            base_dir.to_string_lossy().to_string()
+        } else {
+            String::from("~")
+        },
+    })
+}
+
+/// Resolves a single copy or link command, listing each source file of a
+/// concatenation
+fn plan_copy_link(kind: ActionKind, entry: &CopyLinkOptions, base_dir: &Path,
+                  host: &Host) -> Result<Action> {
+
+    let mut action = plan_file(kind, &entry.src, &entry.dst, base_dir, host)?;
+    if !entry.concat.is_empty() {
+        action.src = entry.concat.iter().map(|x| resolve_src(x, base_dir))
+            .collect::<Vec<_>>().join(" + ");
+    }
+    Ok(action)
+}
+
+/// Resolves a single command that installs a source file at a destination
+fn plan_file(kind: ActionKind, src: &str, dst: &str, base_dir: &Path,
+             host: &Host) -> Result<Action> {
+
+    let _dst = if host.is_local() {
+        // Relative destinations are relative to the manifest during installs
+        let _dst = base_dir.join(tilde(dst).as_ref());
+        absolute(&_dst).with_context(|| {
+            format!("Failed to make {} absolute", dst)
+        })?.to_string_lossy().to_string()
+    } else {
+        format!("{}:{}", host,
+                resolve_path(dst, &format!("~/{}", SSH_INSTALL_DIR),
+                             host.options.remote_os))
+    };
+
+    Ok(Action {
+        kind,
+        src: resolve_src(src, base_dir),
+        dst: Some(_dst),
+        command: None,
+        cwd: None,
+    })
+}
+
+/// Resolves the path of a source file, which is relative to the manifest
+fn resolve_src(src: &str, base_dir: &Path) -> String {
+    base_dir.join(src).to_string_lossy().to_string()
+}
+
+/// Prints an execution plan in a human-readable format
+///
+/// ```
+/// print_plan(&build_plan(&manifest, &[], &Host::default(),
+///                       LinkMode::Symlink)?);
+/// ```
+pub fn print_plan(plan: &Plan) {
+    let target = plan.host.as_deref().unwrap_or("local machine");
+    println!("{} {}", "Target:".bold(), target);
+    println!("{} {}", "Tag rules:".bold(), if plan.tag_rules.is_empty() {
+        String::from("(none)")
+    } else {
+        plan.tag_rules.join(" ")
+    });
+
+    print_hooks(&plan.pre_install, "pre_install", &plan.hook_cwd);
+
+    for step in &plan.steps {
+        let step_str = format!("[{}/{}]", step.step, plan.steps.len()).bold();
+        print!("{} Tags: {}", step_str, if step.tags.is_empty() {
+            String::from("(none)")
+        } else {
+            step.tags.join(", ")
+        });
+        if let Some(reason) = &step.skip_reason {
+            println!(" {}", format!("(skipped: {})", reason).yellow());
+            continue;
+        }
+        println!();
+
+        for action in &step.actions {
+            match action.kind {
+                ActionKind::Run => {
+                    println!("  Run {} in {}",
+                             action.command.as_deref().unwrap_or_default(),
+                             action.cwd.as_deref().unwrap_or_default());
+                },
+                ActionKind::Package => {
+                    println!("  Install package {} unless installed",
+                             action.src);
+                },
+                ActionKind::Brew | ActionKind::Extension => {
+                    println!("  Run {} in {} unless installed",
+                             action.command.as_deref().unwrap_or_default(),
+                             action.cwd.as_deref().unwrap_or_default());
+                },
+                ActionKind::Cron => match &action.command {
+                    Some(line) => {
+                        println!("  Add cron job {}: {}", action.src, line);
+                    },
+                    None => println!("  Remove cron job {}", action.src),
+                },
+                ActionKind::Defaults => {
+                    println!("  Run {} unless already set",
+                             action.command.as_deref().unwrap_or_default());
+                },
+                ActionKind::Systemd => {
+                    let command = action.command.as_deref().unwrap_or_default();
+                    match &action.dst {
+                        Some(dst) if command.is_empty() => {
+                            println!("  Install {} to {}", action.src, dst);
+                        },
+                        Some(dst) => {
+                            println!("  Install {} to {} and run {}",
+                                     action.src, dst, command);
+                        },
+                        None => println!("  Run {}", command),
+                    }
+                },
+                ActionKind::Block => {
+                    println!("  Merge {} into {}", action.src,
+                             action.dst.as_deref().unwrap_or_default());
+                },
+                ActionKind::Patch => {
+                    println!("  Patch {} with {}",
+                             action.dst.as_deref().unwrap_or_default(),
+                             action.src);
+                },
+                _ => {
+                    println!("  {} {} to {}", action.kind, action.src,
+                             action.dst.as_deref().unwrap_or_default());
+                },
+            }
+        }
+    }
+
+    print_hooks(&plan.post_install, "post_install", &plan.hook_cwd);
+}
+
+/// Prints the pre-install or post-install hooks of an execution plan
+fn print_hooks(hooks: &[String], name: &str, cwd: &str) {
+    for cmd in hooks {
+        println!("{} Run {} in {}", format!("[{}]", name).bold(), cmd, cwd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::SshOptions;
+    use crate::manifest::{FetchOptions, PackageOptions, RepoOptions, RunOptions,
+        Step, SystemdOptions, SystemdScope};
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
+
+    fn local() -> Host {
+        Host::default()
+    }
+
+    fn remote() -> Host {
+        Host::new("user@host", SshOptions::default())
+    }
+
+    fn manifest() -> Manifest {
+        Manifest {
+            steps: vec![
+                Step {
+                    copy: vec![CopyLinkOptions {
+                        src: String::from("foo"),
+                        dst: String::from("dir/foo"),
+                        link_type: None,
+                        preserve_mtime: false,
+                        relative: false,
+                        remote: false,
+                        eol: None,
+                        concat: vec![],
+                        template: None,
+                    }],
+                    link: vec![CopyLinkOptions {
+                        src: String::from("bar"),
+                        dst: String::from("/etc/bar"),
+                        link_type: None,
+                        preserve_mtime: false,
+                        relative: false,
+                        remote: false,
+                        eol: None,
+                        concat: vec![],
+                        template: None,
+                    }],
+                    block: vec![],
+                    patch: vec![],
+                    packages: vec![],
+                    brew: vec![],
+                    repo: vec![],
+                    fetch: vec![],
+                    systemd: vec![],
+                    defaults: vec![],
+                    extensions: vec![],
+                    cron: vec![],
+                    run: vec![RunOptions {
+                        src: String::from("script.sh"),
+                        prefix: String::from("sh"),
+                        postfix: String::from("$COLIRU_RULES"),
+                        timeout: None,
+                        retries: 0,
+                        retry_delay: 0,
+                        user: None,
+                        shell: None,
+                        cleanup: false,
+                        env: BTreeMap::new(),
+                        creates: vec![],
+                    }],
+                    stow: vec![],
+                    parallel: false,
+                    tags: vec![String::from("linux")],
+                },
+                Step {
+                    copy: vec![],
+                    link: vec![CopyLinkOptions {
+                        src: String::from("baz"),
+                        dst: String::from("baz"),
+                        link_type: None,
+                        preserve_mtime: false,
+                        relative: false,
+                        remote: false,
+                        eol: None,
+                        concat: vec![],
+                        template: None,
+                    }],
+                    block: vec![],
+                    patch: vec![],
+                    packages: vec![],
+                    brew: vec![],
+                    repo: vec![],
+                    fetch: vec![],
+                    systemd: vec![],
+                    defaults: vec![],
+                    extensions: vec![],
+                    cron: vec![],
+                    run: vec![],
+                    stow: vec![],
+                    parallel: false,
+                    tags: vec![String::from("windows")],
+                },
+            ],
+            pre_install: vec![String::from("git pull")],
+            post_install: vec![],
+            hosts: BTreeMap::new(),
+            vars: BTreeMap::new(),
+            exclusive_tags: vec![],
+            base_dir: PathBuf::from("/repo"),
+        }
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_build_plan_local() {
+        let rules = [String::from("linux")];
+
+        let plan = build_plan(&manifest(), &rules, &local(), LinkMode::Symlink)
+            .unwrap();
+
+        assert_eq!(plan.host, None);
+        assert_eq!(plan.steps[0].skip_reason, None);
+        assert_eq!(plan.steps[0].actions, vec![
+            Action {
+                kind: ActionKind::Copy,
+                src: String::from("/repo/foo"),
+                dst: Some(String::from("/repo/dir/foo")),
+                command: None,
+                cwd: None,
+            },
+            Action {
+                kind: ActionKind::Link,
+                src: String::from("/repo/bar"),
+                dst: Some(String::from("/etc/bar")),
+                command: None,
+                cwd: None,
+            },
+            Action {
+                kind: ActionKind::Run,
+                src: String::from("/repo/script.sh"),
+                dst: None,
+                command: Some(String::from("sh script.sh linux")),
+                cwd: Some(String::from("/repo")),
+            },
+        ]);
+        assert_eq!(plan.steps[1].skip_reason,
+                   Some(String::from("doesn't match tag rule linux")));
+        assert_eq!(plan.steps[1].actions, vec![]);
+        assert_eq!(plan.pre_install, vec![String::from("git pull")]);
+        assert_eq!(plan.hook_cwd, "/repo");
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_build_plan_ssh() {
+        let plan = build_plan(&manifest(), &[], &remote(),
+                              LinkMode::Symlink).unwrap();
+
+        assert_eq!(plan.host, Some(String::from("user@host")));
+        let dsts: Vec<Option<String>> = plan.steps.iter().flat_map(|x| {
+            x.actions.iter().map(|y| y.dst.clone())
+        }).collect();
+        assert_eq!(dsts, vec![
+            Some(String::from("user@host:~/.coliru/dir/foo")),
+            Some(String::from("user@host:/etc/bar")),
+            Some(String::from("user@host:~/.coliru/script.sh")),
+            None,
+            Some(String::from("user@host:~/.coliru/baz")),
+        ]);
+        assert_eq!(plan.steps[0].actions[1].kind, ActionKind::Copy);
+        assert_eq!(plan.steps[0].actions[3].cwd,
+                   Some(String::from("~/.coliru")));
+        assert_eq!(plan.hook_cwd, "~");
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_build_plan_copy() {
+        let plan = build_plan(&manifest(), &[], &local(),
+                              LinkMode::Copy).unwrap();
+
+        assert_eq!(plan.steps[0].actions[1].kind, ActionKind::Copy);
+        assert_eq!(plan.steps[1].actions[0].kind, ActionKind::Copy);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_build_plan_link_type() {
+        let mut manifest = manifest();
+        manifest.steps[1].link[0].link_type = Some(LinkMode::Hardlink);
+
+        let plan = build_plan(&manifest, &[], &local(),
+                              LinkMode::Copy).unwrap();
+
+        assert_eq!(plan.steps[0].actions[1].kind, ActionKind::Copy);
+        assert_eq!(plan.steps[1].actions[0].kind, ActionKind::HardLink);
+    }
+
+    #[test]
+    fn test_build_plan_packages() {
+        let mut manifest = manifest();
+        manifest.steps[0].packages = vec![PackageOptions {
+            name: String::from("git"),
+            apt: None,
+            dnf: None,
+            pacman: None,
+            zypper: None,
+        }];
+
+        let plan = build_plan(&manifest, &[], &local(),
+                              LinkMode::Symlink).unwrap();
+
+        assert_eq!(plan.steps[0].actions[2], Action {
+            kind: ActionKind::Package,
+            src: String::from("git"),
+            dst: None,
+            command: None,
+            cwd: None,
+        });
+        assert_eq!(plan.steps[0].actions[3].kind, ActionKind::Run);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_build_plan_brew() {
+        let mut manifest = manifest();
+        manifest.steps[0].brew = vec![
+            BrewOptions::Cask(String::from("firefox")),
+            BrewOptions::Brewfile(String::from("Brewfile")),
+        ];
+
+        let plan = build_plan(&manifest, &[], &local(),
+                              LinkMode::Symlink).unwrap();
+
+        assert_eq!(plan.steps[0].actions[2], Action {
+            kind: ActionKind::Brew,
+            src: String::from("firefox"),
+            dst: None,
+            command: Some(String::from("brew install --cask 'firefox'")),
+            cwd: Some(String::from("/repo")),
+        });
+        assert_eq!(plan.steps[0].actions[3].src, "/repo/Brewfile");
+        assert_eq!(plan.steps[0].actions[4].kind, ActionKind::Run);
+
+        let plan = build_plan(&manifest, &[], &remote(), LinkMode::Symlink)
+            .unwrap();
+
+        assert_eq!(plan.steps[0].actions[2].dst,
+                   Some(String::from("user@host:~/.coliru/Brewfile")));
+        assert_eq!(plan.steps[0].actions[4].cwd,
+                   Some(String::from("~/.coliru")));
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_build_plan_repo_fetch() {
+        let mut manifest = manifest();
+        manifest.steps[1].repo = vec![RepoOptions {
+            url: String::from("https://example.com/theme.git"),
+            dst: String::from("/themes/theme"),
+            rev: None,
+        }];
+
+        let plan = build_plan(&manifest, &[], &local(),
+                              LinkMode::Symlink).unwrap();
+
+        assert_eq!(plan.steps[1].actions[1], Action {
+            kind: ActionKind::Repo,
+            src: String::from("https://example.com/theme.git"),
+            dst: Some(String::from("/themes/theme")),
+            command: None,
+            cwd: None,
+        });
+
+        manifest.steps[1].fetch = vec![FetchOptions {
+            url: String::from("https://example.com/tool"),
+            dst: String::from("bin/tool"),
+            sha256: String::from("abc"),
+            mode: None,
+        }];
+
+        let plan = build_plan(&manifest, &[], &remote(), LinkMode::Symlink)
+            .unwrap();
+
+        assert_eq!(plan.steps[1].actions[2].kind, ActionKind::Fetch);
+        assert_eq!(plan.steps[1].actions[2].src, "https://example.com/tool");
+        assert_eq!(plan.steps[1].actions[2].dst,
+                   Some(String::from("user@host:~/.coliru/bin/tool")));
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_build_plan_systemd() {
+        let mut manifest = manifest();
+        manifest.steps[1].systemd = vec![
+            SystemdOptions {
+                src: Some(String::from("syncthing.service")),
+                unit: String::from("syncthing.service"),
+                scope: SystemdScope::User,
+                enable: true,
+                start: true,
+            },
+            SystemdOptions {
+                src: None,
+                unit: String::from("sshd.service"),
+                scope: SystemdScope::System,
+                enable: true,
+                start: false,
+            },
+        ];
+
+        let plan = build_plan(&manifest, &[], &remote(), LinkMode::Symlink)
+            .unwrap();
+
+        assert_eq!(plan.steps[1].actions[1].dst,
+                   Some(String::from("user@host:~/.coliru/syncthing.service")));
+        assert_eq!(plan.steps[1].actions[2], Action {
+            kind: ActionKind::Systemd,
+            src: String::from("/repo/syncthing.service"),
+            dst: Some(String::from(
+                "user@host:~/.config/systemd/user/syncthing.service")),
+            command: Some(String::from(
+                "systemctl --user enable 'syncthing.service' && systemctl \
+                 --user start 'syncthing.service'")),
+            cwd: Some(String::from("~/.coliru")),
+        });
+        assert_eq!(plan.steps[1].actions[3], Action {
+            kind: ActionKind::Systemd,
+            src: String::from("sshd.service"),
+            dst: None,
+            command: Some(String::from(
+                "sudo systemctl enable 'sshd.service'")),
+            cwd: Some(String::from("~/.coliru")),
+        });
+    }
+}
@@ -0,0 +1,391 @@
+//! Previews of the changes that copy and link commands would make
+//!
+//! ```
+//! let change = preview_copy("foo", "~/foo")?;
+//! if change == Change::Update {
+//!     print!("{}", diff_files("foo", "~/foo")?.unwrap_or_default());
+//! }
+//! ```
+
+use anyhow::{Context, Result};
+use shellexpand::tilde;
+use similar::TextDiff;
+use std::fmt;
+use std::fs;
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, absolute};
+use super::hash::HashAlgorithm;
+use super::local::{relative_target, resolve_local_path};
+use super::ssh::Host;
+use super::verify::{Status, verify_copy, verify_link};
+
+/// The change that a copy or link command would make to its destination
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Change {
+    /// The destination doesn't exist and would be created
+    Create,
+
+    /// The destination is a file whose contents would be replaced
+    Update,
+
+    /// The destination has the same contents but different permissions
+    UpdatePermissions,
+
+    /// The destination is a symbolic link that would be replaced
+    ReplaceSymlink,
+
+    /// The destination is a file that would be replaced by a link
+    ReplaceFile,
+
+    /// The destination already matches the manifest
+    Unchanged,
+}
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Change::Create => write!(f, "create"),
+            Change::Update => write!(f, "update (contents differ)"),
+            Change::UpdatePermissions => {
+                write!(f, "update (permissions differ)")
+            },
+            Change::ReplaceSymlink => write!(f, "replace symlink"),
+            Change::ReplaceFile => write!(f, "replace file"),
+            Change::Unchanged => write!(f, "no change"),
+        }
+    }
+}
+
+/// Determines the change that copying a file on the local machine would make
+///
+/// Copies preserve the source file's permissions, so a destination with the
+/// same contents but different permissions would still be updated.
+///
+/// ```
+/// assert_eq!(preview_copy("foo", "missing")?, Change::Create);
+/// ```
+pub fn preview_copy(src: &str, dst: &str) -> Result<Change> {
+    if is_same_path(src, dst)? { return Ok(Change::Unchanged); }
+
+    let _dst = tilde(dst).to_string();
+    if let Ok(metadata) = fs::symlink_metadata(&_dst) {
+        if metadata.is_symlink() { return Ok(Change::ReplaceSymlink); }
+    }
+
+    let verification = verify_copy(src, dst, &Host::default(),
+                                   HashAlgorithm::Sha256)?;
+    Ok(match verification.status {
+        Status::Ok if !same_permissions(src, &_dst)? => {
+            Change::UpdatePermissions
+        },
+        Status::Ok => Change::Unchanged,
+        Status::Missing => Change::Create,
+        _ => Change::Update,
+    })
+}
+
+/// Determines the change that linking a file on the local machine would make
+///
+/// ```
+/// assert_eq!(preview_link("foo", "missing")?, Change::Create);
+/// ```
+pub fn preview_link(src: &str, dst: &str) -> Result<Change> {
+    let src_abs = absolute(src).with_context(|| {
+        format!("Failed to make {} absolute", src)
+    })?;
+    preview_symlink(src, dst, &src_abs)
+}
+
+/// Determines the change that linking a file on the local machine with a
+/// relative target would make
+///
+/// ```
+/// assert_eq!(preview_relative_link("foo", "missing")?, Change::Create);
+/// ```
+pub fn preview_relative_link(src: &str, dst: &str) -> Result<Change> {
+    preview_symlink(src, dst, &relative_target(src, dst)?)
+}
+
+/// Determines the change that creating a symbolic link with a specific target
+/// would make
+///
+/// Links to the right file are still replaced if their target is written
+/// differently (e.g. if it's absolute instead of relative).
+fn preview_symlink(src: &str, dst: &str, target: &Path) -> Result<Change> {
+    if is_same_path(src, dst)? { return Ok(Change::Unchanged); }
+
+    let verification = verify_link(src, dst, HashAlgorithm::Sha256)?;
+    let other_target = fs::read_link(tilde(dst).as_ref())
+        .is_ok_and(|x| x != target);
+    Ok(match verification.status {
+        Status::Ok if other_target => Change::ReplaceSymlink,
+        Status::Ok => Change::Unchanged,
+        Status::Missing => Change::Create,
+        Status::WrongTarget => Change::ReplaceSymlink,
+        Status::NotLink | Status::Differs => Change::ReplaceFile,
+    })
+}
+
+/// Determines the change that hard linking a file on the local machine would
+/// make
+///
+/// ```
+/// assert_eq!(preview_hard_link("foo", "missing")?, Change::Create);
+/// ```
+pub fn preview_hard_link(src: &str, dst: &str) -> Result<Change> {
+    if is_same_path(src, dst)? { return Ok(Change::Unchanged); }
+
+    let _dst = tilde(dst).to_string();
+    let Ok(dst_metadata) = fs::symlink_metadata(&_dst) else {
+        return Ok(Change::Create);
+    };
+    if dst_metadata.is_symlink() { return Ok(Change::ReplaceSymlink); }
+
+    let src_metadata = fs::metadata(src).with_context(|| {
+        format!("Failed to read {}", src)
+    })?;
+    Ok(if is_same_file(&src_metadata, &dst_metadata) {
+        Change::Unchanged
+    } else {
+        Change::ReplaceFile
+    })
+}
+
+/// Computes a unified diff of the changes that replacing a destination file
+/// with a source file would make
+///
+/// Relative paths are relative to `base_dir`. Returns None if either file isn't
+/// valid UTF-8, in which case the files can't be compared line by line.
+///
+/// ```
+/// let diff_text = diff_files("foo", "~/foo", Path::new("dotfiles"))?;
+/// print!("{}", diff_text.unwrap_or_default());
+/// ```
+pub fn diff_files(src: &str, dst: &str, base_dir: &Path) ->
+    Result<Option<String>> {
+
+    let new = fs::read(resolve_local_path(src, base_dir)).with_context(|| {
+        format!("Failed to read {}", src)
+    })?;
+    let old = fs::read(resolve_local_path(dst, base_dir)).with_context(|| {
+        format!("Failed to read {}", dst)
+    })?;
+    let (Ok(old), Ok(new)) = (String::from_utf8(old), String::from_utf8(new))
+        else { return Ok(None) };
+
+    let diff = TextDiff::from_lines(&old, &new);
+    Ok(Some(diff.unified_diff().header(dst, src).to_string()))
+}
+
+/// Checks whether a source and destination refer to the same path, in which
+/// case copy and link commands do nothing
+fn is_same_path(src: &str, dst: &str) -> Result<bool> {
+    let src_abs = absolute(src).with_context(|| {
+        format!("Failed to make {} absolute", src)
+    })?;
+    let dst_abs = absolute(Path::new(tilde(dst).as_ref())).with_context(|| {
+        format!("Failed to make {} absolute", dst)
+    })?;
+    Ok(src_abs == dst_abs)
+}
+
+/// Checks whether a source and destination file have the same permissions
+fn same_permissions(src: &str, dst: &str) -> Result<bool> {
+    let src_permissions = fs::metadata(src).with_context(|| {
+        format!("Failed to read {}", src)
+    })?.permissions();
+    let dst_permissions = fs::metadata(dst).with_context(|| {
+        format!("Failed to read {}", dst)
+    })?.permissions();
+    Ok(src_permissions == dst_permissions)
+}
+
+/// Checks whether two files are hard links to the same underlying file
+///
+/// On non-Unix platforms, files are never considered the same so that hard
+/// links are always replaced.
+#[cfg(target_family = "unix")]
+fn is_same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+#[cfg(not(target_family = "unix"))]
+fn is_same_file(_a: &fs::Metadata, _b: &fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{setup_integration, write_file};
+
+    #[test]
+    fn test_preview_copy_basic() {
+        let tmp = setup_integration("test_preview_copy_basic");
+
+        let src = tmp.local.join("foo");
+        let same = tmp.local.join("same");
+        let different = tmp.local.join("different");
+        write_file(&src, "contents of foo");
+        write_file(&same, "contents of foo");
+        write_file(&different, "contents of bar");
+
+        let src = src.to_str().unwrap();
+        let missing = tmp.local.join("missing");
+        assert_eq!(preview_copy(src, missing.to_str().unwrap()).unwrap(),
+                   Change::Create);
+        assert_eq!(preview_copy(src, same.to_str().unwrap()).unwrap(),
+                   Change::Unchanged);
+        assert_eq!(preview_copy(src, different.to_str().unwrap()).unwrap(),
+                   Change::Update);
+        assert_eq!(preview_copy(src, src).unwrap(), Change::Unchanged);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_preview_copy_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let tmp = setup_integration("test_preview_copy_permissions");
+
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+        write_file(&dst, "contents of foo");
+        fs::set_permissions(&src, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::set_permissions(&dst, fs::Permissions::from_mode(0o644)).unwrap();
+
+        let result = preview_copy(src.to_str().unwrap(), dst.to_str().unwrap());
+
+        assert_eq!(result.unwrap(), Change::UpdatePermissions);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_preview_copy_symlink() {
+        let tmp = setup_integration("test_preview_copy_symlink");
+
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+        std::os::unix::fs::symlink(&src, &dst).unwrap();
+
+        let result = preview_copy(src.to_str().unwrap(), dst.to_str().unwrap());
+
+        assert_eq!(result.unwrap(), Change::ReplaceSymlink);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_preview_link_basic() {
+        let tmp = setup_integration("test_preview_link_basic");
+
+        let src = tmp.local.join("foo");
+        let other = tmp.local.join("other");
+        let linked = tmp.local.join("linked");
+        let wrong = tmp.local.join("wrong");
+        let file = tmp.local.join("file");
+        write_file(&src, "contents of foo");
+        write_file(&other, "contents of other");
+        write_file(&file, "contents of foo");
+        std::os::unix::fs::symlink(&src, &linked).unwrap();
+        std::os::unix::fs::symlink(&other, &wrong).unwrap();
+
+        let src = src.to_str().unwrap();
+        let missing = tmp.local.join("missing");
+        assert_eq!(preview_link(src, missing.to_str().unwrap()).unwrap(),
+                   Change::Create);
+        assert_eq!(preview_link(src, linked.to_str().unwrap()).unwrap(),
+                   Change::Unchanged);
+        assert_eq!(preview_link(src, wrong.to_str().unwrap()).unwrap(),
+                   Change::ReplaceSymlink);
+        assert_eq!(preview_link(src, file.to_str().unwrap()).unwrap(),
+                   Change::ReplaceFile);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_preview_relative_link_basic() {
+        let tmp = setup_integration("test_preview_relative_link_basic");
+
+        let src = tmp.local.join("foo");
+        let relative = tmp.local.join("relative");
+        let absolute = tmp.local.join("absolute");
+        write_file(&src, "contents of foo");
+        std::os::unix::fs::symlink("foo", &relative).unwrap();
+        std::os::unix::fs::symlink(&src, &absolute).unwrap();
+
+        let src = src.to_str().unwrap();
+        let relative = relative.to_str().unwrap();
+        let absolute = absolute.to_str().unwrap();
+        assert_eq!(preview_relative_link(src, relative).unwrap(),
+                   Change::Unchanged);
+        assert_eq!(preview_relative_link(src, absolute).unwrap(),
+                   Change::ReplaceSymlink);
+        assert_eq!(preview_link(src, relative).unwrap(),
+                   Change::ReplaceSymlink);
+        assert_eq!(preview_link(src, absolute).unwrap(), Change::Unchanged);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_preview_hard_link_basic() {
+        let tmp = setup_integration("test_preview_hard_link_basic");
+
+        let src = tmp.local.join("foo");
+        let linked = tmp.local.join("linked");
+        let file = tmp.local.join("file");
+        let symlink = tmp.local.join("symlink");
+        write_file(&src, "contents of foo");
+        write_file(&file, "contents of foo");
+        fs::hard_link(&src, &linked).unwrap();
+        std::os::unix::fs::symlink(&src, &symlink).unwrap();
+
+        let src = src.to_str().unwrap();
+        let missing = tmp.local.join("missing");
+        assert_eq!(preview_hard_link(src, missing.to_str().unwrap()).unwrap(),
+                   Change::Create);
+        assert_eq!(preview_hard_link(src, linked.to_str().unwrap()).unwrap(),
+                   Change::Unchanged);
+        assert_eq!(preview_hard_link(src, file.to_str().unwrap()).unwrap(),
+                   Change::ReplaceFile);
+        assert_eq!(preview_hard_link(src, symlink.to_str().unwrap()).unwrap(),
+                   Change::ReplaceSymlink);
+    }
+
+    #[test]
+    fn test_diff_files_basic() {
+        let tmp = setup_integration("test_diff_files_basic");
+
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "line 1\nline 2\n");
+        write_file(&dst, "line 1\nold line\n");
+
+        let result = diff_files(src.to_str().unwrap(), dst.to_str().unwrap(),
+                                Path::new("."));
+
+        assert_eq!(result.unwrap().unwrap(), format!("\
+--- {}
++++ {}
+@@ -1,2 +1,2 @@
+ line 1
+-old line
++line 2
+", dst.display(), src.display()));
+    }
+
+    #[test]
+    fn test_diff_files_binary() {
+        let tmp = setup_integration("test_diff_files_binary");
+
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        fs::write(&src, [0xff, 0xfe]).unwrap();
+        write_file(&dst, "text\n");
+
+        let result = diff_files(src.to_str().unwrap(), dst.to_str().unwrap(),
+                                Path::new("."));
+
+        assert_eq!(result.unwrap(), None);
+    }
+}
@@ -0,0 +1,138 @@
+//! Installation by running coliru on another machine
+//!
+//! Instead of staging every copy and running scripts from `~/.coliru`, the
+//! directory containing the manifest is sent to `~/.coliru/repo` on the other
+//! machine, and coliru installs it there. Links, paths, and scripts therefore
+//! behave exactly like they do when installing on the local machine.
+//!
+//! ```
+//! let args = vec![String::from("--dry-run")];
+//! let failure = remote_exec(Path::new("manifest.yml"), &host, &args, false)?;
+//! ```
+
+use anyhow::{Context, Result, anyhow, bail};
+use std::env;
+use std::fs::read_dir;
+use std::path::Path;
+use tempfile::Builder;
+use super::core::{SSH_INSTALL_DIR, STAGING_PREFIX};
+use super::exit::{Error, Failure};
+use super::facts::{local_facts, remote_facts};
+use super::local::quote;
+use super::ssh::{Host, RemoteOs, preflight_connection, send_command,
+                 send_command_status, send_staged_files, stage_file};
+
+/// The directory in `~/.coliru` on the remote machine that the manifest's
+/// directory is sent to
+pub const REPO_DIR: &str = "repo";
+
+/// The directory in `~/.coliru` on the remote machine that the local coliru
+/// binary is sent to
+pub const BINARY_DIR: &str = "bin";
+
+/// Items in the manifest's directory that aren't sent to the remote machine
+const IGNORED_ITEMS: [&str; 1] = [".git"];
+
+/// Installs a manifest on another machine by sending the manifest's directory
+/// to it and running coliru there with a set of arguments
+///
+/// The remote copy of the directory is replaced each time. If `push_binary` is
+/// true, the local coliru binary is also sent and executed, which requires
+/// both machines to have the same operating system and CPU architecture.
+/// Otherwise, `coliru` must be installed on the remote machine. Returns an Err
+/// if the files can't be sent or coliru can't be executed, and returns the
+/// class of failure that coliru exited with otherwise.
+///
+/// ```
+/// let args = vec![String::from("--tag-rules"), String::from("linux")];
+/// let failure = remote_exec(Path::new("dotfiles/manifest.yml"), &host,
+///                           &args, true)?;
+/// ```
+pub fn remote_exec(manifest: &Path, host: &Host, args: &[String],
+                   push_binary: bool) -> Result<Option<Failure>> {
+
+    if host.options.remote_os == RemoteOs::Windows {
+        bail!("Remote execution isn't supported for Windows hosts");
+    }
+    let base_dir = match manifest.parent() {
+        Some(dir) if dir != Path::new("") => dir,
+        _ => Path::new("."),
+    };
+    let manifest_name = manifest.file_name()
+        .with_context(|| format!("Invalid manifest {}", manifest.display()))?;
+
+    preflight_connection(host)?;
+    let binary = if push_binary {
+        check_platform(host)?;
+        format!("~/{}/{}/coliru", SSH_INSTALL_DIR, BINARY_DIR)
+    } else {
+        String::from("coliru")
+    };
+
+    println!("Sending {} to {}", base_dir.display(), host);
+    let staging_dir = Builder::new().prefix(STAGING_PREFIX).tempdir()
+        .context("Failed to create temporary directory")?;
+    let repo = format!("~/{}/{}", SSH_INSTALL_DIR, REPO_DIR);
+    stage_dir(base_dir, &repo, staging_dir.path())?;
+    if push_binary {
+        let exe = env::current_exe()
+            .context("Failed to locate the coliru binary")?;
+        stage_file(&exe.to_string_lossy(), &binary, staging_dir.path(), false)?;
+    }
+    send_command(&format!("rm -rf {}", repo), host, None)
+        .with_context(|| format!("Failed to remove {}:{}", host, repo))?;
+    send_staged_files(staging_dir.path(), host, None)
+        .context("Failed to transfer staged files")?;
+
+    let mut command = format!("cd {} && {} {}", repo, binary,
+                              quote(&manifest_name.to_string_lossy()));
+    for arg in args {
+        command.push(' ');
+        command.push_str(&quote(arg));
+    }
+    let code = send_command_status(&command, host)
+        .context("Failed to run coliru")?;
+    match code {
+        0 => Ok(None),
+        _ => match Failure::from_code(code) {
+            Some(failure) => Ok(Some(failure)),
+            None => Err(anyhow!(Error::Critical(format!(
+                "Failed to run coliru on {} (exit status: {}), which may not \
+                 be installed (see --push-binary)", host, code)))),
+        },
+    }
+}
+
+/// Returns an Err if another machine's operating system or CPU architecture
+/// differs from the local machine's, so that it can't run the local binary
+fn check_platform(host: &Host) -> Result<()> {
+    let local = local_facts();
+    let remote = remote_facts(host)?;
+    if local.os != remote.os || local.arch != remote.arch {
+        bail!("Can't send a {} {} binary to {}, which runs {} {}", local.os,
+              local.arch, host, remote.os, remote.arch);
+    }
+    Ok(())
+}
+
+/// Recursively stages the contents of a directory to be sent to a directory on
+/// another machine, skipping [`IGNORED_ITEMS`]
+fn stage_dir(dir: &Path, dst: &str, staging_dir: &Path) -> Result<()> {
+    let items = read_dir(dir).and_then(|x| x.collect::<Result<Vec<_>, _>>())
+        .with_context(|| {
+            format!("Failed to list contents of {}", dir.display())
+        })?;
+    for item in items {
+        let name = item.file_name().to_string_lossy().to_string();
+        if IGNORED_ITEMS.contains(&name.as_str()) { continue; }
+
+        let src = item.path();
+        let dst = format!("{}/{}", dst, name);
+        if src.is_dir() {
+            stage_dir(&src, &dst, staging_dir)?;
+        } else {
+            stage_file(&src.to_string_lossy(), &dst, staging_dir, true)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,197 @@
+//! Rendering of copied dotfiles whose contents are transformed
+//!
+//! Copy commands that concatenate multiple source files, convert line endings,
+//! or are templated install a rendered temporary file instead of their source
+//! file. The rendered file keeps the permissions and modification time of the
+//! first source file.
+//!
+//! ```
+//! if let Some(rendered) = render_copy(&copy, Path::new("."), false)? {
+//!     copy_file(rendered.path().to_str().unwrap(), &copy.dst)?;
+//! }
+//! ```
+
+use anyhow::{Context, Result, bail};
+use regex::bytes::{Captures, Regex};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use tempfile::NamedTempFile;
+use super::eol::convert_line_endings;
+use super::local::resolve_local_path;
+use super::manifest::{CopyLinkOptions, LineEnding};
+
+/// Writes the contents that a copy command installs to a temporary file, if
+/// they differ from the contents of its source file
+///
+/// The source files are concatenated in order, their placeholders are replaced
+/// if the command is templated, and their line endings are then converted
+/// according to the command's `eol` option. Native line endings are `\r\n` when
+/// installing on Windows and `\n` otherwise, and always `\n` if `remote` is
+/// true. Returns None if the command has a single source file, no `eol` option,
+/// and isn't templated. The temporary file is deleted when the returned value
+/// is dropped. Relative source paths are relative to `base_dir`.
+///
+/// ```
+/// let rendered = render_copy(&copy, Path::new("dotfiles"), false)?;
+/// ```
+pub fn render_copy(entry: &CopyLinkOptions, base_dir: &Path, remote: bool) ->
+    Result<Option<NamedTempFile>> {
+
+    if entry.concat.is_empty() && entry.eol.is_none() &&
+        entry.template.is_none() {
+        return Ok(None);
+    }
+
+    let sources = entry.sources();
+    let mut contents = vec![];
+    for src in &sources {
+        let path = resolve_local_path(src, base_dir);
+        contents.extend(fs::read(path).with_context(|| {
+            format!("Failed to read {}", src)
+        })?);
+    }
+    if let Some(values) = &entry.template {
+        contents = render_template(&contents, values).with_context(|| {
+            format!("Failed to render {}", entry.src)
+        })?;
+    }
+    if let Some(eol) = entry.eol {
+        let crlf = match eol {
+            LineEnding::Lf => false,
+            LineEnding::Crlf => true,
+            LineEnding::Native => cfg!(target_family = "windows") && !remote,
+        };
+        contents = convert_line_endings(&contents, crlf);
+    }
+
+    let path = resolve_local_path(sources[0], base_dir);
+    let metadata = fs::metadata(path).with_context(|| {
+        format!("Failed to read {}", sources[0])
+    })?;
+    let mut rendered = NamedTempFile::new()
+        .context("Failed to create temporary file")?;
+    rendered.write_all(&contents)
+        .and_then(|_| {
+            fs::set_permissions(rendered.path(), metadata.permissions())
+        })
+        .and_then(|_| metadata.modified())
+        .and_then(|mtime| rendered.as_file().set_modified(mtime))
+        .with_context(|| format!("Failed to render {}", entry.src))?;
+    Ok(Some(rendered))
+}
+
+/// Replaces each placeholder (e.g. `{{ facts.os }}`) in the contents of a file
+/// with its value
+///
+/// Returns an Err if a placeholder doesn't have a value.
+///
+/// ```
+/// let values = BTreeMap::from([(String::from("facts.os"),
+///                               String::from("linux"))]);
+/// assert_eq!(render_template(b"os={{ facts.os }}", &values)?, b"os=linux");
+/// ```
+pub fn render_template(contents: &[u8], values: &BTreeMap<String, String>) ->
+    Result<Vec<u8>> {
+
+    let pattern = Regex::new(r"\{\{\s*([A-Za-z0-9_.]+)\s*\}\}").unwrap();
+    if let Some(unknown) = pattern.captures_iter(contents).find(|x| {
+        !values.contains_key(&*String::from_utf8_lossy(&x[1]))
+    }) {
+        bail!("{} has no value", String::from_utf8_lossy(&unknown[0]));
+    }
+    Ok(pattern.replace_all(contents, |x: &Captures| {
+        values[&*String::from_utf8_lossy(&x[1])].as_bytes().to_vec()
+    }).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{setup_integration, write_file};
+
+    fn entry(src: &str, concat: &[&str], eol: Option<LineEnding>) ->
+        CopyLinkOptions {
+
+        CopyLinkOptions {
+            src: String::from(src),
+            dst: String::from("~/foo"),
+            link_type: None,
+            preserve_mtime: false,
+            relative: false,
+            remote: false,
+            eol,
+            concat: concat.iter().map(|x| x.to_string()).collect(),
+            template: None,
+        }
+    }
+
+    #[test]
+    fn test_render_copy_eol() {
+        let tmp = setup_integration("test_render_copy_eol");
+
+        let src = tmp.local.join("foo");
+        write_file(&src, "foo\nbar\n");
+
+        let src = src.to_str().unwrap();
+        let base_dir = Path::new(".");
+        let crlf = render_copy(&entry(src, &[], Some(LineEnding::Crlf)),
+                               base_dir, false).unwrap().unwrap();
+        let native = render_copy(&entry(src, &[], Some(LineEnding::Native)),
+                                 base_dir, true).unwrap().unwrap();
+        let plain = render_copy(&entry(src, &[], None), base_dir, false)
+            .unwrap();
+
+        assert_eq!(fs::read(crlf.path()).unwrap(), b"foo\r\nbar\r\n");
+        assert_eq!(fs::read(native.path()).unwrap(), b"foo\nbar\n");
+        assert_eq!(fs::metadata(crlf.path()).unwrap().modified().unwrap(),
+                   fs::metadata(src).unwrap().modified().unwrap());
+        assert_eq!(plain.is_none(), true);
+    }
+
+    #[test]
+    fn test_render_copy_concat() {
+        let tmp = setup_integration("test_render_copy_concat");
+
+        let foo = tmp.local.join("foo");
+        let bar = tmp.local.join("bar");
+        write_file(&foo, "foo\r\n");
+        write_file(&bar, "bar\n");
+
+        // Relative sources are read from the base directory
+        let concat = render_copy(&entry("foo + bar", &["foo", "bar"], None),
+                                 &tmp.local, false).unwrap().unwrap();
+        let lf = render_copy(&entry("foo + bar", &["foo", "bar"],
+                                    Some(LineEnding::Lf)), &tmp.local, false)
+            .unwrap().unwrap();
+
+        assert_eq!(fs::read(concat.path()).unwrap(), b"foo\r\nbar\n");
+        assert_eq!(fs::read(lf.path()).unwrap(), b"foo\nbar\n");
+    }
+
+    #[test]
+    fn test_render_copy_template() {
+        let tmp = setup_integration("test_render_copy_template");
+
+        let src = tmp.local.join("foo");
+        write_file(&src, "os={{ facts.os }}\nhome={{facts.home}}\n");
+
+        let mut templated = entry(src.to_str().unwrap(), &[], None);
+        templated.template = Some(BTreeMap::from([
+            (String::from("facts.os"), String::from("linux")),
+            (String::from("facts.home"), String::from("/home/user")),
+        ]));
+        let base_dir = Path::new(".");
+        let rendered = render_copy(&templated, base_dir, false).unwrap()
+            .unwrap();
+        templated.template = Some(BTreeMap::new());
+        let missing = render_copy(&templated, base_dir, false);
+
+        assert_eq!(fs::read(rendered.path()).unwrap(),
+                   b"os=linux\nhome=/home/user\n");
+        assert_eq!(format!("{:#}", missing.unwrap_err()),
+                   format!("Failed to render {}: {{{{ facts.os }}}} has no \
+                            value", src.display()));
+    }
+}
@@ -0,0 +1,148 @@
+//! Git commands for cloning and updating repositories
+//!
+//! A repo command clones a repository the first time it's installed, and
+//! fetches it and checks out its revision every time after that. Each
+//! function returns a POSIX shell command, so that repositories can be
+//! installed the same way on the local machine and over SSH.
+//!
+//! ```
+//! let repo = RepoOptions { url: String::from("https://github.com/x/y"),
+//!                          dst: String::from("~/y"), rev: None };
+//! println!("{}", clone_command(&repo));
+//! ```
+
+use super::local::quote;
+use super::manifest::RepoOptions;
+use super::ssh::quote_path;
+
+/// Returns a shell command that succeeds if a repo command's destination is
+/// already a Git repository
+///
+/// ```
+/// assert_eq!(exists_command(&repo), "test -d ~/'y'/.git");
+/// ```
+pub fn exists_command(repo: &RepoOptions) -> String {
+    format!("test -d {}/.git", quote_path(repo.dst.trim_end_matches('/')))
+}
+
+/// Returns a shell command that clones a repo command's repository and checks
+/// out its revision, if it has one
+///
+/// ```
+/// assert_eq!(clone_command(&repo),
+///            "git clone --quiet 'https://github.com/x/y' ~/'y'");
+/// ```
+pub fn clone_command(repo: &RepoOptions) -> String {
+    let dst = quote_path(repo.dst.trim_end_matches('/'));
+    let clone = format!("git clone --quiet {} {}", quote(&repo.url), dst);
+    match &repo.rev {
+        Some(rev) => {
+            format!("{} && git -C {} checkout --quiet {}", clone, dst,
+                    quote(rev))
+        },
+        None => clone,
+    }
+}
+
+/// Returns a shell command that fetches a repo command's repository
+///
+/// ```
+/// assert_eq!(fetch_command(&repo), "git -C ~/'y' fetch --quiet origin");
+/// ```
+pub fn fetch_command(repo: &RepoOptions) -> String {
+    format!("git -C {} fetch --quiet origin",
+            quote_path(repo.dst.trim_end_matches('/')))
+}
+
+/// Returns a shell command that succeeds if a fetched repository has already
+/// checked out its revision, or the latest commit of its branch if it doesn't
+/// have one
+///
+/// ```
+/// let current = current_command(&repo);
+/// ```
+pub fn current_command(repo: &RepoOptions) -> String {
+    let dst = quote_path(repo.dst.trim_end_matches('/'));
+    format!("test \"$(git -C {dst} rev-parse HEAD)\" = \"$({})\"",
+            target_commit(repo))
+}
+
+/// Returns a shell command that checks out the revision of a fetched
+/// repository, or fast-forwards its branch if it doesn't have one
+///
+/// ```
+/// let update = update_command(&repo);
+/// ```
+pub fn update_command(repo: &RepoOptions) -> String {
+    let dst = quote_path(repo.dst.trim_end_matches('/'));
+    match &repo.rev {
+        Some(_) => {
+            format!("git -C {dst} checkout --quiet \"$({})\"",
+                    target_commit(repo))
+        },
+        None => format!("git -C {dst} merge --quiet --ff-only '@{{upstream}}'"),
+    }
+}
+
+/// Returns a shell command that prints the commit that a fetched repository
+/// should have checked out
+///
+/// Branch names are resolved to the fetched branch on `origin` rather than any
+/// local branch with the same name, which may be out of date.
+fn target_commit(repo: &RepoOptions) -> String {
+    let dst = quote_path(repo.dst.trim_end_matches('/'));
+    let parse = format!("git -C {dst} rev-parse --quiet --verify");
+    match &repo.rev {
+        Some(rev) => {
+            format!("{parse} {} 2>/dev/null || {parse} {}",
+                    quote(&format!("origin/{}^{{commit}}", rev)),
+                    quote(&format!("{}^{{commit}}", rev)))
+        },
+        None => format!("{parse} '@{{upstream}}'"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn repo(rev: Option<&str>) -> RepoOptions {
+        RepoOptions {
+            url: String::from("https://example.com/theme.git"),
+            dst: String::from("~/.themes/theme/"),
+            rev: rev.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_repo_clone_command() {
+        assert_eq!(exists_command(&repo(None)),
+                   "test -d ~/'.themes/theme'/.git");
+        assert_eq!(clone_command(&repo(None)),
+                   "git clone --quiet 'https://example.com/theme.git' \
+                    ~/'.themes/theme'");
+        assert_eq!(clone_command(&repo(Some("v1"))),
+                   "git clone --quiet 'https://example.com/theme.git' \
+                    ~/'.themes/theme' && git -C ~/'.themes/theme' checkout \
+                    --quiet 'v1'");
+    }
+
+    #[test]
+    fn test_repo_update_command() {
+        assert_eq!(fetch_command(&repo(None)),
+                   "git -C ~/'.themes/theme' fetch --quiet origin");
+        assert_eq!(current_command(&repo(None)),
+                   "test \"$(git -C ~/'.themes/theme' rev-parse HEAD)\" = \
+                    \"$(git -C ~/'.themes/theme' rev-parse --quiet --verify \
+                    '@{upstream}')\"");
+        assert_eq!(update_command(&repo(None)),
+                   "git -C ~/'.themes/theme' merge --quiet --ff-only \
+                    '@{upstream}'");
+        assert_eq!(update_command(&repo(Some("main"))),
+                   "git -C ~/'.themes/theme' checkout --quiet \"$(git -C \
+                    ~/'.themes/theme' rev-parse --quiet --verify \
+                    'origin/main^{commit}' 2>/dev/null || git -C \
+                    ~/'.themes/theme' rev-parse --quiet --verify \
+                    'main^{commit}')\"");
+    }
+}
@@ -0,0 +1,185 @@
+//! Machine-readable reports of the outcome of each installation step
+//!
+//! ```
+//! let report = Report::new(&[String::from("linux")], "");
+//! write_report(Path::new("report.xml"), &report)?;
+//! ```
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use super::exit::Failure;
+
+/// The outcome of a single installation step
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StepReport {
+    /// The 1-based index of the step among the steps that were executed
+    pub step: usize,
+
+    /// The step's tags
+    pub tags: Vec<String>,
+
+    /// The class of failure with the greatest precedence among any errors that
+    /// occurred, or None if the step succeeded
+    pub failure: Option<Failure>,
+
+    /// The error messages of any commands that failed
+    pub errors: Vec<String>,
+
+    /// How long the step took to execute, in seconds
+    pub duration: f64,
+}
+
+/// The outcome of an installation
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Report {
+    /// The machine that dotfiles were installed on, if not the local machine
+    pub host: Option<String>,
+
+    /// The tag rules used to filter steps
+    pub tag_rules: Vec<String>,
+
+    /// How long the installation took, in seconds
+    pub duration: f64,
+
+    /// The outcome of each executed step, in order
+    pub steps: Vec<StepReport>,
+}
+impl Report {
+    /// Creates an empty report
+    ///
+    /// ```
+    /// let report = Report::new(&[String::from("linux")], "user@hostname");
+    /// ```
+    pub fn new(tag_rules: &[String], host: &str) -> Report {
+        Report {
+            host: if host.is_empty() { None } else { Some(host.to_owned()) },
+            tag_rules: tag_rules.to_vec(),
+            duration: 0.0,
+            steps: vec![],
+        }
+    }
+}
+
+/// Writes a report to a file, as JUnit XML if the file has an `.xml` extension
+/// and as JSON otherwise
+///
+/// ```
+/// write_report(Path::new("report.json"), &report)?;
+/// ```
+pub fn write_report(path: &Path, report: &Report) -> Result<()> {
+    let is_xml = path.extension().is_some_and(|x| x == "xml");
+    let contents = if is_xml {
+        format_junit(report)
+    } else {
+        serde_json::to_string_pretty(report)
+            .context("Failed to serialize report")? + "\n"
+    };
+    fs::write(path, contents).with_context(|| {
+        format!("Failed to write report to {}", path.display())
+    })
+}
+
+/// Formats a report as a JUnit XML test suite with one test case per step
+fn format_junit(report: &Report) -> String {
+    let failures = report.steps.iter().filter(|x| x.failure.is_some()).count();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml += &format!("<testsuite name=\"coliru\" tests=\"{}\" failures=\"{}\" \
+                     time=\"{:.3}\">\n", report.steps.len(), failures,
+                    report.duration);
+
+    for step in &report.steps {
+        let name = format!("[{}/{}] {}", step.step, report.steps.len(),
+                           step.tags.join(", "));
+        xml += &format!("  <testcase name=\"{}\" classname=\"coliru\" \
+                         time=\"{:.3}\"", escape_xml(name.trim_end()),
+                        step.duration);
+        match step.failure {
+            None => xml += "/>\n",
+            Some(failure) => {
+                xml += ">\n";
+                xml += &format!("    <failure type=\"{:?}\" message=\"{}\">{}\
+                                 </failure>\n", failure,
+                                escape_xml(step.errors.first()
+                                    .map_or("", |x| x.as_str())),
+                                escape_xml(&step.errors.join("\n")));
+                xml += "  </testcase>\n";
+            },
+        }
+    }
+
+    xml + "</testsuite>\n"
+}
+
+/// Escapes the characters in a string that aren't allowed in XML attributes
+/// and text
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+        .replace('"', "&quot;").replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{read_file, setup_integration};
+
+    fn report() -> Report {
+        Report {
+            host: None,
+            tag_rules: vec![String::from("linux")],
+            duration: 1.5,
+            steps: vec![
+                StepReport {
+                    step: 1,
+                    tags: vec![String::from("linux"), String::from("macos")],
+                    failure: None,
+                    errors: vec![],
+                    duration: 0.25,
+                },
+                StepReport {
+                    step: 2,
+                    tags: vec![String::from("linux")],
+                    failure: Some(Failure::Script),
+                    errors: vec![String::from("Exit status: <1>")],
+                    duration: 1.0,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_write_report_junit() {
+        let tmp = setup_integration("test_write_report_junit");
+        let path = tmp.local.join("report.xml");
+
+        write_report(&path, &report()).unwrap();
+
+        assert_eq!(read_file(&path), "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<testsuite name=\"coliru\" tests=\"2\" failures=\"1\" time=\"1.500\">
+  <testcase name=\"[1/2] linux, macos\" classname=\"coliru\" time=\"0.250\"/>
+  <testcase name=\"[2/2] linux\" classname=\"coliru\" time=\"1.000\">
+    <failure type=\"Script\" message=\"Exit status: &lt;1&gt;\">\
+Exit status: &lt;1&gt;</failure>
+  </testcase>
+</testsuite>
+");
+    }
+
+    #[test]
+    fn test_write_report_json() {
+        let tmp = setup_integration("test_write_report_json");
+        let path = tmp.local.join("report.json");
+
+        write_report(&path, &report()).unwrap();
+
+        let json: serde_json::Value = serde_json::from_str(&read_file(&path))
+            .unwrap();
+        assert_eq!(json["tag_rules"][0], "linux");
+        assert_eq!(json["steps"][0]["failure"], serde_json::Value::Null);
+        assert_eq!(json["steps"][1]["failure"], "script");
+        assert_eq!(json["steps"][1]["errors"][0], "Exit status: <1>");
+        assert_eq!(json["steps"][1]["duration"], 1.0);
+    }
+}
@@ -0,0 +1,358 @@
+//! Destinations for the output of an install
+//!
+//! Installs report what they do as a series of [`Event`]s instead of printing
+//! text, so that the same install can be shown as colored console output, as
+//! JSON, or not at all.
+//!
+//! ```
+//! let options = InstallOptions { reporter: Arc::new(JsonReporter),
+//!                                ..InstallOptions::default() };
+//! ```
+
+use colored::Colorize;
+use serde::Serialize;
+use std::fmt;
+use std::sync::Mutex;
+
+/// Something that happened during an install
+///
+/// Steps are numbered from 1, and hooks are reported with a step of `None`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// A command started, e.g. `Copy foo to ~/foo`
+    Action {
+        /// The step that the command belongs to
+        step: Option<usize>,
+
+        /// The label of the step or hook, e.g. `1/3` or `pre_install`
+        label: String,
+
+        /// A description of the command
+        description: String,
+    },
+
+    /// The current command finished, or started running if its outcome is
+    /// [`Pending`](Outcome::Pending)
+    Outcome {
+        /// The step that the command belongs to
+        step: Option<usize>,
+
+        /// The outcome of the command
+        outcome: Outcome,
+
+        /// Details of the outcome, e.g. the pattern that excluded a command or
+        /// the change that a dry run would make
+        detail: Option<String>,
+    },
+
+    /// The changes that a dry run would make to a file
+    Diff {
+        /// The step that the file belongs to
+        step: Option<usize>,
+
+        /// A unified diff of the file's contents
+        text: String,
+    },
+
+    /// The output of a script or of SSH
+    Output {
+        /// The step that the output belongs to
+        step: Option<usize>,
+
+        /// The output, with each line indented
+        text: String,
+
+        /// Whether the output was written to stderr
+        stderr: bool,
+    },
+
+    /// A script failed and will be run again
+    Retry {
+        /// The step that the script belongs to
+        step: Option<usize>,
+
+        /// The number of the retry, starting from 1
+        retry: u32,
+
+        /// The number of times the script may be retried
+        retries: u32,
+
+        /// The error that the script failed with
+        message: String,
+    },
+
+    /// A command failed
+    Error {
+        /// The step that the command belongs to
+        step: Option<usize>,
+
+        /// The error message
+        message: String,
+    },
+}
+
+/// The outcome of a command
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The destination was created
+    Created,
+
+    /// The destination was updated
+    Updated,
+
+    /// The destination already matched the manifest
+    Unchanged,
+
+    /// The command failed
+    Failed,
+
+    /// The command was excluded
+    Skipped,
+
+    /// The command was only previewed
+    DryRun,
+
+    /// The command is still running or will be completed later (e.g. a file
+    /// that's transferred over SSH at the end of the step)
+    Pending,
+}
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Created => write!(f, "created"),
+            Outcome::Updated => write!(f, "updated"),
+            Outcome::Unchanged => write!(f, "unchanged"),
+            Outcome::Failed => write!(f, "failed"),
+            Outcome::Skipped => write!(f, "skipped"),
+            Outcome::DryRun => write!(f, "dry run"),
+            Outcome::Pending => write!(f, "pending"),
+        }
+    }
+}
+
+/// A destination for the events of an install
+///
+/// Events are reported in order for each step, and the events of concurrent
+/// steps are never interleaved.
+pub trait Reporter: Send + Sync {
+    /// Reports an event
+    fn report(&self, event: &Event);
+
+    /// Whether scripts may write their output directly to the terminal instead
+    /// of having it captured and reported as [`Event::Output`]
+    fn streams_output(&self) -> bool {
+        false
+    }
+
+    /// Reports that the install is complete, after all of its events
+    fn finish(&self) {}
+}
+
+/// Prints events as colored text
+pub struct ConsoleReporter;
+impl Reporter for ConsoleReporter {
+    fn report(&self, event: &Event) {
+        match event {
+            Event::Action { label, description, .. } => {
+                print!("{} {}", format!("[{}]", label).bold(), description);
+            },
+            Event::Outcome { outcome, detail, .. } => {
+                println!("{}", outcome_suffix(*outcome, detail.as_deref()));
+            },
+            Event::Diff { text, .. } => {
+                for line in text.lines() {
+                    println!("{}", color_diff_line(line));
+                }
+            },
+            Event::Output { text, stderr: false, .. } => print!("{}", text),
+            Event::Output { text, stderr: true, .. } => eprint!("{}", text),
+            Event::Retry { retry, retries, message, .. } => {
+                eprintln!("  {} {}", format!("Retrying ({}/{}):", retry,
+                                             retries).yellow(), message);
+            },
+            Event::Error { message, .. } => {
+                eprintln!("  {} {}", "Error:".bold().red(), message);
+            },
+        }
+    }
+
+    fn streams_output(&self) -> bool {
+        true
+    }
+}
+
+/// Prints each event as a line of JSON
+pub struct JsonReporter;
+impl Reporter for JsonReporter {
+    fn report(&self, event: &Event) {
+        // Events only contain strings and numbers, so they always serialize
+        if let Ok(line) = serde_json::to_string(event) {
+            println!("{}", line);
+        }
+    }
+}
+
+/// Discards every event, for installs whose progress is only followed through
+/// an [`InstallObserver`](crate::InstallObserver)
+pub struct NullReporter;
+impl Reporter for NullReporter {
+    fn report(&self, _event: &Event) {}
+}
+
+/// Prints only errors and retried scripts to stderr, each preceded by the
+/// command that caused it, followed by a summary if any errors occurred
+#[derive(Default)]
+pub struct QuietReporter {
+    /// The most recent command that hasn't been printed yet
+    action: Mutex<Option<String>>,
+
+    /// The steps of the errors that occurred, in order
+    errors: Mutex<Vec<Option<usize>>>,
+}
+impl Reporter for QuietReporter {
+    fn report(&self, event: &Event) {
+        let mut action = self.action.lock().unwrap();
+        match event {
+            Event::Action { label, description, .. } => {
+                *action = Some(format!("{} {}", format!("[{}]", label).bold(),
+                                       description));
+            },
+            Event::Retry { retry, retries, message, .. } => {
+                if let Some(action) = action.take() {
+                    eprintln!("{}", action);
+                }
+                eprintln!("  {} {}", format!("Retrying ({}/{}):", retry,
+                                             retries).yellow(), message);
+            },
+            Event::Error { step, message } => {
+                if let Some(action) = action.take() {
+                    eprintln!("{}", action);
+                }
+                eprintln!("  {} {}", "Error:".bold().red(), message);
+                self.errors.lock().unwrap().push(*step);
+            },
+            _ => (),
+        }
+    }
+
+    fn finish(&self) {
+        // Watch mode reuses the reporter for each re-install
+        let errors = std::mem::take(&mut *self.errors.lock().unwrap());
+        if let Some(summary) = error_summary(&errors) {
+            eprintln!("{}", summary.bold());
+        }
+    }
+}
+
+/// Returns a summary of the errors that occurred during an install, given the
+/// step of each error, or None if no errors occurred
+///
+/// ```
+/// assert_eq!(error_summary(&[Some(1), Some(1), Some(3)]),
+///            Some(String::from("3 errors in 2 steps")));
+/// ```
+fn error_summary(errors: &[Option<usize>]) -> Option<String> {
+    if errors.is_empty() { return None; }
+    let plural = |n: usize, noun: &str| {
+        format!("{} {}{}", n, noun, if n == 1 { "" } else { "s" })
+    };
+
+    let mut steps: Vec<_> = errors.iter().flatten().collect();
+    steps.sort();
+    steps.dedup();
+    Some(match steps.len() {
+        0 => plural(errors.len(), "error"),
+        n => format!("{} in {}", plural(errors.len(), "error"),
+                     plural(n, "step")),
+    })
+}
+
+/// Records events so that tests can assert on them
+#[cfg(test)]
+#[derive(Default)]
+pub struct CaptureReporter {
+    /// The events that were reported, in order
+    pub events: Mutex<Vec<Event>>,
+}
+#[cfg(test)]
+impl Reporter for CaptureReporter {
+    fn report(&self, event: &Event) {
+        self.events.lock().unwrap().push(event.clone());
+    }
+}
+
+/// Returns the text that follows a command's description on the console to
+/// show its outcome
+fn outcome_suffix(outcome: Outcome, detail: Option<&str>) -> String {
+    let label = match outcome {
+        Outcome::Created => "created".green(),
+        Outcome::Updated => "updated".yellow(),
+        Outcome::Unchanged => "unchanged".dimmed(),
+        Outcome::Failed => "FAILED".bold().red(),
+        Outcome::Skipped => {
+            return format!(" ({}: excluded by {})", "skipped".cyan(),
+                           detail.unwrap_or_default());
+        },
+        Outcome::DryRun => {
+            return match detail {
+                Some(change) => format!(" (DRY RUN: {})", change),
+                None => String::from(" (DRY RUN)"),
+            };
+        },
+        Outcome::Pending => return String::new(),
+    };
+    format!(" ({})", label)
+}
+
+/// Colors a line of a unified diff according to its type
+fn color_diff_line(line: &str) -> colored::ColoredString {
+    if line.starts_with("+++") || line.starts_with("---") {
+        line.bold()
+    } else if line.starts_with('+') {
+        line.green()
+    } else if line.starts_with('-') {
+        line.red()
+    } else if line.starts_with("@@") {
+        line.cyan()
+    } else {
+        line.normal()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outcome_suffix() {
+        assert_eq!(outcome_suffix(Outcome::Created, None), " (created)");
+        assert_eq!(outcome_suffix(Outcome::Skipped, Some("*.sh")),
+                   " (skipped: excluded by *.sh)");
+        assert_eq!(outcome_suffix(Outcome::DryRun, Some("create")),
+                   " (DRY RUN: create)");
+        assert_eq!(outcome_suffix(Outcome::DryRun, None), " (DRY RUN)");
+        assert_eq!(outcome_suffix(Outcome::Pending, None), "");
+    }
+
+    #[test]
+    fn test_error_summary() {
+        assert_eq!(error_summary(&[]), None);
+        assert_eq!(error_summary(&[Some(2)]),
+                   Some(String::from("1 error in 1 step")));
+        assert_eq!(error_summary(&[Some(1), Some(1), Some(3)]),
+                   Some(String::from("3 errors in 2 steps")));
+        assert_eq!(error_summary(&[None]), Some(String::from("1 error")));
+    }
+
+    #[test]
+    fn test_event_json() {
+        let event = Event::Outcome { step: Some(2), outcome: Outcome::DryRun,
+                                     detail: Some(String::from("create")) };
+
+        assert_eq!(serde_json::to_string(&event).unwrap(),
+                   "{\"event\":\"outcome\",\"step\":2,\"outcome\":\"dry_run\",\
+                    \"detail\":\"create\"}");
+    }
+}
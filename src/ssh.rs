@@ -5,49 +5,313 @@
 //!
 //! ```
 //! let staging_dir = Path::new("/tmp/staging");
-//! let host = "user@hostname";
-//! stage_file("foo.sh", "~/foo.sh", staging_dir);
-//! send_staged_files(staging_dir, host);
-//! send_command("bash ~/foo.sh", host);
+//! let host = Host::new("user@hostname", SshOptions::default());
+//! stage_file("foo.sh", "~/foo.sh", staging_dir, false);
+//! send_staged_files(staging_dir, &host, None);
+//! send_command("bash ~/foo.sh", &host, None);
 //! ```
 
 use anyhow::{bail, anyhow, Context, Result};
+use clap::ValueEnum;
+use colored::Colorize;
+use serde::Deserialize;
 use std::env;
-use shellexpand::tilde_with_context;
-use std::fs::{read_dir, remove_dir_all};
+use std::fmt;
+use shellexpand::{tilde, tilde_with_context};
+use std::fs::{read_dir, read_to_string, remove_dir, remove_dir_all,
+    remove_file};
+use std::io::{self, IsTerminal, Write};
+#[cfg(target_family = "unix")]
+use std::fs::metadata;
+#[cfg(target_family = "unix")]
+use std::os::unix::fs::PermissionsExt;
+#[cfg(target_family = "unix")]
+use std::time::UNIX_EPOCH;
 use std::path::{MAIN_SEPARATOR_STR, Path, PathBuf};
-use std::process::{Command, Stdio};
-use super::local::copy_file;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tempfile::{NamedTempFile, TempPath};
+use super::exit::{Error, Failure, classify};
+use super::hash::{HashAlgorithm, hash_file, hash_reader};
+use super::backup::utc_fields;
+#[cfg(feature = "native-ssh")]
+use super::native;
+use super::local::{CapturedOutput, copy_file, copy_file_preserving_mtime,
+    escalation, execute_command, execute_command_indented,
+    execute_command_with_input, quote, shell_command_line};
+use super::manifest::Shell;
+
+/// The exit status used by remote commands to indicate that a file is missing
+const MISSING_FILE_STATUS: i32 = 100;
+
+/// The exit status used by SSH to indicate that the connection failed
+const SSH_ERROR_STATUS: i32 = 255;
+
+/// How long to wait before retrying a transfer or command that failed because
+/// of a network problem, which doubles after each retry
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The environment variable that the escalation command's password is read
+/// from with [`SudoPassword::Env`]
+const SUDO_PASSWORD_VAR: &str = "COLIRU_SUDO_PASSWORD";
+
+/// The environment variable that the SSH password is read from instead of
+/// prompting for it
+const SSH_PASSWORD_VAR: &str = "COLIRU_SSH_PASSWORD";
+
+/// The environment variable that marks coliru as being executed by SSH through
+/// `SSH_ASKPASS`, which holds the location of the file that the SSH password is
+/// read from
+const ASKPASS_VAR: &str = "COLIRU_ASKPASS";
+
+/// A remote machine along with the options used to connect to it
+///
+/// ```
+/// let host = Host::new("user@hostname", SshOptions::default());
+/// assert_eq!(host.to_string(), "user@hostname");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Host {
+    /// An SSH alias or a string in the form `[user@]hostname[:port]`, or an
+    /// empty string for the local machine
+    pub name: String,
+
+    /// The options used to connect to the machine
+    pub options: SshOptions,
+}
+impl Host {
+    /// Creates a new Host that's connected to with a set of options
+    pub fn new(name: &str, options: SshOptions) -> Host {
+        Host { name: name.to_owned(), options }
+    }
+
+    /// Returns whether the host is the local machine
+    pub fn is_local(&self) -> bool {
+        self.name.is_empty()
+    }
+}
+impl fmt::Display for Host {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+/// Options that control how SSH and SCP authenticate with remote machines
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SshOptions {
+    /// The private key used to authenticate, passed to `-i`
+    pub identity_file: Option<String>,
+
+    /// The SSH configuration file used instead of `~/.ssh/config`, passed to
+    /// `-F`
+    pub config_file: Option<String>,
+
+    /// The bastion host that connections are made through, passed to `-J`
+    pub jump_host: Option<String>,
+
+    /// Whether to compress data sent over connections, passed as `-C`
+    pub compress: bool,
+
+    /// How many seconds to wait for a connection before giving up, passed to
+    /// `-o ConnectTimeout`
+    pub connect_timeout: Option<u64>,
+
+    /// Whether to forward the local SSH agent to remote machines (e.g. for
+    /// scripts that clone Git repositories over SSH), passed as
+    /// `-o ForwardAgent=yes`
+    pub forward_agent: bool,
+
+    /// Whether to fail instead of prompting for passwords and passphrases,
+    /// passed as `-o BatchMode=yes`
+    pub batch_mode: bool,
+
+    /// How many times to retry transfers and commands that fail because of a
+    /// network problem
+    pub retries: u32,
+
+    /// How the keys of remote machines are checked, passed to
+    /// `-o StrictHostKeyChecking`, or None to use SSH's configuration
+    pub host_key_policy: Option<HostKeyPolicy>,
+
+    /// How files and commands are sent to remote machines
+    pub transport: Transport,
+
+    /// The operating system of remote machines
+    pub remote_os: RemoteOs,
+
+    /// How the escalation command set with `--sudo` gets a password on remote
+    /// machines
+    pub sudo_password: SudoPassword,
+
+    /// The password used to authenticate with remote machines, or None to let
+    /// SSH authenticate on its own
+    pub password: Option<SshPassword>,
+}
+
+/// A password used to authenticate with remote machines, which is read once by
+/// [`read_ssh_password`] and shared by every connection
+///
+/// The password is written to a file that only the current user can read,
+/// which SSH reads it from through [`askpass_password`]. The file is removed
+/// once every copy of the SshPassword has been dropped.
+#[derive(Clone)]
+pub struct SshPassword(Arc<(String, TempPath)>);
+impl SshPassword {
+    /// Returns the password
+    pub fn as_str(&self) -> &str {
+        &self.0.0
+    }
+
+    /// Returns the path of the file that the password is written to
+    fn path(&self) -> &Path {
+        &self.0.1
+    }
+}
+impl fmt::Debug for SshPassword {
+    /// Hides the password, so that it isn't printed by accident
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SshPassword(..)")
+    }
+}
+impl PartialEq for SshPassword {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+/// How files and commands are sent to remote machines
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// The `ssh` and `scp` programs
+    #[default]
+    Scp,
+
+    /// The `ssh` and `sftp` programs, for servers that don't allow SCP
+    Sftp,
+
+    /// The `ssh` and `rsync` programs, which only transfer the parts of files
+    /// that changed
+    Rsync,
+
+    /// The `ssh` and `tar` programs, which transfer every file in a single
+    /// stream
+    Tar,
+
+    /// A built-in SSH client, which requires the `native-ssh` feature
+    Native,
+}
+
+/// How the host keys of remote machines are checked before connecting
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyPolicy {
+    /// Only connect to hosts whose keys are already in `~/.ssh/known_hosts`
+    Strict,
+
+    /// Add the keys of unknown hosts to `~/.ssh/known_hosts`, but refuse to
+    /// connect to known hosts whose keys changed
+    AcceptNew,
+
+    /// Connect to any host without checking its key
+    Off,
+}
+
+/// The operating system of a remote machine, which determines how paths and
+/// commands are sent to it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteOs {
+    /// A Unix-like operating system with a POSIX shell
+    #[default]
+    Unix,
+
+    /// Windows running OpenSSH, whose default shell is the command prompt
+    Windows,
+}
+
+/// How the escalation command set with `--sudo` gets a password on remote
+/// machines
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SudoPassword {
+    /// The escalation command doesn't need a password, and fails instead of
+    /// prompting for one
+    #[default]
+    None,
+
+    /// A terminal is allocated on the remote machine, so that the escalation
+    /// command can prompt for a password
+    Tty,
+
+    /// The password is read from `COLIRU_SUDO_PASSWORD` and passed to
+    /// `sudo -S`
+    Env,
+}
 
 /// Makes a relative path absolute according to a certain base directory
 ///
-/// Paths begining with tildes are interpreted as absolute paths.
+/// Paths begining with tildes are interpreted as absolute paths. If the remote
+/// machine runs Windows, the path is first translated with
+/// [`translate_windows_path`].
 ///
 /// ```
-/// assert_eq!(resolve_path("dir1/foo", "~/dir2"), "~/dir2/dir1/foo");
-/// assert_eq!(resolve_path("/dir1/foo", "~/dir2"), "/dir1/foo");
-/// assert_eq!(resolve_path("~/dir1/foo", "~/dir2"), "~/dir1/foo");
+/// let os = RemoteOs::Unix;
+/// assert_eq!(resolve_path("dir1/foo", "~/dir2", os), "~/dir2/dir1/foo");
+/// assert_eq!(resolve_path("/dir1/foo", "~/dir2", os), "/dir1/foo");
+/// assert_eq!(resolve_path("~/dir1/foo", "~/dir2", os), "~/dir1/foo");
 /// ```
-pub fn resolve_path(src: &str, dir: &str) -> String {
-    if !src.starts_with("~") && Path::new(src).is_relative() {
+pub fn resolve_path(src: &str, dir: &str, os: RemoteOs) -> String {
+    let src = match os {
+        RemoteOs::Unix => src.to_owned(),
+        RemoteOs::Windows => translate_windows_path(src),
+    };
+    if !src.starts_with(['~', '/']) && Path::new(&src).is_relative() {
         return format!("{dir}/{src}")
     }
-    src.to_owned()
+    src
+}
+
+/// Translates a Windows path into the form that OpenSSH on Windows expects
+///
+/// Backslashes are replaced with forward slashes, `%USERPROFILE%` is replaced
+/// with a tilde, and paths that start with a drive letter are prefixed with a
+/// forward slash.
+///
+/// ```
+/// assert_eq!(translate_windows_path("%USERPROFILE%\\foo"), "~/foo");
+/// assert_eq!(translate_windows_path("C:\\foo"), "/C:/foo");
+/// assert_eq!(translate_windows_path("dir\\foo"), "dir/foo");
+/// ```
+pub fn translate_windows_path(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    if let Some(rest) = path.strip_prefix("%USERPROFILE%") {
+        return format!("~{rest}");
+    }
+    let bytes = path.as_bytes();
+    if bytes.len() >= 2 && bytes[0].is_ascii_alphabetic() && bytes[1] == b':' {
+        return format!("/{path}");
+    }
+    path
 }
 
 /// Copies a file to an SCP staging directory
 ///
 /// Tildes are expanded and relative paths are interpreted relative to the
-/// remote user's home directory.
+/// remote user's home directory. The staged file keeps the source file's
+/// modification time if `preserve_mtime` is true.
 ///
 /// ```
 /// // Prepare to transfer foo to ~/foo, bar to /bar, and baz to ~/baz
 /// let staging_dir = Path::new("/tmp/staging");
-/// stage_file("foo", "~/foo", staging_dir);
-/// stage_file("bar", "/bar", staging_dir);
-/// stage_file("baz", "baz", staging_dir);
+/// stage_file("foo", "~/foo", staging_dir, false);
+/// stage_file("bar", "/bar", staging_dir, false);
+/// stage_file("baz", "baz", staging_dir, true);
 /// ```
-pub fn stage_file(src: &str, dst: &str, staging_dir: &Path) -> Result<()> {
+pub fn stage_file(src: &str, dst: &str, staging_dir: &Path,
+                  preserve_mtime: bool) -> Result<()> {
     // Staging directories are used to copy multiple files at once while
     // automatically creating missing directories on the remote machine. The
     // example code above produces the following staging directory layout:
@@ -82,103 +346,1239 @@ pub fn stage_file(src: &str, dst: &str, staging_dir: &Path) -> Result<()> {
             None => Err(anyhow!("Failed to get root of {}", _dst.display())),
         }?).join(MAIN_SEPARATOR_STR);
 
-        let dst_without_root = _dst.strip_prefix(root).with_context(|| {
-            format!("Failed to strip root from {}", _dst.display())
-        })?;
-        _dst = root_dir.join(dst_without_root);
+        let dst_without_root = _dst.strip_prefix(root).with_context(|| {
+            format!("Failed to strip root from {}", _dst.display())
+        })?;
+        _dst = root_dir.join(dst_without_root);
+    }
+
+    if preserve_mtime {
+        copy_file_preserving_mtime(src, _dst.to_string_lossy().to_mut())
+    } else {
+        copy_file(src, _dst.to_string_lossy().to_mut())
+    }
+}
+
+/// Transfers the files in an SCP staging directory to a remote machine
+///
+/// `host` may be an SSH alias or a string in the form `user@hostname`. Use
+/// [`stage_file`] to produce a staging directory. The permissions and
+/// modification time of each staged file are applied to the transferred file,
+/// since SCP doesn't update the permissions of existing files. The contents of
+/// the staging directory are deleted after they are successfully transferred.
+/// The output of SCP is captured instead of printed if `capture` is provided.
+/// If an escalation command has been set with `--sudo`, files outside of the
+/// home directory are transferred to a temporary directory and then placed with
+/// `install` by that command on the remote machine, which gets a password
+/// according to the host's [`SudoPassword`]. Attributes aren't applied on
+/// Windows hosts, which don't support escalation commands. Files that already
+/// exist with the same contents aren't transferred, except to Windows hosts.
+///
+/// ```
+/// send_staged_files(Path::new("/tmp/staging"), &host, None);
+/// ```
+pub fn send_staged_files(staging_dir: &Path, host: &Host,
+                         mut capture: Option<&mut CapturedOutput>) ->
+    Result<()> {
+
+    // Files placed with the escalation command get their attributes from it,
+    // and Windows hosts don't have chmod or touch
+    let windows = host.options.remote_os == RemoteOs::Windows;
+    let attributes_cmd = if windows {
+        None
+    } else {
+        staged_attributes_command(staging_dir, escalation().is_none())?
+    };
+    if !windows {
+        remove_unchanged_files(staging_dir, host, capture.as_deref_mut())
+            .context("Failed to compare staged files")?;
+    }
+
+    let home_dir = staging_dir.join("home");
+    if home_dir.exists() {
+        send_dir(home_dir.to_string_lossy().to_mut(),
+                 if windows { "." } else { "~" }, host,
+                 capture.as_deref_mut())?;
+        remove_dir_all(&home_dir).with_context(|| {
+            format!("Failed to remove staging dir {} after use",
+                    &home_dir.display())
+        })?;
+    }
+    let root_dir = staging_dir.join("root");
+    if root_dir.exists() {
+        if let Some(command) = escalation() {
+            if windows {
+                bail!("Installing files with {} isn't supported on Windows \
+                       hosts", command);
+            }
+            let temp_dir = format!("~/.coliru-root-{}", std::process::id());
+            let script = escalated_install_script(&root_dir)?;
+            send_command(&format!("mkdir -p {temp_dir}"), host,
+                         capture.as_deref_mut())?;
+            send_dir(root_dir.to_string_lossy().to_mut(), &temp_dir, host,
+                     capture.as_deref_mut())?;
+            // The temporary directory is removed even if installation fails
+            send_escalated_command(&format!("{} sh -c {} sh {temp_dir}; \
+                                             status=$?; rm -rf {temp_dir}; \
+                                             exit $status",
+                                            escalation_prefix(command,
+                                                host.options.sudo_password),
+                                            quote(&script)), host,
+                                   capture.as_deref_mut())
+                .with_context(|| format!("Failed to install files with {}",
+                                         command))?;
+        } else {
+            send_dir(root_dir.to_string_lossy().to_mut(), "/", host,
+                     capture.as_deref_mut())?;
+        }
+        remove_dir_all(&root_dir).with_context(|| {
+            format!("Failed to remove staging dir {} after use",
+                    &root_dir.display())
+        })?;
+    }
+    if let Some(cmd) = attributes_cmd {
+        send_command(&cmd, host, capture)
+            .context("Failed to set attributes of transferred files")?;
+    }
+    Ok(())
+}
+
+/// Removes the files in an SCP staging directory whose destinations on a remote
+/// machine already have the same contents, so that only changed files are
+/// transferred
+///
+/// The remote files are hashed with `sha256sum` (or `shasum` if it's missing)
+/// by a single command. Directories left empty are removed as well.
+///
+/// ```
+/// remove_unchanged_files(Path::new("/tmp/staging"), &host, None)?;
+/// ```
+fn remove_unchanged_files(staging_dir: &Path, host: &Host,
+                          capture: Option<&mut CapturedOutput>) -> Result<()> {
+    let mut files = vec![];
+    staged_files(&staging_dir.join("home"), "~", &mut files)?;
+    staged_files(&staging_dir.join("root"), "", &mut files)?;
+    if files.is_empty() { return Ok(()); }
+
+    // Each remote file's hash is printed on its own line, or a dash if the file
+    // doesn't exist or can't be read (e.g. it's only readable by root)
+    let paths: Vec<String> = files.iter().map(|x| quote_path(&x.1)).collect();
+    let command = format!("h() {{ sha256sum 2>/dev/null || shasum -a 256; }}; \
+                           for f in {}; do if [ -f \"$f\" ]; then \
+                           h 2>/dev/null < \"$f\" || echo -; else echo -; fi; \
+                           done", paths.join(" "));
+    let mut output = CapturedOutput::default();
+    send_command(&command, host, Some(&mut output))?;
+    if let Some(capture) = capture {
+        capture.stderr.push_str(&output.stderr);
+    }
+
+    let hashes: Vec<&str> = output.stdout.lines().map(|x| {
+        x.split_whitespace().next().unwrap_or_default()
+    }).collect();
+    if hashes.len() != files.len() {
+        bail!("Unexpected output from remote machine");
+    }
+    for ((path, _), remote_hash) in files.iter().zip(hashes) {
+        if hash_file(path, HashAlgorithm::Sha256)? == remote_hash {
+            remove_file(path).with_context(|| {
+                format!("Failed to remove {}", path.display())
+            })?;
+        }
+    }
+    remove_empty_dirs(&staging_dir.join("home"))?;
+    remove_empty_dirs(&staging_dir.join("root"))?;
+    Ok(())
+}
+
+/// Recursively lists the files in a staging directory along with their remote
+/// destinations
+fn staged_files(dir: &Path, remote_dir: &str,
+                files: &mut Vec<(PathBuf, String)>) -> Result<()> {
+    if !dir.exists() { return Ok(()); }
+
+    let mut items = read_dir(dir).and_then(|x| x.collect::<Result<Vec<_>, _>>())
+        .with_context(|| {
+            format!("Failed to list contents of {}", dir.display())
+        })?;
+    items.sort_by_key(|x| x.file_name());
+
+    for item in items {
+        let path = item.path();
+        let remote_path = format!("{}/{}", remote_dir,
+                                  item.file_name().to_string_lossy());
+        if path.is_dir() {
+            staged_files(&path, &remote_path, files)?;
+        } else {
+            files.push((path, remote_path));
+        }
+    }
+    Ok(())
+}
+
+/// Recursively removes the empty directories in a directory, including the
+/// directory itself, and returns whether it was removed
+fn remove_empty_dirs(dir: &Path) -> Result<bool> {
+    if !dir.is_dir() { return Ok(false); }
+
+    let items = read_dir(dir).and_then(|x| x.collect::<Result<Vec<_>, _>>())
+        .with_context(|| {
+            format!("Failed to list contents of {}", dir.display())
+        })?;
+    let mut is_empty = true;
+    for item in items {
+        if !remove_empty_dirs(&item.path())? {
+            is_empty = false;
+        }
+    }
+    if is_empty {
+        remove_dir(dir).with_context(|| {
+            format!("Failed to remove {}", dir.display())
+        })?;
+    }
+    Ok(is_empty)
+}
+
+/// Builds a shell command that applies the permissions and modification time of
+/// each file in an SCP staging directory to its destination on the remote
+/// machine
+///
+/// Files outside of the home directory are only included if `include_root` is
+/// true. Returns None if there are no staged files or if permissions aren't
+/// available on the local platform.
+///
+/// ```
+/// let cmd = staged_attributes_command(Path::new("/tmp/staging"), true)?;
+/// assert_eq!(cmd, Some(String::from("chmod 755 ~/'foo.sh' && \\
+///     env TZ=UTC0 touch -m -t 202410101204.05 ~/'foo.sh'")));
+/// ```
+#[cfg(target_family = "unix")]
+pub fn staged_attributes_command(staging_dir: &Path, include_root: bool) ->
+    Result<Option<String>> {
+
+    let mut files = vec![];
+    staged_attributes(&staging_dir.join("home"), "~", &mut files)?;
+    if include_root {
+        staged_attributes(&staging_dir.join("root"), "", &mut files)?;
+    }
+    files.sort();
+
+    if files.is_empty() { return Ok(None); }
+    Ok(Some(files.iter().map(|(path, mode, mtime)| {
+        format!("chmod {:o} {path} && {}", mode, touch_command(path, *mtime),
+                path=quote_path(path))
+    }).collect::<Vec<_>>().join(" && ")))
+}
+#[cfg(not(target_family = "unix"))]
+pub fn staged_attributes_command(_staging_dir: &Path, _include_root: bool) ->
+    Result<Option<String>> {
+
+    Ok(None)
+}
+
+/// Builds a shell command that sets the modification time of a file on the
+/// remote machine
+#[cfg(target_family = "unix")]
+fn touch_command(path: &str, mtime: u64) -> String {
+    let [year, month, day, hour, minute, second] = utc_fields(mtime);
+    format!("env TZ=UTC0 touch -m -t {:04}{:02}{:02}{:02}{:02}.{:02} {}", year,
+            month, day, hour, minute, second, quote_path(path))
+}
+
+/// Builds a shell script that places each file in the `root` subdirectory of an
+/// SCP staging directory from a temporary directory on the remote machine
+///
+/// The script is meant to be run by the escalation command, with the temporary
+/// directory that the files were transferred to as its first argument. Each
+/// file is placed with `install`, which creates it with the staged file's
+/// permissions, and then given the staged file's modification time.
+///
+/// ```
+/// let script = escalated_install_script(Path::new("/tmp/staging/root"))?;
+/// assert_eq!(script, "mkdir -p '/etc' && \
+///     install -m 644 \"$1\"'/etc/foo.conf' '/etc/foo.conf' && \
+///     env TZ=UTC0 touch -m -t 202410101204.05 '/etc/foo.conf'");
+/// ```
+#[cfg(target_family = "unix")]
+pub fn escalated_install_script(root_dir: &Path) -> Result<String> {
+    let mut files = vec![];
+    staged_attributes(root_dir, "", &mut files)?;
+    files.sort();
+
+    Ok(files.iter().map(|(path, mode, mtime)| {
+        let parent = Path::new(path).parent().unwrap_or(Path::new("/"));
+        format!("mkdir -p {} && install -m {:o} \"$1\"{path} {path} && {}",
+                quote_path(&parent.to_string_lossy()), mode,
+                touch_command(path, *mtime), path=quote_path(path))
+    }).collect::<Vec<_>>().join(" && "))
+}
+#[cfg(not(target_family = "unix"))]
+pub fn escalated_install_script(_root_dir: &Path) -> Result<String> {
+    // Permissions aren't available, so files are copied as they are
+    Ok(String::from("cp -R \"$1\"/. /"))
+}
+
+/// Returns the escalation command with the options needed to get a password
+/// according to `strategy`
+///
+/// ```
+/// assert_eq!(escalation_prefix("sudo", SudoPassword::None), "sudo -n");
+/// ```
+fn escalation_prefix(command: &str, strategy: SudoPassword) -> String {
+    match strategy {
+        SudoPassword::None => format!("{command} -n"),
+        SudoPassword::Tty => command.to_owned(),
+        SudoPassword::Env => format!("{command} -S -p ''"),
+    }
+}
+
+/// Executes a command that runs the escalation command on another machine via
+/// SSH, giving it a password according to the host's [`SudoPassword`]
+///
+/// Output isn't captured with [`SudoPassword::Tty`], since the escalation
+/// command prompts for its password on the terminal.
+fn send_escalated_command(command: &str, host: &Host,
+                          capture: Option<&mut CapturedOutput>) -> Result<()> {
+    let password = match host.options.sudo_password {
+        SudoPassword::None => return send_command(command, host, capture),
+        SudoPassword::Tty => None,
+        SudoPassword::Env => {
+            Some(env::var(SUDO_PASSWORD_VAR).unwrap_or_default() + "\n")
+        },
+    };
+
+    #[cfg(feature = "native-ssh")]
+    if let Some(options) = native_options(host) {
+        let Some(password) = password else {
+            bail!("The tty sudo password strategy isn't supported by the \
+                   native transport");
+        };
+        return native::send_command(command, &host.name, options, false, None,
+                                    Some(password.as_bytes()), capture);
+    }
+
+    match password {
+        Some(password) => send_command_with(command, host, &[], |cmd| {
+            execute_command_with_input(cmd, password.as_bytes(), capture)
+        }),
+        None => send_command_with(command, host, &["-tt"], |cmd| {
+            execute_command(cmd, None)
+        }),
+    }
+}
+
+/// Recursively collects the remote path, permission bits, and modification
+/// time of each file in a staging subdirectory
+#[cfg(target_family = "unix")]
+fn staged_attributes(dir: &Path, remote_dir: &str,
+                     files: &mut Vec<(String, u32, u64)>) -> Result<()> {
+    if !dir.exists() { return Ok(()); }
+
+    let items = read_dir(dir).with_context(|| {
+        format!("Failed to list contents of {}", dir.display())
+    })?;
+    for item in items {
+        let path = item.with_context(|| {
+            format!("Failed to list contents of {}", dir.display())
+        })?.path();
+        let remote_path = format!("{}/{}", remote_dir,
+            path.file_name().unwrap_or_default().to_string_lossy());
+
+        let metadata = metadata(&path).with_context(|| {
+            format!("Failed to read {}", path.display())
+        })?;
+        if metadata.is_dir() {
+            staged_attributes(&path, &remote_path, files)?;
+        } else {
+            let mtime = metadata.modified().ok()
+                .and_then(|x| x.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |x| x.as_secs());
+            files.push((remote_path, metadata.permissions().mode() & 0o7777,
+                        mtime));
+        }
+    }
+    Ok(())
+}
+
+/// Copies a directory to another machine via SCP and merges it with a
+/// destination directory
+///
+/// `host` may be an SSH alias or a string in the form `user@hostname`. The
+/// output of SCP is captured instead of printed if `capture` is provided. The
+/// transfer is retried according to [`with_retries`].
+///
+/// ```
+/// send_dir("new_home", "~/", &host, None);
+/// ```
+fn send_dir(src: &str, dst: &str, host: &Host,
+            capture: Option<&mut CapturedOutput>) -> Result<()> {
+    with_retries(host, capture, |capture| {
+        send_dir_once(src, dst, host, capture)
+    })
+}
+
+/// Copies a directory to another machine once with the host's transport,
+/// without retrying
+fn send_dir_once(src: &str, dst: &str, host: &Host,
+                 capture: Option<&mut CapturedOutput>) -> Result<()> {
+    #[cfg(feature = "native-ssh")]
+    if let Some(options) = native_options(host) {
+        return native::send_dir(Path::new(src), dst, &host.name, options);
+    }
+
+    match host.options.transport {
+        Transport::Sftp => return send_dir_sftp(src, dst, host, capture),
+        Transport::Rsync => return send_dir_rsync(src, dst, host, capture),
+        Transport::Tar => return send_dir_tar(src, dst, host, capture),
+        _ => {},
+    }
+
+    // To avoid the source directory being copied as a subdirectory of the
+    // destination directory, we must send the contents of the directory as
+    // separate sources of a single SCP command.
+    let items = read_dir(src).and_then(|x| x.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to list contents of {}", src))?;
+    if items.is_empty() { return Ok(()); }
+
+    let (destination, port) = split_port(&host.name);
+    let mut cmd = Command::new("scp");
+    cmd.stdout(Stdio::null());
+
+    add_connection_options(&mut cmd, &host.options);
+    if let Some(port) = port {
+        cmd.args(["-P", port]);
+    }
+    cmd.arg("-r");
+    cmd.args(items.iter().map(|x| x.path()));
+    cmd.arg(format!("{destination}:{dst}"));
+
+    let status = execute_command(&mut cmd, capture)?;
+    if !status.success() {
+        bail!("SCP terminated unsuccessfully: {}", status);
+    }
+    Ok(())
+}
+
+/// Copies a directory to another machine via SFTP and merges it with a
+/// destination directory
+///
+/// `host` may be an SSH alias or a string in the form `user@hostname`. The
+/// output of SFTP is captured instead of printed if `capture` is provided.
+///
+/// ```
+/// send_dir_sftp("new_home", "~", &host, None);
+/// ```
+fn send_dir_sftp(src: &str, dst: &str, host: &Host,
+                 capture: Option<&mut CapturedOutput>) -> Result<()> {
+    let mut batch = String::new();
+    sftp_batch(Path::new(src), &home_relative_path(dst), &mut batch)?;
+    let batch_file = NamedTempFile::new()
+        .and_then(|mut x| x.write_all(batch.as_bytes()).map(|_| x))
+        .context("Failed to write SFTP batch file")?;
+
+    let (destination, port) = split_port(&host.name);
+    let mut cmd = Command::new("sftp");
+    cmd.stdout(Stdio::null());
+
+    add_connection_options(&mut cmd, &host.options);
+    if let Some(port) = port {
+        cmd.args(["-P", port]);
+    }
+    cmd.arg("-b").arg(batch_file.path()).arg(destination);
+
+    let status = execute_command(&mut cmd, capture)?;
+    if !status.success() {
+        bail!("SFTP terminated unsuccessfully: {}", status);
+    }
+    Ok(())
+}
+
+/// Copies a directory to another machine via rsync and merges it with a
+/// destination directory
+///
+/// `host` may be an SSH alias or a string in the form `user@hostname`. Files
+/// whose contents already match are skipped and only the changed parts of
+/// other files are transferred. The output of rsync is captured instead of
+/// printed if `capture` is provided.
+///
+/// ```
+/// send_dir_rsync("new_home", "~", &host, None);
+/// ```
+fn send_dir_rsync(src: &str, dst: &str, host: &Host,
+                  capture: Option<&mut CapturedOutput>) -> Result<()> {
+    let mut cmd = Command::new("rsync");
+    cmd.stdout(Stdio::null());
+
+    // Staged files are always newer than their destinations, so contents are
+    // compared with checksums instead of modification times
+    cmd.args(["--recursive", "--links", "--perms", "--times", "--checksum"]);
+    cmd.args(["-e", &ssh_command_line(host)]);
+    add_askpass(&mut cmd, &host.options);
+    // Trailing slashes merge the contents of src into dst
+    cmd.arg(format!("{}/", src.trim_end_matches(['/', '\\'])));
+    cmd.arg(format!("{}:{}/", split_port(&host.name).0,
+                    home_relative_path(dst).trim_end_matches('/')));
+
+    let status = execute_command(&mut cmd, capture)?;
+    if !status.success() {
+        bail!("rsync terminated unsuccessfully: {}", status);
+    }
+    Ok(())
+}
+
+/// Copies a directory to another machine by piping a tar archive through SSH
+/// and merges it with a destination directory
+///
+/// `host` may be an SSH alias or a string in the form `user@hostname`. Unlike
+/// [`send_dir`], which starts a connection for each item, every file is sent
+/// over a single connection. The output of SSH and tar is captured instead of
+/// printed if `capture` is provided.
+///
+/// ```
+/// send_dir_tar("new_home", "~", &host, None);
+/// ```
+fn send_dir_tar(src: &str, dst: &str, host: &Host,
+                capture: Option<&mut CapturedOutput>) -> Result<()> {
+    // Archiving each item instead of the directory itself keeps tar from
+    // changing the attributes of the destination directory
+    let mut items = read_dir(src).and_then(|x| {
+        x.map(|item| item.map(|x| x.file_name())).collect::<Result<Vec<_>, _>>()
+    }).with_context(|| format!("Failed to list contents of {}", src))?;
+    if items.is_empty() { return Ok(()); }
+    items.sort();
+
+    let mut tar = Command::new("tar");
+    // Keep macOS from adding ._ files with extended attributes
+    tar.env("COPYFILE_DISABLE", "1");
+    tar.args(["-C", src, "-cf", "-"]).args(&items);
+    tar.stdout(Stdio::piped());
+    let mut tar_child = tar.spawn().with_context(|| {
+        format!("Failed to execute {:?}", tar)
+    })?;
+    let archive = tar_child.stdout.take().with_context(|| {
+        format!("Failed to read output of {:?}", tar)
+    })?;
+
+    let remote_dir = quote_path(&home_relative_path(dst));
+    let mut cmd = remote_command(host, &[], &format!(
+        "mkdir -p {remote_dir} && tar -C {remote_dir} -xf -"));
+    cmd.stdin(Stdio::from(archive));
+    cmd.stdout(Stdio::null());
+
+    let status = execute_command(&mut cmd, capture);
+    let tar_status = tar_child.wait().with_context(|| {
+        format!("Failed to execute {:?}", tar)
+    })?;
+    let status = status?;
+    if status.code() == Some(SSH_ERROR_STATUS) {
+        return Err(anyhow!(Error::Transport(format!(
+            "SSH terminated unsuccessfully: {}", status))));
+    }
+    if !status.success() {
+        bail!("SSH terminated unsuccessfully: {}", status);
+    }
+    if !tar_status.success() {
+        bail!("tar terminated unsuccessfully: {}", tar_status);
+    }
+    Ok(())
+}
+
+/// Returns the SSH command used to connect to a host as a single string for
+/// programs that run SSH themselves (e.g. rsync's `-e` option)
+///
+/// ```
+/// let host = Host::new("user@hostname:2222", SshOptions::default());
+/// assert_eq!(ssh_command_line(&host), "ssh -p 2222");
+/// ```
+fn ssh_command_line(host: &Host) -> String {
+    let cmd = ssh_command(host);
+    let mut args = vec![cmd.get_program().to_string_lossy().to_string()];
+    args.extend(cmd.get_args().map(|x| {
+        let arg = x.to_string_lossy();
+        if arg.contains(char::is_whitespace) {
+            format!("'{}'", arg)
+        } else {
+            arg.to_string()
+        }
+    }));
+    args.join(" ")
+}
+
+/// Converts a remote path that may start with a tilde into one that SFTP and
+/// rsync understand, since they resolve relative paths relative to the remote
+/// user's home directory instead of expanding tildes
+///
+/// ```
+/// assert_eq!(home_relative_path("~/foo"), "./foo");
+/// assert_eq!(home_relative_path("/foo"), "/foo");
+/// ```
+pub fn home_relative_path(path: &str) -> String {
+    match path.strip_prefix('~') {
+        Some(rest) => format!(".{rest}"),
+        None => path.to_owned(),
+    }
+}
+
+/// Recursively appends the SFTP batch commands that upload the contents of a
+/// local directory into a remote directory, creating any missing directories
+///
+/// ```
+/// let mut batch = String::new();
+/// sftp_batch(Path::new("new_home"), ".", &mut batch)?;
+/// ```
+fn sftp_batch(src: &Path, dst: &str, batch: &mut String) -> Result<()> {
+    let mut items = read_dir(src).and_then(|x| x.collect::<Result<Vec<_>, _>>())
+        .with_context(|| {
+            format!("Failed to list contents of {}", src.display())
+        })?;
+    items.sort_by_key(|x| x.file_name());
+
+    for item in items {
+        let _src = item.path();
+        let _dst = format!("{}/{}", dst.trim_end_matches('/'),
+                           item.file_name().to_string_lossy());
+        if _src.is_dir() {
+            // A leading dash ignores the error if the directory already exists
+            batch.push_str(&format!("-mkdir {}\n", sftp_quote(&_dst)));
+            sftp_batch(&_src, &_dst, batch)?;
+        } else {
+            batch.push_str(&format!("put {} {}\n",
+                                    sftp_quote(&_src.to_string_lossy()),
+                                    sftp_quote(&_dst)));
+        }
+    }
+    Ok(())
+}
+
+/// Quotes a path for use in an SFTP batch file
+fn sftp_quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Executes a command on another machine via SSH
+///
+/// `host` may be an SSH alias or a string in the form `user@hostname`. The
+/// command's stdout and stderr are captured instead of printed if `capture` is
+/// provided. Errors caused by SSH itself rather than the command are classified
+/// as transport failures. The command is retried according to
+/// [`with_retries`], so it should be safe to execute more than once.
+///
+/// ```
+/// send_command("echo 'Hello World'", &host, None);
+/// ```
+pub fn send_command(command: &str, host: &Host,
+                    capture: Option<&mut CapturedOutput>) -> Result<()> {
+    with_retries(host, capture, |capture| {
+        #[cfg(feature = "native-ssh")]
+        if let Some(options) = native_options(host) {
+            return native::send_command(command, &host.name, options, false,
+                                        None, None, capture);
+        }
+
+        send_command_with(command, host, &[], |cmd| {
+            execute_command(cmd, capture)
+        })
+    })
+}
+
+/// Executes a command on another machine via SSH, printing its output, and
+/// returns its exit code
+///
+/// Unlike [`send_command`], the command exiting unsuccessfully isn't an error.
+/// Errors caused by SSH itself are classified as transport failures. The
+/// command isn't retried.
+///
+/// ```
+/// let code = send_command_status("exit 3", &host)?;
+/// ```
+pub fn send_command_status(command: &str, host: &Host) -> Result<i32> {
+    #[cfg(feature = "native-ssh")]
+    if let Some(options) = native_options(host) {
+        return native::send_command_status(command, &host.name, options,
+                                           false, None, None, None);
+    }
+
+    let mut cmd = remote_command(host, &[], command);
+    let status = execute_command(&mut cmd, None).with_context(|| {
+        Error::Transport(String::from("Failed to run SSH"))
+    })?;
+    match status.code() {
+        Some(code) if code != SSH_ERROR_STATUS => Ok(code),
+        _ => Err(anyhow!(Error::Transport(format!(
+            "SSH terminated unsuccessfully: {}", status)))),
+    }
+}
+
+/// Makes an attempt to transfer files or execute a command on another machine,
+/// retrying it if it fails because of a network problem
+///
+/// Attempts are retried up to the number of times set by the host's options,
+/// waiting twice as long before each retry as before the previous one. Errors
+/// that aren't caused by network problems (e.g. authentication failures) are
+/// returned immediately. A message describing each retried error is captured
+/// instead of printed if `capture` is provided.
+///
+/// ```
+/// with_retries(&host, None, |capture| {
+///     send_command_with("true", &host, &[], |cmd| {
+///         execute_command(cmd, capture)
+///     })
+/// })?;
+/// ```
+fn with_retries<T, F>(host: &Host, mut capture: Option<&mut CapturedOutput>,
+                      mut attempt: F) -> Result<T>
+    where F: FnMut(Option<&mut CapturedOutput>) -> Result<T> {
+
+    let retries = host.options.retries;
+    let mut delay = RETRY_DELAY;
+    for retry in 1..=retries {
+        let why = match attempt(capture.as_deref_mut()) {
+            Ok(value) => return Ok(value),
+            Err(why) => why,
+        };
+        if !is_transient(&why, host) { return Err(why); }
+
+        let message = format!("  {} {:#}\n",
+                              format!("Retrying ({}/{}):", retry, retries)
+                                  .yellow(), why);
+        match capture.as_deref_mut() {
+            Some(capture) => capture.stderr.push_str(&message),
+            None => eprint!("{}", message),
+        }
+        thread::sleep(delay);
+        delay *= 2;
+    }
+    attempt(capture)
+}
+
+/// Determines whether an error was caused by a network problem that may resolve
+/// itself, rather than by authentication or by the remote command
+///
+/// A new connection is made to tell the difference. If it fails, the error is
+/// transient unless authentication failed. If it succeeds, the error is only
+/// transient if it was caused by SSH rather than the command (e.g. a dropped
+/// connection).
+fn is_transient(why: &anyhow::Error, host: &Host) -> bool {
+    match check_connection(host) {
+        Ok(()) => classify(why, Failure::Minor) == Failure::Transport,
+        Err(probe) => {
+            connection_error_reason(&format!("{:#}", probe)) !=
+                Some("authentication failed")
+        },
+    }
+}
+
+/// Deletes a file on another machine via SSH if it exists
+///
+/// ```
+/// remove_remote_file("~/.coliru/foo.sh", &host, None)?;
+/// ```
+pub fn remove_remote_file(path: &str, host: &Host,
+                          capture: Option<&mut CapturedOutput>) -> Result<()> {
+    let command = match host.options.remote_os {
+        RemoteOs::Unix => format!("rm -f {}", quote_path(path)),
+        RemoteOs::Windows => {
+            let path = match path.strip_prefix("~/") {
+                Some(rest) => format!("%USERPROFILE%/{}", rest),
+                None => path.trim_start_matches('/').to_owned(),
+            };
+            format!("del /f /q \"{}\" 2>nul", path.replace('/', "\\"))
+        },
+    };
+    send_command(&command, host, capture)
+}
+
+/// Executes a run command on another machine via SSH, indenting each line of
+/// its output like [`run_command`](super::local::run_command)
+///
+/// The SSH connection is closed if the command runs for longer than `timeout`.
+/// On Unix hosts, the command is also stopped with [`with_remote_timeout`].
+///
+/// ```
+/// run_remote_command("bash ~/foo.sh", &host, None, None);
+/// ```
+pub fn run_remote_command(command: &str, host: &Host,
+                          timeout: Option<Duration>,
+                          capture: Option<&mut CapturedOutput>) -> Result<()> {
+    let command = match timeout {
+        Some(timeout) if host.options.remote_os == RemoteOs::Unix => {
+            with_remote_timeout(command, timeout)
+        },
+        _ => command.to_owned(),
+    };
+
+    #[cfg(feature = "native-ssh")]
+    if let Some(options) = native_options(host) {
+        return native::send_command(&command, &host.name, options, true,
+                                    timeout, None, capture);
+    }
+
+    send_command_with(&command, host, &[], |cmd| {
+        execute_command_indented(cmd, timeout, capture)
+    })
+}
+
+/// Returns a command line that executes a run command in a directory on another
+/// machine via SSH
+///
+/// On Unix hosts, the command is executed by `shell` if one is provided. On
+/// Windows hosts, the command is executed by `shell` or by Windows PowerShell,
+/// since the default shell of OpenSSH on Windows is the command prompt.
+///
+/// ```
+/// assert_eq!(remote_command_line("sh foo.sh", ".coliru", None,
+///                                RemoteOs::Unix),
+///            "cd .coliru && sh foo.sh");
+/// ```
+pub fn remote_command_line(command: &str, dir: &str, shell: Option<Shell>,
+                           os: RemoteOs) -> String {
+
+    if os == RemoteOs::Unix {
+        return format!("cd {} && {}", dir, shell_command_line(command, shell));
+    }
+
+    let shell = shell.unwrap_or(Shell::Powershell);
+    let command = match shell {
+        Shell::Powershell | Shell::Pwsh => {
+            format!("Set-Location {}; {}", dir, command)
+        },
+        _ => format!("cd {} && {}", dir, command),
+    };
+    let (program, args) = shell.invocation();
+    format!("{} {} \"{}\"", program, args.join(" "),
+            command.replace('"', "\\\""))
+}
+
+/// Wraps a command so that a Unix host stops it if it runs for longer than
+/// `timeout`, even after the SSH connection is closed
+///
+/// The host's `timeout` program is used if it has one, which kills every
+/// process in the command's process group when it's from GNU coreutils. Hosts
+/// without it (e.g. macOS) run the command without a timeout.
+pub fn with_remote_timeout(command: &str, timeout: Duration) -> String {
+    let script = format!("if command -v timeout >/dev/null 2>&1; then \
+                          exec timeout -s KILL {} sh -c \"$1\"; fi; \
+                          exec sh -c \"$1\"", timeout.as_secs());
+    format!("sh -c {} sh {}", quote(&script), quote(command))
+}
+
+/// Executes a command on another machine via SSH using a function that runs
+/// the local SSH process, passing additional `options` to SSH
+fn send_command_with<F>(command: &str, host: &Host, options: &[&str],
+                        execute: F) -> Result<()>
+    where F: FnOnce(&mut Command) -> Result<ExitStatus> {
+
+    let mut cmd = remote_command(host, options, command);
+
+    // Errors that were already classified (e.g. timeouts) aren't caused by SSH
+    let status = execute(&mut cmd).map_err(|why| {
+        if why.is::<Error>() { return why; }
+        why.context(Error::Transport(String::from("Failed to run SSH")))
+    })?;
+    if status.code() == Some(SSH_ERROR_STATUS) {
+        return Err(anyhow!(Error::Transport(format!(
+            "SSH terminated unsuccessfully: {}", status))));
+    }
+    if !status.success() {
+        bail!("SSH terminated unsuccessfully: {}", status);
+    }
+    Ok(())
+}
+
+/// Checks whether a connection can be made to another machine via SSH without
+/// any user interaction
+///
+/// The host's password is used if it has one. Returns an Err containing SSH's
+/// error message if the connection fails.
+///
+/// ```
+/// check_connection(&host)?;
+/// ```
+pub fn check_connection(host: &Host) -> Result<()> {
+    #[cfg(feature = "native-ssh")]
+    if let Some(options) = native_options(host) {
+        return native::connect(&host.name, options).map(|_| ());
+    }
+
+    let mut options = vec![];
+    // Batch mode also disables the host's password
+    if host.options.password.is_none() {
+        options.extend(["-o", "BatchMode=yes"]);
+    }
+    options.extend(["-o", "ConnectTimeout=5"]);
+    let mut cmd = remote_command(host, &options, "exit 0");
+
+    let output = cmd.output().with_context(|| {
+        format!("Failed to execute {:?}", cmd)
+    })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{}", stderr.trim());
+    }
+    Ok(())
+}
+
+/// Checks whether a connection can be made to another machine before anything
+/// is installed on it
+///
+/// Returns an Err classified as a transport failure if the connection fails,
+/// which describes whether the hostname couldn't be resolved, authentication
+/// failed, or the connection timed out when that can be determined from the
+/// error.
+///
+/// ```
+/// preflight_connection(&host)?;
+/// ```
+pub fn preflight_connection(host: &Host) -> Result<()> {
+    check_connection(host).map_err(|why| {
+        let reason = connection_error_reason(&format!("{:#}", why))
+            .map(|x| format!(" ({})", x))
+            .unwrap_or_default();
+        why.context(Error::Transport(format!("Failed to connect to {}{}",
+                                             host, reason)))
+    })
+}
+
+/// Describes the cause of a failed SSH connection based on its error message
+fn connection_error_reason(message: &str) -> Option<&'static str> {
+    let message = message.to_lowercase();
+    let matches = |patterns: &[&str]| {
+        patterns.iter().any(|x| message.contains(x))
+    };
+
+    if matches(&["could not resolve hostname", "name or service not known",
+                 "failed to lookup address"]) {
+        Some("the hostname couldn't be resolved")
+    } else if matches(&["permission denied", "too many authentication",
+                        "failed to authenticate", "host key"]) {
+        Some("authentication failed")
+    } else if matches(&["timed out"]) {
+        Some("the connection timed out")
+    } else if matches(&["connection refused"]) {
+        Some("the connection was refused")
+    } else {
+        None
+    }
+}
+
+/// Computes the hash of a file on another machine via SSH
+///
+/// `host` may be an SSH alias or a string in the form `user@hostname`. The file
+/// is streamed over SSH and hashed locally, so nothing on the remote machine is
+/// modified and any hash algorithm can be used. Returns None if the file
+/// doesn't exist. Returns an Err for Windows hosts.
+///
+/// ```
+/// let hash = hash_remote_file("~/.bashrc", &host, HashAlgorithm::Sha256)?;
+/// ```
+pub fn hash_remote_file(path: &str, host: &Host, algorithm: HashAlgorithm) ->
+    Result<Option<String>> {
+
+    if host.options.remote_os == RemoteOs::Windows {
+        bail!("Hashing files on Windows hosts isn't supported");
+    }
+    let quoted = quote_path(path);
+    let command = format!("[ -f {quoted} ] || exit {MISSING_FILE_STATUS}; \
+                           cat {quoted}");
+
+    #[cfg(feature = "native-ssh")]
+    if let Some(options) = native_options(host) {
+        let (status, hash) = native::read_command(&command, &host.name,
+            options, |x| hash_reader(x, algorithm))?;
+        return match status {
+            0 => Ok(Some(hash?)),
+            MISSING_FILE_STATUS => Ok(None),
+            _ => bail!("SSH terminated unsuccessfully: exit status: {}",
+                       status),
+        };
+    }
+
+    let mut cmd = remote_command(host, &[], &command);
+    cmd.stdout(Stdio::piped());
+
+    let mut child = cmd.spawn().with_context(|| {
+        format!("Failed to execute {:?}", cmd)
+    })?;
+    let hash = match child.stdout.as_mut() {
+        Some(stdout) => hash_reader(stdout, algorithm),
+        None => Err(anyhow!("Failed to read output of {:?}", cmd)),
+    };
+    let status = child.wait().with_context(|| {
+        format!("Failed to execute {:?}", cmd)
+    })?;
+
+    if status.code() == Some(MISSING_FILE_STATUS) {
+        return Ok(None);
+    }
+    if !status.success() {
+        bail!("SSH terminated unsuccessfully: {}", status);
+    }
+    Ok(Some(hash?))
+}
+
+/// Creates a Command that executes a command on another machine via SSH,
+/// passing additional `options` to SSH
+///
+/// ```
+/// let mut cmd = remote_command(&host, &[], "echo 'Hello World'");
+/// ```
+fn remote_command(host: &Host, options: &[&str], command: &str) -> Command {
+    let mut cmd = ssh_command(host);
+    cmd.args(options);
+    cmd.args([split_port(&host.name).0, command]);
+    cmd
+}
+
+/// Creates an SSH Command with the options shared by all SSH connections to a
+/// host, which doesn't include the host's destination
+fn ssh_command(host: &Host) -> Command {
+    let mut cmd = Command::new("ssh");
+    add_connection_options(&mut cmd, &host.options);
+    if let Some(port) = split_port(&host.name).1 {
+        cmd.args(["-p", port]);
+    }
+    cmd
+}
+
+/// Adds a set of options to an SSH or SCP Command
+fn add_connection_options(cmd: &mut Command, options: &SshOptions) {
+    match options.host_key_policy {
+        Some(HostKeyPolicy::Strict) => {
+            cmd.args(["-o", "StrictHostKeyChecking=yes"]);
+        },
+        Some(HostKeyPolicy::AcceptNew) => {
+            cmd.args(["-o", "StrictHostKeyChecking=accept-new"]);
+        },
+        Some(HostKeyPolicy::Off) => {
+            cmd.args(["-o", "StrictHostKeyChecking=no"]);
+        },
+        None => {},
+    }
+    if let Some(path) = &options.config_file {
+        cmd.args(["-F", &tilde(path)]);
+    }
+    if let Some(path) = &options.identity_file {
+        cmd.args(["-i", &tilde(path)]);
+    }
+    if let Some(host) = &options.jump_host {
+        cmd.args(["-J", host]);
     }
+    if options.compress {
+        cmd.arg("-C");
+    }
+    if let Some(secs) = options.connect_timeout {
+        cmd.args(["-o", &format!("ConnectTimeout={}", secs)]);
+    }
+    if options.forward_agent {
+        cmd.args(["-o", "ForwardAgent=yes"]);
+    }
+    if options.batch_mode {
+        cmd.args(["-o", "BatchMode=yes"]);
+    }
+    if options.password.is_some() {
+        // The password never changes, so prompting again can't succeed
+        cmd.args(["-o", "NumberOfPasswordPrompts=1"]);
+        add_askpass(cmd, options);
+    }
+}
 
-    copy_file(src, _dst.to_string_lossy().to_mut())
+/// Makes SSH read the password in a set of options from coliru through
+/// `SSH_ASKPASS` instead of prompting for it on the terminal
+///
+/// Only the location of the password file is added to the environment, which is
+/// inherited by programs that run SSH themselves (e.g. rsync).
+fn add_askpass(cmd: &mut Command, options: &SshOptions) {
+    let Some(password) = &options.password else { return; };
+    let Ok(exe) = env::current_exe() else { return; };
+    cmd.env("SSH_ASKPASS", exe);
+    cmd.env("SSH_ASKPASS_REQUIRE", "force");
+    cmd.env(ASKPASS_VAR, password.path().as_os_str());
 }
 
-/// Transfers the files in an SCP staging directory to a remote machine
+/// Checks whether a set of SSH options can be used together
 ///
-/// `host` may be an SSH alias or a string in the form `user@hostname`. Use
-/// [`stage_file`] to produce a staging directory. The contents of the staging
-/// directory are deleted after they are successfully transferred.
+/// Returns an Err if the native transport is selected but coliru was built
+/// without the `native-ssh` feature, or with a jump host, which it doesn't
+/// support. Also returns an Err if the rsync or tar transport is selected for
+/// Windows hosts, which don't have those programs.
 ///
 /// ```
-/// send_staged_files(Path::new("/tmp/staging"), "user@hostname");
+/// check_ssh_options(&SshOptions {
+///     identity_file: Some(String::from("~/.ssh/deploy_key")),
+///     ..SshOptions::default()
+/// })?;
 /// ```
-pub fn send_staged_files(staging_dir: &Path, host: &str) -> Result<()> {
-    let home_dir = staging_dir.join("home");
-    if home_dir.exists() {
-        send_dir(home_dir.to_string_lossy().to_mut(), "~", host)?;
-        remove_dir_all(&home_dir).with_context(|| {
-            format!("Failed to remove staging dir {} after use",
-                    &home_dir.display())
-        })?;
+pub fn check_ssh_options(options: &SshOptions) -> Result<()> {
+    if options.transport == Transport::Native &&
+        cfg!(not(feature = "native-ssh")) {
+
+        bail!("The native transport requires coliru to be built with the \
+               native-ssh feature");
     }
-    let root_dir = staging_dir.join("root");
-    if root_dir.exists() {
-        send_dir(root_dir.to_string_lossy().to_mut(), "/", host)?;
-        remove_dir_all(&root_dir).with_context(|| {
-            format!("Failed to remove staging dir {} after use",
-                    &root_dir.display())
-        })?;
+    if options.transport == Transport::Native && options.jump_host.is_some() {
+        bail!("The native transport doesn't support jump hosts");
+    }
+    if options.remote_os == RemoteOs::Windows &&
+        matches!(options.transport, Transport::Rsync | Transport::Tar) {
+
+        bail!("The {} transport doesn't support Windows hosts",
+              format!("{:?}", options.transport).to_lowercase());
     }
     Ok(())
 }
 
-/// Copies a directory to another machine via SCP and merges it with a
-/// destination directory
+/// Checks whether the escalation command can get a password on remote machines
+/// with a strategy
 ///
-/// `host` may be an SSH alias or a string in the form `user@hostname`.
+/// Returns an Err if the password would be read from an environment variable
+/// that isn't set.
 ///
 /// ```
-/// send_dir("new_home", "~/", "user@hostname");
+/// check_sudo_password(SudoPassword::Tty)?;
 /// ```
-fn send_dir(src: &str, dst: &str, host: &str) -> Result<()> {
-    // To avoid the source directory being copied as a subdirectory of the
-    // destination directory, we must send the contents of the directory
-    // item by item.
-    let items = read_dir(&src).with_context(|| {
-        format!("Failed to list contents of {}", src)
-    })?;
-    for item in items {
-        let _src = item.with_context(|| {
-            format!("Failed to list contents of {}", src)
-        })?.path();
+pub fn check_sudo_password(strategy: SudoPassword) -> Result<()> {
+    if strategy == SudoPassword::Env &&
+        env::var_os(SUDO_PASSWORD_VAR).is_none() {
+        bail!("{} must be set to read the sudo password from the environment",
+              SUDO_PASSWORD_VAR);
+    }
+    Ok(())
+}
 
-        let mut cmd = Command::new("scp");
-        cmd.stdout(Stdio::null());
+/// Reads the password used to authenticate with a host, which can then be
+/// given to every SSH connection through [`SshOptions::password`]
+///
+/// The password is read from `COLIRU_SSH_PASSWORD` if it's set and prompted for
+/// once on the terminal otherwise, so that the many SSH and SCP processes of an
+/// installation don't each prompt for it. `COLIRU_SSH_PASSWORD` is then removed
+/// from the environment so that other programs don't inherit it. Returns an Err
+/// if the password must be prompted for but stdin isn't a terminal.
+///
+/// ```
+/// let password = read_ssh_password("user@hostname")?;
+/// ```
+pub fn read_ssh_password(host: &str) -> Result<SshPassword> {
+    let password = match env::var(SSH_PASSWORD_VAR) {
+        Ok(password) => password,
+        Err(_) => {
+            prompt_password(&format!("{}'s password: ", split_port(host).0))?
+        },
+    };
+    env::remove_var(SSH_PASSWORD_VAR);
+    let path = write_password_file(&password)?;
+    Ok(SshPassword(Arc::new((password, path))))
+}
 
-        if env::var("COLIRU_TEST").is_ok() {
-            cmd.args(["-o", "StrictHostKeyChecking=no", "-P", "2222"]);
-        }
-        cmd.args(["-r", &_src.to_string_lossy(), &format!("{host}:{dst}")]);
+/// Writes a password to a new temporary file that only the current user can
+/// read, which is removed when the returned path is dropped
+fn write_password_file(password: &str) -> Result<TempPath> {
+    let mut file = NamedTempFile::new()
+        .context("Failed to create password file")?;
+    file.write_all(password.as_bytes())
+        .context("Failed to write password file")?;
+    Ok(file.into_temp_path())
+}
 
-        let status = cmd.status().with_context(|| {
-            format!("Failed to execute {:?}", cmd)
-        })?;
-        if !status.success() {
-            bail!("SCP terminated unsuccessfully: {}", status);
-        }
+/// Prompts for a password on the terminal without echoing it
+fn prompt_password(prompt: &str) -> Result<String> {
+    if !io::stdin().is_terminal() {
+        bail!("{} must be set to authenticate with a password when stdin \
+               isn't a terminal", SSH_PASSWORD_VAR);
     }
-    Ok(())
+    eprint!("{}", prompt);
+    let _ = io::stderr().flush();
+
+    // Failing to disable echoing isn't worth stopping over
+    #[cfg(target_family = "unix")]
+    let _ = Command::new("stty").arg("-echo").status();
+    let mut password = String::new();
+    let result = io::stdin().read_line(&mut password);
+    #[cfg(target_family = "unix")]
+    let _ = Command::new("stty").arg("echo").status();
+    eprintln!();
+
+    result.context("Failed to read password")?;
+    Ok(password.trim_end_matches(['\r', '\n']).to_owned())
 }
 
-/// Executes a command on another machine via SSH
+/// Returns the password that coliru should print if SSH executed it through
+/// `SSH_ASKPASS`, or None if coliru was executed normally
 ///
-/// `host` may be an SSH alias or a string in the form `user@hostname`.
+/// ```
+/// if let Some(password) = askpass_password() {
+///     println!("{}", password);
+/// }
+/// ```
+pub fn askpass_password() -> Option<String> {
+    let path = env::var_os(ASKPASS_VAR)?;
+    Some(read_to_string(path).unwrap_or_default())
+}
+
+/// Returns the program used to transfer files with the transport in a set of
+/// options, or None if the built-in SSH client is used
 ///
 /// ```
-/// send_command("echo 'Hello World'");
+/// assert_eq!(transfer_program(&SshOptions::default()), Some("scp"));
 /// ```
-pub fn send_command(command: &str, host: &str) -> Result<()> {
-    let mut cmd = Command::new("ssh");
-    if env::var("COLIRU_TEST").is_ok() {
-        cmd.args(["-o", "StrictHostKeyChecking=no", "-p", "2222"]);
+pub fn transfer_program(options: &SshOptions) -> Option<&'static str> {
+    match options.transport {
+        Transport::Scp => Some("scp"),
+        Transport::Sftp => Some("sftp"),
+        Transport::Rsync => Some("rsync"),
+        Transport::Tar => Some("tar"),
+        Transport::Native => None,
     }
-    cmd.args([host, command]);
+}
 
-    let status = cmd.status().with_context(|| {
-        format!("Failed to execute {:?}", cmd)
-    })?;
-    if !status.success() {
-        bail!("SSH terminated unsuccessfully: {}", status);
+/// Returns the options of a host if it's connected to with the native transport
+#[cfg(feature = "native-ssh")]
+fn native_options(host: &Host) -> Option<&SshOptions> {
+    Some(&host.options).filter(|x| x.transport == Transport::Native)
+}
+
+/// Splits a host in the form `[user@]hostname[:port]` into the destination
+/// that SSH connects to and the port, if any
+///
+/// ```
+/// assert_eq!(split_port("user@hostname:2222"),
+///            ("user@hostname", Some("2222")));
+/// assert_eq!(split_port("user@[::1]"), ("user@[::1]", None));
+/// ```
+pub fn split_port(host: &str) -> (&str, Option<&str>) {
+    match host.rsplit_once(':') {
+        Some((destination, port)) if !port.is_empty() &&
+            port.bytes().all(|x| x.is_ascii_digit()) &&
+            (!destination.contains(':') || destination.ends_with(']')) => {
+
+            (destination, Some(port))
+        },
+        _ => (host, None),
     }
-    Ok(())
+}
+
+/// Sets the port of a host, replacing any port that it already has
+///
+/// ```
+/// assert_eq!(with_port("user@hostname:22", 2222), "user@hostname:2222");
+/// ```
+pub fn with_port(host: &str, port: u16) -> String {
+    format!("{}:{}", split_port(host).0, port)
+}
+
+/// Quotes a path for use in a remote shell command
+///
+/// A leading `~/` is left unquoted so that it is still expanded by the shell.
+///
+/// ```
+/// assert_eq!(quote_path("~/foo bar"), "~/'foo bar'");
+/// assert_eq!(quote_path("/it's"), "'/it'\\''s'");
+/// ```
+pub fn quote_path(path: &str) -> String {
+    let (prefix, rest) = match path.strip_prefix("~/") {
+        Some(rest) => ("~/", rest),
+        None => ("", path),
+    };
+    format!("{prefix}'{}'", rest.replace('\'', "'\\''"))
 }
 
 #[cfg(test)]
@@ -191,16 +1591,47 @@ mod tests {
     use regex::Regex;
     use std::fs;
 
+    /// Returns a host with the options used to connect to the SSH test server,
+    /// whose host key isn't known
+    fn test_host(name: &str) -> Host {
+        Host::new(name, SshOptions {
+            host_key_policy: Some(HostKeyPolicy::Off),
+            ..SshOptions::default()
+        })
+    }
+
+    #[test]
+    fn test_connection_error_reason() {
+        let dns = "ssh: Could not resolve hostname foo: Name or service not \
+                   known";
+        let auth = "test@localhost: Permission denied (publickey,password).";
+        let timeout = "ssh: connect to host 10.0.0.1 port 22: Connection timed \
+                       out";
+        let refused = "ssh: connect to host localhost port 22: Connection \
+                       refused";
+
+        assert_eq!(connection_error_reason(dns),
+                   Some("the hostname couldn't be resolved"));
+        assert_eq!(connection_error_reason(auth),
+                   Some("authentication failed"));
+        assert_eq!(connection_error_reason(timeout),
+                   Some("the connection timed out"));
+        assert_eq!(connection_error_reason(refused),
+                   Some("the connection was refused"));
+        assert_eq!(connection_error_reason("kex_exchange_identification"),
+                   None);
+    }
+
     #[test]
     fn test_resolve_path_relative() {
-        let result = resolve_path("dir1/foo", "~/dir2");
+        let result = resolve_path("dir1/foo", "~/dir2", RemoteOs::Unix);
 
         assert_eq!(result, "~/dir2/dir1/foo");
     }
 
     #[test]
     fn test_resolve_path_tilde() {
-        let result = resolve_path("~/dir1/foo", "~/dir2");
+        let result = resolve_path("~/dir1/foo", "~/dir2", RemoteOs::Unix);
 
         assert_eq!(result, "~/dir1/foo");
     }
@@ -208,7 +1639,7 @@ mod tests {
     #[test]
     #[cfg(target_family = "unix")]
     fn test_resolve_path_absolute() {
-        let result = resolve_path("/dir1/foo", "~/dir2");
+        let result = resolve_path("/dir1/foo", "~/dir2", RemoteOs::Unix);
 
         assert_eq!(result, "/dir1/foo");
     }
@@ -216,11 +1647,110 @@ mod tests {
     #[test]
     #[cfg(target_family = "windows")]
     fn test_resolve_path_absolute() {
-        let result = resolve_path("C:\\dir1\\foo", "~/dir2");
+        let result = resolve_path("C:\\dir1\\foo", "~/dir2", RemoteOs::Unix);
 
         assert_eq!(result, "C:\\dir1\\foo");
     }
 
+    #[test]
+    fn test_staged_files() {
+        let tmp = setup_integration("test_staged_files");
+
+        fs::create_dir_all(tmp.local.join("home").join("dir")).unwrap();
+        fs::create_dir_all(tmp.local.join("root").join("etc")).unwrap();
+        write_file(&tmp.local.join("home").join("dir").join("foo"), "foo");
+        write_file(&tmp.local.join("home").join("bar"), "bar");
+        write_file(&tmp.local.join("root").join("etc").join("baz"), "baz");
+
+        let mut files = vec![];
+        staged_files(&tmp.local.join("home"), "~", &mut files).unwrap();
+        staged_files(&tmp.local.join("root"), "", &mut files).unwrap();
+
+        let remote: Vec<&str> = files.iter().map(|x| x.1.as_str()).collect();
+        assert_eq!(remote, ["~/bar", "~/dir/foo", "/etc/baz"]);
+        assert_eq!(files[1].0, tmp.local.join("home").join("dir").join("foo"));
+    }
+
+    #[test]
+    fn test_remove_empty_dirs() {
+        let tmp = setup_integration("test_remove_empty_dirs");
+
+        let staging = tmp.local.join("staging");
+        fs::create_dir_all(staging.join("home").join("empty").join("nested"))
+            .unwrap();
+        fs::create_dir_all(staging.join("home").join("dir")).unwrap();
+        write_file(&staging.join("home").join("dir").join("foo"), "foo");
+        fs::create_dir_all(staging.join("root").join("etc")).unwrap();
+
+        assert_eq!(remove_empty_dirs(&staging.join("home")).unwrap(), false);
+        assert_eq!(remove_empty_dirs(&staging.join("root")).unwrap(), true);
+        assert_eq!(staging.join("home").join("empty").exists(), false);
+        assert_eq!(staging.join("home").join("dir").join("foo").exists(),
+                   true);
+        assert_eq!(staging.join("root").exists(), false);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_with_remote_timeout() {
+        let command = with_remote_timeout("echo 'foo  bar' \"$0\"",
+                                          Duration::from_secs(5));
+
+        let output = Command::new("sh").args(["-c", &command]).output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), "foo  bar sh\n");
+        assert_eq!(output.status.success(), true);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_write_password_file() {
+        let path = write_password_file("hunter2").unwrap();
+
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(read_file(&path), "hunter2");
+        assert_eq!(mode & 0o777, 0o600);
+
+        let path_buf = path.to_path_buf();
+        drop(path);
+        assert_eq!(path_buf.exists(), false);
+    }
+
+    #[test]
+    fn test_translate_windows_path() {
+        assert_eq!(translate_windows_path("%USERPROFILE%\\.gitconfig"),
+                   "~/.gitconfig");
+        assert_eq!(translate_windows_path("C:\\Tools\\foo.ini"),
+                   "/C:/Tools/foo.ini");
+        assert_eq!(translate_windows_path("dir\\foo"), "dir/foo");
+        assert_eq!(translate_windows_path("~/foo"), "~/foo");
+    }
+
+    #[test]
+    fn test_split_port() {
+        assert_eq!(split_port("user@hostname:2222"),
+                   ("user@hostname", Some("2222")));
+        assert_eq!(split_port("hostname"), ("hostname", None));
+        assert_eq!(split_port("hostname:"), ("hostname:", None));
+        assert_eq!(split_port("[::1]:22"), ("[::1]", Some("22")));
+        assert_eq!(split_port("::1"), ("::1", None));
+        assert_eq!(with_port("user@hostname:22", 2222), "user@hostname:2222");
+    }
+
+    #[test]
+    fn test_quote_path_tilde() {
+        let result = quote_path("~/dir 1/foo");
+
+        assert_eq!(result, "~/'dir 1/foo'");
+    }
+
+    #[test]
+    fn test_quote_path_single_quote() {
+        let result = quote_path("/dir1/it's");
+
+        assert_eq!(result, "'/dir1/it'\\''s'");
+    }
+
     #[test]
     fn test_stage_file_tilde() {
         let tmp = setup_integration("test_stage_file_tilde");
@@ -231,7 +1761,7 @@ mod tests {
         let staging  = &tmp.local;
         write_file(&src, "contents of foo");
 
-        let result = stage_file(src.to_str().unwrap(), dst, staging);
+        let result = stage_file(src.to_str().unwrap(), dst, staging, false);
 
         assert_eq!(result.is_ok(), true);
         assert_eq!(dst_real.exists(), true);
@@ -249,7 +1779,7 @@ mod tests {
         let staging  = &tmp.local;
         write_file(&src, "contents of foo");
 
-        let result = stage_file(src.to_str().unwrap(), dst, staging);
+        let result = stage_file(src.to_str().unwrap(), dst, staging, false);
 
         assert_eq!(result.is_ok(), true);
         assert_eq!(dst_real.exists(), true);
@@ -266,19 +1796,88 @@ mod tests {
         let staging  = &tmp.local;
         write_file(&src, "contents of foo");
 
-        let result = stage_file(src.to_str().unwrap(), dst, staging);
+        let result = stage_file(src.to_str().unwrap(), dst, staging, false);
 
         assert_eq!(result.is_ok(), true);
         assert_eq!(dst_real.exists(), true);
         assert_eq!(read_file(&dst_real), "contents of foo");
     }
 
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_staged_attributes_command_basic() {
+        let tmp = setup_integration("test_staged_attributes_command_basic");
+
+        let script = tmp.local.join("home").join("bin").join("it's.sh");
+        let config = tmp.local.join("root").join("etc").join("foo.conf");
+        fs::create_dir_all(script.parent().unwrap()).unwrap();
+        fs::create_dir_all(config.parent().unwrap()).unwrap();
+        write_file(&script, "echo foo");
+        write_file(&config, "foo = bar");
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755))
+            .unwrap();
+        fs::set_permissions(&config, fs::Permissions::from_mode(0o600))
+            .unwrap();
+        let mtime = UNIX_EPOCH + std::time::Duration::from_secs(1728561845);
+        for path in [&script, &config] {
+            fs::File::options().write(true).open(path).unwrap()
+                .set_modified(mtime).unwrap();
+        }
+
+        let result = staged_attributes_command(&tmp.local, true);
+
+        assert_eq!(result.unwrap(), Some(String::from("\
+chmod 600 '/etc/foo.conf' && \
+env TZ=UTC0 touch -m -t 202410101204.05 '/etc/foo.conf' && \
+chmod 755 ~/'bin/it'\\''s.sh' && \
+env TZ=UTC0 touch -m -t 202410101204.05 ~/'bin/it'\\''s.sh'")));
+        let result = staged_attributes_command(&tmp.local, false);
+        assert_eq!(result.unwrap(), Some(String::from("\
+chmod 755 ~/'bin/it'\\''s.sh' && \
+env TZ=UTC0 touch -m -t 202410101204.05 ~/'bin/it'\\''s.sh'")));
+        let result = staged_attributes_command(&tmp.local.join("missing"),
+                                               true);
+        assert_eq!(result.unwrap(), None);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_escalated_install_script() {
+        let tmp = setup_integration("test_escalated_install_script");
+
+        let config = tmp.local.join("etc").join("foo.conf");
+        let motd = tmp.local.join("motd");
+        fs::create_dir_all(config.parent().unwrap()).unwrap();
+        write_file(&config, "foo = bar");
+        write_file(&motd, "Hello World");
+        fs::set_permissions(&config, fs::Permissions::from_mode(0o600))
+            .unwrap();
+        fs::set_permissions(&motd, fs::Permissions::from_mode(0o644))
+            .unwrap();
+        let mtime = UNIX_EPOCH + std::time::Duration::from_secs(1728561845);
+        for path in [&config, &motd] {
+            fs::File::options().write(true).open(path).unwrap()
+                .set_modified(mtime).unwrap();
+        }
+
+        let result = escalated_install_script(&tmp.local);
+
+        assert_eq!(result.unwrap(), "\
+mkdir -p '/etc' && \
+install -m 600 \"$1\"'/etc/foo.conf' '/etc/foo.conf' && \
+env TZ=UTC0 touch -m -t 202410101204.05 '/etc/foo.conf' && \
+mkdir -p '/' && \
+install -m 644 \"$1\"'/motd' '/motd' && \
+env TZ=UTC0 touch -m -t 202410101204.05 '/motd'");
+    }
+
     #[test]
     #[cfg(target_family = "unix")]
     fn test_send_staged_files_no_files() {
         let tmp = setup_integration("test_send_staged_files_no_files");
+        let host = test_host(SSH_HOST);
 
-        let result = send_staged_files(&tmp.local, SSH_HOST);
+        let result = send_staged_files(&tmp.local, &host, None);
 
         assert_eq!(result.is_ok(), true);
     }
@@ -287,15 +1886,16 @@ mod tests {
     #[cfg(target_family = "unix")]
     fn test_send_staged_files_home() {
         let tmp = setup_integration("test_send_staged_files_home");
+        let host = test_host(SSH_HOST);
 
         let src = tmp.local.join("home").join("test_send_staged_files_home");
         let src_foo = src.join("foo");
         let src_bar = src.join("dir").join("bar");
-        fs::create_dir_all(&src_bar.parent().unwrap()).unwrap();
+        fs::create_dir_all(src_bar.parent().unwrap()).unwrap();
         write_file(&src_foo, "contents of foo");
         write_file(&src_bar, "contents of bar");
 
-        let result = send_staged_files(&tmp.local, SSH_HOST);
+        let result = send_staged_files(&tmp.local, &host, None);
 
         let dst_foo = tmp.ssh.join("foo");
         let dst_bar = tmp.ssh.join("dir").join("bar");
@@ -308,20 +1908,42 @@ mod tests {
         assert_eq!(tmp.local.join("root").exists(), false);
     }
 
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_send_staged_files_unreadable() {
+        let tmp = setup_integration("test_send_staged_files_unreadable");
+        let host = test_host(SSH_HOST);
+
+        let src = tmp.local.join("home")
+            .join("test_send_staged_files_unreadable").join("foo");
+        fs::create_dir_all(src.parent().unwrap()).unwrap();
+        write_file(&src, "contents of foo");
+        let dst = tmp.ssh.join("foo");
+        write_file(&dst, "old contents");
+        fs::set_permissions(&dst, fs::Permissions::from_mode(0o200)).unwrap();
+
+        let result = send_staged_files(&tmp.local, &host, None);
+
+        fs::set_permissions(&dst, fs::Permissions::from_mode(0o644)).unwrap();
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(read_file(&dst), "contents of foo");
+    }
+
     #[test]
     #[cfg(target_family = "unix")]
     fn test_send_staged_files_root() {
         let tmp = setup_integration("test_send_staged_files_root");
+        let host = test_host(SSH_HOST);
 
         let src = tmp.local.join("root").join("home").join("test")
             .join("test_send_staged_files_root");
         let src_foo = src.join("foo");
         let src_bar = src.join("dir").join("bar");
-        fs::create_dir_all(&src_bar.parent().unwrap()).unwrap();
+        fs::create_dir_all(src_bar.parent().unwrap()).unwrap();
         write_file(&src_foo, "contents of foo");
         write_file(&src_bar, "contents of bar");
 
-        let result = send_staged_files(&tmp.local, SSH_HOST);
+        let result = send_staged_files(&tmp.local, &host, None);
 
         let dst_foo = tmp.ssh.join("foo");
         let dst_bar = tmp.ssh.join("dir").join("bar");
@@ -338,6 +1960,7 @@ mod tests {
     #[cfg(target_family = "unix")]
     fn test_send_dir_basic() {
         let tmp = setup_integration("test_send_dir_basic");
+        let host = test_host(SSH_HOST);
 
         write_file(&tmp.local.join("foo"), "contents of foo");
         write_file(&tmp.local.join("bar"), "contents of bar");
@@ -346,7 +1969,8 @@ mod tests {
         let dst_foo = tmp.ssh.join("foo");
         let dst_bar = tmp.ssh.join("bar");
 
-        let result = send_dir(tmp.local.to_str().unwrap(), dst, SSH_HOST);
+        let result = send_dir(tmp.local.to_str().unwrap(), dst, &host,
+                              None);
 
         assert_eq!(result.is_ok(), true);
         assert_eq!(dst_foo.exists(), true);
@@ -359,18 +1983,20 @@ mod tests {
     #[cfg(target_family = "unix")]
     fn test_send_dir_nested_dir() {
         let tmp = setup_integration("test_send_dir_nested_dir");
+        let host = test_host(SSH_HOST);
 
         let src_foo = tmp.local.join("foo");
         let src_bar = tmp.local.join("dir").join("bar");
         write_file(&src_foo, "contents of foo");
-        fs::create_dir_all(&src_bar.parent().unwrap()).unwrap();
+        fs::create_dir_all(src_bar.parent().unwrap()).unwrap();
         write_file(&src_bar, "contents of bar");
 
         let dst = "~/test_send_dir_nested_dir";
         let dst_foo = tmp.ssh.join("foo");
         let dst_bar = tmp.ssh.join("dir").join("bar");
 
-        let result = send_dir(tmp.local.to_str().unwrap(), dst, SSH_HOST);
+        let result = send_dir(tmp.local.to_str().unwrap(), dst, &host,
+                              None);
 
         assert_eq!(result.is_ok(), true);
         assert_eq!(dst_foo.exists(), true);
@@ -383,6 +2009,7 @@ mod tests {
     #[cfg(target_family = "unix")]
     fn test_send_dir_merge_dir() {
         let tmp = setup_integration("test_send_dir_merge_dir");
+        let host = test_host(SSH_HOST);
 
         let src_bar = tmp.local.join("dir").join("bar");
         fs::create_dir_all(src_bar.parent().unwrap()).unwrap();
@@ -393,11 +2020,12 @@ mod tests {
         let dst_bar = tmp.ssh.join("dir").join("bar");
         let dst_baz = tmp.ssh.join("dir").join("baz");
         write_file(&dst_foo, "old contents of foo");
-        fs::create_dir_all(&dst_bar.parent().unwrap()).unwrap();
+        fs::create_dir_all(dst_bar.parent().unwrap()).unwrap();
         write_file(&dst_bar, "old contents of bar");
         write_file(&dst_baz, "old contents of baz");
 
-        let result = send_dir(tmp.local.to_str().unwrap(), dst, SSH_HOST);
+        let result = send_dir(tmp.local.to_str().unwrap(), dst, &host,
+                              None);
 
         assert_eq!(result.is_ok(), true);
         assert_eq!(dst_foo.exists(), true);
@@ -408,6 +2036,127 @@ mod tests {
         assert_eq!(read_file(&dst_baz), "old contents of baz");
     }
 
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_send_dir_sftp_merge_dir() {
+        let tmp = setup_integration("test_send_dir_sftp_merge_dir");
+        let host = test_host(SSH_HOST);
+
+        let src_bar = tmp.local.join("dir").join("bar");
+        fs::create_dir_all(src_bar.parent().unwrap()).unwrap();
+        write_file(&src_bar, "new contents of bar");
+        write_file(&tmp.local.join("foo"), "new contents of foo");
+
+        let dst = "~/test_send_dir_sftp_merge_dir";
+        let dst_foo = tmp.ssh.join("foo");
+        let dst_bar = tmp.ssh.join("dir").join("bar");
+        let dst_baz = tmp.ssh.join("dir").join("baz");
+        fs::create_dir_all(dst_bar.parent().unwrap()).unwrap();
+        write_file(&dst_bar, "old contents of bar");
+        write_file(&dst_baz, "old contents of baz");
+
+        let result = send_dir_sftp(tmp.local.to_str().unwrap(), dst, &host,
+                                   None);
+
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(read_file(&dst_foo), "new contents of foo");
+        assert_eq!(read_file(&dst_bar), "new contents of bar");
+        assert_eq!(read_file(&dst_baz), "old contents of baz");
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_send_dir_rsync_merge_dir() {
+        let tmp = setup_integration("test_send_dir_rsync_merge_dir");
+        let host = test_host(SSH_HOST);
+
+        let src_bar = tmp.local.join("dir").join("bar");
+        fs::create_dir_all(src_bar.parent().unwrap()).unwrap();
+        write_file(&src_bar, "new contents of bar");
+        write_file(&tmp.local.join("foo"), "new contents of foo");
+
+        let dst = "~/test_send_dir_rsync_merge_dir";
+        let dst_foo = tmp.ssh.join("foo");
+        let dst_bar = tmp.ssh.join("dir").join("bar");
+        let dst_baz = tmp.ssh.join("dir").join("baz");
+        fs::create_dir_all(dst_bar.parent().unwrap()).unwrap();
+        write_file(&dst_bar, "old contents of bar");
+        write_file(&dst_baz, "old contents of baz");
+
+        let result = send_dir_rsync(tmp.local.to_str().unwrap(), dst,
+                                    &host, None);
+
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(read_file(&dst_foo), "new contents of foo");
+        assert_eq!(read_file(&dst_bar), "new contents of bar");
+        assert_eq!(read_file(&dst_baz), "old contents of baz");
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_send_dir_tar_merge_dir() {
+        let tmp = setup_integration("test_send_dir_tar_merge_dir");
+        let host = test_host(SSH_HOST);
+
+        let src_bar = tmp.local.join("dir").join("bar");
+        fs::create_dir_all(src_bar.parent().unwrap()).unwrap();
+        write_file(&src_bar, "new contents of bar");
+        write_file(&tmp.local.join("foo"), "new contents of foo");
+
+        let dst = "~/test_send_dir_tar_merge_dir";
+        let dst_foo = tmp.ssh.join("foo");
+        let dst_bar = tmp.ssh.join("dir").join("bar");
+        let dst_baz = tmp.ssh.join("dir").join("baz");
+        fs::create_dir_all(dst_bar.parent().unwrap()).unwrap();
+        write_file(&dst_bar, "old contents of bar");
+        write_file(&dst_baz, "old contents of baz");
+
+        let result = send_dir_tar(tmp.local.to_str().unwrap(), dst, &host,
+                                  None);
+
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(read_file(&dst_foo), "new contents of foo");
+        assert_eq!(read_file(&dst_bar), "new contents of bar");
+        assert_eq!(read_file(&dst_baz), "old contents of baz");
+    }
+
+    #[test]
+    fn test_sftp_batch() {
+        let tmp = setup_integration("test_sftp_batch");
+
+        let src_foo = tmp.local.join("foo");
+        let src_bar = tmp.local.join("dir").join("bar");
+        write_file(&src_foo, "contents of foo");
+        fs::create_dir_all(src_bar.parent().unwrap()).unwrap();
+        write_file(&src_bar, "contents of bar");
+
+        let mut batch = String::new();
+        let result = sftp_batch(&tmp.local, &home_relative_path("~/dst"),
+                                &mut batch);
+
+        assert_eq!(result.is_ok(), true);
+        assert_eq!(batch, format!("\
+-mkdir \"./dst/dir\"
+put {} \"./dst/dir/bar\"
+put {} \"./dst/foo\"
+", sftp_quote(&src_bar.to_string_lossy()),
+   sftp_quote(&src_foo.to_string_lossy())));
+    }
+
+    #[test]
+    fn test_ssh_command_line() {
+        let host = test_host("user@hostname:2222");
+
+        assert_eq!(ssh_command_line(&host),
+                   "ssh -o StrictHostKeyChecking=no -p 2222");
+    }
+
+    #[test]
+    fn test_sftp_quote() {
+        assert_eq!(sftp_quote("/foo bar"), "\"/foo bar\"");
+        assert_eq!(sftp_quote("C:\\\"foo\""), "\"C:\\\\\\\"foo\\\"\"");
+    }
+
     #[test]
     fn test_send_dir_bad_host() {
         let tmp = setup_integration("test_send_dir_bad_host");
@@ -416,9 +2165,11 @@ mod tests {
         write_file(&tmp.local.join("bar"), "contents of bar");
 
         let dst = "~/test_send_dir_bad_host";
-        let bad_host = "fake@coliru.test.internal"; // Will be a DNS error
+        // Will be a DNS error
+        let bad_host = test_host("fake@coliru.test.internal");
 
-        let result = send_dir(tmp.local.to_str().unwrap(), dst, bad_host);
+        let result = send_dir(tmp.local.to_str().unwrap(), dst, &bad_host,
+                              None);
         let expected = Regex::new("SCP terminated unsuccessfully: \
                                    exit (status|code): \\d+").unwrap();
 
@@ -430,30 +2181,84 @@ mod tests {
     #[cfg(target_family = "unix")]
     fn test_send_command_basic() {
         let tmp = setup_integration("test_send_command_basic");
+        let host = test_host(SSH_HOST);
 
         let dst = "~/test_send_command_basic/foo";
         let dst_real = tmp.ssh.join("foo");
         let cmd = format!("echo 'contents of foo' > {}", dst);
 
-        let result = send_command(&cmd, SSH_HOST);
+        let result = send_command(&cmd, &host, None);
 
         assert_eq!(result.is_ok(), true);
         assert_eq!(dst_real.exists(), true);
         assert_eq!(read_file(&dst_real), "contents of foo\n");
     }
 
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_hash_remote_file_basic() {
+        let tmp = setup_integration("test_hash_remote_file_basic");
+        let host = test_host(SSH_HOST);
+
+        write_file(&tmp.ssh.join("foo"), "abc");
+
+        let result = hash_remote_file("~/test_hash_remote_file_basic/foo",
+                                      &host, HashAlgorithm::Sha256);
+
+        assert_eq!(result.unwrap(), Some(String::from("\
+ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")));
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_hash_remote_file_missing() {
+        let _tmp = setup_integration("test_hash_remote_file_missing");
+        let host = test_host(SSH_HOST);
+
+        let result = hash_remote_file("~/test_hash_remote_file_missing/foo",
+                                      &host, HashAlgorithm::Sha256);
+
+        assert_eq!(result.unwrap(), None);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_check_connection_basic() {
+        let _tmp = setup_integration("test_check_connection_basic");
+        let host = test_host(SSH_HOST);
+
+        let result = check_connection(&host);
+
+        assert_eq!(result.is_ok(), true);
+    }
+
     #[test]
     fn test_send_command_bad_host() {
         let _tmp = setup_integration("test_send_command_bad_host");
 
-        let cmd = format!("echo Hello World");
-        let bad_host = "fake@coliru.test.internal"; // Will be a DNS error
+        let cmd = "echo Hello World".to_string();
+        // Will be a DNS error
+        let bad_host = test_host("fake@coliru.test.internal");
 
-        let result = send_command(&cmd, bad_host);
+        let result = send_command(&cmd, &bad_host, None);
         let expected = Regex::new("SSH terminated unsuccessfully: \
                                    exit (status|code): \\d+").unwrap();
 
         assert_eq!(result.is_ok(), false);
         assert_eq!(expected.is_match(&result.unwrap_err().to_string()), true);
     }
+
+    #[test]
+    fn test_check_connection_bad_host() {
+        let _tmp = setup_integration("test_check_connection_bad_host");
+
+        // Will be a DNS error
+        let bad_host = test_host("fake@coliru.test.internal");
+
+        let result = check_connection(&bad_host);
+
+        assert_eq!(result.is_ok(), false);
+        assert_eq!(result.unwrap_err().to_string()
+                   .contains("coliru.test.internal"), true);
+    }
 }
@@ -0,0 +1,208 @@
+//! Records of the destinations that coliru has installed
+//!
+//! Destinations on the local machine are recorded in a state file as they're
+//! installed, so that files coliru didn't create are never replaced by accident.
+//! Destinations on other machines are appended to `~/.coliru/installed` on the
+//! remote machine, so that they can be uninstalled later.
+//!
+//! ```
+//! let path = default_state_file()?;
+//! let mut state = load_state(&path)?;
+//! if !state.is_managed("~/.bashrc") {
+//!     state.record("~/.bashrc");
+//! }
+//! save_state(&path, &state)?;
+//! ```
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use super::backup::{expand_path, write_atomic};
+use super::core::SSH_INSTALL_DIR;
+use super::local::{CapturedOutput, normalize_path};
+use super::ssh::{Host, quote_path, send_command};
+
+/// The name of the state file on remote machines, relative to the remote
+/// installation directory
+pub const REMOTE_STATE_FILE: &str = "installed";
+
+/// The destinations that coliru has installed on the local machine
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct InstallState {
+    /// The absolute paths of the installed destinations
+    files: BTreeSet<PathBuf>,
+}
+impl InstallState {
+    /// Checks whether coliru has installed a destination
+    ///
+    /// ```
+    /// let managed = state.is_managed("~/.bashrc");
+    /// ```
+    pub fn is_managed(&self, dst: &str) -> bool {
+        state_key(dst).is_some_and(|x| self.files.contains(&x))
+    }
+
+    /// Records that coliru has installed a destination
+    ///
+    /// ```
+    /// state.record("~/.bashrc");
+    /// ```
+    pub fn record(&mut self, dst: &str) {
+        if let Some(key) = state_key(dst) {
+            self.files.insert(key);
+        }
+    }
+
+    /// Returns the absolute paths of the installed destinations
+    ///
+    /// ```
+    /// for path in state.files() {
+    ///     println!("{}", path.display());
+    /// }
+    /// ```
+    pub fn files(&self) -> impl Iterator<Item = &Path> {
+        self.files.iter().map(PathBuf::as_path)
+    }
+
+    /// Forgets that coliru has installed a destination
+    ///
+    /// ```
+    /// state.forget(Path::new("/home/user/.bashrc"));
+    /// ```
+    pub fn forget(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+}
+
+/// Reads a state file, which is empty if it doesn't exist
+///
+/// ```
+/// let state = load_state(&default_state_file()?)?;
+/// ```
+pub fn load_state(path: &Path) -> Result<InstallState> {
+    if !path.exists() { return Ok(InstallState::default()); }
+
+    let contents = fs::read_to_string(path).with_context(|| {
+        format!("Failed to read {}", path.display())
+    })?;
+    serde_json::from_str(&contents).with_context(|| {
+        format!("Failed to parse {}", path.display())
+    })
+}
+
+/// Overwrites a state file
+///
+/// ```
+/// save_state(&default_state_file()?, &state)?;
+/// ```
+pub fn save_state(path: &Path, state: &InstallState) -> Result<()> {
+    let contents = serde_json::to_string_pretty(state)?;
+    write_atomic(path, |file| Ok(file.write_all(contents.as_bytes())?))
+}
+
+/// Returns the location of the state file on remote machines
+fn remote_state_file() -> String {
+    format!("~/{}/{}", SSH_INSTALL_DIR, REMOTE_STATE_FILE)
+}
+
+/// Returns a shell command that records a set of destinations in the state file
+/// on a remote machine
+///
+/// Destinations may start with `~` to refer to the remote user's home
+/// directory, which is expanded before they're recorded.
+///
+/// ```
+/// let command = record_remote_command(&["~/.bashrc".to_owned()]);
+/// send_command(&command, &host, None)?;
+/// ```
+pub fn record_remote_command(dsts: &[String]) -> String {
+    let paths: Vec<String> = dsts.iter().map(|x| quote_path(x)).collect();
+    format!("mkdir -p ~/{} && printf '%s\\n' {} >> {}", SSH_INSTALL_DIR,
+            paths.join(" "), remote_state_file())
+}
+
+/// Reads the destinations recorded in the state file on a remote machine
+///
+/// The destinations are sorted and deduplicated, and are empty if the state
+/// file doesn't exist.
+///
+/// ```
+/// let files = load_remote_state(&host)?;
+/// ```
+pub fn load_remote_state(host: &Host) -> Result<Vec<String>> {
+    let mut captured = CapturedOutput::default();
+    let command = format!("cat {} 2>/dev/null || true", remote_state_file());
+    send_command(&command, host, Some(&mut captured)).with_context(|| {
+        format!("Failed to read {}:{}", host, remote_state_file())
+    })?;
+
+    let files: BTreeSet<&str> = captured.stdout.lines()
+        .map(|x| x.trim_end_matches('\r'))
+        .filter(|x| !x.is_empty())
+        .collect();
+    Ok(files.into_iter().map(String::from).collect())
+}
+
+/// Removes a set of files and the state file from a remote machine
+///
+/// ```
+/// remove_remote_files(&["/home/user/.bashrc".to_owned()], &host, None)?;
+/// ```
+pub fn remove_remote_files(files: &[String], host: &Host,
+                           capture: Option<&mut CapturedOutput>) -> Result<()> {
+    let mut command = String::from("rm -f");
+    for file in files {
+        command.push(' ');
+        command.push_str(&quote_path(file));
+    }
+    command.push(' ');
+    command.push_str(&remote_state_file());
+    send_command(&command, host, capture)
+}
+
+/// Returns the absolute path that a destination is recorded under
+fn state_key(dst: &str) -> Option<PathBuf> {
+    expand_path(Path::new(dst)).ok().map(|x| normalize_path(&x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::setup_integration;
+
+    #[test]
+    fn test_state_basic() {
+        let tmp = setup_integration("test_state_basic");
+        let path = tmp.local.join("state").join("installed.json");
+        let foo = tmp.local.join("foo");
+        let foo = foo.to_str().unwrap();
+        let bar = tmp.local.join("dir").join("..").join("bar");
+        let bar = bar.to_str().unwrap();
+
+        let mut state = load_state(&path).unwrap();
+        assert_eq!(state.is_managed(foo), false);
+        state.record(foo);
+        state.record(bar);
+        save_state(&path, &state).unwrap();
+
+        let state = load_state(&path).unwrap();
+        let bar = tmp.local.join("bar");
+        assert_eq!(state.is_managed(foo), true);
+        assert_eq!(state.is_managed(bar.to_str().unwrap()), true);
+        assert_eq!(state.is_managed(tmp.local.join("baz").to_str().unwrap()),
+                   false);
+    }
+
+    #[test]
+    fn test_record_remote_command() {
+        let dsts = [String::from("~/foo bar"), String::from("/it's")];
+
+        let command = record_remote_command(&dsts);
+
+        assert_eq!(command, "mkdir -p ~/.coliru && printf '%s\\n' ~/'foo bar' \
+                             '/it'\\''s' >> ~/.coliru/installed");
+    }
+}
@@ -0,0 +1,291 @@
+//! Expansion of GNU Stow packages into link commands
+//!
+//! A stow command mirrors the structure of a package directory into a target
+//! directory, following the conventions of GNU Stow so that existing packages
+//! can be installed without being rewritten as explicit link commands.
+//!
+//! ```
+//! let package = StowOptions { src: String::from("vim"),
+//!                             dst: String::from("~"), dotfiles: true };
+//! let links = stow_links(&package, Path::new("."))?;
+//! ```
+
+use super::manifest::{CopyLinkOptions, StowOptions};
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+use shellexpand::tilde;
+use std::fs::{self, read_to_string};
+use std::path::Path;
+
+/// The file in a package that overrides the patterns of ignored files
+const LOCAL_IGNORE_FILE: &str = ".stow-local-ignore";
+
+/// The file that overrides the patterns of ignored files for every package
+/// without a local ignore file
+const GLOBAL_IGNORE_FILE: &str = "~/.stow-global-ignore";
+
+/// The patterns of files that GNU Stow ignores when a package has no ignore
+/// files
+const DEFAULT_IGNORE: &[&str] = &[
+    r"RCS", r".+,v", r"CVS", r"\.\#.+", r"\.cvsignore", r"\.svn", r"_darcs",
+    r"\.hg", r"\.git", r"\.gitignore", r"\.gitmodules", r".+~", r"\#.*\#",
+    r"^/README.*", r"^/LICENSE.*", r"^/COPYING",
+];
+
+/// The patterns of the files in a package that aren't linked
+#[derive(Debug)]
+struct IgnoreList {
+    /// The patterns that are matched against paths relative to the package,
+    /// which start with `/`
+    path: Option<Regex>,
+
+    /// The patterns that are matched against file names
+    basename: Option<Regex>,
+}
+impl IgnoreList {
+    /// Parses the contents of a stow ignore file
+    ///
+    /// Each line is a regular expression, and comments start with a `#` at the
+    /// start of a line or after whitespace. Patterns that contain a `/` are
+    /// matched against the path of a file, and all other patterns must match
+    /// the entire name of a file.
+    ///
+    /// ```
+    /// let ignore = IgnoreList::parse("\\.git\n^/README.*  # docs")?;
+    /// ```
+    fn parse(contents: &str) -> Result<IgnoreList> {
+        let mut path_patterns = vec![];
+        let mut basename_patterns = vec![];
+        let comment = Regex::new(r"^#.*|\s+#.*")?;
+        for line in contents.lines() {
+            let line = comment.replace(line, "");
+            let pattern = line.trim().replace(r"\#", "#");
+            if pattern.is_empty() {
+                continue;
+            }
+            if pattern.contains('/') {
+                path_patterns.push(pattern);
+            } else {
+                basename_patterns.push(pattern);
+            }
+        }
+
+        let compile = |patterns: Vec<String>, template: &str| {
+            if patterns.is_empty() {
+                return Ok(None);
+            }
+            let pattern = template.replace("{}", &patterns.join("|"));
+            Regex::new(&pattern).map(Some).with_context(|| {
+                format!("Invalid ignore pattern in {}", patterns.join(", "))
+            })
+        };
+        Ok(IgnoreList {
+            path: compile(path_patterns, "(^|/)(?:{})(/|$)")?,
+            basename: compile(basename_patterns, "^(?:{})$")?,
+        })
+    }
+
+    /// Checks if a file is ignored, given its path relative to the package
+    /// (starting with `/`) and its name
+    fn is_ignored(&self, path: &str, name: &str) -> bool {
+        name == LOCAL_IGNORE_FILE ||
+            self.path.as_ref().is_some_and(|x| x.is_match(path)) ||
+            self.basename.as_ref().is_some_and(|x| x.is_match(name))
+    }
+}
+
+/// Returns the link commands that install the files of a stow package
+///
+/// Each file in the package is linked to the same path under the target
+/// directory with a relative symbolic link, like GNU Stow with `--no-folding`.
+/// Files are ignored according to the package's `.stow-local-ignore` file,
+/// `~/.stow-global-ignore`, or GNU Stow's default patterns, in that order. If
+/// `dotfiles` is set, a `dot-` prefix on any part of a path is replaced with a
+/// `.`.
+///
+/// ```
+/// let links = stow_links(&package, Path::new("."))?;
+/// ```
+pub fn stow_links(stow: &StowOptions, base_dir: &Path) ->
+    Result<Vec<CopyLinkOptions>> {
+
+    let package = base_dir.join(&stow.src);
+    if !package.is_dir() {
+        bail!("Stow package {} is not a directory", stow.src);
+    }
+    let ignore = ignore_list(&package)?;
+
+    let src_dir = stow.src.trim_end_matches('/');
+    let dst_dir = stow.dst.trim_end_matches('/');
+    Ok(package_files(&package, "", &ignore)?.into_iter().map(|path| {
+        let dst = if stow.dotfiles {
+            path.split('/').map(|x| match x.strip_prefix("dot-") {
+                Some(name) => format!(".{}", name),
+                None => x.to_owned(),
+            }).collect::<Vec<_>>().join("/")
+        } else {
+            path.clone()
+        };
+        CopyLinkOptions {
+            src: format!("{}{}", src_dir, path),
+            dst: format!("{}{}", dst_dir, dst),
+            link_type: None,
+            preserve_mtime: false,
+            relative: true,
+            remote: false,
+            eol: None,
+            concat: vec![],
+            template: None,
+        }
+    }).collect())
+}
+
+/// Reads the ignore list that applies to a package
+fn ignore_list(package: &Path) -> Result<IgnoreList> {
+    let global = tilde(GLOBAL_IGNORE_FILE).to_string();
+    for path in [package.join(LOCAL_IGNORE_FILE), Path::new(&global).into()] {
+        if path.is_file() {
+            let contents = read_to_string(&path).with_context(|| {
+                format!("Failed to read {}", path.display())
+            })?;
+            return IgnoreList::parse(&contents).with_context(|| {
+                format!("Failed to parse {}", path.display())
+            });
+        }
+    }
+    IgnoreList::parse(&DEFAULT_IGNORE.join("\n"))
+}
+
+/// Recursively lists the files in a package directory that aren't ignored, as
+/// sorted `/` separated paths that start with `prefix` and a `/`
+fn package_files(dir: &Path, prefix: &str, ignore: &IgnoreList) ->
+    Result<Vec<String>> {
+
+    let mut names = vec![];
+    for entry in fs::read_dir(dir).with_context(|| {
+        format!("Failed to read {}", dir.display())
+    })? {
+        let entry = entry.with_context(|| {
+            format!("Failed to read {}", dir.display())
+        })?;
+        names.push(entry.file_name().to_string_lossy().to_string());
+    }
+    names.sort();
+
+    let mut files = vec![];
+    for name in names {
+        let path = format!("{}/{}", prefix, name);
+        if ignore.is_ignored(&path, &name) {
+            continue;
+        }
+        if dir.join(&name).is_dir() {
+            files.extend(package_files(&dir.join(&name), &path, ignore)?);
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{setup_integration, write_file};
+
+    fn stow(src: &str, dotfiles: bool) -> StowOptions {
+        StowOptions { src: src.to_owned(), dst: String::from("~"), dotfiles }
+    }
+
+    #[test]
+    fn test_ignore_list_default() {
+        let ignore = IgnoreList::parse(&DEFAULT_IGNORE.join("\n")).unwrap();
+
+        assert_eq!(ignore.is_ignored("/.git", ".git"), true);
+        assert_eq!(ignore.is_ignored("/vim/.gitignore", ".gitignore"), true);
+        assert_eq!(ignore.is_ignored("/vimrc~", "vimrc~"), true);
+        assert_eq!(ignore.is_ignored("/README.md", "README.md"), true);
+        assert_eq!(ignore.is_ignored("/vim/README.md", "README.md"), false);
+        assert_eq!(ignore.is_ignored("/.gitconfig", ".gitconfig"), false);
+        assert_eq!(ignore.is_ignored("/.stow-local-ignore",
+                                     ".stow-local-ignore"), true);
+    }
+
+    #[test]
+    fn test_ignore_list_comments() {
+        let ignore = IgnoreList::parse("# comment\n\\.cache  # cache\n\
+                                        notes\\#1\n^/extra/.*\n").unwrap();
+
+        assert_eq!(ignore.is_ignored("/.cache", ".cache"), true);
+        assert_eq!(ignore.is_ignored("/notes#1", "notes#1"), true);
+        assert_eq!(ignore.is_ignored("/extra/foo", "foo"), true);
+        assert_eq!(ignore.is_ignored("/comment", "comment"), false);
+        assert_eq!(ignore.is_ignored("/.git", ".git"), false);
+    }
+
+    #[test]
+    fn test_ignore_list_invalid() {
+        assert_eq!(IgnoreList::parse("foo(").is_err(), true);
+    }
+
+    #[test]
+    fn test_stow_links_basic() {
+        let tmp = setup_integration("test_stow_links_basic");
+
+        fs::create_dir_all(tmp.local.join("vim/dot-vim/colors")).unwrap();
+        fs::create_dir_all(tmp.local.join("vim/.git")).unwrap();
+        write_file(&tmp.local.join("vim/dot-vimrc"), "");
+        write_file(&tmp.local.join("vim/dot-vim/colors/theme.vim"), "");
+        write_file(&tmp.local.join("vim/README.md"), "");
+        write_file(&tmp.local.join("vim/.git/HEAD"), "");
+
+        let links = stow_links(&stow("vim/", true), &tmp.local).unwrap();
+
+        let paths: Vec<(&str, &str)> = links.iter().map(|x| {
+            (x.src.as_str(), x.dst.as_str())
+        }).collect();
+        assert_eq!(paths, vec![
+            ("vim/dot-vim/colors/theme.vim", "~/.vim/colors/theme.vim"),
+            ("vim/dot-vimrc", "~/.vimrc"),
+        ]);
+        assert_eq!(links.iter().all(|x| x.relative), true);
+    }
+
+    #[test]
+    fn test_stow_links_no_dotfiles() {
+        let tmp = setup_integration("test_stow_links_no_dotfiles");
+
+        fs::create_dir_all(tmp.local.join("zsh")).unwrap();
+        write_file(&tmp.local.join("zsh/dot-zshrc"), "");
+
+        let links = stow_links(&stow("zsh", false), &tmp.local).unwrap();
+
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].src, "zsh/dot-zshrc");
+        assert_eq!(links[0].dst, "~/dot-zshrc");
+    }
+
+    #[test]
+    fn test_stow_links_local_ignore() {
+        let tmp = setup_integration("test_stow_links_local_ignore");
+
+        fs::create_dir_all(tmp.local.join("git/.config/git")).unwrap();
+        write_file(&tmp.local.join("git/.stow-local-ignore"), "\\.config\n");
+        write_file(&tmp.local.join("git/.config/git/config"), "");
+        write_file(&tmp.local.join("git/.gitconfig"), "");
+        write_file(&tmp.local.join("git/README.md"), "");
+
+        let links = stow_links(&stow("git", false), &tmp.local).unwrap();
+
+        let dsts: Vec<&str> = links.iter().map(|x| x.dst.as_str()).collect();
+        assert_eq!(dsts, vec!["~/.gitconfig", "~/README.md"]);
+    }
+
+    #[test]
+    fn test_stow_links_missing() {
+        let tmp = setup_integration("test_stow_links_missing");
+
+        let why = stow_links(&stow("missing", false), &tmp.local).unwrap_err();
+
+        assert_eq!(why.to_string(), "Stow package missing is not a directory");
+    }
+}
@@ -0,0 +1,155 @@
+//! Shell commands for installing and enabling systemd units
+//!
+//! User units are managed with `systemctl --user` and installed in
+//! `~/.config/systemd/user`, and system units are managed with `sudo systemctl`
+//! and installed in `/etc/systemd/system`. Each function returns a POSIX shell
+//! command, so that units can be managed the same way on the local machine and
+//! over SSH.
+//!
+//! ```
+//! let enable = enable_command(&unit);
+//! ```
+
+use super::local::quote;
+use super::manifest::{SystemdOptions, SystemdScope};
+use super::ssh::quote_path;
+
+/// Returns a description of a systemd command for output
+///
+/// ```
+/// assert_eq!(describe(&unit), "Install user unit syncthing.service");
+/// ```
+pub fn describe(unit: &SystemdOptions) -> String {
+    let verb = if unit.src.is_some() { "Install" } else { "Enable" };
+    format!("{} {} unit {}", verb, unit.scope, unit.unit)
+}
+
+/// Returns the path that a systemd command's unit file is installed at
+///
+/// ```
+/// assert_eq!(unit_path(&unit), "~/.config/systemd/user/syncthing.service");
+/// ```
+pub fn unit_path(unit: &SystemdOptions) -> String {
+    match unit.scope {
+        SystemdScope::User => format!("~/.config/systemd/user/{}", unit.unit),
+        SystemdScope::System => format!("/etc/systemd/system/{}", unit.unit),
+    }
+}
+
+/// Returns the command that runs `systemctl` in a systemd command's scope
+fn systemctl(unit: &SystemdOptions) -> &'static str {
+    match unit.scope {
+        SystemdScope::User => "systemctl --user",
+        SystemdScope::System => "sudo systemctl",
+    }
+}
+
+/// Returns a shell command that succeeds if a systemd command's unit file
+/// exists
+pub fn exists_command(unit: &SystemdOptions) -> String {
+    format!("test -e {}", quote_path(&unit_path(unit)))
+}
+
+/// Returns a shell command that succeeds if a systemd command's unit file is
+/// already installed with the same contents as its source, which it must have
+///
+/// ```
+/// let installed = installed_command(&unit);
+/// ```
+pub fn installed_command(unit: &SystemdOptions) -> String {
+    let src = unit.src.as_deref().unwrap_or_default();
+    format!("cmp -s {} {}", quote_path(src), quote_path(&unit_path(unit)))
+}
+
+/// Returns a shell command that installs a systemd command's unit file, which
+/// it must have, and reloads the unit files of its scope
+///
+/// ```
+/// let install = install_command(&unit);
+/// ```
+pub fn install_command(unit: &SystemdOptions) -> String {
+    let src = quote_path(unit.src.as_deref().unwrap_or_default());
+    let path = unit_path(unit);
+    let (dir, _) = path.rsplit_once('/').unwrap_or_default();
+    let sudo = if unit.scope == SystemdScope::System { "sudo " } else { "" };
+    format!("{sudo}mkdir -p {} && {sudo}cp {} {} && {} daemon-reload",
+            quote_path(dir), src, quote_path(&path), systemctl(unit))
+}
+
+/// Returns a shell command that succeeds if a systemd command's unit is
+/// enabled
+pub fn enabled_command(unit: &SystemdOptions) -> String {
+    format!("{} is-enabled --quiet {}", systemctl(unit), quote(&unit.unit))
+}
+
+/// Returns a shell command that enables a systemd command's unit
+pub fn enable_command(unit: &SystemdOptions) -> String {
+    format!("{} enable {}", systemctl(unit), quote(&unit.unit))
+}
+
+/// Returns a shell command that succeeds if a systemd command's unit is
+/// running
+pub fn active_command(unit: &SystemdOptions) -> String {
+    format!("{} is-active --quiet {}", systemctl(unit), quote(&unit.unit))
+}
+
+/// Returns a shell command that starts a systemd command's unit, or restarts
+/// it if `restart` is true (e.g. because its unit file changed)
+pub fn start_command(unit: &SystemdOptions, restart: bool) -> String {
+    let verb = if restart { "restart" } else { "start" };
+    format!("{} {} {}", systemctl(unit), verb, quote(&unit.unit))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(src: Option<&str>, scope: SystemdScope) -> SystemdOptions {
+        SystemdOptions {
+            src: src.map(String::from),
+            unit: String::from("syncthing.service"),
+            scope,
+            enable: true,
+            start: true,
+        }
+    }
+
+    #[test]
+    fn test_systemd_describe() {
+        let user = unit(Some("syncthing.service"), SystemdScope::User);
+        let system = unit(None, SystemdScope::System);
+
+        assert_eq!(describe(&user), "Install user unit syncthing.service");
+        assert_eq!(describe(&system), "Enable system unit syncthing.service");
+        assert_eq!(unit_path(&user),
+                   "~/.config/systemd/user/syncthing.service");
+        assert_eq!(unit_path(&system),
+                   "/etc/systemd/system/syncthing.service");
+    }
+
+    #[test]
+    fn test_systemd_commands() {
+        let user = unit(Some("units/syncthing.service"), SystemdScope::User);
+        let system = unit(Some("syncthing.service"), SystemdScope::System);
+
+        assert_eq!(installed_command(&user),
+                   "cmp -s 'units/syncthing.service' \
+                    ~/'.config/systemd/user/syncthing.service'");
+        assert_eq!(install_command(&user),
+                   "mkdir -p ~/'.config/systemd/user' && cp \
+                    'units/syncthing.service' \
+                    ~/'.config/systemd/user/syncthing.service' && systemctl \
+                    --user daemon-reload");
+        assert_eq!(install_command(&system),
+                   "sudo mkdir -p '/etc/systemd/system' && sudo cp \
+                    'syncthing.service' \
+                    '/etc/systemd/system/syncthing.service' && sudo systemctl \
+                    daemon-reload");
+        assert_eq!(enabled_command(&user),
+                   "systemctl --user is-enabled --quiet 'syncthing.service'");
+        assert_eq!(enable_command(&system),
+                   "sudo systemctl enable 'syncthing.service'");
+        assert_eq!(start_command(&user, true),
+                   "systemctl --user restart 'syncthing.service'");
+    }
+}
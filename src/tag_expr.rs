@@ -0,0 +1,498 @@
+//! Parsing and evaluation of tag rules
+//!
+//! A tag rule is a boolean expression over tags, built from `!` (NOT), `&&`
+//! (AND), and `||` (OR) with parentheses for grouping. A comma is shorthand for
+//! `||`, and a leading caret (`^`) negates the entire rule. Tags in rules and
+//! in manifests may be glob patterns (e.g. `ubuntu-*`).
+//!
+//! ```
+//! let expr = TagExpr::parse("(linux || macos) && !work")?;
+//! assert_eq!(expr.eval(&["linux", "personal"]), true);
+//! ```
+
+use anyhow::{Result, bail};
+use glob::Pattern;
+use super::local::quote_if_needed;
+use super::manifest::Shell;
+
+/// A parsed tag rule
+#[derive(Clone, Debug, PartialEq)]
+pub enum TagExpr {
+    /// Satisfied by steps with a tag, which may be a glob pattern
+    Tag(String),
+
+    /// Satisfied by steps that don't satisfy an expression
+    Not(Box<TagExpr>),
+
+    /// Satisfied by steps that satisfy all of a list of expressions
+    And(Vec<TagExpr>),
+
+    /// Satisfied by steps that satisfy any of a list of expressions
+    Or(Vec<TagExpr>),
+}
+
+/// A token in a tag rule
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Tag(String),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+impl TagExpr {
+    /// Parses a tag rule
+    ///
+    /// `!` binds tighter than `&&`, which binds tighter than `||` and `,`.
+    /// Returns an Err if the rule is empty, is missing an operand, contains
+    /// unbalanced parentheses or an invalid glob pattern, or contains a caret
+    /// after its start.
+    ///
+    /// ```
+    /// let expr = TagExpr::parse("^linux,macos")?;
+    /// assert_eq!(TagExpr::parse("linux &&").is_ok(), false);
+    /// ```
+    pub fn parse(rule: &str) -> Result<TagExpr> {
+        let (negated, body) = match rule.strip_prefix('^') {
+            Some(body) => (true, body),
+            None => (false, rule),
+        };
+
+        let tokens = tokenize(rule, body)?;
+        let mut parser = Parser { rule, tokens: &tokens, pos: 0 };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            None => {},
+            Some(Token::Close) => {
+                bail!("\"{}\" contains an unmatched parenthesis", rule);
+            },
+            Some(_) => bail!("\"{}\" is missing an operator", rule),
+        }
+
+        Ok(if negated { TagExpr::Not(Box::new(expr)) } else { expr })
+    }
+
+    /// Returns the tags (or glob patterns) that an expression refers to
+    ///
+    /// ```
+    /// let expr = TagExpr::parse("(linux || macos) && !work")?;
+    /// assert_eq!(expr.tags(), ["linux", "macos", "work"]);
+    /// ```
+    pub fn tags(&self) -> Vec<&str> {
+        match self {
+            TagExpr::Tag(tag) => vec![tag.as_str()],
+            TagExpr::Not(expr) => expr.tags(),
+            TagExpr::And(exprs) | TagExpr::Or(exprs) => {
+                exprs.iter().flat_map(|x| x.tags()).collect()
+            },
+        }
+    }
+
+    /// Returns the tags (or glob patterns) that an expression refers to without
+    /// negating them, which are the tags that it selects
+    ///
+    /// ```
+    /// let expr = TagExpr::parse("(linux || macos) && !work")?;
+    /// assert_eq!(expr.selected_tags(), ["linux", "macos"]);
+    /// ```
+    pub fn selected_tags(&self) -> Vec<&str> {
+        match self {
+            TagExpr::Tag(tag) => vec![tag.as_str()],
+            TagExpr::Not(expr) => expr.negated_tags(),
+            TagExpr::And(exprs) | TagExpr::Or(exprs) => {
+                exprs.iter().flat_map(|x| x.selected_tags()).collect()
+            },
+        }
+    }
+
+    /// Returns the tags (or glob patterns) that an expression negates
+    fn negated_tags(&self) -> Vec<&str> {
+        match self {
+            TagExpr::Tag(_) => vec![],
+            TagExpr::Not(expr) => expr.selected_tags(),
+            TagExpr::And(exprs) | TagExpr::Or(exprs) => {
+                exprs.iter().flat_map(|x| x.negated_tags()).collect()
+            },
+        }
+    }
+
+    /// Checks if a list of tags satisfies an expression
+    ///
+    /// ```
+    /// let expr = TagExpr::parse("linux && !work")?;
+    /// assert_eq!(expr.eval(&["linux"]), true);
+    /// assert_eq!(expr.eval(&["linux", "work"]), false);
+    /// ```
+    pub fn eval<S: AsRef<str>>(&self, tags: &[S]) -> bool {
+        match self {
+            TagExpr::Tag(tag) => tags.iter().any(|x| {
+                tag_matches(tag, x.as_ref())
+            }),
+            TagExpr::Not(expr) => !expr.eval(tags),
+            TagExpr::And(exprs) => exprs.iter().all(|x| x.eval(tags)),
+            TagExpr::Or(exprs) => exprs.iter().any(|x| x.eval(tags)),
+        }
+    }
+}
+
+/// Splits the body of a tag rule (without any leading caret) into tokens
+fn tokenize(rule: &str, body: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '!' => tokens.push(Token::Not),
+            ',' => tokens.push(Token::Or),
+            '&' | '|' => {
+                if chars.next_if_eq(&c).is_none() {
+                    bail!("\"{}\" contains a single {} instead of {}{}", rule,
+                          c, c, c);
+                }
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            },
+            '^' => bail!("\"{}\" contains ^ after the start of the rule", rule),
+            _ if c.is_whitespace() => {},
+            _ => {
+                let mut tag = String::from(c);
+                while let Some(c) = chars.next_if(|x| is_tag_char(*x)) {
+                    tag.push(c);
+                }
+                if let Err(why) = Pattern::new(&tag) {
+                    bail!("\"{}\" contains an invalid pattern: {}", rule,
+                          why.msg);
+                }
+                tokens.push(Token::Tag(tag));
+            },
+        }
+    }
+    Ok(tokens)
+}
+
+/// Returns the tags that a tag rule refers to which don't match any tag in a
+/// manifest, along with the most similar manifest tag for each of them, if any
+///
+/// Rules that can't be parsed are ignored.
+///
+/// ```
+/// let tags = [String::from("linux"), String::from("macos")];
+/// let result = unknown_tags("lniux,macos", &tags);
+/// assert_eq!(result, [(String::from("lniux"), Some("linux"))]);
+/// ```
+pub fn unknown_tags<'a>(rule: &str, tags: &'a [String]) ->
+    Vec<(String, Option<&'a str>)> {
+
+    let Ok(expr) = TagExpr::parse(rule) else { return vec![] };
+    let mut unknown: Vec<(String, Option<&'a str>)> = vec![];
+    for tag in expr.tags() {
+        if tags.iter().any(|x| tag_matches(tag, x)) ||
+            unknown.iter().any(|x| x.0 == tag) { continue; }
+        unknown.push((String::from(tag), closest_tag(tag, tags)));
+    }
+    unknown
+}
+
+/// Returns the tag in a list that is most similar to another tag, if any is
+/// close enough to be a likely typo
+///
+/// ```
+/// let tags = [String::from("linux"), String::from("macos")];
+/// assert_eq!(closest_tag("lniux", &tags), Some("linux"));
+/// assert_eq!(closest_tag("windows", &tags), None);
+/// ```
+pub fn closest_tag<'a>(tag: &str, tags: &'a [String]) -> Option<&'a str> {
+    let max_distance = (tag.chars().count() / 3).max(1);
+    tags.iter().map(|x| (edit_distance(tag, x), x))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, x)| x.as_str())
+}
+
+/// Returns the number of single-character insertions, deletions,
+/// substitutions, and transpositions needed to turn one string into another
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // rows[i][j] is the distance between a[..i] and b[..j]
+    let mut rows = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() { row[0] = i; }
+    for (j, cell) in rows[0].iter_mut().enumerate() { *cell = j; }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            rows[i][j] = (rows[i - 1][j] + 1).min(rows[i][j - 1] + 1)
+                .min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                rows[i][j] = rows[i][j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+/// Returns the tags in a group that a list of tag rules selects (see
+/// [`TagExpr::selected_tags`])
+///
+/// Rules that can't be parsed are ignored.
+///
+/// ```
+/// let group = [String::from("linux"), String::from("macos")];
+/// assert_eq!(selected_group_tags(&["linux", "^macos"], &group), ["linux"]);
+/// ```
+pub fn selected_group_tags<'a, S: AsRef<str>>(rules: &[S], group: &'a [String])
+    -> Vec<&'a str> {
+
+    let exprs: Vec<_> = rules.iter().filter_map(|x| {
+        TagExpr::parse(x.as_ref()).ok()
+    }).collect();
+    group.iter().filter(|tag| {
+        exprs.iter().flat_map(|x| x.selected_tags())
+            .any(|x| tag_matches(x, tag))
+    }).map(|x| x.as_str()).collect()
+}
+
+/// Checks if a tag from a rule matches a tag from a manifest, where either may
+/// be a glob pattern
+///
+/// ```
+/// assert_eq!(tag_matches("ubuntu-*", "ubuntu-24.04"), true);
+/// assert_eq!(tag_matches("ubuntu-24.04", "ubuntu-*"), true);
+/// assert_eq!(tag_matches("ubuntu-*", "debian-12"), false);
+/// ```
+fn tag_matches(rule_tag: &str, tag: &str) -> bool {
+    let matches = |pattern: &str, text: &str| {
+        Pattern::new(pattern).is_ok_and(|x| x.matches(text))
+    };
+    rule_tag == tag || matches(rule_tag, tag) || matches(tag, rule_tag)
+}
+
+/// Checks if a character may appear in a tag
+fn is_tag_char(c: char) -> bool {
+    !c.is_whitespace() && !"()!,&|^".contains(c)
+}
+
+/// A recursive descent parser for the tokens of a tag rule
+struct Parser<'a> {
+    rule: &'a str,
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    /// Parses a list of expressions separated by `||` or `,`
+    fn or(&mut self) -> Result<TagExpr> {
+        let mut exprs = vec![self.and()?];
+        while self.tokens.get(self.pos) == Some(&Token::Or) {
+            self.pos += 1;
+            exprs.push(self.and()?);
+        }
+        Ok(if exprs.len() == 1 { exprs.remove(0) } else { TagExpr::Or(exprs) })
+    }
+
+    /// Parses a list of expressions separated by `&&`
+    fn and(&mut self) -> Result<TagExpr> {
+        let mut exprs = vec![self.unary()?];
+        while self.tokens.get(self.pos) == Some(&Token::And) {
+            self.pos += 1;
+            exprs.push(self.unary()?);
+        }
+        Ok(if exprs.len() == 1 { exprs.remove(0) } else { TagExpr::And(exprs) })
+    }
+
+    /// Parses a tag or a parenthesized expression with any number of `!`s
+    fn unary(&mut self) -> Result<TagExpr> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        match token {
+            Some(Token::Not) => Ok(TagExpr::Not(Box::new(self.unary()?))),
+            Some(Token::Tag(tag)) => Ok(TagExpr::Tag(tag)),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                if self.tokens.get(self.pos) != Some(&Token::Close) {
+                    bail!("\"{}\" contains an unmatched parenthesis",
+                          self.rule);
+                }
+                self.pos += 1;
+                Ok(expr)
+            },
+            _ => bail!("\"{}\" contains an empty tag", self.rule),
+        }
+    }
+}
+
+/// Joins a list of tag rules with spaces for `$COLIRU_RULES`, quoting the rules
+/// that contain characters `shell` would interpret (e.g. `(` or `&&`)
+///
+/// ```
+/// let rules = [String::from("linux,macos"), String::from("!work && gui")];
+/// assert_eq!(join_tag_rules(&rules, Shell::Sh),
+///            "linux,macos '!work && gui'");
+/// ```
+pub fn join_tag_rules(rules: &[String], shell: Shell) -> String {
+    rules.iter().map(|x| quote_if_needed(x, shell)).collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(name: &str) -> TagExpr {
+        TagExpr::Tag(String::from(name))
+    }
+
+    #[test]
+    fn test_tag_expr_parse_legacy() {
+        assert_eq!(TagExpr::parse("linux").unwrap(), tag("linux"));
+        assert_eq!(TagExpr::parse("linux,macos").unwrap(),
+                   TagExpr::Or(vec![tag("linux"), tag("macos")]));
+        assert_eq!(TagExpr::parse("^linux,macos").unwrap(),
+                   TagExpr::Not(Box::new(TagExpr::Or(vec![tag("linux"),
+                                                          tag("macos")]))));
+    }
+
+    #[test]
+    fn test_tag_expr_parse_precedence() {
+        let result = TagExpr::parse("a || !b && c").unwrap();
+
+        assert_eq!(result, TagExpr::Or(vec![
+            tag("a"),
+            TagExpr::And(vec![TagExpr::Not(Box::new(tag("b"))), tag("c")]),
+        ]));
+    }
+
+    #[test]
+    fn test_tag_expr_parse_parentheses() {
+        let result = TagExpr::parse("(linux || macos) && !work").unwrap();
+
+        assert_eq!(result, TagExpr::And(vec![
+            TagExpr::Or(vec![tag("linux"), tag("macos")]),
+            TagExpr::Not(Box::new(tag("work"))),
+        ]));
+    }
+
+    #[test]
+    fn test_tag_expr_parse_errors() {
+        let error = |rule| TagExpr::parse(rule).unwrap_err().to_string();
+
+        assert_eq!(error(""), "\"\" contains an empty tag");
+        assert_eq!(error("linux &&"), "\"linux &&\" contains an empty tag");
+        assert_eq!(error("!"), "\"!\" contains an empty tag");
+        assert_eq!(error("()"), "\"()\" contains an empty tag");
+        assert_eq!(error("(linux"),
+                   "\"(linux\" contains an unmatched parenthesis");
+        assert_eq!(error("linux)"),
+                   "\"linux)\" contains an unmatched parenthesis");
+        assert_eq!(error("linux & gui"),
+                   "\"linux & gui\" contains a single & instead of &&");
+        assert_eq!(error("linux gui"), "\"linux gui\" is missing an operator");
+        assert_eq!(error("linux,^work"),
+                   "\"linux,^work\" contains ^ after the start of the rule");
+        assert_eq!(error("ubuntu-[2"),
+                   "\"ubuntu-[2\" contains an invalid pattern: invalid range \
+                    pattern");
+    }
+
+    #[test]
+    fn test_tag_expr_eval() {
+        let expr = TagExpr::parse("(linux || macos) && !work").unwrap();
+
+        assert_eq!(expr.eval(&["linux"]), true);
+        assert_eq!(expr.eval(&["macos", "gui"]), true);
+        assert_eq!(expr.eval(&["linux", "work"]), false);
+        assert_eq!(expr.eval(&["windows"]), false);
+        assert_eq!(expr.eval::<&str>(&[]), false);
+    }
+
+    #[test]
+    fn test_tag_expr_eval_wildcards() {
+        let expr = TagExpr::parse("ubuntu-* && !*-server").unwrap();
+
+        assert_eq!(expr.eval(&["ubuntu-22.04"]), true);
+        assert_eq!(expr.eval(&["ubuntu-24.04", "gui"]), true);
+        assert_eq!(expr.eval(&["ubuntu-24.04", "ubuntu-server"]), false);
+        assert_eq!(expr.eval(&["debian-12"]), false);
+        assert_eq!(TagExpr::parse("ubuntu-2?.04").unwrap()
+                   .eval(&["ubuntu-24.04"]), true);
+        assert_eq!(TagExpr::parse("ubuntu-24.04").unwrap()
+                   .eval(&["ubuntu-*"]), true);
+        assert_eq!(TagExpr::parse("ubuntu-24.04").unwrap()
+                   .eval(&["ubuntu-22.*"]), false);
+    }
+
+    #[test]
+    fn test_tag_expr_unknown_tags() {
+        let tags = [
+            String::from("linux"),
+            String::from("macos"),
+            String::from("ubuntu-24.04"),
+        ];
+
+        assert_eq!(unknown_tags("linux && !macos", &tags), vec![]);
+        assert_eq!(unknown_tags("ubuntu-*", &tags), vec![]);
+        assert_eq!(unknown_tags("(lniux || mac) && !lniux", &tags), vec![
+            (String::from("lniux"), Some("linux")),
+            (String::from("mac"), None),
+        ]);
+        assert_eq!(unknown_tags("debian-*", &tags), vec![
+            (String::from("debian-*"), None),
+        ]);
+    }
+
+    #[test]
+    fn test_tag_expr_selected_tags() {
+        let expr = TagExpr::parse("(a || !(b && !c)) && !d").unwrap();
+
+        assert_eq!(expr.selected_tags(), ["a", "c"]);
+        assert_eq!(TagExpr::parse("^a,b").unwrap().selected_tags(),
+                   Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_tag_expr_selected_group_tags() {
+        let group = [
+            String::from("linux"),
+            String::from("macos"),
+            String::from("windows"),
+        ];
+
+        assert_eq!(selected_group_tags(&["linux", "gui"], &group), ["linux"]);
+        assert_eq!(selected_group_tags(&["^windows"], &group),
+                   Vec::<&str>::new());
+        assert_eq!(selected_group_tags(&["linux,macos"], &group),
+                   ["linux", "macos"]);
+        assert_eq!(selected_group_tags(&["*o*"], &group),
+                   ["macos", "windows"]);
+    }
+
+    #[test]
+    fn test_tag_expr_edit_distance() {
+        assert_eq!(edit_distance("linux", "linux"), 0);
+        assert_eq!(edit_distance("lniux", "linux"), 1);
+        assert_eq!(edit_distance("linx", "linux"), 1);
+        assert_eq!(edit_distance("macos", "linux"), 5);
+        assert_eq!(edit_distance("", "work"), 4);
+    }
+
+    #[test]
+    fn test_tag_expr_join_tag_rules() {
+        let rules = [
+            String::from("linux,macos"),
+            String::from("^work"),
+            String::from("(gui || ssh) && !server"),
+        ];
+
+        assert_eq!(join_tag_rules(&rules, Shell::Sh),
+                   "linux,macos ^work '(gui || ssh) && !server'");
+        assert_eq!(join_tag_rules(&rules, Shell::Fish),
+                   "linux,macos '^work' '(gui || ssh) && !server'");
+        assert_eq!(join_tag_rules(&rules, Shell::Cmd),
+                   "\"linux,macos\" \"^work\" \"(gui || ssh) && !server\"");
+        assert_eq!(join_tag_rules(&rules, Shell::Powershell),
+                   "'linux,macos' '^work' '(gui || ssh) && !server'");
+    }
+}
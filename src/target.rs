@@ -0,0 +1,696 @@
+//! Machines that dotfiles are installed on
+//!
+//! The commands of each manifest step are installed through a [`Target`], which
+//! hides whether files are installed on the local machine ([`LocalTarget`]) or
+//! staged and sent to another machine over SSH ([`SshTarget`]) or to a
+//! container ([`ContainerTarget`]). A new target is created for each step with
+//! [`new_target`], and [`Target::finish`] completes the step's installation
+//! once each of its files has been installed.
+//!
+//! ```
+//! let state = Mutex::new(InstallState::default());
+//! let host = Host::new("user@hostname", SshOptions::default());
+//! let mut target = new_target(&host, None, &state, false,
+//!                             Path::new("dotfiles"),
+//!                             Path::new("/tmp/staging"));
+//! target.install("bashrc", "~/.bashrc", LinkMode::Copy, &copy, true)?;
+//! target.finish(None)?;
+//! target.run("sh script.sh", None, None, None)?;
+//! ```
+
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use super::backup::{backup_file, default_store};
+use super::container::{self, Container, check_container,
+    run_container_command};
+use super::core::SSH_INSTALL_DIR;
+use super::facts::{Facts, container_facts, local_facts, remote_facts};
+use super::local::{CapturedOutput, copy_file, copy_file_preserving_mtime,
+    hard_link_file, link_file, link_file_relative, resolve_local_path,
+    run_command};
+use super::manifest::{CopyLinkOptions, LinkMode, Shell};
+use super::preview::{Change, preview_copy, preview_hard_link, preview_link,
+    preview_relative_link};
+use super::ssh::{Host, RemoteOs, preflight_connection, quote_path,
+    remote_command_line, remove_remote_file, resolve_path, run_remote_command,
+    send_command, send_staged_files, stage_file};
+use super::state::{InstallState, record_remote_command};
+
+/// A machine that the commands of a manifest step are installed on
+pub trait Target {
+    /// Returns the host that commands are installed on, or an empty string for
+    /// the local machine
+    fn host(&self) -> &str;
+
+    /// Returns whether the machine runs Windows
+    fn is_windows(&self) -> bool;
+
+    /// Returns the directory on the local machine that relative sources are
+    /// relative to
+    fn base_dir(&self) -> &Path;
+
+    /// Checks whether the machine can be reached before anything is installed
+    /// on it, returning an Err classified as a transport failure if it can't
+    fn connect(&self) -> Result<()>;
+
+    /// Collects facts about the machine
+    fn facts(&self) -> Result<Facts>;
+
+    /// Returns the shell that sets the environment variables of run commands
+    /// that don't have their own shell
+    fn default_shell(&self) -> Shell {
+        if self.is_windows() { Shell::Cmd } else { Shell::Sh }
+    }
+
+    /// Returns the path on the machine that a destination refers to
+    fn resolve_dst(&self, dst: &str) -> String;
+
+    /// Returns how a link command is installed on the machine, according to
+    /// its type or to `default` if it doesn't have one
+    fn link_mode(&self, link: &CopyLinkOptions, default: LinkMode) -> LinkMode;
+
+    /// Returns the change that installing a file would make to its
+    /// destination, or None if the destination can't be inspected in advance
+    fn preview(&self, src: &str, dst: &str, mode: LinkMode,
+               entry: &CopyLinkOptions) -> Option<Result<Change>>;
+
+    /// Installs a file for a copy or link command, returning the change that
+    /// was made, or None if it isn't known until [`finish`](Target::finish)
+    /// is called
+    ///
+    /// `src` may be a rendered copy of `entry`'s source. The destination is
+    /// recorded in the install state if `record` is true.
+    fn install(&mut self, src: &str, dst: &str, mode: LinkMode,
+               entry: &CopyLinkOptions, record: bool) ->
+        Result<Option<Change>>;
+
+    /// Returns the copy commands that send the scripts of run commands (and
+    /// the Brewfiles of brew commands) to the machine before they're used,
+    /// which aren't recorded in the install state
+    fn script_copies(&self, srcs: &[&str]) -> Vec<CopyLinkOptions>;
+
+    /// Completes the installation of the files installed by
+    /// [`install`](Target::install), which must be done before any run
+    /// commands are executed
+    fn finish(&mut self, capture: Option<&mut CapturedOutput>) -> Result<()>;
+
+    /// Executes a run command in the directory that scripts are run from,
+    /// indenting each line of its output
+    fn run(&self, command: &str, shell: Option<Shell>,
+           timeout: Option<Duration>, capture: Option<&mut CapturedOutput>) ->
+        Result<()>;
+
+    /// Executes a command that checks the machine (e.g. whether a package is
+    /// installed) in the directory that scripts are run from and returns
+    /// whether it succeeded, discarding its output since it's expected to fail
+    ///
+    /// ```
+    /// let installed = target.check("command -v git >/dev/null 2>&1");
+    /// ```
+    fn check(&self, command: &str) -> bool {
+        let mut discarded = CapturedOutput::default();
+        self.run(command, None, None, Some(&mut discarded)).is_ok()
+    }
+
+    /// Executes a manifest hook, indenting each line of its output
+    fn run_hook(&self, command: &str, capture: Option<&mut CapturedOutput>) ->
+        Result<()>;
+
+    /// Removes the script of a run command once it has run, if it was sent to
+    /// the machine
+    fn remove_script(&self, src: &str, capture: Option<&mut CapturedOutput>) ->
+        Result<()>;
+
+    /// Formats a path on the machine for output
+    ///
+    /// ```
+    /// assert_eq!(target.display_path("~/.bashrc"),
+    ///            "user@hostname:~/.bashrc");
+    /// ```
+    fn display_path(&self, path: &str) -> String {
+        match self.host() {
+            "" => path.to_owned(),
+            host => format!("{}:{}", host, path),
+        }
+    }
+}
+
+/// Returns the shell that executes run commands without their own shell on
+/// `host`, which may be the local machine
+///
+/// ```
+/// assert_eq!(host_shell(&Host::default()), Shell::Sh);
+/// ```
+pub fn host_shell(host: &Host) -> Shell {
+    let windows = if host.is_local() {
+        cfg!(target_family = "windows")
+    } else {
+        host.options.remote_os == RemoteOs::Windows
+    };
+    match (windows, host.is_local()) {
+        (false, _) => Shell::Sh,
+        (true, true) => Shell::Cmd,
+        (true, false) => Shell::Powershell,
+    }
+}
+
+/// Creates the target for a step that installs commands in `container` if one
+/// is provided, and on `host` (which may be the local machine) otherwise
+///
+/// Relative sources (and relative destinations on the local machine) are
+/// relative to `base_dir`. Local destinations are recorded in `state` and only
+/// replace existing files that coliru didn't install if `force` is true. Files
+/// for other machines and containers are staged in `staging_dir`, which is
+/// created when the first file is staged.
+///
+/// ```
+/// let target = new_target(&Host::default(), None, &state, false,
+///                         Path::new("dotfiles"), Path::new("/tmp/staging"));
+/// ```
+pub fn new_target<'a>(host: &'a Host, container: Option<&'a Container>,
+                      state: &'a Mutex<InstallState>, force: bool,
+                      base_dir: &'a Path, staging_dir: &Path) ->
+    Box<dyn Target + 'a> {
+
+    if let Some(container) = container {
+        Box::new(ContainerTarget::new(container, base_dir, staging_dir))
+    } else if host.is_local() {
+        Box::new(LocalTarget { state, force, base_dir })
+    } else {
+        Box::new(SshTarget::new(host, base_dir, staging_dir))
+    }
+}
+
+/// Stages the source of a remote link for the `~/.coliru` directory of a Unix
+/// machine, so that re-installing a changed source file updates every
+/// destination that links to it
+///
+/// Returns the path of the staged source on the machine along with a command
+/// that links to it from `dst`.
+///
+/// ```
+/// let (target, command) = stage_remote_link("/home/user/dotfiles/vimrc",
+///                                           "~/.vimrc", &entry,
+///                                           Path::new("/tmp/staging"))?;
+/// ```
+fn stage_remote_link(src: &str, dst: &str, entry: &CopyLinkOptions,
+                     staging_dir: &Path) -> Result<(String, String)> {
+    let base_dir = format!("~/{}", SSH_INSTALL_DIR);
+    let target = resolve_path(&entry.src, &base_dir, RemoteOs::Unix);
+    if !target.starts_with(&base_dir) {
+        bail!("Remote links must have a relative source");
+    }
+    stage_file(src, &target, staging_dir, entry.preserve_mtime)
+        .with_context(|| {
+            format!("Failed to copy {} to staging directory", entry.src)
+        })?;
+    let command = format!("mkdir -p \"$(dirname {})\" && ln -sfn {} {}",
+                          quote_path(dst), quote_path(&target),
+                          quote_path(dst));
+    Ok((target, command))
+}
+
+/// Backs up a file on the local machine before it's overwritten by a copy or
+/// link command
+pub fn backup_dst(src: &str, dst: &str) -> Result<()> {
+    let store = default_store()?;
+    backup_file(&store, Path::new(src), Path::new(dst)).with_context(|| {
+        format!("Failed to back up {}", dst)
+    })?;
+    Ok(())
+}
+
+/// The local machine, where files are installed immediately
+pub struct LocalTarget<'a> {
+    /// The install state that installed destinations are recorded in
+    state: &'a Mutex<InstallState>,
+
+    /// Whether to replace existing files that coliru didn't install
+    force: bool,
+
+    /// The directory that relative sources and destinations are relative to
+    base_dir: &'a Path,
+}
+impl Target for LocalTarget<'_> {
+    fn host(&self) -> &str { "" }
+
+    fn is_windows(&self) -> bool {
+        cfg!(target_family = "windows")
+    }
+
+    fn base_dir(&self) -> &Path {
+        self.base_dir
+    }
+
+    fn connect(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn facts(&self) -> Result<Facts> {
+        Ok(local_facts())
+    }
+
+    fn resolve_dst(&self, dst: &str) -> String {
+        dst.to_owned()
+    }
+
+    fn link_mode(&self, link: &CopyLinkOptions, default: LinkMode) ->
+        LinkMode {
+
+        link.link_mode(default)
+    }
+
+    fn preview(&self, src: &str, dst: &str, mode: LinkMode,
+               entry: &CopyLinkOptions) -> Option<Result<Change>> {
+        let src = &resolve_local_path(src, self.base_dir);
+        let dst = &resolve_local_path(dst, self.base_dir);
+        Some(match mode {
+            LinkMode::Symlink if entry.relative => {
+                preview_relative_link(src, dst)
+            },
+            LinkMode::Symlink => preview_link(src, dst),
+            LinkMode::Hardlink => preview_hard_link(src, dst),
+            LinkMode::Copy => preview_copy(src, dst),
+        })
+    }
+
+    /// Backs up the destination and installs the file, unless the destination
+    /// already matches
+    ///
+    /// Existing destinations that aren't recorded in the install state are
+    /// conflicts, which are only replaced if `force` is true. If the
+    /// destination couldn't be inspected beforehand, the file is installed
+    /// anyway so that any underlying error is reported.
+    fn install(&mut self, src: &str, dst: &str, mode: LinkMode,
+               entry: &CopyLinkOptions, record: bool) ->
+        Result<Option<Change>> {
+
+        let install: fn(&str, &str) -> Result<()> = match mode {
+            LinkMode::Symlink if entry.relative => link_file_relative,
+            LinkMode::Symlink => link_file,
+            LinkMode::Hardlink => hard_link_file,
+            LinkMode::Copy if entry.preserve_mtime => {
+                copy_file_preserving_mtime
+            },
+            LinkMode::Copy => copy_file,
+        };
+        let change = self.preview(src, dst, mode, entry)
+            .and_then(|x| x.ok())
+            .unwrap_or(Change::Update);
+        let (_src, _dst) = (resolve_local_path(src, self.base_dir),
+                            resolve_local_path(dst, self.base_dir));
+        if change != Change::Unchanged {
+            let exists = fs::symlink_metadata(&_dst).is_ok();
+            let managed = !record ||
+                self.state.lock().unwrap().is_managed(&_dst);
+            if exists && !self.force && !managed {
+                bail!("{} already exists and wasn't installed by coliru (use \
+                       --force to replace it)", dst);
+            }
+            backup_dst(&_src, &_dst)?;
+            install(&_src, &_dst)?;
+        }
+        if record {
+            self.state.lock().unwrap().record(&_dst);
+        }
+        Ok(Some(change))
+    }
+
+    /// Returns no commands, since scripts are run where they are
+    fn script_copies(&self, _srcs: &[&str]) -> Vec<CopyLinkOptions> {
+        vec![]
+    }
+
+    fn finish(&mut self, _capture: Option<&mut CapturedOutput>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Executes the run command in the directory containing the manifest
+    fn run(&self, command: &str, shell: Option<Shell>,
+           timeout: Option<Duration>, capture: Option<&mut CapturedOutput>) ->
+        Result<()> {
+
+        run_command(command, self.base_dir, shell, timeout, capture)
+    }
+
+    /// Executes the hook in the directory containing the manifest
+    fn run_hook(&self, command: &str, capture: Option<&mut CapturedOutput>) ->
+        Result<()> {
+
+        run_command(command, self.base_dir, None, None, capture)
+    }
+
+    fn remove_script(&self, _src: &str,
+                     _capture: Option<&mut CapturedOutput>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Another machine, where files are staged and then transferred together over
+/// SSH once every command in the step has been installed
+pub struct SshTarget<'a> {
+    /// The host that files are installed on
+    host: &'a Host,
+
+    /// The directory on the local machine that relative sources are relative
+    /// to
+    base_dir: &'a Path,
+
+    /// The directory that files are staged in
+    dir: PathBuf,
+
+    /// The remote destinations of the staged files and links, which are
+    /// recorded in the remote state file
+    dsts: Vec<String>,
+
+    /// The shell commands executed after the staged files are transferred
+    commands: Vec<String>,
+}
+impl SshTarget<'_> {
+    /// Creates a new SshTarget that reads relative sources from `base_dir`
+    /// and stages files in `dir`
+    ///
+    /// ```
+    /// let target = SshTarget::new(&host, Path::new("dotfiles"),
+    ///                             Path::new("/tmp/staging"));
+    /// ```
+    pub fn new<'a>(host: &'a Host, base_dir: &'a Path, dir: &Path) ->
+        SshTarget<'a> {
+
+        SshTarget { host, base_dir, dir: dir.to_path_buf(), dsts: vec![],
+                    commands: vec![] }
+    }
+
+    /// Stages a remote link with [`stage_remote_link`] and queues the command
+    /// that creates it
+    ///
+    /// Both the source and the link are recorded in the remote state file.
+    fn link(&mut self, src: &str, dst: &str, entry: &CopyLinkOptions) ->
+        Result<()> {
+
+        if self.is_windows() {
+            bail!("Remote links aren't supported on Windows hosts");
+        }
+        let (target, command) = stage_remote_link(
+            &resolve_local_path(src, self.base_dir), dst, entry, &self.dir)?;
+        self.commands.push(command);
+        self.dsts.extend([target, dst.to_owned()]);
+        Ok(())
+    }
+}
+impl Target for SshTarget<'_> {
+    fn host(&self) -> &str {
+        &self.host.name
+    }
+
+    fn is_windows(&self) -> bool {
+        self.host.options.remote_os == RemoteOs::Windows
+    }
+
+    fn base_dir(&self) -> &Path {
+        self.base_dir
+    }
+
+    fn connect(&self) -> Result<()> {
+        preflight_connection(self.host)
+    }
+
+    fn facts(&self) -> Result<Facts> {
+        remote_facts(self.host)
+    }
+
+    /// Returns Windows PowerShell for Windows hosts, which is the shell that
+    /// run commands are executed with, and `sh` otherwise
+    fn default_shell(&self) -> Shell {
+        if self.is_windows() { Shell::Powershell } else { Shell::Sh }
+    }
+
+    /// Resolves relative destinations relative to `~/.coliru`
+    fn resolve_dst(&self, dst: &str) -> String {
+        resolve_path(dst, &format!("~/{}", SSH_INSTALL_DIR),
+                     self.host.options.remote_os)
+    }
+
+    /// Returns [`LinkMode::Symlink`] for remote links and [`LinkMode::Copy`]
+    /// otherwise, since other links are installed as copies
+    fn link_mode(&self, link: &CopyLinkOptions, default: LinkMode) ->
+        LinkMode {
+
+        if link.remote && link.link_mode(default) == LinkMode::Symlink {
+            LinkMode::Symlink
+        } else {
+            LinkMode::Copy
+        }
+    }
+
+    /// Returns None, since destinations on other machines aren't inspected
+    fn preview(&self, _src: &str, _dst: &str, _mode: LinkMode,
+               _entry: &CopyLinkOptions) -> Option<Result<Change>> {
+        None
+    }
+
+    /// Stages the file, whose outcome isn't known until the staged files are
+    /// transferred
+    fn install(&mut self, src: &str, dst: &str, mode: LinkMode,
+               entry: &CopyLinkOptions, record: bool) ->
+        Result<Option<Change>> {
+
+        if mode == LinkMode::Symlink {
+            self.link(src, dst, entry)?;
+            return Ok(None);
+        }
+        stage_file(&resolve_local_path(src, self.base_dir), dst, &self.dir,
+                   entry.preserve_mtime)
+            .with_context(|| {
+                format!("Failed to copy {} to staging directory", entry.src)
+            })?;
+        if record {
+            self.dsts.push(dst.to_owned());
+        }
+        Ok(None)
+    }
+
+    fn script_copies(&self, srcs: &[&str]) -> Vec<CopyLinkOptions> {
+        srcs.iter().map(|x| CopyLinkOptions::script(x, x)).collect()
+    }
+
+    /// Transfers every staged file at once and then executes the queued
+    /// commands
+    ///
+    /// The destinations of the staged files are recorded in the remote state
+    /// file, except on Windows hosts, which don't have the shell used to record
+    /// them.
+    fn finish(&mut self, capture: Option<&mut CapturedOutput>) -> Result<()> {
+        if !self.dir.exists() { return Ok(()); }
+
+        let mut capture = capture;
+        send_staged_files(&self.dir, self.host, capture.as_deref_mut())
+            .context("Failed to transfer staged files")?;
+        let mut commands = std::mem::take(&mut self.commands);
+        let context = if commands.is_empty() {
+            "Failed to transfer staged files"
+        } else {
+            "Failed to create links"
+        };
+        if !self.dsts.is_empty() && !self.is_windows() {
+            commands.push(record_remote_command(&self.dsts));
+        }
+        if commands.is_empty() { return Ok(()); }
+        send_command(&commands.join(" && "), self.host, capture)
+            .context(context)
+    }
+
+    /// Executes the run command in `~/.coliru` on the remote machine
+    fn run(&self, command: &str, shell: Option<Shell>,
+           timeout: Option<Duration>, capture: Option<&mut CapturedOutput>) ->
+        Result<()> {
+
+        let command = remote_command_line(command, SSH_INSTALL_DIR, shell,
+                                          self.host.options.remote_os);
+        run_remote_command(&command, self.host, timeout, capture)
+    }
+
+    /// Executes the hook in the home directory on the remote machine
+    fn run_hook(&self, command: &str, capture: Option<&mut CapturedOutput>) ->
+        Result<()> {
+
+        run_remote_command(command, self.host, None, capture)
+    }
+
+    fn remove_script(&self, src: &str, capture: Option<&mut CapturedOutput>) ->
+        Result<()> {
+
+        let script = resolve_path(src, &format!("~/{}", SSH_INSTALL_DIR),
+                                  self.host.options.remote_os);
+        remove_remote_file(&script, self.host, capture)
+            .with_context(|| format!("Failed to delete {}", script))
+    }
+}
+
+/// A container, where files are staged and then copied into the container
+/// together once every command in the step has been installed
+pub struct ContainerTarget<'a> {
+    /// The container that files are installed in
+    container: &'a Container,
+
+    /// The directory on the local machine that relative sources are relative
+    /// to
+    base_dir: &'a Path,
+
+    /// The directory that files are staged in
+    dir: PathBuf,
+
+    /// The destinations of the staged files and links in the container, which
+    /// are recorded in its remote state file
+    dsts: Vec<String>,
+
+    /// The shell commands executed after the staged files are transferred
+    commands: Vec<String>,
+}
+impl ContainerTarget<'_> {
+    /// Creates a new ContainerTarget that reads relative sources from
+    /// `base_dir` and stages files in `dir`
+    ///
+    /// ```
+    /// let target = ContainerTarget::new(&container, Path::new("dotfiles"),
+    ///                                   Path::new("/tmp/staging"));
+    /// ```
+    pub fn new<'a>(container: &'a Container, base_dir: &'a Path, dir: &Path) ->
+        ContainerTarget<'a> {
+
+        ContainerTarget { container, base_dir, dir: dir.to_path_buf(),
+                          dsts: vec![], commands: vec![] }
+    }
+
+    /// Returns the directory in the container that scripts are run from
+    fn install_dir() -> String {
+        format!("~/{}", SSH_INSTALL_DIR)
+    }
+}
+impl Target for ContainerTarget<'_> {
+    fn host(&self) -> &str {
+        &self.container.name
+    }
+
+    /// Returns false, since containers are expected to run Linux
+    fn is_windows(&self) -> bool {
+        false
+    }
+
+    fn base_dir(&self) -> &Path {
+        self.base_dir
+    }
+
+    fn connect(&self) -> Result<()> {
+        check_container(self.container)
+    }
+
+    fn facts(&self) -> Result<Facts> {
+        container_facts(self.container)
+    }
+
+    /// Resolves relative destinations relative to `~/.coliru`
+    fn resolve_dst(&self, dst: &str) -> String {
+        resolve_path(dst, &Self::install_dir(), RemoteOs::Unix)
+    }
+
+    /// Returns [`LinkMode::Symlink`] for remote links and [`LinkMode::Copy`]
+    /// otherwise, like [`SshTarget`]
+    fn link_mode(&self, link: &CopyLinkOptions, default: LinkMode) ->
+        LinkMode {
+
+        if link.remote && link.link_mode(default) == LinkMode::Symlink {
+            LinkMode::Symlink
+        } else {
+            LinkMode::Copy
+        }
+    }
+
+    /// Returns None, since destinations in containers aren't inspected
+    fn preview(&self, _src: &str, _dst: &str, _mode: LinkMode,
+               _entry: &CopyLinkOptions) -> Option<Result<Change>> {
+        None
+    }
+
+    /// Stages the file, whose outcome isn't known until the staged files are
+    /// transferred
+    fn install(&mut self, src: &str, dst: &str, mode: LinkMode,
+               entry: &CopyLinkOptions, record: bool) ->
+        Result<Option<Change>> {
+
+        let src = resolve_local_path(src, self.base_dir);
+        if mode == LinkMode::Symlink {
+            let (target, command) = stage_remote_link(&src, dst, entry,
+                                                      &self.dir)?;
+            self.commands.push(command);
+            self.dsts.extend([target, dst.to_owned()]);
+            return Ok(None);
+        }
+        stage_file(&src, dst, &self.dir, entry.preserve_mtime)
+            .with_context(|| {
+                format!("Failed to copy {} to staging directory", entry.src)
+            })?;
+        if record {
+            self.dsts.push(dst.to_owned());
+        }
+        Ok(None)
+    }
+
+    fn script_copies(&self, srcs: &[&str]) -> Vec<CopyLinkOptions> {
+        srcs.iter().map(|x| CopyLinkOptions::script(x, x)).collect()
+    }
+
+    /// Copies every staged file into the container at once and then executes
+    /// the queued commands
+    fn finish(&mut self, capture: Option<&mut CapturedOutput>) -> Result<()> {
+        if !self.dir.exists() { return Ok(()); }
+
+        let mut capture = capture;
+        container::send_staged_files(&self.dir, self.container,
+                                     capture.as_deref_mut())
+            .context("Failed to transfer staged files")?;
+        let mut commands = std::mem::take(&mut self.commands);
+        let context = if commands.is_empty() {
+            "Failed to transfer staged files"
+        } else {
+            "Failed to create links"
+        };
+        if !self.dsts.is_empty() {
+            commands.push(record_remote_command(&self.dsts));
+        }
+        if commands.is_empty() { return Ok(()); }
+        container::send_command(&commands.join(" && "), self.container,
+                                capture)
+            .context(context)
+    }
+
+    /// Executes the run command in `~/.coliru` in the container
+    fn run(&self, command: &str, shell: Option<Shell>,
+           timeout: Option<Duration>, capture: Option<&mut CapturedOutput>) ->
+        Result<()> {
+
+        let command = remote_command_line(command, &Self::install_dir(), shell,
+                                          RemoteOs::Unix);
+        run_container_command(&command, self.container, timeout, capture)
+    }
+
+    /// Executes the hook in the home directory in the container
+    fn run_hook(&self, command: &str, capture: Option<&mut CapturedOutput>) ->
+        Result<()> {
+
+        run_container_command(&format!("cd && {}", command), self.container,
+                              None, capture)
+    }
+
+    fn remove_script(&self, src: &str, capture: Option<&mut CapturedOutput>) ->
+        Result<()> {
+
+        let script = self.resolve_dst(src);
+        container::send_command(&format!("rm -f {}", quote_path(&script)),
+                                self.container, capture)
+            .with_context(|| format!("Failed to delete {}", script))
+    }
+}
@@ -0,0 +1,649 @@
+//! Interactive prompts for choosing which steps of a manifest to install
+//!
+//! Steps are chosen from a full-screen list that shows their tags and
+//! commands, where keys toggle steps or every step with a tag, and a dry run of
+//! the selected steps is previewed beside the list whenever they change. Tags
+//! can also be chosen on their own with a line-based prompt to build a set of
+//! tag rules.
+//!
+//! ```
+//! let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+//! let selected = select_steps(&manifest, &[], |x, y| {
+//!     let options = InstallOptions { observer: Some(y), ..dry_run_options };
+//!     install_manifest(x.clone(), &options).map(|_| ())
+//! })?;
+//! ```
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use ratatui::{Frame, Terminal};
+use ratatui::backend::Backend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind,
+                                KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span, Text};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph, Wrap};
+use std::io::{self, BufRead, Write, stdout};
+use std::sync::{Arc, Mutex};
+use super::core::InstallObserver;
+use super::manifest::{Manifest, Step, get_manifest_tags, unmatched_tag_rule};
+
+/// The keys that are listed beneath the steps
+const HELP: &str = "up/down: move  space: toggle  tab: steps/tags  a: all  \
+                    n: none  enter: install  q: quit";
+
+/// The help text that's printed beneath the list of tags
+const TAG_PROMPT: &str = "Toggle tags (e.g. 1 or 2-4), then press enter to \
+                          continue (with no tags, every step is installed):";
+
+/// Collects the commands of a dry run as the lines of a preview
+struct PreviewObserver {
+    /// The 1-based numbers of the previewed steps in the full manifest, since
+    /// the dry run only numbers the selected steps
+    numbers: Vec<usize>,
+
+    /// The lines of the preview, in the order their commands were executed
+    lines: Mutex<Vec<String>>,
+}
+impl InstallObserver for PreviewObserver {
+    fn on_action(&self, step: Option<usize>, description: &str,
+                 outcome: &str) {
+        let label = match step.and_then(|x| self.numbers.get(x - 1)) {
+            Some(number) => format!("{}.", number),
+            None => String::from("hook:"),
+        };
+        self.lines.lock().unwrap().push(format!("{} {} ({})", label,
+                                                description, outcome));
+    }
+
+    fn on_error(&self, _step: Option<usize>, message: &str) {
+        self.lines.lock().unwrap().push(format!("  Error: {}", message));
+    }
+}
+
+/// The list that has focus in the step selector
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Focus {
+    /// The list of steps, which toggles a single step
+    Steps,
+
+    /// The list of tags, which toggles every step with a tag
+    Tags,
+}
+
+/// What the step selector does after a key is pressed
+#[derive(Debug, PartialEq)]
+enum Action {
+    /// Wait for another key
+    Continue,
+
+    /// Install the selected steps
+    Install,
+
+    /// Quit without installing anything
+    Quit,
+}
+
+/// The state of the step selector
+struct App<'a> {
+    /// The manifest that steps are selected from
+    manifest: &'a Manifest,
+
+    /// The tags of every step, sorted and without duplicates
+    tags: Vec<String>,
+
+    /// Whether each step of the manifest is selected
+    selected: Vec<bool>,
+
+    /// The list that keys move through and toggle
+    focus: Focus,
+
+    /// The 0-based index of the highlighted step
+    step_cursor: usize,
+
+    /// The 0-based index of the highlighted tag
+    tag_cursor: usize,
+
+    /// The lines of the latest preview
+    preview: Vec<String>,
+
+    /// Whether the selection has changed since the latest preview
+    stale: bool,
+}
+
+impl<'a> App<'a> {
+    /// Creates a step selector that starts with the steps that match
+    /// `tag_rules`
+    fn new(manifest: &'a Manifest, tag_rules: &[String]) -> App<'a> {
+        App {
+            manifest,
+            tags: get_manifest_tags(manifest.clone()),
+            selected: manifest.steps.iter().map(|x| {
+                unmatched_tag_rule(tag_rules, &x.tags).is_none()
+            }).collect(),
+            focus: Focus::Steps,
+            step_cursor: 0,
+            tag_cursor: 0,
+            preview: vec![],
+            stale: true,
+        }
+    }
+
+    /// Updates the selection according to a key press and returns what to do
+    /// next
+    ///
+    /// ```
+    /// let action = app.handle_key(KeyEvent::from(KeyCode::Char(' ')));
+    /// assert_eq!(action, Action::Continue);
+    /// ```
+    fn handle_key(&mut self, key: KeyEvent) -> Action {
+        let (cursor, len) = match self.focus {
+            Focus::Steps => (&mut self.step_cursor, self.selected.len()),
+            Focus::Tags => (&mut self.tag_cursor, self.tags.len()),
+        };
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL)
+                => return Action::Quit,
+            KeyCode::Up | KeyCode::Char('k') => {
+                *cursor = cursor.saturating_sub(1);
+            },
+            KeyCode::Down | KeyCode::Char('j') => {
+                *cursor = (*cursor + 1).min(len.saturating_sub(1));
+            },
+            KeyCode::Tab | KeyCode::BackTab if !self.tags.is_empty() => {
+                self.focus = match self.focus {
+                    Focus::Steps => Focus::Tags,
+                    Focus::Tags => Focus::Steps,
+                };
+            },
+            KeyCode::Char(' ') if len > 0 => {
+                let i = *cursor;
+                match self.focus {
+                    Focus::Steps => self.selected[i] = !self.selected[i],
+                    Focus::Tags => toggle_tag(&mut self.selected,
+                                              &self.manifest.steps,
+                                              &self.tags[i]),
+                }
+                self.stale = true;
+            },
+            KeyCode::Char('a') => {
+                self.selected.fill(true);
+                self.stale = true;
+            },
+            KeyCode::Char('n') => {
+                self.selected.fill(false);
+                self.stale = true;
+            },
+            KeyCode::Enter | KeyCode::Char('i') => return Action::Install,
+            KeyCode::Esc | KeyCode::Char('q') => return Action::Quit,
+            _ => {},
+        }
+        Action::Continue
+    }
+
+    /// Previews the selected steps again if they've changed
+    ///
+    /// `preview` is called with the selected steps and an observer, and should
+    /// perform a dry run that reports its progress to the observer. Any error
+    /// it returns is shown at the end of the preview.
+    fn refresh_preview<F>(&mut self, preview: &F)
+        where F: Fn(&Manifest, Arc<dyn InstallObserver>) -> Result<()> {
+
+        if !self.stale { return; }
+        self.stale = false;
+
+        let observer = Arc::new(PreviewObserver {
+            numbers: (1..=self.selected.len()).filter(|x| {
+                self.selected[x - 1]
+            }).collect(),
+            lines: Mutex::new(vec![]),
+        });
+        let result = preview(&selected_steps(self.manifest, &self.selected),
+                             observer.clone());
+        self.preview = std::mem::take(&mut observer.lines.lock().unwrap());
+        if let Err(why) = result {
+            self.preview.push(format!("Error: {:#}", why));
+        }
+    }
+
+    /// Draws the lists of steps and tags beside the preview, above a line of
+    /// help
+    fn render(&self, frame: &mut Frame) {
+        let [main, help] = Layout::vertical([Constraint::Fill(1),
+                                             Constraint::Length(1)])
+            .areas(frame.area());
+        let [lists, preview] = Layout::horizontal([Constraint::Fill(1),
+                                                   Constraint::Fill(1)])
+            .areas(main);
+        let tags_height = if self.tags.is_empty() { 0 } else {
+            (self.tags.len() as u16 + 2).min(lists.height / 2)
+        };
+        let [steps, tags] = Layout::vertical([Constraint::Fill(1),
+                                              Constraint::Length(tags_height)])
+            .areas(lists);
+
+        let items = self.manifest.steps.iter().enumerate().map(|(i, step)| {
+            let tags = if step.tags.is_empty() {
+                String::from("(no tags)")
+            } else {
+                step.tags.join(", ")
+            };
+            ListItem::new(Text::from(vec![
+                Line::from(vec![mark(self.selected[i]),
+                                Span::raw(format!(" {}. ", i + 1)),
+                                Span::styled(tags, Color::Cyan)]),
+                Line::from(format!("      {}", step_summary(step))),
+            ]))
+        });
+        self.render_list(frame, List::new(items), steps, Focus::Steps);
+
+        let items = self.tags.iter().map(|tag| {
+            let tagged: Vec<_> = self.manifest.steps.iter().zip(&self.selected)
+                .filter(|x| x.0.tags.contains(tag)).map(|x| *x.1).collect();
+            let mark = if tagged.iter().all(|x| *x) {
+                mark(true)
+            } else if tagged.iter().any(|x| *x) {
+                Span::styled("[-]", Color::Yellow)
+            } else {
+                mark(false)
+            };
+            ListItem::new(Line::from(vec![mark,
+                                          Span::raw(format!(" {}", tag))]))
+        });
+        self.render_list(frame, List::new(items), tags, Focus::Tags);
+
+        let lines: Vec<Line> = if self.preview.is_empty() {
+            vec![Line::from("(no commands)")]
+        } else {
+            self.preview.iter().map(|x| {
+                if x.trim_start().starts_with("Error:") {
+                    Line::styled(x.as_str(), Color::Red)
+                } else {
+                    Line::from(x.as_str())
+                }
+            }).collect()
+        };
+        frame.render_widget(Paragraph::new(lines)
+                                .block(Block::bordered().title("Dry run"))
+                                .wrap(Wrap { trim: false }), preview);
+
+        frame.render_widget(Paragraph::new(HELP)
+                                .style(Modifier::DIM), help);
+    }
+
+    /// Draws a list of steps or tags, highlighting its cursor if it has focus
+    fn render_list(&self, frame: &mut Frame, list: List,
+                   area: ratatui::layout::Rect, focus: Focus) {
+        let (title, cursor) = match focus {
+            Focus::Steps => ("Steps", self.step_cursor),
+            Focus::Tags => ("Tags", self.tag_cursor),
+        };
+        let mut block = Block::bordered().title(title);
+        let mut state = ListState::default();
+        if self.focus == focus {
+            block = block.border_style(Style::new().fg(Color::Cyan)
+                                       .add_modifier(Modifier::BOLD));
+            state.select(Some(cursor));
+        }
+        let list = list.block(block)
+            .highlight_style(Modifier::REVERSED);
+        frame.render_stateful_widget(list, area, &mut state);
+    }
+}
+
+/// Returns a checkbox for whether something is selected
+fn mark(selected: bool) -> Span<'static> {
+    if selected {
+        Span::styled("[x]", Color::Green)
+    } else {
+        Span::raw("[ ]")
+    }
+}
+
+/// Shows a full-screen list of the steps of a manifest, starting with the
+/// steps that match `tag_rules`, and returns a copy of the manifest with only
+/// the selected steps, or None if the selector is quit
+///
+/// `preview` is called with the selected steps and an observer whenever they
+/// change, and should perform a dry run that reports its progress to the
+/// observer, which is then shown beside the list. Any error it returns is
+/// shown without closing the selector.
+///
+/// ```
+/// let selected = select_steps(&manifest, &[], |_, _| Ok(()))?;
+/// ```
+pub fn select_steps<F>(manifest: &Manifest, tag_rules: &[String], preview: F)
+    -> Result<Option<Manifest>>
+    where F: Fn(&Manifest, Arc<dyn InstallObserver>) -> Result<()> {
+
+    let mut terminal = ratatui::try_init()
+        .context("Failed to set up terminal")?;
+    let result = run_app(&mut terminal, App::new(manifest, tag_rules),
+                         event::read, &preview);
+    ratatui::restore();
+    result
+}
+
+/// Draws the step selector and handles the keys from `next_event` until the
+/// selected steps are installed or the selector is quit
+fn run_app<B, E, F>(terminal: &mut Terminal<B>, mut app: App, mut next_event: E,
+                    preview: &F) -> Result<Option<Manifest>>
+    where B: Backend,
+          E: FnMut() -> io::Result<Event>,
+          F: Fn(&Manifest, Arc<dyn InstallObserver>) -> Result<()> {
+
+    loop {
+        app.refresh_preview(preview);
+        terminal.draw(|x| app.render(x)).context("Failed to draw terminal")?;
+
+        let Event::Key(key) = next_event().context("Failed to read input")?
+            else { continue };
+        if key.kind != KeyEventKind::Press { continue; }
+        match app.handle_key(key) {
+            Action::Continue => {},
+            Action::Install => {
+                return Ok(Some(selected_steps(app.manifest, &app.selected)));
+            },
+            Action::Quit => return Ok(None),
+        }
+    }
+}
+
+/// Prompts for tags with checkboxes and returns the tag rules that select
+/// them, which are empty if no tags are selected (or `input` ends)
+///
+/// Tags are toggled by number or by name, and an empty line finishes the
+/// selection. The selected tags are combined into a single rule so that steps
+/// with any of them are installed.
+///
+/// ```
+/// let tags = get_manifest_tags(manifest.clone());
+/// let tag_rules = select_tags(&tags, stdin().lock())?;
+/// ```
+pub fn select_tags<R: BufRead>(tags: &[String], mut input: R) ->
+    Result<Vec<String>> {
+
+    let mut selected = vec![false; tags.len()];
+    loop {
+        println!();
+        println!("{}", "Select the tags of the steps to install:".bold());
+        for (i, tag) in tags.iter().enumerate() {
+            let mark = if selected[i] { "[x]".green() } else { "[ ]".normal() };
+            println!("{} {} {}", mark, format!("{}.", i + 1).bold(), tag);
+        }
+        println!("{}", TAG_PROMPT.bold());
+        print!("> ");
+        stdout().flush().context("Failed to write prompt")?;
+
+        let mut line = String::new();
+        let eof = input.read_line(&mut line)
+            .context("Failed to read input")? == 0;
+        let line = line.trim();
+        if eof || line.is_empty() {
+            if eof { println!(); }
+            break;
+        }
+
+        let toggled = match tags.iter().position(|x| x == line) {
+            Some(i) => Ok(vec![i]),
+            None => parse_numbers(line, tags.len(), "tag"),
+        };
+        match toggled {
+            Ok(indices) => {
+                for i in indices {
+                    selected[i] = !selected[i];
+                }
+            },
+            Err(why) => eprintln!("  {} {}", "Error:".bold().red(), why),
+        }
+    }
+
+    let selected: Vec<_> = tags.iter().zip(selected).filter(|x| x.1)
+        .map(|x| x.0.as_str()).collect();
+    if selected.is_empty() {
+        Ok(vec![])
+    } else {
+        Ok(vec![selected.join(",")])
+    }
+}
+
+/// Returns a one-line summary of the commands in a step
+///
+/// ```
+/// assert_eq!(step_summary(&step), "Copy gitconfig; Link bashrc, vimrc");
+/// ```
+fn step_summary(step: &Step) -> String {
+    let srcs = |kind: &str, srcs: Vec<&str>| {
+        (!srcs.is_empty()).then(|| format!("{} {}", kind, srcs.join(", ")))
+    };
+    let count = |noun: &str, n: usize| {
+        (n > 0).then(|| format!("{} {} command{}", n, noun,
+                                if n == 1 { "" } else { "s" }))
+    };
+
+    let parts = [
+        srcs("Copy", step.copy.iter().map(|x| x.src.as_str()).collect()),
+        srcs("Link", step.link.iter().map(|x| x.src.as_str()).collect()),
+        srcs("Merge", step.block.iter().map(|x| x.src.as_str()).collect()),
+        srcs("Patch with", step.patch.iter().map(|x| x.src.as_str()).collect()),
+        count("package", step.packages.len()),
+        count("brew", step.brew.len()),
+        count("repo", step.repo.len()),
+        count("fetch", step.fetch.len()),
+        count("systemd", step.systemd.len()),
+        count("defaults", step.defaults.len()),
+        count("extension", step.extensions.len()),
+        count("cron", step.cron.len()),
+        srcs("Run", step.run.iter().map(|x| x.src.as_str()).collect()),
+        srcs("Stow", step.stow.iter().map(|x| x.src.as_str()).collect()),
+    ];
+    let parts: Vec<_> = parts.into_iter().flatten().collect();
+    if parts.is_empty() {
+        String::from("(no commands)")
+    } else {
+        parts.join("; ")
+    }
+}
+
+/// Parses a list of 1-based numbers and ranges of numbers (e.g. `1, 3-4`) up
+/// to `max` into 0-based indices, using `noun` to describe what else a word
+/// could have been in errors
+///
+/// ```
+/// assert_eq!(parse_numbers("1, 3-4", 5, "tag"), Ok(vec![0, 2, 3]));
+/// ```
+fn parse_numbers(line: &str, max: usize, noun: &str) ->
+    Result<Vec<usize>, String> {
+
+    let mut indices = vec![];
+    for word in line.split([' ', ',']).filter(|x| !x.is_empty()) {
+        let (start, end) = word.split_once('-').unwrap_or((word, word));
+        let (Ok(start), Ok(end)) = (start.parse::<usize>(), end.parse::<usize>())
+            else { return Err(format!("{} isn't a {}", word, noun)) };
+        if start == 0 || start > end || end > max {
+            return Err(format!("{} isn't between 1 and {}", word, max));
+        }
+        indices.extend(start - 1..end);
+    }
+    if indices.is_empty() {
+        return Err(String::from("Nothing was entered"));
+    }
+    Ok(indices)
+}
+
+/// Toggles the steps with a tag, selecting them all unless they're all
+/// selected already
+fn toggle_tag(selected: &mut [bool], steps: &[Step], tag: &str) {
+    let tagged: Vec<_> = (0..steps.len()).filter(|i| {
+        steps[*i].tags.iter().any(|x| x == tag)
+    }).collect();
+    let select = !tagged.iter().all(|i| selected[*i]);
+    for i in tagged {
+        selected[i] = select;
+    }
+}
+
+/// Returns a copy of a manifest with only its selected steps
+fn selected_steps(manifest: &Manifest, selected: &[bool]) -> Manifest {
+    Manifest {
+        steps: manifest.steps.iter().zip(selected).filter(|x| *x.1)
+            .map(|x| x.0.clone()).collect(),
+        ..manifest.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::parse_manifest_file;
+    use ratatui::backend::TestBackend;
+    use std::path::Path;
+
+    fn manifest() -> Manifest {
+        parse_manifest_file(Path::new("examples/test/manifest.yml")).unwrap()
+    }
+
+    fn steps() -> Vec<Step> {
+        manifest().steps
+    }
+
+    fn screen(terminal: &Terminal<TestBackend>) -> String {
+        let buffer = terminal.backend().buffer();
+        buffer.content().chunks(buffer.area.width as usize).map(|x| {
+            x.iter().map(|x| x.symbol()).collect::<String>() + "\n"
+        }).collect()
+    }
+
+    #[test]
+    fn test_tui_parse_numbers() {
+        assert_eq!(parse_numbers("1, 3-4", 5, "tag"), Ok(vec![0, 2, 3]));
+        assert_eq!(parse_numbers("2 2", 2, "tag"), Ok(vec![1, 1]));
+        assert_eq!(parse_numbers("0", 2, "tag"),
+                   Err(String::from("0 isn't between 1 and 2")));
+        assert_eq!(parse_numbers("1-x", 2, "tag"),
+                   Err(String::from("1-x isn't a tag")));
+    }
+
+    #[test]
+    fn test_tui_select_tags() {
+        let tags = vec![String::from("linux"), String::from("macos"),
+                        String::from("windows")];
+
+        let rules = select_tags(&tags, "1-3\nmacos\n\n3\n".as_bytes());
+        assert_eq!(rules.unwrap(), vec![String::from("linux,windows")]);
+        let rules = select_tags(&tags, "4\n".as_bytes());
+        assert_eq!(rules.unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_tui_toggle_tag() {
+        let steps = steps();
+        let mut selected = vec![true, false, false];
+
+        toggle_tag(&mut selected, &steps, "windows");
+        assert_eq!(selected, vec![true, false, true]);
+        toggle_tag(&mut selected, &steps, "windows");
+        assert_eq!(selected, vec![false, false, false]);
+    }
+
+    #[test]
+    fn test_tui_preview_observer() {
+        let observer = PreviewObserver { numbers: vec![1, 3],
+                                         lines: Mutex::new(vec![]) };
+
+        observer.on_action(Some(2), "Copy foo to ~/foo", "dry run: create");
+        observer.on_action(None, "Run git pull", "dry run");
+
+        let lines = observer.lines.into_inner().unwrap();
+        assert_eq!(lines, vec![
+            String::from("3. Copy foo to ~/foo (dry run: create)"),
+            String::from("hook: Run git pull (dry run)"),
+        ]);
+    }
+
+    #[test]
+    fn test_tui_handle_key() {
+        let manifest = manifest();
+        let mut app = App::new(&manifest, &[String::from("linux")]);
+        let key = |x| KeyEvent::from(x);
+        assert_eq!(app.selected, vec![true, true, false]);
+
+        assert_eq!(app.handle_key(key(KeyCode::Down)), Action::Continue);
+        assert_eq!(app.handle_key(key(KeyCode::Char(' '))), Action::Continue);
+        assert_eq!(app.selected, vec![true, false, false]);
+        app.handle_key(key(KeyCode::Char('j')));
+        app.handle_key(key(KeyCode::Char('j')));
+        app.handle_key(key(KeyCode::Char(' ')));
+        assert_eq!(app.selected, vec![true, false, true]);
+
+        // Tags are sorted, and toggling one selects all of its steps first
+        app.handle_key(key(KeyCode::Tab));
+        app.handle_key(key(KeyCode::Down));
+        app.handle_key(key(KeyCode::Char(' ')));
+        assert_eq!(app.tags[app.tag_cursor], "macos");
+        assert_eq!(app.selected, vec![true, true, true]);
+        app.handle_key(key(KeyCode::Char(' ')));
+        assert_eq!(app.selected, vec![false, false, true]);
+
+        app.handle_key(key(KeyCode::Char('a')));
+        assert_eq!(app.selected, vec![true, true, true]);
+        app.handle_key(key(KeyCode::Char('n')));
+        assert_eq!(app.selected, vec![false, false, false]);
+        assert_eq!(app.handle_key(key(KeyCode::Enter)), Action::Install);
+        assert_eq!(app.handle_key(key(KeyCode::Char('q'))), Action::Quit);
+    }
+
+    #[test]
+    fn test_tui_render() {
+        let manifest = manifest();
+        let mut app = App::new(&manifest, &[String::from("linux")]);
+        app.preview = vec![String::from("1. Copy gitconfig (dry run)"),
+                           String::from("  Error: Not found")];
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+
+        terminal.draw(|x| app.render(x)).unwrap();
+        let screen = screen(&terminal);
+        assert_eq!(screen.contains("[x] 1. windows, linux, macos"), true);
+        assert_eq!(screen.contains("      Copy gitconfig"), true);
+        assert_eq!(screen.contains("[ ] 3. windows"), true);
+        assert_eq!(screen.contains("[x] linux"), true);
+        assert_eq!(screen.contains("[-] windows"), true);
+        assert_eq!(screen.contains("1. Copy gitconfig (dry run)"), true);
+        assert_eq!(screen.contains("Error: Not found"), true);
+    }
+
+    #[test]
+    fn test_tui_run_app() {
+        let manifest = manifest();
+        let app = App::new(&manifest, &[String::from("linux")]);
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+        let mut keys = vec![KeyCode::Enter, KeyCode::Char(' '), KeyCode::Down]
+            .into_iter().map(|x| Event::Key(KeyEvent::from(x)));
+        let previews = Mutex::new(vec![]);
+
+        let selected = run_app(&mut terminal, app, || {
+            Ok(keys.next_back().unwrap())
+        }, &|x, observer| {
+            previews.lock().unwrap().push(x.steps.len());
+            observer.on_action(Some(1), "Copy gitconfig", "dry run");
+            Ok(())
+        }).unwrap().unwrap();
+
+        // The preview is refreshed only when the selection changes
+        assert_eq!(previews.into_inner().unwrap(), vec![2, 1]);
+        assert_eq!(selected.steps, vec![manifest.steps[0].clone()]);
+        assert_eq!(screen(&terminal).contains("1. Copy gitconfig (dry run)"),
+                   true);
+    }
+
+    #[test]
+    fn test_tui_step_summary() {
+        let steps = steps();
+
+        assert_eq!(step_summary(&steps[0]), "Copy gitconfig");
+        assert_eq!(step_summary(&steps[1]),
+                   "Copy scripts/foo; Link bashrc, vimrc; Run \
+                    scripts/script.sh");
+    }
+}
@@ -0,0 +1,109 @@
+//! Removal of previously installed dotfiles
+//!
+//! Uninstalling only removes the destinations recorded in the install state, so
+//! files that coliru didn't create are never removed. Destinations on the local
+//! machine are read from the local state file, and destinations on other
+//! machines are read from the state file in `~/.coliru` on that machine.
+//!
+//! ```
+//! let failure = uninstall(&Host::default(), false)?;
+//! let failure = uninstall(&host, false)?;
+//! ```
+
+use anyhow::{Context, Result, bail};
+use colored::Colorize;
+use std::fs;
+use std::io::ErrorKind;
+use super::backup::default_state_file;
+use super::exit::{Failure, classify};
+use super::ssh::{Host, RemoteOs};
+use super::state::{load_remote_state, load_state, remove_remote_files,
+                   save_state};
+
+/// Removes the dotfiles that coliru installed on the local machine or on
+/// another machine via SSH
+///
+/// If `host` is the local machine, the destinations in the local state file are
+/// removed. Otherwise, the destinations recorded on `host` are removed.
+/// Destinations are forgotten once they're removed. If `dry_run` is true, the
+/// destinations are listed without removing them. Returns an Err if the state
+/// file can't be read or written and returns the class of failure with the
+/// greatest precedence among any other errors otherwise.
+///
+/// ```
+/// let failure = uninstall(&host, true)?;
+/// ```
+pub fn uninstall(host: &Host, dry_run: bool) -> Result<Option<Failure>> {
+    if host.is_local() {
+        uninstall_local(dry_run)
+    } else {
+        uninstall_remote(host, dry_run)
+    }
+}
+
+/// Removes the destinations in the local state file
+fn uninstall_local(dry_run: bool) -> Result<Option<Failure>> {
+    let path = default_state_file()?;
+    let mut state = load_state(&path)?;
+    let files: Vec<_> = state.files().map(|x| x.to_path_buf()).collect();
+    if files.is_empty() {
+        println!("Nothing to uninstall");
+        return Ok(None);
+    }
+
+    let mut failure = None;
+    for file in files {
+        print!("Remove {}", file.display());
+        if dry_run {
+            println!(" (DRY RUN)");
+            continue;
+        }
+
+        match fs::remove_file(&file) {
+            Ok(()) => println!(" ({})", "removed".green()),
+            Err(why) if why.kind() == ErrorKind::NotFound => {
+                println!(" ({})", "missing".dimmed());
+            },
+            Err(why) => {
+                println!(" ({})", "FAILED".bold().red());
+                let why = anyhow::Error::new(why).context(format!(
+                    "Failed to remove {}", file.display()));
+                eprintln!("  {} {:#}", "Error:".bold().red(), why);
+                failure = failure.max(Some(classify(&why, Failure::LocalFile)));
+                continue;
+            },
+        }
+        state.forget(&file);
+    }
+
+    if !dry_run {
+        save_state(&path, &state)?;
+    }
+    Ok(failure)
+}
+
+/// Removes the destinations recorded on another machine
+fn uninstall_remote(host: &Host, dry_run: bool) -> Result<Option<Failure>> {
+    if host.options.remote_os == RemoteOs::Windows {
+        bail!("Uninstalling from Windows hosts isn't supported");
+    }
+    let files = load_remote_state(host)?;
+    if files.is_empty() {
+        println!("Nothing to uninstall");
+        return Ok(None);
+    }
+
+    for file in &files {
+        print!("Remove {}:{}", host, file);
+        println!("{}", if dry_run { " (DRY RUN)" } else { "" });
+    }
+    if dry_run { return Ok(None); }
+
+    let result = remove_remote_files(&files, host, None)
+        .context("Failed to remove files");
+    if let Err(why) = result {
+        eprintln!("  {} {:#}", "Error:".bold().red(), why);
+        return Ok(Some(classify(&why, Failure::Transport)));
+    }
+    Ok(None)
+}
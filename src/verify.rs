@@ -0,0 +1,526 @@
+//! Read-only verification of installed dotfiles
+//!
+//! Verification compares the destinations of copy and link commands against
+//! the manifest, and checks that the files created by run commands exist,
+//! without modifying the local or remote machine.
+//!
+//! ```
+//! let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+//! let compliant = verify_manifest(manifest, &["linux"], &Host::default(),
+//!                                 LinkMode::Symlink, HashAlgorithm::Sha256,
+//!                                 false)?;
+//! ```
+
+use anyhow::{Context, Result, bail};
+use colored::{Colorize, ColoredString};
+use serde::Serialize;
+use shellexpand::tilde;
+use std::env::set_current_dir;
+use std::fmt;
+use std::fs;
+use std::path::{Path, absolute};
+use super::core::SSH_INSTALL_DIR;
+use super::facts::apply_facts;
+use super::hash::{HashAlgorithm, hash_file};
+#[cfg(target_family = "unix")]
+use super::local::normalize_path;
+use super::manifest::{CopyLinkOptions, LinkMode, Manifest, RunOptions,
+    filter_manifest_steps};
+use super::render::render_copy;
+use super::ssh::{Host, RemoteOs, hash_remote_file, quote_path, resolve_path,
+    send_command_status};
+
+/// The state of an installed dotfile relative to the manifest
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    /// The destination matches the manifest
+    Ok,
+
+    /// The destination doesn't exist
+    Missing,
+
+    /// The destination's contents differ from the source file
+    Differs,
+
+    /// The destination isn't a symbolic link
+    NotLink,
+
+    /// The destination is a symbolic link that points to the wrong file
+    WrongTarget,
+}
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Status::Ok => write!(f, "{}", "ok".green()),
+            Status::Missing => write!(f, "{}", "missing".red()),
+            Status::Differs => write!(f, "{}", "contents differ".red()),
+            Status::NotLink => write!(f, "{}", "not a link".red()),
+            Status::WrongTarget => write!(f, "{}", "wrong link target".red()),
+        }
+    }
+}
+
+/// The result of verifying a single copy or link command
+#[derive(Debug, PartialEq)]
+pub struct Verification {
+    /// The state of the destination
+    pub status: Status,
+
+    /// The hash of the source file
+    pub src_hash: String,
+
+    /// The hash of the destination file, if it exists
+    pub dst_hash: Option<String>,
+}
+
+/// A machine-readable verification report
+#[derive(Debug, Serialize)]
+struct Report {
+    /// The algorithm used to compute hashes
+    algorithm: HashAlgorithm,
+
+    /// Whether every destination is compliant
+    compliant: bool,
+
+    /// The verified copy and link commands and files created by run commands,
+    /// in manifest order
+    entries: Vec<ReportEntry>,
+}
+
+/// A single copy or link command, or a file created by a run command, in a
+/// verification report
+#[derive(Debug, Serialize)]
+struct ReportEntry {
+    /// The 1-based index of the step containing the command
+    step: usize,
+
+    /// The type of command (`copy`, `link`, `hard_link`, or `run`)
+    kind: String,
+
+    /// The source file or script
+    src: String,
+
+    /// The destination file, or the file created by a script
+    dst: String,
+
+    /// The machine containing the destination, if not the local machine
+    host: Option<String>,
+
+    /// The state of the destination, if it could be verified
+    status: Option<Status>,
+
+    /// The hash of the source file
+    src_hash: Option<String>,
+
+    /// The hash of the destination file
+    dst_hash: Option<String>,
+
+    /// The error that prevented verification, if any
+    error: Option<String>,
+}
+
+/// Verifies that the dotfiles in a manifest are installed according to a set of
+/// tag rules, without making any changes
+///
+/// `host` may be the local machine. Link commands without a type are verified
+/// according to `link_mode`, hard links are verified by their contents, and the
+/// files that run commands create must exist. If `json` is true, a JSON report
+/// containing file hashes is printed instead of the usual output.
+/// Returns an Err if a critical error occurs and returns a bool indicating
+/// whether every destination is compliant otherwise.
+///
+/// ```
+/// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// let compliant = verify_manifest(manifest, &["linux"], &Host::default(),
+///                                 LinkMode::Symlink, HashAlgorithm::Sha256,
+///                                 false)?;
+/// ```
+pub fn verify_manifest(manifest: Manifest, tag_rules: &[String], host: &Host,
+                       link_mode: LinkMode, algorithm: HashAlgorithm,
+                       json: bool) ->
+    Result<bool> {
+
+    let filtered_manifest = apply_facts(filter_manifest_steps(manifest,
+                                                             tag_rules),
+                                        host)?;
+    set_current_dir(filtered_manifest.base_dir)
+        .context("Failed to set working directory")?;
+
+    let mut entries = vec![];
+
+    for (i, step) in filtered_manifest.steps.iter().enumerate() {
+        let step_str = format!("[{}/{}]", i+1,
+            filtered_manifest.steps.len()).bold();
+
+        for _copy in &step.copy {
+            let entry = verify_entry(i+1, _copy, host, LinkMode::Copy,
+                                     algorithm);
+            if !json { print_entry(&entry, &step_str); }
+            entries.push(entry);
+        }
+
+        for link in &step.link {
+            // Links are installed as copies over SSH
+            let mode = if host.is_local() {
+                link.link_mode(link_mode)
+            } else {
+                LinkMode::Copy
+            };
+            let entry = verify_entry(i+1, link, host, mode, algorithm);
+            if !json { print_entry(&entry, &step_str); }
+            entries.push(entry);
+        }
+
+        for run in &step.run {
+            for path in &run.creates {
+                let entry = verify_run_entry(i+1, run, path, host);
+                if !json { print_entry(&entry, &step_str); }
+                entries.push(entry);
+            }
+        }
+    }
+
+    let compliant = entries.iter().all(|x| x.status == Some(Status::Ok));
+
+    if json {
+        let report = Report { algorithm, compliant, entries };
+        println!("{}", serde_json::to_string_pretty(&report)
+            .context("Failed to serialize report")?);
+    }
+
+    Ok(compliant)
+}
+
+/// Verifies a single copy or link command
+fn verify_entry(step: usize, entry: &CopyLinkOptions, host: &Host,
+                mode: LinkMode, algorithm: HashAlgorithm) -> ReportEntry {
+
+    let dst = if host.is_local() {
+        entry.dst.clone()
+    } else {
+        resolve_path(&entry.dst, &format!("~/{}", SSH_INSTALL_DIR),
+                     host.options.remote_os)
+    };
+
+    let result = if mode == LinkMode::Symlink {
+        verify_link(&entry.src, &dst, algorithm)
+    } else {
+        // Compare against the rendered contents of concatenated files and
+        // files with converted line endings, whose sources are relative to
+        // the working directory
+        let remote = !host.is_local();
+        render_copy(entry, Path::new("."), remote).and_then(|rendered| {
+            let src = rendered.as_ref().map_or(entry.src.clone(), |x| {
+                x.path().to_string_lossy().to_string()
+            });
+            verify_copy(&src, &dst, host, algorithm)
+        })
+    };
+
+    let mut report_entry = ReportEntry {
+        step,
+        kind: String::from(match mode {
+            LinkMode::Symlink => "link",
+            LinkMode::Hardlink => "hard_link",
+            LinkMode::Copy => "copy",
+        }),
+        src: entry.src.clone(),
+        dst,
+        host: if host.is_local() { None } else { Some(host.name.clone()) },
+        status: None,
+        src_hash: None,
+        dst_hash: None,
+        error: None,
+    };
+    match result {
+        Ok(verification) => {
+            report_entry.status = Some(verification.status);
+            report_entry.src_hash = Some(verification.src_hash);
+            report_entry.dst_hash = verification.dst_hash;
+        },
+        Err(why) => report_entry.error = Some(format!("{:#}", why)),
+    }
+    report_entry
+}
+
+/// Verifies a single file created by a run command
+fn verify_run_entry(step: usize, run: &RunOptions, path: &str, host: &Host) ->
+    ReportEntry {
+
+    // Scripts are executed in the install directory over SSH
+    let dst = if host.is_local() {
+        path.to_owned()
+    } else {
+        resolve_path(path, &format!("~/{}", SSH_INSTALL_DIR),
+                     host.options.remote_os)
+    };
+    let result = verify_created(&dst, host);
+
+    ReportEntry {
+        step,
+        kind: String::from("run"),
+        src: run.src.clone(),
+        dst,
+        host: if host.is_local() { None } else { Some(host.name.clone()) },
+        status: result.as_ref().ok().copied(),
+        src_hash: None,
+        dst_hash: None,
+        error: result.err().map(|why| format!("{:#}", why)),
+    }
+}
+
+/// Prints the result of verifying a single copy or link command, or a file
+/// created by a run command
+fn print_entry(entry: &ReportEntry, step_str: &ColoredString) {
+    let (kind, verb) = match entry.kind.as_str() {
+        "link" => ("Link", "to"),
+        "hard_link" => ("Hard link", "to"),
+        "run" => ("Run", "created"),
+        _ => ("Copy", "to"),
+    };
+    print!("{} {} {} {} ", step_str, kind, entry.src, verb);
+    if let Some(host) = &entry.host {
+        print!("{}:", host);
+    }
+    print!("{}: ", entry.dst);
+
+    match (&entry.status, &entry.error) {
+        (Some(status), _) => println!("{}", status),
+        (None, why) => {
+            println!("{}", "error".red());
+            eprintln!("  {} {}", "Error:".bold().red(),
+                      why.as_deref().unwrap_or_default());
+        },
+    }
+}
+
+/// Checks whether a destination file has the same contents as a source file
+///
+/// `host` may be the local machine.
+///
+/// ```
+/// let verification = verify_copy("foo", "~/foo", &host,
+///                                HashAlgorithm::Sha256)?;
+/// ```
+pub fn verify_copy(src: &str, dst: &str, host: &Host, algorithm: HashAlgorithm)
+    -> Result<Verification> {
+
+    let src_hash = hash_file(Path::new(src), algorithm)?;
+
+    let dst_hash = if host.is_local() {
+        let _dst = tilde(dst).to_string();
+        if Path::new(&_dst).exists() {
+            Some(hash_file(Path::new(&_dst), algorithm)?)
+        } else {
+            None
+        }
+    } else {
+        hash_remote_file(dst, host, algorithm)?
+    };
+
+    let status = match &dst_hash {
+        None => Status::Missing,
+        Some(hash) if *hash == src_hash => Status::Ok,
+        Some(_) => Status::Differs,
+    };
+    Ok(Verification { status, src_hash, dst_hash })
+}
+
+/// Checks whether a file or directory that a script creates exists
+///
+/// `host` may be the local machine. Returns an Err for Windows hosts.
+///
+/// ```
+/// let status = verify_created("~/.vim/autoload/plug.vim", &host)?;
+/// ```
+pub fn verify_created(path: &str, host: &Host) -> Result<Status> {
+    let exists = if host.is_local() {
+        Path::new(tilde(path).as_ref()).exists()
+    } else if host.options.remote_os == RemoteOs::Windows {
+        bail!("Checking files on Windows hosts isn't supported");
+    } else {
+        send_command_status(&format!("[ -e {} ]", quote_path(path)), host)? == 0
+    };
+    Ok(if exists { Status::Ok } else { Status::Missing })
+}
+
+/// Checks whether a destination on the local machine is a link to a source file
+///
+/// On non-Unix platforms, where hard links are used instead of symbolic links,
+/// the contents of the files are compared instead.
+///
+/// ```
+/// let verification = verify_link("foo", "~/foo", HashAlgorithm::Sha256)?;
+/// ```
+#[cfg(target_family = "unix")]
+pub fn verify_link(src: &str, dst: &str, algorithm: HashAlgorithm) ->
+    Result<Verification> {
+
+    let src_hash = hash_file(Path::new(src), algorithm)?;
+
+    let _dst = tilde(dst).to_string();
+    let metadata = match fs::symlink_metadata(&_dst) {
+        Err(_) => {
+            return Ok(Verification {
+                status: Status::Missing, src_hash, dst_hash: None
+            });
+        },
+        Ok(metadata) => metadata,
+    };
+
+    // Links may be broken, in which case there are no contents to hash
+    let dst_hash = if Path::new(&_dst).exists() {
+        Some(hash_file(Path::new(&_dst), algorithm)?)
+    } else {
+        None
+    };
+
+    if !metadata.file_type().is_symlink() {
+        return Ok(Verification { status: Status::NotLink, src_hash, dst_hash });
+    }
+
+    let target = fs::read_link(&_dst).with_context(|| {
+        format!("Failed to read link {}", dst)
+    })?;
+    let src_abs = absolute(src).with_context(|| {
+        format!("Failed to make {} absolute", src)
+    })?;
+    // Relative targets are resolved against the link's directory
+    let dst_abs = absolute(&_dst).with_context(|| {
+        format!("Failed to make {} absolute", dst)
+    })?;
+    let target = match dst_abs.parent() {
+        Some(dir) => dir.join(target),
+        None => target,
+    };
+    let status = if normalize_path(&target) == normalize_path(&src_abs) {
+        Status::Ok
+    } else {
+        Status::WrongTarget
+    };
+    Ok(Verification { status, src_hash, dst_hash })
+}
+#[cfg(not(target_family = "unix"))]
+pub fn verify_link(src: &str, dst: &str, algorithm: HashAlgorithm) ->
+    Result<Verification> {
+
+    verify_copy(src, dst, &Host::default(), algorithm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{setup_integration, write_file};
+
+    #[cfg(target_family = "unix")]
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn test_verify_copy_ok() {
+        let tmp = setup_integration("test_verify_copy_ok");
+
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+        write_file(&dst, "contents of foo");
+
+        let result = verify_copy(src.to_str().unwrap(), dst.to_str().unwrap(),
+                                 &Host::default(), HashAlgorithm::Sha256);
+
+        assert_eq!(result.unwrap().status, Status::Ok);
+    }
+
+    #[test]
+    fn test_verify_copy_differs() {
+        let tmp = setup_integration("test_verify_copy_differs");
+
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+        write_file(&dst, "contents of bar");
+
+        let result = verify_copy(src.to_str().unwrap(), dst.to_str().unwrap(),
+                                 &Host::default(), HashAlgorithm::Sha256);
+
+        assert_eq!(result.unwrap().status, Status::Differs);
+    }
+
+    #[test]
+    fn test_verify_copy_missing() {
+        let tmp = setup_integration("test_verify_copy_missing");
+
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+
+        let result = verify_copy(src.to_str().unwrap(), dst.to_str().unwrap(),
+                                 &Host::default(), HashAlgorithm::Sha256);
+
+        assert_eq!(result.unwrap().status, Status::Missing);
+    }
+
+    #[test]
+    fn test_verify_created() {
+        let tmp = setup_integration("test_verify_created");
+
+        let foo = tmp.local.join("foo");
+        let bar = tmp.local.join("bar");
+        write_file(&foo, "contents of foo");
+
+        let host = Host::default();
+        let result_foo = verify_created(foo.to_str().unwrap(), &host);
+        let result_bar = verify_created(bar.to_str().unwrap(), &host);
+
+        assert_eq!(result_foo.unwrap(), Status::Ok);
+        assert_eq!(result_bar.unwrap(), Status::Missing);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_verify_link_ok() {
+        let tmp = setup_integration("test_verify_link_ok");
+
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+        symlink(&src, &dst).unwrap();
+
+        let result = verify_link(src.to_str().unwrap(), dst.to_str().unwrap(),
+                                 HashAlgorithm::Sha256);
+
+        assert_eq!(result.unwrap().status, Status::Ok);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_verify_link_wrong_target() {
+        let tmp = setup_integration("test_verify_link_wrong_target");
+
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+        symlink("missing", &dst).unwrap();
+
+        let result = verify_link(src.to_str().unwrap(), dst.to_str().unwrap(),
+                                 HashAlgorithm::Sha256);
+
+        assert_eq!(result.unwrap().status, Status::WrongTarget);
+    }
+
+    #[test]
+    #[cfg(target_family = "unix")]
+    fn test_verify_link_not_link() {
+        let tmp = setup_integration("test_verify_link_not_link");
+
+        let src = tmp.local.join("foo");
+        let dst = tmp.local.join("bar");
+        write_file(&src, "contents of foo");
+        write_file(&dst, "contents of foo");
+
+        let result = verify_link(src.to_str().unwrap(), dst.to_str().unwrap(),
+                                 HashAlgorithm::Sha256);
+
+        assert_eq!(result.unwrap().status, Status::NotLink);
+    }
+}
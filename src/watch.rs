@@ -0,0 +1,293 @@
+//! Live re-installation of dotfiles when their source files change
+//!
+//! ```
+//! let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+//! let options = InstallOptions { tag_rules: vec![String::from("linux")],
+//!                                ..InstallOptions::default() };
+//! watch_manifest(manifest, &options)?;
+//! ```
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+use notify::{Event, EventKind, RecursiveMode, Watcher, recommended_watcher};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf, absolute};
+use std::sync::mpsc::{RecvTimeoutError, channel};
+use std::time::Duration;
+use super::core::{InstallOptions, install_manifest};
+use super::exit::Failure;
+use super::manifest::{Manifest, Step, filter_manifest_steps};
+
+/// How long to wait for more changes before re-installing, so that a burst of
+/// file system events (e.g. an editor saving a file) causes a single re-install
+const DEBOUNCE_DELAY: Duration = Duration::from_millis(200);
+
+/// Installs the dotfiles in a manifest and then re-installs the copy, link,
+/// block, and patch commands whose source files change until the program is
+/// interrupted
+///
+/// Run commands are only executed during the initial installation and commands
+/// that match an `exclude` pattern are always skipped. Steps are installed one
+/// at a time without a dry run or a report, regardless of `options`. Returns an
+/// Err if a critical error occurs and returns the class of failure with the
+/// greatest precedence among any minor errors that occurred if the file system
+/// watcher stops unexpectedly.
+///
+/// ```
+/// let manifest = parse_manifest_file(Path::new("manifest.yml"))?;
+/// watch_manifest(manifest, &InstallOptions::default())?;
+/// ```
+pub fn watch_manifest(manifest: Manifest, options: &InstallOptions) ->
+    Result<Option<Failure>> {
+
+    let options = InstallOptions { dry_run: false, diff: false, jobs: 1,
+                                   report: None, ..options.clone() };
+    let base_dir = options.base_dir.clone()
+        .unwrap_or_else(|| manifest.base_dir.clone());
+    let steps = filter_manifest_steps(manifest.clone(), &options.tag_rules)
+        .steps;
+    let mut failure = install_manifest(manifest, &options)?;
+
+    let (tx, rx) = channel();
+    let mut watcher = recommended_watcher(tx)
+        .context("Failed to create file system watcher")?;
+    for dir in source_dirs(&steps, &base_dir)? {
+        watcher.watch(&dir, RecursiveMode::NonRecursive).with_context(|| {
+            format!("Failed to watch {}", dir.display())
+        })?;
+    }
+
+    println!("{}", "Watching for changes...".bold());
+
+    let mut changed = HashSet::new();
+    loop {
+        // Block until a change occurs, then collect changes until none occur
+        // for DEBOUNCE_DELAY
+        let result = if changed.is_empty() {
+            rx.recv().map_err(|_| RecvTimeoutError::Disconnected)
+        } else {
+            rx.recv_timeout(DEBOUNCE_DELAY)
+        };
+
+        match result {
+            Ok(Ok(event)) => changed.extend(changed_paths(event)),
+            Ok(Err(why)) => {
+                eprintln!("  {} {:#}", "Error:".bold().red(),
+                          anyhow::Error::new(why)
+                              .context("Failed to watch source files"));
+                failure = failure.max(Some(Failure::LocalFile));
+            },
+            Err(RecvTimeoutError::Timeout) => {
+                // Hooks are only executed during the initial installation
+                let changed_manifest = Manifest {
+                    steps: changed_steps(&steps, &changed, &base_dir),
+                    pre_install: vec![],
+                    post_install: vec![],
+                    hosts: BTreeMap::new(),
+                    vars: BTreeMap::new(),
+                    exclusive_tags: vec![],
+                    base_dir: base_dir.clone(),
+                };
+                changed.clear();
+                if changed_manifest.steps.is_empty() { continue; }
+
+                failure = failure.max(install_manifest(changed_manifest,
+                                                       &options)?);
+                println!("{}", "Watching for changes...".bold());
+            },
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    Ok(failure)
+}
+
+/// Returns the de-duplicated absolute parent directories of the source files
+/// of the copy, link, block, and patch commands in a set of steps, whose
+/// relative sources are relative to `base_dir`
+///
+/// Directories are watched instead of files because many editors save files by
+/// replacing them, which would stop a watcher from receiving further events.
+fn source_dirs(steps: &[Step], base_dir: &Path) -> Result<HashSet<PathBuf>> {
+    let mut dirs = HashSet::new();
+    for step in steps {
+        let copies = step.copy.iter().chain(step.link.iter())
+            .flat_map(|x| x.sources());
+        let blocks = step.block.iter().map(|x| x.src.as_str());
+        let patches = step.patch.iter().map(|x| x.src.as_str());
+        for _src in copies.chain(blocks).chain(patches) {
+            let src = absolute(base_dir.join(_src)).with_context(|| {
+                format!("Failed to make {} absolute", _src)
+            })?;
+            if let Some(parent) = src.parent() {
+                dirs.insert(parent.to_path_buf());
+            }
+        }
+    }
+    Ok(dirs)
+}
+
+/// Returns the absolute paths of the files created or modified in an event
+fn changed_paths(event: Event) -> Vec<PathBuf> {
+    match event.kind {
+        EventKind::Create(_) | EventKind::Modify(_) => {
+            event.paths.iter().filter_map(|x| absolute(x).ok()).collect()
+        },
+        _ => vec![],
+    }
+}
+
+/// Returns copies of a set of steps that only contain the copy, link, block,
+/// and patch commands whose source files are in a set of absolute paths
+///
+/// Relative sources are relative to `base_dir`, and steps without any matching
+/// commands are omitted.
+fn changed_steps(steps: &[Step], changed: &HashSet<PathBuf>, base_dir: &Path) ->
+    Vec<Step> {
+
+    let is_changed = |src: &str| {
+        absolute(base_dir.join(src)).is_ok_and(|x| changed.contains(&x))
+    };
+
+    steps.iter().map(|step| Step {
+        copy: step.copy.iter().filter(|x| {
+            x.sources().into_iter().any(is_changed)
+        }).cloned().collect(),
+        link: step.link.iter().filter(|x| is_changed(&x.src)).cloned()
+            .collect(),
+        block: step.block.iter().filter(|x| is_changed(&x.src)).cloned()
+            .collect(),
+        patch: step.patch.iter().filter(|x| is_changed(&x.src)).cloned()
+            .collect(),
+        packages: vec![],
+        brew: vec![],
+        repo: vec![],
+        fetch: vec![],
+        systemd: vec![],
+        defaults: vec![],
+        extensions: vec![],
+        cron: vec![],
+        run: vec![],
+        stow: vec![],
+        parallel: step.parallel,
+        tags: step.tags.clone(),
+    }).filter(|step| {
+        !step.copy.is_empty() || !step.link.is_empty() ||
+            !step.block.is_empty() || !step.patch.is_empty()
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::CopyLinkOptions;
+
+    fn entry(src: &str, dst: &str) -> CopyLinkOptions {
+        CopyLinkOptions {
+            src: String::from(src),
+            dst: String::from(dst),
+            link_type: None,
+            preserve_mtime: false,
+            relative: false,
+            remote: false,
+            eol: None,
+            concat: vec![],
+            template: None,
+        }
+    }
+
+    fn steps() -> Vec<Step> {
+        vec![
+            Step {
+                copy: vec![entry("gitconfig", "~/.gitconfig")],
+                link: vec![],
+                block: vec![],
+                patch: vec![],
+                packages: vec![],
+                brew: vec![],
+                repo: vec![],
+                fetch: vec![],
+                systemd: vec![],
+                defaults: vec![],
+                extensions: vec![],
+                cron: vec![],
+                run: vec![],
+                stow: vec![],
+                parallel: false,
+                tags: vec![String::from("linux")],
+            },
+            Step {
+                copy: vec![entry("bashrc", "~/.bashrc")],
+                link: vec![entry("vimrc", "~/.vimrc"),
+                           entry("dir/vimrc", "~/.config/vimrc")],
+                block: vec![],
+                patch: vec![],
+                packages: vec![],
+                brew: vec![],
+                repo: vec![],
+                fetch: vec![],
+                systemd: vec![],
+                defaults: vec![],
+                extensions: vec![],
+                cron: vec![],
+                run: vec![],
+                stow: vec![],
+                parallel: false,
+                tags: vec![String::from("macos")],
+            },
+        ]
+    }
+
+    #[test]
+    fn test_source_dirs() {
+        let result = source_dirs(&steps(), Path::new("dotfiles")).unwrap();
+
+        let base_dir = absolute("dotfiles").unwrap();
+        assert_eq!(result, HashSet::from([
+            base_dir.clone(),
+            base_dir.join("dir"),
+        ]));
+    }
+
+    #[test]
+    fn test_changed_steps_none() {
+        let changed = HashSet::from([absolute("dotfiles/foo").unwrap()]);
+
+        let result = changed_steps(&steps(), &changed, Path::new("dotfiles"));
+
+        assert_eq!(result, vec![]);
+    }
+
+    #[test]
+    fn test_changed_steps_some() {
+        let changed = HashSet::from([
+            absolute("dotfiles/vimrc").unwrap(),
+            absolute("dotfiles/dir/vimrc").unwrap(),
+            absolute("bashrc").unwrap(),
+        ]);
+
+        let result = changed_steps(&steps(), &changed, Path::new("dotfiles"));
+
+        assert_eq!(result, vec![
+            Step {
+                copy: vec![],
+                link: vec![entry("vimrc", "~/.vimrc"),
+                           entry("dir/vimrc", "~/.config/vimrc")],
+                block: vec![],
+                patch: vec![],
+                packages: vec![],
+                brew: vec![],
+                repo: vec![],
+                fetch: vec![],
+                systemd: vec![],
+                defaults: vec![],
+                extensions: vec![],
+                cron: vec![],
+                run: vec![],
+                stow: vec![],
+                parallel: false,
+                tags: vec![String::from("macos")],
+            },
+        ]);
+    }
+}
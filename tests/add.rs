@@ -0,0 +1,51 @@
+//! End to end tests that test the add subcommand
+
+mod test_utils;
+
+use test_utils::*;
+use std::fs::{create_dir_all, read_link};
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_add_basic() {
+    let (dirs, mut cmd) = setup_e2e_local("test_add_basic");
+    create_dir_all(dirs.home.join(".config/kitty")).unwrap();
+    write_file(&dirs.home.join(".config/kitty/kitty.conf"), "kitty #1\n");
+    cmd.args(["add", "~/.config/kitty/kitty.conf", "--tags", "linux", "gui"]);
+
+    let expected = "\
+Moved ~/.config/kitty/kitty.conf to config/kitty/kitty.conf
+Linked config/kitty/kitty.conf to ~/.config/kitty/kitty.conf
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.local.join("config/kitty/kitty.conf")),
+               "kitty #1\n");
+    assert_eq!(read_link(dirs.home.join(".config/kitty/kitty.conf")).unwrap(),
+               dirs.local.join("config/kitty/kitty.conf"));
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["manifest.yml", "-t", "gui", "--dry-run"]);
+
+    let expected = "\
+[1/1] Link config/kitty/kitty.conf to ~/.config/kitty/kitty.conf (DRY RUN: no change)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_add_missing_file() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_add_missing_file");
+    cmd.args(["add", "missing"]);
+
+    let expected = "Error: missing doesn't exist\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+}
@@ -12,33 +12,126 @@ fn test_basic_help() {
     let expected = format!("\
 A minimal, flexible, dotfile installer
 
-Usage: coliru{EXE_SUFFIX} [OPTIONS] <MANIFEST>
+Usage: coliru{EXE_SUFFIX} [OPTIONS] [MANIFEST]
+       coliru{EXE_SUFFIX} <COMMAND>
+
+Commands:
+  verify     Check installed dotfiles without making any changes
+  restore    Restore a backup of a file that was overwritten by coliru
+  init       Generate a starting manifest for a directory of dotfiles
+  gc         Remove old backups according to retention policies
+  add        Move a file into the dotfile repository and link it back
+  doctor     Check the environment for problems that would prevent installation
+  plan       Show the resolved installation steps without executing them
+  uninstall  Remove the dotfiles that coliru installed
+  clean      Remove scripts and staging files that coliru left behind
+  import     Convert dotfiles managed by another tool into a coliru manifest
+  export     Convert a manifest into the format of another provisioning tool
+  tui        Choose the steps to install from an interactive list
+  lint       Check a manifest for common mistakes without installing anything
 
 Arguments:
-  <MANIFEST>  The path to the coliru manifest file
+  [MANIFEST]  The path to the coliru manifest file, or - to read it from stdin
 
 Options:
-  -t, --tag-rules [<RULE>...]  The set of tag rules to enforce
-  -l, --list-tags              List available tags and quit without installing
-  -n, --dry-run                Do a trial run without any permanent changes
-      --host <HOST>            Install dotfiles on another machine over SSH
-      --copy                   Interpret link commands as copy commands
-      --no-color               Disable color output
-  -h, --help                   Print help
-  -V, --version                Print version
+  -t, --tag-rules [<RULE>...]     The set of tag rules to enforce
+      --tag-rules-file <PATH>     Read additional tag rules from a file
+      --auto-tags                 Skip steps tagged for another OS or CPU architecture (the default with --host)
+      --no-auto-tags              Don't skip steps tagged for another OS or CPU architecture
+      --strict-tags               Fail if a tag rule uses a tag that isn't in the manifest
+  -l, --list-tags                 List available tags and quit without installing
+      --tag-usage                 Show which steps use each tag and match the rules with --list-tags
+  -n, --dry-run                   Do a trial run without any permanent changes
+      --diff                      Show changes to file contents when installing or during a dry run [aliases: show-diff]
+      --host <HOST>               Install dotfiles on another machine over SSH
+      --docker <CONTAINER>        Install dotfiles in the Docker CONTAINER
+      --podman <CONTAINER>        Install dotfiles in the Podman CONTAINER
+  -p, --port <PORT>               Connect to the host over SSH on a different PORT
+      --ssh-key <PATH>            Authenticate with the host using the private key at PATH
+      --ssh-config <PATH>         Read SSH options from the configuration file at PATH
+      --jump <HOST>               Connect to the host through the bastion HOST
+      --host-key-policy <POLICY>  Check host keys with POLICY (strict, accept-new, or off)
+      --transport <TRANSPORT>     Send files to the host with TRANSPORT (e.g. scp or rsync)
+      --compress                  Compress data sent to the host over SSH
+      --connect-timeout <SECS>    Give up connecting to the host after SECS seconds
+      --forward-agent             Forward the local SSH agent to the host
+      --batch-mode                Fail instead of prompting for SSH passwords
+      --ssh-password              Prompt once for the SSH password and reuse it
+      --ssh-retries <N>           Retry SSH transfers up to N times after network failures
+      --remote-os <OS>            Treat the host as running OS (unix or windows)
+      --link-mode <MODE>          Install untyped links as a symlink, hardlink, or copy
+      --copy                      Same as --link-mode copy
+      --preserve-mtime            Keep the modification times of copied files
+      --relative-links            Create symlinks with relative targets
+      --remote-links              Create symlinks instead of copies over SSH
+      --remote-exec               Install dotfiles by running coliru on the host
+      --push-binary               Send this coliru binary to the host with --remote-exec
+  -j, --jobs <N>                  Execute up to N parallel steps concurrently [default: 1]
+      --script-timeout <SECS>     Kill scripts that run for longer than SECS seconds
+      --shell <SHELL>             Execute scripts with a different SHELL (e.g. bash or pwsh)
+      --cleanup-scripts           Delete scripts from the host after they succeed
+      --env <NAME=VALUE>          Execute scripts with the environment variable NAME=VALUE
+  -w, --watch                     Re-install dotfiles when they change
+      --sudo                      Use sudo to install files in locations that aren't writable
+      --sudo-password <MODE>      Give sudo a password over SSH with MODE (none, tty, or env)
+  -f, --force                     Replace existing files that coliru didn't install
+      --fix-links                 Only re-create broken or misdirected symlinks
+      --exclude <PATTERN>         Skip commands whose src or dst matches a glob PATTERN
+      --report <PATH>             Write a JSON or JUnit (.xml) report of each step
+      --json                      Print each action, outcome, and error as a line of JSON
+  -q, --quiet                     Only print errors, retries, and a summary of any errors
+      --no-color                  Disable color output
+  -h, --help                      Print help
+  -V, --version                   Print version
 
 Examples:
+  # Generate a starting manifest for the dotfiles in the current directory
+  coliru init
+
   # List tags in manifest
   coliru manifest.yml --list-tags
 
   # Preview installation steps with tags matching A && (B || C) && !D
   coliru manifest.yml --tag-rules A B,C ^D --dry-run
 
+  # Preview installation steps with tags matching (A || B) && !C
+  coliru manifest.yml --tag-rules '(A || B) && !C' --dry-run
+
+  # Show the resolved installation steps, including skipped steps
+  coliru plan manifest.yml --tag-rules A B,C ^D
+
   # Install dotfiles on local machine
   coliru manifest.yml --tag-rules A B,C ^D
 
+  # Re-create links that broke after moving the dotfile repository
+  coliru manifest.yml --tag-rules A B,C ^D --fix-links
+
+  # Install dotfiles and re-install them whenever they are edited
+  coliru manifest.yml --tag-rules A B,C ^D --watch
+
   # Install dotfiles to user@hostname over SSH
   coliru manifest.yml --tag-rules A B,C ^D --host user@hostname
+
+  # Restore the most recent backup of a file that was overwritten
+  coliru restore ~/.bashrc
+
+  # Move a file into the dotfile repository and link it back
+  coliru add ~/.config/kitty/kitty.conf --tags linux macos
+
+  # Check for problems that would prevent installing dotfiles over SSH
+  coliru doctor manifest.yml --host user@hostname
+
+  # Keep only the 5 most recent backups of each file
+  coliru gc --keep 5
+
+  # Remove the dotfiles that were installed on user@hostname
+  coliru uninstall --host user@hostname
+
+  # Remove scripts that were copied to user@hostname
+  coliru clean --host user@hostname
+
+  # Check dotfiles on user@hostname without making any changes
+  coliru verify manifest.yml --tag-rules A B,C ^D --host user@hostname
 ");
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -56,7 +149,8 @@ error: unexpected argument '--foo' found
 
   tip: to pass '--foo' as a value, use '-- --foo'
 
-Usage: coliru{EXE_SUFFIX} [OPTIONS] <MANIFEST>
+Usage: coliru{EXE_SUFFIX} [OPTIONS] [MANIFEST]
+       coliru{EXE_SUFFIX} <COMMAND>
 
 For more information, try '--help'.
 ");
@@ -76,7 +170,7 @@ fn test_basic_empty_manifest() {
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, expected);
     assert_eq!(&stdout, "");
-    assert_eq!(exitcode, Some(2));
+    assert_eq!(exitcode, Some(3));
 }
 
 #[test]
@@ -90,7 +184,7 @@ fn test_basic_missing_manifest() {
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, expected);
     assert_eq!(&stdout, "");
-    assert_eq!(exitcode, Some(2));
+    assert_eq!(exitcode, Some(3));
 }
 
 #[test]
@@ -104,7 +198,7 @@ fn test_basic_missing_manifest() {
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, expected);
     assert_eq!(&stdout, "");
-    assert_eq!(exitcode, Some(2));
+    assert_eq!(exitcode, Some(3));
 }
 
 #[test]
@@ -112,13 +206,13 @@ fn test_basic_missing_manifest() {
 fn test_basic_absolute_manifest() {
     let (dirs, mut cmd) = setup_e2e_local("test_basic_absolute_manifest");
     let manifest_path = dirs.local.join("manifest.yml");
-    cmd.args([&manifest_path.to_str().unwrap(), "--dry-run", "-t", "linux"]);
+    cmd.args([manifest_path.to_str().unwrap(), "--dry-run", "-t", "linux"]);
 
     let expected = "\
-[1/2] Copy gitconfig to ~/.gitconfig (DRY RUN)
-[2/2] Copy foo to foo (DRY RUN)
-[2/2] Link bashrc to ~/.bashrc (DRY RUN)
-[2/2] Link vimrc to ~/.vimrc (DRY RUN)
+[1/2] Copy gitconfig to ~/.gitconfig (DRY RUN: create)
+[2/2] Copy foo to foo (DRY RUN: no change)
+[2/2] Link bashrc to ~/.bashrc (DRY RUN: create)
+[2/2] Link vimrc to ~/.vimrc (DRY RUN: create)
 [2/2] Run sh script.sh arg1 linux (DRY RUN)
 ";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
@@ -149,10 +243,10 @@ fn test_basic_absolute_manifest() {
     cmd.args([&manifest_path.to_str().unwrap(), "--dry-run", "-t", "linux"]);
 
     let expected = "\
-[1/2] Copy gitconfig to .gitconfig (DRY RUN)
-[2/2] Copy foo to foo (DRY RUN)
-[2/2] Link bashrc to .bashrc (DRY RUN)
-[2/2] Link vimrc to .vimrc (DRY RUN)
+[1/2] Copy gitconfig to .gitconfig (DRY RUN: create)
+[2/2] Copy foo to foo (DRY RUN: no change)
+[2/2] Link bashrc to .bashrc (DRY RUN: create)
+[2/2] Link vimrc to .vimrc (DRY RUN: create)
 [2/2] Run sh script.sh arg1 linux (DRY RUN)
 ";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
@@ -190,3 +284,198 @@ windows
     assert_eq!(&stdout, expected);
     assert_eq!(exitcode, Some(0));
 }
+
+#[test]
+fn test_basic_list_tags_usage() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_basic_list_tags_usage");
+    cmd.args(["manifest.yml", "--list-tags", "--tag-usage", "-t", "windows"]);
+
+    let expected = "\
+linux    2 steps (1, 2)
+macos    2 steps (1, 2)
+windows  2 steps (1, 3)
+
+Steps matching tag rules: 1, 3
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_basic_color_env() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_basic_color_env");
+    cmd.args(["manifest.yml", "-n", "-t", "linux"]);
+    cmd.env("CLICOLOR_FORCE", "1");
+
+    let (stdout, _stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(stdout.contains("\x1b["), true);
+    assert_eq!(exitcode, Some(0));
+
+    cmd.env("NO_COLOR", "1");
+    let (stdout, _stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(stdout.contains("\x1b["), false);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_basic_invalid_tag_rules() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_basic_invalid_tag_rules");
+    cmd.args(["manifest.yml", "-t", "linux,"]);
+
+    let expected = "Error: Invalid tag rules: \"linux,\" contains an empty \
+                    tag\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(4));
+}
+
+#[test]
+fn test_basic_unknown_rule_tag() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_basic_unknown_rule_tag");
+    cmd.args(["manifest.yml", "--list-tags", "-t", "lniux,macos", "^wrk"]);
+
+    let expected_stdout = "\
+linux
+macos
+windows
+";
+    let expected_stderr = "\
+Warning: Tag rule \"lniux,macos\" refers to unknown tag lniux (did you mean \
+linux?)
+Warning: Tag rule \"^wrk\" refers to unknown tag wrk
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected_stderr);
+    assert_eq!(&stdout, expected_stdout);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_basic_unknown_rule_tag_strict() {
+    let (_dirs, mut cmd) = setup_e2e_local(
+        "test_basic_unknown_rule_tag_strict");
+    cmd.args(["manifest.yml", "--strict-tags", "-t", "lniux"]);
+
+    let expected = "Error: Tag rule \"lniux\" refers to unknown tag lniux (did \
+                    you mean linux?)\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(4));
+}
+
+#[test]
+fn test_basic_exclusive_tags() {
+    let (dirs, mut cmd) = setup_e2e_local("test_basic_exclusive_tags");
+    cmd.args(["exclusive.yml", "-n", "-t", "^windows"]);
+    write_file(&dirs.local.join("exclusive.yml"), "\
+exclusive_tags: [ [ linux, macos, windows ] ]
+steps:
+  - copy: [ { src: gitconfig, dst: ~/.gitconfig } ]
+    tags: [ linux, macos ]
+");
+
+    let expected = "Error: Invalid tag rules: Exactly one of linux, macos, \
+                    windows must be selected, but none are\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(4));
+}
+
+#[test]
+fn test_basic_invalid_tag_rule_expression() {
+    let (_dirs, mut cmd) = setup_e2e_local(
+        "test_basic_invalid_tag_rule_expression");
+    cmd.args(["manifest.yml", "-t", "(linux || macos"]);
+
+    let expected = "Error: Invalid tag rules: \"(linux || macos\" contains \
+                    an unmatched parenthesis\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(4));
+}
+
+#[test]
+#[cfg(not(feature = "native-ssh"))]
+fn test_basic_native_unsupported() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_basic_native_unsupported");
+    cmd.args(["manifest.yml", "--host", "user@hostname", "--transport",
+              "native"]);
+
+    let expected = "Error: The native transport requires coliru to be built \
+                    with the native-ssh feature\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+}
+
+#[test]
+fn test_basic_windows_host_rsync() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_basic_windows_host_rsync");
+    cmd.args(["manifest.yml", "--host", "user@hostname", "--remote-os",
+              "windows", "--transport", "rsync"]);
+
+    let expected = "Error: The rsync transport doesn't support Windows \
+                    hosts\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+}
+
+#[test]
+fn test_basic_remote_exec_without_host() {
+    let (_dirs, mut cmd) = setup_e2e_local(
+        "test_basic_remote_exec_without_host");
+    cmd.args(["manifest.yml", "--remote-exec"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "Error: --remote-exec requires a host\n");
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+}
+
+#[test]
+fn test_basic_docker_with_host() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_basic_docker_with_host");
+    cmd.args(["manifest.yml", "--docker", "foo", "--host", "bar"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert!(stderr.starts_with("error: the argument '--docker <CONTAINER>' \
+                                cannot be used with '--host <HOST>'\n"));
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+}
+
+#[test]
+fn test_basic_askpass() {
+    let (dirs, mut cmd) = setup_e2e_local("test_basic_askpass");
+    write_file(&dirs.local.join("password"), "hunter2");
+    cmd.env("COLIRU_ASKPASS", dirs.local.join("password"));
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "hunter2\n");
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_basic_password_without_terminal() {
+    let (_dirs, mut cmd) = setup_e2e_local(
+        "test_basic_password_without_terminal");
+    cmd.args(["manifest.yml", "--host", "user@hostname", "--ssh-password"]);
+    cmd.env_remove("COLIRU_SSH_PASSWORD");
+
+    let expected = "Error: COLIRU_SSH_PASSWORD must be set to authenticate \
+                    with a password when stdin isn't a terminal\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+}
@@ -0,0 +1,103 @@
+//! End to end tests that test the clean subcommand
+
+mod test_utils;
+
+use test_utils::*;
+use std::fs::{File, create_dir_all};
+use std::time::{Duration, SystemTime};
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_clean_local() {
+    let (dirs, mut cmd) = setup_e2e_local("test_clean_local");
+    let tmp = dirs.local.join("tmp");
+    let old = tmp.join("coliru-staging-old");
+    let new = tmp.join("coliru-staging-new");
+    let other = tmp.join("other");
+    for dir in [&old, &new, &other] {
+        create_dir_all(dir).unwrap();
+    }
+    let two_hours_ago = SystemTime::now() - Duration::from_secs(2 * 60 * 60);
+    File::open(&old).unwrap().set_modified(two_hours_ago).unwrap();
+    File::open(&other).unwrap().set_modified(two_hours_ago).unwrap();
+    cmd.env("TMPDIR", &tmp);
+    cmd.args(["clean", "--dry-run"]);
+
+    // Recently modified staging directories may belong to running installs
+    let expected = format!("Remove {} (DRY RUN)\n", old.display());
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, &expected);
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(old.exists(), true);
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.env("TMPDIR", &tmp);
+    cmd.args(["clean"]);
+
+    let expected = format!("Remove {} (removed)\n", old.display());
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, &expected);
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(old.exists(), false);
+    assert_eq!(new.exists(), true);
+    assert_eq!(other.exists(), true);
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_clean_local_nothing() {
+    let (dirs, mut cmd) = setup_e2e_local("test_clean_local_nothing");
+    create_dir_all(dirs.local.join("tmp")).unwrap();
+    cmd.env("TMPDIR", dirs.local.join("tmp"));
+    cmd.args(["clean"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "Nothing to clean\n");
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_clean_local_backups() {
+    let (dirs, mut cmd) = setup_e2e_local("test_clean_local_backups");
+    cmd.args(["manifest.yml", "-t", "linux", "--copy"]);
+    run_command(&mut cmd);
+    write_file(&dirs.local.join("bashrc"), "bash #2\n");
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["manifest.yml", "-t", "linux", "--copy"]);
+    run_command(&mut cmd);
+
+    let mut cmd = coliru_command(&dirs);
+    create_dir_all(dirs.local.join("tmp")).unwrap();
+    cmd.env("TMPDIR", dirs.local.join("tmp"));
+    cmd.args(["clean", "--backups"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "Removed 1 backups and 1 files (8 B)\n");
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_clean_ssh_dry_run() {
+    let (dirs, mut cmd) = setup_e2e_ssh("test_clean_ssh_dry_run");
+    cmd.args(["manifest.yml", "-t", "linux"]);
+    run_command(&mut cmd);
+
+    // Other SSH tests share the remote install directory, so only the files
+    // copied by this test are checked
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["clean", "--host", SSH_HOST, "--dry-run"]);
+
+    let line = format!("Remove {SSH_HOST}:/home/test/.coliru/\
+                        test_clean_ssh_dry_run/script.sh (DRY RUN)\n");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert!(stdout.contains(&line), "{}", stdout);
+    assert!(!stdout.contains("test_clean_ssh_dry_run/foo"), "{}", stdout);
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(dirs.ssh_cwd.join("script.sh").exists(), true);
+}
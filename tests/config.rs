@@ -0,0 +1,222 @@
+//! End to end tests that test the user configuration file
+
+mod test_utils;
+
+use test_utils::*;
+use std::fs::create_dir_all;
+
+/// Writes the user configuration file in the home temporary directory
+fn write_config(dirs: &TempDirs, contents: &str) {
+    let config_dir = dirs.home.join(".config").join("coliru");
+    create_dir_all(&config_dir).unwrap();
+    write_file(&config_dir.join("config.toml"), contents);
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_config_defaults() {
+    let (dirs, mut cmd) = setup_e2e_local("test_config_defaults");
+    write_config(&dirs, &format!("\
+manifest = \"{}\"
+tag_rules = [\"linux\"]
+", dirs.local.join("manifest.yml").display()));
+    cmd.current_dir(&dirs.home);
+    cmd.args(["--dry-run"]);
+
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig (DRY RUN: create)
+[2/2] Copy foo to foo (DRY RUN: no change)
+[2/2] Link bashrc to ~/.bashrc (DRY RUN: create)
+[2/2] Link vimrc to ~/.vimrc (DRY RUN: create)
+[2/2] Run sh script.sh arg1 linux (DRY RUN)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_config_overridden() {
+    let (dirs, mut cmd) = setup_e2e_local("test_config_overridden");
+    write_config(&dirs, "\
+manifest = \"missing.yml\"
+tag_rules = [\"windows\"]
+copy = true
+");
+    cmd.args(["manifest.yml", "--dry-run", "-t", "linux", "^windows"]);
+
+    let expected = "\
+[1/1] Copy foo to foo (DRY RUN: no change)
+[1/1] Copy bashrc to ~/.bashrc (DRY RUN: create)
+[1/1] Copy vimrc to ~/.vimrc (DRY RUN: create)
+[1/1] Run sh script.sh arg1 linux ^windows (DRY RUN)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_config_invalid() {
+    let (dirs, mut cmd) = setup_e2e_local("test_config_invalid");
+    write_config(&dirs, "tag_rules = \"linux\"\n");
+    cmd.args(["manifest.yml"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(stderr.starts_with(&format!("Error: Failed to parse {}: ",
+        dirs.home.join(".config/coliru/config.toml").display())), true);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+}
+
+#[test]
+fn test_config_no_manifest() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_config_no_manifest");
+    cmd.args(["-t", "linux"]);
+
+    let expected = "Error: No manifest was provided on the command line, in \
+                    COLIRU_MANIFEST, or in the config file\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_config_env_overrides_config() {
+    let (dirs, mut cmd) = setup_e2e_local("test_config_env_overrides_config");
+    write_config(&dirs, "\
+manifest = \"missing.yml\"
+tag_rules = [\"windows\"]
+copy = true
+");
+    cmd.env("COLIRU_MANIFEST", "manifest.yml");
+    cmd.env("COLIRU_TAG_RULES", "linux ^windows");
+    cmd.env("COLIRU_COPY", "false");
+    cmd.args(["--dry-run"]);
+
+    let expected = "\
+[1/1] Copy foo to foo (DRY RUN: no change)
+[1/1] Link bashrc to ~/.bashrc (DRY RUN: create)
+[1/1] Link vimrc to ~/.vimrc (DRY RUN: create)
+[1/1] Run sh script.sh arg1 linux ^windows (DRY RUN)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_config_args_override_env() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_config_args_override_env");
+    cmd.env("COLIRU_MANIFEST", "missing.yml");
+    cmd.env("COLIRU_TAG_RULES", "windows");
+    cmd.args(["manifest.yml", "--dry-run", "-t", "linux", "^windows"]);
+
+    let expected = "\
+[1/1] Copy foo to foo (DRY RUN: no change)
+[1/1] Link bashrc to ~/.bashrc (DRY RUN: create)
+[1/1] Link vimrc to ~/.vimrc (DRY RUN: create)
+[1/1] Run sh script.sh arg1 linux ^windows (DRY RUN)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_config_env_invalid_flag() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_config_env_invalid_flag");
+    cmd.env("COLIRU_COPY", "sometimes");
+    cmd.args(["manifest.yml"]);
+
+    let expected = "Error: Invalid value for COLIRU_COPY: sometimes (expected \
+                    true or false)\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+}
+
+#[test]
+fn test_config_env_invalid_host_key_policy() {
+    let (_dirs, mut cmd) = setup_e2e_local(
+        "test_config_env_invalid_host_key_policy");
+    cmd.env("COLIRU_HOST_KEY_POLICY", "ask");
+    cmd.args(["manifest.yml"]);
+
+    let expected = "Error: Invalid value for COLIRU_HOST_KEY_POLICY: ask \
+                    (expected strict, accept-new, or off)\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_config_relative_config_home() {
+    let (dirs, mut cmd) = setup_e2e_local("test_config_relative_config_home");
+    write_config(&dirs, "tag_rules = [\"linux\"]\n");
+    create_dir_all(dirs.local.join("coliru")).unwrap();
+    write_file(&dirs.local.join("coliru/config.toml"), "hots = \"laptop\"\n");
+    cmd.env("XDG_CONFIG_HOME", ".");
+    cmd.args(["manifest.yml", "--dry-run"]);
+
+    // Relative paths in $XDG_CONFIG_HOME are ignored
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig (DRY RUN: create)
+[2/2] Copy foo to foo (DRY RUN: no change)
+[2/2] Link bashrc to ~/.bashrc (DRY RUN: create)
+[2/2] Link vimrc to ~/.vimrc (DRY RUN: create)
+[2/2] Run sh script.sh arg1 linux (DRY RUN)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_config_env_config_path() {
+    let (dirs, mut cmd) = setup_e2e_local("test_config_env_config_path");
+    write_file(&dirs.local.join("custom.toml"), "hots = \"laptop\"\n");
+    cmd.env("COLIRU_CONFIG", "custom.toml");
+    cmd.args(["manifest.yml"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(stderr.starts_with("Error: Failed to parse custom.toml: "),
+               true);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_config_replaced_files_quarantine() {
+    let (dirs, mut cmd) = setup_e2e_local(
+        "test_config_replaced_files_quarantine");
+    write_config(&dirs, "replaced_files = \"quarantine\"\n");
+    write_file(&dirs.home.join(".gitconfig"), "git #0\n");
+    cmd.args(["manifest.yml", "-t", "linux", "--force"]);
+
+    let (_, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(exitcode, Some(0));
+
+    // Assert the replaced file was moved into the quarantine directory
+    let quarantine = dirs.home.join(".local/state/coliru/quarantine");
+    let moved = std::fs::read_dir(&quarantine).unwrap().next().unwrap()
+        .unwrap().path().join(dirs.home.strip_prefix("/").unwrap())
+        .join(".gitconfig");
+    assert_eq!(read_file(&moved), "git #0\n");
+    assert_eq!(read_file(&dirs.home.join(".gitconfig")), "git #1\n");
+}
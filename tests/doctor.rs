@@ -0,0 +1,59 @@
+//! End to end tests that test the doctor subcommand
+
+mod test_utils;
+
+use regex::Regex;
+use test_utils::*;
+use std::fs::remove_file;
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_doctor_healthy() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_doctor_healthy");
+    cmd.args(["doctor", "manifest.yml", "-t", "linux"]);
+
+    let expected = Regex::new("^\\[(ok|warning)\\] ssh: .*
+(  Fix: .*
+)?\\[(ok|warning)\\] scp: .*
+(  Fix: .*
+)?\\[ok\\] links: symbolic links are supported
+\\[ok\\] manifest: manifest.yml is valid \\(2 matching steps\\)
+\\[ok\\] sources: all source files exist
+\\[ok\\] destinations: all destinations are writable
+$").unwrap();
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert!(expected.is_match(&stdout), "{}", stdout);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_doctor_missing_source() {
+    let (dirs, mut cmd) = setup_e2e_local("test_doctor_missing_source");
+    remove_file(dirs.local.join("vimrc")).unwrap();
+    cmd.args(["doctor", "manifest.yml", "-t", "linux"]);
+
+    let expected = "\
+[error] sources: missing vimrc
+  Fix: Create the missing files or fix their paths, which are relative to \
+the manifest
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(stdout.contains(expected), true, "{}", stdout);
+    assert_eq!(exitcode, Some(1));
+}
+
+#[test]
+fn test_doctor_invalid_manifest() {
+    let (dirs, mut cmd) = setup_e2e_local("test_doctor_invalid_manifest");
+    write_file(&dirs.local.join("manifest.yml"), "steps: {}");
+    cmd.args(["doctor", "manifest.yml"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(stdout.contains("[error] manifest: manifest.yml is invalid"),
+               true);
+    assert_eq!(exitcode, Some(1));
+}
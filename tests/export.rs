@@ -0,0 +1,81 @@
+//! End to end tests that test the export subcommand
+
+mod test_utils;
+
+use test_utils::*;
+use std::process::Command;
+
+#[test]
+fn test_export_ansible() {
+    let (dirs, mut cmd) = setup_e2e_local("test_export_ansible");
+    write_file(&dirs.local.join("export.yml"), "\
+steps:
+  - copy: [ { src: gitconfig, dst: ~/.gitconfig } ]
+    tags: [ linux ]
+  - copy: [ { src: vimrc, dst: ~/_vimrc } ]
+    tags: [ windows ]
+");
+    cmd.args(["export", "ansible", "export.yml", "-t", "linux"]);
+
+    let expected = format!("\
+# Generated by coliru export ansible
+- name: Install dotfiles
+  hosts: all
+  tasks:
+  - name: Copy gitconfig to ~/.gitconfig
+    ansible.builtin.copy:
+      src: {}
+      dest: ~/.gitconfig
+      mode: preserve
+    tags:
+    - linux
+", dirs.local.join("gitconfig").display());
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, &expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_export_ansible_missing_manifest() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_export_ansible_missing");
+    cmd.args(["export", "ansible", "missing.yml"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(stderr.starts_with("Error: Failed to parse missing.yml"), true);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(3));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_export_shell_install() {
+    let (dirs, mut cmd) = setup_e2e_local("test_export_shell_install");
+    cmd.args(["export", "shell", "manifest.yml", "-t", "linux"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(exitcode, Some(0));
+    write_file(&dirs.local.join("install.sh"), &stdout);
+
+    let mut cmd = Command::new("sh");
+    cmd.arg(dirs.local.join("install.sh")).env("HOME", &dirs.home);
+
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig
+[2/2] Copy foo to foo
+[2/2] Link bashrc to ~/.bashrc
+[2/2] Link vimrc to ~/.vimrc
+[2/2] Run sh script.sh arg1 linux
+foo!
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.home.join(".gitconfig")), "git #1\n");
+    assert_eq!(read_file(&dirs.home.join(".bashrc")), "bash #1\n");
+    assert_eq!(dirs.home.join(".bashrc").is_symlink(), true);
+    assert_eq!(read_file(&dirs.local.join("log.txt")),
+               "script.sh called with arg1 linux\n");
+}
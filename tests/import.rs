@@ -0,0 +1,54 @@
+//! End to end tests that test the import subcommand
+
+mod test_utils;
+
+use test_utils::*;
+use std::fs::create_dir_all;
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_import_chezmoi_install() {
+    let (dirs, mut cmd) = setup_e2e_local("test_import_chezmoi_install");
+    create_dir_all(dirs.local.join("chezmoi/dot_config/git")).unwrap();
+    write_file(&dirs.local.join("chezmoi/dot_bashrc"), "bash #3\n");
+    write_file(&dirs.local.join("chezmoi/dot_config/git/config.tmpl"),
+               "email = {{ .email }}\n");
+    write_file(&dirs.local.join("chezmoi/symlink_dot_vimrc"), ".vim/vimrc");
+    cmd.args(["import", "chezmoi", "chezmoi", "--output", "imported"]);
+
+    let expected = "\
+Created imported/manifest.yml
+Review config/git/config (template)
+Skipped symlink_dot_vimrc (symlinks aren't supported)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["imported/manifest.yml"]);
+
+    let expected = "\
+[1/1] Copy bashrc to ~/.bashrc (created)
+[1/1] Copy config/git/config to ~/.config/git/config (created)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.home.join(".bashrc")), "bash #3\n");
+}
+
+#[test]
+fn test_import_chezmoi_missing() {
+    let (dirs, mut cmd) = setup_e2e_local("test_import_chezmoi_missing");
+    cmd.args(["import", "chezmoi", "missing", "-o", "imported"]);
+
+    let expected = "Error: missing is not a directory\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+    assert_eq!(dirs.local.join("imported").exists(), false);
+}
@@ -0,0 +1,52 @@
+//! End to end tests that test the init subcommand
+
+mod test_utils;
+
+use test_utils::*;
+use std::fs::remove_file;
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_init_install() {
+    let (dirs, mut cmd) = setup_e2e_local("test_init_install");
+    remove_file(dirs.local.join("manifest.yml")).unwrap();
+    cmd.args(["init"]);
+
+    let expected = "\
+Created ./manifest.yml
+Skipped foo (unrecognized)
+Skipped script.bat (unrecognized)
+Skipped script.sh (unrecognized)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["manifest.yml", "-t", "linux"]);
+
+    let expected = "\
+[1/2] Link gitconfig to ~/.gitconfig (created)
+[2/2] Link bashrc to ~/.bashrc (created)
+[2/2] Link vimrc to ~/.vimrc (created)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.home.join(".bashrc")), "bash #1\n");
+}
+
+#[test]
+fn test_init_existing() {
+    let (dirs, mut cmd) = setup_e2e_local("test_init_existing");
+    cmd.args(["init", "."]);
+
+    let expected = "Error: ./manifest.yml already exists\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+    assert_eq!(read_file(&dirs.local.join("manifest.yml")).is_empty(), false);
+}
@@ -0,0 +1,48 @@
+//! End to end tests that test the lint subcommand
+
+mod test_utils;
+
+use test_utils::*;
+
+#[test]
+fn test_lint_clean() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_lint_clean");
+    cmd.args(["lint", "manifest.yml"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "No problems found\n");
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_lint_problems() {
+    let (dirs, mut cmd) = setup_e2e_local("test_lint_problems");
+    cmd.args(["lint", "lint.yml"]);
+    write_file(&dirs.local.join("lint.yml"), "\
+steps:
+  - copy:
+    - { src: gitconfig, dst: ~/.gitconfig }
+    - { src: missing, dst: ~/missing }
+    tags: [ linux, macos ]
+  - link: [ { src: bashrc, dst: ~/.gitconfig } ]
+    run: [ { src: script.sh } ]
+    tags: [ linux, macos ]
+  - tags: [ macso ]
+");
+
+    let expected = "\
+[error] step 1: source missing doesn't exist
+[warning] step 2: script script.sh isn't executable, so it needs a prefix \
+(e.g. sh) or to be made executable with chmod +x
+[error] step 2: destination ~/.gitconfig is also installed by step 1
+[warning] step 3: step doesn't have any commands
+[warning] step 3: tag macso isn't used by any other step (did you mean macos?)
+Found 2 errors and 3 warnings
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(1));
+}
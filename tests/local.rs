@@ -3,7 +3,7 @@
 mod test_utils;
 
 use test_utils::*;
-use std::fs::remove_file;
+use std::fs::{File, remove_file};
 
 #[test]
 #[cfg(target_family = "unix")]
@@ -12,12 +12,12 @@ fn test_local_standard() {
     cmd.args(["manifest.yml", "-t", "linux"]);
 
     let expected = "\
-[1/2] Copy gitconfig to ~/.gitconfig
-[2/2] Copy foo to foo
-[2/2] Link bashrc to ~/.bashrc
-[2/2] Link vimrc to ~/.vimrc
+[1/2] Copy gitconfig to ~/.gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link bashrc to ~/.bashrc (created)
+[2/2] Link vimrc to ~/.vimrc (created)
 [2/2] Run sh script.sh arg1 linux
-foo!
+  foo!
 ";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -49,11 +49,11 @@ fn test_local_standard() {
     cmd.args(["manifest.yml", "-t", "windows"]);
 
     let expected = "\
-[1/2] Copy gitconfig to .gitconfig
-[2/2] Copy foo to foo
-[2/2] Link vimrc to _vimrc
+[1/2] Copy gitconfig to .gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link vimrc to _vimrc (created)
 [2/2] Run  script.bat arg1 windows
-foo!\r
+  foo!\r
 ";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -84,11 +84,11 @@ fn test_local_run_alternate_tag_rules_1() {
     cmd.args(["manifest.yml", "-t", "linux", "^windows"]);
 
     let expected = "\
-[1/1] Copy foo to foo
-[1/1] Link bashrc to ~/.bashrc
-[1/1] Link vimrc to ~/.vimrc
+[1/1] Copy foo to foo (unchanged)
+[1/1] Link bashrc to ~/.bashrc (created)
+[1/1] Link vimrc to ~/.vimrc (created)
 [1/1] Run sh script.sh arg1 linux ^windows
-foo!
+  foo!
 ";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -119,12 +119,12 @@ fn test_local_run_alternate_tag_rules_2() {
     cmd.args(["manifest.yml", "-t", "macos"]);
 
     let expected = "\
-[1/2] Copy gitconfig to ~/.gitconfig
-[2/2] Copy foo to foo
-[2/2] Link bashrc to ~/.bashrc
-[2/2] Link vimrc to ~/.vimrc
+[1/2] Copy gitconfig to ~/.gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link bashrc to ~/.bashrc (created)
+[2/2] Link vimrc to ~/.vimrc (created)
 [2/2] Run sh script.sh arg1 macos
-foo!
+  foo!
 ";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -149,6 +149,120 @@ foo!
     assert_eq!(log_contents, "script.sh called with arg1 macos\n");
 }
 
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_run_tag_rule_expression() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_run_tag_rule_expression");
+    cmd.args(["manifest.yml", "-t", "(linux || macos) && !windows"]);
+
+    let expected = "\
+[1/1] Copy foo to foo (unchanged)
+[1/1] Link bashrc to ~/.bashrc (created)
+[1/1] Link vimrc to ~/.vimrc (created)
+[1/1] Run sh script.sh arg1 '(linux || macos) && !windows'
+  foo!
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    let log_contents = read_file(&dirs.local.join("log.txt"));
+    assert_eq!(log_contents,
+               "script.sh called with arg1 (linux || macos) && !windows\n");
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_tag_rules_file() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_tag_rules_file");
+    cmd.args(["manifest.yml", "--tag-rules-file", "rules", "-t", "^windows"]);
+    write_file(&dirs.local.join("rules"), "# Tag rules\nlinux\n");
+
+    let expected = "\
+[1/1] Copy foo to foo (unchanged)
+[1/1] Link bashrc to ~/.bashrc (created)
+[1/1] Link vimrc to ~/.vimrc (created)
+[1/1] Run sh script.sh arg1 linux ^windows
+  foo!
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_local_tag_rules_file_missing() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_local_tag_rules_file_missing");
+    cmd.args(["manifest.yml", "--tag-rules-file", "missing"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(stderr.starts_with("Error: Failed to read missing: "), true);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(4));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_exclude() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_exclude");
+    cmd.args(["manifest.yml", "-t", "linux", "--exclude", "~/.vim*",
+              "--exclude", "*.sh"]);
+
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link bashrc to ~/.bashrc (created)
+[2/2] Link vimrc to ~/.vimrc (skipped: excluded by ~/.vim*)
+[2/2] Run sh script.sh arg1 linux (skipped: excluded by *.sh)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout.replace(&dirs.home.to_string_lossy().to_string(), "~"),
+               expected);
+    assert_eq!(exitcode, Some(0));
+
+    // Assert excluded commands are skipped
+    let bash_exists = dirs.home.join(".bashrc").exists();
+    let vim_exists = dirs.home.join(".vimrc").exists();
+    let log_exists = dirs.local.join("log.txt").exists();
+    assert_eq!(bash_exists, true);
+    assert_eq!(vim_exists, false);
+    assert_eq!(log_exists, false);
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_reinstall() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_reinstall");
+    cmd.args(["manifest.yml", "-t", "linux"]);
+    run_command(&mut cmd);
+    write_file(&dirs.home.join(".gitconfig"), "git #0\n");
+    remove_file(dirs.home.join(".vimrc")).unwrap();
+    write_file(&dirs.home.join(".vimrc"), "vim #1\n");
+
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig (updated)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link bashrc to ~/.bashrc (unchanged)
+[2/2] Link vimrc to ~/.vimrc (updated)
+[2/2] Run sh script.sh arg1 linux
+  foo!
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    // Assert files are correctly copied/linked
+    let git_contents = read_file(&dirs.home.join(".gitconfig"));
+    write_file(&dirs.local.join("vimrc"), "vim #2\n");
+    let vim_contents = read_file(&dirs.home.join(".vimrc"));
+    assert_eq!(git_contents, "git #1\n");
+    assert_eq!(vim_contents, "vim #2\n");
+}
+
 #[test]
 #[cfg(target_family = "unix")]
 fn test_local_dry_run() {
@@ -156,10 +270,10 @@ fn test_local_dry_run() {
     cmd.args(["manifest.yml", "--dry-run", "-t", "linux"]);
 
     let expected = "\
-[1/2] Copy gitconfig to ~/.gitconfig (DRY RUN)
-[2/2] Copy foo to foo (DRY RUN)
-[2/2] Link bashrc to ~/.bashrc (DRY RUN)
-[2/2] Link vimrc to ~/.vimrc (DRY RUN)
+[1/2] Copy gitconfig to ~/.gitconfig (DRY RUN: create)
+[2/2] Copy foo to foo (DRY RUN: no change)
+[2/2] Link bashrc to ~/.bashrc (DRY RUN: create)
+[2/2] Link vimrc to ~/.vimrc (DRY RUN: create)
 [2/2] Run sh script.sh arg1 linux (DRY RUN)
 ";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
@@ -182,6 +296,71 @@ fn test_local_dry_run() {
     assert_eq!(log_exists, false);
 }
 
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_dry_run_diff() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_dry_run_diff");
+    cmd.args(["manifest.yml", "--dry-run", "--diff", "-t", "linux"]);
+    write_file(&dirs.home.join(".gitconfig"), "git #0\n");
+    write_file(&dirs.home.join(".bashrc"), "bash #1\n");
+
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig (DRY RUN: update (contents differ))
+--- ~/.gitconfig
++++ gitconfig
+@@ -1 +1 @@
+-git #0
++git #1
+[2/2] Copy foo to foo (DRY RUN: no change)
+[2/2] Link bashrc to ~/.bashrc (DRY RUN: replace file)
+[2/2] Link vimrc to ~/.vimrc (DRY RUN: create)
+[2/2] Run sh script.sh arg1 linux (DRY RUN)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    // Assert existing files are unchanged
+    let git_contents = read_file(&dirs.home.join(".gitconfig"));
+    assert_eq!(git_contents, "git #0\n");
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_show_diff() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_show_diff");
+    cmd.args(["manifest.yml", "--show-diff", "--force", "-t", "linux"]);
+    write_file(&dirs.home.join(".gitconfig"), "git #0\n");
+    write_file(&dirs.home.join(".bashrc"), "bash #0\n");
+
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig (updated)
+--- ~/.gitconfig
++++ gitconfig
+@@ -1 +1 @@
+-git #0
++git #1
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link bashrc to ~/.bashrc (updated)
+--- ~/.bashrc
++++ bashrc
+@@ -1 +1 @@
+-bash #0
++bash #1
+[2/2] Link vimrc to ~/.vimrc (created)
+[2/2] Run sh script.sh arg1 linux
+  foo!
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    let git_contents = read_file(&dirs.home.join(".gitconfig"));
+    assert_eq!(git_contents, "git #1\n");
+}
+
 #[test]
 #[cfg(target_family = "windows")]
 fn test_local_dry_run() {
@@ -189,9 +368,9 @@ fn test_local_dry_run() {
     cmd.args(["manifest.yml", "--dry-run", "-t", "windows"]);
 
     let expected = "\
-[1/2] Copy gitconfig to .gitconfig (DRY RUN)
-[2/2] Copy foo to foo (DRY RUN)
-[2/2] Link vimrc to _vimrc (DRY RUN)
+[1/2] Copy gitconfig to .gitconfig (DRY RUN: create)
+[2/2] Copy foo to foo (DRY RUN: no change)
+[2/2] Link vimrc to _vimrc (DRY RUN: create)
 [2/2] Run  script.bat arg1 windows (DRY RUN)
 ";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
@@ -214,6 +393,103 @@ fn test_local_dry_run() {
     assert_eq!(log_exists, false);
 }
 
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_auto_tags() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_local_auto_tags");
+    cmd.args(["manifest.yml", "--dry-run", "--auto-tags"]);
+
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig (DRY RUN: create)
+[2/2] Copy foo to foo (DRY RUN: no change)
+[2/2] Link bashrc to ~/.bashrc (DRY RUN: create)
+[2/2] Link vimrc to ~/.vimrc (DRY RUN: create)
+[2/2] Run sh script.sh arg1  (DRY RUN)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "windows")]
+fn test_local_auto_tags() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_local_auto_tags");
+    cmd.args(["manifest.yml", "--dry-run", "--auto-tags"]);
+
+    let expected = "\
+[1/2] Copy gitconfig to .gitconfig (DRY RUN: create)
+[2/2] Copy foo to foo (DRY RUN: no change)
+[2/2] Link vimrc to _vimrc (DRY RUN: create)
+[2/2] Run  script.bat arg1  (DRY RUN)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_jobs_parallel() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_jobs_parallel");
+    cmd.args(["manifest.yml", "--jobs", "2"]);
+    write_file(&dirs.local.join("slow.sh"), "sleep 1; echo slow >> log.txt");
+    write_file(&dirs.local.join("fast.sh"), "echo fast >> log.txt");
+    write_file(&dirs.local.join("check.sh"), "cat \"$1\"");
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - run: [ { src: slow.sh, prefix: sh } ]
+    parallel: true
+  - run: [ { src: fast.sh, prefix: sh } ]
+    parallel: true
+  - run: [ { src: check.sh, prefix: sh, postfix: log.txt } ]
+");
+
+    // Parallel steps may complete in any order, but the next step that isn't
+    // parallel waits for both of them
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(stdout.ends_with("\
+[3/3] Run sh check.sh log.txt
+  fast
+  slow
+"), true);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_jobs() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_jobs");
+    cmd.args(["manifest.yml", "-t", "linux", "--jobs", "2"]);
+
+    // Steps that aren't parallel are still executed in order
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link bashrc to ~/.bashrc (created)
+[2/2] Link vimrc to ~/.vimrc (created)
+[2/2] Run sh script.sh arg1 linux
+  foo!
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    // Assert files are correctly copied/linked/run
+    write_file(&dirs.local.join("bashrc"), "bash #2\n");
+    write_file(&dirs.local.join("gitconfig"), "git #2\n");
+    let bash_contents = read_file(&dirs.home.join(".bashrc"));
+    let git_contents = read_file(&dirs.home.join(".gitconfig"));
+    let log_contents = read_file(&dirs.local.join("log.txt"));
+    assert_eq!(bash_contents, "bash #2\n");
+    assert_eq!(git_contents, "git #1\n");
+    assert_eq!(log_contents, "script.sh called with arg1 linux\n");
+}
+
 #[test]
 #[cfg(target_family = "unix")]
 fn test_local_copy() {
@@ -221,12 +497,12 @@ fn test_local_copy() {
     cmd.args(["manifest.yml", "--copy", "-t", "linux"]);
 
     let expected = "\
-[1/2] Copy gitconfig to ~/.gitconfig
-[2/2] Copy foo to foo
-[2/2] Copy bashrc to ~/.bashrc
-[2/2] Copy vimrc to ~/.vimrc
+[1/2] Copy gitconfig to ~/.gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Copy bashrc to ~/.bashrc (created)
+[2/2] Copy vimrc to ~/.vimrc (created)
 [2/2] Run sh script.sh arg1 linux
-foo!
+  foo!
 ";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -251,6 +527,114 @@ foo!
     assert_eq!(log_contents, "script.sh called with arg1 linux\n");
 }
 
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_link_mode() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_link_mode");
+    cmd.args(["manifest.yml", "--link-mode", "hardlink", "-t", "linux"]);
+    let manifest = read_file(&dirs.local.join("manifest.yml"));
+    write_file(&dirs.local.join("manifest.yml"), &manifest.replace("\
+      dst: ~/.vimrc
+", "\
+      dst: ~/.vimrc
+      type: copy
+"));
+
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Hard link bashrc to ~/.bashrc (created)
+[2/2] Copy vimrc to ~/.vimrc (created)
+[2/2] Run sh script.sh arg1 linux
+  foo!
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    // Assert files are correctly hard linked/copied
+    write_file(&dirs.local.join("bashrc"), "bash #2\n");
+    write_file(&dirs.local.join("vimrc"), "vim #2\n");
+    let bash_contents = read_file(&dirs.home.join(".bashrc"));
+    let bash_is_symlink = dirs.home.join(".bashrc").is_symlink();
+    let vim_contents = read_file(&dirs.home.join(".vimrc"));
+    assert_eq!(bash_contents, "bash #2\n");
+    assert_eq!(bash_is_symlink, false);
+    assert_eq!(vim_contents, "vim #1\n");
+
+    // Assert hard links that are already installed are left alone
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig (unchanged)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Hard link bashrc to ~/.bashrc (unchanged)
+[2/2] Copy vimrc to ~/.vimrc (updated)
+[2/2] Run sh script.sh arg1 linux
+  foo!
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_preserve_mtime() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_preserve_mtime");
+    cmd.args(["manifest.yml", "--copy", "-t", "linux"]);
+    let manifest = read_file(&dirs.local.join("manifest.yml"));
+    write_file(&dirs.local.join("manifest.yml"), &manifest.replace("\
+      dst: ~/.gitconfig
+", "\
+      dst: ~/.gitconfig
+      preserve_mtime: true
+"));
+    let mtime = std::time::UNIX_EPOCH + std::time::Duration::from_secs(
+        1728561845);
+    for name in ["gitconfig", "bashrc"] {
+        let file = std::fs::File::options().write(true)
+            .open(dirs.local.join(name)).unwrap();
+        file.set_modified(mtime).unwrap();
+    }
+
+    let (_, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(exitcode, Some(0));
+
+    // Assert only the modification time of gitconfig is preserved
+    let git_mtime = std::fs::metadata(dirs.home.join(".gitconfig")).unwrap()
+        .modified().unwrap();
+    let bash_mtime = std::fs::metadata(dirs.home.join(".bashrc")).unwrap()
+        .modified().unwrap();
+    assert_eq!(git_mtime, mtime);
+    assert_ne!(bash_mtime, mtime);
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_relative_links() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_relative_links");
+    cmd.args(["manifest.yml", "--relative-links", "-t", "linux"]);
+
+    let (_, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(exitcode, Some(0));
+
+    // Assert links have relative targets
+    write_file(&dirs.local.join("bashrc"), "bash #2\n");
+    let target = std::fs::read_link(dirs.home.join(".bashrc")).unwrap();
+    let bash_contents = read_file(&dirs.home.join(".bashrc"));
+    assert_eq!(target.is_relative(), true);
+    assert_eq!(bash_contents, "bash #2\n");
+
+    // Assert relative links that are already installed are left alone
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(stdout.contains("Link bashrc to ~/.bashrc (unchanged)"), true);
+    assert_eq!(exitcode, Some(0));
+}
+
 #[test]
 #[cfg(target_family = "windows")]
 fn test_local_copy() {
@@ -258,11 +642,11 @@ fn test_local_copy() {
     cmd.args(["manifest.yml", "--copy", "-t", "windows"]);
 
     let expected = "\
-[1/2] Copy gitconfig to .gitconfig
-[2/2] Copy foo to foo
-[2/2] Copy vimrc to _vimrc
+[1/2] Copy gitconfig to .gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Copy vimrc to _vimrc (created)
 [2/2] Run  script.bat arg1 windows
-foo!\r
+  foo!\r
 ";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -288,96 +672,399 @@ foo!\r
 
 #[test]
 #[cfg(target_family = "unix")]
-fn test_local_run_failure() {
-    let (dirs, mut cmd) = setup_e2e_local("test_local_run_failure");
+fn test_local_hooks() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_hooks");
     cmd.args(["manifest.yml", "-t", "linux"]);
-    write_file(&dirs.local.join("script.sh"), "exit 1");
+    let manifest = read_file(&dirs.local.join("manifest.yml"));
+    write_file(&dirs.local.join("manifest.yml"), &format!("\
+pre_install:
+  - echo pre
+post_install:
+  - echo post
+  - test -f ~/.bashrc && echo installed
+{}", manifest));
 
-    let expected_stdout = "\
-[1/2] Copy gitconfig to ~/.gitconfig
-[2/2] Copy foo to foo
-[2/2] Link bashrc to ~/.bashrc
-[2/2] Link vimrc to ~/.vimrc
+    let expected = "\
+[pre_install] Run echo pre
+  pre
+[1/2] Copy gitconfig to ~/.gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link bashrc to ~/.bashrc (created)
+[2/2] Link vimrc to ~/.vimrc (created)
 [2/2] Run sh script.sh arg1 linux
+  foo!
+[post_install] Run echo post
+  post
+[post_install] Run test -f ~/.bashrc && echo installed
+  installed
 ";
-    let expected_stderr = "  Error: Process terminated unsuccessfully: \
-                           exit status: 1\n";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
-    assert_eq!(&stderr, expected_stderr);
-    assert_eq!(&stdout, expected_stdout);
-    assert_eq!(exitcode, Some(1));
-
-    // Assert files are correctly copied/linked/run
-    write_file(&dirs.local.join("bashrc"), "bash #2\n");
-    write_file(&dirs.local.join("gitconfig"), "git #2\n");
-    write_file(&dirs.local.join("vimrc"), "vim #2\n");
-    let bash_contents = read_file(&dirs.home.join(".bashrc"));
-    let git_contents = read_file(&dirs.home.join(".gitconfig"));
-    let vim1_contents = read_file(&dirs.home.join(".vimrc"));
-    let vim2_exists = dirs.home.join("_vimrc").exists();
-    let foo_contents = read_file(&dirs.local.join("foo"));
-    assert_eq!(bash_contents, "bash #2\n");
-    assert_eq!(git_contents, "git #1\n");
-    assert_eq!(vim1_contents, "vim #2\n");
-    assert_eq!(vim2_exists, false);
-    assert_eq!(foo_contents, "foo!\n");
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
 }
 
 #[test]
-#[cfg(target_family = "windows")]
-fn test_local_run_failure() {
-    let (dirs, mut cmd) = setup_e2e_local("test_local_run_failure");
-    cmd.args(["manifest.yml", "-t", "windows"]);
-    write_file(&dirs.local.join("script.bat"), "@echo off\r\nexit 1");
+#[cfg(target_family = "unix")]
+fn test_local_hook_failure() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_hook_failure");
+    cmd.args(["manifest.yml", "-t", "linux"]);
+    let manifest = read_file(&dirs.local.join("manifest.yml"));
+    write_file(&dirs.local.join("manifest.yml"), &format!("\
+pre_install:
+  - exit 1
+  - echo unreachable
+{}", manifest));
 
-    let expected_stdout = "\
-[1/2] Copy gitconfig to .gitconfig
-[2/2] Copy foo to foo
-[2/2] Link vimrc to _vimrc
-[2/2] Run  script.bat arg1 windows
-";
-    let expected_stderr = "  Error: Process terminated unsuccessfully: \
-                           exit code: 1\n";
+    let expected_stdout = "[pre_install] Run exit 1\n";
+    let expected_stderr = "Error: Failed to run pre_install hook: Process \
+                           terminated unsuccessfully: exit status: 1\n";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, expected_stderr);
     assert_eq!(&stdout, expected_stdout);
-    assert_eq!(exitcode, Some(1));
+    assert_eq!(exitcode, Some(2));
 
-    // Assert files are correctly copied/linked/run
-    write_file(&dirs.local.join("gitconfig"), "git #2\r\n");
-    write_file(&dirs.local.join("vimrc"), "vim #2\r\n");
-    let bash_exists = dirs.local.join(".bashrc").exists();
-    let git_contents = read_file(&dirs.local.join(".gitconfig"));
-    let vim1_exists = dirs.local.join(".vimrc").exists();
-    let vim2_contents = read_file(&dirs.local.join("_vimrc"));
-    let foo_contents = read_file(&dirs.local.join("foo"));
-    assert_eq!(bash_exists, false);
-    assert_eq!(git_contents, "git #1\r\n");
-    assert_eq!(vim1_exists, false);
-    assert_eq!(vim2_contents, "vim #2\r\n");
-    assert_eq!(foo_contents, "foo!\r\n");
+    // Assert no steps were executed
+    let git_exists = dirs.home.join(".gitconfig").exists();
+    assert_eq!(git_exists, false);
 }
 
 #[test]
 #[cfg(target_family = "unix")]
-fn test_local_missing_file() {
-    let (dirs, mut cmd) = setup_e2e_local("test_local_missing_file");
+fn test_local_host_overrides() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_host_overrides");
     cmd.args(["manifest.yml", "-t", "linux"]);
-    remove_file(&dirs.local.join("gitconfig")).unwrap();
+    let hostname = gethostname::gethostname().to_string_lossy().to_string();
+    let manifest = read_file(&dirs.local.join("manifest.yml"));
+    write_file(&dirs.local.join("manifest.yml"), &format!("{}
+hosts:
+  {}:
+    dst:
+      ~/.gitconfig: ~/.gitconfig-host
+    steps:
+      - copy:
+        - src: vimrc
+          dst: ~/.vimrc-host
+        tags: [ linux ]
+  not-{}:
+    steps:
+      - copy: [ {{ src: bashrc, dst: ~/.bashrc-other }} ]
+", manifest, hostname, hostname));
 
-    let expected_stdout = "\
-[1/2] Copy gitconfig to ~/.gitconfig
-[2/2] Copy foo to foo
-[2/2] Link bashrc to ~/.bashrc
-[2/2] Link vimrc to ~/.vimrc
-[2/2] Run sh script.sh arg1 linux
-foo!
+    let expected = "\
+[1/3] Copy gitconfig to ~/.gitconfig-host (created)
+[2/3] Copy foo to foo (unchanged)
+[2/3] Link bashrc to ~/.bashrc (created)
+[2/3] Link vimrc to ~/.vimrc (created)
+[2/3] Run sh script.sh arg1 linux
+  foo!
+[3/3] Copy vimrc to ~/.vimrc-host (created)
 ";
-    let expected_stderr = "  Error: No such file or directory (os error 2)\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    // Assert overrides are applied
+    let git1_exists = dirs.home.join(".gitconfig").exists();
+    let git2_contents = read_file(&dirs.home.join(".gitconfig-host"));
+    let vim_contents = read_file(&dirs.home.join(".vimrc-host"));
+    let bash_exists = dirs.home.join(".bashrc-other").exists();
+    assert_eq!(git1_exists, false);
+    assert_eq!(git2_contents, "git #1\n");
+    assert_eq!(vim_contents, "vim #1\n");
+    assert_eq!(bash_exists, false);
+}
+
+#[test]
+fn test_local_host_vars() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_host_vars");
+    cmd.args(["manifest.yml"]);
+    let hostname = gethostname::gethostname().to_string_lossy().to_string();
+    write_file(&dirs.local.join("gitconfig"),
+               "email = {{ email }}\neditor = {{editor}}\n");
+    write_file(&dirs.local.join("manifest.yml"), &format!("\
+steps:
+  - copy: [ {{ src: gitconfig, dst: ~/.gitconfig, template: true }} ]
+vars:
+  email: me@example.com
+  editor: vim
+hosts:
+  {}:
+    vars:
+      email: me@work.example.com
+", hostname));
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "[1/1] Copy gitconfig to ~/.gitconfig (created)\n");
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.home.join(".gitconfig")),
+               "email = me@work.example.com\neditor = vim\n");
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_run_facts() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_run_facts");
+    cmd.args(["manifest.yml", "-t", "linux", "^windows"]);
+    let manifest = read_file(&dirs.local.join("manifest.yml"));
+    write_file(&dirs.local.join("manifest.yml"), &manifest.replace(
+        "arg1 $COLIRU_RULES", "$COLIRU_OS $COLIRU_REMOTE $COLIRU_RULES"));
+
+    let expected = format!("\
+[1/1] Copy foo to foo (unchanged)
+[1/1] Link bashrc to ~/.bashrc (created)
+[1/1] Link vimrc to ~/.vimrc (created)
+[1/1] Run sh script.sh {} false linux ^windows
+  foo!
+", std::env::consts::OS);
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, &expected);
+    assert_eq!(exitcode, Some(0));
+
+    // Assert facts are passed to scripts
+    let log_contents = read_file(&dirs.local.join("log.txt"));
+    assert_eq!(log_contents, format!("script.sh called with {} false linux \
+                                      ^windows\n", std::env::consts::OS));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_report() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_report");
+    cmd.args(["manifest.yml", "-t", "linux", "--report", "report.json"]);
+    write_file(&dirs.local.join("script.sh"), "exit 1");
+
+    let (_stdout, _stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(exitcode, Some(6));
+
+    // Assert report is written relative to the original working directory
+    let report = read_file(&dirs.local.join("report.json"));
+    let report: serde_json::Value = serde_json::from_str(&report).unwrap();
+    assert_eq!(report["tag_rules"][0], "linux");
+    assert_eq!(report["steps"][0]["step"], 1);
+    assert_eq!(report["steps"][0]["failure"], serde_json::Value::Null);
+    assert_eq!(report["steps"][1]["tags"][1], "macos");
+    assert_eq!(report["steps"][1]["failure"], "script");
+    assert_eq!(report["steps"][1]["errors"][0],
+               "Process terminated unsuccessfully: exit status: 1");
+    assert_eq!(report["steps"][1]["duration"].is_f64(), true);
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_report_junit() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_report_junit");
+    cmd.args(["manifest.yml", "-t", "linux", "--report", "report.xml"]);
+    write_file(&dirs.local.join("script.sh"), "exit 1");
+
+    let (_stdout, _stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(exitcode, Some(6));
+
+    let report = read_file(&dirs.local.join("report.xml"));
+    assert_eq!(report.contains("tests=\"2\" failures=\"1\""), true);
+    assert_eq!(report.contains("<testcase name=\"[2/2] linux, macos\""), true);
+    assert_eq!(report.contains("<failure type=\"Script\" message=\"Process \
+                                terminated unsuccessfully: exit status: 1\">"),
+               true);
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_run_failure() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_run_failure");
+    cmd.args(["manifest.yml", "-t", "linux"]);
+    write_file(&dirs.local.join("script.sh"), "exit 1");
+
+    let expected_stdout = "\
+[1/2] Copy gitconfig to ~/.gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link bashrc to ~/.bashrc (created)
+[2/2] Link vimrc to ~/.vimrc (created)
+[2/2] Run sh script.sh arg1 linux
+";
+    let expected_stderr = "  Error: Process terminated unsuccessfully: \
+                           exit status: 1\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected_stderr);
+    assert_eq!(&stdout, expected_stdout);
+    assert_eq!(exitcode, Some(6));
+
+    // Assert files are correctly copied/linked/run
+    write_file(&dirs.local.join("bashrc"), "bash #2\n");
+    write_file(&dirs.local.join("gitconfig"), "git #2\n");
+    write_file(&dirs.local.join("vimrc"), "vim #2\n");
+    let bash_contents = read_file(&dirs.home.join(".bashrc"));
+    let git_contents = read_file(&dirs.home.join(".gitconfig"));
+    let vim1_contents = read_file(&dirs.home.join(".vimrc"));
+    let vim2_exists = dirs.home.join("_vimrc").exists();
+    let foo_contents = read_file(&dirs.local.join("foo"));
+    assert_eq!(bash_contents, "bash #2\n");
+    assert_eq!(git_contents, "git #1\n");
+    assert_eq!(vim1_contents, "vim #2\n");
+    assert_eq!(vim2_exists, false);
+    assert_eq!(foo_contents, "foo!\n");
+}
+
+#[test]
+#[cfg(target_family = "windows")]
+fn test_local_run_failure() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_run_failure");
+    cmd.args(["manifest.yml", "-t", "windows"]);
+    write_file(&dirs.local.join("script.bat"), "@echo off\r\nexit 1");
+
+    let expected_stdout = "\
+[1/2] Copy gitconfig to .gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link vimrc to _vimrc (created)
+[2/2] Run  script.bat arg1 windows
+";
+    let expected_stderr = "  Error: Process terminated unsuccessfully: \
+                           exit code: 1\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected_stderr);
+    assert_eq!(&stdout, expected_stdout);
+    assert_eq!(exitcode, Some(6));
+
+    // Assert files are correctly copied/linked/run
+    write_file(&dirs.local.join("gitconfig"), "git #2\r\n");
+    write_file(&dirs.local.join("vimrc"), "vim #2\r\n");
+    let bash_exists = dirs.local.join(".bashrc").exists();
+    let git_contents = read_file(&dirs.local.join(".gitconfig"));
+    let vim1_exists = dirs.local.join(".vimrc").exists();
+    let vim2_contents = read_file(&dirs.local.join("_vimrc"));
+    let foo_contents = read_file(&dirs.local.join("foo"));
+    assert_eq!(bash_exists, false);
+    assert_eq!(git_contents, "git #1\r\n");
+    assert_eq!(vim1_exists, false);
+    assert_eq!(vim2_contents, "vim #2\r\n");
+    assert_eq!(foo_contents, "foo!\r\n");
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_script_timeout() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_script_timeout");
+    cmd.args(["manifest.yml", "-t", "linux", "--script-timeout", "1"]);
+    write_file(&dirs.local.join("script.sh"), "echo slow; sleep 10");
+
+    let expected_stdout = "\
+[1/2] Copy gitconfig to ~/.gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link bashrc to ~/.bashrc (created)
+[2/2] Link vimrc to ~/.vimrc (created)
+[2/2] Run sh script.sh arg1 linux
+  slow
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "  Error: Timed out after 1s\n");
+    assert_eq!(&stdout, expected_stdout);
+    assert_eq!(exitcode, Some(6));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_run_retries() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_run_retries");
+    cmd.args(["manifest.yml"]);
+    write_file(&dirs.local.join("flaky.sh"), "\
+if [ -f attempted ]; then echo downloaded; else touch attempted; exit 1; fi
+");
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - run:
+    - { src: flaky.sh, prefix: sh, retries: 2, retry_delay: 0 }
+");
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "  Retrying (1/2): Process terminated \
+                         unsuccessfully: exit status: 1\n");
+    assert_eq!(&stdout, "[1/1] Run sh flaky.sh \n  downloaded\n");
+    assert_eq!(exitcode, Some(0));
+
+    // Assert scripts that fail every attempt are reported once
+    write_file(&dirs.local.join("flaky.sh"), "exit 3");
+    let expected_stderr = "  Retrying (1/2): Process terminated \
+unsuccessfully: exit status: 3
+  Retrying (2/2): Process terminated unsuccessfully: exit status: 3
+  Error: Process terminated unsuccessfully: exit status: 3
+";
+    let (_, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected_stderr);
+    assert_eq!(exitcode, Some(6));
+}
+
+#[test]
+fn test_local_run_user_dry_run() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_run_user_dry_run");
+    cmd.args(["manifest.yml", "--dry-run"]);
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - run: [ { src: setup.sh, prefix: sh, user: svc } ]
+");
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "[1/1] Run sh setup.sh  as svc (DRY RUN)\n");
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_run_shell() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_run_shell");
+    cmd.args(["manifest.yml"]);
+    write_file(&dirs.local.join("arrays.sh"), "a=(foo bar); echo ${a[1]}\n");
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - run: [ { src: arrays.sh, prefix: ., shell: bash } ]
+");
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "[1/1] Run . arrays.sh \n  bar\n");
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_run_env() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_run_env");
+    cmd.args(["manifest.yml", "--env", "B=it's $HOME"]);
+    write_file(&dirs.local.join("env.sh"), "echo \"$A, $B\"\n");
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - run: [ { src: env.sh, prefix: sh, env: { A: foo, B: bar } } ]
+");
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "[1/1] Run sh env.sh \n  foo, it's $HOME\n");
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_missing_file() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_missing_file");
+    cmd.args(["manifest.yml", "-t", "linux"]);
+    remove_file(dirs.local.join("gitconfig")).unwrap();
+
+    let expected_stdout = "\
+[1/2] Copy gitconfig to ~/.gitconfig (FAILED)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link bashrc to ~/.bashrc (created)
+[2/2] Link vimrc to ~/.vimrc (created)
+[2/2] Run sh script.sh arg1 linux
+  foo!
+";
+    let expected_stderr = "  Error: No such file or directory (os error 2)\n";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, expected_stderr);
     assert_eq!(&stdout, expected_stdout);
-    assert_eq!(exitcode, Some(1));
+    assert_eq!(exitcode, Some(5));
 
     // Assert files are correctly copied/linked/run
     write_file(&dirs.local.join("bashrc"), "bash #2\n");
@@ -402,18 +1089,18 @@ fn test_local_missing_file() {
     remove_file(&dirs.local.join("vimrc")).unwrap();
 
     let expected_stdout = "\
-[1/2] Copy gitconfig to .gitconfig
-[2/2] Copy foo to foo
-[2/2] Link vimrc to _vimrc
+[1/2] Copy gitconfig to .gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link vimrc to _vimrc (FAILED)
 [2/2] Run  script.bat arg1 windows
-foo!\r
+  foo!\r
 ";
     let expected_stderr = "  Error: The system cannot find the file specified. \
                            (os error 2)\n";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, expected_stderr);
     assert_eq!(&stdout, expected_stdout);
-    assert_eq!(exitcode, Some(1));
+    assert_eq!(exitcode, Some(5));
 
     // Assert files are correctly copied/linked/run
     write_file(&dirs.local.join("gitconfig"), "git #2\r\n");
@@ -431,16 +1118,16 @@ foo!\r
 #[cfg(target_family = "unix")]
 fn test_local_relative_manifest() {
     let (dirs, mut cmd) = setup_e2e_local("test_local_relative_manifest");
-    cmd.current_dir(&dirs.local.parent().unwrap());
+    cmd.current_dir(dirs.local.parent().unwrap());
     cmd.args(["test_local_relative_manifest/manifest.yml", "-t", "linux"]);
 
     let expected = "\
-[1/2] Copy gitconfig to ~/.gitconfig
-[2/2] Copy foo to foo
-[2/2] Link bashrc to ~/.bashrc
-[2/2] Link vimrc to ~/.vimrc
+[1/2] Copy gitconfig to ~/.gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link bashrc to ~/.bashrc (created)
+[2/2] Link vimrc to ~/.vimrc (created)
 [2/2] Run sh script.sh arg1 linux
-foo!
+  foo!
 ";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -473,11 +1160,11 @@ fn test_local_different_cwd() {
     cmd.args(["test_local_different_cwd/manifest.yml", "-t", "windows"]);
 
     let expected = "\
-[1/2] Copy gitconfig to .gitconfig
-[2/2] Copy foo to foo
-[2/2] Link vimrc to _vimrc
+[1/2] Copy gitconfig to .gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link vimrc to _vimrc (created)
 [2/2] Run  script.bat arg1 windows
-foo!\r
+  foo!\r
 ";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -500,3 +1187,801 @@ foo!\r
     assert_eq!(foo_contents, "foo!\r\n");
     assert_eq!(log_contents, "script.bat called with arg1 windows \r\n");
 }
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_fix_links() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_fix_links");
+    cmd.args(["manifest.yml", "--fix-links", "-t", "linux"]);
+    let bashrc = dirs.home.join(".bashrc");
+    std::os::unix::fs::symlink("/old/dotfiles/bashrc", &bashrc).unwrap();
+
+    let expected = "\
+[2/2] Link bashrc to ~/.bashrc (fixed, was /old/dotfiles/bashrc)
+[2/2] Link vimrc to ~/.vimrc (skipped: missing)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    // Assert only broken links are re-created
+    let bash_contents = read_file(&bashrc);
+    assert_eq!(bash_contents, "bash #1\n");
+    assert_eq!(dirs.home.join(".gitconfig").exists(), false);
+    assert_eq!(dirs.home.join(".vimrc").exists(), false);
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_unmanaged_file() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_unmanaged_file");
+    cmd.args(["manifest.yml", "-t", "linux"]);
+    write_file(&dirs.home.join(".gitconfig"), "git #0\n");
+
+    let expected_stdout = "\
+[1/2] Copy gitconfig to ~/.gitconfig (FAILED)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link bashrc to ~/.bashrc (created)
+[2/2] Link vimrc to ~/.vimrc (created)
+[2/2] Run sh script.sh arg1 linux
+  foo!
+";
+    let expected_stderr = "  Error: ~/.gitconfig already exists and wasn't \
+                           installed by coliru (use --force to replace it)\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected_stderr);
+    assert_eq!(&stdout, expected_stdout);
+    assert_eq!(exitcode, Some(5));
+    assert_eq!(read_file(&dirs.home.join(".gitconfig")), "git #0\n");
+
+    // Assert unmanaged files are replaced with --force
+    cmd.arg("--force");
+    let (_, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.home.join(".gitconfig")), "git #1\n");
+
+    // Assert files installed by coliru are replaced without --force
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["manifest.yml", "-t", "linux"]);
+    write_file(&dirs.home.join(".gitconfig"), "git #2\n");
+    let (_, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.home.join(".gitconfig")), "git #1\n");
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_xdg_placeholders() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_xdg_placeholders");
+    cmd.args(["manifest.yml"]);
+    cmd.env("XDG_CONFIG_HOME", dirs.local.join("config"));
+    cmd.env_remove("XDG_DATA_HOME");
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - copy:
+    - src: gitconfig
+      dst: '{XDG_CONFIG_HOME}/git/config'
+    - src: bashrc
+      dst: '{XDG_DATA_HOME}/bashrc'
+");
+
+    let (_, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(exitcode, Some(0));
+
+    let git_contents = read_file(&dirs.local.join("config").join("git")
+                                 .join("config"));
+    let bash_contents = read_file(&dirs.home.join(".local").join("share")
+                                  .join("bashrc"));
+    assert_eq!(git_contents, read_file(&dirs.local.join("gitconfig")));
+    assert_eq!(bash_contents, read_file(&dirs.local.join("bashrc")));
+}
+
+#[test]
+fn test_local_eol() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_eol");
+    cmd.args(["manifest.yml"]);
+    write_file(&dirs.local.join("unix.txt"), "foo\r\nbar\n");
+    write_file(&dirs.local.join("windows.txt"), "foo\nbar\n");
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - copy:
+    - { src: unix.txt, dst: unix_copy.txt, eol: lf }
+    - { src: windows.txt, dst: windows_copy.txt, eol: crlf }
+");
+
+    let (_, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(exitcode, Some(0));
+
+    let unix_contents = read_file(&dirs.local.join("unix_copy.txt"));
+    let windows_contents = read_file(&dirs.local.join("windows_copy.txt"));
+    assert_eq!(unix_contents, "foo\nbar\n");
+    assert_eq!(windows_contents, "foo\r\nbar\r\n");
+
+    // Assert converted copies that are already installed are left alone
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(stdout, "\
+[1/1] Copy unix.txt to unix_copy.txt (unchanged)
+[1/1] Copy windows.txt to windows_copy.txt (unchanged)
+");
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_local_template() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_template");
+    cmd.args(["manifest.yml"]);
+    write_file(&dirs.local.join("foo"), "os={{ facts.os }}\n\
+                                         remote={{ facts.remote }}\n");
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - copy:
+    - { src: foo, dst: foo_copy, template: true }
+");
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "[1/1] Copy foo to foo_copy (created)\n");
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.local.join("foo_copy")),
+               format!("os={}\nremote=false\n", std::env::consts::OS));
+}
+
+#[test]
+fn test_local_concat() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_concat");
+    cmd.args(["manifest.yml"]);
+    write_file(&dirs.local.join("base"), "[user]\n");
+    write_file(&dirs.local.join("work"), "email = work\n");
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - copy:
+    - { src: [base, work], dst: ~/.gitconfig }
+");
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout,
+               "[1/1] Copy base + work to ~/.gitconfig (created)\n");
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.home.join(".gitconfig")),
+               "[user]\nemail = work\n");
+
+    // Assert concatenated copies that are already installed are left alone
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout,
+               "[1/1] Copy base + work to ~/.gitconfig (unchanged)\n");
+    assert_eq!(exitcode, Some(0));
+
+    // Assert changes to any source file are installed
+    write_file(&dirs.local.join("work"), "email = home\n");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout,
+               "[1/1] Copy base + work to ~/.gitconfig (updated)\n");
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.home.join(".gitconfig")),
+               "[user]\nemail = home\n");
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_block() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_block");
+    cmd.args(["manifest.yml"]);
+    write_file(&dirs.local.join("aliases"), "alias ll='ls -l'\n");
+    write_file(&dirs.home.join(".bashrc"), "export A=1\n");
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - block:
+    - src: aliases
+      dst: ~/.bashrc
+");
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "[1/1] Merge aliases into ~/.bashrc (updated)\n");
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.home.join(".bashrc")), "\
+export A=1
+# BEGIN coliru aliases
+alias ll='ls -l'
+# END coliru aliases
+");
+
+    // Assert the block is updated in place
+    write_file(&dirs.local.join("aliases"), "alias la='ls -a'\n");
+    let bashrc = read_file(&dirs.home.join(".bashrc"));
+    write_file(&dirs.home.join(".bashrc"), &format!("{bashrc}export B=2\n"));
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "[1/1] Merge aliases into ~/.bashrc (updated)\n");
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.home.join(".bashrc")), "\
+export A=1
+# BEGIN coliru aliases
+alias la='ls -a'
+# END coliru aliases
+export B=2
+");
+
+    // Assert blocks that are up to date are left alone
+    let (stdout, _, _) = run_command(&mut cmd);
+    assert_eq!(&stdout, "[1/1] Merge aliases into ~/.bashrc (unchanged)\n");
+}
+
+#[test]
+fn test_local_patch() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_patch");
+    cmd.args(["manifest.yml"]);
+    write_file(&dirs.local.join("config"), "a = 1\nb = 2\nc = 3\n");
+    write_file(&dirs.local.join("config.patch"), "\
+--- a/config
++++ b/config
+@@ -1,3 +1,3 @@
+ a = 1
+-b = 2
++b = 20
+ c = 3
+");
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - patch:
+    - src: config.patch
+      dst: config
+");
+
+    // Assert dry runs check whether the patch applies
+    let mut dry_run_cmd = coliru_command(&dirs);
+    dry_run_cmd.args(["manifest.yml", "--dry-run"]);
+    let (stdout, stderr, exitcode) = run_command(&mut dry_run_cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "[1/1] Patch config with config.patch (DRY RUN: \
+                         update (contents differ))\n");
+    assert_eq!(exitcode, Some(0));
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "[1/1] Patch config with config.patch (updated)\n");
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.local.join("config")), "a = 1\nb = 20\nc = 3\n");
+
+    // Assert patches that are already applied are left alone
+    let (stdout, _, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stdout, "[1/1] Patch config with config.patch (unchanged)\n");
+    assert_eq!(exitcode, Some(0));
+
+    // Assert patches that don't apply are reported
+    write_file(&dirs.local.join("config"), "a = 1\nb = 3\nc = 3\n");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stdout, "[1/1] Patch config with config.patch (FAILED)\n");
+    assert_eq!(&stderr, "  Error: Failed to apply config.patch to config: \
+                         Patch doesn't apply cleanly\n");
+    assert_eq!(exitcode, Some(5));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_json() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_json");
+    cmd.args(["manifest.yml", "-t", "linux", "--exclude", "~/.vim*",
+              "--json"]);
+
+    let expected = "\
+{\"event\":\"action\",\"step\":1,\"label\":\"1/2\",\"description\":\"Copy gitconfig to ~/.gitconfig\"}
+{\"event\":\"outcome\",\"step\":1,\"outcome\":\"created\",\"detail\":null}
+{\"event\":\"action\",\"step\":2,\"label\":\"2/2\",\"description\":\"Copy foo to foo\"}
+{\"event\":\"outcome\",\"step\":2,\"outcome\":\"unchanged\",\"detail\":null}
+{\"event\":\"action\",\"step\":2,\"label\":\"2/2\",\"description\":\"Link bashrc to ~/.bashrc\"}
+{\"event\":\"outcome\",\"step\":2,\"outcome\":\"created\",\"detail\":null}
+{\"event\":\"action\",\"step\":2,\"label\":\"2/2\",\"description\":\"Link vimrc to ~/.vimrc\"}
+{\"event\":\"outcome\",\"step\":2,\"outcome\":\"skipped\",\"detail\":\"~/.vim*\"}
+{\"event\":\"action\",\"step\":2,\"label\":\"2/2\",\"description\":\"Run sh script.sh arg1 linux\"}
+{\"event\":\"outcome\",\"step\":2,\"outcome\":\"pending\",\"detail\":null}
+{\"event\":\"output\",\"step\":2,\"text\":\"  foo!\\n\",\"stderr\":false}
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout.replace(&dirs.home.to_string_lossy().to_string(), "~"),
+               expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_quiet() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_quiet");
+    cmd.args(["manifest.yml", "-t", "linux", "--quiet"]);
+    remove_file(dirs.local.join("gitconfig")).unwrap();
+
+    let expected_stderr = "\
+[1/2] Copy gitconfig to ~/.gitconfig
+  Error: No such file or directory (os error 2)
+1 error in 1 step
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, expected_stderr);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(5));
+
+    // Assert the remaining commands are still executed
+    let log_contents = read_file(&dirs.local.join("log.txt"));
+    assert_eq!(log_contents, "script.sh called with arg1 linux\n");
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_stdin() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_stdin");
+    let manifest = File::open(dirs.local.join("manifest.yml")).unwrap();
+    cmd.args(["-", "-t", "linux"]).stdin(manifest);
+
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig (created)
+[2/2] Copy foo to foo (unchanged)
+[2/2] Link bashrc to ~/.bashrc (created)
+[2/2] Link vimrc to ~/.vimrc (created)
+[2/2] Run sh script.sh arg1 linux
+  foo!
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    // Assert relative paths are resolved against the working directory
+    let git_contents = read_file(&dirs.home.join(".gitconfig"));
+    assert_eq!(git_contents, "git #1\n");
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_packages() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (dirs, mut cmd) = setup_e2e_local("test_local_packages");
+    std::fs::create_dir_all(dirs.local.join("bin")).unwrap();
+    for (program, contents) in [
+        ("apt-get", "#!/bin/sh\necho \"apt-get $*\"\n"),
+        ("dpkg", "#!/bin/sh\n[ \"$2\" = git ]\n"),
+        ("sudo", "#!/bin/sh\nexec \"$@\"\n"),
+    ] {
+        let path = dirs.local.join("bin").join(program);
+        write_file(&path, contents);
+        std::fs::set_permissions(&path, PermissionsExt::from_mode(0o755))
+            .unwrap();
+    }
+    let path = format!("{}:{}", dirs.local.join("bin").display(),
+                       std::env::var("PATH").unwrap());
+    cmd.args(["manifest.yml"]).env("PATH", path);
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - packages: [ git, { name: fd, apt: fd-find } ]
+");
+
+    let expected = "\
+[1/1] Install package git with apt (unchanged)
+[1/1] Install package fd-find with apt (created)
+  apt-get install -y fd-find
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    cmd.arg("--dry-run");
+    let expected = "\
+[1/1] Install package git with apt (DRY RUN: unchanged)
+[1/1] Install package fd-find with apt (DRY RUN: install)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_local_tui() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_tui");
+    write_file(&dirs.local.join("input.txt"), "i\n");
+    let input = File::open(dirs.local.join("input.txt")).unwrap();
+    cmd.args(["tui", "manifest.yml", "-t", "linux"]).stdin(input);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr,
+               "Error: The tui subcommand requires an interactive terminal\n");
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+
+    // Assert nothing is installed without a terminal to select steps in
+    assert_eq!(dirs.home.join(".gitconfig").exists(), false);
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_brew() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (dirs, mut cmd) = setup_e2e_local("test_local_brew");
+    std::fs::create_dir_all(dirs.local.join("bin")).unwrap();
+    write_file(&dirs.local.join("bin/brew"), "\
+#!/bin/sh
+case \"$*\" in
+    'list --formula git') exit 0 ;;
+    list*) exit 1 ;;
+    *) echo \"brew $*\" ;;
+esac
+");
+    std::fs::set_permissions(dirs.local.join("bin/brew"),
+                             PermissionsExt::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", dirs.local.join("bin").display(),
+                       std::env::var("PATH").unwrap());
+    cmd.args(["manifest.yml"]).env("PATH", path);
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - brew: [ git, { cask: firefox } ]
+");
+
+    let expected = "\
+[1/1] Install formula git with Homebrew (unchanged)
+[1/1] Install cask firefox with Homebrew (created)
+  brew install --cask firefox
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    cmd.arg("--dry-run");
+    let expected = "\
+[1/1] Install formula git with Homebrew (DRY RUN: unchanged)
+[1/1] Install cask firefox with Homebrew (DRY RUN: install)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_repo() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_repo");
+    let git = |args: &[&str]| {
+        let mut git = std::process::Command::new("git");
+        git.args(["-C", "theme", "-c", "user.name=a", "-c", "user.email=a@a"])
+            .args(args).current_dir(&dirs.local);
+        assert_eq!(run_command(&mut git).2, Some(0));
+    };
+    std::fs::create_dir_all(dirs.local.join("theme")).unwrap();
+    git(&["init", "--quiet"]);
+    write_file(&dirs.local.join("theme/theme.zsh"), "v1\n");
+    git(&["add", "."]);
+    git(&["commit", "--quiet", "-m", "v1"]);
+    cmd.args(["manifest.yml"]);
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - repo: [ { url: ./theme, dst: ~/.themes/theme } ]
+");
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout,
+               "[1/1] Clone ./theme to ~/.themes/theme (created)\n");
+    assert_eq!(exitcode, Some(0));
+    let theme_contents = read_file(&dirs.home.join(".themes/theme/theme.zsh"));
+    assert_eq!(theme_contents, "v1\n");
+
+    let (stdout, _, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stdout,
+               "[1/1] Clone ./theme to ~/.themes/theme (unchanged)\n");
+    assert_eq!(exitcode, Some(0));
+
+    write_file(&dirs.local.join("theme/theme.zsh"), "v2\n");
+    git(&["commit", "--quiet", "-am", "v2"]);
+    let mut dry_run = coliru_command(&dirs);
+    dry_run.args(["manifest.yml", "--dry-run"]);
+    let (stdout, _, _) = run_command(&mut dry_run);
+    assert_eq!(&stdout,
+               "[1/1] Clone ./theme to ~/.themes/theme (DRY RUN: update)\n");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout,
+               "[1/1] Clone ./theme to ~/.themes/theme (updated)\n");
+    assert_eq!(exitcode, Some(0));
+    let theme_contents = read_file(&dirs.home.join(".themes/theme/theme.zsh"));
+    assert_eq!(theme_contents, "v2\n");
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_fetch() {
+    let (dirs, mut cmd) = setup_e2e_local("test_local_fetch");
+    write_file(&dirs.local.join("tool"), "tool v1\n");
+    let url = format!("file://{}", dirs.local.join("tool").display());
+    cmd.args(["manifest.yml"]);
+    write_file(&dirs.local.join("manifest.yml"), &format!("\
+steps:
+  - fetch:
+    - url: {url}
+      dst: ~/bin/tool
+      sha256: 8419ff13897cbe37259aafcdf99caa54532b826445935be0fcb71456525ebc26
+      mode: 755
+"));
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(stdout, format!("[1/1] Download {url} to ~/bin/tool \
+                                (created)\n"));
+    assert_eq!(exitcode, Some(0));
+    let tool_contents = read_file(&dirs.home.join("bin/tool"));
+    assert_eq!(tool_contents, "tool v1\n");
+    let tool_mode = std::fs::metadata(dirs.home.join("bin/tool")).unwrap();
+    assert_eq!(std::os::unix::fs::PermissionsExt::mode(
+        &tool_mode.permissions()) & 0o777, 0o755);
+
+    let (stdout, _, exitcode) = run_command(&mut cmd);
+    assert_eq!(stdout, format!("[1/1] Download {url} to ~/bin/tool \
+                                (unchanged)\n"));
+    assert_eq!(exitcode, Some(0));
+
+    // Assert a file with the wrong hash doesn't replace the destination
+    write_file(&dirs.local.join("tool"), "tool v2\n");
+    write_file(&dirs.home.join("bin/tool"), "old\n");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(stdout, format!("[1/1] Download {url} to ~/bin/tool \
+                                (FAILED)\n"));
+    assert_eq!(stderr, format!("  Error: Failed to download {url}: Checksum \
+                                doesn't match 8419ff13897cbe37259aafcdf99caa\
+                                54532b826445935be0fcb71456525ebc26\n"));
+    assert_eq!(exitcode, Some(6));
+    let tool_contents = read_file(&dirs.home.join("bin/tool"));
+    assert_eq!(tool_contents, "old\n");
+    assert_eq!(dirs.home.join("bin/tool.coliru-tmp").exists(), false);
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_systemd() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (dirs, mut cmd) = setup_e2e_local("test_local_systemd");
+    let bin = dirs.local.join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    write_file(&bin.join("systemctl"), &format!("\
+#!/bin/sh
+[ \"$1\" = --user ] && shift
+case \"$1\" in
+    is-enabled) test -e '{0}'/\"enabled-$3\" ;;
+    is-active) test -e '{0}'/\"active-$3\" ;;
+    enable) touch '{0}'/\"enabled-$2\" && echo \"enabled $2\" ;;
+    start|restart) touch '{0}'/\"active-$2\" && echo \"$1ed $2\" ;;
+esac
+", bin.display()));
+    std::fs::set_permissions(bin.join("systemctl"),
+                             PermissionsExt::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+    cmd.args(["manifest.yml"]).env("PATH", &path);
+    write_file(&dirs.local.join("foo.service"), "[Service]\n");
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - systemd: [ { src: foo.service } ]
+");
+
+    let expected = "\
+[1/1] Install user unit foo.service (created)
+  enabled foo.service
+  started foo.service
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+    let unit_contents = read_file(
+        &dirs.home.join(".config/systemd/user/foo.service"));
+    assert_eq!(unit_contents, "[Service]\n");
+
+    let (stdout, _, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stdout, "[1/1] Install user unit foo.service (unchanged)\n");
+    assert_eq!(exitcode, Some(0));
+
+    // Assert a running unit is restarted when its unit file changes
+    write_file(&dirs.local.join("foo.service"), "[Service]\nType=simple\n");
+    let mut dry_run = coliru_command(&dirs);
+    dry_run.args(["manifest.yml", "--dry-run"]).env("PATH", &path);
+    let (stdout, _, exitcode) = run_command(&mut dry_run);
+    assert_eq!(&stdout, "[1/1] Install user unit foo.service (DRY RUN: \
+                         install, restart)\n");
+    assert_eq!(exitcode, Some(0));
+
+    let expected = "\
+[1/1] Install user unit foo.service (updated)
+  restarted foo.service
+";
+    let (stdout, _, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_defaults() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (dirs, mut cmd) = setup_e2e_local("test_local_defaults");
+    let bin = dirs.local.join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    write_file(&bin.join("defaults"), &format!("\
+#!/bin/sh
+case \"$1 $4\" in
+    read*) cat '{0}'/\"$2.$3\" 2>/dev/null ;;
+    'write -bool') test \"$5\" = true && echo 1 > '{0}'/\"$2.$3\" ;;
+    write*) echo \"$5\" > '{0}'/\"$2.$3\" ;;
+esac
+", bin.display()));
+    std::fs::set_permissions(bin.join("defaults"),
+                             PermissionsExt::from_mode(0o755)).unwrap();
+    write_file(&bin.join("com.apple.dock.tilesize"), "48\n");
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+    cmd.args(["manifest.yml"]).env("PATH", &path);
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - defaults:
+    - { domain: com.apple.dock, key: autohide, value: true }
+    - { domain: com.apple.dock, key: tilesize, value: 36 }
+");
+
+    let mut dry_run = coliru_command(&dirs);
+    dry_run.args(["manifest.yml", "--dry-run"]).env("PATH", &path);
+    let expected = "\
+[1/1] Set com.apple.dock autohide to true (DRY RUN: unset -> 1)
+[1/1] Set com.apple.dock tilesize to 36 (DRY RUN: 48 -> 36)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut dry_run);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    let expected = "\
+[1/1] Set com.apple.dock autohide to true (created)
+[1/1] Set com.apple.dock tilesize to 36 (updated)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+    let autohide = read_file(&bin.join("com.apple.dock.autohide"));
+    assert_eq!(autohide, "1\n");
+
+    let expected = "\
+[1/1] Set com.apple.dock autohide to true (unchanged)
+[1/1] Set com.apple.dock tilesize to 36 (unchanged)
+";
+    let (stdout, _, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_extensions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (dirs, mut cmd) = setup_e2e_local("test_local_extensions");
+    std::fs::create_dir_all(dirs.local.join("bin")).unwrap();
+    write_file(&dirs.local.join("bin/code"), "\
+#!/bin/sh
+case \"$1\" in
+    --list-extensions) echo 'MS-Python.Python' ;;
+    --install-extension) echo \"Installed $2\" ;;
+esac
+");
+    std::fs::set_permissions(dirs.local.join("bin/code"),
+                             PermissionsExt::from_mode(0o755)).unwrap();
+    let path = format!("{}:{}", dirs.local.join("bin").display(),
+                       std::env::var("PATH").unwrap());
+    cmd.args(["manifest.yml"]).env("PATH", path);
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - extensions: [ ms-python.python, vscodevim.vim ]
+");
+
+    let expected = "\
+[1/1] Install extension ms-python.python with code (unchanged)
+[1/1] Install extension vscodevim.vim with code (created)
+  Installed vscodevim.vim
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    cmd.arg("--dry-run");
+    let expected = "\
+[1/1] Install extension ms-python.python with code (DRY RUN: unchanged)
+[1/1] Install extension vscodevim.vim with code (DRY RUN: install)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_local_cron() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (dirs, mut cmd) = setup_e2e_local("test_local_cron");
+    let bin = dirs.local.join("bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    write_file(&bin.join("crontab"), &format!("\
+#!/bin/sh
+case \"$1\" in
+    -l) cat '{0}/tab' 2>/dev/null ;;
+    -) cat > '{0}/tab.new' && mv '{0}/tab.new' '{0}/tab' ;;
+esac
+", bin.display()));
+    std::fs::set_permissions(bin.join("crontab"),
+                             PermissionsExt::from_mode(0o755)).unwrap();
+    write_file(&bin.join("tab"), "\
+MAILTO=me
+# coliru: backup
+0 4 * * * ~/bin/old-backup.sh
+# coliru: old
+@daily ~/bin/old.sh
+");
+    let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap());
+    cmd.args(["manifest.yml"]).env("PATH", &path);
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - cron:
+    - { name: backup, schedule: 0 3 * * *, command: ~/bin/backup.sh }
+    - { name: sync, schedule: '@hourly', command: ~/bin/sync.sh }
+    - { name: old, remove: true }
+");
+
+    let mut dry_run = coliru_command(&dirs);
+    dry_run.args(["manifest.yml", "--dry-run"]).env("PATH", &path);
+    let expected = "\
+[1/1] Add cron job backup (DRY RUN: update)
+[1/1] Add cron job sync (DRY RUN: add)
+[1/1] Remove cron job old (DRY RUN: remove)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut dry_run);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    let expected = "\
+[1/1] Add cron job backup (updated)
+[1/1] Add cron job sync (created)
+[1/1] Remove cron job old (updated)
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&bin.join("tab")), "\
+MAILTO=me
+# coliru: backup
+0 3 * * * ~/bin/backup.sh
+# coliru: sync
+@hourly ~/bin/sync.sh
+");
+
+    let expected = "\
+[1/1] Add cron job backup (unchanged)
+[1/1] Add cron job sync (unchanged)
+[1/1] Remove cron job old (unchanged)
+";
+    let (stdout, _, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
@@ -0,0 +1,56 @@
+//! End to end tests that test the plan subcommand
+
+mod test_utils;
+
+use test_utils::*;
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_plan_basic() {
+    let (dirs, mut cmd) = setup_e2e_local("test_plan_basic");
+    cmd.args(["plan", "manifest.yml", "-t", "linux"]);
+
+    let local = dirs.local.display();
+    let home = dirs.home.display();
+    let expected = format!("\
+Target: local machine
+Tag rules: linux
+[1/3] Tags: windows, linux, macos
+  Copy {local}/gitconfig to {home}/.gitconfig
+[2/3] Tags: linux, macos
+  Copy {local}/foo to {local}/foo
+  Link {local}/bashrc to {home}/.bashrc
+  Link {local}/vimrc to {home}/.vimrc
+  Run sh script.sh arg1 linux in {local}
+[3/3] Tags: windows (skipped: doesn't match tag rule linux)
+");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, &expected);
+    assert_eq!(exitcode, Some(0));
+
+    // Nothing is installed
+    assert_eq!(dirs.home.join(".gitconfig").exists(), false);
+}
+
+#[test]
+fn test_plan_json() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_plan_json");
+    cmd.args(["plan", "manifest.yml", "-t", "linux", "--host", SSH_HOST,
+              "--json"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    let plan: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    assert_eq!(&stderr, "");
+    assert_eq!(plan["host"], SSH_HOST);
+    assert_eq!(plan["steps"][0]["actions"][0]["kind"], "copy");
+    assert_eq!(plan["steps"][1]["actions"][2]["kind"], "copy");
+    assert_eq!(plan["steps"][1]["actions"][3]["dst"],
+               format!("{}:~/.coliru/script.sh", SSH_HOST));
+    assert_eq!(plan["steps"][1]["actions"][4]["command"],
+               "sh script.sh arg1 linux");
+    assert_eq!(plan["steps"][1]["actions"][4]["cwd"], "~/.coliru");
+    assert_eq!(plan["steps"][2]["skip_reason"],
+               "doesn't match tag rule linux");
+    assert_eq!(exitcode, Some(0));
+}
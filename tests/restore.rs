@@ -0,0 +1,103 @@
+//! End to end tests that test backups and the restore subcommand
+
+mod test_utils;
+
+use regex::Regex;
+use test_utils::*;
+use std::fs::remove_file;
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_restore_latest() {
+    let (dirs, mut cmd) = setup_e2e_local("test_restore_latest");
+    cmd.args(["manifest.yml", "-t", "linux", "--copy", "--force"]);
+    write_file(&dirs.home.join(".bashrc"), "bash #0\n");
+    run_command(&mut cmd);
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["restore", "~/.bashrc"]);
+
+    let expected = "Restored version 1 of ~/.bashrc\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.home.join(".bashrc")), "bash #0\n");
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_restore_relative_state_home() {
+    let (dirs, mut cmd) = setup_e2e_local("test_restore_relative_state_home");
+    cmd.args(["manifest.yml", "-t", "linux", "--copy", "--force"]);
+    cmd.env("XDG_STATE_HOME", "state");
+    write_file(&dirs.home.join(".bashrc"), "bash #0\n");
+    run_command(&mut cmd);
+
+    // Relative paths in $XDG_STATE_HOME are ignored
+    assert_eq!(dirs.local.join("state").exists(), false);
+    assert_eq!(dirs.home.join(".local/state/coliru/backups").exists(), true);
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_restore_list() {
+    let (dirs, mut cmd) = setup_e2e_local("test_restore_list");
+    cmd.args(["manifest.yml", "-t", "linux", "--force"]);
+    write_file(&dirs.home.join(".vimrc"), "vim #0\n");
+    run_command(&mut cmd);
+    // Identical backups are only stored once
+    remove_file(dirs.home.join(".vimrc")).unwrap();
+    write_file(&dirs.home.join(".vimrc"), "vim #0\n");
+    run_command(&mut cmd);
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["restore", "~/.vimrc", "--list"]);
+
+    let expected = Regex::new("^1  \\d{4}-\\d{2}-\\d{2} \\d{2}:\\d{2}:\\d{2} UTC  \
+                               [0-9a-f]{12}\n$").unwrap();
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert!(expected.is_match(&stdout), "{}", stdout);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_restore_no_backups() {
+    let (dirs, mut cmd) = setup_e2e_local("test_restore_no_backups");
+    cmd.args(["restore", "~/.bashrc"]);
+
+    let expected = format!("Error: No backups of {} exist\n",
+                           dirs.home.join(".bashrc").display());
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, &expected);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(2));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_restore_gc() {
+    let (dirs, mut cmd) = setup_e2e_local("test_restore_gc");
+    cmd.args(["manifest.yml", "-t", "linux", "--copy", "--force"]);
+    for i in 0..3 {
+        write_file(&dirs.home.join(".bashrc"), &format!("bash #{i}{i}\n"));
+        run_command(&mut cmd);
+    }
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["gc", "--keep", "1"]);
+
+    let expected = "Removed 2 backups and 2 files (18 B)\n";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["restore", "~/.bashrc", "--list"]);
+
+    let (stdout, _, _) = run_command(&mut cmd);
+    assert_eq!(stdout.lines().count(), 1);
+}
@@ -6,7 +6,8 @@ mod test_utils;
 
 use test_utils::*;
 use regex::Regex;
-use std::fs::remove_file;
+use std::fs::{read_link, remove_file};
+use std::path::Path;
 
 #[test]
 #[cfg(target_family = "unix")]
@@ -21,7 +22,7 @@ fn test_ssh_standard() {
 [2/2] Copy vimrc to {SSH_HOST}:~/test_ssh_standard/.vimrc
 [2/2] Copy test_ssh_standard/script.sh to {SSH_HOST}:~/.coliru/test_ssh_standard/script.sh
 [2/2] Run sh test_ssh_standard/script.sh arg1 linux on {SSH_HOST}
-foo!
+  foo!
 ");
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -43,6 +44,69 @@ foo!
     assert_eq!(log_contents, "script.sh called with arg1 linux\n");
 }
 
+#[test]
+#[cfg(all(target_family = "unix", feature = "native-ssh"))]
+fn test_ssh_native() {
+    let (dirs, mut cmd) = setup_e2e_ssh("test_ssh_native");
+    cmd.args(["manifest.yml", "-t", "linux", "--transport", "native"]);
+
+    let expected = format!("\
+[1/2] Copy gitconfig to {SSH_HOST}:~/test_ssh_native/.gitconfig
+[2/2] Copy test_ssh_native/foo to {SSH_HOST}:~/.coliru/test_ssh_native/foo
+[2/2] Copy bashrc to {SSH_HOST}:~/test_ssh_native/.bashrc
+[2/2] Copy vimrc to {SSH_HOST}:~/test_ssh_native/.vimrc
+[2/2] Copy test_ssh_native/script.sh to {SSH_HOST}:~/.coliru/test_ssh_native/script.sh
+[2/2] Run sh test_ssh_native/script.sh arg1 linux on {SSH_HOST}
+  foo!
+");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, &expected);
+    assert_eq!(exitcode, Some(0));
+
+    // Assert files are correctly copied/run
+    let bash_contents = read_file(&dirs.ssh.join(".bashrc"));
+    let git_contents = read_file(&dirs.ssh.join(".gitconfig"));
+    let foo_contents = read_file(&dirs.ssh_cwd.join("foo"));
+    let log_contents = read_file(&dirs.ssh_cwd.join("log.txt"));
+    assert_eq!(bash_contents, "bash #1\n");
+    assert_eq!(git_contents, "git #1\n");
+    assert_eq!(foo_contents, "foo!\n");
+    assert_eq!(log_contents, "script.sh called with arg1 linux\n");
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_ssh_docker() {
+    let (dirs, mut cmd) = setup_e2e_docker("test_ssh_docker");
+    cmd.args(["manifest.yml", "-t", "linux"]);
+
+    let host = DOCKER_CONTAINER;
+    let expected = format!("\
+[1/2] Copy gitconfig to {host}:~/test_ssh_docker/.gitconfig
+[2/2] Copy test_ssh_docker/foo to {host}:~/.coliru/test_ssh_docker/foo
+[2/2] Copy bashrc to {host}:~/test_ssh_docker/.bashrc
+[2/2] Copy vimrc to {host}:~/test_ssh_docker/.vimrc
+[2/2] Copy test_ssh_docker/script.sh to {host}:~/.coliru/test_ssh_docker/script.sh
+[2/2] Run sh test_ssh_docker/script.sh arg1 linux on {host}
+  foo!
+");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, &expected);
+    assert_eq!(exitcode, Some(0));
+
+    // Assert files are correctly copied/run
+    let bash_contents = read_file(&dirs.ssh.join(".bashrc"));
+    let git_contents = read_file(&dirs.ssh.join(".gitconfig"));
+    let foo_contents = read_file(&dirs.ssh_cwd.join("foo"));
+    let log_contents = read_file(&dirs.ssh_cwd.join("log.txt"));
+    assert_eq!(bash_contents, "bash #1\n");
+    assert_eq!(git_contents, "git #1\n");
+    assert_eq!(foo_contents, "foo!\n");
+    assert_eq!(log_contents, "script.sh called with arg1 linux\n");
+}
+
 #[test]
 #[cfg(target_family = "unix")]
 fn test_ssh_run_alternate_tag_rules_1() {
@@ -55,7 +119,7 @@ fn test_ssh_run_alternate_tag_rules_1() {
 [1/1] Copy vimrc to {SSH_HOST}:~/test_ssh_run_alternate_tag_rules_1/.vimrc
 [1/1] Copy test_ssh_run_alternate_tag_rules_1/script.sh to {SSH_HOST}:~/.coliru/test_ssh_run_alternate_tag_rules_1/script.sh
 [1/1] Run sh test_ssh_run_alternate_tag_rules_1/script.sh arg1 linux ^windows on {SSH_HOST}
-foo!
+  foo!
 ");
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -90,7 +154,7 @@ fn test_ssh_run_alternate_tag_rules_2() {
 [2/2] Copy vimrc to {SSH_HOST}:~/test_ssh_run_alternate_tag_rules_2/.vimrc
 [2/2] Copy test_ssh_run_alternate_tag_rules_2/script.sh to {SSH_HOST}:~/.coliru/test_ssh_run_alternate_tag_rules_2/script.sh
 [2/2] Run sh test_ssh_run_alternate_tag_rules_2/script.sh arg1 macos on {SSH_HOST}
-foo!
+  foo!
 ");
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -145,6 +209,26 @@ fn test_ssh_dry_run() {
     assert_eq!(log_exists, false);
 }
 
+#[test]
+fn test_ssh_auto_tags() {
+    let (_dirs, mut cmd) = setup_e2e_ssh("test_ssh_auto_tags");
+    cmd.args(["manifest.yml", "--dry-run", "--auto-tags"]);
+
+    // The test SSH server runs Linux, so the Windows step is skipped
+    let expected = format!("\
+[1/2] Copy gitconfig to {SSH_HOST}:~/test_ssh_auto_tags/.gitconfig (DRY RUN)
+[2/2] Copy test_ssh_auto_tags/foo to {SSH_HOST}:~/.coliru/test_ssh_auto_tags/foo (DRY RUN)
+[2/2] Copy bashrc to {SSH_HOST}:~/test_ssh_auto_tags/.bashrc (DRY RUN)
+[2/2] Copy vimrc to {SSH_HOST}:~/test_ssh_auto_tags/.vimrc (DRY RUN)
+[2/2] Copy test_ssh_auto_tags/script.sh to {SSH_HOST}:~/.coliru/test_ssh_auto_tags/script.sh (DRY RUN)
+[2/2] Run sh test_ssh_auto_tags/script.sh arg1  on {SSH_HOST} (DRY RUN)
+");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, &expected);
+    assert_eq!(exitcode, Some(0));
+}
+
 #[test]
 #[cfg(target_family = "unix")]
 fn test_ssh_copy() {
@@ -158,7 +242,7 @@ fn test_ssh_copy() {
 [2/2] Copy vimrc to {SSH_HOST}:~/test_ssh_copy/.vimrc
 [2/2] Copy test_ssh_copy/script.sh to {SSH_HOST}:~/.coliru/test_ssh_copy/script.sh
 [2/2] Run sh test_ssh_copy/script.sh arg1 linux on {SSH_HOST}
-foo!
+  foo!
 ");
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -199,7 +283,7 @@ fn test_ssh_run_failure() {
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, expected_stderr);
     assert_eq!(&stdout, &expected_stdout);
-    assert_eq!(exitcode, Some(1));
+    assert_eq!(exitcode, Some(6));
 
     // Assert files are correctly copied/run
     let bash_contents = read_file(&dirs.ssh.join(".bashrc"));
@@ -219,23 +303,23 @@ fn test_ssh_run_failure() {
 fn test_ssh_missing_file() {
     let (dirs, mut cmd) = setup_e2e_ssh("test_ssh_missing_file");
     cmd.args(["manifest.yml", "-t", "linux"]);
-    remove_file(&dirs.local.join("vimrc")).unwrap();
+    remove_file(dirs.local.join("vimrc")).unwrap();
 
     let expected_stdout = format!("\
 [1/2] Copy gitconfig to {SSH_HOST}:~/test_ssh_missing_file/.gitconfig
 [2/2] Copy test_ssh_missing_file/foo to {SSH_HOST}:~/.coliru/test_ssh_missing_file/foo
 [2/2] Copy bashrc to {SSH_HOST}:~/test_ssh_missing_file/.bashrc
-[2/2] Copy vimrc to {SSH_HOST}:~/test_ssh_missing_file/.vimrc
+[2/2] Copy vimrc to {SSH_HOST}:~/test_ssh_missing_file/.vimrc (FAILED)
 [2/2] Copy test_ssh_missing_file/script.sh to {SSH_HOST}:~/.coliru/test_ssh_missing_file/script.sh
 [2/2] Run sh test_ssh_missing_file/script.sh arg1 linux on {SSH_HOST}
-foo!
+  foo!
 ");
     let expected_stderr = "  Error: Failed to copy vimrc to staging directory: \
                            No such file or directory (os error 2)\n";
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, expected_stderr);
     assert_eq!(&stdout, &expected_stdout);
-    assert_eq!(exitcode, Some(1));
+    assert_eq!(exitcode, Some(5));
 
     // Assert files are correctly copied/run
     let bash_contents = read_file(&dirs.ssh.join(".bashrc"));
@@ -252,7 +336,7 @@ foo!
 #[cfg(target_family = "unix")]
 fn test_ssh_different_cwd() {
     let (dirs, mut cmd) = setup_e2e_ssh("test_ssh_different_cwd");
-    cmd.current_dir(&dirs.local.parent().unwrap());
+    cmd.current_dir(dirs.local.parent().unwrap());
     cmd.args(["test_ssh_different_cwd/manifest.yml", "-t", "linux"]);
 
     let expected = format!("\
@@ -262,7 +346,7 @@ fn test_ssh_different_cwd() {
 [2/2] Copy vimrc to {SSH_HOST}:~/test_ssh_different_cwd/.vimrc
 [2/2] Copy test_ssh_different_cwd/script.sh to {SSH_HOST}:~/.coliru/test_ssh_different_cwd/script.sh
 [2/2] Run sh test_ssh_different_cwd/script.sh arg1 linux on {SSH_HOST}
-foo!
+  foo!
 ");
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
     assert_eq!(&stderr, "");
@@ -285,48 +369,150 @@ foo!
 }
 
 #[test]
-fn test_ssh_bad_host() {
+#[cfg(target_family = "unix")]
+fn test_ssh_remote_links() {
+    let (dirs, mut cmd) = setup_e2e_ssh("test_ssh_remote_links");
+    cmd.args(["manifest.yml", "-t", "linux", "--remote-links"]);
+
+    let expected = format!("\
+[1/2] Copy gitconfig to {SSH_HOST}:~/test_ssh_remote_links/.gitconfig
+[2/2] Copy test_ssh_remote_links/foo to {SSH_HOST}:~/.coliru/test_ssh_remote_links/foo
+[2/2] Link bashrc to {SSH_HOST}:~/test_ssh_remote_links/.bashrc
+[2/2] Link vimrc to {SSH_HOST}:~/test_ssh_remote_links/.vimrc
+[2/2] Copy test_ssh_remote_links/script.sh to {SSH_HOST}:~/.coliru/test_ssh_remote_links/script.sh
+[2/2] Run sh test_ssh_remote_links/script.sh arg1 linux on {SSH_HOST}
+  foo!
+");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, &expected);
+    assert_eq!(exitcode, Some(0));
+
+    // Assert links point to the sources in ~/.coliru
+    let bash_link = read_link(dirs.ssh.join(".bashrc")).unwrap();
+    let bash_contents = read_file(&dirs.ssh.parent().unwrap()
+                                  .join(".coliru").join("bashrc"));
+    assert_eq!(bash_link, Path::new("/home/test/.coliru/bashrc"));
+    assert_eq!(bash_contents, "bash #1\n");
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_ssh_remote_exec() {
+    let (dirs, mut cmd) = setup_e2e_ssh("test_ssh_remote_exec");
+    cmd.args(["manifest.yml", "-t", "linux", "--remote-exec",
+              "--push-binary"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert!(stdout.starts_with(&format!("Sending . to {SSH_HOST}\n")));
+    assert_eq!(exitcode, Some(0));
+
+    // Assert links point to the sources in the remote copy of the repository,
+    // like they would after a local installation
+    let bash_link = read_link(dirs.ssh.join(".bashrc")).unwrap();
+    assert_eq!(bash_link, Path::new("/home/test/.coliru/repo/bashrc"));
+}
+
+#[test]
+fn test_ssh_remote_links_dry_run() {
     // Use setup_e2e_local instead of setup_e2e_ssh to avoid regular --host
-    let (_dirs, mut cmd) = setup_e2e_local("test_ssh_bad_host");
-    let bad_host = "fake@coliru.test.internal"; // Will be a DNS error
-    cmd.args(["manifest.yml", "-t", "linux", "--host", bad_host]);
+    let (_dirs, mut cmd) = setup_e2e_local("test_ssh_remote_links_dry_run");
+    let host = "fake@coliru.test.internal";
+    cmd.args(["manifest.yml", "-t", "linux", "--host", host, "--remote-links",
+              "--dry-run", "--no-auto-tags"]);
 
     // setup_e2e_local will install to CWD instead of $HOME on Windows:
     let expected_stdout = Regex::new(&format!("\
-\\[1/2] Copy gitconfig to {bad_host}:~/(.coliru/)?.gitconfig
-\\[2/2] Copy foo to {bad_host}:~/.coliru/foo
-\\[2/2] Copy bashrc to {bad_host}:~/(.coliru/)?.bashrc
-\\[2/2] Copy vimrc to {bad_host}:~/(.coliru/)?.vimrc
-\\[2/2] Copy script.sh to {bad_host}:~/.coliru/script.sh
-\\[2/2] Run sh script.sh arg1 linux on {bad_host}
+\\[2/2] Link bashrc to {host}:~/(.coliru/)?.bashrc \\(DRY RUN\\)
+\\[2/2] Link vimrc to {host}:~/(.coliru/)?.vimrc \\(DRY RUN\\)
 ")).unwrap();
-    // Exact std output varies significantly across machines;
-    let expected_stderr = Regex::new("\
-ssh: Could not resolve hostname coliru.test.internal: [\\w \\.]+\r?(
-[\\w :]+\r?)?
-  Error: Failed to transfer staged files: SCP terminated unsuccessfully: \
-    exit (status|code): \\d+
-ssh: Could not resolve hostname coliru.test.internal: [\\w \\.]+\r?(
-[\\w :]+\r?)?
-  Error: Failed to transfer staged files: SCP terminated unsuccessfully: \
-    exit (status|code): \\d+
-ssh: Could not resolve hostname coliru.test.internal: [\\w \\.]+\r?(
-[\\w :]+\r?)?
-  Error: Failed to transfer staged files: SCP terminated unsuccessfully: \
-    exit (status|code): \\d+
-ssh: Could not resolve hostname coliru.test.internal: [\\w \\.]+\r?(
-[\\w :]+\r?)?
-  Error: Failed to transfer staged files: SCP terminated unsuccessfully: \
-    exit (status|code): \\d+
-ssh: Could not resolve hostname coliru.test.internal: [\\w \\.]+\r?(
-[\\w :]+\r?)?
-  Error: Failed to transfer staged files: SCP terminated unsuccessfully: \
-    exit (status|code): \\d+
-ssh: Could not resolve hostname coliru.test.internal: [\\w \\.]+\r?
-  Error: SSH terminated unsuccessfully: exit (status|code): \\d+
-").unwrap();
     let (stdout, stderr, exitcode) = run_command(&mut cmd);
-    assert_eq!(expected_stderr.is_match(&stderr), true);
+    assert_eq!(&stderr, "");
     assert_eq!(expected_stdout.is_match(&stdout), true);
-    assert_eq!(exitcode, Some(1));
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_ssh_bad_host() {
+    // Use setup_e2e_local instead of setup_e2e_ssh to avoid regular --host
+    let (_dirs, mut cmd) = setup_e2e_local("test_ssh_bad_host");
+    let bad_host = "fake@coliru.test.internal"; // Will be a DNS error
+    cmd.args(["manifest.yml", "-t", "linux", "--host", bad_host]);
+
+    // The connection is checked once before anything is installed, and the
+    // exact error message varies across machines
+    let expected_stderr = Regex::new(&format!("\
+^Error: Failed to connect to {bad_host} \\(the hostname couldn't be \
+    resolved\\): ssh: Could not resolve hostname coliru.test.internal: \
+    [\\w \\.]+\r?(\n[\\w :]+\r?)?\n$")).unwrap();
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert!(expected_stderr.is_match(&stderr), "{}", stderr);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(7));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_ssh_cleanup_scripts() {
+    let (dirs, mut cmd) = setup_e2e_ssh("test_ssh_cleanup_scripts");
+    cmd.args(["manifest.yml", "-t", "linux", "--cleanup-scripts"]);
+
+    let (_stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(exitcode, Some(0));
+
+    // Scripts are deleted after they run, but their output is kept
+    assert_eq!(dirs.ssh_cwd.join("script.sh").exists(), false);
+    assert_eq!(read_file(&dirs.ssh_cwd.join("log.txt")),
+               "script.sh called with arg1 linux\n");
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_ssh_run_env() {
+    let (dirs, mut cmd) = setup_e2e_ssh("test_ssh_run_env");
+    cmd.args(["manifest.yml", "--env", "B=it's $HOME"]);
+    write_file(&dirs.local.join("env.sh"), "echo \"$A, $B\" > env.txt\n");
+    write_file(&dirs.local.join("manifest.yml"), "\
+steps:
+  - run: [ { src: env.sh, prefix: sh, env: { A: foo, B: bar } } ]
+");
+
+    let (_stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.ssh_cwd.join("env.txt")), "foo, it's $HOME\n");
+}
+
+#[test]
+fn test_ssh_bad_jump_host() {
+    let (_dirs, mut cmd) = setup_e2e_ssh("test_ssh_bad_jump_host");
+    let bad_host = "fake@coliru.test.internal"; // Will be a DNS error
+    cmd.args(["manifest.yml", "-t", "linux", "--jump", bad_host]);
+
+    // The host is only reachable through the jump host, which doesn't exist
+    let expected_stderr = Regex::new(&format!("\
+^Error: Failed to connect to {SSH_HOST} \\(the hostname couldn't be \
+    resolved\\): ssh: Could not resolve hostname coliru.test.internal: \
+    ")).unwrap();
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert!(expected_stderr.is_match(&stderr), "{}", stderr);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(7));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_ssh_missing_config_file() {
+    let (_dirs, mut cmd) = setup_e2e_ssh("test_ssh_missing_config_file");
+    cmd.args(["manifest.yml", "-t", "linux", "--ssh-config", "missing"]);
+
+    let expected_stderr = Regex::new(&format!("\
+^Error: Failed to connect to {SSH_HOST}: Can't open user config file missing: \
+    No such file or directory\r?\n$")).unwrap();
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert!(expected_stderr.is_match(&stderr), "{}", stderr);
+    assert_eq!(&stdout, "");
+    assert_eq!(exitcode, Some(7));
 }
@@ -15,9 +15,11 @@ use std::path::{Path, PathBuf};
 use std::process::Command;
 
 /// The SSH test server
-// StrictHostKeyChecking option and correct port are set automatically in
-// src/ssh.rs when COLIRU_TEST environment variable is set.
-pub const SSH_HOST: &str = "test@localhost";
+// E2E tests set COLIRU_HOST_KEY_POLICY to off, since its host key is unknown
+pub const SSH_HOST: &str = "test@localhost:2222";
+
+/// The container running the SSH test server, used with `--docker`
+pub const DOCKER_CONTAINER: &str = "test@coliru-ssh";
 
 /// A set of temporary directories that are automatically deleted when the value
 /// is dropped
@@ -88,7 +90,6 @@ pub fn setup_integration(name: &str) -> TempDirs {
     if cfg!(target_family = "unix") {
         env::set_var("HOME", dirs.home.parent().unwrap());
     }
-    env::set_var("COLIRU_TEST", "1");
     dirs
 }
 
@@ -103,17 +104,44 @@ pub fn setup_integration(name: &str) -> TempDirs {
 /// ```
 fn setup_e2e(name: &str) -> (TempDirs, Command) {
     let dirs = TempDirs::new(name);
+    let cmd = coliru_command(&dirs);
+    (dirs, cmd)
+}
 
+/// Creates a coliru Command for E2E tests that uses an existing set of
+/// temporary directories
+///
+/// The Command's working directory is set to the local temporary directory, and
+/// on Unix, the Command's `$HOME` variable is set to the home temporary
+/// directory. This is useful for running multiple commands in a single test.
+///
+/// ```
+/// let (dirs, cmd_1) = setup_e2e_local("test_foo");
+/// let cmd_2 = coliru_command(&dirs);
+/// ```
+pub fn coliru_command(dirs: &TempDirs) -> Command {
     let exe = env::current_exe().unwrap().parent().unwrap().to_path_buf()
         .join(format!("../coliru{}", env::consts::EXE_SUFFIX));
     let mut cmd = Command::new(exe);
     cmd.current_dir(&dirs.local);
     if cfg!(target_family = "unix") {
         cmd.env("HOME", &dirs.home);
+    } else {
+        cmd.env("APPDATA", &dirs.home);
+        cmd.env("LOCALAPPDATA", &dirs.home);
     }
-    cmd.env("COLIRU_TEST", "1");
-
-    (dirs, cmd)
+    // Keep backups and config files in the home temporary directory
+    cmd.env_remove("XDG_CONFIG_HOME");
+    cmd.env_remove("XDG_STATE_HOME");
+    // Ignore any options that are set in the environment
+    for var in ["COLIRU_CONFIG", "COLIRU_COPY", "COLIRU_HOST",
+                "COLIRU_MANIFEST", "COLIRU_NO_COLOR", "COLIRU_TAG_RULES",
+                "CLICOLOR_FORCE", "NO_COLOR"] {
+        cmd.env_remove(var);
+    }
+    // Don't check the host key of the SSH test server
+    cmd.env("COLIRU_HOST_KEY_POLICY", "off");
+    cmd
 }
 
 /// Initializes temporary directories and a coliru Command for local E2E tests
@@ -157,6 +185,22 @@ pub fn setup_e2e_ssh(name: &str) -> (TempDirs, Command) {
     (dirs, cmd)
 }
 
+/// Initializes temporary directories and a coliru Command for Docker E2E tests
+///
+/// Like [`setup_e2e_ssh`], except that the `--docker` flag is set to the
+/// container running the test SSH server instead of setting `--host`.
+///
+/// ```
+/// let (dirs, cmd) = setup_e2e_docker("test_foo");
+/// ```
+pub fn setup_e2e_docker(name: &str) -> (TempDirs, Command) {
+    let (dirs, mut cmd) = setup_e2e(name);
+    cmd.args(["--docker", DOCKER_CONTAINER]);
+    copy_manifest(&dirs.local, &format!("~/{name}/"), &format!("{name}/"));
+
+    (dirs, cmd)
+}
+
 /// Initializes a basic dotfiles repository in a directory
 ///
 /// The dotfiles from `examples/test/` are used as a starting template. All
@@ -180,7 +224,7 @@ fn copy_manifest(dir: &Path, home_dir: &str, script_dir: &str) {
     };
 
     copy_file("manifest.yml");
-    fs::create_dir_all(&dir.join(script_dir)).unwrap();
+    fs::create_dir_all(dir.join(script_dir)).unwrap();
     copy_file("scripts/script.bat");
     copy_file("scripts/script.sh");
     copy_file("scripts/foo");
@@ -0,0 +1,125 @@
+//! End to end tests that test the uninstall subcommand
+
+mod test_utils;
+
+use test_utils::*;
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_uninstall_local() {
+    let (dirs, mut cmd) = setup_e2e_local("test_uninstall_local");
+    cmd.args(["manifest.yml", "-t", "linux", "--copy"]);
+    run_command(&mut cmd);
+    write_file(&dirs.home.join(".profile"), "profile\n");
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["uninstall"]);
+
+    let (home, local) = (dirs.home.display(), dirs.local.display());
+    let expected = format!("\
+Remove {home}/.bashrc (removed)
+Remove {home}/.gitconfig (removed)
+Remove {home}/.vimrc (removed)
+Remove {local}/foo (removed)
+");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, &expected);
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(dirs.home.join(".bashrc").exists(), false);
+    assert_eq!(dirs.home.join(".gitconfig").exists(), false);
+    assert_eq!(dirs.home.join(".profile").exists(), true);
+    assert_eq!(dirs.local.join("bashrc").exists(), true);
+
+    // Removed files are forgotten
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["uninstall"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "Nothing to uninstall\n");
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_uninstall_local_missing() {
+    let (dirs, mut cmd) = setup_e2e_local("test_uninstall_local_missing");
+    cmd.args(["manifest.yml", "-t", "linux", "--copy"]);
+    run_command(&mut cmd);
+    std::fs::remove_file(dirs.home.join(".vimrc")).unwrap();
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["uninstall"]);
+
+    let (home, local) = (dirs.home.display(), dirs.local.display());
+    let expected = format!("\
+Remove {home}/.bashrc (removed)
+Remove {home}/.gitconfig (removed)
+Remove {home}/.vimrc (missing)
+Remove {local}/foo (removed)
+");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, &expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_uninstall_local_dry_run() {
+    let (dirs, mut cmd) = setup_e2e_local("test_uninstall_local_dry_run");
+    cmd.args(["manifest.yml", "-t", "linux", "--copy"]);
+    run_command(&mut cmd);
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["uninstall", "--dry-run"]);
+
+    let (home, local) = (dirs.home.display(), dirs.local.display());
+    let expected = format!("\
+Remove {home}/.bashrc (DRY RUN)
+Remove {home}/.gitconfig (DRY RUN)
+Remove {home}/.vimrc (DRY RUN)
+Remove {local}/foo (DRY RUN)
+");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, &expected);
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.home.join(".bashrc")), "bash #1\n");
+}
+
+#[test]
+fn test_uninstall_local_nothing() {
+    let (dirs, _cmd) = setup_e2e_local("test_uninstall_local_nothing");
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["uninstall"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, "Nothing to uninstall\n");
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+fn test_uninstall_ssh_dry_run() {
+    let (dirs, mut cmd) = setup_e2e_ssh("test_uninstall_ssh_dry_run");
+    cmd.args(["manifest.yml", "-t", "linux"]);
+    run_command(&mut cmd);
+
+    // Other SSH tests share the remote state file, so only the files installed
+    // by this test are checked
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["uninstall", "--host", SSH_HOST, "--dry-run"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    for name in [".bashrc", ".gitconfig", ".vimrc"] {
+        let line = format!("Remove {SSH_HOST}:/home/test/\
+                            test_uninstall_ssh_dry_run/{name} (DRY RUN)\n");
+        assert!(stdout.contains(&line), "{}", stdout);
+    }
+    assert_eq!(exitcode, Some(0));
+    assert_eq!(read_file(&dirs.ssh.join(".bashrc")), "bash #1\n");
+}
@@ -0,0 +1,181 @@
+//! End to end tests that test the verify subcommand
+
+mod test_utils;
+
+use test_utils::*;
+use std::fs::remove_file;
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_verify_installed() {
+    let (dirs, mut cmd) = setup_e2e_local("test_verify_installed");
+    cmd.args(["manifest.yml", "-t", "linux"]);
+    run_command(&mut cmd);
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["verify", "manifest.yml", "-t", "linux"]);
+
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig: ok
+[2/2] Copy foo to foo: ok
+[2/2] Link bashrc to ~/.bashrc: ok
+[2/2] Link vimrc to ~/.vimrc: ok
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(0));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_verify_not_installed() {
+    let (_dirs, mut cmd) = setup_e2e_local("test_verify_not_installed");
+    cmd.args(["verify", "manifest.yml", "-t", "linux"]);
+
+    // foo is copied to itself, so it is always installed
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig: missing
+[2/2] Copy foo to foo: ok
+[2/2] Link bashrc to ~/.bashrc: missing
+[2/2] Link vimrc to ~/.vimrc: missing
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(1));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_verify_modified() {
+    let (dirs, mut cmd) = setup_e2e_local("test_verify_modified");
+    cmd.args(["manifest.yml", "-t", "linux"]);
+    run_command(&mut cmd);
+    write_file(&dirs.home.join(".gitconfig"), "git #2\n");
+    remove_file(dirs.home.join(".bashrc")).unwrap();
+    write_file(&dirs.home.join(".bashrc"), "bash #1\n");
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["verify", "manifest.yml", "-t", "linux", "--copy"]);
+
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig: contents differ
+[2/2] Copy foo to foo: ok
+[2/2] Copy bashrc to ~/.bashrc: ok
+[2/2] Copy vimrc to ~/.vimrc: ok
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(1));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_verify_not_link() {
+    let (dirs, mut cmd) = setup_e2e_local("test_verify_not_link");
+    cmd.args(["manifest.yml", "-t", "linux", "--copy"]);
+    run_command(&mut cmd);
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["verify", "manifest.yml", "-t", "linux"]);
+
+    let expected = "\
+[1/2] Copy gitconfig to ~/.gitconfig: ok
+[2/2] Copy foo to foo: ok
+[2/2] Link bashrc to ~/.bashrc: not a link
+[2/2] Link vimrc to ~/.vimrc: not a link
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(1));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_verify_creates() {
+    let (dirs, mut cmd) = setup_e2e_local("test_verify_creates");
+    write_file(&dirs.local.join("creates.yml"), "\
+steps:
+  - run:
+    - src: script.sh
+      prefix: sh
+      creates: [ log.txt, ~/.missing ]
+    - src: script.sh
+      prefix: sh
+      creates: foo
+");
+    cmd.args(["creates.yml"]);
+    run_command(&mut cmd);
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["verify", "creates.yml"]);
+
+    let expected = "\
+[1/1] Run script.sh created log.txt: ok
+[1/1] Run script.sh created ~/.missing: missing
+[1/1] Run script.sh created foo: ok
+";
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, expected);
+    assert_eq!(exitcode, Some(1));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_verify_ssh() {
+    let (dirs, mut cmd) = setup_e2e_ssh("test_verify_ssh");
+    cmd.args(["manifest.yml", "-t", "linux"]);
+    run_command(&mut cmd);
+    write_file(&dirs.ssh.join(".vimrc"), "vim #2\n");
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["verify", "manifest.yml", "-t", "linux", "--host", SSH_HOST]);
+
+    let expected = format!("\
+[1/2] Copy gitconfig to {SSH_HOST}:~/test_verify_ssh/.gitconfig: ok
+[2/2] Copy test_verify_ssh/foo to {SSH_HOST}:~/.coliru/test_verify_ssh/foo: ok
+[2/2] Copy bashrc to {SSH_HOST}:~/test_verify_ssh/.bashrc: ok
+[2/2] Copy vimrc to {SSH_HOST}:~/test_verify_ssh/.vimrc: contents differ
+");
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    assert_eq!(&stderr, "");
+    assert_eq!(&stdout, &expected);
+    assert_eq!(exitcode, Some(1));
+}
+
+#[test]
+#[cfg(target_family = "unix")]
+fn test_verify_json() {
+    let (dirs, mut cmd) = setup_e2e_local("test_verify_json");
+    cmd.args(["manifest.yml", "-t", "linux", "--copy"]);
+    run_command(&mut cmd);
+    write_file(&dirs.home.join(".gitconfig"), "abc");
+
+    let mut cmd = coliru_command(&dirs);
+    cmd.args(["verify", "manifest.yml", "-t", "linux", "--copy", "--json",
+              "--hash", "blake3"]);
+
+    let (stdout, stderr, exitcode) = run_command(&mut cmd);
+    let report: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let entries = report["entries"].as_array().unwrap();
+    assert_eq!(&stderr, "");
+    assert_eq!(report["algorithm"], "blake3");
+    assert_eq!(report["compliant"], false);
+    assert_eq!(entries.len(), 4);
+    assert_eq!(entries[0]["step"], 1);
+    assert_eq!(entries[0]["kind"], "copy");
+    assert_eq!(entries[0]["src"], "gitconfig");
+    assert_eq!(entries[0]["dst"], "~/.gitconfig");
+    assert_eq!(entries[0]["host"], serde_json::Value::Null);
+    assert_eq!(entries[0]["status"], "differs");
+    assert_eq!(entries[0]["dst_hash"], "\
+6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85");
+    assert_eq!(entries[3]["src"], "vimrc");
+    assert_eq!(entries[3]["status"], "ok");
+    assert_eq!(entries[3]["src_hash"], entries[3]["dst_hash"]);
+    assert_eq!(exitcode, Some(1));
+}